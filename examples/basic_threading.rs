@@ -6,10 +6,13 @@ extern crate alloc;
 use alloc::{vec, vec::Vec, boxed::Box};
 
 use preemptive_mlthreading_rust::{
-    ThreadBuilder, JoinHandle, yield_now, Mutex, 
+    DefaultArch, Kernel, RoundRobinScheduler, ThreadBuilder, JoinHandle, yield_now, Mutex, 
     init_security, SecurityConfig, get_security_stats
 };
 
+/// Kernel the example's threads are spawned through
+type ExampleKernel = Kernel<DefaultArch, RoundRobinScheduler>;
+
 /// Shared counter protected by mutex
 static COUNTER: Mutex<u32> = Mutex::new(0);
 
@@ -59,6 +62,9 @@ fn compute_worker(thread_id: u32) -> u64 {
 fn main() -> Result<(), Box<dyn core::error::Error>> {
     println!("=== Basic Threading Example ===");
     
+    let kernel = ExampleKernel::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("Failed to initialize kernel");
+    
     // Initialize security subsystem
     let security_config = SecurityConfig {
         enable_stack_canaries: true,
@@ -68,6 +74,7 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
         enable_aslr: false,
         enable_audit_logging: true,
         use_secure_rng: true,
+        enable_speculation_barriers: false,
         panic_on_violation: true,
     };
     
@@ -79,11 +86,12 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
     
     // Spawn worker threads with different configurations
     for i in 0..4 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("worker_{}", i))
             .stack_size(64 * 1024) // 64KB stack
-            .priority(5 + (i % 3) as u8) // Varying priorities 5-7
-            .spawn(move || worker_thread(i, 50))
+            .priority(5 + (i % 3) as u8); // Varying priorities 5-7
+        let handle = kernel
+            .spawn(builder, move || worker_thread(i, 50))
             .expect("Failed to spawn worker thread");
         
         handles.push(handle);
@@ -94,12 +102,13 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
     
     for i in 0..2 {
         let affinity_mask = 1u64 << i; // Pin to specific CPU
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("compute_{}", i))
             .stack_size(128 * 1024) // Larger stack for compute work
             .priority(8) // High priority
-            .cpu_affinity(affinity_mask)
-            .spawn(move || compute_worker(i))
+            .cpu_affinity(affinity_mask);
+        let handle = kernel
+            .spawn(builder, move || compute_worker(i))
             .expect("Failed to spawn compute thread");
         
         compute_handles.push(handle);
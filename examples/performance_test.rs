@@ -6,7 +6,7 @@ extern crate alloc;
 use alloc::{vec, vec::Vec, format};
 
 use preemptive_mlthreading_rust::{
-    ThreadBuilder, JoinHandle, yield_now, Duration,
+    DefaultArch, Kernel, RoundRobinScheduler, ThreadBuilder, JoinHandle, yield_now, Duration,
     ThreadMetrics, SystemMetrics, ThreadProfiler, HealthMonitor,
     // Performance modules
     perf::{
@@ -19,6 +19,9 @@ use preemptive_mlthreading_rust::{
     }
 };
 
+/// Kernel the example's threads are spawned through
+type ExampleKernel = Kernel<DefaultArch, RoundRobinScheduler>;
+
 /// CPU-intensive benchmark task
 fn cpu_benchmark_task(task_id: u32, iterations: u32) -> (u32, u64) {
     let start_time = preemptive_mlthreading_rust::time::get_monotonic_time();
@@ -193,7 +196,7 @@ fn busy_wait_cycles(cycles: u32) {
     }
 }
 
-fn run_cpu_benchmarks() -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rust::ThreadError> {
+fn run_cpu_benchmarks(kernel: &ExampleKernel) -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== CPU Benchmarks ===");
     
     let mut handles = vec![];
@@ -207,11 +210,12 @@ fn run_cpu_benchmarks() -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rust::
     ];
     
     for (i, &(iterations, label)) in test_configs.iter().enumerate() {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("cpu_bench_{}", i))
             .priority(10 + (i % 4) as u8)
-            .cpu_affinity(1u64 << (i % 4))
-            .spawn(move || {
+            .cpu_affinity(1u64 << (i % 4));
+        let handle = kernel
+            .spawn(builder, move || {
                 println!("Starting {} CPU benchmark with {} iterations", label, iterations);
                 cpu_benchmark_task(i as u32, iterations)
             })
@@ -239,7 +243,7 @@ fn run_cpu_benchmarks() -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rust::
     Ok(results)
 }
 
-fn run_memory_benchmarks() -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rust::ThreadError> {
+fn run_memory_benchmarks(kernel: &ExampleKernel) -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Memory Benchmarks ===");
     
     let mut handles = vec![];
@@ -248,11 +252,12 @@ fn run_memory_benchmarks() -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rus
     let memory_sizes = [1, 4, 16, 32]; // MB
     
     for (i, &size_mb) in memory_sizes.iter().enumerate() {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("mem_bench_{}", i))
             .priority(10)
-            .stack_size(256 * 1024) // Larger stack for memory work
-            .spawn(move || {
+            .stack_size(256 * 1024); // Larger stack for memory work
+        let handle = kernel
+            .spawn(builder, move || {
                 println!("Starting memory benchmark with {}MB", size_mb);
                 memory_benchmark_task(i as u32, size_mb)
             })
@@ -277,7 +282,7 @@ fn run_memory_benchmarks() -> Result<Vec<(u32, u64)>, preemptive_mlthreading_rus
     Ok(results)
 }
 
-fn run_lock_contention_benchmark() -> Result<Vec<u32>, preemptive_mlthreading_rust::ThreadError> {
+fn run_lock_contention_benchmark(kernel: &ExampleKernel) -> Result<Vec<u32>, preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Lock Contention Benchmark ===");
     
     use preemptive_mlthreading_rust::Mutex;
@@ -287,11 +292,12 @@ fn run_lock_contention_benchmark() -> Result<Vec<u32>, preemptive_mlthreading_ru
     
     // Multiple threads contending for the same lock
     for i in 0..8 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("lock_bench_{}", i))
             .priority(10)
-            .cpu_affinity(1u64 << (i % 4))
-            .spawn(move || lock_contention_task(i, &SHARED_COUNTER))
+            .cpu_affinity(1u64 << (i % 4));
+        let handle = kernel
+            .spawn(builder, move || lock_contention_task(i, &SHARED_COUNTER))
             .expect("Failed to spawn lock benchmark");
         
         handles.push(handle);
@@ -317,17 +323,18 @@ fn run_lock_contention_benchmark() -> Result<Vec<u32>, preemptive_mlthreading_ru
     Ok(results)
 }
 
-fn run_context_switch_benchmark() -> Result<Vec<u32>, preemptive_mlthreading_rust::ThreadError> {
+fn run_context_switch_benchmark(kernel: &ExampleKernel) -> Result<Vec<u32>, preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Context Switch Benchmark ===");
     
     let mut handles = vec![];
     
     // Many threads for maximum context switching
     for i in 0..16 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("ctx_bench_{}", i))
-            .priority(8 + (i % 8) as u8)
-            .spawn(move || context_switch_benchmark(i))
+            .priority(8 + (i % 8) as u8);
+        let handle = kernel
+            .spawn(builder, move || context_switch_benchmark(i))
             .expect("Failed to spawn context switch benchmark");
         
         handles.push(handle);
@@ -357,16 +364,17 @@ fn run_context_switch_benchmark() -> Result<Vec<u32>, preemptive_mlthreading_rus
     Ok(results)
 }
 
-fn run_fast_path_benchmark() -> Result<Vec<u32>, preemptive_mlthreading_rust::ThreadError> {
+fn run_fast_path_benchmark(kernel: &ExampleKernel) -> Result<Vec<u32>, preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Fast Path Operations Benchmark ===");
     
     let mut handles = vec![];
     
     for i in 0..4 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("fast_bench_{}", i))
-            .priority(12)
-            .spawn(move || fast_path_benchmark(i))
+            .priority(12);
+        let handle = kernel
+            .spawn(builder, move || fast_path_benchmark(i))
             .expect("Failed to spawn fast path benchmark");
         
         handles.push(handle);
@@ -444,6 +452,9 @@ fn show_performance_optimizations() {
 fn main() -> Result<(), Box<dyn core::error::Error>> {
     println!("=== Performance Testing and Benchmarking ===");
     
+    let kernel = ExampleKernel::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("Failed to initialize kernel");
+    
     // Initialize performance optimizations
     init_context_switch_optimization();
     init_cache_optimization();
@@ -457,11 +468,11 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
     show_performance_optimizations();
     
     // Run comprehensive benchmarks
-    let _cpu_results = run_cpu_benchmarks()?;
-    let _memory_results = run_memory_benchmarks()?;
-    let _lock_results = run_lock_contention_benchmark()?;
-    let _context_results = run_context_switch_benchmark()?;
-    let _fast_path_results = run_fast_path_benchmark()?;
+    let _cpu_results = run_cpu_benchmarks(&kernel)?;
+    let _memory_results = run_memory_benchmarks(&kernel)?;
+    let _lock_results = run_lock_contention_benchmark(&kernel)?;
+    let _context_results = run_context_switch_benchmark(&kernel)?;
+    let _fast_path_results = run_fast_path_benchmark(&kernel)?;
     
     // System-wide performance metrics
     println!("\n=== System Performance Metrics ===");
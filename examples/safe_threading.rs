@@ -2,7 +2,8 @@
 
 use core::sync::atomic::{AtomicU32, Ordering};
 use preemptive_threads::{
-    protected_stack, yield_now, Mutex, StackStatus, ThreadBuilder, ATOMIC_SCHEDULER,
+    protected_stack, yield_now, DefaultArch, Kernel, Mutex, RoundRobinScheduler, StackStatus,
+    ThreadBuilder, ATOMIC_SCHEDULER,
 };
 
 static COUNTER: Mutex<u32> = Mutex::new(0);
@@ -147,6 +148,9 @@ fn demo_mutex() {
 }
 
 fn demo_thread_builder() {
+    let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("Failed to initialize kernel");
+
     // Create a thread with builder pattern
     let builder = ThreadBuilder::new()
         .stack_size(128 * 1024) // 128KB stack
@@ -158,14 +162,13 @@ fn demo_thread_builder() {
     println!("    Priority: 6/7");
     println!("    Name: worker_thread");
 
-    // Note: spawn() returns NotImplemented in no_std environment
-    match builder.spawn(|| {
+    // The thread is queued on the kernel's scheduler and runs once it is
+    // switched to
+    match kernel.spawn(builder, || {
         println!("This would run in the thread");
     }) {
-        Err(preemptive_threads::ThreadError::NotImplemented) => {
-            println!("  (Thread spawning not implemented in no_std demo)");
-        }
-        _ => {}
+        Ok(handle) => println!("  Spawned thread {:?}", handle.thread_id()),
+        Err(error) => println!("  Failed to spawn thread: {:?}", error),
     }
 }
 
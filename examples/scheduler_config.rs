@@ -6,7 +6,7 @@ extern crate alloc;
 use alloc::{vec, vec::Vec};
 
 use preemptive_mlthreading_rust::{
-    ThreadBuilder, JoinHandle, yield_now, Duration,
+    DefaultArch, Kernel, RoundRobinScheduler, ThreadBuilder, JoinHandle, yield_now, Duration,
     NewScheduler, RoundRobinScheduler, CpuId,
     ThreadMetrics, SystemMetrics, ThreadProfiler
 };
@@ -14,6 +14,9 @@ use preemptive_mlthreading_rust::{
 #[cfg(feature = "work-stealing")]
 use preemptive_mlthreading_rust::WorkStealingScheduler;
 
+/// Kernel the example's threads are spawned through
+type ExampleKernel = Kernel<DefaultArch, RoundRobinScheduler>;
+
 /// High-priority real-time task
 fn realtime_task(task_id: u32) -> u32 {
     println!("RT Task {} started", task_id);
@@ -84,7 +87,7 @@ fn busy_wait_microseconds(us: u32) {
     }
 }
 
-fn demonstrate_round_robin_scheduler() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_round_robin_scheduler(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Round Robin Scheduler Demo ===");
     
     // Configure round-robin scheduler
@@ -95,11 +98,12 @@ fn demonstrate_round_robin_scheduler() -> Result<(), preemptive_mlthreading_rust
     
     // Create threads with equal priority (round-robin behavior)
     for i in 0..3 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("rr_task_{}", i))
             .priority(10) // Same priority for all
-            .scheduler(Box::new(scheduler.clone()))
-            .spawn(move || background_task(i))
+            .scheduler(Box::new(scheduler.clone()));
+        let handle = kernel
+            .spawn(builder, move || background_task(i))
             .expect("Failed to spawn round-robin task");
         
         handles.push(handle);
@@ -115,7 +119,7 @@ fn demonstrate_round_robin_scheduler() -> Result<(), preemptive_mlthreading_rust
 }
 
 #[cfg(feature = "work-stealing")]
-fn demonstrate_work_stealing_scheduler() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_work_stealing_scheduler(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Work Stealing Scheduler Demo ===");
     
     // Configure work-stealing scheduler with NUMA awareness
@@ -129,12 +133,13 @@ fn demonstrate_work_stealing_scheduler() -> Result<(), preemptive_mlthreading_ru
     for i in 0..6 {
         let cpu_affinity = if i < 3 { 0x0F } else { 0xF0 }; // Split across NUMA nodes
         
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("ws_task_{}", i))
             .priority(12)
             .cpu_affinity(cpu_affinity)
-            .scheduler(Box::new(scheduler.clone()))
-            .spawn(move || {
+            .scheduler(Box::new(scheduler.clone()));
+        let handle = kernel
+            .spawn(builder, move || {
                 let mut result = 0u64;
                 // Irregular work load that benefits from work stealing
                 for j in 0..(100 * (i + 1)) {
@@ -175,17 +180,18 @@ fn fibonacci(n: u32) -> u64 {
     }
 }
 
-fn demonstrate_priority_scheduling() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_priority_scheduling(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Priority Scheduling Demo ===");
     
     let mut handles = vec![];
     
     // High priority real-time tasks
     for i in 0..2 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("rt_task_{}", i))
-            .priority(20) // Highest priority
-            .spawn(move || realtime_task(i))
+            .priority(20); // Highest priority
+        let handle = kernel
+            .spawn(builder, move || realtime_task(i))
             .expect("Failed to spawn real-time task");
         
         handles.push(handle);
@@ -193,10 +199,11 @@ fn demonstrate_priority_scheduling() -> Result<(), preemptive_mlthreading_rust::
     
     // Medium priority interactive tasks  
     for i in 0..3 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("ui_task_{}", i))
-            .priority(15) // Medium priority
-            .spawn(move || interactive_task(i))
+            .priority(15); // Medium priority
+        let handle = kernel
+            .spawn(builder, move || interactive_task(i))
             .expect("Failed to spawn interactive task");
         
         handles.push(handle);
@@ -204,10 +211,11 @@ fn demonstrate_priority_scheduling() -> Result<(), preemptive_mlthreading_rust::
     
     // Low priority background tasks
     for i in 0..2 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("bg_task_{}", i))
-            .priority(5) // Low priority
-            .spawn(move || background_task(i))
+            .priority(5); // Low priority
+        let handle = kernel
+            .spawn(builder, move || background_task(i))
             .expect("Failed to spawn background task");
         
         handles.push(handle);
@@ -222,7 +230,7 @@ fn demonstrate_priority_scheduling() -> Result<(), preemptive_mlthreading_rust::
     Ok(())
 }
 
-fn demonstrate_cpu_affinity() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_cpu_affinity(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== CPU Affinity Demo ===");
     
     let mut handles = vec![];
@@ -231,11 +239,12 @@ fn demonstrate_cpu_affinity() -> Result<(), preemptive_mlthreading_rust::ThreadE
     for cpu_id in 0..4u32 {
         let affinity_mask = 1u64 << cpu_id;
         
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("cpu_{}_task", cpu_id))
             .priority(10)
-            .cpu_affinity(affinity_mask)
-            .spawn(move || {
+            .cpu_affinity(affinity_mask);
+        let handle = kernel
+            .spawn(builder, move || {
                 println!("Task running on CPU {} (mask: 0x{:x})", cpu_id, affinity_mask);
                 
                 // CPU-specific work
@@ -264,7 +273,7 @@ fn demonstrate_cpu_affinity() -> Result<(), preemptive_mlthreading_rust::ThreadE
     Ok(())
 }
 
-fn demonstrate_performance_monitoring() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_performance_monitoring(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Performance Monitoring Demo ===");
     
     // Create profiler for performance analysis
@@ -276,11 +285,12 @@ fn demonstrate_performance_monitoring() -> Result<(), preemptive_mlthreading_rus
     
     // Create monitored tasks
     for i in 0..3 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("monitored_task_{}", i))
             .priority(12)
-            .enable_profiling(true)
-            .spawn(move || {
+            .enable_profiling(true);
+        let handle = kernel
+            .spawn(builder, move || {
                 // Mixed workload for interesting metrics
                 for j in 0..200 {
                     if j % 3 == 0 {
@@ -330,15 +340,18 @@ fn demonstrate_performance_monitoring() -> Result<(), preemptive_mlthreading_rus
 fn main() -> Result<(), Box<dyn core::error::Error>> {
     println!("=== Advanced Scheduler Configuration Example ===");
     
+    let kernel = ExampleKernel::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("Failed to initialize kernel");
+    
     // Demonstrate different scheduling approaches
-    demonstrate_round_robin_scheduler()?;
+    demonstrate_round_robin_scheduler(&kernel)?;
     
     #[cfg(feature = "work-stealing")]
-    demonstrate_work_stealing_scheduler()?;
+    demonstrate_work_stealing_scheduler(&kernel)?;
     
-    demonstrate_priority_scheduling()?;
-    demonstrate_cpu_affinity()?;
-    demonstrate_performance_monitoring()?;
+    demonstrate_priority_scheduling(&kernel)?;
+    demonstrate_cpu_affinity(&kernel)?;
+    demonstrate_performance_monitoring(&kernel)?;
     
     println!("\n=== All Scheduler Demonstrations Completed Successfully ===");
     Ok(())
@@ -6,7 +6,7 @@ extern crate alloc;
 use alloc::{vec, vec::Vec, string::ToString};
 
 use preemptive_mlthreading_rust::{
-    ThreadBuilder, JoinHandle, yield_now,
+    DefaultArch, Kernel, RoundRobinScheduler, ThreadBuilder, JoinHandle, yield_now,
     SecurityConfig, SecurityFeature, SecurityViolation,
     init_security, get_security_stats, configure_security_feature,
     // Security modules
@@ -19,6 +19,9 @@ use preemptive_mlthreading_rust::{
     }
 };
 
+/// Kernel the example's threads are spawned through
+type ExampleKernel = Kernel<DefaultArch, RoundRobinScheduler>;
+
 /// Simulates processing of untrusted data in an isolated environment
 fn isolated_untrusted_worker(worker_id: u32) -> u32 {
     println!("Isolated worker {} processing untrusted data", worker_id);
@@ -113,7 +116,7 @@ fn cfi_protected_worker(worker_id: u32) -> u32 {
     result
 }
 
-fn demonstrate_thread_isolation() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_thread_isolation(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Thread Isolation Demo ===");
     
     let mut handles = vec![];
@@ -127,11 +130,12 @@ fn demonstrate_thread_isolation() -> Result<(), preemptive_mlthreading_rust::Thr
             ..Default::default()
         };
         
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("isolated_worker_{}", i))
             .priority(8)
-            .isolation_config(isolation_config)
-            .spawn(move || {
+            .isolation_config(isolation_config);
+        let handle = kernel
+            .spawn(builder, move || {
                 // Create isolation domain for this thread
                 let thread_id = preemptive_mlthreading_rust::thread_new::current_thread_id();
                 if let Err(_) = create_isolation_domain(thread_id, IsolationConfig::default()) {
@@ -147,10 +151,11 @@ fn demonstrate_thread_isolation() -> Result<(), preemptive_mlthreading_rust::Thr
     
     // Create trusted system workers
     for i in 0..1 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("trusted_worker_{}", i))
-            .priority(15) // Higher priority
-            .spawn(move || trusted_system_worker(i))
+            .priority(15); // Higher priority
+        let handle = kernel
+            .spawn(builder, move || trusted_system_worker(i))
             .expect("Failed to spawn trusted worker");
         
         handles.push(handle);
@@ -165,20 +170,21 @@ fn demonstrate_thread_isolation() -> Result<(), preemptive_mlthreading_rust::Thr
     Ok(())
 }
 
-fn demonstrate_stack_protection() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_stack_protection(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Stack Protection Demo ===");
     
     let mut handles = vec![];
     
     // Create threads with stack protection enabled
     for i in 0..3 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("stack_protected_{}", i))
             .stack_size(128 * 1024) // 128KB stack
             .priority(10)
             .enable_stack_canaries(true)
-            .enable_guard_pages(false) // Disable for this example
-            .spawn(move || stack_protected_worker(i))
+            .enable_guard_pages(false); // Disable for this example
+        let handle = kernel
+            .spawn(builder, move || stack_protected_worker(i))
             .expect("Failed to spawn stack-protected worker");
         
         handles.push(handle);
@@ -193,18 +199,19 @@ fn demonstrate_stack_protection() -> Result<(), preemptive_mlthreading_rust::Thr
     Ok(())
 }
 
-fn demonstrate_cfi_protection() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_cfi_protection(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== CFI Protection Demo ===");
     
     let mut handles = vec![];
     
     // Create threads with CFI protection
     for i in 0..2 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("cfi_protected_{}", i))
             .priority(12)
-            .enable_cfi(true)
-            .spawn(move || cfi_protected_worker(i))
+            .enable_cfi(true);
+        let handle = kernel
+            .spawn(builder, move || cfi_protected_worker(i))
             .expect("Failed to spawn CFI-protected worker");
         
         handles.push(handle);
@@ -219,7 +226,7 @@ fn demonstrate_cfi_protection() -> Result<(), preemptive_mlthreading_rust::Threa
     Ok(())
 }
 
-fn demonstrate_aslr() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_aslr(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== ASLR Demo ===");
     
     let mut layouts = vec![];
@@ -244,11 +251,12 @@ fn demonstrate_aslr() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     let mut handles = vec![];
     
     for i in 0..2 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("aslr_worker_{}", i))
             .priority(10)
-            .enable_aslr(true)
-            .spawn(move || {
+            .enable_aslr(true);
+        let handle = kernel
+            .spawn(builder, move || {
                 println!("ASLR worker {} running with randomized layout", i);
                 
                 // Do some work to show the randomized addresses are functional
@@ -276,7 +284,7 @@ fn demonstrate_aslr() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     Ok(())
 }
 
-fn demonstrate_audit_logging() -> Result<(), preemptive_mlthreading_rust::ThreadError> {
+fn demonstrate_audit_logging(kernel: &ExampleKernel) -> Result<(), preemptive_mlthreading_rust::ThreadError> {
     println!("\n=== Audit Logging Demo ===");
     
     // Log some events
@@ -290,11 +298,12 @@ fn demonstrate_audit_logging() -> Result<(), preemptive_mlthreading_rust::Thread
     
     // Create audited threads
     for i in 0..2 {
-        let handle = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("audited_worker_{}", i))
             .priority(10)
-            .enable_audit_logging(true)
-            .spawn(move || {
+            .enable_audit_logging(true);
+        let handle = kernel
+            .spawn(builder, move || {
                 // Log thread start
                 log_thread_event(
                     preemptive_mlthreading_rust::thread_new::current_thread_id(),
@@ -359,6 +368,9 @@ fn demonstrate_audit_logging() -> Result<(), preemptive_mlthreading_rust::Thread
 fn main() -> Result<(), Box<dyn core::error::Error>> {
     println!("=== Security Hardening Features Demo ===");
     
+    let kernel = ExampleKernel::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("Failed to initialize kernel");
+    
     // Initialize comprehensive security
     let security_config = SecurityConfig {
         enable_stack_canaries: true,
@@ -368,6 +380,7 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
         enable_aslr: true,
         enable_audit_logging: true,
         use_secure_rng: true,
+        enable_speculation_barriers: false, // IBPB needs ring 0
         panic_on_violation: false, // Continue on violations for demo
     };
     
@@ -382,11 +395,11 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
     configure_security_feature(SecurityFeature::Audit, true);
     
     // Demonstrate each security feature
-    demonstrate_thread_isolation()?;
-    demonstrate_stack_protection()?;
-    demonstrate_cfi_protection()?;
-    demonstrate_aslr()?;
-    demonstrate_audit_logging()?;
+    demonstrate_thread_isolation(&kernel)?;
+    demonstrate_stack_protection(&kernel)?;
+    demonstrate_cfi_protection(&kernel)?;
+    demonstrate_aslr(&kernel)?;
+    demonstrate_audit_logging(&kernel)?;
     
     // Final security statistics
     let stats = get_security_stats();
@@ -96,7 +96,7 @@ fn fuzz_thread_lifecycle(operations: Vec<ThreadOperation>) {
             
             ThreadOperation::SetPriority { thread_idx, priority } => {
                 if thread_idx < handles.len() {
-                    handles[thread_idx].thread().set_priority(priority.clamp(1, 10));
+                    let _ = handles[thread_idx].thread().set_priority(priority.clamp(1, 10));
                 }
             }
             
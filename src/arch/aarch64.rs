@@ -3,9 +3,11 @@
 //! This module provides ARM64-specific context switching, interrupt handling,
 //! FPU/NEON management, and SVE support for high-performance computing.
 
-use super::Arch;
+use super::{Arch, ThreadEntry, UserFrame};
 use core::arch::asm;
 use portable_atomic::{AtomicU64, Ordering};
+#[cfg(feature = "arm64-sve")]
+extern crate alloc;
 
 /// AArch64 architecture implementation.
 pub struct Aarch64Arch;
@@ -33,6 +35,11 @@ pub struct Aarch64Context {
     pub fpcr: u32, // Floating-point control register
     #[cfg(feature = "full-fpu")]
     pub fpsr: u32, // Floating-point status register
+
+    /// SVE state, allocated once the thread first uses SVE (see
+    /// [`sve`](super::sve))
+    #[cfg(feature = "arm64-sve")]
+    pub sve_state: Option<alloc::boxed::Box<[u128]>>,
 }
 
 impl Default for Aarch64Context {
@@ -48,6 +55,8 @@ impl Default for Aarch64Context {
             fpcr: 0,
             #[cfg(feature = "full-fpu")]
             fpsr: 0,
+            #[cfg(feature = "arm64-sve")]
+            sve_state: None,
         }
     }
 }
@@ -55,78 +64,127 @@ impl Default for Aarch64Context {
 unsafe impl Send for Aarch64Context {}
 unsafe impl Sync for Aarch64Context {}
 
+/// Byte offsets of [`Aarch64Context`] fields.
+pub mod offsets {
+    use super::Aarch64Context;
+    use crate::arch::field_offset;
+
+    /// Start of `x0`-`x30`, 8 bytes apart
+    pub const X: usize = field_offset!(Aarch64Context, x);
+    pub const SP: usize = field_offset!(Aarch64Context, sp);
+    pub const PC: usize = field_offset!(Aarch64Context, pc);
+    pub const PSTATE: usize = field_offset!(Aarch64Context, pstate);
+    /// Start of `v0`-`v31`, 16 bytes apart
+    #[cfg(feature = "full-fpu")]
+    pub const NEON_STATE: usize = field_offset!(Aarch64Context, neon_state);
+    #[cfg(feature = "full-fpu")]
+    pub const FPCR: usize = field_offset!(Aarch64Context, fpcr);
+    #[cfg(feature = "full-fpu")]
+    pub const FPSR: usize = field_offset!(Aarch64Context, fpsr);
+}
+
+// `context_switch` hard-codes these offsets
+const _: () = {
+    assert!(offsets::X == 0);
+    assert!(offsets::SP == 248);
+    assert!(offsets::PC == 256);
+    assert!(offsets::PSTATE == 264);
+};
+
+// `save_fpu` and `restore_fpu` hard-code these offsets
+#[cfg(feature = "full-fpu")]
+const _: () = {
+    assert!(offsets::NEON_STATE == 272);
+    assert!(offsets::FPCR == 784);
+    assert!(offsets::FPSR == 788);
+};
+
+// First code a new thread runs, with its entry point in x19 and the
+// argument in x20 (see `init_context`)
+core::arch::global_asm!(
+    ".pushsection .text.preemptive_threads_thread_stub, \"ax\"",
+    ".global preemptive_threads_thread_stub",
+    ".balign 4",
+    "preemptive_threads_thread_stub:",
+    "mov x0, x20",
+    "blr x19",
+    "brk #0",
+    ".popsection",
+);
+
+extern "C" {
+    fn preemptive_threads_thread_stub();
+}
+
 impl Arch for Aarch64Arch {
+    /// 8-bit ASIDs are always implemented; 16-bit ones need `TCR_EL1.AS`.
+    const ASID_BITS: u32 = 8;
+
     type SavedContext = Aarch64Context;
 
     unsafe fn context_switch(prev: *mut Self::SavedContext, next: *const Self::SavedContext) {
         unsafe {
             asm!(
-                // Save current context
-                "stp x0, x1, [{prev}, #0]",
-                "stp x2, x3, [{prev}, #16]", 
-                "stp x4, x5, [{prev}, #32]",
-                "stp x6, x7, [{prev}, #48]",
-                "stp x8, x9, [{prev}, #64]",
-                "stp x10, x11, [{prev}, #80]",
-                "stp x12, x13, [{prev}, #96]",
-                "stp x14, x15, [{prev}, #112]",
-                "stp x16, x17, [{prev}, #128]",
-                "stp x18, x19, [{prev}, #144]",
-                "stp x20, x21, [{prev}, #160]",
-                "stp x22, x23, [{prev}, #176]",
-                "stp x24, x25, [{prev}, #192]",
-                "stp x26, x27, [{prev}, #208]",
-                "stp x28, x29, [{prev}, #224]",
-                "str x30, [{prev}, #240]",
+                // Save the callee-saved registers x19-x30
+                "stp x19, x20, [x0, #152]",
+                "stp x21, x22, [x0, #168]",
+                "stp x23, x24, [x0, #184]",
+                "stp x25, x26, [x0, #200]",
+                "stp x27, x28, [x0, #216]",
+                "stp x29, x30, [x0, #232]",
                 
-                // Save stack pointer and link register (return address)
-                "mov x0, sp",
-                "str x0, [{prev}, #248]",    // sp offset
-                "adr x0, 1f",                // get return address
-                "str x0, [{prev}, #256]",    // pc offset
+                // Save stack pointer and the address to resume at
+                "mov x2, sp",
+                "str x2, [x0, #248]",        // sp offset
+                "adr x2, 2f",                // get return address
+                "str x2, [x0, #256]",        // pc offset
                 
                 // Save processor state
-                "mrs x0, nzcv",
-                "str x0, [{prev}, #264]",    // pstate offset
+                "mrs x2, nzcv",
+                "str x2, [x0, #264]",        // pstate offset
                 
                 // Load new context
-                "ldp x0, x1, [{next}, #0]",
-                "ldp x2, x3, [{next}, #16]",
-                "ldp x4, x5, [{next}, #32]",
-                "ldp x6, x7, [{next}, #48]",
-                "ldp x8, x9, [{next}, #64]",
-                "ldp x10, x11, [{next}, #80]",
-                "ldp x12, x13, [{next}, #96]",
-                "ldp x14, x15, [{next}, #112]",
-                "ldp x16, x17, [{next}, #128]",
-                "ldp x18, x19, [{next}, #144]",
-                "ldp x20, x21, [{next}, #160]",
-                "ldp x22, x23, [{next}, #176]",
-                "ldp x24, x25, [{next}, #192]",
-                "ldp x26, x27, [{next}, #208]",
-                "ldp x28, x29, [{next}, #224]",
-                "ldr x30, [{next}, #240]",
+                "ldp x19, x20, [x1, #152]",
+                "ldp x21, x22, [x1, #168]",
+                "ldp x23, x24, [x1, #184]",
+                "ldp x25, x26, [x1, #200]",
+                "ldp x27, x28, [x1, #216]",
+                "ldp x29, x30, [x1, #232]",
                 
-                // Restore stack pointer  
-                "ldr x0, [{next}, #248]",
-                "mov sp, x0",
+                // Restore stack pointer
+                "ldr x2, [x1, #248]",
+                "mov sp, x2",
                 
                 // Restore processor state
-                "ldr x0, [{next}, #264]",
-                "msr nzcv, x0",
+                "ldr x2, [x1, #264]",
+                "msr nzcv, x2",
                 
                 // Jump to new context
-                "ldr x0, [{next}, #256]",    // load pc
-                "br x0",                     // branch to new context
+                "ldr x2, [x1, #256]",        // load pc
+                "br x2",                     // branch to new context
                 
-                "1:",                        // return label for save
-                prev = in(reg) prev,
-                next = in(reg) next,
+                "2:",                        // return label for save
+                // Pinned to registers the restore does not overwrite
+                in("x0") prev,
+                in("x1") next,
+                // Only the callee-saved registers survive the switch
                 clobber_abi("C")
             );
         }
     }
 
+    unsafe fn init_context(stack_top: *mut u8, entry: ThreadEntry, arg: usize) -> Option<Self::SavedContext> {
+        let mut ctx = Aarch64Context {
+            sp: (stack_top as usize & !15) as u64,
+            pc: preemptive_threads_thread_stub as usize as u64,
+            pstate: 0,
+            ..Aarch64Context::default()
+        };
+        ctx.x[19] = entry as usize as u64;
+        ctx.x[20] = arg as u64;
+        Some(ctx)
+    }
+
     #[cfg(feature = "full-fpu")]
     unsafe fn save_fpu(ctx: &mut Self::SavedContext) {
         unsafe {
@@ -159,6 +217,10 @@ impl Arch for Aarch64Arch {
                 options(nostack)
             );
         }
+        #[cfg(feature = "arm64-sve")]
+        unsafe {
+            super::sve::save(ctx);
+        }
     }
 
     #[cfg(feature = "full-fpu")]
@@ -193,6 +255,10 @@ impl Arch for Aarch64Arch {
                 options(nostack)
             );
         }
+        #[cfg(feature = "arm64-sve")]
+        unsafe {
+            super::sve::restore(ctx);
+        }
     }
 
     fn enable_interrupts() {
@@ -224,8 +290,138 @@ impl Arch for Aarch64Arch {
         }
         (daif & 0x80) == 0  // IRQ bit (bit 7) is clear when interrupts enabled
     }
+
+    fn saved_stack_pointer(ctx: &Self::SavedContext) -> Option<usize> {
+        Some(ctx.sp as usize)
+    }
+
+    fn saved_program_counter(ctx: &Self::SavedContext) -> Option<usize> {
+        Some(ctx.pc as usize)
+    }
+
+    #[cfg(feature = "arm64-sve")]
+    fn has_extended_state(ctx: &Self::SavedContext) -> bool {
+        ctx.sve_state.is_some()
+    }
+
+    fn relocate_stack(ctx: &mut Self::SavedContext, old_low: usize, old_high: usize, new_low: usize) {
+        ctx.sp = super::relocate_pointer(ctx.sp, old_low, old_high, new_low);
+        // x29 is the frame pointer
+        ctx.x[29] = super::relocate_pointer(ctx.x[29], old_low, old_high, new_low);
+    }
+
+    fn cycle_counter() -> Option<(u64, u64)> {
+        match TIMER_FREQ.load(Ordering::Relaxed) {
+            0 => None,
+            freq => Some((get_timestamp(), freq)),
+        }
+    }
+
+    unsafe fn switch_address_space(root: usize, asid: u16, flush: bool) {
+        let tagged_asid = (asid as u64) << 48;
+        // Safety: `root` maps the running code and stack per the caller
+        unsafe {
+            if flush {
+                asm!(
+                    "tlbi aside1, {}",
+                    "dsb nsh",
+                    in(reg) tagged_asid,
+                    options(nostack, preserves_flags)
+                );
+            }
+            asm!(
+                "msr ttbr0_el1, {}",
+                "isb",
+                in(reg) tagged_asid | root as u64,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+
+    unsafe fn flush_tlb(asid: Option<u16>) {
+        unsafe {
+            match asid {
+                Some(asid) => asm!(
+                    "tlbi aside1, {}",
+                    "dsb nsh",
+                    "isb",
+                    in(reg) (asid as u64) << 48,
+                    options(nostack, preserves_flags)
+                ),
+                None => asm!("tlbi vmalle1", "dsb nsh", "isb", options(nostack, preserves_flags)),
+            }
+        }
+    }
+
+    /// Return with `eret` to EL0 using `SP_EL0`; traps land on `SP_EL1`,
+    /// the kernel stack the thread runs on.
+    unsafe fn return_to_user(frame: &UserFrame) -> ! {
+        // EL0t with DAIF clear; only the NZCV condition flags are kept
+        let spsr = frame.flags as u64 & SPSR_NZCV;
+
+        // Safety: the caller guarantees a user-accessible frame
+        unsafe {
+            asm!(
+                "msr sp_el0, {sp}",
+                "msr elr_el1, {pc}",
+                "msr spsr_el1, {spsr}",
+                "mov x1, xzr", "mov x2, xzr", "mov x3, xzr", "mov x4, xzr",
+                "mov x5, xzr", "mov x6, xzr", "mov x7, xzr", "mov x8, xzr",
+                "mov x9, xzr", "mov x10, xzr", "mov x11, xzr", "mov x12, xzr",
+                "mov x13, xzr", "mov x14, xzr", "mov x15, xzr", "mov x16, xzr",
+                "mov x17, xzr", "mov x18, xzr", "mov x19, xzr", "mov x20, xzr",
+                "mov x21, xzr", "mov x22, xzr", "mov x23, xzr", "mov x24, xzr",
+                "mov x25, xzr", "mov x26, xzr", "mov x27, xzr", "mov x28, xzr",
+                "mov x29, xzr", "mov x30, xzr",
+                "eret",
+                sp = in(reg) frame.sp as u64,
+                pc = in(reg) frame.pc as u64,
+                spsr = in(reg) spsr,
+                in("x0") frame.ret as u64,
+                options(noreturn)
+            );
+        }
+    }
+
+    /// Stop speculative loads from bypassing earlier stores (`ssbb`) and
+    /// from consuming mispredicted conditional-select results (`csdb`)
+    /// across the switch.
+    fn speculation_barrier() {
+        // Safety: both are barrier hints without architectural side effects;
+        // encoded directly for assemblers without the speculation extensions
+        unsafe {
+            asm!(
+                ".inst 0xd503309f", // ssbb
+                "hint #20",         // csdb
+                "isb",
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    /// Needs CNTKCTL_EL1.EL0PCTEN set for EL0 access.
+    fn user_counter() -> Option<u64> {
+        Some(get_timestamp())
+    }
+
+    /// Store `cpu + 1` in TPIDRRO_EL0, read-only at EL0; TPIDR_EL1 that
+    /// holds it for the kernel isn't readable there.
+    unsafe fn publish_cpu_id(cpu: usize) {
+        // Safety: TPIDRRO_EL0 is not used by this crate otherwise
+        unsafe { asm!("msr tpidrro_el0, {}", in(reg) cpu as u64 + 1, options(nomem, nostack)) };
+    }
+
+    fn user_cpu_id() -> Option<usize> {
+        let value: u64;
+        // Safety: TPIDRRO_EL0 is readable at every exception level
+        unsafe { asm!("mrs {}, tpidrro_el0", out(reg) value, options(nomem, nostack)) };
+        (value as usize).checked_sub(1)
+    }
 }
 
+/// SPSR condition flags user code may set.
+const SPSR_NZCV: u64 = 0xf << 28;
+
 // Timer frequency storage  
 static TIMER_FREQ: AtomicU64 = AtomicU64::new(0);
 
@@ -247,7 +443,11 @@ pub fn init() {
             val = in(reg) 1u64, // Enable timer (bit 0 = 1)
             options(nomem, nostack)
         );
+
+        #[cfg(feature = "arm64-sve")]
+        super::sve::init();
     }
+    crate::sched::dispatch::set_timer_rearm(rearm_preemption_timer);
 }
 
 /// Set up ARM64 timer for preemption with specified interval in microseconds.
@@ -330,19 +530,20 @@ pub unsafe fn timer_interrupt_handler() {
             options(nomem, nostack)
         );
         
-        // Get scheduler reference and handle preemption
-        if let Some(scheduler) = crate::scheduler::get_global_scheduler() {
-            scheduler.handle_timer_interrupt();
-        }
+        // Let the global scheduler preempt the current thread
+        crate::sched::global::timer_tick();
         
-        // Re-setup timer for next preemption (1ms default)
-        if setup_preemption_timer(1000).is_err() {
-            // Timer setup failed, disable preemption
-            return;
-        }
+        // Re-setup timer for next preemption, unless a switch already did
+        crate::sched::dispatch::rearm_timer();
     }
 }
 
+/// Re-arm the preemption timer (1ms default) after it fired.
+fn rearm_preemption_timer() {
+    // On failure the timer stays off, disabling preemption
+    let _ = unsafe { setup_preemption_timer(1000) };
+}
+
 /// Memory barrier operations for ARM64.
 pub fn memory_barrier_full() {
     unsafe {
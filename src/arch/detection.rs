@@ -4,6 +4,7 @@
 //! to enable architecture-specific optimizations.
 
 use portable_atomic::{AtomicBool, Ordering};
+use alloc::vec::Vec;
 
 /// CPU architecture types.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -32,6 +33,10 @@ pub struct CpuFeatures {
     pub supports_avx: bool,
     #[cfg(feature = "x86_64")]
     pub supports_avx512: bool,
+    /// `rdfsbase`/`wrfsbase`/`rdgsbase`/`wrgsbase`, usable once
+    /// `CR4.FSGSBASE` is set
+    #[cfg(feature = "x86_64")]
+    pub supports_fsgsbase: bool,
     
     // ARM64-specific features
     #[cfg(feature = "arm64")]
@@ -107,6 +112,8 @@ fn perform_detection() -> CpuFeatures {
         supports_avx: detect_x86_64_avx(),
         #[cfg(feature = "x86_64")]
         supports_avx512: detect_x86_64_avx512(),
+        #[cfg(feature = "x86_64")]
+        supports_fsgsbase: detect_x86_64_fsgsbase(),
         
         #[cfg(feature = "arm64")]
         supports_neon: detect_arm64_neon(),
@@ -259,6 +266,20 @@ fn detect_x86_64_avx512() -> bool {
     false
 }
 
+/// Check CPUID leaf 7, EBX bit 0, for the FS/GS base instructions.
+#[cfg(all(feature = "x86_64", target_arch = "x86_64"))]
+#[allow(unused_unsafe)]
+fn detect_x86_64_fsgsbase() -> bool {
+    use core::arch::x86_64::__cpuid_count;
+
+    unsafe { __cpuid_count(0, 0).eax >= 7 && __cpuid_count(7, 0).ebx & 1 != 0 }
+}
+
+#[cfg(all(feature = "x86_64", not(target_arch = "x86_64")))]
+fn detect_x86_64_fsgsbase() -> bool {
+    false
+}
+
 // ARM64-specific detection functions
 #[cfg(feature = "arm64")]
 fn detect_arm64_cache_line_size() -> u32 {
@@ -277,16 +298,36 @@ fn detect_arm64_neon() -> bool {
     true
 }
 
-#[cfg(feature = "arm64")]
+#[cfg(all(feature = "arm64", target_arch = "aarch64"))]
+fn detect_arm64_sve() -> bool {
+    // ID_AA64PFR0_EL1.SVE, bits 35:32
+    let pfr0: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0, options(nomem, nostack, preserves_flags));
+    }
+    (pfr0 >> 32) & 0xf != 0
+}
+
+#[cfg(all(feature = "arm64", not(target_arch = "aarch64")))]
 fn detect_arm64_sve() -> bool {
-    // Would check ID_AA64PFR0_EL1 register for SVE support
-    // For now, return false as not all ARM64 systems have SVE
     false
 }
 
-#[cfg(feature = "arm64")]
+#[cfg(all(feature = "arm64", target_arch = "aarch64"))]
+fn detect_arm64_sve2() -> bool {
+    if !detect_arm64_sve() {
+        return false;
+    }
+    // ID_AA64ZFR0_EL1.SVEver, bits 3:0, by encoding for older assemblers
+    let zfr0: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, S3_0_C0_C4_4", out(reg) zfr0, options(nomem, nostack, preserves_flags));
+    }
+    zfr0 & 0xf != 0
+}
+
+#[cfg(all(feature = "arm64", not(target_arch = "aarch64")))]
 fn detect_arm64_sve2() -> bool {
-    // Would check for SVE2 support in system registers
     false
 }
 
@@ -326,6 +367,150 @@ fn detect_riscv_v_extension() -> bool {
     cfg!(feature = "riscv-vector")
 }
 
+/// How closely two CPUs share caches.
+///
+/// Variants are ordered from closest to farthest, so they can be compared
+/// directly when ranking steal victims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheDistance {
+    /// Same logical CPU
+    Same,
+    /// Hardware threads of the same core (share L1/L2)
+    SmtSibling,
+    /// Different cores sharing the last-level cache
+    SharedLlc,
+    /// No shared cache
+    Remote,
+}
+
+/// Cache sharing relationships between logical CPUs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTopology {
+    /// Physical core each logical CPU belongs to
+    pub core_ids: Vec<u32>,
+    /// Last-level cache domain each logical CPU belongs to
+    pub llc_ids: Vec<u32>,
+}
+
+impl CacheTopology {
+    /// Build a topology where CPUs are numbered contiguously: SMT siblings
+    /// are adjacent and each LLC covers `cpus_per_llc` consecutive CPUs.
+    pub fn uniform(cpu_count: usize, threads_per_core: usize, cpus_per_llc: usize) -> Self {
+        let threads_per_core = threads_per_core.max(1);
+        let cpus_per_llc = cpus_per_llc.max(threads_per_core);
+        
+        Self {
+            core_ids: (0..cpu_count).map(|cpu| (cpu / threads_per_core) as u32).collect(),
+            llc_ids: (0..cpu_count).map(|cpu| (cpu / cpus_per_llc) as u32).collect(),
+        }
+    }
+    
+    /// Number of CPUs described by this topology.
+    pub fn cpu_count(&self) -> usize {
+        self.core_ids.len()
+    }
+    
+    /// Classify how closely two CPUs share caches.
+    ///
+    /// CPUs outside the topology are treated as remote.
+    pub fn distance(&self, from: usize, to: usize) -> CacheDistance {
+        if from == to {
+            return CacheDistance::Same;
+        }
+        
+        match (self.core_ids.get(from), self.core_ids.get(to)) {
+            (Some(a), Some(b)) if a == b => return CacheDistance::SmtSibling,
+            (Some(_), Some(_)) => {}
+            _ => return CacheDistance::Remote,
+        }
+        
+        if self.llc_ids[from] == self.llc_ids[to] {
+            CacheDistance::SharedLlc
+        } else {
+            CacheDistance::Remote
+        }
+    }
+    
+    /// Hardware threads sharing a physical core with `cpu`, excluding `cpu`.
+    pub fn smt_siblings(&self, cpu: usize) -> Vec<usize> {
+        let Some(&core) = self.core_ids.get(cpu) else {
+            return Vec::new();
+        };
+        
+        (0..self.cpu_count())
+            .filter(|&other| other != cpu && self.core_ids[other] == core)
+            .collect()
+    }
+    
+    /// Check whether any physical core runs more than one hardware thread.
+    pub fn has_smt(&self) -> bool {
+        self.core_ids
+            .iter()
+            .enumerate()
+            .any(|(cpu, core)| self.core_ids[..cpu].contains(core))
+    }
+    
+    /// Order in which `cpu` should try other CPUs when stealing work.
+    ///
+    /// SMT siblings come first, then CPUs sharing the LLC, then remote CPUs;
+    /// within each group, CPUs are ordered by index starting after `cpu`.
+    pub fn steal_order(&self, cpu: usize, cpu_count: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (1..cpu_count)
+            .map(|offset| (cpu + offset) % cpu_count)
+            .collect();
+        
+        // Stable sort keeps the rotation order inside each distance class
+        order.sort_by_key(|&victim| self.distance(cpu, victim));
+        order
+    }
+}
+
+/// Detect cache topology for `cpu_count` logical CPUs.
+///
+/// Assumes contiguous numbering of SMT siblings and LLC domains; platforms
+/// with other layouts should provide the topology explicitly.
+pub fn detect_cache_topology(cpu_count: usize) -> CacheTopology {
+    let (threads_per_core, cpus_per_llc) = detect_cache_sharing(cpu_count);
+    CacheTopology::uniform(cpu_count, threads_per_core, cpus_per_llc)
+}
+
+/// Detect (logical CPUs per core, logical CPUs per LLC).
+#[cfg(all(feature = "x86_64", target_arch = "x86_64"))]
+#[allow(unused_unsafe)]
+fn detect_cache_sharing(cpu_count: usize) -> (usize, usize) {
+    use core::arch::x86_64::__cpuid_count;
+    
+    let max_leaf = unsafe { __cpuid_count(0, 0) }.eax;
+    
+    // Leaf 0xB level 0: logical processors per core
+    let threads_per_core = if max_leaf >= 0xB {
+        (unsafe { __cpuid_count(0xB, 0) }.ebx & 0xFFFF).max(1) as usize
+    } else {
+        1
+    };
+    
+    // Leaf 4: walk cache levels, the last valid one is the LLC
+    let mut cpus_per_llc = cpu_count;
+    if max_leaf >= 4 {
+        for subleaf in 0..8 {
+            let cache = unsafe { __cpuid_count(4, subleaf) };
+            if cache.eax & 0x1F == 0 {
+                break;
+            }
+            cpus_per_llc = (((cache.eax >> 14) & 0xFFF) + 1) as usize;
+        }
+    }
+    
+    (threads_per_core, cpus_per_llc.min(cpu_count.max(1)))
+}
+
+/// Detect (logical CPUs per core, logical CPUs per LLC).
+#[cfg(not(all(feature = "x86_64", target_arch = "x86_64")))]
+fn detect_cache_sharing(cpu_count: usize) -> (usize, usize) {
+    // Without topology registers, assume no SMT and a single shared LLC
+    (1, cpu_count.max(1))
+}
+
 /// Runtime optimization controller.
 pub struct RuntimeOptimizer {
     features: CpuFeatures,
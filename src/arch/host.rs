@@ -0,0 +1,224 @@
+//! Host OS backed architecture for development.
+//!
+//! With the `std` feature and no hardware architecture enabled, [`HostArch`]
+//! is the default architecture. Each thread of this crate is carried by a
+//! host thread, and a context switch hands a baton from the outgoing host
+//! thread to the incoming one: exactly one of them runs at a time, as on a
+//! single CPU. The scheduler, timers and sync primitives then run their
+//! real code paths on a development machine, under a debugger and with the
+//! host's tooling, before anything is flashed to hardware.
+//!
+//! A [`HostContext`] is a small handle, and a zeroed one is valid: it stands
+//! for a thread that has not been switched out yet, and is bound to the
+//! calling host thread the first time it is. Threads with an entry point
+//! get a parked host thread from [`HostArch::spawn_context`], or from
+//! [`Arch::init_context`], which ignores the stack it is given.
+//!
+//! Interrupts are modelled by a single flag. Nothing delivers timer
+//! interrupts on its own; a host thread that calls the kernel's timer
+//! handler periodically stands in for the timer.
+//!
+//! ```ignore
+//! let worker = HostArch::spawn_context(worker_main);
+//! let mut main = HostContext::default();
+//! // Runs worker_main until it switches back to `main`
+//! unsafe { HostArch::context_switch(&mut main, &worker) };
+//! ```
+
+use super::{Arch, ThreadEntry};
+use portable_atomic::{AtomicBool, Ordering};
+extern crate alloc;
+extern crate std;
+use alloc::{sync::Arc, vec::Vec};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// Handoff point of one host thread.
+struct Baton {
+    /// Set when the host thread may run
+    run: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Baton {
+    fn new() -> Self {
+        Self {
+            run: Mutex::new(false),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn pass(&self) {
+        *self.run.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        self.wake.notify_one();
+    }
+
+    fn wait(&self) {
+        let mut run = self.run.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while !*run {
+            run = self.wake.wait(run).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *run = false;
+    }
+}
+
+/// Batons of all host threads; context slot `n` uses entry `n - 1`.
+static BATONS: Mutex<Vec<Arc<Baton>>> = Mutex::new(Vec::new());
+
+/// Simulated interrupt enable flag.
+static INTERRUPTS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+fn new_slot() -> usize {
+    let mut batons = BATONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    batons.push(Arc::new(Baton::new()));
+    batons.len()
+}
+
+fn baton(slot: usize) -> Arc<Baton> {
+    BATONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())[slot - 1].clone()
+}
+
+/// Saved state of a thread carried by a host thread.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HostContext {
+    /// Host thread slot (0 = not bound to a host thread yet)
+    slot: usize,
+}
+
+/// Architecture backed by host threads.
+pub struct HostArch;
+
+impl HostArch {
+    /// Create a context that runs `entry` on a new host thread the first
+    /// time it is switched to.
+    ///
+    /// Like a hardware context, `entry` must end by switching to another
+    /// thread: if it returns, its host thread exits and nothing else runs.
+    pub fn spawn_context(entry: fn()) -> HostContext {
+        let slot = new_slot();
+        let baton = baton(slot);
+        std::thread::spawn(move || {
+            baton.wait();
+            entry();
+        });
+        HostContext { slot }
+    }
+}
+
+impl Arch for HostArch {
+    type SavedContext = HostContext;
+
+    unsafe fn context_switch(prev: *mut Self::SavedContext, next: *const Self::SavedContext) {
+        // Safety: the caller guarantees both contexts are valid
+        let (prev, next) = unsafe { (&mut *prev, (*next).slot) };
+        if prev.slot == 0 {
+            prev.slot = new_slot();
+        }
+        // A context that never ran has nothing to resume
+        if next == 0 || next == prev.slot {
+            return;
+        }
+
+        let own = baton(prev.slot);
+        baton(next).pass();
+        own.wait();
+    }
+
+    unsafe fn init_context(_stack_top: *mut u8, entry: ThreadEntry, arg: usize) -> Option<Self::SavedContext> {
+        let slot = new_slot();
+        let baton = baton(slot);
+        std::thread::spawn(move || {
+            baton.wait();
+            entry(arg)
+        });
+        Some(HostContext { slot })
+    }
+
+    #[cfg(feature = "full-fpu")]
+    unsafe fn save_fpu(_ctx: &mut Self::SavedContext) {}
+
+    #[cfg(feature = "full-fpu")]
+    unsafe fn restore_fpu(_ctx: &Self::SavedContext) {}
+
+    fn enable_interrupts() {
+        INTERRUPTS_ENABLED.store(true, Ordering::Release);
+    }
+
+    fn disable_interrupts() {
+        INTERRUPTS_ENABLED.store(false, Ordering::Release);
+    }
+
+    fn interrupts_enabled() -> bool {
+        INTERRUPTS_ENABLED.load(Ordering::Acquire)
+    }
+
+    fn cycle_counter() -> Option<(u64, u64)> {
+        static START: OnceLock<std::time::Instant> = OnceLock::new();
+        let elapsed = START.get_or_init(std::time::Instant::now).elapsed();
+        Some((elapsed.as_nanos() as u64, 1_000_000_000))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use portable_atomic::AtomicUsize;
+
+    static MAIN_SLOT: AtomicUsize = AtomicUsize::new(0);
+    static WORKER_SLOT: AtomicUsize = AtomicUsize::new(0);
+    static STEPS: AtomicUsize = AtomicUsize::new(0);
+
+    fn worker() {
+        let mut own = HostContext { slot: WORKER_SLOT.load(Ordering::Acquire) };
+        let main = HostContext { slot: MAIN_SLOT.load(Ordering::Acquire) };
+        loop {
+            STEPS.fetch_add(1, Ordering::AcqRel);
+            unsafe { HostArch::context_switch(&mut own, &main) };
+        }
+    }
+
+    #[test]
+    fn test_host_threads_take_turns() {
+        let worker_context = HostArch::spawn_context(worker);
+        WORKER_SLOT.store(worker_context.slot, Ordering::Release);
+
+        // Binding the main context happens on its first switch, so give it
+        // a slot the worker can switch back to first
+        let mut main = HostContext { slot: new_slot() };
+        MAIN_SLOT.store(main.slot, Ordering::Release);
+
+        for step in 1..=3 {
+            unsafe { HostArch::context_switch(&mut main, &worker_context) };
+            // The worker ran exactly once per switch and handed back
+            assert_eq!(STEPS.load(Ordering::Acquire), step);
+        }
+
+        HostArch::disable_interrupts();
+        assert!(!HostArch::interrupts_enabled());
+        HostArch::enable_interrupts();
+    }
+
+    static STARTED_SLOT: AtomicUsize = AtomicUsize::new(0);
+    static STARTER_SLOT: AtomicUsize = AtomicUsize::new(0);
+    static ARG: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn started(arg: usize) -> ! {
+        ARG.store(arg, Ordering::Release);
+        let mut own = HostContext { slot: STARTED_SLOT.load(Ordering::Acquire) };
+        let starter = HostContext { slot: STARTER_SLOT.load(Ordering::Acquire) };
+        loop {
+            unsafe { HostArch::context_switch(&mut own, &starter) };
+        }
+    }
+
+    #[test]
+    fn test_init_context_passes_argument() {
+        let context = unsafe { HostArch::init_context(core::ptr::null_mut(), started, 42) }.unwrap();
+        STARTED_SLOT.store(context.slot, Ordering::Release);
+        let mut starter = HostContext { slot: new_slot() };
+        STARTER_SLOT.store(starter.slot, Ordering::Release);
+
+        unsafe { HostArch::context_switch(&mut starter, &context) };
+        assert_eq!(ARG.load(Ordering::Acquire), 42);
+    }
+}
@@ -17,6 +17,10 @@
 /// inline assembly. All methods marked as unsafe have specific preconditions
 /// that must be upheld by the caller.
 pub trait Arch {
+    /// Number of ASID (PCID) bits TLB entries are tagged with; 0 if the
+    /// TLB is untagged or address spaces are not supported.
+    const ASID_BITS: u32 = 0;
+
     /// Architecture-specific saved context type.
     ///
     /// This type must contain all CPU registers and state needed to fully
@@ -35,6 +39,22 @@ pub trait Arch {
     /// - The `next` context must represent a valid execution state
     unsafe fn context_switch(prev: *mut Self::SavedContext, next: *const Self::SavedContext);
 
+    /// Build the context of a thread that has not run yet.
+    ///
+    /// Switching to the returned context calls `entry(arg)` on the stack
+    /// growing down from `stack_top`, with interrupts disabled. Returns
+    /// `None` if the architecture cannot start code on a stack of its own,
+    /// in which case the thread's entry point is run by whoever picks it;
+    /// this is the default.
+    ///
+    /// # Safety
+    ///
+    /// `stack_top` must be the highest address of a writable stack that
+    /// stays allocated while the context is in use.
+    unsafe fn init_context(_stack_top: *mut u8, _entry: ThreadEntry, _arg: usize) -> Option<Self::SavedContext> {
+        None
+    }
+
     /// Save floating point unit state to the given context.
     ///
     /// # Safety
@@ -71,6 +91,164 @@ pub trait Arch {
     ///
     /// Returns `true` if interrupts are enabled, `false` otherwise.
     fn interrupts_enabled() -> bool;
+
+    /// Get the stack pointer recorded in a saved context.
+    ///
+    /// Returns `None` if the architecture does not track it.
+    fn saved_stack_pointer(_ctx: &Self::SavedContext) -> Option<usize> {
+        None
+    }
+
+    /// Check whether a saved context holds register state that only the
+    /// full switch path (with [`save_fpu`](Self::save_fpu) and
+    /// [`restore_fpu`](Self::restore_fpu)) switches, such as SVE state.
+    fn has_extended_state(_ctx: &Self::SavedContext) -> bool {
+        false
+    }
+
+    /// Get the program counter a saved context resumes at.
+    ///
+    /// Returns `None` if the context does not record it, e.g. because the
+    /// switch resumes inline.
+    fn saved_program_counter(_ctx: &Self::SavedContext) -> Option<usize> {
+        None
+    }
+
+    /// Rebase stack-relative registers in a saved context after its stack
+    /// was copied from `[old_low, old_high)` to a region starting at
+    /// `new_low`.
+    ///
+    /// Only the stack and frame pointers are adjusted; other pointers into
+    /// the old stack are left untouched.
+    fn relocate_stack(_ctx: &mut Self::SavedContext, _old_low: usize, _old_high: usize, _new_low: usize) {}
+
+    /// Load the page tables rooted at `root`, tagged with `asid`.
+    ///
+    /// If `flush` is set, TLB entries already tagged with `asid` on this
+    /// CPU are dropped first. The default does nothing.
+    ///
+    /// # Safety
+    ///
+    /// `root` must be the physical address of valid page tables that map
+    /// the running code and stack.
+    unsafe fn switch_address_space(_root: usize, _asid: u16, _flush: bool) {}
+
+    /// Drop this CPU's TLB entries tagged with `asid`, or all non-global
+    /// entries if `asid` is `None`. The default does nothing.
+    ///
+    /// # Safety
+    ///
+    /// Must run at a privilege level allowed to maintain the TLB.
+    unsafe fn flush_tlb(_asid: Option<u16>) {}
+
+    /// Resume user-mode execution with the state in `frame`.
+    ///
+    /// The return path of syscalls and exceptions taken from user mode, and
+    /// the way into user mode for a new user thread. Privileged bits in
+    /// `frame.flags` are forced to their user-mode values and registers not
+    /// in the frame are cleared so no kernel state leaks to user code. Traps
+    /// from user mode land on the stack given to
+    /// [`set_kernel_stack`](Self::set_kernel_stack). The default panics.
+    ///
+    /// # Safety
+    ///
+    /// `frame.pc` and `frame.sp` must be user-accessible in the loaded
+    /// address space, and the trap entry path must be set up.
+    unsafe fn return_to_user(_frame: &UserFrame) -> ! {
+        panic!("user mode is not supported on this architecture")
+    }
+
+    /// Start running user code at `entry` on `user_stack`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`return_to_user`](Self::return_to_user).
+    unsafe fn enter_user_mode(entry: usize, user_stack: usize) -> ! {
+        unsafe { Self::return_to_user(&UserFrame::new(entry, user_stack)) }
+    }
+
+    /// Set the stack traps from user mode switch to on this CPU.
+    ///
+    /// Called when a user thread is switched in, with the top of its
+    /// kernel stack. The default does nothing.
+    ///
+    /// # Safety
+    ///
+    /// `top` must be the top of a kernel stack owned by the thread about
+    /// to run.
+    unsafe fn set_kernel_stack(_top: usize) {}
+
+    /// Keep speculation from carrying state across a switch between
+    /// threads in different isolation domains.
+    ///
+    /// Called before the switch when speculation barriers are enabled in
+    /// the security configuration. The default does nothing.
+    fn speculation_barrier() {}
+
+    /// Read a free-running cycle counter together with its frequency in Hz.
+    ///
+    /// Used to timestamp context switches for per-thread CPU time. Returns
+    /// `None` if there is no counter or its frequency is not known yet.
+    fn cycle_counter() -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Read the cycle counter the way user-mode code can.
+    ///
+    /// Same counter as [`cycle_counter`](Self::cycle_counter) but without
+    /// consulting kernel state, so it works from user threads. Returns
+    /// `None` if there is no counter user mode may read.
+    fn user_counter() -> Option<u64> {
+        None
+    }
+
+    /// Make `cpu` readable from user mode on this CPU.
+    ///
+    /// # Safety
+    ///
+    /// Must run privileged, on the CPU numbered `cpu`.
+    unsafe fn publish_cpu_id(_cpu: usize) {}
+
+    /// Read the id published by [`publish_cpu_id`](Self::publish_cpu_id)
+    /// without a privilege transition. Returns `None` if none was
+    /// published or the architecture has no register for it.
+    fn user_cpu_id() -> Option<usize> {
+        None
+    }
+}
+
+/// Function a new context starts in, see [`Arch::init_context`].
+pub type ThreadEntry = extern "C" fn(usize) -> !;
+
+/// User-mode state resumed by [`Arch::return_to_user`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserFrame {
+    /// Instruction to resume at
+    pub pc: usize,
+    /// User stack pointer
+    pub sp: usize,
+    /// Status flags (RFLAGS, SPSR or SSTATUS); privileged bits are ignored
+    pub flags: usize,
+    /// Value for the return register (syscall result)
+    pub ret: usize,
+}
+
+impl UserFrame {
+    /// Frame that starts user code at `pc` on the stack `sp`.
+    pub const fn new(pc: usize, sp: usize) -> Self {
+        Self { pc, sp, flags: 0, ret: 0 }
+    }
+}
+
+/// Shift `value` from `[old_low, old_high]` to the same offset from `new_low`.
+#[allow(dead_code)]
+pub(crate) fn relocate_pointer(value: u64, old_low: usize, old_high: usize, new_low: usize) -> u64 {
+    let addr = value as usize;
+    if addr >= old_low && addr <= old_high {
+        (addr - old_low + new_low) as u64
+    } else {
+        value
+    }
 }
 
 /// A no-op architecture implementation for testing and fallback purposes.
@@ -79,6 +257,13 @@ pub trait Arch {
 /// in production code where real context switching is required.
 pub struct NoOpArch;
 
+#[cfg(test)]
+std::thread_local! {
+    /// Interrupt state of each test thread, so tests can check what code
+    /// leaves it in without racing each other.
+    static TEST_INTERRUPTS: core::cell::Cell<bool> = const { core::cell::Cell::new(true) };
+}
+
 impl Arch for NoOpArch {
     type SavedContext = ();
 
@@ -97,18 +282,43 @@ impl Arch for NoOpArch {
     }
 
     fn enable_interrupts() {
-        // No-op for testing
+        #[cfg(test)]
+        TEST_INTERRUPTS.with(|enabled| enabled.set(true));
     }
 
     fn disable_interrupts() {
-        // No-op for testing
+        #[cfg(test)]
+        TEST_INTERRUPTS.with(|enabled| enabled.set(false));
     }
 
     fn interrupts_enabled() -> bool {
+        #[cfg(test)]
+        return TEST_INTERRUPTS.with(|enabled| enabled.get());
+        #[cfg(not(test))]
         true
     }
 }
 
+/// Byte offset of `$field` in `$ty`, usable in constants.
+///
+/// Context switch assembly addresses saved registers at hard-coded offsets;
+/// each architecture checks them against the struct layout with this at
+/// compile time.
+#[allow(unused_macros)]
+macro_rules! field_offset {
+    ($ty:ty, $field:ident) => {{
+        let base = core::mem::MaybeUninit::<$ty>::uninit();
+        let base = base.as_ptr();
+        // Safety: only the field's address is computed, nothing is read
+        let field = unsafe { core::ptr::addr_of!((*base).$field) };
+        // Safety: both pointers are into the same value
+        unsafe { (field as *const u8).offset_from(base as *const u8) as usize }
+    }};
+}
+
+#[allow(unused_imports)]
+pub(crate) use field_offset;
+
 // Include architecture-specific implementations
 #[cfg(feature = "x86_64")]
 pub mod x86_64;
@@ -116,11 +326,21 @@ pub mod x86_64;
 #[cfg(feature = "arm64")]
 pub mod aarch64;
 
+#[cfg(feature = "arm64-sve")]
+pub mod sve;
+
 #[cfg(feature = "riscv64")]
 pub mod riscv;
 
+#[cfg(feature = "sim")]
+pub mod sim;
+
+#[cfg(feature = "std")]
+pub mod host;
+
 pub mod barriers;
 pub mod detection;
+pub mod percpu;
 
 // Re-export the default architecture for the current target
 #[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
@@ -132,7 +352,13 @@ pub use aarch64::Aarch64Arch as DefaultArch;
 #[cfg(all(any(target_arch = "riscv64"), feature = "riscv64"))]
 pub use riscv::RiscvArch as DefaultArch;
 
-#[cfg(all(feature = "std-shim", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64"))))]
+#[cfg(all(feature = "sim", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64"))))]
+pub use sim::SimArch as DefaultArch;
+
+#[cfg(all(feature = "std", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64", feature = "sim"))))]
+pub use host::HostArch as DefaultArch;
+
+#[cfg(all(feature = "std-shim", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64", feature = "sim", feature = "std"))))]
 pub use NoOpArch as DefaultArch;
 
 // Fallback for when no specific architecture is enabled
@@ -140,6 +366,8 @@ pub use NoOpArch as DefaultArch;
     all(target_arch = "x86_64", feature = "x86_64"),
     all(target_arch = "aarch64", feature = "arm64"), 
     all(any(target_arch = "riscv64"), feature = "riscv64"),
-    all(feature = "std-shim", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64")))
+    all(feature = "sim", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64"))),
+    all(feature = "std", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64", feature = "sim"))),
+    all(feature = "std-shim", not(any(feature = "x86_64", feature = "arm64", feature = "riscv64", feature = "sim", feature = "std")))
 )))]
 pub use NoOpArch as DefaultArch;
\ No newline at end of file
@@ -0,0 +1,228 @@
+//! Per-CPU identification.
+//!
+//! Each CPU stores its logical index in an architecture register that is
+//! cheap to read from any context:
+//!
+//! - x86_64: `IA32_TSC_AUX`, read with `rdtscp`, and a per-CPU area the
+//!   kernel GS base points at, read with a much cheaper GS-relative load.
+//!   The area also records whether the CPU turned on FSGSBASE, which is
+//!   enabled per CPU in `CR4`, and the FS base the CPU was last given, so
+//!   context switches don't read it back from its MSR
+//! - aarch64: `TPIDR_EL1`
+//! - riscv64: the `tp` register
+//!
+//! Platform bring-up code must call [`set_cpu_id`] once on every CPU before
+//! threads are scheduled there. Without an architecture feature enabled,
+//! every CPU reports index 0.
+
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+use portable_atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Maximum number of CPUs with per-CPU state.
+pub const MAX_CPUS: usize = 64;
+
+/// Per-CPU area the kernel GS base points at on x86_64, set up by
+/// [`set_cpu_id`].
+///
+/// Read with GS-relative loads, which use the GS base whether or not the
+/// CPU has FSGSBASE.
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+#[repr(C)]
+struct PerCpuArea {
+    /// Logical index of the CPU, at offset 0
+    cpu: AtomicUsize,
+    /// Whether `CR4.FSGSBASE` is set on the CPU, at offset 8
+    fsgsbase: AtomicBool,
+    /// FS base last set on the CPU, at offset 16
+    fs_base: AtomicU64,
+}
+
+// The GS-relative loads below hard-code these offsets
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+const _: () = {
+    assert!(field_offset!(PerCpuArea, cpu) == 0);
+    assert!(field_offset!(PerCpuArea, fsgsbase) == 8);
+    assert!(field_offset!(PerCpuArea, fs_base) == 16);
+};
+
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+#[allow(clippy::declare_interior_mutable_const)]
+const AREA_INIT: PerCpuArea = PerCpuArea {
+    cpu: AtomicUsize::new(0),
+    fsgsbase: AtomicBool::new(false),
+    fs_base: AtomicU64::new(0),
+};
+
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+static AREAS: [PerCpuArea; MAX_CPUS] = [AREA_INIT; MAX_CPUS];
+
+/// Set once a CPU points its GS base at its [`PerCpuArea`]. From then on
+/// GS is read on every CPU, which is why [`set_cpu_id`] must run on each
+/// before anything there relies on [`cpu_id`].
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+static AREAS_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Check whether this CPU has `CR4.FSGSBASE` set, per its per-CPU area.
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+#[inline(always)]
+pub(crate) fn fsgsbase_enabled() -> bool {
+    if !AREAS_INSTALLED.load(Ordering::Relaxed) {
+        return false;
+    }
+    let enabled: u8;
+    // Safety: the GS base points at this CPU's area
+    unsafe {
+        core::arch::asm!(
+            "mov {}, byte ptr gs:[8]",
+            out(reg_byte) enabled,
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    enabled != 0
+}
+
+/// Get the FS base last set on this CPU, per its per-CPU area, or `None`
+/// before [`set_cpu_id`] has run.
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+#[inline(always)]
+pub(crate) fn tracked_fs_base() -> Option<u64> {
+    if !AREAS_INSTALLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let base: u64;
+    // Safety: the GS base points at this CPU's area
+    unsafe {
+        core::arch::asm!(
+            "mov {}, qword ptr gs:[16]",
+            out(reg) base,
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    Some(base)
+}
+
+/// Record `base` as this CPU's FS base in its per-CPU area.
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+#[inline(always)]
+pub(crate) fn track_fs_base(base: u64) {
+    if !AREAS_INSTALLED.load(Ordering::Relaxed) {
+        return;
+    }
+    // Safety: the GS base points at this CPU's area
+    unsafe {
+        core::arch::asm!(
+            "mov qword ptr gs:[16], {}",
+            in(reg) base,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Get the logical index of the CPU executing this code.
+#[inline(always)]
+pub fn cpu_id() -> usize {
+    #[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+    {
+        if AREAS_INSTALLED.load(Ordering::Relaxed) {
+            let id: usize;
+            // Safety: the GS base points at this CPU's area
+            unsafe {
+                core::arch::asm!(
+                    "mov {}, qword ptr gs:[0]",
+                    out(reg) id,
+                    options(nostack, preserves_flags, readonly)
+                );
+            }
+            return id % MAX_CPUS;
+        }
+        let aux: u32;
+        unsafe {
+            core::arch::asm!(
+                "rdtscp",
+                out("eax") _,
+                out("edx") _,
+                out("ecx") aux,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        return (aux & !super::x86_64::TSC_AUX_PUBLISHED) as usize % MAX_CPUS;
+    }
+
+    #[cfg(all(target_arch = "aarch64", feature = "arm64"))]
+    {
+        let id: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, tpidr_el1", out(reg) id, options(nomem, nostack, preserves_flags));
+        }
+        return id as usize % MAX_CPUS;
+    }
+
+    #[cfg(all(target_arch = "riscv64", feature = "riscv64"))]
+    {
+        let id: usize;
+        unsafe {
+            core::arch::asm!("mv {}, tp", out(reg) id, options(nomem, nostack, preserves_flags));
+        }
+        return id % MAX_CPUS;
+    }
+
+    #[allow(unreachable_code)]
+    0
+}
+
+/// Record the logical index of the CPU executing this code.
+///
+/// # Safety
+///
+/// Must be called once per CPU during bring-up, in a privileged context,
+/// before anything on that CPU relies on [`cpu_id`]. On riscv64 this
+/// overwrites `tp`, so it must not be used where `tp` holds a TLS pointer.
+/// On x86_64 it points the kernel GS base at this crate's per-CPU area, so
+/// nothing else may keep its own data in GS, gives user code a null GS
+/// base, and sets `CR4.FSGSBASE` if the CPU has FSGSBASE.
+pub unsafe fn set_cpu_id(cpu: usize) {
+    #[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+    unsafe {
+        // IA32_TSC_AUX, which user code reads with `rdtscp`
+        if super::x86_64::rdtscp_supported() {
+            super::x86_64::write_tsc_aux(cpu);
+        }
+        let area = &AREAS[cpu % MAX_CPUS];
+        area.cpu.store(cpu, Ordering::Relaxed);
+        // Read once here; from now on the base is tracked in the area
+        area.fs_base.store(super::x86_64::read_fs_base_msr(), Ordering::Relaxed);
+        area.fsgsbase.store(super::x86_64::enable_fsgsbase(), Ordering::Relaxed);
+        // Through the MSR: whether this CPU may use `wrgsbase` is only
+        // known once GS points at its area
+        super::x86_64::write_gs_base_msr(area as *const PerCpuArea as u64);
+        // Swapped in by `return_to_user`
+        super::x86_64::write_kernel_gs_base_msr(0);
+        AREAS_INSTALLED.store(true, Ordering::Release);
+    }
+
+    #[cfg(all(target_arch = "aarch64", feature = "arm64"))]
+    unsafe {
+        core::arch::asm!("msr tpidr_el1, {}", in(reg) cpu as u64, options(nostack, preserves_flags));
+    }
+
+    #[cfg(all(target_arch = "riscv64", feature = "riscv64"))]
+    unsafe {
+        core::arch::asm!("mv tp, {}", in(reg) cpu, options(nostack, preserves_flags));
+    }
+
+    let _ = cpu;
+}
+
+#[cfg(all(test, target_arch = "x86_64", feature = "x86_64"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_falls_back_before_areas_installed() {
+        // No CPU has pointed GS at its area here, so nothing reads GS
+        assert!(!AREAS_INSTALLED.load(Ordering::Relaxed));
+        assert!(!fsgsbase_enabled());
+        assert_eq!(tracked_fs_base(), None);
+        assert!(cpu_id() < MAX_CPUS);
+    }
+}
@@ -2,11 +2,32 @@
 //!
 //! This module provides RISC-V-specific context switching, interrupt handling,
 //! and vector extension support for high-performance computing.
+//!
+//! The crate runs in S-mode by default, under an SBI firmware, using the
+//! `s*` CSRs and the Sstc `stimecmp` timer. With the `riscv-mmode` feature
+//! it runs in M-mode instead, for MCU-class parts without S-mode: the same
+//! code uses the `m*` CSRs and times preemption with the [CLINT](clint).
 
-use super::Arch;
+use super::{Arch, ThreadEntry, UserFrame};
 use core::arch::asm;
 use portable_atomic::{AtomicU64, Ordering};
 
+/// Name of the `name` CSR or instruction of the privilege mode the crate
+/// runs in, such as `sstatus` or `mstatus` for `status`.
+#[cfg(not(feature = "riscv-mmode"))]
+macro_rules! mode {
+    ($name:literal) => {
+        concat!("s", $name)
+    };
+}
+
+#[cfg(feature = "riscv-mmode")]
+macro_rules! mode {
+    ($name:literal) => {
+        concat!("m", $name)
+    };
+}
+
 /// RISC-V architecture implementation.
 pub struct RiscvArch;
 
@@ -77,149 +98,194 @@ impl Default for RiscvContext {
 unsafe impl Send for RiscvContext {}
 unsafe impl Sync for RiscvContext {}
 
+/// Byte offsets of [`RiscvContext`] fields.
+pub mod offsets {
+    use super::RiscvContext;
+    use crate::arch::field_offset;
+
+    /// Start of `x1`-`x31`, 8 bytes apart
+    pub const X: usize = field_offset!(RiscvContext, x);
+    pub const SP: usize = field_offset!(RiscvContext, sp);
+    pub const PC: usize = field_offset!(RiscvContext, pc);
+    pub const STATUS: usize = field_offset!(RiscvContext, status);
+    #[cfg(feature = "riscv-vector")]
+    pub const VECTOR_STATE: usize = field_offset!(RiscvContext, vector_state);
+    #[cfg(feature = "riscv-vector")]
+    pub const VSTART: usize = field_offset!(RiscvContext, vstart);
+    #[cfg(feature = "riscv-vector")]
+    pub const VXSAT: usize = field_offset!(RiscvContext, vxsat);
+    #[cfg(feature = "riscv-vector")]
+    pub const VXRM: usize = field_offset!(RiscvContext, vxrm);
+    #[cfg(feature = "riscv-vector")]
+    pub const VL: usize = field_offset!(RiscvContext, vl);
+    #[cfg(feature = "riscv-vector")]
+    pub const VTYPE: usize = field_offset!(RiscvContext, vtype);
+}
+
+// `context_switch` hard-codes these offsets. The FPU and vector state move
+// with the enabled extensions, so their assembly addresses them relative
+// to their own fields instead.
+const _: () = {
+    assert!(offsets::X == 0);
+    assert!(offsets::SP == 248);
+    assert!(offsets::PC == 256);
+    assert!(offsets::STATUS == 264);
+};
+
+// The vector CSRs are stored 8 bytes apart from `vstart` on
+#[cfg(feature = "riscv-vector")]
+const _: () = {
+    assert!(offsets::VXSAT == offsets::VSTART + 8);
+    assert!(offsets::VXRM == offsets::VSTART + 16);
+    assert!(offsets::VL == offsets::VSTART + 24);
+    assert!(offsets::VTYPE == offsets::VSTART + 32);
+};
+
+// First code a new thread runs, with its entry point in s2 and the
+// argument in s3 (see `init_context`)
+core::arch::global_asm!(
+    ".pushsection .text.preemptive_threads_thread_stub, \"ax\"",
+    ".global preemptive_threads_thread_stub",
+    ".balign 4",
+    "preemptive_threads_thread_stub:",
+    "mv a0, s3",
+    "jalr s2",
+    "unimp",
+    ".popsection",
+);
+
+extern "C" {
+    fn preemptive_threads_thread_stub();
+}
+
 impl Arch for RiscvArch {
     type SavedContext = RiscvContext;
 
     unsafe fn context_switch(prev: *mut Self::SavedContext, next: *const Self::SavedContext) {
         unsafe {
             asm!(
-                // Save current context (x1-x31)
-                "sd x1, 0({prev})",     // ra (return address)
-                "sd x3, 16({prev})",    // gp (global pointer)
-                "sd x4, 24({prev})",    // tp (thread pointer)
-                "sd x5, 32({prev})",    // t0
-                "sd x6, 40({prev})",    // t1
-                "sd x7, 48({prev})",    // t2
-                "sd x8, 56({prev})",    // s0/fp (frame pointer)
-                "sd x9, 64({prev})",    // s1
-                "sd x10, 72({prev})",   // a0
-                "sd x11, 80({prev})",   // a1
-                "sd x12, 88({prev})",   // a2
-                "sd x13, 96({prev})",   // a3
-                "sd x14, 104({prev})",  // a4
-                "sd x15, 112({prev})",  // a5
-                "sd x16, 120({prev})",  // a6
-                "sd x17, 128({prev})",  // a7
-                "sd x18, 136({prev})",  // s2
-                "sd x19, 144({prev})",  // s3
-                "sd x20, 152({prev})",  // s4
-                "sd x21, 160({prev})",  // s5
-                "sd x22, 168({prev})",  // s6
-                "sd x23, 176({prev})",  // s7
-                "sd x24, 184({prev})",  // s8
-                "sd x25, 192({prev})",  // s9
-                "sd x26, 200({prev})",  // s10
-                "sd x27, 208({prev})",  // s11
-                "sd x28, 216({prev})",  // t3
-                "sd x29, 224({prev})",  // t4
-                "sd x30, 232({prev})",  // t5
-                "sd x31, 240({prev})",  // t6
+                // Save the callee-saved registers
+                "sd x1, 0(a0)",         // ra (return address)
+                "sd x8, 56(a0)",        // s0/fp (frame pointer)
+                "sd x9, 64(a0)",        // s1
+                "sd x18, 136(a0)",      // s2
+                "sd x19, 144(a0)",      // s3
+                "sd x20, 152(a0)",      // s4
+                "sd x21, 160(a0)",      // s5
+                "sd x22, 168(a0)",      // s6
+                "sd x23, 176(a0)",      // s7
+                "sd x24, 184(a0)",      // s8
+                "sd x25, 192(a0)",      // s9
+                "sd x26, 200(a0)",      // s10
+                "sd x27, 208(a0)",      // s11
                 
                 // Save stack pointer
-                "sd sp, 248({prev})",   // sp offset
+                "sd sp, 248(a0)",       // sp offset
                 
                 // Save program counter (return address)
-                "la t0, 1f",            // load return address
-                "sd t0, 256({prev})",   // pc offset
+                "la t0, 2f",            // load return address
+                "sd t0, 256(a0)",       // pc offset
                 
                 // Save status register
-                "csrr t0, sstatus",
-                "sd t0, 264({prev})",   // status offset
+                concat!("csrr t0, ", mode!("status")),
+                "sd t0, 264(a0)",       // status offset
                 
                 // Load new context
-                "ld x1, 0({next})",     // ra
-                "ld x3, 16({next})",    // gp
-                "ld x4, 24({next})",    // tp
-                "ld x5, 32({next})",    // t0
-                "ld x6, 40({next})",    // t1
-                "ld x7, 48({next})",    // t2
-                "ld x8, 56({next})",    // s0/fp
-                "ld x9, 64({next})",    // s1
-                "ld x10, 72({next})",   // a0
-                "ld x11, 80({next})",   // a1
-                "ld x12, 88({next})",   // a2
-                "ld x13, 96({next})",   // a3
-                "ld x14, 104({next})",  // a4
-                "ld x15, 112({next})",  // a5
-                "ld x16, 120({next})",  // a6
-                "ld x17, 128({next})",  // a7
-                "ld x18, 136({next})",  // s2
-                "ld x19, 144({next})",  // s3
-                "ld x20, 152({next})",  // s4
-                "ld x21, 160({next})",  // s5
-                "ld x22, 168({next})",  // s6
-                "ld x23, 176({next})",  // s7
-                "ld x24, 184({next})",  // s8
-                "ld x25, 192({next})",  // s9
-                "ld x26, 200({next})",  // s10
-                "ld x27, 208({next})",  // s11
-                "ld x28, 216({next})",  // t3
-                "ld x29, 224({next})",  // t4
-                "ld x30, 232({next})",  // t5
-                "ld x31, 240({next})",  // t6
+                "ld x1, 0(a1)",         // ra
+                "ld x8, 56(a1)",        // s0/fp
+                "ld x9, 64(a1)",        // s1
+                "ld x18, 136(a1)",      // s2
+                "ld x19, 144(a1)",      // s3
+                "ld x20, 152(a1)",      // s4
+                "ld x21, 160(a1)",      // s5
+                "ld x22, 168(a1)",      // s6
+                "ld x23, 176(a1)",      // s7
+                "ld x24, 184(a1)",      // s8
+                "ld x25, 192(a1)",      // s9
+                "ld x26, 200(a1)",      // s10
+                "ld x27, 208(a1)",      // s11
                 
                 // Load stack pointer
-                "ld sp, 248({next})",   // sp
+                "ld sp, 248(a1)",       // sp
                 
                 // Load status register  
-                "ld t0, 264({next})",   // status
-                "csrw sstatus, t0",
+                "ld t0, 264(a1)",       // status
+                concat!("csrw ", mode!("status"), ", t0"),
                 
                 // Jump to new context
-                "ld t0, 256({next})",   // load pc
+                "ld t0, 256(a1)",       // load pc
                 "jr t0",                // jump to new context
                 
-                "1:",                   // return label for save
-                prev = in(reg) prev,
-                next = in(reg) next,
-                out("t0") _,
-                options(nostack)
+                "2:",                   // return label for save
+                // Pinned to registers the restore does not overwrite
+                in("a0") prev,
+                in("a1") next,
+                // Only the callee-saved registers survive the switch
+                clobber_abi("C")
             );
         }
     }
 
+    unsafe fn init_context(stack_top: *mut u8, entry: ThreadEntry, arg: usize) -> Option<Self::SavedContext> {
+        let status: u64;
+        // Safety: reading the status register has no side effects
+        unsafe { asm!(concat!("csrr {}, ", mode!("status")), out(reg) status, options(nomem, nostack)) };
+        let mut ctx = RiscvContext {
+            sp: (stack_top as usize & !15) as u64,
+            pc: preemptive_threads_thread_stub as usize as u64,
+            // Interrupts stay disabled until the thread enables them
+            status: status & !(STATUS_IE as u64),
+            ..RiscvContext::default()
+        };
+        // s2 and s3, x0 is not saved
+        ctx.x[17] = entry as usize as u64;
+        ctx.x[18] = arg as u64;
+        Some(ctx)
+    }
+
     #[cfg(feature = "riscv-float")]
     unsafe fn save_fpu(ctx: &mut Self::SavedContext) {
         unsafe {
             asm!(
                 // Save floating-point registers f0-f31
-                "fsd f0, 272({ctx})",   // f state offset
-                "fsd f1, 280({ctx})",
-                "fsd f2, 288({ctx})",
-                "fsd f3, 296({ctx})",
-                "fsd f4, 304({ctx})",
-                "fsd f5, 312({ctx})",
-                "fsd f6, 320({ctx})",
-                "fsd f7, 328({ctx})",
-                "fsd f8, 336({ctx})",
-                "fsd f9, 344({ctx})",
-                "fsd f10, 352({ctx})",
-                "fsd f11, 360({ctx})",
-                "fsd f12, 368({ctx})",
-                "fsd f13, 376({ctx})",
-                "fsd f14, 384({ctx})",
-                "fsd f15, 392({ctx})",
-                "fsd f16, 400({ctx})",
-                "fsd f17, 408({ctx})",
-                "fsd f18, 416({ctx})",
-                "fsd f19, 424({ctx})",
-                "fsd f20, 432({ctx})",
-                "fsd f21, 440({ctx})",
-                "fsd f22, 448({ctx})",
-                "fsd f23, 456({ctx})",
-                "fsd f24, 464({ctx})",
-                "fsd f25, 472({ctx})",
-                "fsd f26, 480({ctx})",
-                "fsd f27, 488({ctx})",
-                "fsd f28, 496({ctx})",
-                "fsd f29, 504({ctx})",
-                "fsd f30, 512({ctx})",
-                "fsd f31, 520({ctx})",
+                "fsd f0, 0({f})",      // relative to `ctx.f`
+                "fsd f1, 8({f})",
+                "fsd f2, 16({f})",
+                "fsd f3, 24({f})",
+                "fsd f4, 32({f})",
+                "fsd f5, 40({f})",
+                "fsd f6, 48({f})",
+                "fsd f7, 56({f})",
+                "fsd f8, 64({f})",
+                "fsd f9, 72({f})",
+                "fsd f10, 80({f})",
+                "fsd f11, 88({f})",
+                "fsd f12, 96({f})",
+                "fsd f13, 104({f})",
+                "fsd f14, 112({f})",
+                "fsd f15, 120({f})",
+                "fsd f16, 128({f})",
+                "fsd f17, 136({f})",
+                "fsd f18, 144({f})",
+                "fsd f19, 152({f})",
+                "fsd f20, 160({f})",
+                "fsd f21, 168({f})",
+                "fsd f22, 176({f})",
+                "fsd f23, 184({f})",
+                "fsd f24, 192({f})",
+                "fsd f25, 200({f})",
+                "fsd f26, 208({f})",
+                "fsd f27, 216({f})",
+                "fsd f28, 224({f})",
+                "fsd f29, 232({f})",
+                "fsd f30, 240({f})",
+                "fsd f31, 248({f})",
                 
                 // Save floating-point control and status register
                 "csrr t0, fcsr",
-                "sw t0, 528({ctx})",    // fcsr offset
-                ctx = in(reg) ctx,
+                "sw t0, 0({fcsr})",
+                f = in(reg) ctx.f.as_mut_ptr(),
+                fcsr = in(reg) core::ptr::addr_of_mut!(ctx.fcsr),
                 out("t0") _,
                 options(nostack)
             );
@@ -231,43 +297,44 @@ impl Arch for RiscvArch {
         unsafe {
             asm!(
                 // Restore floating-point control and status register
-                "lw t0, 528({ctx})",    // fcsr offset
+                "lw t0, 0({fcsr})",
                 "csrw fcsr, t0",
                 
                 // Restore floating-point registers f0-f31
-                "fld f0, 272({ctx})",   // f state offset
-                "fld f1, 280({ctx})",
-                "fld f2, 288({ctx})",
-                "fld f3, 296({ctx})",
-                "fld f4, 304({ctx})",
-                "fld f5, 312({ctx})",
-                "fld f6, 320({ctx})",
-                "fld f7, 328({ctx})",
-                "fld f8, 336({ctx})",
-                "fld f9, 344({ctx})",
-                "fld f10, 352({ctx})",
-                "fld f11, 360({ctx})",
-                "fld f12, 368({ctx})",
-                "fld f13, 376({ctx})",
-                "fld f14, 384({ctx})",
-                "fld f15, 392({ctx})",
-                "fld f16, 400({ctx})",
-                "fld f17, 408({ctx})",
-                "fld f18, 416({ctx})",
-                "fld f19, 424({ctx})",
-                "fld f20, 432({ctx})",
-                "fld f21, 440({ctx})",
-                "fld f22, 448({ctx})",
-                "fld f23, 456({ctx})",
-                "fld f24, 464({ctx})",
-                "fld f25, 472({ctx})",
-                "fld f26, 480({ctx})",
-                "fld f27, 488({ctx})",
-                "fld f28, 496({ctx})",
-                "fld f29, 504({ctx})",
-                "fld f30, 512({ctx})",
-                "fld f31, 520({ctx})",
-                ctx = in(reg) ctx,
+                "fld f0, 0({f})",      // relative to `ctx.f`
+                "fld f1, 8({f})",
+                "fld f2, 16({f})",
+                "fld f3, 24({f})",
+                "fld f4, 32({f})",
+                "fld f5, 40({f})",
+                "fld f6, 48({f})",
+                "fld f7, 56({f})",
+                "fld f8, 64({f})",
+                "fld f9, 72({f})",
+                "fld f10, 80({f})",
+                "fld f11, 88({f})",
+                "fld f12, 96({f})",
+                "fld f13, 104({f})",
+                "fld f14, 112({f})",
+                "fld f15, 120({f})",
+                "fld f16, 128({f})",
+                "fld f17, 136({f})",
+                "fld f18, 144({f})",
+                "fld f19, 152({f})",
+                "fld f20, 160({f})",
+                "fld f21, 168({f})",
+                "fld f22, 176({f})",
+                "fld f23, 184({f})",
+                "fld f24, 192({f})",
+                "fld f25, 200({f})",
+                "fld f26, 208({f})",
+                "fld f27, 216({f})",
+                "fld f28, 224({f})",
+                "fld f29, 232({f})",
+                "fld f30, 240({f})",
+                "fld f31, 248({f})",
+                f = in(reg) ctx.f.as_ptr(),
+                fcsr = in(reg) core::ptr::addr_of!(ctx.fcsr),
                 out("t0") _,
                 options(nostack)
             );
@@ -277,7 +344,8 @@ impl Arch for RiscvArch {
     fn enable_interrupts() {
         unsafe {
             asm!(
-                "csrsi sstatus, 0x2",  // Set SIE bit (bit 1) in sstatus
+                concat!("csrs ", mode!("status"), ", {ie}"),  // Set SIE/MIE
+                ie = in(reg) STATUS_IE,
                 options(nomem, nostack)
             );
         }
@@ -286,25 +354,125 @@ impl Arch for RiscvArch {
     fn disable_interrupts() {
         unsafe {
             asm!(
-                "csrci sstatus, 0x2",  // Clear SIE bit (bit 1) in sstatus
+                concat!("csrc ", mode!("status"), ", {ie}"),  // Clear SIE/MIE
+                ie = in(reg) STATUS_IE,
                 options(nomem, nostack)
             );
         }
     }
 
     fn interrupts_enabled() -> bool {
-        let sstatus: u64;
+        let status: usize;
         unsafe {
             asm!(
-                "csrr {sstatus}, sstatus",
-                sstatus = out(reg) sstatus,
+                concat!("csrr {status}, ", mode!("status")),
+                status = out(reg) status,
                 options(nostack, readonly)
             );
         }
-        (sstatus & 0x2) != 0  // Check SIE bit (bit 1)
+        (status & STATUS_IE) != 0
+    }
+
+    fn saved_stack_pointer(ctx: &Self::SavedContext) -> Option<usize> {
+        Some(ctx.sp as usize)
+    }
+
+    fn saved_program_counter(ctx: &Self::SavedContext) -> Option<usize> {
+        Some(ctx.pc as usize)
+    }
+
+    fn relocate_stack(ctx: &mut Self::SavedContext, old_low: usize, old_high: usize, new_low: usize) {
+        ctx.sp = super::relocate_pointer(ctx.sp, old_low, old_high, new_low);
+        // s0/fp is x8, stored at index 7 since x0 is not saved
+        ctx.x[7] = super::relocate_pointer(ctx.x[7], old_low, old_high, new_low);
+    }
+
+    fn cycle_counter() -> Option<(u64, u64)> {
+        match TIMER_FREQ.load(Ordering::Relaxed) {
+            0 => None,
+            freq => Some((get_timestamp(), freq)),
+        }
+    }
+
+    /// Return with `sret` (`mret` in M-mode) to U-mode. The trap vector is
+    /// expected to swap `sp` with `sscratch` (`mscratch`), which holds the
+    /// kernel stack.
+    unsafe fn return_to_user(frame: &UserFrame) -> ! {
+        let mut status: usize;
+        // Safety: reading the status register has no side effects
+        unsafe { asm!(concat!("csrr {}, ", mode!("status")), out(reg) status, options(nomem, nostack)) };
+        // Return to U-mode with interrupts enabled once there
+        status = (status & !STATUS_PP) | STATUS_PIE;
+
+        // Safety: the caller guarantees a user-accessible frame
+        unsafe {
+            asm!(
+                concat!("csrw ", mode!("epc"), ", {pc}"),
+                concat!("csrw ", mode!("status"), ", {status}"),
+                "mv sp, {sp}",
+                "mv ra, zero", "mv gp, zero", "mv tp, zero", "mv t0, zero",
+                "mv t1, zero", "mv t2, zero", "mv s0, zero", "mv s1, zero",
+                "mv a1, zero", "mv a2, zero", "mv a3, zero", "mv a4, zero",
+                "mv a5, zero", "mv a6, zero", "mv a7, zero", "mv s2, zero",
+                "mv s3, zero", "mv s4, zero", "mv s5, zero", "mv s6, zero",
+                "mv s7, zero", "mv s8, zero", "mv s9, zero", "mv s10, zero",
+                "mv s11, zero", "mv t3, zero", "mv t4, zero", "mv t5, zero",
+                "mv t6, zero",
+                mode!("ret"),
+                pc = in(reg) frame.pc,
+                status = in(reg) status,
+                sp = in(reg) frame.sp,
+                in("a0") frame.ret,
+                options(noreturn)
+            );
+        }
+    }
+
+    /// Needs `scounteren.TM` set for U-mode access.
+    #[cfg(not(feature = "riscv-mmode"))]
+    fn user_counter() -> Option<u64> {
+        Some(get_timestamp())
+    }
+
+    /// M-mode time comes from the memory-mapped CLINT, which U-mode cannot
+    /// read.
+    #[cfg(feature = "riscv-mmode")]
+    fn user_counter() -> Option<u64> {
+        None
+    }
+
+    unsafe fn set_kernel_stack(top: usize) {
+        // Safety: the scratch register is only read by the trap vector
+        unsafe { asm!(concat!("csrw ", mode!("scratch"), ", {}"), in(reg) top, options(nomem, nostack)) };
     }
 }
 
+/// SSTATUS supervisor interrupt enable.
+#[cfg(not(feature = "riscv-mmode"))]
+const STATUS_IE: usize = 1 << 1;
+/// SSTATUS previous privilege: set for S-mode, clear for U-mode.
+#[cfg(not(feature = "riscv-mmode"))]
+const STATUS_PP: usize = 1 << 8;
+/// SSTATUS previous interrupt enable, restored into SIE by `sret`.
+#[cfg(not(feature = "riscv-mmode"))]
+const STATUS_PIE: usize = 1 << 5;
+/// SIE supervisor timer interrupt enable.
+#[cfg(not(feature = "riscv-mmode"))]
+const TIMER_IE: usize = 1 << 5;
+
+/// MSTATUS machine interrupt enable.
+#[cfg(feature = "riscv-mmode")]
+const STATUS_IE: usize = 1 << 3;
+/// MSTATUS previous privilege, two bits: clear for U-mode.
+#[cfg(feature = "riscv-mmode")]
+const STATUS_PP: usize = 0b11 << 11;
+/// MSTATUS previous interrupt enable, restored into MIE by `mret`.
+#[cfg(feature = "riscv-mmode")]
+const STATUS_PIE: usize = 1 << 7;
+/// MIE machine timer interrupt enable.
+#[cfg(feature = "riscv-mmode")]
+const TIMER_IE: usize = 1 << 7;
+
 // Timer frequency storage
 static TIMER_FREQ: AtomicU64 = AtomicU64::new(0);
 
@@ -316,12 +484,14 @@ pub fn init() {
         let freq = 10_000_000u64; // 10MHz
         TIMER_FREQ.store(freq, Ordering::Relaxed);
         
-        // Enable timer interrupts
+        // Enable timer interrupts (STIE, or MTIE in M-mode)
         asm!(
-            "csrsi sie, 0x20",  // Enable timer interrupts (STIE bit 5)
+            concat!("csrs ", mode!("ie"), ", {ie}"),
+            ie = in(reg) TIMER_IE,
             options(nomem, nostack)
         );
     }
+    crate::sched::dispatch::set_timer_rearm(rearm_preemption_timer);
 }
 
 /// Set up RISC-V timer for preemption with specified interval in microseconds.
@@ -334,28 +504,14 @@ pub unsafe fn setup_preemption_timer(interval_us: u32) -> Result<(), &'static st
     // Calculate ticks for the desired interval
     let ticks = (freq * interval_us as u64) / 1_000_000;
     
-    unsafe {
-        // Read current time
-        let current: u64;
-        asm!(
-            "csrr {current}, time",
-            current = out(reg) current,
-            options(nostack, readonly)
-        );
-        
-        // Set compare value (current + interval)  
-        let compare_val = current + ticks;
-        asm!(
-            "csrw stimecmp, {val}",
-            val = in(reg) compare_val,
-            options(nomem, nostack)
-        );
-    }
+    // Set compare value (current + interval)
+    unsafe { set_timer_compare(get_timestamp() + ticks) };
     
     Ok(())
 }
 
 /// Get current RISC-V timestamp counter value.
+#[cfg(not(feature = "riscv-mmode"))]
 pub fn get_timestamp() -> u64 {
     let time: u64;
     unsafe {
@@ -368,6 +524,84 @@ pub fn get_timestamp() -> u64 {
     time
 }
 
+/// Get current RISC-V timestamp counter value, `mtime` in M-mode.
+#[cfg(feature = "riscv-mmode")]
+pub fn get_timestamp() -> u64 {
+    clint::mtime()
+}
+
+/// Raise the timer interrupt once the timestamp reaches `value`.
+#[cfg(not(feature = "riscv-mmode"))]
+unsafe fn set_timer_compare(value: u64) {
+    unsafe {
+        asm!(
+            "csrw stimecmp, {val}",
+            val = in(reg) value,
+            options(nomem, nostack)
+        );
+    }
+}
+
+/// Raise the timer interrupt once the timestamp reaches `value`.
+#[cfg(feature = "riscv-mmode")]
+unsafe fn set_timer_compare(value: u64) {
+    unsafe { clint::set_mtimecmp(value) };
+}
+
+/// Core-local interruptor, the machine timer of M-mode parts.
+///
+/// Its base address is platform specific; set it with [`set_base`] before
+/// [`init`](super::init) if it is not at [`DEFAULT_BASE`].
+#[cfg(feature = "riscv-mmode")]
+pub mod clint {
+    use core::arch::asm;
+    use portable_atomic::{AtomicUsize, Ordering};
+
+    /// CLINT base address on QEMU `virt` and SiFive parts.
+    pub const DEFAULT_BASE: usize = 0x0200_0000;
+
+    /// Offset of hart 0's `mtimecmp`, 8 bytes per hart.
+    const MTIMECMP: usize = 0x4000;
+    /// Offset of `mtime`.
+    const MTIME: usize = 0xbff8;
+
+    static BASE: AtomicUsize = AtomicUsize::new(DEFAULT_BASE);
+
+    /// Use the CLINT at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the address of the CLINT, mapped for M-mode access.
+    pub unsafe fn set_base(base: usize) {
+        BASE.store(base, Ordering::Relaxed);
+    }
+
+    /// Get the address of the CLINT in use.
+    pub fn base() -> usize {
+        BASE.load(Ordering::Relaxed)
+    }
+
+    /// Read `mtime`.
+    pub fn mtime() -> u64 {
+        // Safety: `set_base` guarantees the CLINT is mapped
+        unsafe { core::ptr::read_volatile((base() + MTIME) as *const u64) }
+    }
+
+    /// Write this hart's `mtimecmp`, which also clears a pending timer
+    /// interrupt if `value` is in the future.
+    ///
+    /// # Safety
+    ///
+    /// Must be called in M-mode.
+    pub unsafe fn set_mtimecmp(value: u64) {
+        let hart: usize;
+        // Safety: mhartid is readable in M-mode
+        unsafe { asm!("csrr {}, mhartid", out(reg) hart, options(nomem, nostack)) };
+        // Safety: `set_base` guarantees the CLINT is mapped
+        unsafe { core::ptr::write_volatile((base() + MTIMECMP + 8 * hart) as *mut u64, value) };
+    }
+}
+
 /// Convert RISC-V timer ticks to nanoseconds.
 pub fn ticks_to_ns(ticks: u64) -> u64 {
     let freq = TIMER_FREQ.load(Ordering::Relaxed);
@@ -389,26 +623,23 @@ pub fn ns_to_ticks(ns: u64) -> u64 {
 /// RISC-V-specific timer interrupt handler.
 pub unsafe fn timer_interrupt_handler() {
     unsafe {
-        // Clear timer interrupt by setting stimecmp to max value
-        asm!(
-            "csrw stimecmp, {val}",
-            val = in(reg) u64::MAX,
-            options(nomem, nostack)
-        );
+        // Clear timer interrupt by setting the compare value to max
+        set_timer_compare(u64::MAX);
         
-        // Get scheduler reference and handle preemption
-        if let Some(scheduler) = crate::scheduler::get_global_scheduler() {
-            scheduler.handle_timer_interrupt();
-        }
+        // Let the global scheduler preempt the current thread
+        crate::sched::global::timer_tick();
         
-        // Re-setup timer for next preemption (1ms default)
-        if setup_preemption_timer(1000).is_err() {
-            // Timer setup failed, disable preemption
-            return;
-        }
+        // Re-setup timer for next preemption, unless a switch already did
+        crate::sched::dispatch::rearm_timer();
     }
 }
 
+/// Re-arm the preemption timer (1ms default) after it fired.
+fn rearm_preemption_timer() {
+    // On failure the timer stays off, disabling preemption
+    let _ = unsafe { setup_preemption_timer(1000) };
+}
+
 /// Memory barrier operations for RISC-V.
 pub fn memory_barrier_full() {
     unsafe {
@@ -457,16 +688,16 @@ pub unsafe fn save_vector_state(ctx: &mut RiscvContext) {
         // Save vector CSRs
         asm!(
             "csrr t0, vstart",
-            "sd t0, 528({ctx})",      // vstart offset
+            "sd t0, 0({csrs})",   // vstart
             "csrr t0, vxsat", 
-            "sd t0, 536({ctx})",      // vxsat offset
+            "sd t0, 8({csrs})",   // vxsat
             "csrr t0, vxrm",
-            "sd t0, 544({ctx})",      // vxrm offset
+            "sd t0, 16({csrs})",  // vxrm
             "csrr t0, vl",
-            "sd t0, 552({ctx})",      // vl offset
+            "sd t0, 24({csrs})",  // vl
             "csrr t0, vtype",
-            "sd t0, 560({ctx})",      // vtype offset
-            ctx = in(reg) ctx,
+            "sd t0, 32({csrs})",  // vtype
+            csrs = in(reg) core::ptr::addr_of_mut!(ctx.vstart),
             out("t0") _,
             options(nostack)
         );
@@ -477,7 +708,7 @@ pub unsafe fn save_vector_state(ctx: &mut RiscvContext) {
         // Note: Using a macro to generate const values for each register
         macro_rules! save_vreg {
             ($reg:literal) => {
-                let offset = 272 + $reg * 8; // vector_state offset
+                let offset = offsets::VECTOR_STATE + $reg * 8;
                 asm!(
                     concat!("vse64.v v", stringify!($reg), ", ({addr})"),
                     addr = in(reg) (ctx as *mut RiscvContext as usize + offset),
@@ -497,17 +728,17 @@ pub unsafe fn restore_vector_state(ctx: &RiscvContext) {
     unsafe {
         // Restore vector CSRs first
         asm!(
-            "ld t0, 528({ctx})",      // vstart offset
+            "ld t0, 0({csrs})",   // vstart
             "csrw vstart, t0",
-            "ld t0, 536({ctx})",      // vxsat offset
+            "ld t0, 8({csrs})",   // vxsat
             "csrw vxsat, t0",
-            "ld t0, 544({ctx})",      // vxrm offset
+            "ld t0, 16({csrs})",  // vxrm
             "csrw vxrm, t0", 
-            "ld t0, 552({ctx})",      // vl offset
+            "ld t0, 24({csrs})",  // vl
             "csrw vl, t0",
-            "ld t0, 560({ctx})",      // vtype offset  
+            "ld t0, 32({csrs})",  // vtype
             "csrw vtype, t0",
-            ctx = in(reg) ctx,
+            csrs = in(reg) core::ptr::addr_of!(ctx.vstart),
             out("t0") _,
             options(nostack)
         );
@@ -516,7 +747,7 @@ pub unsafe fn restore_vector_state(ctx: &RiscvContext) {
         // Note: Using a macro to generate const values for each register
         macro_rules! restore_vreg {
             ($reg:literal) => {
-                let offset = 272 + $reg * 8; // vector_state offset
+                let offset = offsets::VECTOR_STATE + $reg * 8;
                 asm!(
                     concat!("vle64.v v", stringify!($reg), ", ({addr})"),
                     addr = in(reg) (ctx as *const RiscvContext as usize + offset),
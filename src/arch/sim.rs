@@ -0,0 +1,111 @@
+//! Virtual-time architecture backend for deterministic simulation.
+//!
+//! With the `sim` feature and no hardware architecture enabled, [`SimArch`]
+//! is the default architecture. Nothing runs on real hardware: time is the
+//! [`VIRTUAL_CLOCK`], which only moves when the simulation advances it, so
+//! [`Instant::now`](crate::time::Instant::now) and the CPU clock return the
+//! same values on every run. Context switches don't transfer control; each
+//! one is counted and charged a configurable cost in virtual time.
+//! Interrupts are not modelled and always read as enabled.
+//!
+//! [`crate::sim::Simulator`] drives schedulers on top of this backend.
+
+use super::Arch;
+use crate::time::{Duration, Instant};
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Simulated time source.
+pub struct VirtualClock {
+    /// Current virtual time (nanoseconds)
+    now: AtomicU64,
+    /// Virtual time charged per context switch (nanoseconds)
+    switch_cost: AtomicU64,
+    /// Context switches performed through [`SimArch`]
+    switches: AtomicU64,
+}
+
+impl VirtualClock {
+    const fn new() -> Self {
+        Self {
+            now: AtomicU64::new(0),
+            switch_cost: AtomicU64::new(0),
+            switches: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the current virtual time.
+    pub fn now(&self) -> Instant {
+        Instant::from_nanos(self.now.load(Ordering::Acquire))
+    }
+
+    /// Move virtual time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.now.fetch_add(duration.as_nanos(), Ordering::AcqRel);
+    }
+
+    /// Move virtual time forward to `instant`; earlier instants are ignored,
+    /// time never goes backwards.
+    pub fn advance_to(&self, instant: Instant) {
+        self.now.fetch_max(instant.as_nanos(), Ordering::AcqRel);
+    }
+
+    /// Set the virtual time every context switch costs.
+    pub fn set_switch_cost(&self, cost: Duration) {
+        self.switch_cost.store(cost.as_nanos(), Ordering::Release);
+    }
+
+    /// Get the virtual time every context switch costs.
+    pub fn switch_cost(&self) -> Duration {
+        Duration::from_nanos(self.switch_cost.load(Ordering::Acquire))
+    }
+
+    /// Get the number of context switches performed so far.
+    pub fn switches(&self) -> u64 {
+        self.switches.load(Ordering::Acquire)
+    }
+}
+
+/// The virtual clock all simulated CPUs share.
+pub static VIRTUAL_CLOCK: VirtualClock = VirtualClock::new();
+
+/// Saved state of a simulated thread.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimContext {
+    /// Virtual time the thread was last switched out (nanoseconds)
+    pub switched_out_at: u64,
+}
+
+/// Architecture backed by the virtual clock.
+pub struct SimArch;
+
+impl Arch for SimArch {
+    type SavedContext = SimContext;
+
+    unsafe fn context_switch(prev: *mut Self::SavedContext, _next: *const Self::SavedContext) {
+        let clock = &VIRTUAL_CLOCK;
+        // Safety: the caller guarantees `prev` is valid and owned by the
+        // outgoing thread
+        unsafe { (*prev).switched_out_at = clock.now().as_nanos() };
+        clock.switches.fetch_add(1, Ordering::AcqRel);
+        clock.advance(clock.switch_cost());
+    }
+
+    #[cfg(feature = "full-fpu")]
+    unsafe fn save_fpu(_ctx: &mut Self::SavedContext) {}
+
+    #[cfg(feature = "full-fpu")]
+    unsafe fn restore_fpu(_ctx: &Self::SavedContext) {}
+
+    fn enable_interrupts() {}
+
+    fn disable_interrupts() {}
+
+    fn interrupts_enabled() -> bool {
+        true
+    }
+
+    fn cycle_counter() -> Option<(u64, u64)> {
+        Some((VIRTUAL_CLOCK.now().as_nanos(), 1_000_000_000))
+    }
+}
@@ -0,0 +1,343 @@
+//! SVE register state on AArch64.
+//!
+//! SVE widens the 32 NEON registers to Z registers of an implementation
+//! defined [vector length](vector_length), 128 to 2048 bits, and adds 16
+//! predicate registers and the first-fault register. Saving all of that on
+//! every switch would tax threads that never use SVE, so state is only
+//! kept for threads that do:
+//!
+//! - Threads start with SVE disabled in `CPACR_EL1.ZEN`. The first SVE
+//!   instruction of a thread traps, and [`handle_sve_trap`] gives it a save
+//!   area sized for the vector length, clears what the last SVE user left
+//!   in the registers and enables SVE.
+//! - From then on the switch saves and restores the thread's SVE state
+//!   with its NEON state and runs it with SVE enabled, taking the full
+//!   path even between threads of one switch domain. Threads without a
+//!   save area run with SVE disabled, so they can neither read nor clobber
+//!   another thread's SVE registers.
+//!
+//! The platform's synchronous exception handler must pass SVE access traps
+//! on:
+//!
+//! ```ignore
+//! if esr >> 26 == sve::ESR_EC_SVE && unsafe { sve::handle_sve_trap() } {
+//!     return;
+//! }
+//! ```
+//!
+//! The vector length is read once by [`init`](super::aarch64::init) and
+//! assumed to be the same on every CPU.
+
+use super::aarch64::Aarch64Context;
+use core::arch::asm;
+use portable_atomic::{AtomicUsize, Ordering};
+extern crate alloc;
+use alloc::vec;
+
+/// `ESR_EL1` exception class of a trapped SVE instruction.
+pub const ESR_EC_SVE: u64 = 0x19;
+
+/// `CPACR_EL1.ZEN`, SVE enabled at EL0 and EL1 when both bits are set.
+const CPACR_ZEN: u64 = 0b11 << 16;
+
+/// Vector length in bytes, 0 without SVE.
+static VECTOR_LENGTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Read the vector length if the CPU has SVE, leaving SVE disabled.
+///
+/// # Safety
+///
+/// Must be called at EL1.
+pub(crate) unsafe fn init() {
+    if !super::detection::detect_cpu_features().supports_sve {
+        return;
+    }
+    let length: usize;
+    // Safety: SVE is implemented and enabled around `rdvl`
+    unsafe {
+        set_sve_access(true);
+        asm!(
+            ".arch_extension sve",
+            "rdvl {}, #1",
+            out(reg) length,
+            options(nomem, nostack, preserves_flags)
+        );
+        set_sve_access(false);
+    }
+    VECTOR_LENGTH.store(length, Ordering::Relaxed);
+}
+
+/// Get the SVE vector length in bytes, or 0 without SVE.
+pub fn vector_length() -> usize {
+    VECTOR_LENGTH.load(Ordering::Relaxed)
+}
+
+/// Size of a save area for a vector length of `length` bytes: 32 Z
+/// registers, then 16 predicate registers and FFR of an eighth each.
+fn area_len(length: usize) -> usize {
+    let bytes = 32 * length + 17 * (length / 8);
+    (bytes + 15) / 16
+}
+
+/// Give the current thread, which just trapped on an SVE instruction, SVE
+/// state of its own and enable SVE. Returns `false` if the trap is not
+/// one this module can resolve, such as SVE use outside any thread.
+///
+/// # Safety
+///
+/// Must be called at EL1 from the handler of an SVE access trap taken by
+/// the current thread, which resumes the trapped instruction.
+pub unsafe fn handle_sve_trap() -> bool {
+    let length = vector_length();
+    if length == 0 {
+        return false;
+    }
+    let Some(thread) = crate::thread_new::current() else {
+        return false;
+    };
+    let ctx = thread.context_ptr().cast::<Aarch64Context>();
+    if ctx.is_null() {
+        return false;
+    }
+    // Safety: a running thread's context is only touched by its own CPU
+    let ctx = unsafe { &mut *ctx };
+    if ctx.sve_state.is_none() {
+        ctx.sve_state = Some(vec![0; area_len(length)].into_boxed_slice());
+    }
+    // Safety: SVE is implemented, per `length`
+    unsafe {
+        set_sve_access(true);
+        clear_upper_state();
+    }
+    true
+}
+
+/// Enable or disable SVE at EL0 and EL1 on this CPU.
+unsafe fn set_sve_access(enabled: bool) {
+    let cpacr: u64;
+    // Safety: the caller runs at EL1
+    unsafe {
+        asm!("mrs {}, cpacr_el1", out(reg) cpacr, options(nomem, nostack, preserves_flags));
+    }
+    let wanted = if enabled { cpacr | CPACR_ZEN } else { cpacr & !CPACR_ZEN };
+    if wanted != cpacr {
+        unsafe {
+            asm!("msr cpacr_el1, {}", "isb", in(reg) wanted, options(nostack, preserves_flags));
+        }
+    }
+}
+
+/// Clear the parts of the SVE registers the NEON registers do not cover,
+/// keeping the NEON state of the current thread.
+unsafe fn clear_upper_state() {
+    // Safety: SVE is enabled; a NEON write to a V register zeroes the rest
+    // of its Z register
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            "mov v0.16b, v0.16b",
+            "mov v1.16b, v1.16b",
+            "mov v2.16b, v2.16b",
+            "mov v3.16b, v3.16b",
+            "mov v4.16b, v4.16b",
+            "mov v5.16b, v5.16b",
+            "mov v6.16b, v6.16b",
+            "mov v7.16b, v7.16b",
+            "mov v8.16b, v8.16b",
+            "mov v9.16b, v9.16b",
+            "mov v10.16b, v10.16b",
+            "mov v11.16b, v11.16b",
+            "mov v12.16b, v12.16b",
+            "mov v13.16b, v13.16b",
+            "mov v14.16b, v14.16b",
+            "mov v15.16b, v15.16b",
+            "mov v16.16b, v16.16b",
+            "mov v17.16b, v17.16b",
+            "mov v18.16b, v18.16b",
+            "mov v19.16b, v19.16b",
+            "mov v20.16b, v20.16b",
+            "mov v21.16b, v21.16b",
+            "mov v22.16b, v22.16b",
+            "mov v23.16b, v23.16b",
+            "mov v24.16b, v24.16b",
+            "mov v25.16b, v25.16b",
+            "mov v26.16b, v26.16b",
+            "mov v27.16b, v27.16b",
+            "mov v28.16b, v28.16b",
+            "mov v29.16b, v29.16b",
+            "mov v30.16b, v30.16b",
+            "mov v31.16b, v31.16b",
+            "pfalse p0.b",
+            "wrffr p0.b",
+            "pfalse p1.b",
+            "pfalse p2.b",
+            "pfalse p3.b",
+            "pfalse p4.b",
+            "pfalse p5.b",
+            "pfalse p6.b",
+            "pfalse p7.b",
+            "pfalse p8.b",
+            "pfalse p9.b",
+            "pfalse p10.b",
+            "pfalse p11.b",
+            "pfalse p12.b",
+            "pfalse p13.b",
+            "pfalse p14.b",
+            "pfalse p15.b",
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Save the SVE state of `ctx`'s thread, if it has any, after its NEON
+/// state was saved.
+///
+/// # Safety
+///
+/// Must be called at EL1 while `ctx`'s thread is switched out.
+pub(crate) unsafe fn save(ctx: &mut Aarch64Context) {
+    let Some(area) = ctx.sve_state.as_mut() else {
+        return;
+    };
+    let z = area.as_mut_ptr() as *mut u8;
+    // Safety: the area holds the Z registers followed by the predicates
+    let p = unsafe { z.add(32 * vector_length()) };
+    // Safety: SVE is enabled while a thread with a save area runs
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            "str z0, [{z}, #0, mul vl]",
+            "str z1, [{z}, #1, mul vl]",
+            "str z2, [{z}, #2, mul vl]",
+            "str z3, [{z}, #3, mul vl]",
+            "str z4, [{z}, #4, mul vl]",
+            "str z5, [{z}, #5, mul vl]",
+            "str z6, [{z}, #6, mul vl]",
+            "str z7, [{z}, #7, mul vl]",
+            "str z8, [{z}, #8, mul vl]",
+            "str z9, [{z}, #9, mul vl]",
+            "str z10, [{z}, #10, mul vl]",
+            "str z11, [{z}, #11, mul vl]",
+            "str z12, [{z}, #12, mul vl]",
+            "str z13, [{z}, #13, mul vl]",
+            "str z14, [{z}, #14, mul vl]",
+            "str z15, [{z}, #15, mul vl]",
+            "str z16, [{z}, #16, mul vl]",
+            "str z17, [{z}, #17, mul vl]",
+            "str z18, [{z}, #18, mul vl]",
+            "str z19, [{z}, #19, mul vl]",
+            "str z20, [{z}, #20, mul vl]",
+            "str z21, [{z}, #21, mul vl]",
+            "str z22, [{z}, #22, mul vl]",
+            "str z23, [{z}, #23, mul vl]",
+            "str z24, [{z}, #24, mul vl]",
+            "str z25, [{z}, #25, mul vl]",
+            "str z26, [{z}, #26, mul vl]",
+            "str z27, [{z}, #27, mul vl]",
+            "str z28, [{z}, #28, mul vl]",
+            "str z29, [{z}, #29, mul vl]",
+            "str z30, [{z}, #30, mul vl]",
+            "str z31, [{z}, #31, mul vl]",
+            "str p0, [{p}, #0, mul vl]",
+            "str p1, [{p}, #1, mul vl]",
+            "str p2, [{p}, #2, mul vl]",
+            "str p3, [{p}, #3, mul vl]",
+            "str p4, [{p}, #4, mul vl]",
+            "str p5, [{p}, #5, mul vl]",
+            "str p6, [{p}, #6, mul vl]",
+            "str p7, [{p}, #7, mul vl]",
+            "str p8, [{p}, #8, mul vl]",
+            "str p9, [{p}, #9, mul vl]",
+            "str p10, [{p}, #10, mul vl]",
+            "str p11, [{p}, #11, mul vl]",
+            "str p12, [{p}, #12, mul vl]",
+            "str p13, [{p}, #13, mul vl]",
+            "str p14, [{p}, #14, mul vl]",
+            "str p15, [{p}, #15, mul vl]",
+            "rdffr p0.b",
+            "str p0, [{p}, #16, mul vl]",
+            "ldr p0, [{p}, #0, mul vl]",
+            z = in(reg) z,
+            p = in(reg) p,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Restore the SVE state of `ctx`'s thread after its NEON state, running
+/// it with SVE enabled if it has any and disabled otherwise.
+///
+/// # Safety
+///
+/// Must be called at EL1 while `ctx`'s thread is switched in.
+pub(crate) unsafe fn restore(ctx: &Aarch64Context) {
+    let Some(area) = ctx.sve_state.as_ref() else {
+        // Safety: per the caller
+        unsafe { set_sve_access(false) };
+        return;
+    };
+    let z = area.as_ptr() as *const u8;
+    // Safety: the area holds the Z registers followed by the predicates
+    let p = unsafe { z.add(32 * vector_length()) };
+    // Safety: SVE is enabled first; the area was written by `save` or
+    // zeroed for a thread yet to be switched out
+    unsafe {
+        set_sve_access(true);
+        asm!(
+            ".arch_extension sve",
+            "ldr p0, [{p}, #16, mul vl]",
+            "wrffr p0.b",
+            "ldr p0, [{p}, #0, mul vl]",
+            "ldr p1, [{p}, #1, mul vl]",
+            "ldr p2, [{p}, #2, mul vl]",
+            "ldr p3, [{p}, #3, mul vl]",
+            "ldr p4, [{p}, #4, mul vl]",
+            "ldr p5, [{p}, #5, mul vl]",
+            "ldr p6, [{p}, #6, mul vl]",
+            "ldr p7, [{p}, #7, mul vl]",
+            "ldr p8, [{p}, #8, mul vl]",
+            "ldr p9, [{p}, #9, mul vl]",
+            "ldr p10, [{p}, #10, mul vl]",
+            "ldr p11, [{p}, #11, mul vl]",
+            "ldr p12, [{p}, #12, mul vl]",
+            "ldr p13, [{p}, #13, mul vl]",
+            "ldr p14, [{p}, #14, mul vl]",
+            "ldr p15, [{p}, #15, mul vl]",
+            "ldr z0, [{z}, #0, mul vl]",
+            "ldr z1, [{z}, #1, mul vl]",
+            "ldr z2, [{z}, #2, mul vl]",
+            "ldr z3, [{z}, #3, mul vl]",
+            "ldr z4, [{z}, #4, mul vl]",
+            "ldr z5, [{z}, #5, mul vl]",
+            "ldr z6, [{z}, #6, mul vl]",
+            "ldr z7, [{z}, #7, mul vl]",
+            "ldr z8, [{z}, #8, mul vl]",
+            "ldr z9, [{z}, #9, mul vl]",
+            "ldr z10, [{z}, #10, mul vl]",
+            "ldr z11, [{z}, #11, mul vl]",
+            "ldr z12, [{z}, #12, mul vl]",
+            "ldr z13, [{z}, #13, mul vl]",
+            "ldr z14, [{z}, #14, mul vl]",
+            "ldr z15, [{z}, #15, mul vl]",
+            "ldr z16, [{z}, #16, mul vl]",
+            "ldr z17, [{z}, #17, mul vl]",
+            "ldr z18, [{z}, #18, mul vl]",
+            "ldr z19, [{z}, #19, mul vl]",
+            "ldr z20, [{z}, #20, mul vl]",
+            "ldr z21, [{z}, #21, mul vl]",
+            "ldr z22, [{z}, #22, mul vl]",
+            "ldr z23, [{z}, #23, mul vl]",
+            "ldr z24, [{z}, #24, mul vl]",
+            "ldr z25, [{z}, #25, mul vl]",
+            "ldr z26, [{z}, #26, mul vl]",
+            "ldr z27, [{z}, #27, mul vl]",
+            "ldr z28, [{z}, #28, mul vl]",
+            "ldr z29, [{z}, #29, mul vl]",
+            "ldr z30, [{z}, #30, mul vl]",
+            "ldr z31, [{z}, #31, mul vl]",
+            z = in(reg) z,
+            p = in(reg) p,
+            options(nostack, preserves_flags)
+        );
+    }
+}
@@ -2,9 +2,20 @@
 //!
 //! This module provides x86_64-specific context switching, interrupt handling,
 //! and FPU management.
+//!
+//! Each thread has its own FS base, the thread pointer of x86_64 TLS, which
+//! the context switch saves and restores. On CPUs with FSGSBASE,
+//! [`set_cpu_id`](super::percpu::set_cpu_id) turns the instructions on for
+//! the CPU it runs on, and from then on that CPU accesses the FS and GS
+//! bases with `rdfsbase`/`wrfsbase`/`rdgsbase`/`wrgsbase` instead of MSR
+//! accesses, which take hundreds of cycles. Without FSGSBASE the FS base is
+//! tracked in the CPU's per-CPU area, so switches never read its MSR and
+//! only write it when the two threads' bases differ. [`init`] does this
+//! for the boot CPU.
 
-use super::Arch;
+use super::{Arch, ThreadEntry, UserFrame};
 use core::arch::asm;
+use portable_atomic::{AtomicPtr, AtomicU32};
 
 /// x86_64 architecture implementation.
 pub struct X86_64Arch;
@@ -14,6 +25,7 @@ pub struct X86_64Arch;
 /// This structure contains all general-purpose registers and flags
 /// needed to save and restore thread execution state.
 #[repr(C)]
+#[cfg_attr(feature = "full-fpu", repr(align(16)))]
 #[derive(Debug)]
 pub struct X86_64Context {
     /// General-purpose registers
@@ -30,6 +42,9 @@ pub struct X86_64Context {
     /// Extended FPU/SSE state (when full-fpu feature is enabled)
     #[cfg(feature = "full-fpu")]
     pub fpu_state: [u8; 512], // FXSAVE area
+    
+    /// FS base, the thread pointer; last, so the FXSAVE area stays aligned
+    pub fs_base: u64,
 }
 
 impl Default for X86_64Context {
@@ -45,6 +60,7 @@ impl Default for X86_64Context {
             rflags: 0x202, // Default RFLAGS with interrupts enabled
             #[cfg(feature = "full-fpu")]
             fpu_state: [0; 512],
+            fs_base: 0,
         }
     }
 }
@@ -52,7 +68,60 @@ impl Default for X86_64Context {
 unsafe impl Send for X86_64Context {}
 unsafe impl Sync for X86_64Context {}
 
+/// Byte offsets of [`X86_64Context`] fields.
+pub mod offsets {
+    use super::X86_64Context;
+    use crate::arch::field_offset;
+
+    pub const RSP: usize = field_offset!(X86_64Context, rsp);
+    pub const RBP: usize = field_offset!(X86_64Context, rbp);
+    pub const RBX: usize = field_offset!(X86_64Context, rbx);
+    pub const R12: usize = field_offset!(X86_64Context, r12);
+    pub const R13: usize = field_offset!(X86_64Context, r13);
+    pub const R14: usize = field_offset!(X86_64Context, r14);
+    pub const R15: usize = field_offset!(X86_64Context, r15);
+    pub const RFLAGS: usize = field_offset!(X86_64Context, rflags);
+    #[cfg(feature = "full-fpu")]
+    pub const FPU_STATE: usize = field_offset!(X86_64Context, fpu_state);
+    pub const FS_BASE: usize = field_offset!(X86_64Context, fs_base);
+}
+
+// `context_switch` hard-codes these offsets
+const _: () = {
+    assert!(offsets::RSP == 0);
+    assert!(offsets::RBP == 8);
+    assert!(offsets::RBX == 16);
+    assert!(offsets::R12 == 24);
+    assert!(offsets::R13 == 32);
+    assert!(offsets::R14 == 40);
+    assert!(offsets::R15 == 48);
+    assert!(offsets::RFLAGS == 56);
+};
+
+// FXSAVE and FXRSTOR fault on an area not aligned to 16 bytes
+#[cfg(feature = "full-fpu")]
+const _: () = assert!(offsets::FPU_STATE % 16 == 0 && core::mem::align_of::<X86_64Context>() % 16 == 0);
+
+// First code a new thread runs, with its entry point in r12 and the
+// argument in r13 (see `init_context`)
+core::arch::global_asm!(
+    ".pushsection .text.preemptive_threads_thread_stub, \"ax\"",
+    ".global preemptive_threads_thread_stub",
+    "preemptive_threads_thread_stub:",
+    "mov rdi, r13",
+    "call r12",
+    "ud2",
+    ".popsection",
+);
+
+extern "C" {
+    fn preemptive_threads_thread_stub();
+}
+
 impl Arch for X86_64Arch {
+    /// PCIDs, used only while `CR4.PCIDE` is set.
+    const ASID_BITS: u32 = 12;
+
     type SavedContext = X86_64Context;
 
     /// Perform x86_64 context switch using System V ABI calling convention.
@@ -64,39 +133,70 @@ impl Arch for X86_64Arch {
     /// - The `next` context must represent a valid execution state
     /// - Stack pointer in `next` context must point to valid, accessible memory
     unsafe fn context_switch(prev: *mut Self::SavedContext, next: *const Self::SavedContext) {
+        // The kernel keeps nothing of its own in FS, so its base is switched
+        // ahead of the registers. `fs_base` reads no MSR once the CPU has
+        // its per-CPU area.
+        unsafe {
+            let fs_base = fs_base();
+            (*prev).fs_base = fs_base;
+            if (*next).fs_base != fs_base {
+                set_fs_base((*next).fs_base);
+            }
+        }
+
         unsafe {
             asm!(
-                // Save current context
+                // Save current context, resuming at the label below
+                "lea rax, [rip + 2f]",
+                "push rax",                    // Return address for `ret`
                 "pushfq",                      // Save RFLAGS on stack
-                "pop {rflags}",               // Pop into temp register
-                "mov qword ptr [{prev} + 56], {rflags}",   // Store RFLAGS
-                "mov qword ptr [{prev} + 0], rsp",         // Save RSP
-                "mov qword ptr [{prev} + 8], rbp",         // Save RBP
-                "mov qword ptr [{prev} + 16], rbx",        // Save RBX
-                "mov qword ptr [{prev} + 24], r12",        // Save R12
-                "mov qword ptr [{prev} + 32], r13",        // Save R13
-                "mov qword ptr [{prev} + 40], r14",        // Save R14
-                "mov qword ptr [{prev} + 48], r15",        // Save R15
+                "pop rax",                     // Pop into temp register
+                "mov qword ptr [rdi + 56], rax",           // Store RFLAGS
+                "mov qword ptr [rdi + 0], rsp",            // Save RSP
+                "mov qword ptr [rdi + 8], rbp",            // Save RBP
+                "mov qword ptr [rdi + 16], rbx",           // Save RBX
+                "mov qword ptr [rdi + 24], r12",           // Save R12
+                "mov qword ptr [rdi + 32], r13",           // Save R13
+                "mov qword ptr [rdi + 40], r14",           // Save R14
+                "mov qword ptr [rdi + 48], r15",           // Save R15
                 
                 // Restore next context
-                "mov rsp, qword ptr [{next} + 0]",         // Restore RSP
-                "mov rbp, qword ptr [{next} + 8]",         // Restore RBP
-                "mov rbx, qword ptr [{next} + 16]",        // Restore RBX
-                "mov r12, qword ptr [{next} + 24]",        // Restore R12
-                "mov r13, qword ptr [{next} + 32]",        // Restore R13
-                "mov r14, qword ptr [{next} + 40]",        // Restore R14
-                "mov r15, qword ptr [{next} + 48]",        // Restore R15
-                "push qword ptr [{next} + 56]",            // Push RFLAGS onto stack
+                "mov rsp, qword ptr [rsi + 0]",            // Restore RSP
+                "mov rbp, qword ptr [rsi + 8]",            // Restore RBP
+                "mov rbx, qword ptr [rsi + 16]",           // Restore RBX
+                "mov r12, qword ptr [rsi + 24]",           // Restore R12
+                "mov r13, qword ptr [rsi + 32]",           // Restore R13
+                "mov r14, qword ptr [rsi + 40]",           // Restore R14
+                "mov r15, qword ptr [rsi + 48]",           // Restore R15
+                "push qword ptr [rsi + 56]",               // Push RFLAGS onto stack
                 "popfq",                      // Restore RFLAGS
+                "ret",                        // Resume `next`, or start it
                 
-                prev = in(reg) prev,
-                next = in(reg) next,
-                rflags = out(reg) _,
-                options(nostack)
+                "2:",
+                // Pinned to registers the restore does not overwrite
+                in("rdi") prev,
+                in("rsi") next,
+                // Only the callee-saved registers survive the switch
+                clobber_abi("C"),
             );
         }
     }
 
+    unsafe fn init_context(stack_top: *mut u8, entry: ThreadEntry, arg: usize) -> Option<Self::SavedContext> {
+        // `context_switch` returns into the stub, leaving the stack 16-byte
+        // aligned for its call
+        let sp = (stack_top as usize & !15) - 8;
+        // Safety: the caller guarantees the stack is writable
+        unsafe { (sp as *mut u64).write(preemptive_threads_thread_stub as usize as u64) };
+        Some(X86_64Context {
+            rsp: sp as u64,
+            r12: entry as usize as u64,
+            r13: arg as u64,
+            rflags: 0x2, // Interrupts stay disabled until the thread enables them
+            ..X86_64Context::default()
+        })
+    }
+
     #[cfg(feature = "full-fpu")]
     unsafe fn save_fpu(ctx: &mut Self::SavedContext) {
         unsafe {
@@ -138,6 +238,509 @@ impl Arch for X86_64Arch {
         }
         (flags & 0x200) != 0 // Test interrupt flag (IF)
     }
+
+    fn saved_stack_pointer(ctx: &Self::SavedContext) -> Option<usize> {
+        Some(ctx.rsp as usize)
+    }
+
+    fn relocate_stack(ctx: &mut Self::SavedContext, old_low: usize, old_high: usize, new_low: usize) {
+        ctx.rsp = super::relocate_pointer(ctx.rsp, old_low, old_high, new_low);
+        ctx.rbp = super::relocate_pointer(ctx.rbp, old_low, old_high, new_low);
+    }
+
+    fn cycle_counter() -> Option<(u64, u64)> {
+        // The TSC frequency is only known once the APIC timer calibrated it
+        let frequency = crate::time::x86_64_timer::tsc_frequency();
+        if frequency == 0 {
+            return None;
+        }
+
+        let low: u32;
+        let high: u32;
+        unsafe {
+            asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+        }
+        Some((((high as u64) << 32) | low as u64, frequency))
+    }
+
+    unsafe fn switch_address_space(root: usize, asid: u16, flush: bool) {
+        let cr3 = if pcid_enabled() {
+            // Bit 63 keeps the PCID's TLB entries across the load
+            let keep = if flush { 0 } else { CR3_NOFLUSH };
+            root as u64 | (asid as u64 & 0xfff) | keep
+        } else {
+            // Without PCIDs every CR3 load flushes non-global entries
+            root as u64
+        };
+
+        // Safety: `root` maps the running code and stack per the caller
+        unsafe {
+            asm!("mov cr3, {}", in(reg) cr3, options(nostack, preserves_flags));
+        }
+    }
+
+    unsafe fn flush_tlb(asid: Option<u16>) {
+        let cr4: u64;
+        unsafe {
+            asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        }
+
+        match asid {
+            Some(asid) if pcid_enabled() && invpcid_supported() => {
+                // Single-context invalidation of one PCID
+                let descriptor: [u64; 2] = [asid as u64 & 0xfff, 0];
+                unsafe {
+                    asm!(
+                        "invpcid {}, [{}]",
+                        in(reg) 1u64,
+                        in(reg) descriptor.as_ptr(),
+                        options(nostack, preserves_flags)
+                    );
+                }
+            }
+            _ => {
+                // Toggling CR4.PGE drops every entry of every PCID
+                unsafe {
+                    asm!(
+                        "mov cr4, {}",
+                        "mov cr4, {}",
+                        in(reg) cr4 ^ CR4_PGE,
+                        in(reg) cr4,
+                        options(nostack, preserves_flags)
+                    );
+                }
+            }
+        }
+    }
+
+    /// Return with `iretq`, which also restores the user segments.
+    ///
+    /// The live GS base points at the kernel's per-CPU area, which
+    /// [`cpu_id`] reads, so this `swapgs`es user code's base in from
+    /// `IA32_KERNEL_GS_BASE` first. The platform's trap entry must `swapgs`
+    /// back on traps from user mode.
+    ///
+    /// [`cpu_id`]: super::percpu::cpu_id
+    unsafe fn return_to_user(frame: &UserFrame) -> ! {
+        use portable_atomic::Ordering;
+
+        let selectors = USER_SELECTORS.load(Ordering::Relaxed);
+        let code = (selectors & 0xffff) as u64 | 3;
+        let stack = (selectors >> 16) as u64 | 3;
+        let rflags = (frame.flags as u64 & USER_RFLAGS) | RFLAGS_IF | RFLAGS_RESERVED;
+
+        // Safety: the caller guarantees a user-accessible frame
+        unsafe {
+            asm!(
+                // Nothing may run on the kernel side with the user GS base
+                "cli",
+                "push {ss}",
+                "push {sp}",
+                "push {rflags}",
+                "push {cs}",
+                "push {pc}",
+                "xor ebx, ebx",
+                "xor ecx, ecx",
+                "xor edx, edx",
+                "xor esi, esi",
+                "xor edi, edi",
+                "xor ebp, ebp",
+                "xor r8d, r8d",
+                "xor r9d, r9d",
+                "xor r10d, r10d",
+                "xor r11d, r11d",
+                "xor r12d, r12d",
+                "xor r13d, r13d",
+                "xor r14d, r14d",
+                "xor r15d, r15d",
+                "swapgs",
+                "iretq",
+                ss = in(reg) stack,
+                sp = in(reg) frame.sp as u64,
+                rflags = in(reg) rflags,
+                cs = in(reg) code,
+                pc = in(reg) frame.pc as u64,
+                in("rax") frame.ret as u64,
+                options(noreturn)
+            );
+        }
+    }
+
+    unsafe fn set_kernel_stack(top: usize) {
+        use portable_atomic::Ordering;
+
+        let tss = TSS.load(Ordering::Acquire);
+        if !tss.is_null() {
+            // Safety: `set_tss` requires a valid TSS; RSP0 is unaligned
+            unsafe { tss.add(TSS_RSP0).cast::<u64>().write_unaligned(top as u64) };
+        }
+    }
+
+    /// Issue an IBPB, if the CPU supports it, so the next thread can't
+    /// steer indirect branches with predictions trained by the previous
+    /// one, then refill the return stack buffer so `ret` predictions don't
+    /// come from it either.
+    fn speculation_barrier() {
+        if ibpb_supported() {
+            // Safety: IA32_PRED_CMD exists when IBPB is enumerated; writing
+            // bit 0 only flushes predictor state
+            unsafe {
+                asm!(
+                    "wrmsr",
+                    in("ecx") IA32_PRED_CMD,
+                    in("eax") PRED_CMD_IBPB,
+                    in("edx") 0u32,
+                    options(nomem, nostack, preserves_flags)
+                );
+            }
+        }
+
+        // Safety: every call is balanced by the final `add`; the speculation
+        // traps after each call are never reached architecturally
+        unsafe {
+            asm!(
+                "mov {n:e}, 16",
+                "2:",
+                "call 4f",
+                "3:",
+                "pause",
+                "lfence",
+                "jmp 3b",
+                "4:",
+                "call 6f",
+                "5:",
+                "pause",
+                "lfence",
+                "jmp 5b",
+                "6:",
+                "dec {n:e}",
+                "jnz 2b",
+                "add rsp, 256",
+                "lfence",
+                n = out(reg) _,
+            );
+        }
+    }
+
+    fn user_counter() -> Option<u64> {
+        // Safety: RDTSC is allowed in user mode unless CR4.TSD is set
+        Some(unsafe { core::arch::x86_64::_rdtsc() })
+    }
+
+    /// Store `cpu` in IA32_TSC_AUX, for `rdtscp` to return. This is the
+    /// same value [`set_cpu_id`](super::percpu::set_cpu_id) stores.
+    unsafe fn publish_cpu_id(cpu: usize) {
+        if rdtscp_supported() {
+            // Safety: IA32_TSC_AUX exists when RDTSCP is enumerated
+            unsafe { write_tsc_aux(cpu) };
+        }
+    }
+
+    fn user_cpu_id() -> Option<usize> {
+        if !rdtscp_supported() {
+            return None;
+        }
+        let mut aux = 0u32;
+        // Safety: RDTSCP is enumerated and allowed in user mode
+        unsafe { core::arch::x86_64::__rdtscp(&mut aux) };
+        // Whatever firmware left there isn't a CPU id of ours
+        (aux & TSC_AUX_PUBLISHED != 0).then_some((aux & !TSC_AUX_PUBLISHED) as usize)
+    }
+}
+
+/// RFLAGS bits user code may set: CF, PF, AF, ZF, SF, DF and OF.
+const USER_RFLAGS: u64 = 0xcd5;
+/// RFLAGS interrupt enable.
+const RFLAGS_IF: u64 = 1 << 9;
+/// RFLAGS bit 1, always set.
+const RFLAGS_RESERVED: u64 = 1 << 1;
+/// Offset of RSP0 in the 64-bit TSS.
+const TSS_RSP0: usize = 4;
+
+/// User code (low half) and stack (high half) selectors.
+static USER_SELECTORS: AtomicU32 = AtomicU32::new((0x2b << 16) | 0x33);
+/// TSS of this system, for the kernel stack of traps from user mode.
+static TSS: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set the GDT selectors of the user code and stack segments.
+///
+/// Defaults to `0x33` and `0x2b`, the usual 64-bit user selectors. The
+/// requested privilege level is forced to 3.
+pub fn set_user_selectors(code: u16, stack: u16) {
+    USER_SELECTORS.store(((stack as u32) << 16) | code as u32, portable_atomic::Ordering::Relaxed);
+}
+
+/// Register the TSS whose RSP0 is updated for each user thread.
+///
+/// # Safety
+///
+/// `tss` must point to the loaded 64-bit TSS and stay valid. With one TSS
+/// per CPU, platforms should instead update RSP0 themselves.
+pub unsafe fn set_tss(tss: *mut u8) {
+    TSS.store(tss, portable_atomic::Ordering::Release);
+}
+
+/// CR3 bit that keeps the new PCID's TLB entries on load.
+const CR3_NOFLUSH: u64 = 1 << 63;
+/// CR4 global-pages enable.
+const CR4_PGE: u64 = 1 << 7;
+/// CR4 PCID enable.
+const CR4_PCIDE: u64 = 1 << 17;
+
+/// Check whether PCIDs are enabled in CR4.
+fn pcid_enabled() -> bool {
+    let cr4: u64;
+    // Safety: reading CR4 has no side effects at ring 0, where address
+    // spaces are switched
+    unsafe {
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    cr4 & CR4_PCIDE != 0
+}
+
+/// Check CPUID for INVPCID (leaf 7, EBX bit 10).
+fn invpcid_supported() -> bool {
+    use core::arch::x86_64::__cpuid_count;
+
+    // Safety: CPUID is available on every x86_64 CPU
+    unsafe { __cpuid_count(0, 0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 10) != 0 }
+}
+
+/// Prediction command MSR.
+const IA32_PRED_CMD: u32 = 0x49;
+/// Indirect branch prediction barrier bit of `IA32_PRED_CMD`.
+const PRED_CMD_IBPB: u32 = 1;
+
+/// IBPB support: 0 = not probed, 1 = absent, 2 = present.
+static IBPB_SUPPORT: portable_atomic::AtomicU8 = portable_atomic::AtomicU8::new(0);
+
+/// Check CPUID for IBPB, on Intel (leaf 7, EDX bit 26) or AMD (leaf
+/// 0x8000_0008, EBX bit 12).
+fn ibpb_supported() -> bool {
+    use core::arch::x86_64::__cpuid_count;
+    use portable_atomic::Ordering;
+
+    match IBPB_SUPPORT.load(Ordering::Relaxed) {
+        1 => return false,
+        2 => return true,
+        _ => {}
+    }
+
+    // Safety: CPUID is available on every x86_64 CPU
+    let supported = unsafe {
+        let intel = __cpuid_count(0, 0).eax >= 7 && __cpuid_count(7, 0).edx & (1 << 26) != 0;
+        let amd = __cpuid_count(0x8000_0000, 0).eax >= 0x8000_0008
+            && __cpuid_count(0x8000_0008, 0).ebx & (1 << 12) != 0;
+        intel || amd
+    };
+    IBPB_SUPPORT.store(if supported { 2 } else { 1 }, Ordering::Relaxed);
+    supported
+}
+
+/// IA32_TSC_AUX, returned in ECX by `rdtscp`.
+const IA32_TSC_AUX: u32 = 0xC000_0103;
+
+/// Set in IA32_TSC_AUX next to the CPU id once this crate has stored it.
+pub(super) const TSC_AUX_PUBLISHED: u32 = 1 << 31;
+
+/// Store `cpu`, tagged with [`TSC_AUX_PUBLISHED`], in IA32_TSC_AUX.
+///
+/// # Safety
+///
+/// Must be called at ring 0 on a CPU that has IA32_TSC_AUX.
+pub(super) unsafe fn write_tsc_aux(cpu: usize) {
+    // Safety: passed on from the caller
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") IA32_TSC_AUX,
+            in("eax") cpu as u32 | TSC_AUX_PUBLISHED,
+            in("edx") 0u32,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// RDTSCP support: 0 = not probed, 1 = absent, 2 = present.
+static RDTSCP_SUPPORT: portable_atomic::AtomicU8 = portable_atomic::AtomicU8::new(0);
+
+/// Check CPUID leaf 0x8000_0001, EDX bit 27, for RDTSCP.
+pub(super) fn rdtscp_supported() -> bool {
+    use core::arch::x86_64::__cpuid_count;
+    use portable_atomic::Ordering;
+
+    match RDTSCP_SUPPORT.load(Ordering::Relaxed) {
+        1 => return false,
+        2 => return true,
+        _ => {}
+    }
+
+    // Safety: CPUID is available on every x86_64 CPU
+    let supported = unsafe {
+        __cpuid_count(0x8000_0000, 0).eax >= 0x8000_0001
+            && __cpuid_count(0x8000_0001, 0).edx & (1 << 27) != 0
+    };
+    RDTSCP_SUPPORT.store(if supported { 2 } else { 1 }, Ordering::Relaxed);
+    supported
+}
+
+/// FS base MSR.
+const IA32_FS_BASE: u32 = 0xC000_0100;
+/// GS base MSR, the base in use while in the kernel.
+const IA32_GS_BASE: u32 = 0xC000_0101;
+/// GS base MSR `swapgs` exchanges with the live one, user code's base while
+/// in the kernel.
+const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+/// CR4 FS/GS base instructions enable.
+const CR4_FSGSBASE: u64 = 1 << 16;
+
+/// Set `CR4.FSGSBASE` on this CPU if [detection](super::detection) finds
+/// FSGSBASE support. Returns whether it did.
+///
+/// # Safety
+///
+/// Must be called at ring 0.
+pub(super) unsafe fn enable_fsgsbase() -> bool {
+    // Safety: passed on from the caller
+    unsafe { enable_fsgsbase_if(super::detection::detect_cpu_features().supports_fsgsbase) }
+}
+
+/// Set `CR4.FSGSBASE` on this CPU if `supported`. Returns whether it did.
+///
+/// # Safety
+///
+/// Must be called at ring 0, and `supported` only if the CPU has FSGSBASE.
+unsafe fn enable_fsgsbase_if(supported: bool) -> bool {
+    if !supported {
+        return false;
+    }
+    // Safety: the caller runs at ring 0; the bit only enables instructions
+    unsafe {
+        asm!(
+            "mov {tmp}, cr4",
+            "or {tmp}, {bit}",
+            "mov cr4, {tmp}",
+            tmp = out(reg) _,
+            bit = in(reg) CR4_FSGSBASE,
+            options(nostack, preserves_flags)
+        );
+    }
+    true
+}
+
+/// Check whether this CPU accesses the FS and GS bases with the FS/GS base
+/// instructions.
+#[inline(always)]
+pub fn fsgsbase_enabled() -> bool {
+    super::percpu::fsgsbase_enabled()
+}
+
+/// Read an FS or GS base MSR.
+unsafe fn read_base_msr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    // Safety: the caller runs at ring 0, where the base MSRs exist
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Read the FS base MSR, for [`set_cpu_id`](super::percpu::set_cpu_id) to
+/// start tracking the base from.
+///
+/// # Safety
+///
+/// Must be called at ring 0.
+pub(super) unsafe fn read_fs_base_msr() -> u64 {
+    unsafe { read_base_msr(IA32_FS_BASE) }
+}
+
+/// Write an FS or GS base MSR.
+unsafe fn write_base_msr(msr: u32, value: u64) {
+    // Safety: the caller runs at ring 0 with a canonical `value`
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Get the FS base of this CPU.
+///
+/// Without FSGSBASE this is the base last set with [`set_fs_base`], which
+/// is tracked per CPU, and the MSR is only read before
+/// [`set_cpu_id`](super::percpu::set_cpu_id) has run. With it, the base is
+/// read back, as user code may have moved it with `wrfsbase`.
+#[inline(always)]
+pub fn fs_base() -> u64 {
+    if fsgsbase_enabled() {
+        let base: u64;
+        // Safety: `CR4.FSGSBASE` is set on this CPU
+        unsafe { asm!("rdfsbase {}", out(reg) base, options(nomem, nostack, preserves_flags)) };
+        base
+    } else if let Some(base) = super::percpu::tracked_fs_base() {
+        base
+    } else {
+        // Safety: FS bases are only switched at ring 0
+        unsafe { read_base_msr(IA32_FS_BASE) }
+    }
+}
+
+/// Set the FS base of this CPU.
+///
+/// # Safety
+///
+/// Must be called at ring 0 with a canonical address. Code relying on the
+/// current thread's TLS sees `base` from now on.
+#[inline(always)]
+pub unsafe fn set_fs_base(base: u64) {
+    if fsgsbase_enabled() {
+        // Safety: `CR4.FSGSBASE` is set on this CPU
+        unsafe { asm!("wrfsbase {}", in(reg) base, options(nostack, preserves_flags)) };
+    } else {
+        unsafe { write_base_msr(IA32_FS_BASE, base) };
+    }
+    super::percpu::track_fs_base(base);
+}
+
+/// Get the GS base of this CPU.
+///
+/// Returns the kernel's base as long as no `swapgs` is pending.
+#[inline(always)]
+pub fn gs_base() -> u64 {
+    if fsgsbase_enabled() {
+        let base: u64;
+        // Safety: `CR4.FSGSBASE` is set on this CPU
+        unsafe { asm!("rdgsbase {}", out(reg) base, options(nomem, nostack, preserves_flags)) };
+        base
+    } else {
+        // Safety: the GS base is only read at ring 0
+        unsafe { read_base_msr(IA32_GS_BASE) }
+    }
+}
+
+/// Point the GS base of this CPU at `base` through its MSR.
+///
+/// # Safety
+///
+/// Must be called at ring 0 with a canonical address.
+pub(super) unsafe fn write_gs_base_msr(base: u64) {
+    unsafe { write_base_msr(IA32_GS_BASE, base) };
+}
+
+/// Set the GS base user code gets on this CPU's next return to user mode.
+///
+/// # Safety
+///
+/// Must be called at ring 0 with a canonical address, while no `swapgs`
+/// is pending.
+pub(super) unsafe fn write_kernel_gs_base_msr(base: u64) {
+    unsafe { write_base_msr(IA32_KERNEL_GS_BASE, base) };
 }
 
 /// Initialize x86_64-specific features.
@@ -149,6 +752,9 @@ impl Arch for X86_64Arch {
 ///
 /// Must be called once during system initialization with interrupts disabled.
 pub unsafe fn init() {
+    // Give the boot CPU its per-CPU area, turning on FSGSBASE if it has it
+    unsafe { super::percpu::set_cpu_id(0) };
+
     // Initialize timer subsystem
     #[cfg(feature = "x86_64")]
     {
@@ -223,4 +829,15 @@ pub unsafe fn flush_icache() {
     // x86_64 has coherent instruction cache - no explicit flush needed
     // Just ensure all stores are visible
     memory_barrier_full();
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fsgsbase_left_off_without_support() {
+        // Without FSGSBASE, CR4 is not touched, so this runs in user mode
+        assert!(!unsafe { enable_fsgsbase_if(false) });
+        assert!(!fsgsbase_enabled());
+    }
+}
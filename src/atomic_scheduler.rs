@@ -1,92 +1,125 @@
-use crate::error::{ThreadError, ThreadResult};
-use crate::thread::{Thread, ThreadId, ThreadState};
-use core::cell::UnsafeCell;
-use core::mem::MaybeUninit;
-use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
-
-const MAX_THREADS: usize = 32;
-const PRIORITY_LEVELS: usize = 8;
+//! Lock-free priority scheduler.
+//!
+//! [`AtomicScheduler`] keeps one bounded lock-free ring of ready threads
+//! per priority band and a bitmap of the bands that hold threads, so
+//! picking the next thread is a bit scan and one dequeue. It implements
+//! [`Scheduler`] and is the default [global
+//! scheduler](crate::sched::global): preemption checkpoints, timer ticks
+//! and the [`crate::scheduler`] API all queue threads here unless another
+//! scheduler is installed.
+//!
+//! Threads of the same band run round robin. A band whose ring is full
+//! spills into an overflow queue that refills the ring in order, so no
+//! thread is ever dropped or overtaken.
+
+use crate::errors::ThreadResult;
+use crate::sched::{CpuId, Scheduler};
+use crate::scheduler::SCHEDULER;
+use crate::sync::{IrqSafe, SpinLockIrqSave};
+use crate::thread::{Thread as OldThread, ThreadId as OldThreadId};
+use crate::thread_new::{ReadyRef, RunningRef, Thread, ThreadId};
+use portable_atomic::{AtomicU32, AtomicUsize, Ordering};
+extern crate alloc;
+use alloc::collections::VecDeque;
+
+/// Number of priority bands; priority `p` is queued in band `p >> 5`.
+pub const PRIORITY_LEVELS: usize = 8;
+
+/// Slots in each band's ring. Must be a power of two.
+const RING_CAPACITY: usize = 64;
+
+/// Get the band threads of `priority` are queued in.
+fn level_of(priority: u8) -> usize {
+    priority as usize >> 5
+}
 
-/// Lock-free priority queue for thread scheduling
-pub struct PriorityQueue {
-    /// Per-priority circular buffers
-    queues: [CircularBuffer; PRIORITY_LEVELS],
-    /// Bitmap of non-empty priority levels
-    priority_bitmap: AtomicU32,
+/// One slot of a [`Ring`].
+struct Slot {
+    /// Position the slot is next written (`== pos`) or read (`== pos + 1`) at
+    seq: AtomicUsize,
+    /// Reference from `Thread::into_raw`
+    thread: AtomicUsize,
 }
 
-struct CircularBuffer {
-    buffer: [AtomicUsize; MAX_THREADS],
+/// Bounded MPMC ring of threads (Vyukov's algorithm).
+///
+/// Neither side waits on the other, so both are safe from interrupt
+/// handlers. A dequeue can miss a thread whose enqueue is between claiming
+/// and filling its slot; it is returned by a later dequeue.
+struct Ring {
+    slots: [Slot; RING_CAPACITY],
     head: AtomicUsize,
     tail: AtomicUsize,
 }
 
-impl CircularBuffer {
-    #[allow(dead_code)]
-    fn new() -> Self {
+impl Ring {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: Slot = Slot {
+            seq: AtomicUsize::new(0),
+            thread: AtomicUsize::new(0),
+        };
+        let mut slots = [EMPTY; RING_CAPACITY];
+        let mut i = 0;
+        while i < RING_CAPACITY {
+            slots[i] = Slot {
+                seq: AtomicUsize::new(i),
+                thread: AtomicUsize::new(0),
+            };
+            i += 1;
+        }
         Self {
-            buffer: core::array::from_fn(|_| AtomicUsize::new(usize::MAX)),
+            slots,
             head: AtomicUsize::new(0),
             tail: AtomicUsize::new(0),
         }
     }
 
-    fn enqueue(&self, thread_id: ThreadId) -> bool {
-        let mut tail = self.tail.load(Ordering::Acquire);
-
+    /// Queue `thread`, or give it back if the ring is full.
+    fn enqueue(&self, thread: ReadyRef) -> Result<(), ReadyRef> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
         loop {
-            let next_tail = (tail + 1) % MAX_THREADS;
-            let head = self.head.load(Ordering::Acquire);
-
-            if next_tail == head {
-                return false; // Queue full
-            }
-
-            match self.tail.compare_exchange_weak(
-                tail,
-                next_tail,
-                Ordering::Release,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => {
-                    self.buffer[tail].store(thread_id, Ordering::Release);
-                    return true;
+            let slot = &self.slots[pos & (RING_CAPACITY - 1)];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq as isize - pos as isize;
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        slot.thread.store(thread.0.into_raw() as usize, Ordering::Relaxed);
+                        slot.seq.store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(actual) => pos = actual,
                 }
-                Err(actual) => tail = actual,
+            } else if diff < 0 {
+                return Err(thread);
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
             }
         }
     }
 
-    fn dequeue(&self) -> Option<ThreadId> {
-        let mut head = self.head.load(Ordering::Acquire);
-
+    fn dequeue(&self) -> Option<ReadyRef> {
+        let mut pos = self.head.load(Ordering::Relaxed);
         loop {
-            let tail = self.tail.load(Ordering::Acquire);
-
-            if head == tail {
-                return None; // Queue empty
-            }
-
-            let thread_id = self.buffer[head].load(Ordering::Acquire);
-            if thread_id == usize::MAX {
-                // Spurious empty slot, try next
-                head = (head + 1) % MAX_THREADS;
-                continue;
-            }
-
-            let next_head = (head + 1) % MAX_THREADS;
-
-            match self.head.compare_exchange_weak(
-                head,
-                next_head,
-                Ordering::Release,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => {
-                    self.buffer[head].store(usize::MAX, Ordering::Release);
-                    return Some(thread_id);
+            let slot = &self.slots[pos & (RING_CAPACITY - 1)];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq as isize - (pos + 1) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let raw = slot.thread.swap(0, Ordering::Relaxed);
+                        slot.seq.store(pos + RING_CAPACITY, Ordering::Release);
+                        // Safety: the slot held the reference from `enqueue`,
+                        // and winning the head CAS makes it ours alone
+                        return Some(ReadyRef(unsafe { Thread::from_raw(raw as *const ()) }));
+                    }
+                    Err(actual) => pos = actual,
                 }
-                Err(actual) => head = actual,
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
             }
         }
     }
@@ -96,96 +129,102 @@ impl CircularBuffer {
     }
 }
 
-impl PriorityQueue {
-    #[allow(dead_code)]
-    fn new() -> Self {
+impl Drop for Ring {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {}
+    }
+}
+
+/// Ready threads of one priority band.
+///
+/// Once threads spill into `overflow`, later ones queue behind them there
+/// and each dequeue moves them into the ring as it frees up, so the band
+/// stays first in, first out.
+struct Level {
+    ring: Ring,
+    /// Threads that did not fit in the ring
+    overflow: SpinLockIrqSave<VecDeque<ReadyRef>>,
+    /// Number of threads in `overflow`, read without the lock
+    spilled: AtomicUsize,
+}
+
+impl Level {
+    const fn new() -> Self {
         Self {
-            queues: core::array::from_fn(|_| CircularBuffer::new()),
-            priority_bitmap: AtomicU32::new(0),
+            ring: Ring::new(),
+            overflow: SpinLockIrqSave::new(VecDeque::new()),
+            spilled: AtomicUsize::new(0),
         }
     }
 
-    fn enqueue(&self, thread_id: ThreadId, priority: u8) -> bool {
-        let priority_level = (priority as usize).min(PRIORITY_LEVELS - 1);
-
-        if self.queues[priority_level].enqueue(thread_id) {
-            // Set bit in bitmap to indicate non-empty queue
-            self.priority_bitmap
-                .fetch_or(1 << priority_level, Ordering::Release);
-            true
+    fn enqueue(&self, thread: ReadyRef) {
+        let thread = if self.spilled.load(Ordering::Acquire) == 0 {
+            match self.ring.enqueue(thread) {
+                Ok(()) => return,
+                Err(thread) => thread,
+            }
         } else {
-            false
-        }
+            thread
+        };
+        let mut overflow = self.overflow.lock();
+        overflow.push_back(thread);
+        self.spilled.store(overflow.len(), Ordering::Release);
     }
 
-    fn dequeue(&self) -> Option<ThreadId> {
-        let mut bitmap = self.priority_bitmap.load(Ordering::Acquire);
+    fn dequeue(&self) -> Option<ReadyRef> {
+        let thread = self.ring.dequeue();
+        if self.spilled.load(Ordering::Acquire) == 0 {
+            return thread;
+        }
 
-        while bitmap != 0 {
-            // Find highest priority non-empty queue (MSB)
-            let priority_level = 31 - bitmap.leading_zeros() as usize;
-
-            if let Some(thread_id) = self.queues[priority_level].dequeue() {
-                // Check if queue is now empty and clear bit
-                if self.queues[priority_level].is_empty() {
-                    self.priority_bitmap
-                        .fetch_and(!(1 << priority_level), Ordering::Release);
-                }
-                return Some(thread_id);
+        let mut overflow = self.overflow.lock();
+        while let Some(spilled) = overflow.pop_front() {
+            if let Err(spilled) = self.ring.enqueue(spilled) {
+                overflow.push_front(spilled);
+                break;
             }
-
-            // Queue was empty, clear bit and retry
-            bitmap &= !(1 << priority_level);
         }
+        self.spilled.store(overflow.len(), Ordering::Release);
+        match thread {
+            Some(thread) => Some(thread),
+            None => self.ring.dequeue().or_else(|| {
+                let thread = overflow.pop_front();
+                self.spilled.store(overflow.len(), Ordering::Release);
+                thread
+            }),
+        }
+    }
 
-        None
+    fn is_empty(&self) -> bool {
+        self.ring.is_empty() && self.overflow.lock().is_empty()
     }
 }
 
-/// Per-CPU scheduler state
-pub struct CpuScheduler {
-    /// Current running thread on this CPU
-    current_thread: AtomicUsize,
-    /// CPU-local run queue for better cache locality
-    local_queue: UnsafeCell<CircularBuffer>,
-    /// Is this CPU idle?
-    idle: AtomicBool,
+/// Formerly the run queues of an [`AtomicScheduler`], which are now
+/// internal.
+#[deprecated(since = "0.5.0", note = "the run queues are internal; queue threads through `sched::Scheduler`")]
+pub struct PriorityQueue {
+    _private: (),
 }
 
-unsafe impl Sync for CpuScheduler {}
-
-impl CpuScheduler {
-    const fn new() -> Self {
-        Self {
-            current_thread: AtomicUsize::new(usize::MAX),
-            local_queue: UnsafeCell::new(CircularBuffer {
-                buffer: [const { AtomicUsize::new(usize::MAX) }; MAX_THREADS],
-                head: AtomicUsize::new(0),
-                tail: AtomicUsize::new(0),
-            }),
-            idle: AtomicBool::new(true),
-        }
-    }
+/// Formerly the per-CPU state of an [`AtomicScheduler`], which is now
+/// kept by [`thread_new::percpu`](crate::thread_new::percpu).
+#[deprecated(since = "0.5.0", note = "the running thread is kept by `thread_new::percpu`")]
+pub struct CpuScheduler {
+    _private: (),
 }
 
-/// Thread-safe atomic scheduler
+/// Lock-free priority scheduler with one run queue shared by all CPUs.
 pub struct AtomicScheduler {
-    /// Thread pool
-    threads: [UnsafeCell<MaybeUninit<Thread>>; MAX_THREADS],
-    /// Thread allocation bitmap
-    thread_bitmap: AtomicU32,
-    /// Next thread ID counter
-    #[allow(dead_code)]
-    next_thread_id: AtomicUsize,
-    /// Global priority queue
-    global_queue: UnsafeCell<PriorityQueue>,
-    /// Per-CPU schedulers (we'll use just one for now)
-    cpu_schedulers: [CpuScheduler; 1],
-    /// Scheduler lock for critical sections
-    scheduler_lock: AtomicBool,
+    levels: [Level; PRIORITY_LEVELS],
+    /// Bit `n` set when band `n` may hold threads
+    bitmap: AtomicU32,
+    /// Number of queued threads
+    queued: AtomicUsize,
 }
 
-unsafe impl Sync for AtomicScheduler {}
+// Safety: the rings are lock-free and the overflow queues disable interrupts
+unsafe impl IrqSafe for AtomicScheduler {}
 
 impl Default for AtomicScheduler {
     fn default() -> Self {
@@ -194,223 +233,213 @@ impl Default for AtomicScheduler {
 }
 
 impl AtomicScheduler {
+    /// Create a scheduler with empty run queues.
     pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const LEVEL: Level = Level::new();
         Self {
-            threads: [const { UnsafeCell::new(MaybeUninit::uninit()) }; MAX_THREADS],
-            thread_bitmap: AtomicU32::new(0),
-            next_thread_id: AtomicUsize::new(0),
-            global_queue: UnsafeCell::new(PriorityQueue {
-                queues: [const {
-                    CircularBuffer {
-                        buffer: [const { AtomicUsize::new(usize::MAX) }; MAX_THREADS],
-                        head: AtomicUsize::new(0),
-                        tail: AtomicUsize::new(0),
-                    }
-                }; PRIORITY_LEVELS],
-                priority_bitmap: AtomicU32::new(0),
-            }),
-            cpu_schedulers: [CpuScheduler::new(); 1],
-            scheduler_lock: AtomicBool::new(false),
+            levels: [LEVEL; PRIORITY_LEVELS],
+            bitmap: AtomicU32::new(0),
+            queued: AtomicUsize::new(0),
         }
     }
 
-    /// Acquire scheduler lock with exponential backoff
-    fn acquire_lock(&self) {
-        let mut backoff = 1;
-        while self
-            .scheduler_lock
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
-            for _ in 0..backoff {
-                core::hint::spin_loop();
-            }
-            backoff = (backoff * 2).min(1024);
-        }
+    /// Get the number of queued threads.
+    pub fn len(&self) -> usize {
+        self.queued.load(Ordering::Acquire)
     }
 
-    /// Release scheduler lock
-    fn release_lock(&self) {
-        self.scheduler_lock.store(false, Ordering::Release);
-    }
-
-    pub fn spawn_thread(
-        &self,
-        stack: &'static mut [u8],
-        entry_point: fn(),
-        priority: u8,
-    ) -> ThreadResult<ThreadId> {
-        // Find free thread slot
-        let mut bitmap = self.thread_bitmap.load(Ordering::Acquire);
-        let mut slot = None;
-
-        for i in 0..MAX_THREADS {
-            if bitmap & (1 << i) == 0 {
-                // Try to claim this slot
-                if self
-                    .thread_bitmap
-                    .compare_exchange_weak(
-                        bitmap,
-                        bitmap | (1 << i),
-                        Ordering::AcqRel,
-                        Ordering::Acquire,
-                    )
-                    .is_ok()
-                {
-                    slot = Some(i);
-                    break;
-                }
-                // Reload bitmap and retry
-                bitmap = self.thread_bitmap.load(Ordering::Acquire);
-            }
-        }
+    /// Check whether no thread is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        let slot = slot.ok_or(ThreadError::MaxThreadsReached)?;
-        let thread_id = slot;
+    /// Check whether a thread of `priority`'s band or a higher one is queued.
+    fn has_work_at_or_above(&self, priority: u8) -> bool {
+        self.bitmap.load(Ordering::Acquire) >> level_of(priority) != 0
+    }
+}
 
-        // Initialize thread
-        let thread = Thread::new(thread_id, stack, entry_point, priority);
+/// The original API, which kept a table of threads of its own. It now
+/// forwards to [`SCHEDULER`], whose ready threads queue in the [global
+/// scheduler](crate::sched::global). Like [`SCHEDULER`], it must not be
+/// used from several CPUs at once.
+impl AtomicScheduler {
+    #[deprecated(since = "0.5.0", note = "use `Kernel::spawn`, or `SCHEDULER` for threads on caller-provided stacks")]
+    pub fn spawn_thread(&self, stack: &'static mut [u8], entry_point: fn(), priority: u8) -> ThreadResult<OldThreadId> {
+        old_api().spawn_thread(stack, entry_point, priority)
+    }
 
-        unsafe {
-            (*self.threads[slot].get()).write(thread);
-        }
+    #[deprecated(since = "0.5.0", note = "use `Scheduler::pick_next`, or `SCHEDULER`")]
+    pub fn schedule(&self) -> Option<OldThreadId> {
+        old_api().schedule()
+    }
 
-        // Add to run queue
-        let global_queue = unsafe { &*self.global_queue.get() };
-        if !global_queue.enqueue(thread_id, priority) {
-            // Failed to enqueue, free the slot
-            self.thread_bitmap
-                .fetch_and(!(1 << slot), Ordering::Release);
-            return Err(ThreadError::SchedulerFull);
-        }
+    #[deprecated(since = "0.5.0", note = "use `thread_new::current`, or `SCHEDULER`")]
+    pub fn get_current_thread(&self) -> Option<OldThreadId> {
+        old_api().get_current_thread()
+    }
 
-        Ok(thread_id)
+    #[deprecated(since = "0.5.0", note = "use `SCHEDULER`")]
+    pub fn set_current_thread(&self, thread_id: Option<OldThreadId>) {
+        old_api().set_current_thread(thread_id)
     }
 
-    pub fn schedule(&self) -> Option<ThreadId> {
-        let cpu = &self.cpu_schedulers[0];
+    #[deprecated(since = "0.5.0", note = "use `SCHEDULER`")]
+    pub fn get_thread(&self, thread_id: OldThreadId) -> Option<&OldThread> {
+        old_api().get_thread(thread_id)
+    }
 
-        // Try local queue first for better cache locality
-        let local_queue = unsafe { &*cpu.local_queue.get() };
+    /// # Safety
+    /// Returns mutable reference to thread. Caller must ensure thread safety.
+    #[deprecated(since = "0.5.0", note = "use `SCHEDULER`")]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_thread_mut(&self, thread_id: OldThreadId) -> Option<&mut OldThread> {
+        old_api().get_thread_mut(thread_id)
+    }
 
-        if let Some(thread_id) = local_queue.dequeue() {
-            return Some(thread_id);
-        }
+    #[deprecated(since = "0.5.0", note = "use `SCHEDULER`")]
+    pub fn exit_current_thread(&self) {
+        old_api().exit_current_thread()
+    }
 
-        // Fall back to global queue
-        let global_queue = unsafe { &*self.global_queue.get() };
-        global_queue.dequeue()
+    #[deprecated(since = "0.5.0", note = "use `SCHEDULER`")]
+    pub fn switch_context(&self, from_id: OldThreadId, to_id: OldThreadId) -> ThreadResult<()> {
+        old_api().switch_context(from_id, to_id)
     }
+}
 
-    pub fn get_current_thread(&self) -> Option<ThreadId> {
-        let cpu = &self.cpu_schedulers[0];
-        let current = cpu.current_thread.load(Ordering::Acquire);
+/// Get the scheduler the original [`AtomicScheduler`] API forwards to.
+fn old_api() -> &'static mut crate::scheduler::Scheduler {
+    // Safety: callers of the original API keep to one CPU at a time
+    unsafe { SCHEDULER.get() }
+}
 
-        if current == usize::MAX {
-            None
-        } else {
-            Some(current)
-        }
+impl Scheduler for AtomicScheduler {
+    fn enqueue(&self, thread: ReadyRef) {
+        let level = level_of(thread.priority());
+        self.levels[level].enqueue(thread);
+        self.queued.fetch_add(1, Ordering::AcqRel);
+        // Set after the push, so a picker that clears the bit either sees
+        // the thread when it re-checks the band or sees the bit set again
+        self.bitmap.fetch_or(1 << level, Ordering::SeqCst);
     }
 
-    pub fn set_current_thread(&self, thread_id: Option<ThreadId>) {
-        let cpu = &self.cpu_schedulers[0];
-
-        match thread_id {
-            Some(id) => {
-                cpu.current_thread.store(id, Ordering::Release);
-                cpu.idle.store(false, Ordering::Release);
+    fn pick_next(&self, _cpu_id: CpuId) -> Option<ReadyRef> {
+        let mut bitmap = self.bitmap.load(Ordering::SeqCst);
+        while bitmap != 0 {
+            let level = 31 - bitmap.leading_zeros() as usize;
+            if let Some(thread) = self.levels[level].dequeue() {
+                self.queued.fetch_sub(1, Ordering::AcqRel);
+                return Some(thread);
             }
-            None => {
-                cpu.current_thread.store(usize::MAX, Ordering::Release);
-                cpu.idle.store(true, Ordering::Release);
+
+            // Clear the bit of the empty band, restoring it if an enqueue
+            // raced with us
+            self.bitmap.fetch_and(!(1 << level), Ordering::SeqCst);
+            if !self.levels[level].is_empty() {
+                self.bitmap.fetch_or(1 << level, Ordering::SeqCst);
             }
+            bitmap &= !(1 << level);
         }
+        None
     }
 
-    pub fn get_thread(&self, thread_id: ThreadId) -> Option<&Thread> {
-        if thread_id >= MAX_THREADS {
-            return None;
-        }
-
-        let bitmap = self.thread_bitmap.load(Ordering::Acquire);
-        if bitmap & (1 << thread_id) == 0 {
-            return None;
-        }
-
-        unsafe { Some((*self.threads[thread_id].get()).assume_init_ref()) }
+    fn has_higher_priority_work(&self, _cpu_id: CpuId, priority: u8) -> bool {
+        self.bitmap.load(Ordering::Acquire) >> level_of(priority) >> 1 != 0
     }
 
-    /// # Safety
-    /// Returns mutable reference to thread. Caller must ensure thread safety.
-    #[allow(clippy::mut_from_ref)]
-    pub unsafe fn get_thread_mut(&self, thread_id: ThreadId) -> Option<&mut Thread> {
-        if thread_id >= MAX_THREADS {
-            return None;
+    fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+        // Preempt at the end of the time slice, but only for a thread of
+        // the same band or a higher one
+        if current.should_preempt() && self.has_work_at_or_above(current.priority()) {
+            Some(current.prepare_preemption())
+        } else {
+            None
         }
+    }
 
-        let bitmap = self.thread_bitmap.load(Ordering::Acquire);
-        if bitmap & (1 << thread_id) == 0 {
-            return None;
-        }
+    fn set_priority(&self, thread_id: ThreadId, priority: u8) {
+        // The band is chosen from the thread's priority when it is next
+        // enqueued
+        let _ = (thread_id, priority);
+    }
 
-        Some(unsafe { (*self.threads[thread_id].get()).assume_init_mut() })
+    fn stats(&self) -> (usize, usize, usize) {
+        let queued = self.len();
+        (queued, queued, 0)
     }
+}
 
-    pub fn exit_current_thread(&self) {
-        if let Some(thread_id) = self.get_current_thread() {
-            self.acquire_lock();
-
-            unsafe {
-                if let Some(thread) = self.get_thread_mut(thread_id) {
-                    thread.state = ThreadState::Finished;
-
-                    // Wake up joiners
-                    for waiter_id in thread.join_waiters.iter().flatten() {
-                        if let Some(waiter) = self.get_thread_mut(*waiter_id) {
-                            if waiter.state == ThreadState::Blocked {
-                                waiter.state = ThreadState::Ready;
-                                let global_queue = &*self.global_queue.get();
-                                let _ = global_queue.enqueue(*waiter_id, waiter.priority);
-                            }
-                        }
-                    }
-                }
-            }
+/// Default global scheduler.
+pub static ATOMIC_SCHEDULER: AtomicScheduler = AtomicScheduler::new();
 
-            self.release_lock();
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
 
-            // Clear current thread
-            self.set_current_thread(None);
-        }
+    fn ready(pool: &StackPool, id: usize, priority: u8) -> ReadyRef {
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let (thread, _) = Thread::new(ThreadId::new(id as u64), stack, || {}, priority);
+        ReadyRef(thread)
     }
 
-    pub fn switch_context(&self, from_id: ThreadId, to_id: ThreadId) -> ThreadResult<()> {
-        // Validate thread IDs
-        if from_id >= MAX_THREADS || to_id >= MAX_THREADS {
-            return Err(ThreadError::InvalidThreadId);
+    #[test]
+    fn test_priority_order_and_overflow() {
+        let pool = StackPool::new();
+        let scheduler = AtomicScheduler::new();
+
+        scheduler.enqueue(ready(&pool, 49_300, 40));
+        scheduler.enqueue(ready(&pool, 49_301, 250));
+        scheduler.enqueue(ready(&pool, 49_302, 128));
+        scheduler.enqueue(ready(&pool, 49_303, 130));
+
+        // Highest band first, round robin within a band
+        let order: alloc::vec::Vec<u64> =
+            core::iter::from_fn(|| scheduler.pick_next(0)).map(|t| t.id().as_u64()).collect();
+        assert_eq!(order, [49_301, 49_302, 49_303, 49_300]);
+        assert!(scheduler.is_empty());
+
+        // A full ring spills into the overflow queue without losing threads
+        for i in 0..RING_CAPACITY + 4 {
+            scheduler.enqueue(ready(&pool, 49_310 + i, 0));
         }
-
-        let bitmap = self.thread_bitmap.load(Ordering::Acquire);
-        if bitmap & (1 << from_id) == 0 || bitmap & (1 << to_id) == 0 {
-            return Err(ThreadError::InvalidThreadId);
+        assert_eq!(scheduler.stats(), (RING_CAPACITY + 4, RING_CAPACITY + 4, 0));
+        for i in 0..RING_CAPACITY + 4 {
+            assert_eq!(scheduler.pick_next(0).unwrap().id().as_u64(), (49_310 + i) as u64);
         }
+        assert!(scheduler.pick_next(0).is_none());
+    }
 
-        unsafe {
-            // Get thread pointers
-            let from_thread = self
-                .get_thread_mut(from_id)
-                .ok_or(ThreadError::InvalidThreadId)?;
-            let from_context = &mut from_thread.context as *mut _;
-
-            let to_thread = self.get_thread(to_id).ok_or(ThreadError::InvalidThreadId)?;
-            let to_context = &to_thread.context as *const _;
+    #[test]
+    fn test_full_band_stays_round_robin() {
+        let pool = StackPool::new();
+        let scheduler = AtomicScheduler::new();
+        let count = RING_CAPACITY + 4;
+        for i in 0..count {
+            scheduler.enqueue(ready(&pool, 49_400 + i, 0));
+        }
 
-            crate::context::switch_context(from_context, to_context);
+        // Preempted threads go back behind the spilled ones, so every
+        // thread runs once per round
+        for _ in 0..3 {
+            for i in 0..count {
+                let thread = scheduler.pick_next(0).unwrap();
+                assert_eq!(thread.id().as_u64(), (49_400 + i) as u64);
+                scheduler.enqueue(thread);
+            }
         }
+        assert_eq!(scheduler.len(), count);
+    }
 
-        Ok(())
+    #[test]
+    #[allow(deprecated)]
+    fn test_original_api_forwards_to_scheduler() {
+        let _slot = crate::thread_new::percpu::hold_slot();
+        let stack = alloc::boxed::Box::leak(alloc::vec![0u8; 16 * 1024].into_boxed_slice());
+        let id = ATOMIC_SCHEDULER.spawn_thread(stack, || {}, 7).unwrap();
+        assert_eq!(ATOMIC_SCHEDULER.get_thread(id).map(|thread| thread.priority), Some(7));
+        assert_eq!(ATOMIC_SCHEDULER.schedule(), Some(id));
     }
 }
-
-pub static ATOMIC_SCHEDULER: AtomicScheduler = AtomicScheduler::new();
\ No newline at end of file
@@ -0,0 +1,233 @@
+//! Command handlers for interactive debug consoles.
+//!
+//! Embedded shells read a line from their UART or debug probe and hand it
+//! to [`execute`], which runs the command and writes its output to any
+//! [`fmt::Write`]. [`execute_bytes`] does the same over a byte stream
+//! implementing [`crate::io::Write`]. Each handler is also public so shells
+//! can bind them to their own command names.
+//!
+//! ```text
+//! > prio 7 200
+//! thread 7: priority 10 -> 200
+//! ```
+
+use crate::kernel;
+use crate::observability::{GLOBAL_METRICS, HEALTH_MONITOR};
+use crate::security::audit::{self, ExportFormat};
+use crate::thread_new::{registry, PriorityDenied, ThreadId};
+extern crate alloc;
+use core::fmt;
+
+/// Commands understood by [`execute`], with their usage and description.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("help", "list commands"),
+    ("ps", "list live threads"),
+    ("metrics", "dump system metrics"),
+    ("prio <thread> <priority>", "change a thread's priority"),
+    ("health", "run a health check"),
+    ("audit [json|csv|plain]", "export the audit log"),
+];
+
+/// Errors returned by console commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// The command name is not known
+    UnknownCommand,
+    /// A required argument is missing
+    MissingArgument(&'static str),
+    /// An argument could not be parsed
+    InvalidArgument(&'static str),
+    /// No live thread has the given ID
+    NoSuchThread(ThreadId),
+    /// The priority policy refused the change
+    Denied(PriorityDenied),
+    /// The output could not be written
+    Output,
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleError::UnknownCommand => write!(f, "unknown command (try `help`)"),
+            ConsoleError::MissingArgument(name) => write!(f, "missing argument: {}", name),
+            ConsoleError::InvalidArgument(name) => write!(f, "invalid argument: {}", name),
+            ConsoleError::NoSuchThread(id) => write!(f, "no such thread: {}", id),
+            ConsoleError::Denied(denied) => write!(f, "{}", denied),
+            ConsoleError::Output => write!(f, "output error"),
+        }
+    }
+}
+
+impl From<fmt::Error> for ConsoleError {
+    fn from(_: fmt::Error) -> Self {
+        ConsoleError::Output
+    }
+}
+
+/// Run one command line, writing its output to `out`.
+///
+/// Blank lines do nothing. Errors are returned rather than written, so the
+/// shell decides how to report them.
+pub fn execute<W: fmt::Write>(line: &str, out: &mut W) -> Result<(), ConsoleError> {
+    let mut args = line.split_whitespace();
+    let Some(command) = args.next() else {
+        return Ok(());
+    };
+
+    match command {
+        "help" => help(out),
+        "ps" => list_threads(out),
+        "metrics" => dump_metrics(out),
+        "prio" => {
+            let thread = parse_thread(args.next())?;
+            let priority = args
+                .next()
+                .ok_or(ConsoleError::MissingArgument("priority"))?
+                .parse()
+                .map_err(|_| ConsoleError::InvalidArgument("priority"))?;
+            set_priority(out, thread, priority)
+        }
+        "health" => health_check(out),
+        "audit" => {
+            let format = match args.next() {
+                None | Some("plain") => ExportFormat::Plain,
+                Some("json") => ExportFormat::Json,
+                Some("csv") => ExportFormat::Csv,
+                Some(_) => return Err(ConsoleError::InvalidArgument("format")),
+            };
+            export_audit(out, format)
+        }
+        _ => Err(ConsoleError::UnknownCommand),
+    }
+}
+
+/// Run one command line read from a byte stream, writing its output to
+/// `out` as UTF-8.
+pub fn execute_bytes<W: crate::io::Write>(line: &[u8], out: &mut W) -> Result<(), ConsoleError> {
+    let line = core::str::from_utf8(line).map_err(|_| ConsoleError::InvalidArgument("line"))?;
+    execute(line, &mut ByteSink(out))
+}
+
+/// List the available commands.
+pub fn help<W: fmt::Write>(out: &mut W) -> Result<(), ConsoleError> {
+    for (usage, description) in COMMANDS {
+        writeln!(out, "{:<26} {}", usage, description)?;
+    }
+    Ok(())
+}
+
+/// List live threads as a `ps`-style table.
+pub fn list_threads<W: fmt::Write>(out: &mut W) -> Result<(), ConsoleError> {
+    out.write_str(&kernel::format_threads())?;
+    Ok(())
+}
+
+/// Dump system-wide metrics, one per line.
+pub fn dump_metrics<W: fmt::Write>(out: &mut W) -> Result<(), ConsoleError> {
+    let system = GLOBAL_METRICS.generate_report().system;
+
+    writeln!(out, "{:<24}{}", "threads created:", system.threads_created)?;
+    writeln!(out, "{:<24}{}", "threads destroyed:", system.threads_destroyed)?;
+    writeln!(out, "{:<24}{}", "active threads:", system.active_threads)?;
+    writeln!(out, "{:<24}{}", "context switches:", system.total_context_switches)?;
+    writeln!(out, "{:<24}{:.1}", "switches per second:", system.context_switches_per_second)?;
+    writeln!(out, "{:<24}{}", "cpu time (ns):", system.total_cpu_time_ns)?;
+    writeln!(out, "{:<24}{:.1}%", "cpu utilization:", system.cpu_utilization * 100.0)?;
+    writeln!(out, "{:<24}{}", "memory in use:", system.current_memory_usage)?;
+    writeln!(out, "{:<24}{}", "peak memory:", system.peak_memory_usage)?;
+    writeln!(out, "{:<24}{}", "preempt disables:", system.preempt_disables)?;
+    writeln!(out, "{:<24}{}", "max preempt depth:", system.max_preempt_depth)?;
+    writeln!(out, "{:<24}{}", "might-sleep violations:", system.might_sleep_violations)?;
+    writeln!(out, "{:<24}{}", "priority inversions:", system.priority_inversions)?;
+    writeln!(out, "{:<24}{}", "I/O wait (ns):", system.total_io_wait_ns)?;
+    writeln!(out, "{:<24}{}", "other blocked (ns):", system.total_blocked_ns)?;
+    Ok(())
+}
+
+/// Change the priority of a live thread.
+///
+/// The change is put to the priority policy and recorded in the audit log.
+pub fn set_priority<W: fmt::Write>(out: &mut W, thread: ThreadId, priority: u8) -> Result<(), ConsoleError> {
+    let handle = registry::lookup(thread).ok_or(ConsoleError::NoSuchThread(thread))?;
+    let old = handle.priority();
+    handle.set_priority(priority).map_err(ConsoleError::Denied)?;
+
+    writeln!(out, "thread {}: priority {} -> {}", thread, old, priority)?;
+    Ok(())
+}
+
+/// Run a health check and list any issues it finds.
+pub fn health_check<W: fmt::Write>(out: &mut W) -> Result<(), ConsoleError> {
+    let health = HEALTH_MONITOR.check_health();
+
+    writeln!(out, "status: {:?}", health.overall_status)?;
+    for issue in &health.active_issues {
+        writeln!(
+            out,
+            "  [{:?}] {}: {}",
+            issue.severity, issue.component, issue.description
+        )?;
+    }
+    if health.active_issues.is_empty() {
+        writeln!(out, "  no issues")?;
+    }
+    Ok(())
+}
+
+/// Export the audit log in `format`.
+pub fn export_audit<W: fmt::Write>(out: &mut W, format: ExportFormat) -> Result<(), ConsoleError> {
+    let log = audit::export_audit_events(format).map_err(|_| ConsoleError::Output)?;
+    out.write_str(&log)?;
+    Ok(())
+}
+
+fn parse_thread(arg: Option<&str>) -> Result<ThreadId, ConsoleError> {
+    match arg.ok_or(ConsoleError::MissingArgument("thread"))?.parse::<u64>() {
+        Ok(id) if id > 0 => Ok(ThreadId::new(id)),
+        _ => Err(ConsoleError::InvalidArgument("thread")),
+    }
+}
+
+/// Adapts a byte stream to [`fmt::Write`].
+struct ByteSink<'a, W: crate::io::Write>(&'a mut W);
+
+impl<'a, W: crate::io::Write> fmt::Write for ByteSink<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_console_commands() {
+        let mut out = String::new();
+        execute("  ", &mut out).unwrap();
+        assert!(out.is_empty());
+
+        execute("help", &mut out).unwrap();
+        assert_eq!(out.lines().count(), COMMANDS.len());
+
+        out.clear();
+        execute("ps", &mut out).unwrap();
+        assert!(out.starts_with(kernel::ThreadInfo::HEADER));
+
+        assert_eq!(execute("frobnicate", &mut out), Err(ConsoleError::UnknownCommand));
+        assert_eq!(execute("prio", &mut out), Err(ConsoleError::MissingArgument("thread")));
+        assert_eq!(execute("prio 0 5", &mut out), Err(ConsoleError::InvalidArgument("thread")));
+        assert_eq!(execute("prio 42424 999", &mut out), Err(ConsoleError::InvalidArgument("priority")));
+        assert_eq!(
+            execute("prio 42424 5", &mut out),
+            Err(ConsoleError::NoSuchThread(ThreadId::new(42_424)))
+        );
+        assert_eq!(execute("audit xml", &mut out), Err(ConsoleError::InvalidArgument("format")));
+
+        let mut bytes = Vec::new();
+        execute_bytes(b"metrics", &mut bytes).unwrap();
+        assert!(core::str::from_utf8(&bytes).unwrap().starts_with("threads created:"));
+    }
+}
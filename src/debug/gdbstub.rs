@@ -0,0 +1,649 @@
+//! GDB remote serial protocol stub.
+//!
+//! Serves the GDB remote protocol over any byte transport (UART, USB CDC,
+//! a socket) supplied by the integrator. The stub runs in all-stop mode:
+//! while it is in control, every other thread is suspended at its next
+//! preemption checkpoint.
+//!
+//! Each [`ThreadId`] is reported to GDB as one thread. Registers come from
+//! the thread's saved context and memory reads are limited to the stacks of
+//! stopped threads. Breakpoints and single-stepping need architecture
+//! support and are delegated to a [`BreakpointHook`]; the architecture's
+//! debug exception path must call [`on_debug_trap`] so the stub learns
+//! that a thread stopped.
+
+use super::{inspect, DebugCapability, InspectError, SavedContext};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{self, registry, Thread, ThreadId};
+use alloc::format;
+use alloc::vec::Vec;
+
+/// Maximum packet payload size advertised to GDB.
+pub const MAX_PACKET_SIZE: usize = 4096;
+
+/// Signal reported in every stop reply (SIGTRAP).
+const SIGTRAP: u8 = 5;
+
+/// Threads that took a debug trap and have not yet been picked up by the
+/// stub. Each one holds a suspension that the stub takes over.
+static TRAPPED: SpinLockIrqSave<Vec<ThreadId>> = SpinLockIrqSave::new(Vec::new());
+
+/// Byte transport to the debugger.
+pub trait Transport {
+    /// Read one byte, blocking until it arrives.
+    ///
+    /// Returns `None` when the connection has been closed.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// Read one byte if one is available, without blocking.
+    ///
+    /// Used to notice GDB's interrupt request while threads are running.
+    fn poll_byte(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Write bytes to the debugger.
+    fn write_all(&mut self, bytes: &[u8]);
+}
+
+/// Architecture hook for breakpoints and single-stepping.
+pub trait BreakpointHook {
+    /// Insert a breakpoint of GDB type `kind` (0 = software, 1 = hardware)
+    /// covering `size` bytes at `addr`.
+    fn insert_breakpoint(&mut self, kind: u8, addr: usize, size: usize) -> bool;
+
+    /// Remove a breakpoint previously inserted with the same arguments.
+    fn remove_breakpoint(&mut self, kind: u8, addr: usize, size: usize) -> bool;
+
+    /// Arm a single step in a suspended thread's saved context, for example
+    /// by setting the trap flag.
+    fn arm_single_step(&mut self, ctx: &mut SavedContext) -> bool;
+}
+
+/// Hook for targets without breakpoint support.
+pub struct NoBreakpoints;
+
+impl BreakpointHook for NoBreakpoints {
+    fn insert_breakpoint(&mut self, _kind: u8, _addr: usize, _size: usize) -> bool {
+        false
+    }
+
+    fn remove_breakpoint(&mut self, _kind: u8, _addr: usize, _size: usize) -> bool {
+        false
+    }
+
+    fn arm_single_step(&mut self, _ctx: &mut SavedContext) -> bool {
+        false
+    }
+}
+
+/// Why [`GdbStub::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StubExit {
+    /// GDB detached; all threads were resumed
+    Detached,
+    /// GDB sent a kill request; all threads were resumed
+    Killed,
+    /// The transport was closed; all threads were resumed
+    Disconnected,
+}
+
+/// Report that the current thread hit a breakpoint or finished a step.
+///
+/// Call from the architecture's debug exception path once it is safe to
+/// block, i.e. after leaving interrupt context. The thread stays parked
+/// until the debugger resumes it.
+pub fn on_debug_trap() {
+    if let Some(thread) = thread_new::current() {
+        if thread.suspend().is_ok() {
+            TRAPPED.lock().push(thread.id());
+            thread_new::park_while_suspended(&thread);
+        }
+    }
+}
+
+/// Result of handling one packet.
+enum Action {
+    /// Send this payload
+    Reply(Vec<u8>),
+    /// Leave the command loop
+    Exit(StubExit),
+}
+
+/// GDB remote protocol server.
+pub struct GdbStub<'a, T: Transport, H: BreakpointHook> {
+    cap: &'a DebugCapability,
+    transport: T,
+    hook: H,
+    /// Threads suspended by the stub, resumed on continue and exit
+    stopped: Vec<Thread>,
+    /// Thread selected for register access with `Hg`
+    selected: Option<ThreadId>,
+    /// Thread reported in the last stop reply
+    stop_thread: Option<ThreadId>,
+}
+
+impl<'a, T: Transport, H: BreakpointHook> GdbStub<'a, T, H> {
+    /// Create a stub serving GDB over `transport`.
+    pub fn new(cap: &'a DebugCapability, transport: T, hook: H) -> Self {
+        Self {
+            cap,
+            transport,
+            hook,
+            stopped: Vec::new(),
+            selected: None,
+            stop_thread: None,
+        }
+    }
+
+    /// Stop all other threads and serve GDB until it detaches.
+    ///
+    /// Must be called from a thread context that is allowed to block.
+    pub fn run(&mut self) -> StubExit {
+        self.stop_all();
+
+        let exit = loop {
+            let Some(packet) = self.read_packet() else {
+                break StubExit::Disconnected;
+            };
+
+            match self.handle(&packet) {
+                Action::Reply(reply) => self.send_packet(&reply),
+                Action::Exit(exit) => break exit,
+            }
+        };
+
+        self.resume_all();
+        exit
+    }
+
+    /// Suspend every thread except the caller.
+    fn stop_all(&mut self) {
+        // Trapped threads are already suspended; adopt their suspension
+        let trapped: Vec<ThreadId> = core::mem::take(&mut *TRAPPED.lock());
+        for id in &trapped {
+            if let Some(thread) = registry::lookup(*id) {
+                self.stopped.push(thread);
+            }
+        }
+        if let Some(id) = trapped.last() {
+            self.stop_thread = Some(*id);
+        }
+
+        let me = thread_new::current().map(|thread| thread.id());
+        for id in registry::ids() {
+            if Some(id) == me || self.stopped.iter().any(|thread| thread.id() == id) {
+                continue;
+            }
+            if let Some(thread) = registry::lookup(id) {
+                if thread.suspend().is_ok() {
+                    self.stopped.push(thread);
+                }
+            }
+        }
+
+        if self.stop_thread.is_none() {
+            self.stop_thread = self.stopped.first().map(|thread| thread.id());
+        }
+    }
+
+    /// Resume every thread the stub suspended.
+    fn resume_all(&mut self) {
+        for thread in self.stopped.drain(..) {
+            let _ = thread.resume();
+        }
+    }
+
+    /// Let threads run until one traps or GDB interrupts, then stop again.
+    fn wait_for_stop(&mut self) {
+        loop {
+            if !TRAPPED.lock().is_empty() {
+                break;
+            }
+            if self.transport.poll_byte() == Some(0x03) {
+                break;
+            }
+            crate::sync::yield_thread();
+        }
+        self.stop_thread = None;
+        self.stop_all();
+    }
+
+    /// Handle one packet payload.
+    fn handle(&mut self, packet: &[u8]) -> Action {
+        let Some((&command, args)) = packet.split_first() else {
+            return Action::Reply(Vec::new());
+        };
+
+        match command {
+            b'?' => Action::Reply(self.stop_reply()),
+            b'q' => Action::Reply(self.handle_query(args)),
+            b'H' => {
+                if let Some((&op, id)) = args.split_first() {
+                    if op == b'g' {
+                        self.selected = parse_thread_id(id);
+                    }
+                }
+                Action::Reply(b"OK".to_vec())
+            }
+            b'T' => {
+                let alive = parse_thread_id(args).and_then(registry::lookup).is_some();
+                Action::Reply(if alive { b"OK".to_vec() } else { b"E01".to_vec() })
+            }
+            b'g' => Action::Reply(self.read_registers()),
+            b'm' => Action::Reply(self.read_memory(args)),
+            b'Z' | b'z' => Action::Reply(self.handle_breakpoint(command == b'Z', args)),
+            b's' => {
+                self.step();
+                Action::Reply(self.stop_reply())
+            }
+            b'c' => {
+                self.resume_all();
+                self.wait_for_stop();
+                Action::Reply(self.stop_reply())
+            }
+            b'D' => {
+                self.send_packet(b"OK");
+                Action::Exit(StubExit::Detached)
+            }
+            b'k' => Action::Exit(StubExit::Killed),
+            _ => Action::Reply(Vec::new()),
+        }
+    }
+
+    /// Handle a `q` query.
+    fn handle_query(&self, query: &[u8]) -> Vec<u8> {
+        if query.starts_with(b"Supported") {
+            format!("PacketSize={:x}", MAX_PACKET_SIZE).into_bytes()
+        } else if query == b"Attached" {
+            b"1".to_vec()
+        } else if query == b"C" {
+            match self.current_thread() {
+                Some(id) => format!("QC{:x}", id.as_u64()).into_bytes(),
+                None => Vec::new(),
+            }
+        } else if query == b"fThreadInfo" {
+            let mut reply = b"m".to_vec();
+            for (i, id) in registry::ids().into_iter().enumerate() {
+                if i > 0 {
+                    reply.push(b',');
+                }
+                reply.extend_from_slice(format!("{:x}", id.as_u64()).as_bytes());
+            }
+            reply
+        } else if query == b"sThreadInfo" {
+            b"l".to_vec()
+        } else if let Some(id) = query.strip_prefix(b"ThreadExtraInfo,") {
+            let Some(thread) = parse_thread_id(id).and_then(registry::lookup) else {
+                return b"E01".to_vec();
+            };
+            let text = match thread.name() {
+                Some(name) => format!("{} ({:?})", name, thread.state()),
+                None => format!("{:?}", thread.state()),
+            };
+            let mut reply = Vec::with_capacity(text.len() * 2);
+            for byte in text.bytes() {
+                push_hex_byte(&mut reply, byte);
+            }
+            reply
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Thread used for register access.
+    fn current_thread(&self) -> Option<ThreadId> {
+        self.selected.or(self.stop_thread)
+    }
+
+    /// Build a stop reply for the current stop thread.
+    fn stop_reply(&self) -> Vec<u8> {
+        match self.stop_thread {
+            Some(id) => format!("T{:02x}thread:{:x};", SIGTRAP, id.as_u64()).into_bytes(),
+            None => format!("S{:02x}", SIGTRAP).into_bytes(),
+        }
+    }
+
+    /// Handle `g`: read the selected thread's saved registers.
+    fn read_registers(&self) -> Vec<u8> {
+        let Some(id) = self.current_thread() else {
+            return b"E01".to_vec();
+        };
+
+        match inspect(self.cap, id) {
+            Ok(snapshot) => {
+                let mut reply = Vec::new();
+                encode_registers(snapshot.context.as_ref(), &mut reply);
+                if reply.is_empty() {
+                    b"E01".to_vec()
+                } else {
+                    reply
+                }
+            }
+            Err(InspectError::NoSuchThread) => b"E01".to_vec(),
+            Err(_) => b"E02".to_vec(),
+        }
+    }
+
+    /// Handle `m addr,len`: read memory within a stopped thread's stack.
+    fn read_memory(&self, args: &[u8]) -> Vec<u8> {
+        let Some((addr, len)) = parse_addr_len(args) else {
+            return b"E01".to_vec();
+        };
+        let len = len.min(MAX_PACKET_SIZE / 2);
+        let Some(end) = addr.checked_add(len) else {
+            return b"E01".to_vec();
+        };
+
+        let readable = self.stopped.iter().any(|thread| {
+            match (thread.stack_top(), thread.stack_bottom()) {
+                (Some(low), Some(high)) => addr >= low as usize && end <= high as usize,
+                _ => false,
+            }
+        });
+        if !readable {
+            return b"E14".to_vec();
+        }
+
+        // Safety: the range lies within the stack of a thread we hold a
+        // reference to, so the memory stays allocated
+        let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+        let mut reply = Vec::with_capacity(len * 2);
+        for &byte in bytes {
+            push_hex_byte(&mut reply, byte);
+        }
+        reply
+    }
+
+    /// Handle `Z`/`z`: insert or remove a breakpoint through the hook.
+    fn handle_breakpoint(&mut self, insert: bool, args: &[u8]) -> Vec<u8> {
+        let mut fields = args.split(|&b| b == b',');
+        let (Some(kind), Some(addr), Some(size)) = (
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+        ) else {
+            return b"E01".to_vec();
+        };
+
+        // Only software and hardware execution breakpoints
+        if kind > 1 {
+            return Vec::new();
+        }
+
+        let ok = if insert {
+            self.hook.insert_breakpoint(kind as u8, addr as usize, size as usize)
+        } else {
+            self.hook.remove_breakpoint(kind as u8, addr as usize, size as usize)
+        };
+
+        if ok {
+            b"OK".to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Handle `s`: single-step the current thread while others stay stopped.
+    fn step(&mut self) {
+        let Some(id) = self.current_thread() else {
+            return;
+        };
+        let Some(index) = self.stopped.iter().position(|thread| thread.id() == id) else {
+            return;
+        };
+
+        let ctx = self.stopped[index].context_ptr();
+        if ctx.is_null() {
+            return;
+        }
+
+        // Safety: the thread is suspended, so nothing else touches its
+        // saved context until we resume it
+        if !self.hook.arm_single_step(unsafe { &mut *ctx }) {
+            return;
+        }
+
+        let thread = self.stopped.swap_remove(index);
+        let _ = thread.resume();
+
+        loop {
+            if TRAPPED.lock().contains(&id) {
+                break;
+            }
+            if self.transport.poll_byte() == Some(0x03) {
+                break;
+            }
+            crate::sync::yield_thread();
+        }
+
+        // Pick up the trap; if interrupted instead, stop the thread again
+        self.stop_all();
+        self.stop_thread = Some(id);
+    }
+
+    /// Read one packet, acknowledging it. Returns the payload.
+    fn read_packet(&mut self) -> Option<Vec<u8>> {
+        loop {
+            // Skip acks and anything else until the start of a packet
+            while self.transport.read_byte()? != b'$' {}
+
+            let mut payload = Vec::new();
+            let mut sum: u8 = 0;
+            loop {
+                let byte = self.transport.read_byte()?;
+                if byte == b'#' {
+                    break;
+                }
+                if payload.len() < MAX_PACKET_SIZE {
+                    payload.push(byte);
+                }
+                sum = sum.wrapping_add(byte);
+            }
+
+            let high = self.transport.read_byte()?;
+            let low = self.transport.read_byte()?;
+
+            if parse_hex(&[high, low]) == Some(sum as u64) {
+                self.transport.write_all(b"+");
+                return Some(payload);
+            }
+
+            self.transport.write_all(b"-");
+        }
+    }
+
+    /// Send one packet, retransmitting until GDB acknowledges it.
+    fn send_packet(&mut self, payload: &[u8]) {
+        let sum = payload.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        let mut trailer = [b'#', 0, 0];
+        trailer[1] = hex_digit(sum >> 4);
+        trailer[2] = hex_digit(sum & 0xF);
+
+        loop {
+            self.transport.write_all(b"$");
+            self.transport.write_all(payload);
+            self.transport.write_all(&trailer);
+
+            match self.transport.read_byte() {
+                Some(b'-') => continue,
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Encode a saved context in GDB's `g` packet register order.
+#[cfg(all(target_arch = "x86_64", feature = "x86_64"))]
+fn encode_registers(ctx: Option<&SavedContext>, out: &mut Vec<u8>) {
+    // rax rbx rcx rdx rsi rdi rbp rsp r8-r15 rip; only callee-saved
+    // registers are part of the saved context
+    let regs = [
+        None,
+        ctx.map(|c| c.rbx),
+        None,
+        None,
+        None,
+        None,
+        ctx.map(|c| c.rbp),
+        ctx.map(|c| c.rsp),
+        None,
+        None,
+        None,
+        None,
+        ctx.map(|c| c.r12),
+        ctx.map(|c| c.r13),
+        ctx.map(|c| c.r14),
+        ctx.map(|c| c.r15),
+        None,
+    ];
+    for reg in regs {
+        push_register(out, reg, 8);
+    }
+    push_register(out, ctx.map(|c| c.rflags), 4);
+}
+
+/// Encode a saved context in GDB's `g` packet register order.
+#[cfg(all(target_arch = "aarch64", feature = "arm64"))]
+fn encode_registers(ctx: Option<&SavedContext>, out: &mut Vec<u8>) {
+    // x0-x30 sp pc cpsr
+    for i in 0..31 {
+        push_register(out, ctx.map(|c| c.x[i]), 8);
+    }
+    push_register(out, ctx.map(|c| c.sp), 8);
+    push_register(out, ctx.map(|c| c.pc), 8);
+    push_register(out, ctx.map(|c| c.pstate), 4);
+}
+
+/// Encode a saved context in GDB's `g` packet register order.
+#[cfg(all(target_arch = "riscv64", feature = "riscv64"))]
+fn encode_registers(ctx: Option<&SavedContext>, out: &mut Vec<u8>) {
+    // x0-x31 pc; x0 is hardwired to zero and sp is saved separately
+    push_register(out, Some(0), 8);
+    for i in 0..31 {
+        let value = if i == 1 { ctx.map(|c| c.sp) } else { ctx.map(|c| c.x[i]) };
+        push_register(out, value, 8);
+    }
+    push_register(out, ctx.map(|c| c.pc), 8);
+}
+
+/// Without an architecture there are no registers to report.
+#[cfg(not(any(
+    all(target_arch = "x86_64", feature = "x86_64"),
+    all(target_arch = "aarch64", feature = "arm64"),
+    all(target_arch = "riscv64", feature = "riscv64")
+)))]
+fn encode_registers(_ctx: Option<&SavedContext>, _out: &mut Vec<u8>) {}
+
+/// Append a little-endian register value, or `x` digits if unavailable.
+#[allow(dead_code)]
+fn push_register(out: &mut Vec<u8>, value: Option<u64>, size: usize) {
+    match value {
+        Some(value) => {
+            for byte in &value.to_le_bytes()[..size] {
+                push_hex_byte(out, *byte);
+            }
+        }
+        None => out.extend(core::iter::repeat(b'x').take(size * 2)),
+    }
+}
+
+fn hex_digit(nibble: u8) -> u8 {
+    b"0123456789abcdef"[(nibble & 0xF) as usize]
+}
+
+fn push_hex_byte(out: &mut Vec<u8>, byte: u8) {
+    out.push(hex_digit(byte >> 4));
+    out.push(hex_digit(byte & 0xF));
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |acc, &digit| {
+        let value = (digit as char).to_digit(16)?;
+        Some((acc << 4) | value as u64)
+    })
+}
+
+/// Parse a thread ID; `-1` (all) and `0` (any) select no specific thread.
+fn parse_thread_id(digits: &[u8]) -> Option<ThreadId> {
+    if digits == b"-1" {
+        return None;
+    }
+    match parse_hex(digits)? {
+        0 => None,
+        id => Some(ThreadId::new(id)),
+    }
+}
+
+fn parse_addr_len(args: &[u8]) -> Option<(usize, usize)> {
+    let comma = args.iter().position(|&b| b == b',')?;
+    let addr = parse_hex(&args[..comma])?;
+    let len = parse_hex(&args[comma + 1..])?;
+    Some((addr as usize, len as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::VecDeque;
+
+    struct Loopback {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Transport for Loopback {
+        fn read_byte(&mut self) -> Option<u8> {
+            self.input.pop_front()
+        }
+
+        fn write_all(&mut self, bytes: &[u8]) {
+            self.output.extend_from_slice(bytes);
+        }
+    }
+
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let sum = payload.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let mut out = b"$".to_vec();
+        out.extend_from_slice(payload);
+        out.push(b'#');
+        push_hex_byte(&mut out, sum);
+        out
+    }
+
+    #[test]
+    fn test_hex_parsing() {
+        assert_eq!(parse_hex(b"1f"), Some(0x1f));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"zz"), None);
+        assert_eq!(parse_addr_len(b"1000,20"), Some((0x1000, 0x20)));
+        assert_eq!(parse_thread_id(b"-1"), None);
+        assert_eq!(parse_thread_id(b"2a"), Some(ThreadId::new(0x2a)));
+    }
+
+    #[test]
+    fn test_packet_framing() {
+        let mut input = VecDeque::new();
+        input.extend(packet(b"qSupported:multiprocess+"));
+        input.push_back(b'+');
+        input.extend(b"$k#00".iter().copied());
+        input.extend(packet(b"k"));
+
+        let cap = unsafe { DebugCapability::new() };
+        let mut stub = GdbStub::new(
+            &cap,
+            Loopback { input, output: Vec::new() },
+            NoBreakpoints,
+        );
+        assert_eq!(stub.run(), StubExit::Killed);
+
+        let mut expected = b"+".to_vec();
+        expected.extend(packet(b"PacketSize=1000"));
+        // Bad checksum is rejected, then the retransmission is accepted
+        expected.extend_from_slice(b"-+");
+        assert_eq!(stub.transport.output, expected);
+    }
+}
@@ -0,0 +1,131 @@
+//! In-target debugging support.
+//!
+//! Lets a debugger running inside the target read the saved registers and
+//! stack of a thread that is not currently executing. Every entry point
+//! takes a [`DebugCapability`], which only trusted code can create.
+
+pub mod gdbstub;
+
+use crate::arch::{Arch, DefaultArch};
+use crate::perf::context_switch_opt::copy_context;
+use crate::thread_new::{registry, ThreadId, ThreadState};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+
+/// Maximum number of stack bytes copied by [`inspect`].
+pub const MAX_STACK_SNAPSHOT: usize = 4096;
+
+/// Saved register context for the current architecture.
+pub type SavedContext = <DefaultArch as Arch>::SavedContext;
+
+/// Proof that the caller is allowed to inspect other threads.
+pub struct DebugCapability {
+    _private: (),
+}
+
+impl DebugCapability {
+    /// Create a debug capability.
+    ///
+    /// # Safety
+    ///
+    /// The capability grants read access to the registers and stack memory
+    /// of every thread in the system. It must only be handed to trusted
+    /// debugger code.
+    pub unsafe fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+/// Snapshot of a stopped thread.
+#[derive(Debug)]
+pub struct ThreadInspection {
+    /// Thread identifier
+    pub id: ThreadId,
+    /// State the thread was stopped in
+    pub state: ThreadState,
+    /// Thread name, if set
+    pub name: Option<String>,
+    /// Copy of the saved register context, `None` if the thread has never
+    /// been switched out
+    pub context: Option<SavedContext>,
+    /// Stack pointer recorded in the saved context
+    pub stack_pointer: Option<usize>,
+    /// Usable stack range as `(lowest, highest)` addresses
+    pub stack_range: Option<(usize, usize)>,
+    /// Stack contents starting at the stack pointer, at most
+    /// [`MAX_STACK_SNAPSHOT`] bytes
+    pub stack: Vec<u8>,
+}
+
+/// Errors returned by [`inspect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InspectError {
+    /// No live thread has this ID
+    NoSuchThread,
+    /// Thread is not suspended or blocked, so its saved state is not stable
+    NotStopped(ThreadState),
+    /// Saved stack pointer lies outside the thread's stack
+    InvalidStackPointer,
+}
+
+/// Read the saved registers and stack of a stopped thread.
+///
+/// The thread must be [`Suspended`](ThreadState::Suspended) or
+/// [`Blocked`](ThreadState::Blocked). The stack pointer is validated
+/// against the thread's stack bounds before any memory is read.
+pub fn inspect(_cap: &DebugCapability, id: ThreadId) -> Result<ThreadInspection, InspectError> {
+    let thread = registry::lookup(id).ok_or(InspectError::NoSuchThread)?;
+
+    let state = thread.state();
+    if !matches!(state, ThreadState::Suspended | ThreadState::Blocked) {
+        return Err(InspectError::NotStopped(state));
+    }
+
+    let stack_range = match (thread.stack_top(), thread.stack_bottom()) {
+        (Some(low), Some(high)) => Some((low as usize, high as usize)),
+        _ => None,
+    };
+
+    let ctx_ptr = thread.context_ptr();
+    let context = (!ctx_ptr.is_null()).then(|| {
+        let mut copy = MaybeUninit::<SavedContext>::uninit();
+        // Safety: the context belongs to a stopped thread, so it is not
+        // being written; the copy is re-validated against the state below
+        unsafe {
+            copy_context(copy.as_mut_ptr(), ctx_ptr);
+            copy.assume_init()
+        }
+    });
+
+    let stack_pointer = context.as_ref().and_then(DefaultArch::saved_stack_pointer);
+
+    let stack = match (stack_pointer, stack_range) {
+        (Some(sp), Some((low, high))) => {
+            if sp < low || sp > high {
+                return Err(InspectError::InvalidStackPointer);
+            }
+            let len = (high - sp).min(MAX_STACK_SNAPSHOT);
+            // Safety: [sp, sp + len) lies within the thread's stack, which
+            // stays allocated while we hold a reference to the thread
+            unsafe { core::slice::from_raw_parts(sp as *const u8, len) }.to_vec()
+        }
+        _ => Vec::new(),
+    };
+
+    // The thread may have been resumed while we were copying
+    let now = thread.state();
+    if now != state {
+        return Err(InspectError::NotStopped(now));
+    }
+
+    Ok(ThreadInspection {
+        id,
+        state,
+        name: thread.name(),
+        context,
+        stack_pointer,
+        stack_range,
+        stack,
+    })
+}
@@ -2,16 +2,29 @@
 //!
 //! This module provides detailed error types for all threading operations,
 //! enabling proper error handling and debugging throughout the system.
-
+//!
+//! [`ThreadError`] is the single error type used across the crate. Each
+//! error names the subsystem it came from ([`ThreadError::subsystem`]), a
+//! coarse [`ErrorKind`] for callers that only need to decide how to recover,
+//! and optionally the thread it concerns ([`ThreadError::thread_id`]). All
+//! error enums are `#[non_exhaustive]` so new failure modes can be added
+//! without breaking callers.
+
+use crate::io::IoError;
+use crate::sched::CpuSet;
+use crate::thread_new::ThreadId;
 use core::fmt;
 extern crate alloc;
-use alloc::string::String;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
 
 /// Result type for threading operations.
 pub type ThreadResult<T> = Result<T, ThreadError>;
 
 /// Comprehensive error type for all threading operations.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ThreadError {
     /// Thread spawning errors
     Spawn(SpawnError),
@@ -33,10 +46,64 @@ pub enum ThreadError {
     Resource(ResourceError),
     /// Invalid operation errors
     InvalidOperation(InvalidOperationError),
+    /// A blocking operation did not complete before its timeout
+    TimedOut,
+    /// Byte I/O errors, from checkpoints and telemetry streams
+    Io(IoError),
+    /// An error concerning a specific thread
+    InThread {
+        /// The thread the error concerns
+        thread: ThreadId,
+        /// The underlying error
+        error: Box<ThreadError>,
+    },
+}
+
+/// Subsystem an error originated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Subsystem {
+    Spawn,
+    Join,
+    Scheduler,
+    Memory,
+    Timer,
+    Arch,
+    Tls,
+    Security,
+    Resource,
+    Sync,
+    Io,
+    General,
+}
+
+/// Coarse classification of errors, for deciding how to recover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An argument was out of range or malformed
+    InvalidInput,
+    /// The operation is not valid in the current state
+    InvalidState,
+    /// The operation or feature is not supported here
+    NotSupported,
+    /// Memory could not be allocated
+    OutOfMemory,
+    /// A limit or quota was reached
+    LimitExceeded,
+    /// The caller is not allowed to perform the operation
+    PermissionDenied,
+    /// The operation did not complete in time
+    TimedOut,
+    /// The operation would deadlock
+    Deadlock,
+    /// The operation failed
+    Failed,
 }
 
 /// Errors that can occur during thread spawning.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum SpawnError {
     /// System is not initialized
     NotInitialized,
@@ -49,17 +116,20 @@ pub enum SpawnError {
     /// Invalid priority specified
     InvalidPriority(u8),
     /// Invalid CPU affinity specified
-    InvalidAffinity(u64),
+    InvalidAffinity(CpuSet),
     /// Thread name is invalid or too long
     InvalidName(String),
     /// Architecture does not support requested feature
     UnsupportedFeature(String),
     /// Scheduler rejected the thread
     SchedulerRejected,
+    /// Thread stack is mapped executable
+    ExecutableStack,
 }
 
 /// Errors that can occur during thread joining.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum JoinError {
     /// Thread has already been joined
     AlreadyJoined,
@@ -77,6 +147,7 @@ pub enum JoinError {
 
 /// Errors related to scheduling operations.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ScheduleError {
     /// No schedulable threads available
     NoThreadsAvailable,
@@ -94,6 +165,7 @@ pub enum ScheduleError {
 
 /// Memory-related errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum MemoryError {
     /// Out of memory
     OutOfMemory,
@@ -113,6 +185,7 @@ pub enum MemoryError {
 
 /// Timer and timing related errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum TimerError {
     /// Timer not initialized
     NotInitialized,
@@ -130,6 +203,7 @@ pub enum TimerError {
 
 /// Architecture-specific errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ArchError {
     /// Unsupported architecture
     UnsupportedArchitecture,
@@ -147,6 +221,7 @@ pub enum ArchError {
 
 /// Thread-local storage errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum TlsError {
     /// TLS key not found
     KeyNotFound,
@@ -162,6 +237,7 @@ pub enum TlsError {
 
 /// Permission and security errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum PermissionError {
     /// Operation not permitted
     NotPermitted,
@@ -177,6 +253,7 @@ pub enum PermissionError {
 
 /// Resource limit errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ResourceError {
     /// Maximum threads per process exceeded
     MaxThreadsPerProcess,
@@ -194,6 +271,7 @@ pub enum ResourceError {
 
 /// Invalid operation errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum InvalidOperationError {
     /// Operation called on wrong thread
     WrongThread,
@@ -207,6 +285,8 @@ pub enum InvalidOperationError {
     WouldDeadlock,
     /// Operation already in progress
     AlreadyInProgress,
+    /// No thread with the given ID exists
+    InvalidThreadId,
 }
 
 // Display implementations for user-friendly error messages
@@ -224,6 +304,9 @@ impl fmt::Display for ThreadError {
             ThreadError::Permission(e) => write!(f, "Permission error: {}", e),
             ThreadError::Resource(e) => write!(f, "Resource error: {}", e),
             ThreadError::InvalidOperation(e) => write!(f, "Invalid operation: {}", e),
+            ThreadError::TimedOut => write!(f, "Operation timed out"),
+            ThreadError::Io(e) => write!(f, "I/O error: {}", e),
+            ThreadError::InThread { thread, error } => write!(f, "Thread {}: {}", thread, error),
         }
     }
 }
@@ -236,10 +319,11 @@ impl fmt::Display for SpawnError {
             SpawnError::TooManyThreads => write!(f, "Maximum number of threads reached"),
             SpawnError::InvalidStackSize(size) => write!(f, "Invalid stack size: {}", size),
             SpawnError::InvalidPriority(prio) => write!(f, "Invalid priority: {}", prio),
-            SpawnError::InvalidAffinity(affinity) => write!(f, "Invalid CPU affinity: {:#x}", affinity),
+            SpawnError::InvalidAffinity(affinity) => write!(f, "Invalid CPU affinity: {:?}", affinity),
             SpawnError::InvalidName(name) => write!(f, "Invalid thread name: {}", name),
             SpawnError::UnsupportedFeature(feature) => write!(f, "Unsupported feature: {}", feature),
             SpawnError::SchedulerRejected => write!(f, "Scheduler rejected thread creation"),
+            SpawnError::ExecutableStack => write!(f, "Thread stack is mapped executable"),
         }
     }
 }
@@ -356,6 +440,7 @@ impl fmt::Display for InvalidOperationError {
             InvalidOperationError::NotSupported => write!(f, "Operation not supported in current context"),
             InvalidOperationError::WouldDeadlock => write!(f, "Operation would cause deadlock"),
             InvalidOperationError::AlreadyInProgress => write!(f, "Operation already in progress"),
+            InvalidOperationError::InvalidThreadId => write!(f, "Invalid thread ID provided"),
         }
     }
 }
@@ -422,14 +507,157 @@ impl From<InvalidOperationError> for ThreadError {
     }
 }
 
-// Convert from old SpawnError to new system
-impl From<crate::kernel::SpawnError> for SpawnError {
-    fn from(error: crate::kernel::SpawnError) -> Self {
+impl From<IoError> for ThreadError {
+    fn from(error: IoError) -> Self {
+        ThreadError::Io(error)
+    }
+}
+
+/// An invalid-input error with `message` as the parameter description.
+fn invalid(message: String) -> ThreadError {
+    ThreadError::InvalidOperation(InvalidOperationError::InvalidParameter(message))
+}
+
+/// An error for an operation on a thread in the wrong state.
+fn wrong_state() -> ThreadError {
+    ThreadError::InvalidOperation(InvalidOperationError::WrongState)
+}
+
+impl From<crate::thread_new::SuspendError> for ThreadError {
+    fn from(error: crate::thread_new::SuspendError) -> Self {
+        use crate::thread_new::SuspendError;
+        match error {
+            SuspendError::Finished | SuspendError::NotSuspended => wrong_state(),
+            SuspendError::Overflow => ThreadError::Resource(ResourceError::ResourceUnavailable),
+        }
+    }
+}
+
+impl From<crate::thread_new::CheckpointError> for ThreadError {
+    fn from(error: crate::thread_new::checkpoint::CheckpointError) -> Self {
+        use crate::thread_new::CheckpointError;
+        match error {
+            CheckpointError::Io(e) => ThreadError::Io(e),
+            CheckpointError::NotSuspended(_) | CheckpointError::NoStack => wrong_state(),
+            CheckpointError::BadMagic => invalid(String::from("not a checkpoint")),
+            CheckpointError::UnsupportedVersion(version) => invalid(format!("checkpoint version {}", version)),
+            CheckpointError::ArchMismatch => invalid(String::from("checkpoint from another architecture")),
+            CheckpointError::IdInUse(thread) => {
+                ThreadError::InvalidOperation(InvalidOperationError::InvalidThreadId).for_thread(thread)
+            }
+            CheckpointError::InvalidStackPointer => invalid(String::from("checkpointed stack pointer")),
+            CheckpointError::StackAllocationFailed => ThreadError::Memory(MemoryError::OutOfMemory),
+            CheckpointError::Corrupt => invalid(String::from("corrupt checkpoint")),
+        }
+    }
+}
+
+impl From<crate::observability::wire::WireError> for ThreadError {
+    fn from(error: crate::observability::wire::WireError) -> Self {
+        use crate::observability::wire::WireError;
+        match error {
+            WireError::Io(e) => ThreadError::Io(e),
+            WireError::BadMagic => invalid(String::from("not a telemetry record")),
+            WireError::UnsupportedVersion(version) => invalid(format!("telemetry record version {}", version)),
+            WireError::TooLarge => invalid(String::from("telemetry record too large")),
+        }
+    }
+}
+
+#[cfg(feature = "debug")]
+impl From<crate::debug::InspectError> for ThreadError {
+    fn from(error: crate::debug::InspectError) -> Self {
+        use crate::debug::InspectError;
+        match error {
+            InspectError::NoSuchThread => ThreadError::InvalidOperation(InvalidOperationError::InvalidThreadId),
+            InspectError::NotStopped(_) => wrong_state(),
+            InspectError::InvalidStackPointer => invalid(String::from("saved stack pointer")),
+        }
+    }
+}
+
+impl From<crate::sched::BandwidthError> for ThreadError {
+    fn from(error: crate::sched::BandwidthError) -> Self {
+        match error {
+            crate::sched::BandwidthError::ZeroDuration => invalid(String::from("zero bandwidth quota or period")),
+        }
+    }
+}
+
+impl From<crate::console::ConsoleError> for ThreadError {
+    fn from(error: crate::console::ConsoleError) -> Self {
+        use crate::console::ConsoleError;
+        match error {
+            ConsoleError::UnknownCommand | ConsoleError::MissingArgument(_) | ConsoleError::InvalidArgument(_) => {
+                invalid(error.to_string())
+            }
+            ConsoleError::NoSuchThread(thread) => {
+                ThreadError::InvalidOperation(InvalidOperationError::InvalidThreadId).for_thread(thread)
+            }
+            ConsoleError::Denied(_) => ThreadError::Permission(PermissionError::NotPermitted),
+            ConsoleError::Output => ThreadError::Io(IoError::Other),
+        }
+    }
+}
+
+impl From<crate::security::ipc::IpcError> for ThreadError {
+    fn from(error: crate::security::ipc::IpcError) -> Self {
+        use crate::security::ipc::IpcError;
+        match error {
+            IpcError::PermissionDenied => ThreadError::Permission(PermissionError::AccessDenied),
+            IpcError::WouldBlock => ThreadError::Resource(ResourceError::ResourceUnavailable),
+            IpcError::MessageTooLarge | IpcError::BufferTooSmall(_) | IpcError::InvalidConfig => {
+                invalid(error.to_string())
+            }
+            IpcError::Disconnected => wrong_state(),
+            IpcError::TimedOut => ThreadError::TimedOut,
+        }
+    }
+}
+
+impl From<crate::security::shm::ShmError> for ThreadError {
+    fn from(error: crate::security::shm::ShmError) -> Self {
+        use crate::security::shm::ShmError;
+        match error {
+            ShmError::AlreadyExists | ShmError::NotFound | ShmError::InvalidSize | ShmError::OutOfBounds => {
+                invalid(error.to_string())
+            }
+            ShmError::OutOfMemory => ThreadError::Memory(MemoryError::OutOfMemory),
+            ShmError::NotOwner | ShmError::NotGranted => ThreadError::Permission(PermissionError::NotPermitted),
+            ShmError::PermissionDenied => ThreadError::Permission(PermissionError::AccessDenied),
+        }
+    }
+}
+
+impl From<crate::sched::PartitionError> for ThreadError {
+    fn from(error: crate::sched::PartitionError) -> Self {
+        use crate::sched::PartitionError;
         match error {
-            crate::kernel::SpawnError::NotInitialized => SpawnError::NotInitialized,
-            crate::kernel::SpawnError::OutOfMemory => SpawnError::OutOfMemory,
-            crate::kernel::SpawnError::TooManyThreads => SpawnError::TooManyThreads,
-            crate::kernel::SpawnError::InvalidStackSize => SpawnError::InvalidStackSize(0),
+            PartitionError::NoCpus => invalid(String::from("partition without CPUs")),
+            PartitionError::CpuUnavailable(cpu) => ThreadError::Schedule(ScheduleError::InvalidCpu(cpu)),
+            PartitionError::DuplicateName(name) => invalid(format!("duplicate partition {}", name)),
+            PartitionError::UnknownPartition(name) => invalid(format!("unknown partition {}", name)),
+            PartitionError::ZeroDuration => invalid(String::from("zero partition quota or period")),
+        }
+    }
+}
+
+impl From<crate::kernel::SwitchHookError> for ThreadError {
+    fn from(error: crate::kernel::SwitchHookError) -> Self {
+        match error {
+            crate::kernel::SwitchHookError::TooManyHooks => ThreadError::Resource(ResourceError::ResourceUnavailable),
+        }
+    }
+}
+
+impl From<crate::resources::LeaseError> for ThreadError {
+    fn from(error: crate::resources::LeaseError) -> Self {
+        use crate::resources::LeaseError;
+        match error {
+            LeaseError::Held { owner, .. } => {
+                ThreadError::Resource(ResourceError::ResourceUnavailable).for_thread(owner)
+            }
+            LeaseError::NoCurrentThread => ThreadError::InvalidOperation(InvalidOperationError::WrongThread),
         }
     }
 }
@@ -447,6 +675,124 @@ impl From<crate::time::TimerError> for TimerError {
     }
 }
 
+impl ThreadError {
+    /// Attach the thread this error concerns.
+    pub fn for_thread(self, thread: ThreadId) -> Self {
+        match self {
+            // Keep the innermost thread, which is the most specific
+            ThreadError::InThread { .. } => self,
+            error => ThreadError::InThread { thread, error: Box::new(error) },
+        }
+    }
+    
+    /// Get the thread this error concerns, if known.
+    pub fn thread_id(&self) -> Option<ThreadId> {
+        match self {
+            ThreadError::InThread { thread, .. } => Some(*thread),
+            _ => None,
+        }
+    }
+    
+    /// Get the error without any thread attached.
+    pub fn root(&self) -> &ThreadError {
+        match self {
+            ThreadError::InThread { error, .. } => error.root(),
+            error => error,
+        }
+    }
+    
+    /// Get the subsystem the error originated in.
+    pub fn subsystem(&self) -> Subsystem {
+        match self.root() {
+            ThreadError::Spawn(_) => Subsystem::Spawn,
+            ThreadError::Join(_) => Subsystem::Join,
+            ThreadError::Schedule(_) => Subsystem::Scheduler,
+            ThreadError::Memory(_) => Subsystem::Memory,
+            ThreadError::Timer(_) => Subsystem::Timer,
+            ThreadError::Arch(_) => Subsystem::Arch,
+            ThreadError::Tls(_) => Subsystem::Tls,
+            ThreadError::Permission(_) => Subsystem::Security,
+            ThreadError::Resource(_) => Subsystem::Resource,
+            ThreadError::TimedOut => Subsystem::Sync,
+            ThreadError::Io(_) => Subsystem::Io,
+            ThreadError::InvalidOperation(_) | ThreadError::InThread { .. } => Subsystem::General,
+        }
+    }
+    
+    /// Classify the error.
+    pub fn kind(&self) -> ErrorKind {
+        use ErrorKind::*;
+        
+        match self.root() {
+            ThreadError::Spawn(e) => match e {
+                SpawnError::NotInitialized => InvalidState,
+                SpawnError::OutOfMemory => OutOfMemory,
+                SpawnError::TooManyThreads => LimitExceeded,
+                SpawnError::InvalidStackSize(_)
+                | SpawnError::InvalidPriority(_)
+                | SpawnError::InvalidAffinity(_)
+                | SpawnError::InvalidName(_) => InvalidInput,
+                SpawnError::UnsupportedFeature(_) => NotSupported,
+                SpawnError::SchedulerRejected => Failed,
+                SpawnError::ExecutableStack => PermissionDenied,
+            },
+            ThreadError::Join(e) => match e {
+                JoinError::AlreadyJoined | JoinError::StillRunning => InvalidState,
+                JoinError::ThreadPanicked | JoinError::Terminated => Failed,
+                JoinError::Timeout => TimedOut,
+                JoinError::InvalidHandle => InvalidInput,
+            },
+            ThreadError::Schedule(e) => match e {
+                ScheduleError::NoThreadsAvailable
+                | ScheduleError::InvalidState
+                | ScheduleError::PreemptionDisabled => InvalidState,
+                ScheduleError::InvalidCpu(_) => InvalidInput,
+                ScheduleError::PriorityChangeNotAllowed => PermissionDenied,
+                ScheduleError::QueueFull => LimitExceeded,
+            },
+            ThreadError::Memory(e) => match e {
+                MemoryError::OutOfMemory | MemoryError::PoolExhausted => OutOfMemory,
+                MemoryError::StackOverflow | MemoryError::StackUnderflow => Failed,
+                MemoryError::InvalidAddress(_)
+                | MemoryError::AlignmentError
+                | MemoryError::InvalidLayout => InvalidInput,
+            },
+            ThreadError::Timer(e) => match e {
+                TimerError::NotInitialized | TimerError::AlreadyRunning | TimerError::NotRunning => InvalidState,
+                TimerError::InvalidFrequency(_) | TimerError::InvalidConfig => InvalidInput,
+                TimerError::HardwareNotAvailable => NotSupported,
+            },
+            ThreadError::Arch(e) => match e {
+                ArchError::UnsupportedArchitecture => NotSupported,
+                _ => Failed,
+            },
+            ThreadError::Tls(e) => match e {
+                TlsError::KeyNotFound | TlsError::InvalidKey => InvalidInput,
+                TlsError::StorageExhausted => LimitExceeded,
+                TlsError::DataCorrupted => Failed,
+                TlsError::NotSupported => NotSupported,
+            },
+            ThreadError::Permission(_) => PermissionDenied,
+            ThreadError::Resource(_) => LimitExceeded,
+            ThreadError::InvalidOperation(e) => match e {
+                InvalidOperationError::WrongThread
+                | InvalidOperationError::WrongState
+                | InvalidOperationError::AlreadyInProgress => InvalidState,
+                InvalidOperationError::InvalidParameter(_) | InvalidOperationError::InvalidThreadId => InvalidInput,
+                InvalidOperationError::NotSupported => NotSupported,
+                InvalidOperationError::WouldDeadlock => Deadlock,
+            },
+            ThreadError::TimedOut => TimedOut,
+            ThreadError::Io(e) => match e {
+                IoError::UnexpectedEof => InvalidInput,
+                IoError::WriteZero => LimitExceeded,
+                IoError::Other => Failed,
+            },
+            ThreadError::InThread { .. } => Failed,
+        }
+    }
+}
+
 // Convenience constructors for common error patterns
 impl ThreadError {
     /// Create a memory error.
@@ -478,4 +824,106 @@ impl ThreadError {
     pub fn Other(msg: String) -> Self {
         ThreadError::InvalidOperation(InvalidOperationError::InvalidParameter(msg))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::wire::WireError;
+    use crate::sched::BandwidthError;
+    use crate::thread_new::{CheckpointError, SuspendError, ThreadState};
+
+    #[test]
+    fn test_subsystem_errors_convert() {
+        let error = ThreadError::from(CheckpointError::Io(IoError::UnexpectedEof));
+        assert_eq!(error, ThreadError::Io(IoError::UnexpectedEof));
+        assert_eq!(error.subsystem(), Subsystem::Io);
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "I/O error: Unexpected end of input");
+        assert_eq!(ThreadError::from(WireError::Io(IoError::WriteZero)).kind(), ErrorKind::LimitExceeded);
+
+        let thread = ThreadId::new(7);
+        let error = ThreadError::from(CheckpointError::IdInUse(thread));
+        assert_eq!(error.thread_id(), Some(thread));
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+        assert_eq!(ThreadError::from(CheckpointError::NotSuspended(ThreadState::Running)).kind(), ErrorKind::InvalidState);
+        assert_eq!(ThreadError::from(CheckpointError::StackAllocationFailed).kind(), ErrorKind::OutOfMemory);
+        assert_eq!(ThreadError::from(SuspendError::Finished).kind(), ErrorKind::InvalidState);
+        assert_eq!(ThreadError::from(SuspendError::Overflow).kind(), ErrorKind::LimitExceeded);
+        assert_eq!(ThreadError::from(WireError::UnsupportedVersion(9)).kind(), ErrorKind::InvalidInput);
+        assert_eq!(ThreadError::from(BandwidthError::ZeroDuration).kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_console_errors_convert() {
+        use crate::console::ConsoleError;
+
+        let error = ThreadError::from(ConsoleError::MissingArgument("tid"));
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "Invalid operation: Invalid parameter: missing argument: tid");
+        assert_eq!(ThreadError::from(ConsoleError::NoSuchThread(ThreadId::new(3))).thread_id(), Some(ThreadId::new(3)));
+        assert_eq!(ThreadError::from(ConsoleError::Output).subsystem(), Subsystem::Io);
+    }
+
+    #[test]
+    fn test_ipc_errors_convert() {
+        use crate::security::ipc::IpcError;
+
+        assert_eq!(ThreadError::from(IpcError::TimedOut), ThreadError::TimedOut);
+        assert_eq!(ThreadError::from(IpcError::PermissionDenied).kind(), ErrorKind::PermissionDenied);
+        assert_eq!(ThreadError::from(IpcError::WouldBlock).kind(), ErrorKind::LimitExceeded);
+        assert_eq!(ThreadError::from(IpcError::Disconnected).kind(), ErrorKind::InvalidState);
+        let error = ThreadError::from(IpcError::BufferTooSmall(64));
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().ends_with("receive buffer too small, need 64 bytes"));
+    }
+
+    #[test]
+    fn test_shm_errors_convert() {
+        use crate::security::shm::ShmError;
+
+        assert_eq!(ThreadError::from(ShmError::OutOfMemory).kind(), ErrorKind::OutOfMemory);
+        assert_eq!(ThreadError::from(ShmError::NotGranted).subsystem(), Subsystem::Security);
+        assert_eq!(ThreadError::from(ShmError::PermissionDenied).kind(), ErrorKind::PermissionDenied);
+        assert_eq!(ThreadError::from(ShmError::OutOfBounds).kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_partition_errors_convert() {
+        use crate::sched::PartitionError;
+
+        let error = ThreadError::from(PartitionError::CpuUnavailable(3));
+        assert_eq!(error, ThreadError::Schedule(ScheduleError::InvalidCpu(3)));
+        assert_eq!(error.subsystem(), Subsystem::Scheduler);
+        let error = ThreadError::from(PartitionError::UnknownPartition(String::from("rt")));
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().ends_with("unknown partition rt"));
+    }
+
+    #[test]
+    fn test_switch_hook_errors_convert() {
+        let error = ThreadError::from(crate::kernel::SwitchHookError::TooManyHooks);
+        assert_eq!(error.kind(), ErrorKind::LimitExceeded);
+        assert_eq!(error.subsystem(), Subsystem::Resource);
+    }
+
+    #[test]
+    fn test_lease_errors_convert() {
+        use crate::resources::LeaseError;
+
+        let owner = ThreadId::new(5);
+        let error = ThreadError::from(LeaseError::Held { resource: String::from("uart0"), owner });
+        assert_eq!(error.kind(), ErrorKind::LimitExceeded);
+        assert_eq!(error.thread_id(), Some(owner));
+        assert_eq!(ThreadError::from(LeaseError::NoCurrentThread).kind(), ErrorKind::InvalidState);
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_inspect_errors_convert() {
+        use crate::debug::InspectError;
+
+        assert_eq!(ThreadError::from(InspectError::NoSuchThread).kind(), ErrorKind::InvalidInput);
+        assert_eq!(ThreadError::from(InspectError::NotStopped(ThreadState::Running)).kind(), ErrorKind::InvalidState);
+    }
+}
@@ -0,0 +1,133 @@
+//! Minimal byte I/O for `no_std` serialization.
+//!
+//! These traits stand in for `std::io::Read`/`Write` so that checkpoints and
+//! telemetry can be streamed to flash, UART or memory buffers without the
+//! standard library. Multi-byte integers are little-endian.
+
+extern crate alloc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Errors produced by [`Read`] and [`Write`] implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoError {
+    /// Input ended before the requested number of bytes was read
+    UnexpectedEof,
+    /// Output has no room for the data being written
+    WriteZero,
+    /// Transport-specific failure
+    Other,
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoError::UnexpectedEof => write!(f, "Unexpected end of input"),
+            IoError::WriteZero => write!(f, "No room left for output"),
+            IoError::Other => write!(f, "Transport failure"),
+        }
+    }
+}
+
+/// Byte sink.
+pub trait Write {
+    /// Write the whole buffer.
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError>;
+
+    /// Write a single byte.
+    fn write_u8(&mut self, value: u8) -> Result<(), IoError> {
+        self.write_all(&[value])
+    }
+
+    /// Write a little-endian `u16`.
+    fn write_u16(&mut self, value: u16) -> Result<(), IoError> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    /// Write a little-endian `u32`.
+    fn write_u32(&mut self, value: u32) -> Result<(), IoError> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    /// Write a little-endian `u64`.
+    fn write_u64(&mut self, value: u64) -> Result<(), IoError> {
+        self.write_all(&value.to_le_bytes())
+    }
+}
+
+/// Byte source.
+pub trait Read {
+    /// Fill the whole buffer.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError>;
+
+    /// Read a single byte.
+    fn read_u8(&mut self) -> Result<u8, IoError> {
+        let mut buf = [0; 1];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Read a little-endian `u16`.
+    fn read_u16(&mut self) -> Result<u16, IoError> {
+        let mut buf = [0; 2];
+        self.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Read a little-endian `u32`.
+    fn read_u32(&mut self) -> Result<u32, IoError> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Read a little-endian `u64`.
+    fn read_u64(&mut self) -> Result<u64, IoError> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl Write for Vec<u8> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl Write for &mut [u8] {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        if buf.len() > self.len() {
+            return Err(IoError::WriteZero);
+        }
+        let (head, tail) = core::mem::take(self).split_at_mut(buf.len());
+        head.copy_from_slice(buf);
+        *self = tail;
+        Ok(())
+    }
+}
+
+impl Read for &[u8] {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        if buf.len() > self.len() {
+            return Err(IoError::UnexpectedEof);
+        }
+        let (head, tail) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        (**self).write_all(buf)
+    }
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        (**self).read_exact(buf)
+    }
+}
@@ -4,11 +4,30 @@
 //! threading operations and eliminates global singleton state.
 
 use crate::arch::Arch;
-use crate::sched::Scheduler;
-use crate::thread_new::{ThreadId, Thread, JoinHandle, ReadyRef, RunningRef};
-use crate::mem::{StackPool, StackSizeClass};
+use crate::sched::{BandwidthError, Scheduler, CPU_BANDWIDTH};
+use crate::thread_new::{percpu, registry, BlockedOn, ExitCallback, ExitStatus, ThreadBuilder, ThreadId, ThreadState, Thread, JoinHandle, ReadyRef, RunningRef};
+use crate::errors::{ThreadError, ThreadResult};
+use crate::mem::StackPool;
+use crate::sync::SpinLockIrqSave;
+use crate::observability::metrics::GLOBAL_METRICS;
+use crate::observability::resource_limits::{ViolationAction, GLOBAL_RESOURCE_LIMITER};
+use crate::time::{tick::GLOBAL_TICK_COUNTER, Duration, TIMER_WHEEL};
+extern crate alloc;
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+use core::fmt;
 use core::marker::PhantomData;
-use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+use portable_atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Bodies of spawned threads that have not started yet.
+static SPAWN_BODIES: spin::Mutex<BTreeMap<ThreadId, Box<dyn FnOnce() + Send>>> = spin::Mutex::new(BTreeMap::new());
+
+/// Entry point of threads spawned by [`Kernel::spawn`].
+fn run_spawned() {
+    let body = percpu::current_id().and_then(|id| SPAWN_BODIES.lock().remove(&id));
+    if let Some(body) = body {
+        body();
+    }
+}
 
 /// Main kernel handle that manages the threading system.
 ///
@@ -31,7 +50,7 @@ pub struct Kernel<A: Arch, S: Scheduler> {
     /// Next thread ID to assign
     next_thread_id: AtomicUsize,
     /// Currently running thread on each CPU (simplified to single CPU for now)
-    current_thread: spin::Mutex<Option<RunningRef>>,
+    current_thread: SpinLockIrqSave<Option<RunningRef>>,
 }
 
 impl<A: Arch, S: Scheduler> Kernel<A, S> {
@@ -51,7 +70,7 @@ impl<A: Arch, S: Scheduler> Kernel<A, S> {
             _arch: PhantomData,
             initialized: AtomicBool::new(false),
             next_thread_id: AtomicUsize::new(1), // Start from 1, never use 0
-            current_thread: spin::Mutex::new(None),
+            current_thread: SpinLockIrqSave::new(None),
         }
     }
     
@@ -76,10 +95,13 @@ impl<A: Arch, S: Scheduler> Kernel<A, S> {
                 crate::arch::x86_64::init();
             }
             
-            // Initialize timer subsystem for preemption
-            unsafe {
+            // Initialize timer subsystem for preemption, which cooperative
+            // schedulers run without
+            if self.scheduler.is_preemptive() {
                 #[cfg(feature = "x86_64")]
-                crate::time::x86_64_timer::init().map_err(|_| ())?;
+                unsafe {
+                    crate::time::x86_64_timer::init().map_err(|_| ())?;
+                }
             }
             
             Ok(())
@@ -108,71 +130,93 @@ impl<A: Arch, S: Scheduler> Kernel<A, S> {
         &self.scheduler
     }
     
-    /// Spawn a new thread.
-    ///
-    /// # Arguments
+    /// Make this kernel's scheduler the [global
+    /// scheduler](crate::sched::global).
     ///
-    /// * `entry_point` - Function to run in the new thread
-    /// * `priority` - Thread priority (0-255, higher = more important)
+    /// Preemption checkpoints and timer interrupts then queue and pick
+    /// threads from the same run queues as the kernel. Do this before the
+    /// first thread is spawned.
+    pub fn install_global_scheduler(&'static self)
+    where
+        S: 'static,
+    {
+        crate::sched::set_global_scheduler(&self.scheduler);
+    }
+    
+    /// Get the pool thread stacks are allocated from.
+    pub(crate) fn stack_pool(&self) -> &StackPool {
+        &self.stack_pool
+    }
+    
+    /// Spawn a thread configured by `builder` that runs `f`.
     ///
-    /// # Returns
+    /// This is the one path every thread takes into the kernel: the
+    /// spawning thread's child quota is checked, the stack is allocated and
+    /// verified, the thread is registered with the thread table and the
+    /// observability hub, and it is handed to the scheduler. If any step
+    /// fails, nothing is kept.
     ///
-    /// JoinHandle for the newly created thread, or an error if creation fails.
-    pub fn spawn<F>(&self, entry_point: F, priority: u8) -> Result<JoinHandle, SpawnError>
+    /// ```ignore
+    /// let handle = kernel.spawn(ThreadBuilder::new().name("worker").priority(200), move || serve(queue))?;
+    /// ```
+    pub fn spawn<F>(&self, builder: ThreadBuilder, f: F) -> Result<JoinHandle, SpawnError>
     where
         F: FnOnce() + Send + 'static,
     {
         if !self.is_initialized() {
             return Err(SpawnError::NotInitialized);
         }
+        self.reap();
         
-        // Allocate stack
-        let stack = self.stack_pool.allocate(StackSizeClass::Small)
-            .ok_or(SpawnError::OutOfMemory)?;
-        
-        // Generate unique thread ID
+        // The body must be in place before the thread can run
         let thread_id = self.next_thread_id();
-        
-        // Create thread entry point wrapper
-        let entry_wrapper = move || {
-            entry_point();
-        };
-        
-        // For now, simplify to a basic entry point
-        let simple_entry: fn() = || {};
-        
-        // Create thread and join handle
-        let (thread, join_handle) = Thread::new(
-            thread_id,
-            stack,
-            simple_entry,
-            priority,
-        );
-        
-        // Convert to ReadyRef and enqueue in scheduler
-        let ready_ref = ReadyRef(thread);
-        self.scheduler.enqueue(ready_ref);
-        
-        Ok(join_handle)
+        SPAWN_BODIES.lock().insert(thread_id, Box::new(f));
+        match builder.build(thread_id, &self.stack_pool, run_spawned) {
+            Ok((thread, join_handle)) => {
+                self.scheduler.enqueue(ReadyRef(thread));
+                Ok(join_handle)
+            }
+            Err(error) => {
+                SPAWN_BODIES.lock().remove(&thread_id);
+                Err(error)
+            }
+        }
+    }
+    
+    /// Spawn a thread running `entry_point` at `priority`.
+    #[cfg(feature = "compat")]
+    #[deprecated(since = "0.5.0", note = "use `Kernel::spawn` with a `ThreadBuilder`")]
+    pub fn spawn_with_priority<F>(&self, entry_point: F, priority: u8) -> Result<JoinHandle, SpawnError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn(ThreadBuilder::new().priority(priority), entry_point)
     }
     
     /// Yield the current thread, allowing other threads to run.
     pub fn yield_now(&self) {
+        crate::might_sleep!();
+        
         if !self.is_initialized() {
             return; // Can't yield if not initialized
         }
+        self.reap();
         
         if let Some(mut current_guard) = self.current_thread.try_lock() {
             if let Some(current) = current_guard.take() {
+                let prev = current.0.clone();
+                
                 // Current thread is yielding voluntarily
                 self.scheduler.on_yield(current);
                 
                 // Try to pick next thread to run
-                if let Some(next) = self.scheduler.pick_next(0) {
+                if let Some(next) = self.pick_runnable(0) {
                     let running = next.start_running();
+                    let next = running.0.clone();
                     *current_guard = Some(running);
+                    drop(current_guard);
                     
-                    // TODO: Perform actual context switch
+                    self.switch_to(&prev, next);
                 }
             }
         }
@@ -190,28 +234,94 @@ impl<A: Arch, S: Scheduler> Kernel<A, S> {
             return;
         }
         
+        // Everything touched from here on must tolerate interrupt context
+        crate::sync::irq::assert_irq_safe(&self.current_thread);
+        crate::sync::irq::assert_irq_safe(&self.scheduler);
+        
+        // Hold the tick back during boot phases, to replay it later
+        if defer_tick() {
+            return;
+        }
+        
+        // Start new bandwidth periods, releasing threads of unthrottled groups
+        let now = GLOBAL_TICK_COUNTER.now();
+        CPU_BANDWIDTH.refill(now, &self.scheduler);
+        
         if let Some(mut current_guard) = self.current_thread.try_lock() {
             if let Some(ref current) = *current_guard {
-                // Ask scheduler if current thread should be preempted
-                if let Some(ready_thread) = self.scheduler.on_tick(current) {
+                // Charge this tick against the thread's CPU-time quota
+                let tick = Duration::from_nanos(GLOBAL_TICK_COUNTER.ticks_to_nanos(1));
+                let action = GLOBAL_RESOURCE_LIMITER.charge_cpu_time(current.0.id(), tick);
+                let throttled = CPU_BANDWIDTH.charge(current.0.group_id(), tick, now);
+                
+                // Cancelled and over-quota threads are stopped here
+                let exit = if current.0.is_cancelled() {
+                    Some(ExitStatus::Cancelled)
+                } else if action == Some(ViolationAction::Terminate) {
+                    Some(ExitStatus::Terminated)
+                } else {
+                    None
+                };
+                if let Some(status) = exit {
+                    if let Some(current) = current_guard.take() {
+                        let prev = current.0.clone();
+                        current.exit(status);
+                        
+                        if let Some(next) = self.pick_runnable(0) {
+                            let running = next.start_running();
+                            let next = running.0.clone();
+                            *current_guard = Some(running);
+                            drop(current_guard);
+                            
+                            self.switch_to(&prev, next);
+                        }
+                    }
+                    return;
+                }
+                
+                if action == Some(ViolationAction::Suspend) {
+                    // Parks at its next preemption checkpoint until resumed
+                    let _ = current.0.suspend();
+                }
+                
+                // Over-quota threads give up the CPU; otherwise ask the
+                // scheduler whether the current thread should be preempted
+                let preempt = match action {
+                    Some(ViolationAction::Throttle) | Some(ViolationAction::Suspend) => {
+                        Some(current.prepare_preemption())
+                    }
+                    _ if throttled => Some(current.prepare_preemption()),
+                    _ => self.scheduler.on_tick(current),
+                };
+                
+                if let Some(ready_thread) = preempt {
                     // Preempt current thread
                     if let Some(current) = current_guard.take() {
+                        let prev = current.0.clone();
+                        
                         // Current thread was preempted, enqueue it again
-                        self.scheduler.enqueue(ready_thread);
+                        // unless its group has run out of bandwidth
+                        if let Some(ready_thread) = CPU_BANDWIDTH.park_if_throttled(ready_thread) {
+                            self.scheduler.enqueue(ready_thread);
+                        }
                         
                         // Try to pick next thread (could be the same one)
-                        if let Some(next) = self.scheduler.pick_next(0) {
+                        if let Some(next) = self.pick_runnable(0) {
                             let running = next.start_running();
+                            let next = running.0.clone();
                             *current_guard = Some(running);
+                            drop(current_guard);
                             
-                            // TODO: Perform actual context switch
+                            self.switch_to(&prev, next);
                         }
                     }
                 }
             } else {
                 // No current thread, try to schedule one
-                if let Some(next) = self.scheduler.pick_next(0) {
+                if let Some(next) = self.pick_runnable(0) {
                     let running = next.start_running();
+                    running.0.account_switch_in(crate::time::cpu_clock());
+                    crate::thread_new::percpu::set_current(running.0.clone());
                     *current_guard = Some(running);
                     
                     // TODO: Perform actual context switch
@@ -220,25 +330,804 @@ impl<A: Arch, S: Scheduler> Kernel<A, S> {
         }
     }
     
+    /// Limit thread group `group` to `quota` of CPU time every `period`.
+    ///
+    /// The group's threads are throttled once the quota is used up and run
+    /// again when the next period starts. See [`crate::sched::bandwidth`].
+    pub fn set_group_bandwidth(
+        &self,
+        group: u32,
+        quota: Duration,
+        period: Duration,
+    ) -> Result<(), BandwidthError> {
+        CPU_BANDWIDTH.set_bandwidth(group, quota, period, GLOBAL_TICK_COUNTER.now())
+    }
+    
+    /// Remove the bandwidth limit on `group`, rescheduling any parked threads.
+    pub fn clear_group_bandwidth(&self, group: u32) {
+        CPU_BANDWIDTH.clear_bandwidth(group, &self.scheduler);
+    }
+    
+    /// Pick the next thread whose group still has CPU bandwidth left.
+    ///
+    /// Threads of throttled groups are parked until their group's next
+    /// period instead of being run, and cancelled threads exit.
+    fn pick_runnable(&self, cpu_id: usize) -> Option<ReadyRef> {
+        while let Some(next) = self.scheduler.pick_next(cpu_id) {
+            if next.0.is_cancelled() {
+                next.start_running().exit(ExitStatus::Cancelled);
+                continue;
+            }
+            if let Some(next) = CPU_BANDWIDTH.park_if_throttled(next) {
+                return Some(next);
+            }
+        }
+        None
+    }
+    
+    /// Switch execution from `prev` to `next`.
+    ///
+    /// Sibling threads in the same switch domain take the fast path that
+    /// skips FPU handling; everything else takes the full path.
+    fn switch_to(&self, prev: &Thread, next: Thread) {
+        if prev.id() == next.id() {
+            return;
+        }
+        
+        // Publish the new thread in this CPU's slot before it starts running
+        crate::thread_new::percpu::set_current(next.clone());
+        
+        // Safety: called with the current-thread slot already updated and
+        // released, so the next thread observes consistent kernel state
+        unsafe {
+            crate::perf::context_switch_opt::switch_threads(prev, &next);
+        }
+    }
+    
     /// Get current thread statistics.
     pub fn thread_stats(&self) -> (usize, usize, usize) {
         self.scheduler.stats()
     }
+    
+    /// Run the [exit hooks](crate::thread_new::at_exit) and then the [exit
+    /// callbacks](crate::thread_new::ThreadBuilder::on_exit) of finished
+    /// threads, and return the stacks of those whose [`JoinHandle`] was
+    /// dropped to the stack pool.
+    ///
+    /// Without this a detached thread's stack is only freed when the last
+    /// reference to the thread happens to go away, and never goes back to
+    /// the pool. It runs on every spawn and yield, so calling it directly
+    /// is only needed to free stacks early; a low-priority reaper thread
+    /// can loop on it. Exit callbacks run on the thread calling it, never
+    /// on the exiting thread or in interrupt context.
+    ///
+    /// # Returns
+    ///
+    /// The number of threads reaped.
+    pub fn reap(&self) -> usize {
+        if EXITED_COUNT.load(Ordering::Acquire) == 0 {
+            return 0;
+        }
+        
+        let exits: Vec<(ThreadId, ExitStatus, Vec<fn()>, Option<ExitCallback>)> = EXITED
+            .lock()
+            .iter()
+            .map(|exited| {
+                let status = exited.thread.exit_status().unwrap_or(ExitStatus::Completed);
+                (exited.thread.id(), status, exited.thread.take_exit_hooks(), exited.thread.take_on_exit())
+            })
+            .collect();
+        for (thread_id, status, hooks, callback) in exits {
+            for hook in hooks {
+                hook();
+            }
+            if let Some(callback) = callback {
+                callback(thread_id, status);
+            }
+        }
+        
+        // Take the orphans whose stacks came from this kernel's pool, and
+        // reclaim them without holding the lock
+        let orphans: Vec<Exited> = {
+            let mut exited = EXITED.lock();
+            let (orphans, kept) = core::mem::take(&mut *exited).into_iter().partition(|exited| {
+                exited.thread.is_detached()
+                    && exited.thread.stack().map_or(true, |stack| self.stack_pool.owns(stack))
+            });
+            *exited = kept;
+            orphans
+        };
+        
+        let mut reaped = 0;
+        let mut busy = Vec::new();
+        for Exited { thread, collected } in orphans {
+            match thread.try_reclaim_stack() {
+                Ok(stack) => {
+                    if let Some(stack) = stack {
+                        self.stack_pool.deallocate(stack);
+                    }
+                    reaped += 1;
+                }
+                // Still referenced, e.g. by the CPU it just left; try again later
+                Err(thread) => busy.push(Exited { thread, collected }),
+            }
+        }
+        
+        let mut exited = EXITED.lock();
+        exited.extend(busy);
+        EXITED_COUNT.store(exited.len(), Ordering::Release);
+        reaped
+    }
 }
 
-/// Errors that can occur when spawning threads.
+/// Snapshot of one live thread, as listed by [`threads`].
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    /// Thread ID
+    pub id: ThreadId,
+    /// Thread name, if set
+    pub name: Option<String>,
+    /// Execution state
+    pub state: ThreadState,
+    /// Current priority
+    pub priority: u8,
+    /// CPU the thread last ran on
+    pub cpu: usize,
+    /// Stack size in bytes
+    pub stack_size: usize,
+    /// Stack bytes in use at the last measurement
+    pub stack_used: usize,
+    /// Highest stack usage measured
+    pub stack_peak: usize,
+    /// Total CPU time consumed
+    pub cpu_time: Duration,
+    /// What the thread is waiting for, if it is blocked
+    pub blocked_on: Option<BlockedOn>,
+}
+
+impl ThreadInfo {
+    /// Take a snapshot of `thread`.
+    pub fn of(thread: &Thread) -> Self {
+        let (stack_used, stack_peak) = GLOBAL_METRICS
+            .get_thread_metrics(thread.id())
+            .map_or((0, 0), |metrics| (metrics.current_stack_usage, metrics.peak_stack_usage));
+        
+        Self {
+            id: thread.id(),
+            name: thread.name(),
+            state: thread.state(),
+            priority: thread.priority(),
+            cpu: thread.last_cpu(),
+            stack_size: thread.stack_size(),
+            stack_used,
+            stack_peak,
+            cpu_time: thread.cpu_time(),
+            blocked_on: thread.blocked_on(),
+        }
+    }
+    
+    /// Column headings matching the [`Display`](fmt::Display) format;
+    /// stack usage is shown as used/size bytes, and a blocked thread's name
+    /// is followed by what it waits for.
+    pub const HEADER: &'static str = "     ID STATE      PRIO CPU     USED/SIZE           CPU TIME NAME";
+}
+
+/// Formats as one `ps`-style row under [`ThreadInfo::HEADER`].
+impl fmt::Display for ThreadInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpu_time = self.cpu_time.as_nanos();
+        write!(
+            f,
+            "{:>7} {:<10} {:>4} {:>3} {:>8}/{:<8} {:>6}.{:06}s {}",
+            self.id.get(),
+            format!("{:?}", self.state),
+            self.priority,
+            self.cpu,
+            self.stack_used,
+            self.stack_size,
+            cpu_time / 1_000_000_000,
+            cpu_time / 1_000 % 1_000_000,
+            self.name.as_deref().unwrap_or("-"),
+        )?;
+        if let Some(reason) = self.blocked_on {
+            write!(f, " (waiting on {})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Take a snapshot of every live thread, in ascending ID order.
+///
+/// Threads that exit while the snapshot is taken are left out. Each thread
+/// is read separately, so the snapshot is not atomic across threads.
+pub fn threads() -> Vec<ThreadInfo> {
+    registry::ids()
+        .into_iter()
+        .filter_map(registry::lookup)
+        .map(|thread| ThreadInfo::of(&thread))
+        .collect()
+}
+
+/// Render the thread list as a `ps`-style table with a header line.
+pub fn format_threads() -> String {
+    let mut out = String::from(ThreadInfo::HEADER);
+    out.push('\n');
+    for info in threads() {
+        let _ = fmt::Write::write_fmt(&mut out, format_args!("{}\n", info));
+    }
+    out
+}
+
+/// A finished thread, kept until its stack can be reclaimed.
+struct Exited {
+    thread: Thread,
+    /// Whether [`join_any`] has returned the thread already
+    collected: bool,
+}
+
+/// Finished threads that still hold their stack.
+static EXITED: SpinLockIrqSave<Vec<Exited>> = SpinLockIrqSave::new(Vec::new());
+
+/// Number of entries in `EXITED`, so reaping skips the lock when it's empty.
+static EXITED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Record a thread that has just finished.
+pub(crate) fn record_exit(thread: Thread) {
+    let mut exited = EXITED.lock();
+    exited.push(Exited { thread, collected: false });
+    EXITED_COUNT.store(exited.len(), Ordering::Release);
+}
+
+/// Wait for any thread that still has a [`JoinHandle`] to finish, giving up
+/// after `timeout`.
+///
+/// Each finished thread is returned once, oldest first; its handle can
+/// still be joined, which then returns right away. Threads whose handle
+/// was dropped are never returned, their stacks are reclaimed instead (see
+/// [`Kernel::reap`]).
+///
+/// # Errors
+///
+/// [`ThreadError::TimedOut`] if no thread finished in time.
+pub fn join_any(timeout: Duration) -> ThreadResult<ThreadId> {
+    crate::might_sleep!();
+    
+    let deadline = TIMER_WHEEL.arm(timeout);
+    loop {
+        if EXITED_COUNT.load(Ordering::Acquire) > 0 {
+            let mut exited = EXITED.lock();
+            let joinable = exited
+                .iter_mut()
+                .find(|exited| !exited.collected && !exited.thread.is_detached());
+            if let Some(exited) = joinable {
+                exited.collected = true;
+                return Ok(exited.thread.id());
+            }
+        }
+        if deadline.expired() {
+            return Err(ThreadError::TimedOut);
+        }
+        crate::sync::relax();
+    }
+}
+
+/// Maximum number of switch hooks registered at once.
+pub const MAX_SWITCH_HOOKS: usize = 8;
+
+/// Function run on every context switch with the IDs of the outgoing and
+/// incoming threads.
+pub type SwitchHook = fn(prev: ThreadId, next: ThreadId);
+
+/// Handle identifying a registered switch hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchHookId(usize);
+
+/// Cost of a registered switch hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchHookStats {
+    pub id: SwitchHookId,
+    /// Switches the hook ran on
+    pub calls: u64,
+    /// Cycles spent in the hook over all calls (0 without a cycle counter)
+    pub total_cycles: u64,
+    /// Most cycles a single call took
+    pub max_cycles: u64,
+}
+
+/// Errors from registering switch hooks.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum SpawnError {
-    /// Kernel has not been initialized
-    NotInitialized,
-    /// Out of memory for stack allocation
-    OutOfMemory,
-    /// Maximum number of threads reached
-    TooManyThreads,
-    /// Invalid stack size
-    InvalidStackSize,
+pub enum SwitchHookError {
+    /// All [`MAX_SWITCH_HOOKS`] slots are taken
+    TooManyHooks,
+}
+
+struct SwitchHookSlot {
+    /// The hook as a function address, 0 while the slot is free
+    hook: AtomicUsize,
+    calls: AtomicU64,
+    total_cycles: AtomicU64,
+    max_cycles: AtomicU64,
+}
+
+impl SwitchHookSlot {
+    const fn new() -> Self {
+        Self {
+            hook: AtomicUsize::new(0),
+            calls: AtomicU64::new(0),
+            total_cycles: AtomicU64::new(0),
+            max_cycles: AtomicU64::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_SLOT: SwitchHookSlot = SwitchHookSlot::new();
+
+/// Hooks run on every context switch, in slot order.
+static SWITCH_HOOKS: [SwitchHookSlot; MAX_SWITCH_HOOKS] = [FREE_SLOT; MAX_SWITCH_HOOKS];
+
+/// Run `hook` on every context switch.
+///
+/// Hooks run on the switching CPU with preemption disabled, after the
+/// incoming thread's address space is loaded and right before its registers
+/// are restored. That is the place to reprogram MPU regions, page tables or
+/// peripheral ownership for the incoming thread. They must be short and
+/// must not block; [`switch_hook_stats`] shows what each one costs.
+pub fn register_switch_hook(hook: SwitchHook) -> Result<SwitchHookId, SwitchHookError> {
+    for (index, slot) in SWITCH_HOOKS.iter().enumerate() {
+        if slot.hook.compare_exchange(0, hook as usize, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            return Ok(SwitchHookId(index));
+        }
+    }
+    Err(SwitchHookError::TooManyHooks)
+}
+
+/// Stop running a switch hook. Returns `false` if it was not registered.
+pub fn unregister_switch_hook(id: SwitchHookId) -> bool {
+    let Some(slot) = SWITCH_HOOKS.get(id.0) else {
+        return false;
+    };
+    if slot.hook.swap(0, Ordering::AcqRel) == 0 {
+        return false;
+    }
+    slot.calls.store(0, Ordering::Relaxed);
+    slot.total_cycles.store(0, Ordering::Relaxed);
+    slot.max_cycles.store(0, Ordering::Relaxed);
+    true
+}
+
+/// Get the call counts and cycle costs of every registered switch hook.
+pub fn switch_hook_stats() -> Vec<SwitchHookStats> {
+    SWITCH_HOOKS
+        .iter()
+        .enumerate()
+        .filter(|(_, slot)| slot.hook.load(Ordering::Acquire) != 0)
+        .map(|(index, slot)| SwitchHookStats {
+            id: SwitchHookId(index),
+            calls: slot.calls.load(Ordering::Relaxed),
+            total_cycles: slot.total_cycles.load(Ordering::Relaxed),
+            max_cycles: slot.max_cycles.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// Run the registered switch hooks for a switch from `prev` to `next`.
+pub(crate) fn run_switch_hooks(prev: ThreadId, next: ThreadId) {
+    let cycles = || crate::arch::DefaultArch::cycle_counter().map_or(0, |(cycles, _)| cycles);
+    for slot in &SWITCH_HOOKS {
+        let hook = slot.hook.load(Ordering::Acquire);
+        if hook == 0 {
+            continue;
+        }
+        // Safety: non-zero slots only ever hold a `SwitchHook` stored by
+        // `register_switch_hook`
+        let hook: SwitchHook = unsafe { core::mem::transmute::<usize, SwitchHook>(hook) };
+        
+        let start = cycles();
+        hook(prev, next);
+        let spent = cycles().saturating_sub(start);
+        slot.calls.fetch_add(1, Ordering::Relaxed);
+        slot.total_cycles.fetch_add(spent, Ordering::Relaxed);
+        slot.max_cycles.fetch_max(spent, Ordering::Relaxed);
+    }
+}
+
+/// What holds preemption back, and what was held back.
+struct Deferral {
+    /// Whether preemption is switched on, see [`preemption_master_switch`]
+    preemption_on: AtomicBool,
+    /// Number of live [`BootGuard`]s
+    boot_guards: AtomicUsize,
+    /// Whether a timer tick came in while preemption was deferred
+    tick_deferred: AtomicBool,
+    /// Whether a CPU is replaying what was held back
+    replaying: AtomicBool,
+}
+
+impl Deferral {
+    const fn new() -> Self {
+        Self {
+            preemption_on: AtomicBool::new(true),
+            boot_guards: AtomicUsize::new(0),
+            tick_deferred: AtomicBool::new(false),
+            replaying: AtomicBool::new(false),
+        }
+    }
+
+    fn deferred(&self) -> bool {
+        !self.preemption_on.load(Ordering::Acquire) || self.boot_guards.load(Ordering::Acquire) > 0
+    }
+
+    fn defer_tick(&self) -> bool {
+        if !self.deferred() {
+            return false;
+        }
+        self.tick_deferred.store(true, Ordering::Release);
+        true
+    }
+
+    /// Run `replay`, with whether a tick was held back, unless preemption
+    /// is still deferred or another CPU is already replaying.
+    fn replay(&self, mut replay: impl FnMut(bool)) {
+        loop {
+            if self.deferred() {
+                return;
+            }
+            if self.replaying.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                return;
+            }
+            replay(self.tick_deferred.swap(false, Ordering::AcqRel));
+            self.replaying.store(false, Ordering::Release);
+            // A CPU that deferred and undeferred again meanwhile left its
+            // replay to this one
+            if !self.tick_deferred.load(Ordering::Acquire) {
+                return;
+            }
+        }
+    }
+}
+
+static DEFERRAL: Deferral = Deferral::new();
+
+/// Switch preemption on or off for every CPU, returning whether it was on.
+///
+/// While it is off, timer ticks neither preempt threads nor expire
+/// timeouts, and [`preemption_checkpoint`](crate::platform_timer::preemption_checkpoint)
+/// leaves pending preemptions pending. Timekeeping goes on. What was held
+/// back is replayed once preemption is switched back on and no
+/// [`BootGuard`] is left: timeouts that expired in the meantime fire and
+/// unpark their threads, and one tick is accounted to the current thread,
+/// which may switch away from it.
+pub fn preemption_master_switch(enabled: bool) -> bool {
+    let was_enabled = DEFERRAL.preemption_on.swap(enabled, Ordering::AcqRel);
+    if enabled && !was_enabled {
+        replay_deferred();
+    }
+    was_enabled
+}
+
+/// Check whether preemption and timer callbacks are being deferred, by the
+/// [master switch](preemption_master_switch) or a [`BootGuard`].
+pub fn preemption_deferred() -> bool {
+    DEFERRAL.deferred()
+}
+
+/// Hold back a timer tick if preemption is deferred, to replay it later.
+/// Returns whether it was held back.
+pub(crate) fn defer_tick() -> bool {
+    DEFERRAL.defer_tick()
+}
+
+/// Replay what was held back while preemption was deferred, unless it
+/// still is. Only one CPU replays at a time.
+fn replay_deferred() {
+    DEFERRAL.replay(|tick_deferred| {
+        TIMER_WHEEL.advance_to(GLOBAL_TICK_COUNTER.ticks());
+        if tick_deferred {
+            crate::sched::global::timer_tick();
+        }
+    });
+}
+
+/// Scope in which preemption and timer callbacks are deferred, for boot
+/// phases that must not be interleaved with other threads.
+///
+/// ```ignore
+/// {
+///     let _boot = kernel::BootGuard::enter();
+///     init_devices();
+///     init_filesystems();
+/// } // Deferred ticks and wakeups are replayed here
+/// ```
+///
+/// Guards nest, also across threads; what was deferred is replayed when
+/// the last one is dropped, as with the
+/// [master switch](preemption_master_switch), which must be on too.
+pub struct BootGuard {
+    _private: (),
 }
 
+impl BootGuard {
+    /// Start deferring preemption and timer callbacks.
+    pub fn enter() -> Self {
+        DEFERRAL.boot_guards.fetch_add(1, Ordering::AcqRel);
+        Self { _private: () }
+    }
+}
+
+impl Drop for BootGuard {
+    fn drop(&mut self) {
+        if DEFERRAL.boot_guards.fetch_sub(1, Ordering::AcqRel) == 1 {
+            replay_deferred();
+        }
+    }
+}
+
+/// Errors that can occur when spawning threads.
+pub use crate::errors::SpawnError;
+
 // Safety: Kernel can be shared between threads as long as the scheduler is thread-safe
 unsafe impl<A: Arch, S: Scheduler> Send for Kernel<A, S> {}
-unsafe impl<A: Arch, S: Scheduler> Sync for Kernel<A, S> {}
\ No newline at end of file
+unsafe impl<A: Arch, S: Scheduler> Sync for Kernel<A, S> {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SWITCHES_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_switch(prev: ThreadId, next: ThreadId) {
+        if prev.get() == 49_230 && next.get() == 49_231 {
+            SWITCHES_SEEN.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_switch_hooks() {
+        let id = register_switch_hook(count_switch).unwrap();
+        let prev = ThreadId::new(49_230);
+        let next = ThreadId::new(49_231);
+        run_switch_hooks(prev, next);
+        run_switch_hooks(prev, next);
+        assert_eq!(SWITCHES_SEEN.load(Ordering::Relaxed), 2);
+
+        let stats = switch_hook_stats();
+        let ours = stats.iter().find(|stats| stats.id == id).unwrap();
+        assert!(ours.calls >= 2);
+        assert!(ours.max_cycles <= ours.total_cycles);
+
+        assert!(unregister_switch_hook(id));
+        assert!(!unregister_switch_hook(id));
+        run_switch_hooks(prev, next);
+        assert_eq!(SWITCHES_SEEN.load(Ordering::Relaxed), 2);
+
+        // The number of hooks is bounded
+        let ids: Vec<_> = core::iter::from_fn(|| register_switch_hook(count_switch).ok()).collect();
+        assert!(ids.len() <= MAX_SWITCH_HOOKS);
+        assert_eq!(register_switch_hook(count_switch), Err(SwitchHookError::TooManyHooks));
+        for id in ids {
+            unregister_switch_hook(id);
+        }
+    }
+
+    #[test]
+    fn test_deferral_replays_once_undeferred() {
+        let deferral = Deferral::new();
+        let replayed = core::cell::Cell::new(None);
+        assert!(!deferral.defer_tick());
+
+        deferral.boot_guards.store(2, Ordering::Release);
+        deferral.preemption_on.store(false, Ordering::Release);
+        assert!(deferral.defer_tick());
+        deferral.boot_guards.store(0, Ordering::Release);
+        deferral.replay(|tick| replayed.set(Some(tick)));
+        // The master switch still holds it back
+        assert_eq!(replayed.get(), None);
+
+        deferral.preemption_on.store(true, Ordering::Release);
+        deferral.replay(|tick| replayed.set(Some(tick)));
+        assert_eq!(replayed.get(), Some(true));
+        deferral.replay(|tick| replayed.set(Some(tick)));
+        assert_eq!(replayed.get(), Some(false));
+    }
+
+    #[test]
+    fn test_deferral_replays_on_one_cpu_at_a_time() {
+        let deferral = Deferral::new();
+        let replays = core::cell::Cell::new(0);
+        deferral.replay(|_| {
+            replays.set(replays.get() + 1);
+            // As if another CPU replayed meanwhile
+            deferral.replay(|_| replays.set(replays.get() + 1));
+        });
+        assert_eq!(replays.get(), 1);
+        assert!(!deferral.replaying.load(Ordering::Acquire));
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_timer_interrupt_held_back_while_deferred() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+
+        // The replay ticks the global scheduler, so keep this CPU's slot empty
+        let _slot = crate::thread_new::percpu::hold_slot();
+        crate::thread_new::percpu::clear_current();
+
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        kernel.init().unwrap();
+        let _handle = kernel.spawn(ThreadBuilder::new(), || {}).unwrap();
+        let running = kernel.scheduler().pick_next(0).unwrap().start_running();
+        running.0.cancel();
+        *kernel.current_thread.lock() = Some(running);
+
+        // The cancelled thread is only stopped once ticks are let through
+        let was_enabled = preemption_master_switch(false);
+        unsafe { kernel.handle_timer_interrupt() };
+        assert!(kernel.current_thread.lock().is_some());
+
+        preemption_master_switch(was_enabled);
+        unsafe { kernel.handle_timer_interrupt() };
+        assert!(kernel.current_thread.lock().is_none());
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_spawn_path() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+        use alloc::sync::Arc;
+
+        // Spawned without a parent, and the body runs as the spawned thread
+        let _slot = percpu::hold_slot();
+        percpu::clear_current();
+
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        assert_eq!(kernel.spawn(ThreadBuilder::new(), || {}).err(), Some(SpawnError::NotInitialized));
+        kernel.init().unwrap();
+
+        // A rejected spawn keeps neither the body nor a stack
+        let captured = Arc::new(AtomicBool::new(false));
+        let body = captured.clone();
+        let rejected = kernel.spawn(ThreadBuilder::new().stack_size(1024), move || body.store(true, Ordering::Release));
+        assert_eq!(rejected.err(), Some(SpawnError::InvalidStackSize(1024)));
+        assert_eq!(Arc::strong_count(&captured), 1);
+        assert_eq!(kernel.stack_pool().stats().2, 0);
+        assert!(kernel.scheduler().pick_next(0).is_none());
+
+        // An accepted one is configured by the builder and queued
+        let body = captured.clone();
+        let handle = kernel
+            .spawn(ThreadBuilder::new().name("spawn-path").priority(200), move || body.store(true, Ordering::Release))
+            .unwrap();
+        let thread = handle.thread();
+        assert_eq!(thread.name().as_deref(), Some("spawn-path"));
+        assert_eq!(thread.priority(), 200);
+        let ready = kernel.scheduler().pick_next(0).unwrap();
+        assert_eq!(ready.id(), handle.thread_id());
+
+        percpu::set_current(ready.0.clone());
+        run_spawned();
+        assert!(captured.load(Ordering::Acquire));
+        assert_eq!(Arc::strong_count(&captured), 1);
+        ready.start_running().finish();
+        assert_eq!(handle.join(), Ok(()));
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_spawn_over_child_quota() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+
+        let _slot = percpu::hold_slot();
+        percpu::clear_current();
+
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        kernel.init().unwrap();
+        let parent = kernel.spawn(ThreadBuilder::new(), || {}).unwrap().thread();
+        parent.set_max_children(1);
+        let parent = kernel.scheduler().pick_next(0).unwrap().start_running();
+
+        // Spawned from the parent, the second child is one too many
+        percpu::set_current(parent.0.clone());
+        let child = kernel.spawn(ThreadBuilder::new(), || {}).unwrap();
+        let rejected = kernel.spawn(ThreadBuilder::new(), || {});
+        assert_eq!(rejected.err(), Some(SpawnError::TooManyThreads));
+        assert_eq!(kernel.stack_pool().stats().2, 2);
+
+        // The slot frees up once the child has exited
+        kernel.scheduler().pick_next(0).unwrap().start_running().finish();
+        child.join().unwrap();
+        let again = kernel.spawn(ThreadBuilder::new(), || {}).unwrap();
+        assert_eq!(again.thread().parent(), Some(parent.0.id()));
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_cpu_quota_terminates() {
+        use crate::arch::DefaultArch;
+        use crate::observability::resource_limits::{init_resource_limiter, ResourceQuota, ResourceType};
+        use crate::sched::RoundRobinScheduler;
+
+        // Also keeps the deferral test from turning ticks off meanwhile
+        let _slot = percpu::hold_slot();
+        percpu::clear_current();
+        init_resource_limiter().unwrap();
+
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        kernel.init().unwrap();
+        let thread = kernel.spawn(ThreadBuilder::new(), || {}).unwrap().thread();
+        let quota = ResourceQuota { max_cpu_time_ns: 1, hard_limits: true, ..ResourceQuota::default() };
+        GLOBAL_RESOURCE_LIMITER.set_thread_quota(thread.id(), quota);
+        *kernel.current_thread.lock() = Some(kernel.scheduler().pick_next(0).unwrap().start_running());
+
+        // One tick is over the quota
+        unsafe { kernel.handle_timer_interrupt() };
+        assert!(kernel.current_thread.lock().is_none());
+        assert_eq!(thread.exit_status(), Some(ExitStatus::Terminated));
+        assert!(GLOBAL_RESOURCE_LIMITER
+            .get_violation_history()
+            .iter()
+            .any(|violation| violation.thread_id == thread.id() && violation.resource_type == ResourceType::CpuTime));
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_join_any_and_reap() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        kernel.init().unwrap();
+        let kept = kernel.spawn(ThreadBuilder::new(), || {}).unwrap();
+        let detached = kernel.spawn(ThreadBuilder::new(), || {}).unwrap();
+        let kept_id = kept.thread_id();
+        drop(detached);
+        assert_eq!(kernel.stack_pool().stats().2, 2);
+
+        while let Some(ready) = kernel.scheduler().pick_next(0) {
+            ready.start_running().finish();
+        }
+
+        // Only the thread with a live handle is joinable, and only once
+        assert_eq!(join_any(Duration::from_millis(10)), Ok(kept_id));
+        assert_eq!(join_any(Duration::from_nanos(0)), Err(ThreadError::TimedOut));
+        assert_eq!(kept.try_join(), Some(Ok(())));
+
+        // The detached thread's stack goes back to the pool right away
+        assert_eq!(kernel.reap(), 1);
+        assert_eq!(kernel.stack_pool().stats().2, 1);
+        kept.join().unwrap();
+        assert_eq!(kernel.reap(), 1);
+        assert_eq!(kernel.stack_pool().stats().2, 0);
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_exit_hooks_run_in_reverse_before_reclaim() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+
+        static ORDER: spin::Mutex<Vec<u8>> = spin::Mutex::new(Vec::new());
+
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        kernel.init().unwrap();
+        let thread = kernel.spawn(ThreadBuilder::new(), || {}).unwrap().thread();
+        thread.at_exit(|| ORDER.lock().push(1));
+        thread.at_exit(|| ORDER.lock().push(2));
+
+        // Hooks run for threads that did not complete as well
+        while let Some(ready) = kernel.scheduler().pick_next(0) {
+            ready.start_running().exit(ExitStatus::Cancelled);
+        }
+        assert!(ORDER.lock().is_empty());
+        drop(thread);
+        assert_eq!(kernel.reap(), 1);
+        assert_eq!(*ORDER.lock(), [2, 1]);
+        assert_eq!(kernel.stack_pool().stats().2, 0);
+
+        // They run once
+        kernel.reap();
+        assert_eq!(*ORDER.lock(), [2, 1]);
+    }
+}
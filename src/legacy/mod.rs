@@ -0,0 +1,56 @@
+//! The original thread and scheduler implementation.
+//!
+//! [`crate::thread`] and [`crate::scheduler`] keep the API of these types
+//! on top of [`thread_new`](crate::thread_new) and [`sched`](crate::sched)
+//! with the `std-shim` feature, which they need to allocate threads. The
+//! implementation they replaced is kept here. Without `std-shim` they are
+//! this implementation, and with it, the `legacy` feature keeps it for
+//! code that depends on its internals such as the saved register layout or
+//! the fixed-size thread table.
+
+pub mod scheduler;
+pub mod thread;
+
+use scheduler::SCHEDULER;
+
+/// Switch to the next thread of the legacy scheduler.
+pub fn yield_thread() {
+    unsafe {
+        let scheduler = SCHEDULER.get();
+
+        if let Some(current_id) = scheduler.get_current_thread() {
+            if let Some(next_id) = scheduler.schedule() {
+                if current_id != next_id {
+                    scheduler.set_current_thread(Some(next_id));
+                    let _ = scheduler.switch_context(current_id, next_id);
+                }
+            }
+        }
+    }
+}
+
+/// Finish the current thread of the legacy scheduler.
+pub fn exit_thread() -> ! {
+    unsafe {
+        let scheduler = SCHEDULER.get();
+
+        if let Some(current_id) = scheduler.get_current_thread() {
+            scheduler.exit_current_thread();
+
+            if let Some(next_id) = scheduler.schedule() {
+                scheduler.set_current_thread(Some(next_id));
+                let _ = scheduler.switch_context(current_id, next_id);
+            }
+        }
+    }
+
+    loop {
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            core::arch::asm!("hlt")
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        core::hint::spin_loop();
+    }
+}
@@ -11,6 +11,8 @@
 //!
 //! # Features
 //!
+//! - `std`: Enable the host OS backed architecture, which runs threads on
+//!   host threads for development
 //! - `std-shim`: Enable compatibility layer for standard library
 //! - `x86_64`: Enable x86_64 architecture support  
 //! - `arm64`: Enable ARM64 architecture support
@@ -19,6 +21,18 @@
 //! - `mmu`: Enable memory management unit features like guard pages
 //! - `work-stealing`: Enable work-stealing scheduler implementation
 //! - `hardened`: Enable security hardening features
+//! - `vdso`: Enable the reader for the user-mode time and thread id page
+//! - `tracepoints`: Compile in the scheduler tracepoints of `observability::trace`
+//! - `sim`: Enable the virtual-time architecture backend and the `sim`
+//!   scheduler simulator
+//! - `checked-reclaim`: Use boxed, validated memory reclamation in the
+//!   lock-free pools and reclamation schemes, for running under Miri and
+//!   sanitizers
+//! - `fault-injection`: Check the faults configured in `testing::faults` at
+//!   stack allocation, CAS, timer and wakeup sites
+//! - `debug`: Enable in-target inspection of stopped threads
+//! - `log`: Enable the `log` module: semihosting, RTT and in-memory output
+//!   backends for the `log` crate
 //!
 //! # Architecture
 //!
@@ -30,23 +44,40 @@
 
 pub mod arch;
 pub mod atomic_scheduler;
+pub mod console;
 pub mod context;
 pub mod context_full;
+#[cfg(feature = "debug")]
+pub mod debug;
 pub mod error;
 pub mod errors;
+pub mod io;
 pub mod kernel;
+#[cfg(any(feature = "legacy", not(feature = "std-shim")))]
+pub mod legacy;
+#[cfg(feature = "log")]
+pub mod log;
 pub mod mem;
 pub mod observability;
 pub mod perf;
+pub mod pipeline;
 pub mod platform_timer;
 pub mod preemption;
+pub mod resources;
 pub mod safe_api;
 pub mod sched;
+#[cfg(feature = "std-shim")]
 pub mod scheduler;
+#[cfg(not(feature = "std-shim"))]
+pub use legacy::scheduler;
 pub mod security;
 pub mod signal_safe;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod stack_guard;
+pub mod supervisor;
 pub mod sync;
+pub mod testing;
 pub mod thread;
 pub mod thread_new;
 pub mod time;
@@ -73,25 +104,28 @@ fn panic(_info: &PanicInfo) -> ! {
 
 pub use arch::{Arch, DefaultArch};
 pub use atomic_scheduler::{AtomicScheduler, ATOMIC_SCHEDULER};
-pub use error::{ThreadError, ThreadResult};  
-pub use kernel::{Kernel, SpawnError};
+pub use errors::{ErrorKind, Subsystem, ThreadError, ThreadResult};
+pub use kernel::{Kernel, SpawnError, ThreadInfo};
 pub use mem::{ArcLite, Stack, StackPool, StackSizeClass};
 pub use platform_timer::{init_preemption_timer, stop_preemption_timer, preemption_checkpoint};
 pub use safe_api::{
-    exit_thread as safe_exit, yield_now, Mutex, MutexGuard, ThreadBuilder as OldThreadBuilder, ThreadHandle, ThreadPool,
+    exit_thread as safe_exit, yield_now, Condvar, Mutex, MutexGuard, ThreadHandle, ThreadPool,
 };
+#[cfg(feature = "compat")]
+#[allow(deprecated)]
+pub use safe_api::ThreadBuilder as OldThreadBuilder;
 pub use scheduler::{Scheduler as OldScheduler, SCHEDULER};
 pub use stack_guard::{ProtectedStack, StackGuard, StackStats, StackStatus};
-pub use sync::{exit_thread, yield_thread};
+pub use sync::{exit_thread, yield_thread, IrqSafe, SpinLockIrqSave};
 pub use thread::{Thread as OldThread, ThreadState as OldThreadState};
-pub use thread_new::{Thread, ThreadId, ThreadState, JoinHandle, ThreadBuilder, ReadyRef, RunningRef};
+pub use thread_new::{Thread, ThreadId, ThreadState, ExitStatus, JoinHandle, ThreadBuilder, ReadyRef, RunningRef, SuspendError};
 pub use time::{Duration, Instant, Timer, TimerConfig, PreemptGuard, IrqGuard};
-pub use observability::{ThreadMetrics, SystemMetrics, ResourceLimiter, ThreadProfiler, HealthMonitor, ObservabilityConfig, init_observability, cleanup_observability};
+pub use observability::{ThreadMetrics, SystemMetrics, ResourceLimiter, ThreadProfiler, HealthMonitor, HealthSummary, ObservabilityConfig, init_observability, cleanup_observability};
 
 // Security and hardening exports
 pub use security::{SecurityConfig, SecurityViolation, SecurityStats, SecurityFeature, init_security, get_security_stats, configure_security_feature};
 
 // New lock-free scheduler exports
-pub use sched::{Scheduler as NewScheduler, CpuId, RoundRobinScheduler, DefaultScheduler};
+pub use sched::{Scheduler as NewScheduler, CpuId, CpuSet, RoundRobinScheduler, DefaultScheduler};
 #[cfg(feature = "work-stealing")]
 pub use sched::WorkStealingScheduler;
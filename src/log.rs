@@ -0,0 +1,517 @@
+//! Text output and `log` crate integration for `no_std` targets.
+//!
+//! A [`LogBackend`] is a byte sink for human-readable output. Three are
+//! provided:
+//!
+//! - [`Semihosting`] writes through the debugger's semihosting interface
+//!   (ARM, AArch64 and RISC-V)
+//! - [`Rtt`] is a SEGGER RTT up channel read by the debug probe
+//! - [`MemoryRing`] keeps the most recent output in RAM for later reading,
+//!   e.g. from a crash dump or the [`console`](crate::console)
+//!
+//! [`init`] installs a backend as the `log` crate's logger, so `log::info!`
+//! and friends work anywhere in the program. [`kprint!`](crate::kprint) and
+//! [`kprintln!`](crate::kprintln) write to the same backend unconditionally.
+//!
+//! ```ignore
+//! static RING: MemoryRing<4096> = MemoryRing::new();
+//!
+//! preemptive_threads::log::init(&RING, log::LevelFilter::Info).unwrap();
+//! log::info!("scheduler started");
+//! ```
+//!
+//! Log lines are prefixed with the time since boot and the level:
+//!
+//! ```text
+//! [     1.204512] INFO  app::net: link up
+//! ```
+
+use crate::sync::SpinLockIrqSave;
+use crate::time::Instant;
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write as _};
+use portable_atomic::{AtomicPtr, Ordering};
+
+/// Destination for text output.
+///
+/// Writes must not block for long and must be callable from interrupt
+/// handlers; backends drop output they have no room for.
+pub trait LogBackend: Send + Sync {
+    /// Write raw bytes.
+    fn write_bytes(&self, bytes: &[u8]);
+
+    /// Flush buffered output, if the backend buffers.
+    fn flush(&self) {}
+}
+
+/// Logger that formats records onto a registered [`LogBackend`].
+pub struct Logger {
+    /// Registered backend, as a leaked box holding the trait object
+    backend: AtomicPtr<&'static dyn LogBackend>,
+    /// Serializes lines so output from different CPUs does not interleave
+    line: SpinLockIrqSave<()>,
+}
+
+impl Logger {
+    /// Create a logger with no backend; output is discarded until one is set.
+    pub const fn new() -> Self {
+        Self {
+            backend: AtomicPtr::new(core::ptr::null_mut()),
+            line: SpinLockIrqSave::new(()),
+        }
+    }
+
+    /// Send output to `backend` from now on.
+    pub fn set_backend(&self, backend: &'static dyn LogBackend) {
+        // Any previous box is leaked on purpose: other CPUs may still be
+        // writing to the old backend
+        self.backend.store(Box::into_raw(Box::new(backend)), Ordering::Release);
+    }
+
+    /// Get the registered backend.
+    pub fn backend(&self) -> Option<&'static dyn LogBackend> {
+        // Safety: non-null pointers come from a leaked box in `set_backend`
+        unsafe { self.backend.load(Ordering::Acquire).as_ref().copied() }
+    }
+
+    /// Write formatted text without a prefix or trailing newline.
+    pub fn print(&self, args: fmt::Arguments<'_>) {
+        if let Some(backend) = self.backend() {
+            let _line = self.line.lock();
+            let _ = BackendWriter(backend).write_fmt(args);
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ::log::Log for Logger {
+    fn enabled(&self, metadata: &::log::Metadata<'_>) -> bool {
+        metadata.level() <= ::log::max_level() && self.backend().is_some()
+    }
+
+    fn log(&self, record: &::log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let Some(backend) = self.backend() else {
+            return;
+        };
+
+        let nanos = Instant::now().as_nanos();
+        let _line = self.line.lock();
+        let _ = writeln!(
+            BackendWriter(backend),
+            "[{:>6}.{:06}] {:<5} {}: {}",
+            nanos / 1_000_000_000,
+            nanos / 1_000 % 1_000_000,
+            record.level(),
+            record.target(),
+            record.args(),
+        );
+    }
+
+    fn flush(&self) {
+        if let Some(backend) = self.backend() {
+            backend.flush();
+        }
+    }
+}
+
+/// Global logger installed by [`init`].
+pub static LOGGER: Logger = Logger::new();
+
+/// Install `backend` as the destination of the `log` crate's macros, and
+/// log records up to `level`.
+///
+/// Fails if another logger has already been installed; the backend is still
+/// used by [`kprint!`](crate::kprint) in that case.
+pub fn init(backend: &'static dyn LogBackend, level: ::log::LevelFilter) -> Result<(), ::log::SetLoggerError> {
+    LOGGER.set_backend(backend);
+    ::log::set_logger(&LOGGER)?;
+    ::log::set_max_level(level);
+    Ok(())
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments<'_>) {
+    LOGGER.print(args);
+}
+
+/// Print to the global logger's backend.
+#[macro_export]
+macro_rules! kprint {
+    ($($arg:tt)*) => {
+        $crate::log::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print a line to the global logger's backend.
+#[macro_export]
+macro_rules! kprintln {
+    () => {
+        $crate::log::_print(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::log::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Adapts a backend to [`fmt::Write`].
+struct BackendWriter(&'static dyn LogBackend);
+
+impl fmt::Write for BackendWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Output through the debugger's semihosting interface.
+///
+/// Every write traps into the debugger, which makes it slow, and halts the
+/// CPU if no debugger is attached. Use it for bring-up, not production.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64", target_arch = "riscv64"))]
+pub struct Semihosting;
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64", target_arch = "riscv64"))]
+impl Semihosting {
+    /// `SYS_WRITE0`: write a NUL-terminated string to the debug console.
+    const SYS_WRITE0: usize = 0x04;
+
+    /// Issue a semihosting call.
+    ///
+    /// # Safety
+    ///
+    /// A debugger must be attached, and `param` must be valid for `op`.
+    unsafe fn call(op: usize, param: *const u8) -> usize {
+        let result: usize;
+
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            core::arch::asm!(
+                "hlt #0xf000",
+                inout("x0") op => result,
+                in("x1") param,
+                options(nostack)
+            );
+        }
+
+        #[cfg(target_arch = "arm")]
+        unsafe {
+            core::arch::asm!(
+                "bkpt #0xab",
+                inout("r0") op => result,
+                in("r1") param,
+                options(nostack)
+            );
+        }
+
+        // The debugger recognises the call by this exact, uncompressed
+        // instruction sequence
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!(
+                ".option push",
+                ".option norvc",
+                "slli x0, x0, 0x1f",
+                "ebreak",
+                "srai x0, x0, 7",
+                ".option pop",
+                inout("a0") op => result,
+                in("a1") param,
+                options(nostack)
+            );
+        }
+
+        result
+    }
+}
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64", target_arch = "riscv64"))]
+impl LogBackend for Semihosting {
+    fn write_bytes(&self, bytes: &[u8]) {
+        // Copy into NUL-terminated chunks; interior NULs are dropped
+        let mut chunk = [0u8; 65];
+        for part in bytes.chunks(chunk.len() - 1) {
+            let mut len = 0;
+            for &byte in part.iter().filter(|&&byte| byte != 0) {
+                chunk[len] = byte;
+                len += 1;
+            }
+            chunk[len] = 0;
+
+            // Safety: the chunk is NUL-terminated; attaching a debugger is
+            // the documented requirement of this backend
+            unsafe {
+                Self::call(Self::SYS_WRITE0, chunk.as_ptr());
+            }
+        }
+    }
+}
+
+/// Up-buffer descriptor, laid out as the debug probe expects.
+#[repr(C)]
+struct RttUpBuffer {
+    name: *const u8,
+    buffer: *mut u8,
+    size: u32,
+    write: u32,
+    read: u32,
+    flags: u32,
+}
+
+/// RTT control block with one up channel and no down channels.
+#[repr(C)]
+struct RttControlBlock {
+    id: [u8; 16],
+    max_up_buffers: i32,
+    max_down_buffers: i32,
+    up: RttUpBuffer,
+}
+
+/// SEGGER RTT up channel.
+///
+/// The debug probe finds the control block by scanning RAM for its ID and
+/// drains the buffer while the target runs. Output that does not fit
+/// because the probe has not caught up is dropped. Place the channel in a
+/// `static` in RAM:
+///
+/// ```ignore
+/// static RTT: Rtt<1024> = Rtt::new();
+/// ```
+pub struct Rtt<const N: usize> {
+    control: UnsafeCell<RttControlBlock>,
+    buffer: UnsafeCell<[u8; N]>,
+    /// Serializes writers; holds whether the control block is set up
+    writer: SpinLockIrqSave<bool>,
+}
+
+// Safety: the target only writes under `writer`; the probe's accesses are
+// the RTT protocol's concern and go through volatile reads and writes
+unsafe impl<const N: usize> Sync for Rtt<N> {}
+unsafe impl<const N: usize> Send for Rtt<N> {}
+
+impl<const N: usize> Rtt<N> {
+    const CHANNEL_NAME: &'static [u8] = b"Terminal\0";
+
+    /// Create a channel with an `N`-byte buffer.
+    ///
+    /// The control block is published on the first write, once the channel
+    /// is at its final address.
+    pub const fn new() -> Self {
+        Self {
+            control: UnsafeCell::new(RttControlBlock {
+                id: [0; 16],
+                max_up_buffers: 1,
+                max_down_buffers: 0,
+                up: RttUpBuffer {
+                    name: core::ptr::null(),
+                    buffer: core::ptr::null_mut(),
+                    size: 0,
+                    write: 0,
+                    read: 0,
+                    flags: 0,
+                },
+            }),
+            buffer: UnsafeCell::new([0; N]),
+            writer: SpinLockIrqSave::new(false),
+        }
+    }
+
+    /// Fill in the buffer descriptor, then the ID the probe searches for.
+    fn publish(&self) {
+        let control = self.control.get();
+
+        // Safety: called once under `writer`, before the ID makes the block
+        // visible to the probe
+        unsafe {
+            (*control).up.name = Self::CHANNEL_NAME.as_ptr();
+            (*control).up.buffer = self.buffer.get().cast();
+            (*control).up.size = N as u32;
+
+            core::sync::atomic::fence(Ordering::SeqCst);
+            for (index, &byte) in b"SEGGER RTT".iter().enumerate() {
+                core::ptr::write_volatile(&mut (*control).id[index], byte);
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for Rtt<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogBackend for Rtt<N> {
+    fn write_bytes(&self, bytes: &[u8]) {
+        let mut published = self.writer.lock();
+        if !*published {
+            self.publish();
+            *published = true;
+        }
+
+        let control = self.control.get();
+        let buffer = self.buffer.get().cast::<u8>();
+
+        // Safety: writers are serialized by `writer`; `read` is updated by
+        // the probe, so it is only accessed with volatile reads
+        unsafe {
+            let read = core::ptr::read_volatile(&(*control).up.read) as usize;
+            let mut write = (*control).up.write as usize;
+
+            // One byte stays free to tell a full buffer from an empty one
+            let free = if read > write { read - write - 1 } else { N - 1 - (write - read) };
+            for &byte in &bytes[..bytes.len().min(free)] {
+                core::ptr::write_volatile(buffer.add(write), byte);
+                write = (write + 1) % N;
+            }
+
+            core::sync::atomic::fence(Ordering::SeqCst);
+            core::ptr::write_volatile(&mut (*control).up.write, write as u32);
+        }
+    }
+}
+
+struct RingState<const N: usize> {
+    bytes: [u8; N],
+    /// Total bytes ever written
+    written: u64,
+    /// Total bytes ever read or overwritten
+    read: u64,
+    /// Bytes overwritten before they were read
+    dropped: u64,
+}
+
+/// In-memory ring of the most recent output.
+///
+/// When full, new output overwrites the oldest unread bytes.
+pub struct MemoryRing<const N: usize> {
+    state: SpinLockIrqSave<RingState<N>>,
+}
+
+impl<const N: usize> MemoryRing<N> {
+    /// Create an empty ring holding up to `N` bytes.
+    pub const fn new() -> Self {
+        Self {
+            state: SpinLockIrqSave::new(RingState {
+                bytes: [0; N],
+                written: 0,
+                read: 0,
+                dropped: 0,
+            }),
+        }
+    }
+
+    /// Get the number of unread bytes.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock();
+        (state.written - state.read) as usize
+    }
+
+    /// Check whether there are no unread bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of bytes overwritten before they were read.
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().dropped
+    }
+
+    /// Move unread bytes into `out`, oldest first. Returns how many were
+    /// read.
+    pub fn read(&self, out: &mut [u8]) -> usize {
+        let mut state = self.state.lock();
+        let count = ((state.written - state.read) as usize).min(out.len());
+        for slot in &mut out[..count] {
+            *slot = state.bytes[(state.read % N as u64) as usize];
+            state.read += 1;
+        }
+        count
+    }
+
+    /// Copy all unread bytes out without consuming them.
+    pub fn contents(&self) -> Vec<u8> {
+        let state = self.state.lock();
+        (state.read..state.written)
+            .map(|index| state.bytes[(index % N as u64) as usize])
+            .collect()
+    }
+}
+
+impl<const N: usize> Default for MemoryRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogBackend for MemoryRing<N> {
+    fn write_bytes(&self, bytes: &[u8]) {
+        if N == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock();
+        for &byte in bytes {
+            let index = (state.written % N as u64) as usize;
+            state.bytes[index] = byte;
+            state.written += 1;
+
+            if state.written - state.read > N as u64 {
+                state.read += 1;
+                state.dropped += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::log::Log;
+
+    #[test]
+    fn test_memory_ring_and_logger() {
+        static RING: MemoryRing<16> = MemoryRing::new();
+        static LINES: MemoryRing<64> = MemoryRing::new();
+        static TEST_LOGGER: Logger = Logger::new();
+
+        RING.write_bytes(b"0123456789");
+        let mut out = [0u8; 4];
+        assert_eq!(RING.read(&mut out), 4);
+        assert_eq!(&out, b"0123");
+
+        // Overwrites the oldest two unread bytes
+        RING.write_bytes(b"abcdefghijkl");
+        assert_eq!(RING.dropped(), 2);
+        assert_eq!(RING.contents(), b"6789abcdefghijkl");
+        assert_eq!(RING.read(&mut [0u8; 64]), 16);
+        assert!(RING.is_empty());
+
+        TEST_LOGGER.set_backend(&LINES);
+        TEST_LOGGER.print(format_args!("boot {}\n", 1));
+        assert_eq!(LINES.contents(), b"boot 1\n");
+        LINES.read(&mut [0u8; 64]);
+
+        ::log::set_max_level(::log::LevelFilter::Info);
+        TEST_LOGGER.log(
+            &::log::Record::builder()
+                .level(::log::Level::Warn)
+                .target("net")
+                .args(format_args!("up"))
+                .build(),
+        );
+        let line = LINES.contents();
+        assert!(line.starts_with(b"["));
+        assert!(line.ends_with(b"] WARN  net: up\n"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Address spaces with ASID/PCID-tagged TLB entries.
+//!
+//! Kernels that run threads in separate address spaces attach an
+//! [`AddressSpace`] to each such thread. The context switch activates the
+//! next thread's space when it differs from the one loaded on the CPU;
+//! threads without a space run in whatever space is loaded, like kernel
+//! threads borrowing the last user page tables.
+//!
+//! Each space is tagged with an ASID (a PCID on x86_64) so switching does
+//! not flush the TLB. ASIDs are handed out per generation: once a
+//! generation runs out, a new one starts, every space gets a fresh ASID the
+//! next time it is activated, and every CPU flushes its whole TLB once
+//! before loading a space of the new generation.
+//!
+//! After changing a space's mappings, call [`AddressSpace::shootdown`] so
+//! the other CPUs that ran it drop their stale entries.
+
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::arch::{Arch, DefaultArch};
+use crate::sync::SpinLockIrqSave;
+use core::ptr;
+use portable_atomic::{AtomicPtr, AtomicU64, Ordering};
+
+/// Largest number of ASIDs tracked, whatever the architecture offers.
+pub const MAX_ASIDS: usize = 4096;
+
+/// ASID reserved for untagged kernel mappings; never assigned to a space.
+pub const KERNEL_ASID: u16 = 0;
+
+/// Bits of `AddressSpace::asid` holding the ASID; the rest is the
+/// generation (0 = never assigned).
+const ASID_SHIFT: u32 = 16;
+const ASID_MASK: u64 = (1 << ASID_SHIFT) - 1;
+
+/// Every CPU.
+const ALL_CPUS: u64 = if MAX_CPUS >= 64 { u64::MAX } else { (1 << MAX_CPUS) - 1 };
+
+/// A set of page tables threads can run in.
+pub struct AddressSpace {
+    /// Physical address of the page-table root
+    root: usize,
+    /// Generation and ASID last assigned
+    asid: AtomicU64,
+    /// CPUs that have loaded this space and may cache its entries
+    active_cpus: AtomicU64,
+    /// CPUs that must flush this space's entries before using it again
+    stale_cpus: AtomicU64,
+}
+
+impl AddressSpace {
+    /// Create a space for the page tables rooted at `root`.
+    ///
+    /// # Safety
+    ///
+    /// `root` must be the physical address of page tables that map the
+    /// kernel and stay valid while any thread runs in this space.
+    pub const unsafe fn new(root: usize) -> Self {
+        Self {
+            root,
+            asid: AtomicU64::new(0),
+            active_cpus: AtomicU64::new(0),
+            stale_cpus: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the physical address of the page-table root.
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// Get the ASID assigned in the current generation, if any.
+    pub fn asid(&self) -> Option<u16> {
+        ASIDS.current_asid(self)
+    }
+
+    /// Get the CPUs that may hold TLB entries for this space.
+    pub fn active_cpus(&self) -> u64 {
+        self.active_cpus.load(Ordering::Acquire)
+    }
+
+    /// Load this space on the current CPU, unless it already is.
+    ///
+    /// Called by the context switch with preemption disabled.
+    pub fn activate(&self) {
+        let cpu = cpu_id();
+        let bit = 1u64 << cpu;
+        let loaded = ptr::eq(LOADED[cpu].load(Ordering::Relaxed), self);
+        let stale = self.stale_cpus.load(Ordering::Acquire) & bit != 0;
+        if loaded && !stale && !ASIDS.rollover_pending(cpu) {
+            return;
+        }
+
+        let (asid, flush_all) = ASIDS.assign(self, cpu);
+        if flush_all {
+            // Safety: a TLB flush only costs refills
+            unsafe { DefaultArch::flush_tlb(None) };
+        }
+        self.active_cpus.fetch_or(bit, Ordering::AcqRel);
+        let flush = self.stale_cpus.fetch_and(!bit, Ordering::AcqRel) & bit != 0;
+
+        // Safety: `root` is valid per `new`'s contract
+        unsafe { DefaultArch::switch_address_space(self.root, asid, flush && !flush_all) };
+        LOADED[cpu].store(self as *const Self as *mut Self, Ordering::Relaxed);
+    }
+
+    /// Drop this CPU's TLB entries for the space.
+    pub fn flush_local(&self) {
+        if let Some(asid) = self.asid() {
+            // Safety: a TLB flush only costs refills
+            unsafe { DefaultArch::flush_tlb(Some(asid)) };
+        }
+        self.stale_cpus.fetch_and(!(1u64 << cpu_id()), Ordering::AcqRel);
+    }
+
+    /// Make every CPU drop its TLB entries for this space after its
+    /// mappings changed.
+    ///
+    /// Flushes the current CPU right away. Other CPUs that ran the space
+    /// are marked stale and flush before they next use it; if a shootdown
+    /// IPI is registered with [`set_shootdown_ipi`] they are also
+    /// interrupted so a CPU running the space now flushes immediately.
+    /// Returns the CPUs left to flush.
+    pub fn shootdown(&self) -> u64 {
+        let local = 1u64 << cpu_id();
+        let remote = self.active_cpus.load(Ordering::Acquire) & !local;
+        self.stale_cpus.fetch_or(remote, Ordering::AcqRel);
+        self.flush_local();
+
+        if remote != 0 {
+            if let Some(ipi) = *SHOOTDOWN_IPI.lock() {
+                ipi(remote);
+            }
+        }
+        remote
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        ASIDS.release(self);
+        for slot in &LOADED {
+            let _ = slot.compare_exchange(self as *mut Self, ptr::null_mut(), Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Generation-based ASID allocator.
+pub struct AsidAllocator {
+    state: SpinLockIrqSave<AsidState>,
+    /// CPUs that must flush their whole TLB before loading a space of the
+    /// current generation
+    rollover_pending: AtomicU64,
+}
+
+struct AsidState {
+    generation: u64,
+    /// ASIDs in use in the current generation
+    used: [u64; MAX_ASIDS / 64],
+    /// Number of ASIDs available, `KERNEL_ASID` included
+    limit: usize,
+    /// Where to start looking for a free ASID
+    next: usize,
+}
+
+impl AsidAllocator {
+    /// Create an allocator for `bits`-bit ASIDs.
+    ///
+    /// With 0 bits there is no tagging: every space gets `KERNEL_ASID` and
+    /// every activation flushes.
+    pub const fn new(bits: u32) -> Self {
+        let limit = if bits >= 12 { MAX_ASIDS } else { 1 << bits };
+        Self {
+            state: SpinLockIrqSave::new(AsidState {
+                generation: 1,
+                used: [0; MAX_ASIDS / 64],
+                limit,
+                next: 1,
+            }),
+            rollover_pending: AtomicU64::new(0),
+        }
+    }
+
+    /// Get `space`'s ASID if it was assigned in the current generation.
+    pub fn current_asid(&self, space: &AddressSpace) -> Option<u16> {
+        let state = self.state.lock();
+        let tagged = space.asid.load(Ordering::Acquire);
+        (tagged >> ASID_SHIFT == state.generation).then_some((tagged & ASID_MASK) as u16)
+    }
+
+    /// Check whether `cpu` still owes a full flush for the last rollover.
+    pub fn rollover_pending(&self, cpu: usize) -> bool {
+        self.rollover_pending.load(Ordering::Acquire) & (1 << cpu) != 0
+    }
+
+    /// Get the ASID for `space` on `cpu`, assigning one if the space has
+    /// none in the current generation.
+    ///
+    /// Also returns whether `cpu` must flush its whole TLB first.
+    pub fn assign(&self, space: &AddressSpace, cpu: usize) -> (u16, bool) {
+        let mut state = self.state.lock();
+
+        let asid = if state.limit <= 1 {
+            // Untagged: every switch flushes anyway
+            space.stale_cpus.fetch_or(1 << cpu, Ordering::AcqRel);
+            KERNEL_ASID
+        } else {
+            let tagged = space.asid.load(Ordering::Acquire);
+            if tagged >> ASID_SHIFT == state.generation {
+                (tagged & ASID_MASK) as u16
+            } else {
+                let asid = match state.take_free() {
+                    Some(asid) => asid,
+                    None => {
+                        // Out of ASIDs: start a generation, retiring every
+                        // assignment and flushing every CPU once
+                        state.generation += 1;
+                        state.used = [0; MAX_ASIDS / 64];
+                        state.next = 1;
+                        self.rollover_pending.store(ALL_CPUS, Ordering::Release);
+                        state.take_free().unwrap_or(KERNEL_ASID)
+                    }
+                };
+                space.asid.store((state.generation << ASID_SHIFT) | asid as u64, Ordering::Release);
+                asid
+            }
+        };
+
+        let flush_all = self.rollover_pending.fetch_and(!(1 << cpu), Ordering::AcqRel) & (1 << cpu) != 0;
+        (asid, flush_all)
+    }
+
+    /// Give back `space`'s ASID if it belongs to the current generation.
+    pub fn release(&self, space: &AddressSpace) {
+        let mut state = self.state.lock();
+        let tagged = space.asid.swap(0, Ordering::AcqRel);
+        if tagged >> ASID_SHIFT == state.generation {
+            let asid = (tagged & ASID_MASK) as usize;
+            state.used[asid / 64] &= !(1 << (asid % 64));
+        }
+    }
+}
+
+impl AsidState {
+    fn take_free(&mut self) -> Option<u16> {
+        for offset in 0..self.limit - 1 {
+            // Cycle through 1..limit, skipping KERNEL_ASID
+            let asid = 1 + (self.next - 1 + offset) % (self.limit - 1);
+            if self.used[asid / 64] & (1 << (asid % 64)) == 0 {
+                self.used[asid / 64] |= 1 << (asid % 64);
+                self.next = asid + 1;
+                return Some(asid as u16);
+            }
+        }
+        None
+    }
+}
+
+/// Handler that interrupts the CPUs in a mask so they process pending
+/// shootdowns; see [`handle_shootdown_ipi`].
+pub type ShootdownIpi = fn(cpus: u64);
+
+/// Global ASID allocator, sized by the architecture.
+pub static ASIDS: AsidAllocator = AsidAllocator::new(<DefaultArch as Arch>::ASID_BITS);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOTHING_LOADED: AtomicPtr<AddressSpace> = AtomicPtr::new(ptr::null_mut());
+
+/// Space loaded on each CPU.
+static LOADED: [AtomicPtr<AddressSpace>; MAX_CPUS] = [NOTHING_LOADED; MAX_CPUS];
+
+static SHOOTDOWN_IPI: spin::Mutex<Option<ShootdownIpi>> = spin::Mutex::new(None);
+
+/// Register the platform hook that sends shootdown IPIs.
+pub fn set_shootdown_ipi(ipi: ShootdownIpi) {
+    *SHOOTDOWN_IPI.lock() = Some(ipi);
+}
+
+/// Process a shootdown on this CPU; call from the shootdown IPI handler.
+///
+/// Flushes the loaded space if it was marked stale. Other stale spaces
+/// are flushed when they are next activated.
+pub fn handle_shootdown_ipi() {
+    let loaded = LOADED[cpu_id()].load(Ordering::Relaxed);
+    // Safety: spaces clear themselves from `LOADED` when dropped
+    if let Some(space) = unsafe { loaded.as_ref() } {
+        if space.stale_cpus.load(Ordering::Acquire) & (1 << cpu_id()) != 0 {
+            space.flush_local();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asid_generations() {
+        // Three usable ASIDs
+        let asids = AsidAllocator::new(2);
+        let spaces: [AddressSpace; 4] = unsafe {
+            [AddressSpace::new(0x1000), AddressSpace::new(0x2000), AddressSpace::new(0x3000), AddressSpace::new(0x4000)]
+        };
+
+        assert_eq!(asids.assign(&spaces[0], 0), (1, false));
+        assert_eq!(asids.assign(&spaces[1], 0), (2, false));
+        assert_eq!(asids.assign(&spaces[0], 1), (1, false));
+        assert_eq!(asids.current_asid(&spaces[0]), Some(1));
+
+        // Released ASIDs are reused within the generation
+        asids.release(&spaces[1]);
+        assert_eq!(asids.assign(&spaces[2], 0), (3, false));
+        assert_eq!(asids.assign(&spaces[3], 0), (2, false));
+
+        // Running out starts a generation; each CPU flushes once
+        assert_eq!(asids.assign(&spaces[1], 0), (1, true));
+        assert_eq!(asids.current_asid(&spaces[0]), None);
+        assert_eq!(asids.assign(&spaces[0], 0), (2, false));
+        assert!(asids.rollover_pending(1));
+        assert_eq!(asids.assign(&spaces[0], 1), (2, true));
+        assert!(!asids.rollover_pending(1));
+    }
+}
@@ -44,7 +44,7 @@ impl<T> ArcLite<T> {
         #[cfg(feature = "std-shim")]
         {
             extern crate std;
-            use std::alloc::System;
+            use std::alloc::{GlobalAlloc, System};
             let ptr = unsafe { System.alloc(layout) as *mut ArcLiteInner<T> };
             if ptr.is_null() {
                 panic!("Failed to allocate memory for ArcLite");
@@ -119,6 +119,76 @@ impl<T> ArcLite<T> {
         prev_count
     }
     
+    /// Consume the ArcLite, returning an opaque pointer that keeps its
+    /// reference alive.
+    ///
+    /// The reference must eventually be reclaimed with [`ArcLite::from_raw`].
+    pub fn into_raw(this: Self) -> *const () {
+        let ptr = this.ptr.as_ptr() as *const ();
+        core::mem::forget(this);
+        ptr
+    }
+    
+    /// Reconstruct an ArcLite from a pointer returned by [`ArcLite::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` on an `ArcLite<T>` of the same `T`,
+    /// and each pointer may be reclaimed only once per `into_raw` call.
+    pub unsafe fn from_raw(ptr: *const ()) -> Self {
+        Self {
+            ptr: unsafe { NonNull::new_unchecked(ptr as *mut ArcLiteInner<T>) },
+        }
+    }
+    
+    /// Get an opaque pointer identifying this allocation without consuming
+    /// a reference.
+    pub fn as_raw(this: &Self) -> *const () {
+        this.ptr.as_ptr() as *const ()
+    }
+    
+    /// Take a new reference from a pointer returned by [`ArcLite::as_raw`].
+    ///
+    /// Returns `None` if the last reference has already been dropped.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must identify an `ArcLite<T>` allocation of the same `T` whose
+    /// memory has not yet been freed. Callers typically guarantee this by
+    /// removing the pointer from a shared table inside `T`'s `Drop`.
+    pub unsafe fn upgrade_raw(ptr: *const ()) -> Option<Self> {
+        let this = core::mem::ManuallyDrop::new(Self {
+            ptr: unsafe { NonNull::new_unchecked(ptr as *mut ArcLiteInner<T>) },
+        });
+        
+        if this.try_inc() {
+            Some(core::mem::ManuallyDrop::into_inner(this))
+        } else {
+            None
+        }
+    }
+    
+    /// Drop the value if `this` is the last reference, giving `f` mutable
+    /// access to it first.
+    ///
+    /// Hands `this` back if other references exist. Pointers from
+    /// [`ArcLite::as_raw`] can no longer be upgraded once `f` runs.
+    pub fn try_drop_with(this: Self, f: impl FnOnce(&mut T)) -> Result<(), Self> {
+        let inner = unsafe { this.ptr.as_ref() };
+        if inner.count.compare_exchange(1, 0, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return Err(this);
+        }
+        
+        let this = core::mem::ManuallyDrop::new(this);
+        // Safety: the count is zero, so no other reference exists and
+        // `upgrade_raw` can't take a new one
+        unsafe {
+            f(&mut (*this.ptr.as_ptr()).data);
+            this.deallocate();
+        }
+        Ok(())
+    }
+    
     /// Get the current reference count.
     ///
     /// Note that this value may change immediately after being read in
@@ -137,7 +207,7 @@ impl<T> ArcLite<T> {
         #[cfg(feature = "std-shim")]
         {
             extern crate std;
-            use std::alloc::System;
+            use std::alloc::{GlobalAlloc, System};
             let layout = Layout::new::<ArcLiteInner<T>>();
             
             // Drop the data
@@ -60,6 +60,49 @@ struct GarbageItem {
     size: usize,
     /// Alignment of the memory block
     align: usize,
+    /// Frees the block as the box it was allocated as
+    #[cfg(feature = "checked-reclaim")]
+    free: unsafe fn(*mut u8),
+}
+
+impl GarbageItem {
+    fn new<T>(ptr: NonNull<T>) -> Self {
+        Self {
+            ptr: ptr.cast(),
+            size: core::mem::size_of::<T>(),
+            align: core::mem::align_of::<T>(),
+            #[cfg(feature = "checked-reclaim")]
+            free: free_box::<T>,
+        }
+    }
+    
+    /// Free the memory without dropping its contents.
+    ///
+    /// # Safety
+    ///
+    /// No thread may access the memory any more.
+    unsafe fn free(self) {
+        #[cfg(feature = "checked-reclaim")]
+        unsafe { (self.free)(self.ptr.as_ptr()) };
+        
+        #[cfg(not(feature = "checked-reclaim"))]
+        unsafe {
+            let layout = core::alloc::Layout::from_size_align_unchecked(self.size, self.align);
+            alloc::alloc::dealloc(self.ptr.as_ptr(), layout);
+        }
+    }
+}
+
+/// Free a `T` allocated with the global allocator, as a box that doesn't
+/// drop it.
+///
+/// # Safety
+///
+/// `ptr` must point to a `T` allocated with `Layout::new::<T>()` and not be
+/// used again.
+#[cfg(feature = "checked-reclaim")]
+unsafe fn free_box<T>(ptr: *mut u8) {
+    drop(unsafe { Box::from_raw(ptr as *mut core::mem::ManuallyDrop<T>) });
 }
 
 unsafe impl Send for GarbageItem {}
@@ -123,11 +166,19 @@ impl Guard {
         let local_epoch = unsafe { &LOCAL_EPOCHS[thread_id] };
         let current_epoch = self.epoch % EPOCH_COUNT;
         
-        let garbage_item = GarbageItem {
-            ptr: unsafe { NonNull::new_unchecked(ptr as *mut u8) },
-            size: core::mem::size_of::<T>(),
-            align: core::mem::align_of::<T>(),
-        };
+        let garbage_item = GarbageItem::new(unsafe { NonNull::new_unchecked(ptr) });
+        
+        // Catch double frees before they corrupt the allocator
+        #[cfg(feature = "checked-reclaim")]
+        for list in &local_epoch.garbage_lists {
+            if let Some(list) = list.try_lock() {
+                assert!(
+                    list.iter().all(|item| item.ptr != garbage_item.ptr),
+                    "pointer {:p} destroyed twice",
+                    ptr
+                );
+            }
+        }
         
         if let Some(mut garbage_list) = local_epoch.garbage_lists[current_epoch].try_lock() {
             garbage_list.push(garbage_item);
@@ -194,13 +245,7 @@ impl Guard {
             if let Some(mut garbage_list) = local_epoch.garbage_lists[reclaim_epoch].try_lock() {
                 // Free all garbage items
                 for garbage_item in garbage_list.drain(..) {
-                    unsafe {
-                        let layout = core::alloc::Layout::from_size_align_unchecked(
-                            garbage_item.size,
-                            garbage_item.align,
-                        );
-                        alloc::alloc::dealloc(garbage_item.ptr.as_ptr(), layout);
-                    }
+                    unsafe { garbage_item.free() };
                 }
             }
         }
@@ -253,13 +298,7 @@ pub fn unpin_thread(thread_id: usize) {
     for garbage_list_mutex in &local_epoch.garbage_lists {
         if let Some(mut garbage_list) = garbage_list_mutex.try_lock() {
             for garbage_item in garbage_list.drain(..) {
-                unsafe {
-                    let layout = core::alloc::Layout::from_size_align_unchecked(
-                        garbage_item.size,
-                        garbage_item.align,
-                    );
-                    alloc::alloc::dealloc(garbage_item.ptr.as_ptr(), layout);
-                }
+                unsafe { garbage_item.free() };
             }
         }
     }
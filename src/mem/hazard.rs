@@ -3,156 +3,324 @@
 //! Hazard pointers are an alternative to epoch-based reclamation that provides
 //! fine-grained protection for specific memory locations. Each thread can
 //! protect a small number of pointers from being reclaimed by other threads.
+//!
+//! # Records
+//!
+//! A [`HazardDomain`] holds [`MAX_THREADS`] records of
+//! [`HAZARDS_PER_THREAD`] hazard slots each. Every thread claims a record
+//! from [`HAZARD_DOMAIN`] when it is created and gives it back when it
+//! finishes, so [`HazardPointer::new`] never allocates. Code running outside
+//! a managed thread (boot, interrupt handlers, host tests) shares a
+//! record reserved for that purpose, as do threads created while all
+//! records are taken.
+//!
+//! # Reclamation
+//!
+//! [`HazardPointer::retire`] queues a node on the caller's record. Once
+//! the queue reaches the domain's retire threshold it is scanned, and every
+//! node no hazard slot protects is freed. When a thread exits, its record
+//! is scanned one last time and nodes that are still protected move to the
+//! domain's orphan list, which later scans drain; an exiting thread never
+//! leaks what it retired.
+//!
+//! ```ignore
+//! let hazard = HazardPointer::new().ok_or(Busy)?;
+//! let node = head.load_protected(Ordering::Acquire, &hazard);
+//! // `node` cannot be freed until `hazard` is cleared or dropped
+//! ```
 
-use portable_atomic::{AtomicPtr, AtomicUsize, Ordering};
+use portable_atomic::{AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use core::ptr::{self, NonNull};
-use core::sync::atomic::AtomicBool;
 use core::marker::PhantomData;
 extern crate alloc;
 use alloc::{boxed::Box, vec::Vec};
 
 /// Maximum number of hazard pointers per thread.
-const HAZARDS_PER_THREAD: usize = 8;
+pub const HAZARDS_PER_THREAD: usize = 8;
 
 /// Maximum number of threads that can use hazard pointers.
-const MAX_THREADS: usize = 64;
+pub const MAX_THREADS: usize = 64;
 
-/// Maximum number of retired pointers before attempting reclamation.
-const RETIRE_THRESHOLD: usize = 64;
+/// Default number of retired pointers before attempting reclamation.
+pub const RETIRE_THRESHOLD: usize = 64;
 
-/// Global hazard pointer registry.
-static mut HAZARD_REGISTRY: HazardRegistry = HazardRegistry::new();
+/// Record shared by code that has no record of its own.
+const SHARED_RECORD: usize = 0;
 
-/// Global registry of hazard pointers for all threads.
-struct HazardRegistry {
+/// Owner value of a record nobody holds.
+const FREE: u64 = 0;
+
+/// Owner value of the shared record.
+const SHARED_OWNER: u64 = u64::MAX;
+
+/// Global hazard pointer domain used by threads and [`HazardPointer::new`].
+pub static HAZARD_DOMAIN: HazardDomain = HazardDomain::new();
+
+/// A set of hazard records whose slots protect each other's retired nodes.
+pub struct HazardDomain {
     /// Hazard pointer records for each thread
-    thread_records: [ThreadRecord; MAX_THREADS],
-    /// Next available thread ID
-    next_thread_id: AtomicUsize,
+    records: [HazardRecord; MAX_THREADS],
+    /// Retired pointers left behind by exited threads
+    orphans: spin::Mutex<Vec<RetiredPointer>>,
+    /// Retired pointers per record that trigger a scan
+    retire_threshold: AtomicUsize,
+    /// Pointers freed so far
+    reclaimed: AtomicU64,
 }
 
-impl HazardRegistry {
-    const fn new() -> Self {
+/// Counters of a [`HazardDomain`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HazardStats {
+    /// Records held by threads, including the shared record
+    pub records_in_use: usize,
+    /// Retired pointers waiting in records
+    pub retired: usize,
+    /// Retired pointers left behind by exited threads
+    pub orphaned: usize,
+    /// Pointers freed so far
+    pub reclaimed: u64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_RECORD: HazardRecord = HazardRecord::new();
+
+impl HazardDomain {
+    /// Create a domain with every record free except the shared one.
+    pub const fn new() -> Self {
+        let mut records = [FREE_RECORD; MAX_THREADS];
+        records[SHARED_RECORD].owner = AtomicU64::new(SHARED_OWNER);
         Self {
-            thread_records: [const { ThreadRecord::new() }; MAX_THREADS],
-            next_thread_id: AtomicUsize::new(0),
+            records,
+            orphans: spin::Mutex::new(Vec::new()),
+            retire_threshold: AtomicUsize::new(RETIRE_THRESHOLD),
+            reclaimed: AtomicU64::new(0),
         }
     }
-    
-    /// Acquire a thread record for the current thread.
-    fn acquire_thread_record(&self) -> Option<&ThreadRecord> {
-        let thread_id = self.next_thread_id.fetch_add(1, Ordering::AcqRel);
-        if thread_id < MAX_THREADS {
-            let record = &self.thread_records[thread_id];
-            record.thread_id.store(thread_id, Ordering::Release);
-            record.active.store(true, Ordering::Release);
-            Some(record)
-        } else {
-            None
-        }
+
+    /// Claim a free record for `owner` (a non-zero thread ID).
+    ///
+    /// Returns `None` if every record is taken.
+    pub fn acquire(&self, owner: u64) -> Option<&HazardRecord> {
+        debug_assert!(owner != FREE && owner != SHARED_OWNER);
+        self.records.iter().find(|record| {
+            record
+                .owner
+                .compare_exchange(FREE, owner, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })
     }
-    
-    /// Release a thread record.
-    fn release_thread_record(&self, thread_id: usize) {
-        if thread_id < MAX_THREADS {
-            let record = &self.thread_records[thread_id];
-            
-            // Clear all hazard pointers
-            for hazard in &record.hazards {
-                hazard.store(ptr::null_mut(), Ordering::Release);
-            }
-            
-            // Process any remaining retired pointers
-            record.process_retired_list();
-            
-            record.active.store(false, Ordering::Release);
+
+    /// Give back `record`.
+    ///
+    /// Clears its hazards and frees what it retired; anything still
+    /// protected by another record moves to the orphan list. Releasing the
+    /// shared record only scans it.
+    pub fn release(&self, record: &HazardRecord) {
+        let index = self.index_of(record);
+        if index == SHARED_RECORD {
+            self.scan(record);
+            return;
+        }
+
+        for hazard in &record.hazards {
+            hazard.store(ptr::null_mut(), Ordering::Release);
         }
+        record.claimed.store(0, Ordering::Release);
+
+        self.scan(record);
+        let leftover = core::mem::take(&mut *record.retired.lock());
+        if !leftover.is_empty() {
+            self.orphans.lock().extend(leftover);
+        }
+
+        record.owner.store(FREE, Ordering::Release);
+    }
+
+    /// Get the record at `index`.
+    pub fn record(&self, index: usize) -> Option<&HazardRecord> {
+        self.records.get(index)
+    }
+
+    /// Get the record shared by code without a record of its own.
+    pub fn shared_record(&self) -> &HazardRecord {
+        &self.records[SHARED_RECORD]
+    }
+
+    /// Get the index of `record`, which must belong to this domain.
+    pub fn index_of(&self, record: &HazardRecord) -> usize {
+        let offset = record as *const HazardRecord as usize - self.records.as_ptr() as usize;
+        let index = offset / core::mem::size_of::<HazardRecord>();
+        assert!(index < MAX_THREADS, "hazard record from another domain");
+        index
     }
-    
+
     /// Check if a pointer is protected by any hazard pointer.
-    fn is_protected(&self, ptr: *mut u8) -> bool {
-        for record in &self.thread_records {
-            if !record.active.load(Ordering::Acquire) {
-                continue;
-            }
-            
-            for hazard in &record.hazards {
-                if hazard.load(Ordering::Acquire) == ptr {
-                    return true;
-                }
-            }
+    pub fn is_protected(&self, ptr: *mut u8) -> bool {
+        // Free records have no hazards set, so there is no need to skip them
+        self.records
+            .iter()
+            .flat_map(|record| record.hazards.iter())
+            .any(|hazard| hazard.load(Ordering::Acquire) == ptr)
+    }
+
+    /// Set how many pointers a record retires before it is scanned.
+    pub fn set_retire_threshold(&self, threshold: usize) {
+        self.retire_threshold.store(threshold.max(1), Ordering::Relaxed);
+    }
+
+    /// Get how many pointers a record retires before it is scanned.
+    pub fn retire_threshold(&self) -> usize {
+        self.retire_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Free every retired pointer that is no longer protected, in all
+    /// records and the orphan list.
+    ///
+    /// Returns the number of pointers freed.
+    pub fn reclaim(&self) -> usize {
+        let records: usize = self.records.iter().map(|record| self.scan(record)).sum();
+        records + self.scan_orphans()
+    }
+
+    /// Create a hazard pointer using a free slot of `record`, which must
+    /// belong to this domain.
+    ///
+    /// Returns `None` if all of the record's slots are in use.
+    pub fn hazard_pointer(&'static self, record: &'static HazardRecord) -> Option<HazardPointer> {
+        record.claim_slot().map(|hazard_index| HazardPointer {
+            domain: self,
+            thread_record: record,
+            hazard_index,
+        })
+    }
+
+    /// Get the domain's counters.
+    pub fn stats(&self) -> HazardStats {
+        HazardStats {
+            records_in_use: self
+                .records
+                .iter()
+                .filter(|record| record.owner.load(Ordering::Acquire) != FREE)
+                .count(),
+            retired: self.records.iter().map(HazardRecord::retired).sum(),
+            orphaned: self.orphans.lock().len(),
+            reclaimed: self.reclaimed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Retire `retired` on `record`, scanning once the threshold is reached.
+    fn retire(&self, record: &HazardRecord, retired: RetiredPointer) {
+        let pending = {
+            let mut list = record.retired.lock();
+            // Catch double retires before they turn into double frees
+            #[cfg(feature = "checked-reclaim")]
+            assert!(
+                list.iter().all(|pending| pending.ptr != retired.ptr),
+                "pointer {:p} retired twice",
+                retired.ptr
+            );
+            list.push(retired);
+            list.len()
+        };
+
+        if pending >= self.retire_threshold() {
+            self.scan(record);
+            self.scan_orphans();
+        }
+    }
+
+    /// Free the unprotected pointers retired on `record`.
+    fn scan(&self, record: &HazardRecord) -> usize {
+        match record.retired.try_lock() {
+            Some(mut list) => self.free_unprotected(&mut list),
+            // Another CPU is scanning this record
+            None => 0,
+        }
+    }
+
+    fn scan_orphans(&self) -> usize {
+        match self.orphans.try_lock() {
+            Some(mut list) => self.free_unprotected(&mut list),
+            None => 0,
         }
-        false
+    }
+
+    fn free_unprotected(&self, list: &mut Vec<RetiredPointer>) -> usize {
+        let before = list.len();
+        list.retain(|retired| {
+            if self.is_protected(retired.ptr.as_ptr()) {
+                return true; // Keep in list
+            }
+            // Safety: retired pointers are unreachable and, with no hazard
+            // protecting them, no longer read
+            unsafe { (retired.free)(retired.ptr.as_ptr()) };
+            false
+        });
+
+        let freed = before - list.len();
+        self.reclaimed.fetch_add(freed as u64, Ordering::Relaxed);
+        freed
+    }
+}
+
+impl Default for HazardDomain {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 /// Per-thread hazard pointer record.
-struct ThreadRecord {
-    /// Thread ID for this record
-    thread_id: AtomicUsize,
-    /// Whether this thread record is active
-    active: AtomicBool,
+pub struct HazardRecord {
+    /// ID of the thread holding this record (0 = free)
+    owner: AtomicU64,
+    /// Bit per hazard slot handed out as a `HazardPointer`
+    claimed: AtomicU8,
     /// Hazard pointers for this thread
     hazards: [AtomicPtr<u8>; HAZARDS_PER_THREAD],
     /// Retired pointers waiting for reclamation
-    retired_list: spin::Mutex<Vec<RetiredPointer>>,
+    retired: spin::Mutex<Vec<RetiredPointer>>,
 }
 
-impl ThreadRecord {
+impl HazardRecord {
     const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
         const INIT_HAZARD: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
         Self {
-            thread_id: AtomicUsize::new(usize::MAX),
-            active: AtomicBool::new(false),
+            owner: AtomicU64::new(FREE),
+            claimed: AtomicU8::new(0),
             hazards: [INIT_HAZARD; HAZARDS_PER_THREAD],
-            retired_list: spin::Mutex::new(Vec::new()),
+            retired: spin::Mutex::new(Vec::new()),
         }
     }
-    
-    /// Retire a pointer for later reclamation.
-    fn retire_pointer(&self, ptr: *mut u8, size: usize, align: usize) {
-        let retired = RetiredPointer {
-            ptr: unsafe { NonNull::new_unchecked(ptr) },
-            size,
-            align,
-        };
-        
-        if let Some(mut retired_list) = self.retired_list.try_lock() {
-            retired_list.push(retired);
-            
-            // If we have too many retired pointers, try to reclaim some
-            if retired_list.len() >= RETIRE_THRESHOLD {
-                self.try_reclaim(&mut retired_list);
-            }
+
+    /// Get the ID of the thread holding this record, if any.
+    pub fn owner(&self) -> Option<u64> {
+        match self.owner.load(Ordering::Acquire) {
+            FREE | SHARED_OWNER => None,
+            owner => Some(owner),
         }
     }
-    
-    /// Process the retired list and reclaim safe pointers.
-    fn process_retired_list(&self) {
-        if let Some(mut retired_list) = self.retired_list.try_lock() {
-            self.try_reclaim(&mut retired_list);
-        }
+
+    /// Get the number of retired pointers waiting on this record.
+    pub fn retired(&self) -> usize {
+        self.retired.lock().len()
     }
-    
-    /// Try to reclaim pointers that are not protected by hazard pointers.
-    fn try_reclaim(&self, retired_list: &mut Vec<RetiredPointer>) {
-        let registry = unsafe { &HAZARD_REGISTRY };
-        
-        retired_list.retain(|retired| {
-            if !registry.is_protected(retired.ptr.as_ptr()) {
-                // Safe to reclaim this pointer
-                unsafe {
-                    let layout = core::alloc::Layout::from_size_align_unchecked(
-                        retired.size,
-                        retired.align,
-                    );
-                    alloc::alloc::dealloc(retired.ptr.as_ptr(), layout);
-                }
-                false // Remove from list
-            } else {
-                true // Keep in list
+
+    /// Claim a free hazard slot of this record.
+    fn claim_slot(&self) -> Option<usize> {
+        let mut claimed = self.claimed.load(Ordering::Relaxed);
+        loop {
+            let slot = (0..HAZARDS_PER_THREAD).find(|slot| claimed & (1 << slot) == 0)?;
+            match self.claimed.compare_exchange_weak(
+                claimed,
+                claimed | 1 << slot,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(slot),
+                Err(current) => claimed = current,
             }
-        });
+        }
     }
 }
 
@@ -160,22 +328,39 @@ impl ThreadRecord {
 struct RetiredPointer {
     /// Pointer to the memory to be freed
     ptr: NonNull<u8>,
-    /// Size of the memory block
-    size: usize,
-    /// Alignment of the memory block
-    align: usize,
+    /// Drops and deallocates the pointee
+    free: unsafe fn(*mut u8),
 }
 
 unsafe impl Send for RetiredPointer {}
 unsafe impl Sync for RetiredPointer {}
 
+/// Free a pointer that came from `Box::into_raw`.
+///
+/// # Safety
+///
+/// `ptr` must come from `Box::<T>::into_raw` and not be used again.
+unsafe fn free_box<T>(ptr: *mut u8) {
+    drop(unsafe { Box::from_raw(ptr as *mut T) });
+}
+
+/// Get the current thread's record in the global domain.
+fn current_record() -> &'static HazardRecord {
+    crate::thread_new::current()
+        .and_then(|thread| thread.hazard_record())
+        .and_then(|index| HAZARD_DOMAIN.record(index))
+        .unwrap_or_else(|| HAZARD_DOMAIN.shared_record())
+}
+
 /// A hazard pointer that protects a specific memory location.
 ///
 /// While this hazard pointer is active, the protected memory location
 /// will not be reclaimed by other threads.
 pub struct HazardPointer {
+    /// Domain the record belongs to
+    domain: &'static HazardDomain,
     /// Thread record this hazard pointer belongs to
-    thread_record: &'static ThreadRecord,
+    thread_record: &'static HazardRecord,
     /// Index of this hazard pointer in the thread record
     hazard_index: usize,
 }
@@ -183,24 +368,11 @@ pub struct HazardPointer {
 impl HazardPointer {
     /// Create a new hazard pointer for the current thread.
     ///
-    /// Returns `None` if no hazard pointer slots are available.
+    /// Returns `None` if all of the thread's hazard slots are in use.
     pub fn new() -> Option<Self> {
-        let registry = unsafe { &HAZARD_REGISTRY };
-        let thread_record = registry.acquire_thread_record()?;
-        
-        // Find an available hazard pointer slot
-        for (index, hazard) in thread_record.hazards.iter().enumerate() {
-            if hazard.load(Ordering::Acquire).is_null() {
-                return Some(Self {
-                    thread_record,
-                    hazard_index: index,
-                });
-            }
-        }
-        
-        None // No available slots
+        HAZARD_DOMAIN.hazard_pointer(current_record())
     }
-    
+
     /// Protect a pointer with this hazard pointer.
     ///
     /// The protected pointer will not be reclaimed while this hazard pointer
@@ -213,62 +385,69 @@ impl HazardPointer {
     pub unsafe fn protect<T>(&self, ptr: *mut T) -> *mut T {
         let byte_ptr = ptr as *mut u8;
         self.thread_record.hazards[self.hazard_index].store(byte_ptr, Ordering::Release);
-        
+
         // Memory fence to ensure the hazard pointer is visible before any loads
         core::sync::atomic::fence(Ordering::SeqCst);
-        
+
         ptr
     }
-    
+
     /// Clear the protection provided by this hazard pointer.
     pub fn clear(&self) {
         self.thread_record.hazards[self.hazard_index].store(ptr::null_mut(), Ordering::Release);
     }
-    
+
     /// Retire a pointer for safe reclamation.
     ///
-    /// The pointer will be reclaimed when it's no longer protected by any
-    /// hazard pointer.
+    /// The pointer is dropped and freed once no hazard pointer protects it,
+    /// at the latest by a scan after the current thread exits.
     ///
     /// # Safety
     ///
-    /// - `ptr` must be a valid pointer that was allocated with the global allocator
-    /// - `ptr` must not be accessed after this call
-    /// - The caller must ensure that `size` and `align` match the original allocation
+    /// - `ptr` must come from `Box::<T>::into_raw`
+    /// - `ptr` must be unreachable for threads that do not already hold it
+    ///   under a hazard pointer, and must not be retired twice
     pub unsafe fn retire<T>(&self, ptr: *mut T) {
-        if ptr.is_null() {
+        let Some(ptr) = NonNull::new(ptr as *mut u8) else {
             return;
-        }
-        
-        self.thread_record.retire_pointer(
-            ptr as *mut u8,
-            core::mem::size_of::<T>(),
-            core::mem::align_of::<T>(),
-        );
+        };
+
+        self.domain.retire(self.thread_record, RetiredPointer {
+            ptr,
+            free: free_box::<T>,
+        });
     }
 }
 
 impl Drop for HazardPointer {
     fn drop(&mut self) {
         self.clear();
+        self.thread_record
+            .claimed
+            .fetch_and(!(1 << self.hazard_index), Ordering::Release);
     }
 }
 
-/// Initialize hazard pointers for the current thread.
-pub fn init_thread() -> Result<(), &'static str> {
-    let registry = unsafe { &HAZARD_REGISTRY };
-    
-    if registry.acquire_thread_record().is_some() {
-        Ok(())
-    } else {
-        Err("Too many threads using hazard pointers")
-    }
+/// Claim a record in the global domain for code that is not a managed
+/// thread, returning its index.
+///
+/// Threads get a record when they are created; this is only needed to give
+/// other contexts a record of their own instead of the shared one.
+pub fn init_thread() -> Result<usize, &'static str> {
+    static NEXT_OWNER: AtomicU64 = AtomicU64::new(1 << 63);
+    let owner = NEXT_OWNER.fetch_add(1, Ordering::Relaxed);
+    HAZARD_DOMAIN
+        .acquire(owner)
+        .map(|record| HAZARD_DOMAIN.index_of(record))
+        .ok_or("Too many threads using hazard pointers")
 }
 
-/// Clean up hazard pointers for the current thread.
-pub fn cleanup_thread(thread_id: usize) {
-    let registry = unsafe { &HAZARD_REGISTRY };
-    registry.release_thread_record(thread_id);
+/// Give back the record at `index` from [`init_thread`] or a thread's
+/// [`hazard_record`](crate::thread_new::Thread::hazard_record).
+pub fn cleanup_thread(index: usize) {
+    if let Some(record) = HAZARD_DOMAIN.record(index) {
+        HAZARD_DOMAIN.release(record);
+    }
 }
 
 /// Atomic pointer with hazard pointer support.
@@ -288,7 +467,7 @@ impl<T> HazardAtomic<T> {
             _marker: PhantomData,
         }
     }
-    
+
     /// Load the pointer with hazard pointer protection.
     ///
     /// This ensures that the returned pointer is protected from reclamation
@@ -296,26 +475,28 @@ impl<T> HazardAtomic<T> {
     pub fn load_protected(&self, order: Ordering, hazard: &HazardPointer) -> *mut T {
         loop {
             let ptr = self.ptr.load(order);
-            
+
             // Protect the pointer with hazard pointer
             let protected_ptr = unsafe { hazard.protect(ptr) };
-            
+
             // Verify that the pointer hasn't changed
             if self.ptr.load(order) == protected_ptr {
                 return protected_ptr;
             }
-            
+
             // Pointer changed, clear protection and retry
             hazard.clear();
         }
     }
-    
+
     /// Store a new pointer value.
     pub fn store(&self, ptr: *mut T, order: Ordering) {
         self.ptr.store(ptr, order);
     }
-    
-    /// Compare and swap the pointer with hazard pointer support.
+
+    /// Compare and swap the pointer, retiring the old pointer on success.
+    ///
+    /// Pointers stored in the atomic must come from `Box::into_raw`.
     pub fn compare_exchange_weak(
         &self,
         current: *mut T,
@@ -344,50 +525,111 @@ unsafe impl<T: Send + Sync> Sync for HazardAtomic<T> {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_hazard_pointer_creation() {
         let _result = init_thread();
         let hazard = HazardPointer::new();
         assert!(hazard.is_some());
     }
-    
+
     #[test]
     fn test_hazard_protection() {
         let _result = init_thread();
         let hazard = HazardPointer::new().unwrap();
-        
+
         let ptr = 0x1000 as *mut i32;
         let protected = unsafe { hazard.protect(ptr) };
-        
+
         assert_eq!(ptr, protected);
     }
-    
+
     #[test]
     fn test_hazard_atomic_operations() {
         let _result = init_thread();
         let atomic = HazardAtomic::new(ptr::null_mut());
         let hazard = HazardPointer::new().unwrap();
-        
+
         // Test basic load/store operations
         atomic.store(0x1000 as *mut i32, Ordering::SeqCst);
         let loaded = atomic.load_protected(Ordering::SeqCst, &hazard);
         assert_eq!(loaded, 0x1000 as *mut i32);
     }
-    
+
     #[test]
     fn test_pointer_retirement() {
         let _result = init_thread();
         let hazard = HazardPointer::new().unwrap();
-        
+
         // Create a test allocation (in a real scenario this would be actual allocated memory)
         let ptr = Box::into_raw(Box::new(42i32));
-        
+
         // Retire the pointer
         unsafe {
             hazard.retire(ptr);
         }
-        
+
         // The pointer should eventually be reclaimed when it's not protected
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "checked-reclaim")]
+    #[test]
+    #[should_panic(expected = "retired twice")]
+    fn test_checked_reclaim_catches_double_retire() {
+        static DOMAIN: HazardDomain = HazardDomain::new();
+        let record = DOMAIN.acquire(1).unwrap();
+
+        let node = NonNull::new(Box::into_raw(Box::new(7u64)) as *mut u8).unwrap();
+        for _ in 0..2 {
+            DOMAIN.retire(record, RetiredPointer {
+                ptr: node,
+                free: free_box::<u64>,
+            });
+        }
+    }
+
+    #[test]
+    fn test_record_release_orphans_protected_nodes() {
+        static DOMAIN: HazardDomain = HazardDomain::new();
+        DOMAIN.set_retire_threshold(2);
+
+        let reader = DOMAIN.acquire(1).unwrap();
+        let writer = DOMAIN.acquire(2).unwrap();
+        assert_ne!(DOMAIN.index_of(reader), DOMAIN.index_of(writer));
+
+        let slots: Vec<_> = core::iter::from_fn(|| DOMAIN.hazard_pointer(reader)).collect();
+        assert_eq!(slots.len(), HAZARDS_PER_THREAD);
+        drop(slots);
+
+        // The reader protects one of two nodes the writer retires
+        let kept = Box::into_raw(Box::new([0u64; 4]));
+        let freed = Box::into_raw(Box::new([0u64; 4]));
+        reader.hazards[0].store(kept as *mut u8, Ordering::Release);
+        for ptr in [kept, freed] {
+            DOMAIN.retire(writer, RetiredPointer {
+                ptr: NonNull::new(ptr as *mut u8).unwrap(),
+                free: free_box::<[u64; 4]>,
+            });
+        }
+
+        // Reaching the threshold scanned the record
+        assert_eq!(writer.retired(), 1);
+        assert_eq!(DOMAIN.stats().reclaimed, 1);
+
+        // The writer exits; the protected node outlives it
+        DOMAIN.release(writer);
+        assert_eq!(writer.owner(), None);
+        let stats = DOMAIN.stats();
+        assert_eq!((stats.retired, stats.orphaned), (0, 1));
+
+        // Once the reader is done, the orphan is freed
+        DOMAIN.release(reader);
+        assert_eq!(DOMAIN.reclaim(), 1);
+        assert_eq!(DOMAIN.stats(), HazardStats {
+            records_in_use: 1,
+            retired: 0,
+            orphaned: 0,
+            reclaimed: 2,
+        });
+    }
+}
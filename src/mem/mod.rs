@@ -6,26 +6,104 @@
 pub mod stack_pool;
 pub mod arc_lite;
 
+// Pointer+tag atomics for ABA-safe lock-free lists
+pub mod tagged;
+
+// ASID/PCID-tagged address spaces
+pub mod address_space;
+
+// Huge page mapping for large stacks
+#[cfg(feature = "mmu")]
+pub mod page_mapper;
+
 // Epoch-based reclamation for lock-free data structures
 #[cfg(feature = "work-stealing")]
 pub mod epoch;
 
 // Hazard pointers for fine-grained memory reclamation
-#[cfg(feature = "work-stealing")]
 pub mod hazard;
 
+// Stack high-water mark history and size advice
+pub mod watermarks;
+
 // Data race detection utilities for debugging
 #[cfg(debug_assertions)]
 pub mod race_detector;
 
-pub use stack_pool::{Stack, StackPool, StackSizeClass};
+/// Store to an atomic on an inter-thread handoff path.
+///
+/// Expands to `$atomic.store($value, $ordering)`; in debug builds the
+/// store is also checked by [`RACE_DETECTOR`](crate::mem::RACE_DETECTOR).
+#[macro_export]
+macro_rules! handoff_store {
+    ($atomic:expr, $value:expr, $ordering:expr) => {{
+        let atomic = &$atomic;
+        let ordering = $ordering;
+        #[cfg(debug_assertions)]
+        {
+            static SITE: $crate::mem::race_detector::Site = $crate::mem::race_detector::Site {
+                module: module_path!(),
+                file: file!(),
+                line: line!(),
+            };
+            $crate::mem::race_detector::RACE_DETECTOR.record_publish(
+                atomic as *const _ as usize,
+                ordering,
+                &SITE,
+            );
+        }
+        atomic.store($value, ordering)
+    }};
+}
+
+/// Load from an atomic on an inter-thread handoff path.
+///
+/// Expands to `$atomic.load($ordering)`; in debug builds the load is also
+/// checked by [`RACE_DETECTOR`](crate::mem::RACE_DETECTOR).
+#[macro_export]
+macro_rules! handoff_load {
+    ($atomic:expr, $ordering:expr) => {{
+        let atomic = &$atomic;
+        let ordering = $ordering;
+        let value = atomic.load(ordering);
+        #[cfg(debug_assertions)]
+        {
+            static SITE: $crate::mem::race_detector::Site = $crate::mem::race_detector::Site {
+                module: module_path!(),
+                file: file!(),
+                line: line!(),
+            };
+            $crate::mem::race_detector::RACE_DETECTOR.record_consume(
+                atomic as *const _ as usize,
+                ordering,
+                &SITE,
+            );
+        }
+        value
+    }};
+}
+
+pub use stack_pool::{Stack, StackPool, StackSizeClass, StackState};
 pub use arc_lite::ArcLite;
+pub use tagged::{AtomicTaggedPtr, TaggedPtr};
+pub use address_space::{AddressSpace, AsidAllocator, ASIDS};
+pub use watermarks::{
+    StackRecommendation, WatermarkRecord, WatermarkStore, STACK_WATERMARKS, set_watermark_store,
+};
+
+#[cfg(feature = "mmu")]
+pub use page_mapper::{PageMapper, PageProtection, HugePageStats, HUGE_PAGE_SIZE, set_page_mapper, huge_page_stats};
 
 #[cfg(feature = "work-stealing")]
 pub use epoch::{Guard, Atomic, pin_thread, unpin_thread};
 
-#[cfg(feature = "work-stealing")]
-pub use hazard::{HazardPointer, HazardAtomic, init_thread as hazard_init_thread, cleanup_thread as hazard_cleanup_thread};
+pub use hazard::{
+    HazardPointer, HazardAtomic, HazardDomain, HazardRecord, HazardStats, HAZARD_DOMAIN,
+    init_thread as hazard_init_thread, cleanup_thread as hazard_cleanup_thread,
+};
 
 #[cfg(debug_assertions)]
-pub use race_detector::{RaceDetector, RaceDetectorStats, OrderingValidator, RACE_DETECTOR};
\ No newline at end of file
+pub use race_detector::{
+    RaceDetector, RaceDetectorStats, OrderingValidator, OrderingFinding, OrderingIssue, OrderingReport,
+    RACE_DETECTOR,
+};
\ No newline at end of file
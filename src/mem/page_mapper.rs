@@ -0,0 +1,302 @@
+//! Page mapping abstraction for MMU-backed memory.
+//!
+//! The stack pool uses a [`PageMapper`] to obtain 2 MiB huge pages for
+//! large stacks, which reduces TLB pressure for compute-heavy threads.
+//! Integrators register a mapper for their platform; without one the
+//! pool falls back to regular allocations.
+
+use portable_atomic::{AtomicU64, Ordering};
+use core::ptr::NonNull;
+use alloc::vec::Vec;
+use super::AddressSpace;
+
+/// Size of a huge page in bytes (2 MiB).
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Platform hook for mapping and unmapping pages.
+pub trait PageMapper: Send + Sync {
+    /// Map one huge page of [`HUGE_PAGE_SIZE`] bytes.
+    ///
+    /// The returned memory must be readable, writable and aligned to
+    /// [`HUGE_PAGE_SIZE`]. Returns `None` if no huge page is available.
+    fn map_huge_page(&self) -> Option<NonNull<u8>>;
+
+    /// Unmap a huge page previously returned by [`map_huge_page`](Self::map_huge_page).
+    ///
+    /// # Safety
+    ///
+    /// `page` must come from this mapper and must no longer be in use.
+    unsafe fn unmap_huge_page(&self, page: NonNull<u8>);
+
+    /// Get the access rights of the page containing `addr`.
+    ///
+    /// Used for W^X checks on stacks and pool memory. Returns `None` if the
+    /// address is not mapped or the mapper can't tell.
+    fn protection(&self, _addr: usize) -> Option<PageProtection> {
+        None
+    }
+
+    /// Map `[start, start + len)` into `space` with `protection`, or unmap
+    /// it if `protection` is `None`.
+    ///
+    /// Used for shared memory grants; `space` is `None` for domains that
+    /// run in the shared kernel space. The caller flushes the TLB after
+    /// access is reduced. Returns `false` if the mapper can't do this.
+    fn map_shared(
+        &self,
+        _space: Option<&AddressSpace>,
+        _start: usize,
+        _len: usize,
+        _protection: Option<PageProtection>,
+    ) -> bool {
+        false
+    }
+}
+
+/// Access rights of a mapped page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageProtection {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+/// Currently registered page mapper.
+static PAGE_MAPPER: spin::Mutex<Option<&'static dyn PageMapper>> = spin::Mutex::new(None);
+
+/// Huge page usage counters.
+static HUGE_PAGE_HITS: AtomicU64 = AtomicU64::new(0);
+static HUGE_PAGE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Huge page usage statistics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HugePageStats {
+    /// Stacks served from huge page backed memory
+    pub hits: u64,
+    /// Stacks that wanted huge page backing but fell back to regular pages
+    pub misses: u64,
+}
+
+/// Register the page mapper used for huge page stack backing.
+pub fn set_page_mapper(mapper: &'static dyn PageMapper) {
+    *PAGE_MAPPER.lock() = Some(mapper);
+}
+
+/// Get the registered page mapper, if any.
+pub fn page_mapper() -> Option<&'static dyn PageMapper> {
+    *PAGE_MAPPER.lock()
+}
+
+/// Get huge page usage statistics.
+pub fn huge_page_stats() -> HugePageStats {
+    HugePageStats {
+        hits: HUGE_PAGE_HITS.load(Ordering::Relaxed),
+        misses: HUGE_PAGE_MISSES.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn record_huge_page_hit() {
+    HUGE_PAGE_HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_huge_page_miss() {
+    HUGE_PAGE_MISSES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Bump allocator carving stacks out of huge pages.
+///
+/// Stacks carved from a huge page are recycled through the stack pool's
+/// free lists. The arena counts the stacks carved from each page that
+/// have not been released yet, and unmaps a page once its last stack is.
+pub(crate) struct HugePageArena {
+    /// Next free byte in the current huge page
+    next: usize,
+    /// End of the current huge page
+    end: usize,
+    /// Mapped pages and how many of their stacks are still live
+    pages: Vec<(usize, usize)>,
+}
+
+impl HugePageArena {
+    /// Create an empty arena.
+    pub(crate) const fn new() -> Self {
+        Self { next: 0, end: 0, pages: Vec::new() }
+    }
+
+    /// Carve `size` bytes out of the current huge page, mapping a new one
+    /// through `mapper` when the current page is exhausted.
+    pub(crate) fn carve(&mut self, size: usize, mapper: &dyn PageMapper) -> Option<NonNull<u8>> {
+        if size > HUGE_PAGE_SIZE {
+            return None;
+        }
+
+        if self.end - self.next < size {
+            // Remaining tail of the old page is abandoned; the page is
+            // unmapped once its stacks are released
+            let page = mapper.map_huge_page()?;
+            self.next = page.as_ptr() as usize;
+            self.end = self.next + HUGE_PAGE_SIZE;
+            self.pages.push((self.next, 0));
+        }
+
+        let ptr = self.next as *mut u8;
+        self.next += size;
+        if let Some((_, live)) = self.pages.last_mut() {
+            *live += 1;
+        }
+        NonNull::new(ptr)
+    }
+
+    /// Give back a block carved from this arena, unmapping its huge page
+    /// through `mapper` if no other block of it is still live.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`carve`](Self::carve) with the same
+    /// mapper and must no longer be in use.
+    pub(crate) unsafe fn release(&mut self, ptr: NonNull<u8>, mapper: &dyn PageMapper) {
+        let addr = ptr.as_ptr() as usize;
+        let Some(index) = self
+            .pages
+            .iter()
+            .position(|&(page, _)| (page..page + HUGE_PAGE_SIZE).contains(&addr))
+        else {
+            return;
+        };
+
+        let (page, live) = &mut self.pages[index];
+        *live -= 1;
+        if *live > 0 {
+            return;
+        }
+
+        let page = *page;
+        self.pages.swap_remove(index);
+        if self.end == page + HUGE_PAGE_SIZE {
+            // The current page; the next carve maps a fresh one
+            self.next = 0;
+            self.end = 0;
+        }
+        // Safety: the page came from `mapper` and none of its blocks is live
+        unsafe { mapper.unmap_huge_page(NonNull::new_unchecked(page as *mut u8)) };
+    }
+
+    /// Number of huge pages currently mapped by the arena.
+    pub(crate) fn mapped_pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+/// Page mapper backed by Linux `MAP_HUGETLB` mappings.
+#[cfg(target_os = "linux")]
+pub struct HugeTlbMapper;
+
+#[cfg(target_os = "linux")]
+mod linux_impl {
+    use super::*;
+
+    const PROT_READ: i32 = 1;
+    const PROT_WRITE: i32 = 2;
+    const MAP_PRIVATE: i32 = 0x02;
+    const MAP_ANONYMOUS: i32 = 0x20;
+    const MAP_HUGETLB: i32 = 0x40000;
+    const MAP_FAILED: *mut u8 = !0usize as *mut u8;
+
+    extern "C" {
+        fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+        fn munmap(addr: *mut u8, len: usize) -> i32;
+    }
+
+    impl PageMapper for HugeTlbMapper {
+        fn map_huge_page(&self) -> Option<NonNull<u8>> {
+            let ptr = unsafe {
+                mmap(
+                    core::ptr::null_mut(),
+                    HUGE_PAGE_SIZE,
+                    PROT_READ | PROT_WRITE,
+                    MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB,
+                    -1,
+                    0,
+                )
+            };
+
+            if ptr == MAP_FAILED {
+                None
+            } else {
+                NonNull::new(ptr)
+            }
+        }
+
+        unsafe fn unmap_huge_page(&self, page: NonNull<u8>) {
+            unsafe {
+                munmap(page.as_ptr(), HUGE_PAGE_SIZE);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use portable_atomic::AtomicUsize;
+    use std::alloc::{alloc, dealloc, Layout};
+
+    /// Maps huge pages from the heap and counts the pages still mapped.
+    struct HeapMapper {
+        mapped: AtomicUsize,
+    }
+
+    impl PageMapper for HeapMapper {
+        fn map_huge_page(&self) -> Option<NonNull<u8>> {
+            self.mapped.fetch_add(1, Ordering::Relaxed);
+            NonNull::new(unsafe { alloc(layout()) })
+        }
+
+        unsafe fn unmap_huge_page(&self, page: NonNull<u8>) {
+            self.mapped.fetch_sub(1, Ordering::Relaxed);
+            unsafe { dealloc(page.as_ptr(), layout()) };
+        }
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(HUGE_PAGE_SIZE, HUGE_PAGE_SIZE).unwrap()
+    }
+
+    #[test]
+    fn test_page_unmapped_once_its_blocks_are_released() {
+        let mapper = HeapMapper { mapped: AtomicUsize::new(0) };
+        let mut arena = HugePageArena::new();
+
+        let first = arena.carve(HUGE_PAGE_SIZE / 2, &mapper).unwrap();
+        let second = arena.carve(HUGE_PAGE_SIZE / 2, &mapper).unwrap();
+        // Doesn't fit the rest of the first page, which is abandoned
+        let third = arena.carve(HUGE_PAGE_SIZE / 2 + 1, &mapper).unwrap();
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 2);
+        assert_eq!(arena.mapped_pages(), 2);
+
+        unsafe { arena.release(first, &mapper) };
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 2);
+        unsafe { arena.release(second, &mapper) };
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 1);
+
+        // Releasing the current page's last block unmaps it as well, and
+        // the next carve maps a fresh page
+        unsafe { arena.release(third, &mapper) };
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 0);
+        assert_eq!(arena.mapped_pages(), 0);
+
+        let fourth = arena.carve(HUGE_PAGE_SIZE, &mapper).unwrap();
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 1);
+        unsafe { arena.release(fourth, &mapper) };
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_oversized_block_refused() {
+        let mapper = HeapMapper { mapped: AtomicUsize::new(0) };
+        let mut arena = HugePageArena::new();
+
+        assert!(arena.carve(HUGE_PAGE_SIZE + 1, &mapper).is_none());
+        assert_eq!(mapper.mapped.load(Ordering::Relaxed), 0);
+    }
+}
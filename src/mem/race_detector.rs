@@ -2,9 +2,33 @@
 //!
 //! This module provides tools for detecting potential data races and memory
 //! ordering issues during testing and debugging of lock-free algorithms.
+//!
+//! # Handoff validation
+//!
+//! The crate's inter-thread handoff paths, where one thread publishes data
+//! through an atomic and another picks it up, use [`handoff_store!`](crate::handoff_store) and
+//! [`handoff_load!`](crate::handoff_load) instead of plain `store`/`load`. In
+//! debug builds with the detector enabled, each publish is remembered by
+//! address and each load is paired with the last publish it observes:
+//!
+//! - a Release (or stronger) publish read by an Acquire (or stronger) load
+//!   counts as a pairing
+//! - a Relaxed publish or a Relaxed load is flagged, with its call site
+//!
+//! [`RaceDetector::ordering_report`] lists the flagged sites by module. The
+//! publish table is small and unsynchronized across fields, so pairing is
+//! best-effort; a flagged site is always one that used Relaxed on a
+//! handoff, though.
 
-use portable_atomic::{AtomicUsize, AtomicBool, Ordering};
+use portable_atomic::{AtomicUsize, AtomicBool, AtomicPtr, AtomicU8, Ordering};
 use core::sync::atomic::{fence, AtomicU64};
+use core::fmt;
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Number of remembered publishes; addresses hash into this table.
+const PUBLISH_SLOTS: usize = 64;
 
 /// A data race detector that tracks memory accesses and detects potential races.
 ///
@@ -19,6 +43,130 @@ pub struct RaceDetector {
     races_detected: AtomicUsize,
     /// Timestamp for ordering detection
     clock: AtomicU64,
+    /// Last handoff publish per address hash
+    publishes: [PublishSlot; PUBLISH_SLOTS],
+    /// Release/Acquire handoffs observed
+    pairings: AtomicU64,
+    /// Flagged handoff sites
+    findings: spin::Mutex<Vec<OrderingFinding>>,
+}
+
+/// Last publish seen at an address.
+struct PublishSlot {
+    addr: AtomicUsize,
+    ordering: AtomicU8,
+    site: AtomicPtr<Site>,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: PublishSlot = PublishSlot {
+    addr: AtomicUsize::new(0),
+    ordering: AtomicU8::new(0),
+    site: AtomicPtr::new(core::ptr::null_mut()),
+};
+
+/// Source location of an instrumented atomic operation.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Site {
+    /// Module path of the call site
+    pub module: &'static str,
+    /// Source file of the call site
+    pub file: &'static str,
+    /// Line of the call site
+    pub line: u32,
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// What is wrong with a flagged handoff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderingIssue {
+    /// The value was published with a store lacking Release
+    RelaxedPublish,
+    /// A published value was read with a load lacking Acquire
+    RelaxedConsume,
+}
+
+/// A handoff call site flagged by the detector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderingFinding {
+    /// Where the offending operation is
+    pub site: &'static Site,
+    /// What is wrong with it
+    pub issue: OrderingIssue,
+    /// The other side of the handoff, if it was seen
+    pub peer: Option<&'static Site>,
+    /// Times the site was flagged
+    pub count: u64,
+}
+
+/// Summary of handoff validation.
+#[derive(Debug, Clone, Default)]
+pub struct OrderingReport {
+    /// Release/Acquire handoffs observed
+    pub pairings: u64,
+    /// Flagged call sites, in the order they were first flagged
+    pub findings: Vec<OrderingFinding>,
+}
+
+impl OrderingReport {
+    /// Check whether no call site was flagged.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Group the flagged call sites by module.
+    pub fn by_module(&self) -> BTreeMap<&'static str, Vec<OrderingFinding>> {
+        let mut modules: BTreeMap<&'static str, Vec<OrderingFinding>> = BTreeMap::new();
+        for finding in &self.findings {
+            modules.entry(finding.site.module).or_default().push(*finding);
+        }
+        modules
+    }
+}
+
+impl fmt::Display for OrderingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} acquire/release pairings, {} flagged sites",
+            self.pairings,
+            self.findings.len()
+        )?;
+        for (module, findings) in self.by_module() {
+            writeln!(f, "{}:", module)?;
+            for finding in findings {
+                write!(f, "  {} {:?} x{}", finding.site, finding.issue, finding.count)?;
+                if let Some(peer) = finding.peer {
+                    write!(f, " (peer {})", peer)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ordering_to_u8(ordering: Ordering) -> u8 {
+    match ordering {
+        Ordering::Relaxed => 0,
+        Ordering::Release => 1,
+        Ordering::Acquire => 2,
+        Ordering::AcqRel => 3,
+        _ => 4,
+    }
+}
+
+fn has_release(ordering: u8) -> bool {
+    matches!(ordering, 1 | 3 | 4)
+}
+
+fn has_acquire(ordering: Ordering) -> bool {
+    !matches!(ordering, Ordering::Relaxed | Ordering::Release)
 }
 
 impl RaceDetector {
@@ -29,6 +177,9 @@ impl RaceDetector {
             total_accesses: AtomicUsize::new(0),
             races_detected: AtomicUsize::new(0),
             clock: AtomicU64::new(0),
+            publishes: [EMPTY_SLOT; PUBLISH_SLOTS],
+            pairings: AtomicU64::new(0),
+            findings: spin::Mutex::new(Vec::new()),
         }
     }
     
@@ -92,6 +243,79 @@ impl RaceDetector {
         fence(Ordering::SeqCst);
     }
     
+    /// Record that `site` published a value through the atomic at `addr`
+    /// with a store or read-modify-write using `ordering`.
+    pub fn record_publish(&self, addr: usize, ordering: Ordering, site: &'static Site) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        self.total_accesses.fetch_add(1, Ordering::Relaxed);
+        let slot = Self::publish_slot(&self.publishes, addr);
+        let ordering = ordering_to_u8(ordering);
+        slot.site.store(site as *const Site as *mut Site, Ordering::Relaxed);
+        slot.ordering.store(ordering, Ordering::Relaxed);
+        slot.addr.store(addr, Ordering::Release);
+
+        if !has_release(ordering) {
+            self.flag(site, OrderingIssue::RelaxedPublish, None);
+        }
+    }
+
+    /// Record that `site` read a published value from the atomic at `addr`
+    /// with a load or read-modify-write using `ordering`.
+    pub fn record_consume(&self, addr: usize, ordering: Ordering, site: &'static Site) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        self.total_accesses.fetch_add(1, Ordering::Relaxed);
+        let slot = Self::publish_slot(&self.publishes, addr);
+        let publish = (slot.addr.load(Ordering::Acquire) == addr).then(|| {
+            let site = slot.site.load(Ordering::Relaxed);
+            // Safety: slots only ever hold `&'static Site`s
+            let site = unsafe { site.as_ref() };
+            (slot.ordering.load(Ordering::Relaxed), site)
+        });
+
+        if !has_acquire(ordering) {
+            self.flag(site, OrderingIssue::RelaxedConsume, publish.and_then(|(_, site)| site));
+        } else if publish.map_or(false, |(ordering, _)| has_release(ordering)) {
+            self.pairings.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the handoff validation report.
+    pub fn ordering_report(&self) -> OrderingReport {
+        OrderingReport {
+            pairings: self.pairings.load(Ordering::Relaxed),
+            findings: self.findings.lock().clone(),
+        }
+    }
+
+    fn publish_slot(slots: &[PublishSlot; PUBLISH_SLOTS], addr: usize) -> &PublishSlot {
+        // Atomics are at least word aligned; mix in the higher bits
+        let hash = (addr >> 3) ^ (addr >> 11);
+        &slots[hash % PUBLISH_SLOTS]
+    }
+
+    fn flag(&self, site: &'static Site, issue: OrderingIssue, peer: Option<&'static Site>) {
+        let mut findings = self.findings.lock();
+        match findings
+            .iter_mut()
+            .find(|finding| ptr_eq(finding.site, site) && finding.issue == issue)
+        {
+            Some(finding) => {
+                finding.count += 1;
+                finding.peer = finding.peer.or(peer);
+            }
+            None => {
+                self.races_detected.fetch_add(1, Ordering::Relaxed);
+                findings.push(OrderingFinding { site, issue, peer, count: 1 });
+            }
+        }
+    }
+    
     /// Get race detection statistics.
     pub fn stats(&self) -> RaceDetectorStats {
         RaceDetectorStats {
@@ -106,9 +330,18 @@ impl RaceDetector {
         self.total_accesses.store(0, Ordering::Release);
         self.races_detected.store(0, Ordering::Release);
         self.clock.store(0, Ordering::Release);
+        self.pairings.store(0, Ordering::Release);
+        self.findings.lock().clear();
+        for slot in &self.publishes {
+            slot.addr.store(0, Ordering::Release);
+        }
     }
 }
 
+fn ptr_eq(a: &Site, b: &Site) -> bool {
+    core::ptr::eq(a, b)
+}
+
 /// Statistics from the race detector.
 #[derive(Debug, Clone, Copy)]
 pub struct RaceDetectorStats {
@@ -264,6 +497,47 @@ mod tests {
         assert_eq!(stats.total_accesses, 0);
     }
     
+    #[test]
+    fn test_handoff_validation() {
+        static PUBLISH: Site = Site { module: "queue", file: "queue.rs", line: 10 };
+        static RELAXED_PUBLISH: Site = Site { module: "pool", file: "pool.rs", line: 20 };
+        static CONSUME: Site = Site { module: "queue", file: "queue.rs", line: 30 };
+        static RELAXED_CONSUME: Site = Site { module: "queue", file: "queue.rs", line: 40 };
+
+        let detector = RaceDetector::new();
+        let (a, b) = (0x1000, 0x2008);
+
+        // Ignored while disabled
+        detector.record_publish(a, Ordering::Relaxed, &RELAXED_PUBLISH);
+        assert!(detector.ordering_report().is_clean());
+
+        detector.enable();
+        detector.record_publish(a, Ordering::Release, &PUBLISH);
+        detector.record_consume(a, Ordering::Acquire, &CONSUME);
+        detector.record_consume(a, Ordering::Relaxed, &RELAXED_CONSUME);
+        detector.record_consume(a, Ordering::Relaxed, &RELAXED_CONSUME);
+        detector.record_publish(b, Ordering::Relaxed, &RELAXED_PUBLISH);
+
+        let report = detector.ordering_report();
+        assert_eq!(report.pairings, 1);
+        assert_eq!(report.findings.len(), 2);
+        assert_eq!(detector.stats().races_detected, 2);
+
+        let modules = report.by_module();
+        assert_eq!(modules.keys().copied().collect::<Vec<_>>(), ["pool", "queue"]);
+        let consume = modules["queue"][0];
+        assert_eq!(consume.issue, OrderingIssue::RelaxedConsume);
+        assert_eq!(consume.count, 2);
+        assert_eq!(consume.peer, Some(&PUBLISH));
+        assert_eq!(modules["pool"][0].issue, OrderingIssue::RelaxedPublish);
+
+        let text = alloc::format!("{}", report);
+        assert!(text.contains("queue.rs:40 RelaxedConsume x2 (peer queue.rs:10)"));
+
+        detector.reset();
+        assert!(detector.ordering_report().is_clean());
+    }
+
     #[test]
     fn test_ordering_validation() {
         // Valid load orderings
@@ -4,8 +4,15 @@
 //! different size classes and optional guard page support.
 
 use portable_atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "mmu")]
+use portable_atomic::AtomicBool;
 use spin::Mutex;
 use core::ptr::NonNull;
+use crate::security::{SecurityViolation, handle_security_violation};
+use crate::perf::PERF_COUNTERS;
+use crate::perf::numa::{self, NumaNodeId};
+use crate::testing::faults::{self, Fault};
+use super::watermarks::{StackRecommendation, STACK_WATERMARKS};
 // mem and MaybeUninit imports not needed yet
 // use core::mem::{self, MaybeUninit};
 
@@ -22,6 +29,52 @@ extern crate alloc;
 #[cfg(not(feature = "std-shim"))]
 use alloc::vec::Vec;
 
+#[cfg(feature = "std-shim")]
+use std::collections::BTreeMap;
+
+#[cfg(not(feature = "std-shim"))]
+use alloc::collections::BTreeMap;
+
+/// Byte pattern written over the usable area of freed stacks.
+///
+/// A stack that no longer carries this pattern when it is handed out again
+/// was written to after being returned to the pool.
+pub const STACK_POISON: u8 = 0xA5;
+
+/// Whether freed stacks are poisoned and checked on reuse.
+const POISON_FREED_STACKS: bool = cfg!(any(feature = "hardened", debug_assertions));
+
+/// Whether stack lifecycle states are tracked to catch double frees.
+///
+/// Tracking takes a lock and may allocate on every allocation and free, so
+/// release builds only pay for it with `checked-reclaim`. Without it the
+/// pool still records which stacks it created, which only changes when
+/// stack memory is allocated or freed.
+const TRACK_STACK_STATES: bool = cfg!(any(feature = "checked-reclaim", debug_assertions));
+
+/// Where a stack's memory came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StackBacking {
+    /// Regular heap allocation, freed when the stack is dropped
+    Heap,
+    /// Carved out of a huge page owned by the pool
+    HugePage,
+    /// Allocated through the platform's NUMA node allocator, freed through
+    /// it when the stack is dropped
+    NodeLocal,
+    /// Lent by the caller for as long as the program runs
+    Borrowed,
+}
+
+/// Lifecycle state of a stack owned by a [`StackPool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackState {
+    /// The stack has been handed out and not yet returned.
+    Allocated,
+    /// The stack is sitting on the pool's free list.
+    Free,
+}
+
 /// Stack size classes for the pool allocator.
 ///
 /// Different threads may need different stack sizes, so we provide
@@ -81,9 +134,31 @@ pub struct Stack {
     size_class: StackSizeClass,
     /// Whether this stack has guard pages
     has_guard_pages: bool,
+    /// Where the stack memory came from
+    backing: StackBacking,
+    /// NUMA node the memory was allocated on, if known
+    numa_node: Option<NumaNodeId>,
 }
 
 impl Stack {
+    /// Use caller-provided memory as a stack.
+    ///
+    /// The memory is never freed or returned to a pool.
+    #[cfg(feature = "std-shim")]
+    pub(crate) fn borrowed(memory: &'static mut [u8]) -> Self {
+        let size = memory.len();
+        Self {
+            // Safety: slice pointers are never null
+            memory: unsafe { NonNull::new_unchecked(memory.as_mut_ptr()) },
+            total_size: size,
+            usable_size: size,
+            size_class: StackSizeClass::for_size(size).unwrap_or(StackSizeClass::ExtraLarge),
+            has_guard_pages: false,
+            backing: StackBacking::Borrowed,
+            numa_node: None,
+        }
+    }
+    
     /// Get the usable stack size in bytes.
     pub fn size(&self) -> usize {
         self.usable_size
@@ -136,6 +211,16 @@ impl Stack {
         self.has_guard_pages
     }
     
+    /// Check if this stack is backed by a huge page.
+    pub fn is_huge_page_backed(&self) -> bool {
+        self.backing == StackBacking::HugePage
+    }
+    
+    /// Get the NUMA node this stack's memory lives on, if known.
+    pub fn numa_node(&self) -> Option<NumaNodeId> {
+        self.numa_node
+    }
+    
     /// Install a stack canary value for overflow detection.
     ///
     /// This writes a known pattern at the bottom of the usable stack
@@ -151,6 +236,50 @@ impl Stack {
         }
     }
     
+    /// Fill the usable stack area with [`STACK_POISON`].
+    fn poison(&self) {
+        unsafe {
+            core::ptr::write_bytes(self.stack_top() as *mut u8, STACK_POISON, self.usable_size);
+        }
+    }
+    
+    /// Check whether the usable stack area still holds the poison pattern.
+    fn is_poison_intact(&self) -> bool {
+        let memory = unsafe { core::slice::from_raw_parts(self.stack_top(), self.usable_size) };
+        memory.iter().all(|&byte| byte == STACK_POISON)
+    }
+    
+    /// Scrub `len` bytes of the usable area starting at `offset`.
+    ///
+    /// With `verify` set the bytes must still hold [`STACK_POISON`]. The
+    /// pattern is then rewritten and read back. Returns `false` if any word
+    /// differed, which on memory nobody writes to points at a RAM fault.
+    fn scrub(&self, offset: usize, len: usize, verify: bool) -> bool {
+        const PATTERN: u64 = u64::from_ne_bytes([STACK_POISON; 8]);
+        let words = self.stack_top().wrapping_add(offset) as *mut u64;
+        let mut intact = true;
+        for i in 0..len / 8 {
+            // Safety: `offset + len` is within the usable area, which is
+            // page aligned, and volatile accesses make the read-back real
+            unsafe {
+                let word = words.add(i);
+                if verify && word.read_volatile() != PATTERN {
+                    intact = false;
+                }
+                word.write_volatile(PATTERN);
+                if word.read_volatile() != PATTERN {
+                    intact = false;
+                }
+            }
+        }
+        intact
+    }
+    
+    /// Address identifying this stack's memory, shared by all clones.
+    fn address(&self) -> usize {
+        self.memory.as_ptr() as usize
+    }
+    
     /// Check if the stack canary is still intact.
     ///
     /// # Arguments
@@ -173,6 +302,15 @@ impl Stack {
 pub struct StackPool {
     /// Free stacks for each size class
     free_stacks: [Mutex<Vec<Stack>>; 4],
+    /// Allocation state of every stack this pool has created, keyed by
+    /// address; only kept up to date if states are tracked
+    states: Mutex<BTreeMap<usize, StackState>>,
+    /// Huge pages that large stacks are carved from
+    #[cfg(feature = "mmu")]
+    huge_pages: Mutex<super::page_mapper::HugePageArena>,
+    /// Whether new stacks get guard pages
+    #[cfg(feature = "mmu")]
+    guard_pages: AtomicBool,
     /// Statistics counters
     stats: StackPoolStats,
 }
@@ -186,6 +324,8 @@ struct StackPoolStats {
     deallocated: AtomicUsize,
     /// Number of stacks currently in use
     in_use: AtomicUsize,
+    /// Number of stacks whose memory was freed
+    released: AtomicUsize,
 }
 
 impl StackPool {
@@ -198,10 +338,16 @@ impl StackPool {
                 Mutex::new(Vec::new()),
                 Mutex::new(Vec::new()),
             ],
+            states: Mutex::new(BTreeMap::new()),
+            #[cfg(feature = "mmu")]
+            huge_pages: Mutex::new(super::page_mapper::HugePageArena::new()),
+            #[cfg(feature = "mmu")]
+            guard_pages: AtomicBool::new(true),
             stats: StackPoolStats {
                 allocated: AtomicUsize::new(0),
                 deallocated: AtomicUsize::new(0),
                 in_use: AtomicUsize::new(0),
+                released: AtomicUsize::new(0),
             },
         }
     }
@@ -219,51 +365,298 @@ impl StackPool {
     ///
     /// A new stack, or `None` if allocation fails.
     pub fn allocate(&self, size_class: StackSizeClass) -> Option<Stack> {
+        if faults::inject(Fault::StackAllocation) {
+            return None;
+        }
+        
         let class_index = self.size_class_index(size_class);
         
         // Try to get a stack from the free list first
+        if let Some(stack) = self.take_free(class_index, |_| true) {
+            return Some(stack);
+        }
+        
+        // Need to allocate a new stack
+        let stack = self.allocate_new_stack(size_class)?;
+        self.register(&stack);
+        Some(stack)
+    }
+    
+    /// Reserve `count` stacks of one size class at once.
+    ///
+    /// Free stacks are taken under a single lock of the free list and the
+    /// rest are allocated new. Either all `count` stacks are returned or,
+    /// if any allocation fails, none are and the pool is left as it was.
+    pub fn allocate_many(&self, size_class: StackSizeClass, count: usize) -> Option<Vec<Stack>> {
+        if (0..count).any(|_| faults::inject(Fault::StackAllocation)) {
+            return None;
+        }
+        
+        let class_index = self.size_class_index(size_class);
+        let mut stacks = Vec::with_capacity(count);
         if let Some(mut free_list) = self.free_stacks[class_index].try_lock() {
-            if let Some(stack) = free_list.pop() {
-                self.stats.in_use.fetch_add(1, Ordering::AcqRel);
-                return Some(stack);
+            let reused = free_list.len().min(count);
+            let start = free_list.len() - reused;
+            stacks.extend(free_list.drain(start..));
+        }
+        
+        for stack in &stacks {
+            if POISON_FREED_STACKS && !stack.is_poison_intact() {
+                handle_security_violation(SecurityViolation::MemoryViolation);
             }
         }
+        if TRACK_STACK_STATES {
+            let mut states = self.states.lock();
+            for stack in &stacks {
+                states.insert(stack.address(), StackState::Allocated);
+            }
+        }
+        self.stats.in_use.fetch_add(stacks.len(), Ordering::AcqRel);
         
-        // Need to allocate a new stack
-        self.allocate_new_stack(size_class)
+        while stacks.len() < count {
+            match self.allocate_new_stack(size_class) {
+                Some(stack) => {
+                    self.register(&stack);
+                    stacks.push(stack);
+                }
+                None => {
+                    for stack in stacks {
+                        self.deallocate(stack);
+                    }
+                    return None;
+                }
+            }
+        }
+        Some(stacks)
+    }
+    
+    /// Allocate a stack whose memory lives on the given NUMA node.
+    ///
+    /// Free stacks from `node` are reused first. New stacks come from the
+    /// registered [`NodeAllocator`](crate::perf::numa::NodeAllocator); without
+    /// one this falls back to [`allocate`](Self::allocate).
+    pub fn allocate_on_node(&self, size_class: StackSizeClass, node: NumaNodeId) -> Option<Stack> {
+        let class_index = self.size_class_index(size_class);
+        
+        if let Some(stack) = self.take_free(class_index, |stack| stack.numa_node == Some(node)) {
+            PERF_COUNTERS.record_numa_local();
+            return Some(stack);
+        }
+        
+        if let Some(stack) = self.allocate_node_local_stack(size_class, node) {
+            self.register(&stack);
+            PERF_COUNTERS.record_numa_local();
+            return Some(stack);
+        }
+        
+        // Placement of fallback memory is only known for node-local stacks
+        let stack = self.allocate(size_class)?;
+        match stack.numa_node {
+            Some(actual) if actual == node => PERF_COUNTERS.record_numa_local(),
+            Some(_) => PERF_COUNTERS.record_numa_remote(),
+            None => {}
+        }
+        Some(stack)
+    }
+    
+    /// Take the most recently freed stack matching `filter` off a free list.
+    fn take_free(&self, class_index: usize, filter: impl Fn(&Stack) -> bool) -> Option<Stack> {
+        let mut free_list = self.free_stacks[class_index].try_lock()?;
+        let position = free_list.iter().rposition(filter)?;
+        let stack = free_list.swap_remove(position);
+        drop(free_list);
+        
+        // Anything but poison means the stack was written after it was freed
+        if POISON_FREED_STACKS && !stack.is_poison_intact() {
+            handle_security_violation(SecurityViolation::MemoryViolation);
+        }
+        
+        self.set_state(&stack, StackState::Allocated);
+        self.stats.in_use.fetch_add(1, Ordering::AcqRel);
+        Some(stack)
     }
     
     /// Return a stack to the pool for reuse.
     ///
+    /// Returning a stack that is already free, or one that was never
+    /// allocated by this pool, is treated as a memory violation.
+    ///
     /// # Arguments
     ///
     /// * `stack` - The stack to return to the pool
     pub fn deallocate(&self, stack: Stack) {
         let class_index = self.size_class_index(stack.size_class);
         
-        // TODO: In a hardened implementation, we might want to wipe the stack memory
-        #[cfg(feature = "hardened")]
-        {
-            // Wipe stack memory for security
-            unsafe {
-                core::ptr::write_bytes(
-                    stack.memory.as_ptr(),
-                    0,
-                    stack.usable_size,
-                );
-            }
+        // Double free or foreign stack
+        if TRACK_STACK_STATES && self.state_of(&stack) != Some(StackState::Allocated) {
+            handle_security_violation(SecurityViolation::MemoryViolation);
+        }
+        
+        // Poisoning also wipes whatever the previous thread left behind
+        if POISON_FREED_STACKS {
+            stack.poison();
         }
         
         if let Some(mut free_list) = self.free_stacks[class_index].try_lock() {
+            self.set_state(&stack, StackState::Free);
             free_list.push(stack);
             self.stats.in_use.fetch_sub(1, Ordering::AcqRel);
             self.stats.deallocated.fetch_add(1, Ordering::AcqRel);
         } else {
-            // If we can't get the lock, just leak the stack for now
-            // In a real implementation, we might want a different strategy
+            // The free list is busy; free the stack instead of waiting
+            self.clear_state(&stack);
+            self.stats.in_use.fetch_sub(1, Ordering::AcqRel);
+            self.stats.released.fetch_add(1, Ordering::AcqRel);
+            #[cfg(feature = "mmu")]
+            self.release_huge_page_stack(&stack);
+        }
+    }
+    
+    /// Free an allocated stack's memory instead of keeping it for reuse.
+    ///
+    /// Like [`deallocate`](Self::deallocate), releasing a stack that is not
+    /// allocated from this pool is treated as a memory violation.
+    pub fn release(&self, stack: Stack) {
+        // Untracked states stay `Allocated` from creation on, which still
+        // catches foreign stacks
+        if self.clear_state(&stack) != Some(StackState::Allocated) {
+            handle_security_violation(SecurityViolation::MemoryViolation);
+        }
+        self.stats.in_use.fetch_sub(1, Ordering::AcqRel);
+        self.stats.released.fetch_add(1, Ordering::AcqRel);
+        #[cfg(feature = "mmu")]
+        self.release_huge_page_stack(&stack);
+        drop(stack);
+    }
+    
+    /// Get the lifecycle state of a stack.
+    ///
+    /// Returns `None` if the stack was not allocated by this pool, and
+    /// always in release builds without the `checked-reclaim` feature,
+    /// which don't track states.
+    pub fn state_of(&self, stack: &Stack) -> Option<StackState> {
+        if !TRACK_STACK_STATES {
+            return None;
+        }
+        self.states.lock().get(&stack.address()).copied()
+    }
+    
+    /// Verify that a stack is still allocated before it is used.
+    ///
+    /// Raises a memory violation if the stack has already been returned
+    /// to the pool or was never allocated by it. Does nothing when states
+    /// aren't tracked.
+    pub fn assert_allocated(&self, stack: &Stack) {
+        if TRACK_STACK_STATES && self.state_of(stack) != Some(StackState::Allocated) {
+            handle_security_violation(SecurityViolation::MemoryViolation);
+        }
+    }
+    
+    /// Check whether a stack was created by this pool and not yet freed.
+    pub fn owns(&self, stack: &Stack) -> bool {
+        self.states.lock().contains_key(&stack.address())
+    }
+    
+    /// Record a stack this pool just created as allocated.
+    fn register(&self, stack: &Stack) {
+        self.states.lock().insert(stack.address(), StackState::Allocated);
+    }
+    
+    /// Record the lifecycle state of a stack.
+    fn set_state(&self, stack: &Stack, state: StackState) {
+        if TRACK_STACK_STATES {
+            self.states.lock().insert(stack.address(), state);
+        }
+    }
+    
+    /// Forget a stack whose memory is freed or taken out of service,
+    /// returning the state it was in.
+    fn clear_state(&self, stack: &Stack) -> Option<StackState> {
+        self.states.lock().remove(&stack.address())
+    }
+    
+    /// Get the number of free stacks of a size class.
+    pub fn free_count(&self, size_class: StackSizeClass) -> usize {
+        self.free_stacks[self.size_class_index(size_class)].lock().len()
+    }
+    
+    /// Take the least recently freed stack of a size class off its free
+    /// list for scrubbing. It stays free and goes back with
+    /// [`return_scrubbed`](Self::return_scrubbed).
+    ///
+    /// Returns `None` if the list is empty or busy.
+    pub(crate) fn take_for_scrub(&self, size_class: StackSizeClass) -> Option<Stack> {
+        let mut free_list = self.free_stacks[self.size_class_index(size_class)].try_lock()?;
+        (!free_list.is_empty()).then(|| free_list.remove(0))
+    }
+    
+    /// Scrub `len` bytes of a stack taken with
+    /// [`take_for_scrub`](Self::take_for_scrub), starting `offset` bytes
+    /// into its usable area. Returns `false` on a RAM fault.
+    ///
+    /// Contents are only checked when freed stacks are poisoned; otherwise
+    /// the pattern is written and read back.
+    pub(crate) fn scrub(&self, stack: &Stack, offset: usize, len: usize) -> bool {
+        let len = len.min(stack.usable_size.saturating_sub(offset));
+        stack.scrub(offset, len, POISON_FREED_STACKS)
+    }
+    
+    /// Put a scrubbed stack back on its free list, handing it back if the
+    /// list is busy.
+    pub(crate) fn return_scrubbed(&self, stack: Stack) -> Result<(), Stack> {
+        match self.free_stacks[self.size_class_index(stack.size_class)].try_lock() {
+            Some(mut free_list) => {
+                free_list.push(stack);
+                Ok(())
+            }
+            None => Err(stack),
         }
     }
     
+    /// Take a stack that failed scrubbing out of service. Its memory is
+    /// never handed out or freed again.
+    pub(crate) fn retire(&self, stack: Stack) {
+        self.clear_state(&stack);
+        core::mem::forget(stack);
+    }
+    
+    /// Release the spare capacity of a size class's free list once it is
+    /// more than twice what the list holds. Returns the bytes released.
+    pub fn compact(&self, size_class: StackSizeClass) -> usize {
+        let Some(mut free_list) = self.free_stacks[self.size_class_index(size_class)].try_lock() else {
+            return 0;
+        };
+        let capacity = free_list.capacity();
+        if capacity <= 2 * free_list.len() {
+            return 0;
+        }
+        free_list.shrink_to_fit();
+        (capacity - free_list.capacity()) * core::mem::size_of::<Stack>()
+    }
+    
+    /// Suggest a stack size class for each thread name from the stack
+    /// high-water marks recorded as named threads exit.
+    ///
+    /// The history includes earlier runs when a
+    /// [`WatermarkStore`](super::WatermarkStore) is registered.
+    pub fn recommendations() -> Vec<StackRecommendation> {
+        STACK_WATERMARKS.recommendations()
+    }
+    
+    /// Enable or disable guard pages for stacks allocated from now on.
+    ///
+    /// Guard pages can't be placed inside a huge page, so large stacks are
+    /// only backed by huge pages while guard pages are disabled.
+    #[cfg(feature = "mmu")]
+    pub fn set_guard_pages(&self, enabled: bool) {
+        self.guard_pages.store(enabled, Ordering::Release);
+    }
+    
+    /// Get the number of stacks freed with [`release`](Self::release).
+    pub fn released_count(&self) -> usize {
+        self.stats.released.load(Ordering::Acquire)
+    }
+    
     /// Get statistics about the stack pool.
     pub fn stats(&self) -> (usize, usize, usize) {
         (
@@ -285,8 +678,16 @@ impl StackPool {
     
     /// Allocate a new stack of the given size class.
     fn allocate_new_stack(&self, size_class: StackSizeClass) -> Option<Stack> {
+        #[cfg(feature = "mmu")]
+        if let Some(stack) = self.allocate_huge_page_stack(size_class) {
+            return Some(stack);
+        }
+        
         let usable_size = size_class.size();
-        let has_guard_pages = cfg!(feature = "mmu");
+        #[cfg(feature = "mmu")]
+        let has_guard_pages = self.guard_pages.load(Ordering::Acquire);
+        #[cfg(not(feature = "mmu"))]
+        let has_guard_pages = false;
         
         // Calculate total size including guard pages
         let total_size = if has_guard_pages {
@@ -322,6 +723,8 @@ impl StackPool {
                 usable_size,
                 size_class,
                 has_guard_pages,
+                backing: StackBacking::Heap,
+                numa_node: None,
             };
             
             self.stats.allocated.fetch_add(1, Ordering::AcqRel);
@@ -336,6 +739,89 @@ impl StackPool {
         }
     }
     
+    /// Carve a large stack out of a huge page.
+    ///
+    /// Only `Large` and `ExtraLarge` stacks are huge page backed, and only
+    /// when a [`PageMapper`](super::page_mapper::PageMapper) is registered
+    /// and guard pages are disabled with [`set_guard_pages`](Self::set_guard_pages).
+    /// Guard pages can't be placed inside a huge page, so these stacks rely
+    /// on canaries for overflow detection.
+    #[cfg(feature = "mmu")]
+    fn allocate_huge_page_stack(&self, size_class: StackSizeClass) -> Option<Stack> {
+        use super::page_mapper;
+        
+        if !matches!(size_class, StackSizeClass::Large | StackSizeClass::ExtraLarge) {
+            return None;
+        }
+        if self.guard_pages.load(Ordering::Acquire) {
+            return None;
+        }
+        
+        let mapper = page_mapper::page_mapper()?;
+        let usable_size = size_class.size();
+        
+        let Some(memory) = self.huge_pages.lock().carve(usable_size, mapper) else {
+            page_mapper::record_huge_page_miss();
+            return None;
+        };
+        
+        // Pool memory must not be executable; fall back to regular pages
+        let status = crate::security::wx::check_region_with(mapper, memory.as_ptr() as usize, usable_size);
+        if let crate::security::wx::WxStatus::Executable(_) = status {
+            crate::security::SECURITY_STATE.record_violation(crate::security::SecurityViolation::MemoryViolation);
+            page_mapper::record_huge_page_miss();
+            return None;
+        }
+        
+        page_mapper::record_huge_page_hit();
+        self.stats.allocated.fetch_add(1, Ordering::AcqRel);
+        self.stats.in_use.fetch_add(1, Ordering::AcqRel);
+        
+        Some(Stack {
+            memory,
+            total_size: usable_size,
+            usable_size,
+            size_class,
+            has_guard_pages: false,
+            backing: StackBacking::HugePage,
+            numa_node: None,
+        })
+    }
+    
+    /// Give a huge page backed stack's memory back to the arena, which
+    /// unmaps the huge page once none of its stacks is left.
+    #[cfg(feature = "mmu")]
+    fn release_huge_page_stack(&self, stack: &Stack) {
+        if !stack.is_huge_page_backed() {
+            return;
+        }
+        if let Some(mapper) = super::page_mapper::page_mapper() {
+            // Safety: the stack was carved from this arena and is no
+            // longer allocated
+            unsafe { self.huge_pages.lock().release(stack.memory, mapper) };
+        }
+    }
+    
+    /// Allocate a new stack through the platform's NUMA node allocator.
+    fn allocate_node_local_stack(&self, size_class: StackSizeClass, node: NumaNodeId) -> Option<Stack> {
+        let allocator = numa::node_allocator()?;
+        let usable_size = size_class.size();
+        let memory = allocator.allocate_on_node(node, usable_size, 4096)?;
+        
+        self.stats.allocated.fetch_add(1, Ordering::AcqRel);
+        self.stats.in_use.fetch_add(1, Ordering::AcqRel);
+        
+        Some(Stack {
+            memory,
+            total_size: usable_size,
+            usable_size,
+            size_class,
+            has_guard_pages: false,
+            backing: StackBacking::NodeLocal,
+            numa_node: Some(node),
+        })
+    }
+    
     /// Set up guard pages for a stack allocation.
     #[cfg(feature = "mmu")]
     fn setup_guard_pages(&self, _memory: &NonNull<u8>, _total_size: usize) {
@@ -345,19 +831,45 @@ impl StackPool {
     }
 }
 
+#[cfg(feature = "mmu")]
+impl Drop for StackPool {
+    fn drop(&mut self) {
+        // Unmap huge pages whose stacks are all back on the free lists
+        for free in &self.free_stacks {
+            for stack in free.lock().drain(..) {
+                self.release_huge_page_stack(&stack);
+            }
+        }
+    }
+}
+
 impl Drop for Stack {
     fn drop(&mut self) {
-        // TODO: In a real implementation, we'd need to coordinate with the
-        // stack pool to properly deallocate memory
-        #[cfg(feature = "std-shim")]
-        {
-            extern crate std;
-            use std::alloc::{dealloc, Layout};
-            
-            let layout = Layout::from_size_align(self.total_size, 4096).unwrap();
-            unsafe {
-                dealloc(self.memory.as_ptr(), layout);
+        match self.backing {
+            StackBacking::NodeLocal => {
+                if let (Some(allocator), Some(node)) = (numa::node_allocator(), self.numa_node) {
+                    // Safety: node-local stacks are allocated with this node,
+                    // size and alignment in `allocate_node_local_stack`
+                    unsafe { allocator.deallocate_on_node(node, self.memory, self.total_size, 4096) };
+                }
+            }
+            StackBacking::Heap => {
+                // TODO: In a real implementation, we'd need to coordinate with the
+                // stack pool to properly deallocate memory
+                #[cfg(feature = "std-shim")]
+                {
+                    extern crate std;
+                    use std::alloc::{dealloc, Layout};
+                    
+                    let layout = Layout::from_size_align(self.total_size, 4096).unwrap();
+                    unsafe {
+                        dealloc(self.memory.as_ptr(), layout);
+                    }
+                }
             }
+            // Huge pages go back to the mapper through the pool's arena, and
+            // borrowed memory isn't ours to free
+            StackBacking::HugePage | StackBacking::Borrowed => {}
         }
     }
 }
@@ -409,4 +921,143 @@ mod tests {
         
         pool.deallocate(stack);
     }
+    
+    #[cfg(all(feature = "std-shim", any(feature = "checked-reclaim", debug_assertions)))]
+    #[test]
+    fn test_stack_state_tracking() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let alias = stack.clone();
+        
+        assert_eq!(pool.state_of(&stack), Some(StackState::Allocated));
+        pool.deallocate(stack);
+        assert_eq!(pool.state_of(&alias), Some(StackState::Free));
+        
+        // Reuse hands out the same memory with poison intact
+        let reused = pool.allocate(StackSizeClass::Small).unwrap();
+        assert_eq!(pool.state_of(&reused), Some(StackState::Allocated));
+        core::mem::forget(alias);
+        pool.deallocate(reused);
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_pool_owns_its_stacks_until_released() {
+        let pool = StackPool::new();
+        let other = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let alias = stack.clone();
+        
+        assert!(pool.owns(&stack));
+        assert!(!other.owns(&stack));
+        
+        // Still the pool's while it sits on the free list
+        pool.deallocate(stack);
+        assert!(pool.owns(&alias));
+        
+        let reused = pool.allocate(StackSizeClass::Small).unwrap();
+        core::mem::forget(alias);
+        pool.release(reused);
+        assert_eq!(pool.free_count(StackSizeClass::Small), 0);
+        assert_eq!(pool.released_count(), 1);
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_node_local_stack_freed_through_allocator() {
+        extern crate std;
+        use crate::perf::numa::NodeAllocator;
+        use std::alloc::{alloc, dealloc, Layout};
+        
+        /// Node only this test allocates on
+        const NODE: NumaNodeId = 7;
+        static FREED: AtomicUsize = AtomicUsize::new(0);
+        
+        struct HeapNodes;
+        
+        impl NodeAllocator for HeapNodes {
+            fn allocate_on_node(&self, _node: NumaNodeId, size: usize, align: usize) -> Option<NonNull<u8>> {
+                NonNull::new(unsafe { alloc(Layout::from_size_align(size, align).ok()?) })
+            }
+            
+            unsafe fn deallocate_on_node(&self, node: NumaNodeId, memory: NonNull<u8>, size: usize, align: usize) {
+                if node == NODE {
+                    FREED.fetch_add(1, Ordering::Relaxed);
+                }
+                unsafe { dealloc(memory.as_ptr(), Layout::from_size_align(size, align).unwrap()) };
+            }
+        }
+        
+        let _allocator = numa::hold_node_allocator(&HeapNodes);
+        let pool = StackPool::new();
+        let stack = pool.allocate_on_node(StackSizeClass::Small, NODE).unwrap();
+        assert_eq!(stack.numa_node(), Some(NODE));
+        
+        pool.release(stack);
+        assert_eq!(FREED.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.stats().2, 0);
+    }
+    
+    #[cfg(all(feature = "std-shim", any(feature = "checked-reclaim", debug_assertions)))]
+    #[test]
+    #[should_panic]
+    fn test_stack_double_free() {
+        // Leak the pool: `Stack` clones share memory, so dropping both the
+        // pooled stack and the alias would free it twice while unwinding
+        let pool = alloc::boxed::Box::leak(alloc::boxed::Box::new(StackPool::new()));
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let alias = stack.clone();
+        
+        pool.deallocate(stack);
+        pool.deallocate(alias);
+    }
+    
+    #[cfg(feature = "mmu")]
+    #[test]
+    fn test_huge_page_stacks_need_guard_pages_off_and_are_unmapped() {
+        use super::super::page_mapper::{self, PageMapper, HUGE_PAGE_SIZE};
+        use std::alloc::{alloc, dealloc, Layout};
+        
+        static MAPPED: AtomicUsize = AtomicUsize::new(0);
+        
+        struct HeapMapper;
+        
+        impl PageMapper for HeapMapper {
+            fn map_huge_page(&self) -> Option<NonNull<u8>> {
+                MAPPED.fetch_add(1, Ordering::Relaxed);
+                NonNull::new(unsafe { alloc(Layout::from_size_align(HUGE_PAGE_SIZE, HUGE_PAGE_SIZE).unwrap()) })
+            }
+            
+            unsafe fn unmap_huge_page(&self, page: NonNull<u8>) {
+                MAPPED.fetch_sub(1, Ordering::Relaxed);
+                unsafe { dealloc(page.as_ptr(), Layout::from_size_align(HUGE_PAGE_SIZE, HUGE_PAGE_SIZE).unwrap()) };
+            }
+        }
+        
+        page_mapper::set_page_mapper(&HeapMapper);
+        let pool = StackPool::new();
+        // No guard page fits inside a huge page
+        assert!(pool.allocate_huge_page_stack(StackSizeClass::Large).is_none());
+        assert_eq!(MAPPED.load(Ordering::Relaxed), 0);
+        
+        pool.set_guard_pages(false);
+        let released = pool.allocate(StackSizeClass::Large).unwrap();
+        let pooled = pool.allocate(StackSizeClass::Large).unwrap();
+        let contended = pool.allocate(StackSizeClass::Large).unwrap();
+        assert!(released.is_huge_page_backed() && !released.has_guard_pages());
+        assert_eq!(MAPPED.load(Ordering::Relaxed), 1);
+        
+        pool.release(released);
+        // A busy free list frees the stack back to the arena instead
+        let busy = pool.free_stacks[pool.size_class_index(StackSizeClass::Large)].lock();
+        pool.deallocate(contended);
+        drop(busy);
+        pool.deallocate(pooled);
+        assert_eq!(MAPPED.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.stats().2, 0);
+        
+        // The stack left on the free list goes back when the pool does
+        drop(pool);
+        assert_eq!(MAPPED.load(Ordering::Relaxed), 0);
+    }
 }
\ No newline at end of file
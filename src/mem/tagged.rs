@@ -0,0 +1,251 @@
+//! Tagged pointers for ABA-safe compare-and-swap.
+//!
+//! A lock-free list that frees nodes is exposed to the ABA problem: a thread
+//! reads head `A`, stalls, and meanwhile `A` is popped, freed, reallocated
+//! and pushed again. The stalled thread's CAS then succeeds against a head
+//! that merely has the same address. [`AtomicTaggedPtr`] packs a tag next to
+//! the pointer in one 64-bit word; every update made with
+//! [`TaggedPtr::advance`] bumps the tag, so the stale CAS fails.
+//!
+//! On 64-bit targets the tag lives in the top [`TAG_BITS`] bits, which are
+//! unused with 48-bit virtual addresses (x86_64 4-level paging, AArch64
+//! 48-bit VA, RISC-V Sv39/Sv48). Addresses are sign-extended from bit 47
+//! when unpacked, so higher-half kernel pointers work too. On 32-bit targets
+//! the pointer takes the low word and the tag the high word.
+//!
+//! The tag wraps after `2^TAG_BITS` updates; an ABA needs a thread to stall
+//! across exactly a multiple of that many updates of the same word.
+
+use core::fmt;
+use core::marker::PhantomData;
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Number of tag bits packed next to the pointer.
+#[cfg(target_pointer_width = "64")]
+pub const TAG_BITS: u32 = 16;
+/// Number of tag bits packed next to the pointer.
+#[cfg(not(target_pointer_width = "64"))]
+pub const TAG_BITS: u32 = 32;
+
+const ADDR_BITS: u32 = 64 - TAG_BITS;
+const ADDR_MASK: u64 = (1 << ADDR_BITS) - 1;
+const TAG_MASK: u64 = (1 << TAG_BITS) - 1;
+
+/// A pointer and a tag, packed into one `u64`.
+pub struct TaggedPtr<T> {
+    raw: u64,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> TaggedPtr<T> {
+    /// Pack `ptr` with `tag`; tag bits above [`TAG_BITS`] are dropped.
+    ///
+    /// Debug builds panic if `ptr` uses the bits reserved for the tag.
+    pub fn new(ptr: *mut T, tag: u64) -> Self {
+        let addr = ptr as usize as u64;
+        let packed = Self::from_raw((addr & ADDR_MASK) | ((tag & TAG_MASK) << ADDR_BITS));
+        debug_assert!(packed.ptr() == ptr, "pointer {:p} does not fit in {} bits", ptr, ADDR_BITS);
+        packed
+    }
+
+    /// A null pointer with tag 0.
+    pub const fn null() -> Self {
+        Self::from_raw(0)
+    }
+
+    /// Rebuild a tagged pointer from [`into_raw`](Self::into_raw).
+    pub const fn from_raw(raw: u64) -> Self {
+        Self {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get the packed representation.
+    pub const fn into_raw(self) -> u64 {
+        self.raw
+    }
+
+    /// Get the pointer.
+    pub fn ptr(self) -> *mut T {
+        let addr = self.raw & ADDR_MASK;
+        #[cfg(target_pointer_width = "64")]
+        let addr = ((addr << TAG_BITS) as i64 >> TAG_BITS) as u64;
+        addr as usize as *mut T
+    }
+
+    /// Get the tag.
+    pub fn tag(self) -> u64 {
+        self.raw >> ADDR_BITS
+    }
+
+    /// Check whether the pointer is null, whatever the tag.
+    pub fn is_null(self) -> bool {
+        self.ptr().is_null()
+    }
+
+    /// Replace the pointer with `ptr` and increment the tag.
+    ///
+    /// Use this for every update of an [`AtomicTaggedPtr`], so a
+    /// compare-exchange against an older value fails even if the pointer has
+    /// come back.
+    pub fn advance(self, ptr: *mut T) -> Self {
+        Self::new(ptr, self.tag().wrapping_add(1))
+    }
+}
+
+impl<T> Clone for TaggedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TaggedPtr<T> {}
+
+impl<T> PartialEq for TaggedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T> Eq for TaggedPtr<T> {}
+
+impl<T> fmt::Debug for TaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedPtr")
+            .field("ptr", &self.ptr())
+            .field("tag", &self.tag())
+            .finish()
+    }
+}
+
+/// Atomic [`TaggedPtr`].
+pub struct AtomicTaggedPtr<T> {
+    raw: AtomicU64,
+    _marker: PhantomData<*mut T>,
+}
+
+// Safety: only the packed word is shared, and it is accessed atomically;
+// what the pointer refers to is the caller's business, as with `AtomicPtr`
+unsafe impl<T> Send for AtomicTaggedPtr<T> {}
+unsafe impl<T> Sync for AtomicTaggedPtr<T> {}
+
+impl<T> AtomicTaggedPtr<T> {
+    /// Create a null pointer with tag 0.
+    pub const fn null() -> Self {
+        Self {
+            raw: AtomicU64::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Create an atomic holding `ptr` with tag 0.
+    pub fn new(ptr: *mut T) -> Self {
+        Self {
+            raw: AtomicU64::new(TaggedPtr::new(ptr, 0).into_raw()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Load the pointer and tag.
+    pub fn load(&self, order: Ordering) -> TaggedPtr<T> {
+        TaggedPtr::from_raw(self.raw.load(order))
+    }
+
+    /// Store a pointer and tag.
+    pub fn store(&self, value: TaggedPtr<T>, order: Ordering) {
+        self.raw.store(value.into_raw(), order);
+    }
+
+    /// Swap in a pointer and tag, returning the previous ones.
+    pub fn swap(&self, value: TaggedPtr<T>, order: Ordering) -> TaggedPtr<T> {
+        TaggedPtr::from_raw(self.raw.swap(value.into_raw(), order))
+    }
+
+    /// Store `new` if both the pointer and the tag equal `current`.
+    pub fn compare_exchange(
+        &self,
+        current: TaggedPtr<T>,
+        new: TaggedPtr<T>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        self.raw
+            .compare_exchange(current.into_raw(), new.into_raw(), success, failure)
+            .map(TaggedPtr::from_raw)
+            .map_err(TaggedPtr::from_raw)
+    }
+
+    /// Like [`compare_exchange`](Self::compare_exchange), but may fail
+    /// spuriously.
+    pub fn compare_exchange_weak(
+        &self,
+        current: TaggedPtr<T>,
+        new: TaggedPtr<T>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        self.raw
+            .compare_exchange_weak(current.into_raw(), new.into_raw(), success, failure)
+            .map(TaggedPtr::from_raw)
+            .map_err(TaggedPtr::from_raw)
+    }
+}
+
+impl<T> Default for AtomicTaggedPtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> fmt::Debug for AtomicTaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.load(Ordering::Relaxed).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    #[test]
+    fn test_tagged_cas_rejects_reused_pointer() {
+        let node = Box::into_raw(Box::new(7u64));
+        let head = AtomicTaggedPtr::new(node);
+
+        // A reader snapshots the head...
+        let stale = head.load(Ordering::Acquire);
+        assert_eq!(stale.ptr(), node);
+        assert_eq!(stale.tag(), 0);
+
+        // ...and while it stalls the node is popped and pushed back
+        let popped = head.load(Ordering::Acquire);
+        head.store(popped.advance(core::ptr::null_mut()), Ordering::Release);
+        let empty = head.load(Ordering::Acquire);
+        assert!(empty.is_null());
+        head.store(empty.advance(node), Ordering::Release);
+
+        let current = head.load(Ordering::Acquire);
+        assert_eq!(current.ptr(), node);
+        assert_eq!(current.tag(), 2);
+        assert!(head
+            .compare_exchange(stale, stale.advance(core::ptr::null_mut()), Ordering::AcqRel, Ordering::Acquire)
+            .is_err());
+
+        // Tags wrap within their bits
+        let wrapped = TaggedPtr::new(node, TAG_MASK).advance(node);
+        assert_eq!((wrapped.ptr(), wrapped.tag()), (node, 0));
+
+        drop(unsafe { Box::from_raw(node) });
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_tagged_higher_half_pointer() {
+        let kernel = 0xffff_8000_0000_1000usize as *mut u8;
+        let tagged = TaggedPtr::new(kernel, 0xbeef);
+        assert_eq!(tagged.ptr(), kernel);
+        assert_eq!(tagged.tag(), 0xbeef);
+    }
+}
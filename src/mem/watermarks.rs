@@ -0,0 +1,213 @@
+//! Stack high-water mark history and size class recommendations.
+//!
+//! When a named thread exits, the peak stack usage measured for it is folded
+//! into a per-name history in [`STACK_WATERMARKS`]. A [`WatermarkStore`]
+//! registered with [`set_watermark_store`] carries the history across runs:
+//! it is loaded when the store is registered and written back by
+//! [`WatermarkHistory::persist`]. From the history,
+//! [`StackPool::recommendations`](super::StackPool::recommendations)
+//! suggests the smallest size class that fits each name's worst observed
+//! peak with [`HEADROOM_PERCENT`] to spare, so stacks sized by guesswork can
+//! be brought down to what the threads actually use.
+//!
+//! Threads are keyed by the name they were given, so the `worker`,
+//! `worker-2`, ... threads of one builder share a history.
+//!
+//! ```ignore
+//! set_watermark_store(&FLASH_STORE);
+//! run_workload();
+//! STACK_WATERMARKS.persist();
+//! for advice in StackPool::recommendations() {
+//!     if advice.is_over_provisioned() {
+//!         // shrink advice.name's stacks to advice.recommended
+//!     }
+//! }
+//! ```
+
+use super::StackSizeClass;
+use crate::sync::SpinLockIrqSave;
+extern crate alloc;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+/// Headroom kept above the highest observed peak, in percent.
+pub const HEADROOM_PERCENT: usize = 25;
+
+/// Stack usage history of the threads with one name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatermarkRecord {
+    /// Name the threads were given
+    pub name: String,
+    /// Highest stack usage measured across all runs, in bytes
+    pub peak: usize,
+    /// Stack size the most recent thread was given, in bytes
+    pub provisioned: usize,
+    /// Number of exited threads the record covers
+    pub samples: u64,
+}
+
+/// Persistent storage for the watermark history, e.g. a flash sector or a
+/// file on a host.
+pub trait WatermarkStore: Send + Sync {
+    /// Load the history saved by an earlier run.
+    fn load(&self) -> Vec<WatermarkRecord>;
+
+    /// Save the history, replacing what was saved before.
+    fn save(&self, records: &[WatermarkRecord]);
+}
+
+/// Stack size advice for the threads with one name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackRecommendation {
+    /// Name the threads were given
+    pub name: String,
+    /// Size class of the stacks the threads were given, if it is one
+    pub current: Option<StackSizeClass>,
+    /// Smallest size class that fits the peak with headroom
+    pub recommended: StackSizeClass,
+    /// Highest stack usage measured, in bytes
+    pub peak: usize,
+    /// Number of exited threads the advice is based on
+    pub samples: u64,
+}
+
+impl StackRecommendation {
+    /// Check whether the threads were given a larger stack than they need.
+    pub fn is_over_provisioned(&self) -> bool {
+        self.current.is_some_and(|current| current.size() > self.recommended.size())
+    }
+
+    /// Check whether the threads ran closer to the end of their stack than
+    /// the headroom allows.
+    pub fn is_under_provisioned(&self) -> bool {
+        self.current.map_or(true, |current| current.size() < self.recommended.size())
+    }
+}
+
+/// Per-name record of stack high-water marks.
+pub struct WatermarkHistory {
+    records: SpinLockIrqSave<BTreeMap<String, WatermarkRecord>>,
+}
+
+impl WatermarkHistory {
+    const fn new() -> Self {
+        Self {
+            records: SpinLockIrqSave::new(BTreeMap::new()),
+        }
+    }
+
+    /// Fold the peak stack usage of an exited thread into the history of
+    /// `name`.
+    pub fn record(&self, name: &str, peak: usize, provisioned: usize) {
+        let mut records = self.records.lock();
+        let record = records.entry(String::from(name)).or_insert_with(|| WatermarkRecord {
+            name: String::from(name),
+            peak: 0,
+            provisioned,
+            samples: 0,
+        });
+        record.peak = record.peak.max(peak);
+        record.provisioned = provisioned;
+        record.samples += 1;
+    }
+
+    /// Merge records saved by an earlier run into the history.
+    pub fn merge(&self, saved: Vec<WatermarkRecord>) {
+        let mut records = self.records.lock();
+        for saved in saved {
+            match records.get_mut(&saved.name) {
+                Some(record) => {
+                    record.peak = record.peak.max(saved.peak);
+                    record.samples += saved.samples;
+                }
+                None => {
+                    records.insert(saved.name.clone(), saved);
+                }
+            }
+        }
+    }
+
+    /// Get the history of every name.
+    pub fn records(&self) -> Vec<WatermarkRecord> {
+        self.records.lock().values().cloned().collect()
+    }
+
+    /// Write the history to the registered store, if any.
+    pub fn persist(&self) {
+        if let Some(store) = watermark_store() {
+            store.save(&self.records());
+        }
+    }
+
+    /// Suggest a stack size class for every name with a measured peak.
+    pub fn recommendations(&self) -> Vec<StackRecommendation> {
+        self.records
+            .lock()
+            .values()
+            .filter(|record| record.peak > 0)
+            .map(|record| {
+                let needed = record.peak + record.peak * HEADROOM_PERCENT / 100;
+                StackRecommendation {
+                    name: record.name.clone(),
+                    current: StackSizeClass::for_size(record.provisioned)
+                        .filter(|class| class.size() == record.provisioned),
+                    recommended: StackSizeClass::for_size(needed).unwrap_or(StackSizeClass::ExtraLarge),
+                    peak: record.peak,
+                    samples: record.samples,
+                }
+            })
+            .collect()
+    }
+}
+
+/// History of the stack high-water marks of all named threads.
+pub static STACK_WATERMARKS: WatermarkHistory = WatermarkHistory::new();
+
+static WATERMARK_STORE: spin::Mutex<Option<&'static dyn WatermarkStore>> = spin::Mutex::new(None);
+
+/// Register the store that keeps the watermark history across runs, and
+/// merge the history it holds.
+pub fn set_watermark_store(store: &'static dyn WatermarkStore) {
+    *WATERMARK_STORE.lock() = Some(store);
+    STACK_WATERMARKS.merge(store.load());
+}
+
+/// Get the registered watermark store, if any.
+pub fn watermark_store() -> Option<&'static dyn WatermarkStore> {
+    *WATERMARK_STORE.lock()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommendations_from_history() {
+        let history = WatermarkHistory::new();
+        // A previous run saw the sensor thread reach 5 KiB
+        history.merge(alloc::vec![WatermarkRecord {
+            name: String::from("sensor"),
+            peak: 5 * 1024,
+            provisioned: 65536,
+            samples: 3,
+        }]);
+        history.record("sensor", 2 * 1024, 65536);
+        history.record("logger", 3 * 1024, 16384);
+        history.record("idle", 0, 4096);
+
+        let advice = history.recommendations();
+        assert_eq!(advice.len(), 2);
+
+        // Peaks with headroom: 3.75 KiB fits a small stack
+        let logger = &advice[0];
+        assert_eq!(logger.name, "logger");
+        assert_eq!(logger.recommended, StackSizeClass::Small);
+        assert!(logger.is_over_provisioned());
+
+        // The worst peak across runs counts: 6.25 KiB needs a medium stack
+        let sensor = &advice[1];
+        assert_eq!((sensor.peak, sensor.samples), (5 * 1024, 4));
+        assert_eq!(sensor.current, Some(StackSizeClass::Large));
+        assert_eq!(sensor.recommended, StackSizeClass::Medium);
+        assert!(!sensor.is_under_provisioned());
+    }
+}
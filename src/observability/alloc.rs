@@ -0,0 +1,215 @@
+//! Heap accounting through a global allocator wrapper.
+//!
+//! [`TrackingAllocator`] wraps another [`GlobalAlloc`] and attributes every
+//! heap allocation to the thread running on the current CPU. This feeds the
+//! per-thread memory metrics and the resource limiter's memory quotas
+//! automatically, instead of relying on manual
+//! [`Thread::record_memory_allocation`](crate::thread_new::Thread::record_memory_allocation)
+//! calls. It is opt-in:
+//!
+//! ```ignore
+//! use preemptive_threads::observability::alloc::TrackingAllocator;
+//!
+//! #[global_allocator]
+//! static HEAP: TrackingAllocator<MyHeap> = TrackingAllocator::new(MyHeap::new());
+//! ```
+//!
+//! Each block carries a small header naming the thread that allocated it,
+//! so memory freed by another thread is credited back to its owner.
+//! Allocations that would exceed a hard memory quota fail. Allocations made
+//! with no current thread, or by the accounting code itself, are passed
+//! through unattributed.
+
+use super::metrics::GLOBAL_METRICS;
+use super::resource_limits::{ResourceType, GLOBAL_RESOURCE_LIMITER};
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::thread_new::{percpu, ThreadId};
+use crate::time::PreemptGuard;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use portable_atomic::{AtomicBool, Ordering};
+
+/// Size of the owner header in front of each block.
+const HEADER: usize = size_of::<u64>();
+
+/// Owner recorded for unattributed blocks.
+const NO_OWNER: u64 = 0;
+
+/// Set while a CPU is running accounting code, so that allocations made by
+/// the metrics and limiter themselves are not tracked recursively.
+static IN_ACCOUNTING: [AtomicBool; MAX_CPUS] = [IDLE; MAX_CPUS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IDLE: AtomicBool = AtomicBool::new(false);
+
+/// Global allocator wrapper that attributes heap usage to threads.
+pub struct TrackingAllocator<A> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Wrap `inner`.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    /// Get the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+/// Layout of the block including its header, and the offset of the
+/// caller's data within it.
+fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = layout.align().max(HEADER);
+    let size = layout.size().checked_add(offset)?;
+    let align = layout.align().max(align_of::<u64>());
+    Layout::from_size_align(size, align).ok().map(|outer| (outer, offset))
+}
+
+/// Run `f` with accounting marked active on this CPU.
+///
+/// Returns `None` without running `f` if accounting is already active,
+/// i.e. the allocation comes from the accounting code itself.
+fn with_accounting<R>(f: impl FnOnce() -> R) -> Option<R> {
+    // Stay on this CPU so the flag we set is the flag we clear
+    let _preempt = PreemptGuard::enter();
+    let flag = &IN_ACCOUNTING[cpu_id()];
+    if flag.swap(true, Ordering::Acquire) {
+        return None;
+    }
+    let result = f();
+    flag.store(false, Ordering::Release);
+    Some(result)
+}
+
+impl<A: GlobalAlloc> TrackingAllocator<A> {
+    /// Allocate a block with an owner header.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as [`GlobalAlloc::alloc`].
+    unsafe fn allocate(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let Some((outer, offset)) = outer_layout(layout) else {
+            return core::ptr::null_mut();
+        };
+
+        let size = layout.size() as u64;
+        let owner = percpu::current_id().and_then(|thread_id| {
+            with_accounting(|| {
+                GLOBAL_RESOURCE_LIMITER
+                    .check_resource_limit(thread_id, ResourceType::Memory, size)
+                    .map(|()| thread_id)
+            })
+        });
+
+        let Ok(owner) = owner.transpose() else {
+            // Over a hard memory quota
+            return core::ptr::null_mut();
+        };
+
+        // Safety: `outer` has non-zero size because it includes the header
+        let base = unsafe {
+            if zeroed {
+                self.inner.alloc_zeroed(outer)
+            } else {
+                self.inner.alloc(outer)
+            }
+        };
+        if base.is_null() {
+            return base;
+        }
+
+        // Only blocks that were actually charged name an owner
+        let owner = match owner {
+            Some(thread_id) if with_accounting(|| record_allocation(thread_id, size)).is_some() => {
+                thread_id.as_u64()
+            }
+            _ => NO_OWNER,
+        };
+
+        // Safety: `offset` lies within the block and leaves room for the header
+        unsafe { Self::finish(base, offset, owner) }
+    }
+
+    /// Write the owner header and return the caller's pointer.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be a block of at least `offset` bytes allocated with an
+    /// alignment of at least `align_of::<u64>()`, and `offset` a multiple of 8.
+    unsafe fn finish(base: *mut u8, offset: usize, owner: u64) -> *mut u8 {
+        unsafe {
+            let ptr = base.add(offset);
+            (ptr.sub(HEADER) as *mut u64).write(owner);
+            ptr
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.allocate(layout, false) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.allocate(layout, true) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Allocation with this layout succeeded, so the outer layout is valid
+        let Some((outer, offset)) = outer_layout(layout) else {
+            return;
+        };
+
+        // Safety: `ptr` came from `allocate`, which wrote the header in
+        // front of it and handed out `base + offset`
+        let (base, owner) = unsafe {
+            (ptr.sub(offset), (ptr.sub(HEADER) as *const u64).read())
+        };
+
+        if owner != NO_OWNER {
+            let thread_id = ThreadId::new(owner);
+            let _ = with_accounting(|| record_deallocation(thread_id, layout.size() as u64));
+        }
+
+        unsafe { self.inner.dealloc(base, outer) }
+    }
+}
+
+/// Charge a heap allocation of `size` bytes to `thread_id`.
+///
+/// Updates the thread's metrics and its usage against memory quotas.
+pub fn record_allocation(thread_id: ThreadId, size: u64) {
+    GLOBAL_RESOURCE_LIMITER.record_allocation(thread_id, size);
+    GLOBAL_METRICS.record_memory_allocation(thread_id, size);
+}
+
+/// Credit a freed heap block of `size` bytes back to `thread_id`.
+pub fn record_deallocation(thread_id: ThreadId, size: u64) {
+    GLOBAL_RESOURCE_LIMITER.record_deallocation(thread_id, size);
+    GLOBAL_METRICS.record_memory_deallocation(thread_id, size);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::System;
+
+    #[test]
+    fn test_tracking_allocator_alignment() {
+        let heap = TrackingAllocator::new(System);
+
+        for &(size, align) in &[(1, 1), (24, 8), (3, 16), (100, 64), (4096, 4096)] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            unsafe {
+                let ptr = heap.alloc_zeroed(layout);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+                assert!(core::slice::from_raw_parts(ptr, size).iter().all(|&b| b == 0));
+                heap.dealloc(ptr, layout);
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Profiling of preemption-off and interrupts-off windows.
+//!
+//! Worst-case scheduling latency is bounded by the longest stretch a CPU
+//! spends unable to switch threads. Every outermost
+//! [`PreemptGuard`](crate::time::PreemptGuard) and every
+//! [`IrqGuard`](crate::time::IrqGuard) that actually disabled interrupts is
+//! timed from creation to drop. Both constructors are `#[track_caller]`,
+//! as is [`SpinLockIrqSave::lock`](crate::sync::SpinLockIrqSave::lock), so
+//! each window is attributed to the code that opened it rather than to the
+//! guard. Nested preemption-disable guards form one window, attributed to
+//! the outermost.
+//!
+//! [`longest_windows`] lists the [`TOP_WINDOWS`] longest windows seen,
+//! [`worst_sites`] the call sites with the longest windows, and
+//! [`format_report`] renders both:
+//!
+//! ```ignore
+//! critical::reset_critical_sections();
+//! run_workload();
+//! log::info!("{}", critical::format_report());
+//! ```
+//!
+//! Windows ending while another CPU is recording are dropped rather than
+//! waited for, and counted in [`dropped_windows`].
+
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::thread_new::{percpu, ThreadId};
+use crate::time::cpu_clock;
+use core::fmt::{self, Write};
+use core::panic::Location;
+use portable_atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+extern crate alloc;
+use alloc::{string::String, vec::Vec};
+
+/// Number of longest windows kept.
+pub const TOP_WINDOWS: usize = 10;
+
+/// Most call sites tracked at once; windows opened at further ones only
+/// count towards the longest windows.
+pub const MAX_SITES: usize = 64;
+
+/// What a critical section disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SectionKind {
+    /// Preemption, through a `PreemptGuard`
+    PreemptOff,
+    /// Interrupts, through an `IrqGuard`
+    IrqOff,
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionKind::PreemptOff => f.write_str("preempt-off"),
+            SectionKind::IrqOff => f.write_str("irq-off"),
+        }
+    }
+}
+
+/// One timed critical section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CriticalWindow {
+    pub kind: SectionKind,
+    /// Where the guard was created
+    pub site: &'static Location<'static>,
+    pub duration_ns: u64,
+    pub cpu: usize,
+    /// Thread running on the CPU, if any
+    pub thread: Option<ThreadId>,
+}
+
+/// Windows opened at one call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiteStats {
+    pub kind: SectionKind,
+    pub site: &'static Location<'static>,
+    /// Number of windows
+    pub count: u64,
+    pub total_ns: u64,
+    pub max_ns: u64,
+}
+
+impl SiteStats {
+    /// Get the mean window length in nanoseconds.
+    pub fn mean_ns(&self) -> u64 {
+        self.total_ns.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+struct Profile {
+    /// Longest windows, longest first
+    top: [Option<CriticalWindow>; TOP_WINDOWS],
+    sites: [Option<SiteStats>; MAX_SITES],
+}
+
+static PROFILE: spin::Mutex<Profile> = spin::Mutex::new(Profile {
+    top: [None; TOP_WINDOWS],
+    sites: [None; MAX_SITES],
+});
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// [`PREEMPT_START`] of a CPU whose window is not being timed.
+const NOT_TIMED: u64 = u64::MAX;
+
+/// Per-CPU start of the current preemption-off window.
+static PREEMPT_START: [AtomicU64; MAX_CPUS] = [NOT_TIMED_START; MAX_CPUS];
+
+/// Per-CPU site of the current preemption-off window.
+static PREEMPT_SITE: [AtomicPtr<Location<'static>>; MAX_CPUS] = [NO_SITE; MAX_CPUS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_TIMED_START: AtomicU64 = AtomicU64::new(NOT_TIMED);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SITE: AtomicPtr<Location<'static>> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Turn window timing on or off. It is on by default.
+pub fn set_critical_section_profiling(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Release);
+}
+
+/// Check whether windows are being timed.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Get the start time of a window opening now, or `None` when profiling
+/// is off.
+pub(crate) fn window_start() -> Option<u64> {
+    is_enabled().then(|| cpu_clock().as_nanos())
+}
+
+/// Start timing the current CPU's preemption-off window, opened at `site`.
+pub(crate) fn preempt_off(site: &'static Location<'static>) {
+    let Some(start) = window_start() else {
+        return;
+    };
+    let cpu = cpu_id();
+    PREEMPT_SITE[cpu].store(site as *const Location<'static> as *mut _, Ordering::Relaxed);
+    PREEMPT_START[cpu].store(start, Ordering::Release);
+}
+
+/// Close the current CPU's preemption-off window.
+pub(crate) fn preempt_on() {
+    let cpu = cpu_id();
+    let start = PREEMPT_START[cpu].swap(NOT_TIMED, Ordering::AcqRel);
+    let site = PREEMPT_SITE[cpu].load(Ordering::Relaxed);
+    if start == NOT_TIMED || site.is_null() {
+        return;
+    }
+    // Safety: only `preempt_off` stores here, from a `&'static Location`
+    let site = unsafe { &*site };
+    record(SectionKind::PreemptOff, site, cpu_clock().as_nanos().saturating_sub(start));
+}
+
+/// Close an interrupts-off window opened at `site` at `start`.
+pub(crate) fn irq_on(site: &'static Location<'static>, start: u64) {
+    record(SectionKind::IrqOff, site, cpu_clock().as_nanos().saturating_sub(start));
+}
+
+/// Account a window of `duration_ns`.
+fn record(kind: SectionKind, site: &'static Location<'static>, duration_ns: u64) {
+    // Never wait: this runs with preemption or interrupts off, possibly
+    // from an interrupt that hit while this CPU was recording
+    match PROFILE.try_lock() {
+        Some(mut profile) => profile.record(kind, site, duration_ns),
+        None => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Profile {
+    fn record(&mut self, kind: SectionKind, site: &'static Location<'static>, duration_ns: u64) {
+        let same_site = |stats: &SiteStats| stats.kind == kind && stats.site == site;
+        if let Some(stats) = self.sites.iter_mut().flatten().find(|stats| same_site(stats)) {
+            stats.count += 1;
+            stats.total_ns += duration_ns;
+            stats.max_ns = stats.max_ns.max(duration_ns);
+        } else if let Some(slot) = self.sites.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(SiteStats {
+                kind,
+                site,
+                count: 1,
+                total_ns: duration_ns,
+                max_ns: duration_ns,
+            });
+        }
+
+        let position = self
+            .top
+            .iter()
+            .position(|window| !matches!(window, Some(window) if window.duration_ns >= duration_ns));
+        if let Some(position) = position {
+            self.top.copy_within(position..TOP_WINDOWS - 1, position + 1);
+            self.top[position] = Some(CriticalWindow {
+                kind,
+                site,
+                duration_ns,
+                cpu: cpu_id(),
+                thread: percpu::current_id(),
+            });
+        }
+    }
+}
+
+/// Get the longest windows seen, longest first.
+pub fn longest_windows() -> Vec<CriticalWindow> {
+    PROFILE.lock().top.iter().flatten().copied().collect()
+}
+
+/// Get up to `limit` call sites, the one with the longest window first.
+pub fn worst_sites(limit: usize) -> Vec<SiteStats> {
+    let mut sites: Vec<_> = PROFILE.lock().sites.iter().flatten().copied().collect();
+    sites.sort_by(|a, b| b.max_ns.cmp(&a.max_ns).then(b.total_ns.cmp(&a.total_ns)));
+    sites.truncate(limit);
+    sites
+}
+
+/// Get the number of windows dropped because another CPU was recording.
+pub fn dropped_windows() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Forget all windows.
+pub fn reset_critical_sections() {
+    let mut profile = PROFILE.lock();
+    profile.top = [None; TOP_WINDOWS];
+    profile.sites = [None; MAX_SITES];
+    DROPPED.store(0, Ordering::Relaxed);
+}
+
+/// Render the longest windows and the worst call sites, one per line.
+pub fn format_report() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "longest critical sections:");
+    for (rank, window) in longest_windows().iter().enumerate() {
+        let _ = write!(
+            out,
+            "{:>3}. {:>10}ns {:<11} {} cpu {}",
+            rank + 1,
+            window.duration_ns,
+            window.kind,
+            window.site,
+            window.cpu
+        );
+        if let Some(thread) = window.thread {
+            let _ = write!(out, " thread {}", thread);
+        }
+        out.push('\n');
+    }
+
+    let _ = writeln!(out, "worst call sites:");
+    for stats in worst_sites(TOP_WINDOWS) {
+        let _ = writeln!(
+            out,
+            "  {:>10}ns max {:>10}ns mean {:>8} windows {:<11} {}",
+            stats.max_ns,
+            stats.mean_ns(),
+            stats.count,
+            stats.kind,
+            stats.site
+        );
+    }
+
+    let dropped = dropped_windows();
+    if dropped > 0 {
+        let _ = writeln!(out, "{} windows dropped", dropped);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_windows_and_sites() {
+        let here = Location::caller();
+        let there = Location::caller();
+
+        // Other tests' guards record too, so hold the lock instead of
+        // risking dropped windows, and only look at these sites
+        {
+            let mut profile = PROFILE.lock();
+            for duration_ns in 1..=12 {
+                profile.record(SectionKind::IrqOff, here, 1_000_000_000 + duration_ns);
+            }
+            profile.record(SectionKind::PreemptOff, there, 2_000_000_000);
+        }
+
+        let top = longest_windows();
+        assert_eq!(top.len(), TOP_WINDOWS);
+        assert_eq!((top[0].kind, top[0].site, top[0].duration_ns), (SectionKind::PreemptOff, there, 2_000_000_000));
+        assert_eq!(top[1].duration_ns, 1_000_000_012);
+        assert_eq!(top[9].duration_ns, 1_000_000_004);
+
+        let sites = worst_sites(usize::MAX);
+        let stats = sites.iter().find(|stats| stats.site == here).unwrap();
+        assert_eq!((stats.kind, stats.count, stats.max_ns), (SectionKind::IrqOff, 12, 1_000_000_012));
+        assert_eq!(stats.mean_ns(), 1_000_000_006);
+        assert!(format_report().contains(&alloc::format!("{}", there)));
+    }
+}
@@ -0,0 +1,550 @@
+//! JSON and CBOR export of health and resource reports.
+//!
+//! Constrained devices can publish [`SystemHealth`], [`ResourceUsage`] and
+//! [`MetricsReport`] to MQTT or CoAP pipelines directly from a
+//! [`crate::io::Write`] sink. Both formats share one schema, aligned with
+//! the security audit exporter's JSON:
+//!
+//! - `timestamp` fields are monotonic nanoseconds
+//! - severities are reported as `level` and descriptions as `event`
+//! - thread IDs are integers under `thread_id` (or `thread_ids` for lists)
+//! - enums are their variant names, e.g. `"Healthy"`
+//! - non-finite floats become `null`
+//!
+//! CBOR output uses indefinite-length maps and arrays so that reports can
+//! be streamed without buffering.
+
+use super::health::{ComponentHealth, HealthIssue, HealthTrend, SystemHealth};
+use super::inversion::PriorityInversion;
+use super::metrics::{DeviceIoWait, MetricsReport, SystemMetricsSnapshot, ThreadMetrics};
+use super::resource_limits::ResourceUsage;
+use crate::io::{IoError, Write};
+extern crate alloc;
+use alloc::format;
+
+/// Structured sink that reports are walked into.
+///
+/// Implemented by [`JsonEncoder`] and [`CborEncoder`]. Object members are
+/// written as a [`key`](ReportEncoder::key) followed by one value.
+pub trait ReportEncoder {
+    /// Open an object.
+    fn begin_object(&mut self) -> Result<(), IoError>;
+    /// Close the innermost object.
+    fn end_object(&mut self) -> Result<(), IoError>;
+    /// Open an array.
+    fn begin_array(&mut self) -> Result<(), IoError>;
+    /// Close the innermost array.
+    fn end_array(&mut self) -> Result<(), IoError>;
+    /// Write the key of the next object member.
+    fn key(&mut self, key: &str) -> Result<(), IoError>;
+    /// Write an unsigned integer.
+    fn uint(&mut self, value: u64) -> Result<(), IoError>;
+    /// Write a floating-point number.
+    fn float(&mut self, value: f64) -> Result<(), IoError>;
+    /// Write a string.
+    fn string(&mut self, value: &str) -> Result<(), IoError>;
+    /// Write a null value.
+    fn null(&mut self) -> Result<(), IoError>;
+}
+
+/// Reports that can be exported through a [`ReportEncoder`].
+pub trait Export {
+    /// Walk this report into `enc`.
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError>;
+}
+
+/// Compact JSON writer.
+pub struct JsonEncoder<W: Write> {
+    writer: W,
+    need_comma: bool,
+}
+
+impl<W: Write> JsonEncoder<W> {
+    /// Create an encoder writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer, need_comma: false }
+    }
+
+    /// Consume the encoder, returning the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn separator(&mut self) -> Result<(), IoError> {
+        if self.need_comma {
+            self.writer.write_all(b",")?;
+        }
+        self.need_comma = true;
+        Ok(())
+    }
+
+    fn quoted(&mut self, value: &str) -> Result<(), IoError> {
+        self.writer.write_all(b"\"")?;
+        let bytes = value.as_bytes();
+        let mut start = 0;
+        for (i, &b) in bytes.iter().enumerate() {
+            let escape: &[u8] = match b {
+                b'"' => b"\\\"",
+                b'\\' => b"\\\\",
+                b'\n' => b"\\n",
+                b'\r' => b"\\r",
+                b'\t' => b"\\t",
+                0..=0x1f => {
+                    self.writer.write_all(&bytes[start..i])?;
+                    const HEX: &[u8; 16] = b"0123456789abcdef";
+                    self.writer.write_all(&[
+                        b'\\', b'u', b'0', b'0', HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize],
+                    ])?;
+                    start = i + 1;
+                    continue;
+                }
+                _ => continue,
+            };
+            self.writer.write_all(&bytes[start..i])?;
+            self.writer.write_all(escape)?;
+            start = i + 1;
+        }
+        self.writer.write_all(&bytes[start..])?;
+        self.writer.write_all(b"\"")
+    }
+}
+
+impl<W: Write> ReportEncoder for JsonEncoder<W> {
+    fn begin_object(&mut self) -> Result<(), IoError> {
+        self.separator()?;
+        self.need_comma = false;
+        self.writer.write_all(b"{")
+    }
+
+    fn end_object(&mut self) -> Result<(), IoError> {
+        self.need_comma = true;
+        self.writer.write_all(b"}")
+    }
+
+    fn begin_array(&mut self) -> Result<(), IoError> {
+        self.separator()?;
+        self.need_comma = false;
+        self.writer.write_all(b"[")
+    }
+
+    fn end_array(&mut self) -> Result<(), IoError> {
+        self.need_comma = true;
+        self.writer.write_all(b"]")
+    }
+
+    fn key(&mut self, key: &str) -> Result<(), IoError> {
+        self.separator()?;
+        self.quoted(key)?;
+        self.need_comma = false;
+        self.writer.write_all(b":")
+    }
+
+    fn uint(&mut self, value: u64) -> Result<(), IoError> {
+        self.separator()?;
+        let mut digits = [0u8; 20];
+        let mut pos = digits.len();
+        let mut rest = value;
+        loop {
+            pos -= 1;
+            digits[pos] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        self.writer.write_all(&digits[pos..])
+    }
+
+    fn float(&mut self, value: f64) -> Result<(), IoError> {
+        if !value.is_finite() {
+            return self.null();
+        }
+        self.separator()?;
+        self.writer.write_all(format!("{}", value).as_bytes())
+    }
+
+    fn string(&mut self, value: &str) -> Result<(), IoError> {
+        self.separator()?;
+        self.quoted(value)
+    }
+
+    fn null(&mut self) -> Result<(), IoError> {
+        self.separator()?;
+        self.writer.write_all(b"null")
+    }
+}
+
+/// CBOR (RFC 8949) writer.
+pub struct CborEncoder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CborEncoder<W> {
+    /// Create an encoder writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Consume the encoder, returning the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write a major type with its argument in the shortest form.
+    fn head(&mut self, major: u8, value: u64) -> Result<(), IoError> {
+        let major = major << 5;
+        if value < 24 {
+            self.writer.write_u8(major | value as u8)
+        } else if value <= u8::MAX as u64 {
+            self.writer.write_all(&[major | 24, value as u8])
+        } else if value <= u16::MAX as u64 {
+            self.writer.write_u8(major | 25)?;
+            self.writer.write_all(&(value as u16).to_be_bytes())
+        } else if value <= u32::MAX as u64 {
+            self.writer.write_u8(major | 26)?;
+            self.writer.write_all(&(value as u32).to_be_bytes())
+        } else {
+            self.writer.write_u8(major | 27)?;
+            self.writer.write_all(&value.to_be_bytes())
+        }
+    }
+}
+
+impl<W: Write> ReportEncoder for CborEncoder<W> {
+    fn begin_object(&mut self) -> Result<(), IoError> {
+        self.writer.write_u8(0xbf)
+    }
+
+    fn end_object(&mut self) -> Result<(), IoError> {
+        self.writer.write_u8(0xff)
+    }
+
+    fn begin_array(&mut self) -> Result<(), IoError> {
+        self.writer.write_u8(0x9f)
+    }
+
+    fn end_array(&mut self) -> Result<(), IoError> {
+        self.writer.write_u8(0xff)
+    }
+
+    fn key(&mut self, key: &str) -> Result<(), IoError> {
+        self.string(key)
+    }
+
+    fn uint(&mut self, value: u64) -> Result<(), IoError> {
+        self.head(0, value)
+    }
+
+    fn float(&mut self, value: f64) -> Result<(), IoError> {
+        if !value.is_finite() {
+            return self.null();
+        }
+        self.writer.write_u8(0xfb)?;
+        self.writer.write_all(&value.to_bits().to_be_bytes())
+    }
+
+    fn string(&mut self, value: &str) -> Result<(), IoError> {
+        self.head(3, value.len() as u64)?;
+        self.writer.write_all(value.as_bytes())
+    }
+
+    fn null(&mut self) -> Result<(), IoError> {
+        self.writer.write_u8(0xf6)
+    }
+}
+
+/// Write `key: value` for an unsigned integer.
+fn field_uint<E: ReportEncoder>(enc: &mut E, key: &str, value: u64) -> Result<(), IoError> {
+    enc.key(key)?;
+    enc.uint(value)
+}
+
+/// Write `key: value` for a float.
+fn field_float<E: ReportEncoder>(enc: &mut E, key: &str, value: f64) -> Result<(), IoError> {
+    enc.key(key)?;
+    enc.float(value)
+}
+
+/// Write `key: value` for a string.
+fn field_str<E: ReportEncoder>(enc: &mut E, key: &str, value: &str) -> Result<(), IoError> {
+    enc.key(key)?;
+    enc.string(value)
+}
+
+/// Write `key: "Variant"` for an enum.
+fn field_enum<E: ReportEncoder, T: core::fmt::Debug>(
+    enc: &mut E,
+    key: &str,
+    value: &T,
+) -> Result<(), IoError> {
+    field_str(enc, key, &format!("{:?}", value))
+}
+
+impl Export for HealthIssue {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.detected_at.as_nanos())?;
+        field_enum(enc, "level", &self.severity)?;
+        field_enum(enc, "category", &self.category)?;
+        field_str(enc, "component", &self.component)?;
+        field_str(enc, "event", &self.description)?;
+        enc.key("thread_ids")?;
+        enc.begin_array()?;
+        for thread in &self.affected_threads {
+            enc.uint(thread.as_u64())?;
+        }
+        enc.end_array()?;
+        enc.key("context")?;
+        enc.begin_object()?;
+        for (key, value) in &self.context {
+            field_str(enc, key, value)?;
+        }
+        enc.end_object()?;
+        enc.key("remediation")?;
+        match &self.remediation {
+            Some(remediation) => enc.string(remediation)?,
+            None => enc.null()?,
+        }
+        enc.end_object()
+    }
+}
+
+impl Export for ComponentHealth {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        let metrics = &self.metrics;
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.last_check.as_nanos())?;
+        field_enum(enc, "status", &self.status)?;
+        field_float(enc, "resource_utilization", metrics.resource_utilization as f64)?;
+        field_float(enc, "error_rate", metrics.error_rate as f64)?;
+        field_uint(enc, "avg_response_time_us", metrics.avg_response_time_us)?;
+        field_float(enc, "success_rate", metrics.success_rate as f64)?;
+        field_uint(enc, "queue_depth", metrics.queue_depth as u64)?;
+        enc.key("custom_metrics")?;
+        enc.begin_object()?;
+        for (key, value) in &metrics.custom_metrics {
+            field_float(enc, key, *value)?;
+        }
+        enc.end_object()?;
+        export_list(enc, "issues", &self.issues)?;
+        enc.end_object()
+    }
+}
+
+impl Export for HealthTrend {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_enum(enc, "direction", &self.direction)?;
+        field_float(enc, "change_rate", self.change_rate as f64)?;
+        field_float(enc, "confidence", self.confidence as f64)?;
+        enc.key("history")?;
+        enc.begin_array()?;
+        for entry in &self.history {
+            enc.begin_object()?;
+            field_uint(enc, "timestamp", entry.timestamp.as_nanos())?;
+            field_uint(enc, "health_score", entry.health_score as u64)?;
+            field_uint(enc, "active_issues", entry.active_issues as u64)?;
+            enc.end_object()?;
+        }
+        enc.end_array()?;
+        enc.end_object()
+    }
+}
+
+impl Export for SystemHealth {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.timestamp.as_nanos())?;
+        field_enum(enc, "status", &self.overall_status)?;
+        field_uint(enc, "health_score", self.overall_status.score() as u64)?;
+        field_uint(enc, "uptime_ns", self.uptime.as_nanos())?;
+        enc.key("components")?;
+        enc.begin_object()?;
+        for (name, component) in &self.components {
+            enc.key(name)?;
+            component.export(enc)?;
+        }
+        enc.end_object()?;
+        export_list(enc, "issues", &self.active_issues)?;
+        enc.key("trend")?;
+        self.trend.export(enc)?;
+        enc.end_object()
+    }
+}
+
+impl Export for ResourceUsage {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.last_updated.as_nanos())?;
+        field_uint(enc, "thread_id", self.thread_id.as_u64())?;
+        field_uint(enc, "cpu_time_ns", self.cpu_time_ns)?;
+        field_uint(enc, "memory_usage", self.memory_usage)?;
+        field_uint(enc, "peak_memory_usage", self.peak_memory_usage)?;
+        field_uint(enc, "open_files", self.open_files as u64)?;
+        field_uint(enc, "child_threads", self.child_threads as u64)?;
+        field_uint(enc, "network_connections", self.network_connections as u64)?;
+        field_uint(enc, "disk_io_ops", self.disk_io_ops)?;
+        field_uint(enc, "network_bytes_tx", self.network_bytes_tx)?;
+        field_uint(enc, "network_bytes_rx", self.network_bytes_rx)?;
+        enc.end_object()
+    }
+}
+
+impl Export for SystemMetricsSnapshot {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "threads_created", self.threads_created)?;
+        field_uint(enc, "threads_destroyed", self.threads_destroyed)?;
+        field_uint(enc, "active_threads", self.active_threads)?;
+        field_uint(enc, "total_context_switches", self.total_context_switches)?;
+        field_uint(enc, "total_cpu_time_ns", self.total_cpu_time_ns)?;
+        field_float(enc, "cpu_utilization", self.cpu_utilization)?;
+        field_float(enc, "context_switches_per_second", self.context_switches_per_second)?;
+        field_uint(enc, "current_memory_usage", self.current_memory_usage)?;
+        field_uint(enc, "peak_memory_usage", self.peak_memory_usage)?;
+        field_uint(enc, "preempt_disables", self.preempt_disables)?;
+        field_uint(enc, "max_preempt_depth", self.max_preempt_depth)?;
+        field_uint(enc, "might_sleep_violations", self.might_sleep_violations)?;
+        field_uint(enc, "priority_inversions", self.priority_inversions)?;
+        field_uint(enc, "total_io_wait_ns", self.total_io_wait_ns)?;
+        field_uint(enc, "total_blocked_ns", self.total_blocked_ns)?;
+        enc.end_object()
+    }
+}
+
+impl Export for ThreadMetrics {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.last_active.as_nanos())?;
+        field_uint(enc, "thread_id", self.thread_id.as_u64())?;
+        field_uint(enc, "created_at", self.created_at.as_nanos())?;
+        field_uint(enc, "cpu_time_ns", self.cpu_time_ns)?;
+        field_uint(enc, "user_time_ns", self.user_time_ns)?;
+        field_uint(enc, "kernel_time_ns", self.kernel_time_ns)?;
+        field_uint(enc, "context_switches", self.context_switches)?;
+        field_uint(enc, "voluntary_yields", self.voluntary_yields)?;
+        field_uint(enc, "involuntary_preemptions", self.involuntary_preemptions)?;
+        field_uint(enc, "peak_stack_usage", self.peak_stack_usage as u64)?;
+        field_uint(enc, "current_stack_usage", self.current_stack_usage as u64)?;
+        field_uint(enc, "page_faults", self.page_faults)?;
+        field_uint(enc, "priority_changes", self.priority_changes as u64)?;
+        field_uint(enc, "memory_allocations", self.memory_allocations)?;
+        field_uint(enc, "memory_deallocations", self.memory_deallocations)?;
+        field_uint(enc, "total_memory_allocated", self.total_memory_allocated)?;
+        field_uint(enc, "current_memory_usage", self.current_memory_usage)?;
+        field_uint(enc, "peak_memory_usage", self.peak_memory_usage)?;
+        field_uint(enc, "cycles", self.cycles)?;
+        field_uint(enc, "instructions", self.instructions)?;
+        field_uint(enc, "cache_misses", self.cache_misses)?;
+        field_uint(enc, "io_wait_ns", self.io_wait_ns)?;
+        field_uint(enc, "blocked_ns", self.blocked_ns)?;
+        enc.end_object()
+    }
+}
+
+impl Export for MetricsReport {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.timestamp.as_nanos())?;
+        enc.key("system")?;
+        self.system.export(enc)?;
+        export_list(enc, "threads", &self.threads)?;
+        export_list(enc, "priority_inversions", &self.priority_inversions)?;
+        export_list(enc, "io_wait_by_device", &self.io_wait_by_device)?;
+        enc.end_object()
+    }
+}
+
+impl Export for DeviceIoWait {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "device", self.device as u64)?;
+        field_uint(enc, "waits", self.waits)?;
+        field_uint(enc, "wait_ns", self.wait_ns)?;
+        field_uint(enc, "max_wait_ns", self.max_wait_ns)?;
+        enc.end_object()
+    }
+}
+
+impl Export for PriorityInversion {
+    fn export<E: ReportEncoder>(&self, enc: &mut E) -> Result<(), IoError> {
+        enc.begin_object()?;
+        field_uint(enc, "timestamp", self.detected_at.as_nanos())?;
+        field_uint(enc, "lock", self.lock as u64)?;
+        field_uint(enc, "waiter", self.waiter.as_u64())?;
+        field_uint(enc, "waiter_priority", self.waiter_priority as u64)?;
+        field_uint(enc, "owner", self.owner.as_u64())?;
+        field_uint(enc, "owner_priority", self.owner_priority as u64)?;
+        field_uint(enc, "waited_ns", self.waited.as_nanos())?;
+        enc.end_object()
+    }
+}
+
+fn export_list<E: ReportEncoder, T: Export>(
+    enc: &mut E,
+    key: &str,
+    items: &[T],
+) -> Result<(), IoError> {
+    enc.key(key)?;
+    enc.begin_array()?;
+    for item in items {
+        item.export(enc)?;
+    }
+    enc.end_array()
+}
+
+/// Adds `to_json`/`to_cbor` helpers to exportable reports.
+macro_rules! impl_export_helpers {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $ty {
+                /// Write this report as compact JSON.
+                pub fn to_json(&self, writer: &mut impl Write) -> Result<(), IoError> {
+                    self.export(&mut JsonEncoder::new(writer))
+                }
+
+                /// Write this report as CBOR.
+                pub fn to_cbor(&self, writer: &mut impl Write) -> Result<(), IoError> {
+                    self.export(&mut CborEncoder::new(writer))
+                }
+            }
+        )+
+    };
+}
+
+impl_export_helpers!(SystemHealth, ResourceUsage, MetricsReport);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_json_encoder_escapes_and_separates() {
+        let mut out = Vec::new();
+        let mut enc = JsonEncoder::new(&mut out);
+        enc.begin_object().unwrap();
+        field_str(&mut enc, "event", "say \"hi\"\n\u{1}").unwrap();
+        enc.key("values").unwrap();
+        enc.begin_array().unwrap();
+        enc.uint(0).unwrap();
+        enc.uint(1234).unwrap();
+        enc.float(f64::NAN).unwrap();
+        enc.end_array().unwrap();
+        enc.end_object().unwrap();
+        assert_eq!(
+            core::str::from_utf8(&out).unwrap(),
+            r#"{"event":"say \"hi\"\n\u0001","values":[0,1234,null]}"#
+        );
+    }
+
+    #[test]
+    fn test_cbor_encoder_heads() {
+        let mut out = Vec::new();
+        let mut enc = CborEncoder::new(&mut out);
+        enc.begin_object().unwrap();
+        field_uint(&mut enc, "n", 500).unwrap();
+        enc.end_object().unwrap();
+        assert_eq!(out, [0xbf, 0x61, b'n', 0x19, 0x01, 0xf4, 0xff]);
+    }
+}
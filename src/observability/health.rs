@@ -6,8 +6,14 @@
 
 use portable_atomic::{AtomicU64, AtomicBool, Ordering};
 use crate::time::{Duration, Instant};
+use crate::thread_new::{registry, ThreadId};
+use crate::sched::Scheduler;
+use super::lock_chain::{render_chain, LOCK_WAIT_GRAPH};
+use super::resource_limits::GLOBAL_RESOURCE_LIMITER;
+use super::wake_latency::{self, SloId, WakeLatencySlo};
+use super::lost_wakeup;
 extern crate alloc;
-use alloc::{vec::Vec, collections::BTreeMap, string::{String, ToString}, boxed::Box};
+use alloc::{vec, vec::Vec, collections::{BTreeMap, BTreeSet}, string::{String, ToString}, boxed::Box, format};
 use spin::Mutex;
 
 /// Overall system health status.
@@ -61,6 +67,21 @@ pub struct SystemHealth {
     pub trend: HealthTrend,
 }
 
+/// Summary of system health, as returned by
+/// [`HealthMonitor::current_status`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthSummary {
+    /// Overall status, taking the thread and resource counts into account
+    pub status: HealthStatus,
+    /// Live threads not deadlocked, named in an active issue or at a quota
+    pub healthy_threads: usize,
+    /// Live threads
+    pub total_threads: usize,
+    /// Largest fraction of a resource quota used by any thread (0.0 when no
+    /// thread has limits)
+    pub resource_usage: f64,
+}
+
 /// Health status of a specific system component.
 #[derive(Debug, Clone)]
 pub struct ComponentHealth {
@@ -121,6 +142,8 @@ pub struct HealthIssue {
     pub detected_at: Instant,
     /// Additional context data
     pub context: BTreeMap<String, String>,
+    /// Threads implicated in the issue
+    pub affected_threads: Vec<ThreadId>,
     /// Suggested remediation action
     pub remediation: Option<String>,
 }
@@ -214,6 +237,8 @@ pub struct HealthThresholds {
     pub max_error_rate: f32,
     /// Maximum acceptable response time (microseconds)
     pub max_response_time_us: u64,
+    /// Maximum time a thread may stay Ready without running (microseconds)
+    pub max_ready_wait_us: u64,
 }
 
 impl Default for HealthMonitorConfig {
@@ -233,6 +258,7 @@ impl Default for HealthMonitorConfig {
                 context_switch_critical_threshold: 50000.0,
                 max_error_rate: 10.0,
                 max_response_time_us: 100000, // 100ms
+                max_ready_wait_us: 500000, // 500ms
             },
         }
     }
@@ -256,6 +282,10 @@ pub struct HealthMonitor {
     last_check_time: Mutex<Instant>,
     /// Health check counter
     check_counter: AtomicU64,
+    /// Set once the wake latency SLO checker is registered
+    slo_checker_registered: AtomicBool,
+    /// Set once the lost wakeup checker is registered
+    lost_wakeup_checker_registered: AtomicBool,
 }
 
 /// Trait for health checkers.
@@ -292,6 +322,7 @@ impl HealthMonitor {
                     context_switch_critical_threshold: 50000.0,
                     max_error_rate: 10.0,
                     max_response_time_us: 100000,
+                    max_ready_wait_us: 500000,
                 },
             }),
             current_health: Mutex::new(SystemHealth {
@@ -313,6 +344,8 @@ impl HealthMonitor {
             system_start_time: now,
             last_check_time: Mutex::new(now),
             check_counter: AtomicU64::new(0),
+            slo_checker_registered: AtomicBool::new(false),
+            lost_wakeup_checker_registered: AtomicBool::new(false),
         }
     }
     
@@ -340,6 +373,8 @@ impl HealthMonitor {
             system_start_time: now,
             last_check_time: Mutex::new(now),
             check_counter: AtomicU64::new(0),
+            slo_checker_registered: AtomicBool::new(false),
+            lost_wakeup_checker_registered: AtomicBool::new(false),
         }
     }
     
@@ -395,6 +430,44 @@ impl HealthMonitor {
         }
     }
     
+    /// Enable starvation detection for the given scheduler.
+    ///
+    /// Registers a checker that uses the scheduler's run-queue introspection
+    /// to flag threads that have been Ready for longer than
+    /// `HealthThresholds::max_ready_wait_us` without running.
+    pub fn monitor_scheduler(&self, scheduler: &'static dyn Scheduler) {
+        let max_wait_us = self.config.lock().thresholds.max_ready_wait_us;
+        
+        self.register_checker(Box::new(StarvationHealthChecker::new(
+            scheduler,
+            Duration::from_micros(max_wait_us),
+        )));
+    }
+    
+    /// Track a wake latency SLO and report breaches from the health checks.
+    ///
+    /// Each check judges the wakeups since the previous one; a breach is a
+    /// critical issue listing the threads with the slowest wakeups.
+    pub fn register_wake_latency_slo(&self, slo: WakeLatencySlo) -> SloId {
+        let id = wake_latency::register_slo(slo);
+        if !self.slo_checker_registered.swap(true, Ordering::AcqRel) {
+            self.register_checker(Box::new(WakeLatencyHealthChecker::new()));
+        }
+        id
+    }
+    
+    /// Arm a lost wakeup alarm on every blocking operation and report the
+    /// threads still blocked when theirs fires.
+    ///
+    /// See [`lost_wakeup`] for what is reported; `timeout` is normally
+    /// [`lost_wakeup::DEFAULT_TIMEOUT_MS`].
+    pub fn enable_lost_wakeup_alarms(&self, timeout: Duration) {
+        lost_wakeup::enable(timeout);
+        if !self.lost_wakeup_checker_registered.swap(true, Ordering::AcqRel) {
+            self.register_checker(Box::new(LostWakeupHealthChecker::new()));
+        }
+    }
+    
     /// Perform a comprehensive health check.
     pub fn check_health(&self) -> SystemHealth {
         if !self.is_enabled() {
@@ -566,6 +639,67 @@ impl HealthMonitor {
         }
     }
     
+    /// Summarize the health of the system from the global monitor, the
+    /// thread registry and the resource limiter.
+    ///
+    /// ```ignore
+    /// let health = HealthMonitor::current_status();
+    /// println!("{}/{} threads healthy", health.healthy_threads, health.total_threads);
+    /// ```
+    pub fn current_status() -> HealthSummary {
+        HEALTH_MONITOR.summarize()
+    }
+    
+    /// Build a [`HealthSummary`] from this monitor's issues.
+    fn summarize(&self) -> HealthSummary {
+        self.summarize_threads(&registry::ids(), |thread| GLOBAL_RESOURCE_LIMITER.quota_utilization(thread))
+    }
+    
+    /// Build a [`HealthSummary`] of `threads` from this monitor's issues,
+    /// given the fraction of its quotas each thread uses.
+    fn summarize_threads(&self, threads: &[ThreadId], quota_utilization: impl Fn(ThreadId) -> f64) -> HealthSummary {
+        let health = self.get_current_health();
+        let mut unhealthy: BTreeSet<ThreadId> = health
+            .active_issues
+            .iter()
+            .filter(|issue| issue.severity >= IssueSeverity::Warning)
+            .flat_map(|issue| issue.affected_threads.iter().copied())
+            .collect();
+        unhealthy.extend(LOCK_WAIT_GRAPH.deadlocks().into_iter().flatten().map(|(thread, _)| thread));
+        
+        let mut resource_usage: f64 = 0.0;
+        for &thread in threads {
+            let usage = quota_utilization(thread);
+            if usage >= 1.0 {
+                unhealthy.insert(thread);
+            }
+            resource_usage = resource_usage.max(usage);
+        }
+        let healthy_threads = threads.iter().filter(|thread| !unhealthy.contains(thread)).count();
+        
+        // Quota usage is judged by the memory thresholds
+        let (warning, critical) = {
+            let config = self.config.lock();
+            (config.thresholds.memory_warning_threshold as f64, config.thresholds.memory_critical_threshold as f64)
+        };
+        let mut status = health.overall_status;
+        if healthy_threads < threads.len() {
+            status = status.combine(HealthStatus::Warning);
+        }
+        if resource_usage * 100.0 >= critical {
+            status = status.combine(HealthStatus::Critical);
+        } else if resource_usage * 100.0 >= warning {
+            status = status.combine(HealthStatus::Warning);
+        }
+        
+        HealthSummary {
+            status,
+            healthy_threads,
+            total_threads: threads.len(),
+            resource_usage,
+        }
+    }
+    
     /// Report a health issue.
     pub fn report_issue(&self, issue: HealthIssue) {
         if let Some(mut health) = self.current_health.try_lock() {
@@ -648,6 +782,9 @@ impl HealthChecker for PerformanceHealthChecker {
 }
 
 /// Deadlock health checker implementation.
+///
+/// Reports every cycle in the global lock-wait graph, with the rendered
+/// wait-for chain in the issue context.
 pub struct DeadlockHealthChecker {
     name: String,
 }
@@ -666,13 +803,319 @@ impl HealthChecker for DeadlockHealthChecker {
     }
     
     fn check_health(&self) -> ComponentHealth {
-        // TODO: Implement actual deadlock detection
+        let now = Instant::now();
+        let mut issues = Vec::new();
+        
+        for cycle in LOCK_WAIT_GRAPH.deadlocks() {
+            let mut chain = String::new();
+            let _ = render_chain(&LOCK_WAIT_GRAPH, &cycle, &mut chain);
+            
+            let mut context = BTreeMap::new();
+            context.insert("chain".to_string(), chain);
+            
+            issues.push(HealthIssue {
+                severity: IssueSeverity::Fatal,
+                category: IssueCategory::Deadlock,
+                description: format!("{} threads are deadlocked on a lock cycle", cycle.len()),
+                component: self.name.clone(),
+                detected_at: now,
+                context,
+                affected_threads: cycle.iter().map(|&(thread, _)| thread).collect(),
+                remediation: Some("Acquire the locks in the chain in a consistent order".to_string()),
+            });
+        }
+        
         ComponentHealth {
             name: self.name.clone(),
-            status: HealthStatus::Healthy,
+            status: if issues.is_empty() { HealthStatus::Healthy } else { HealthStatus::Failed },
             metrics: ComponentMetrics::default(),
-            last_check: Instant::now(),
-            issues: Vec::new(),
+            last_check: now,
+            issues,
+        }
+    }
+}
+
+/// Starvation health checker implementation.
+///
+/// Flags threads that have been waiting in a run queue for longer than the
+/// configured bound without being scheduled.
+pub struct StarvationHealthChecker {
+    name: String,
+    scheduler: &'static dyn Scheduler,
+    max_ready_wait: Duration,
+}
+
+impl StarvationHealthChecker {
+    pub fn new(scheduler: &'static dyn Scheduler, max_ready_wait: Duration) -> Self {
+        Self {
+            name: "starvation".to_string(),
+            scheduler,
+            max_ready_wait,
+        }
+    }
+    
+    /// Get the configured maximum ready-queue wait.
+    pub fn max_ready_wait(&self) -> Duration {
+        self.max_ready_wait
+    }
+    
+    /// [`check_health`](HealthChecker::check_health) as of `now`.
+    pub fn check_health_at(&self, now: Instant) -> ComponentHealth {
+        let entries = self.scheduler.run_queue_snapshot();
+        
+        let mut starving = Vec::new();
+        let mut longest_wait = Duration::from_nanos(0);
+        
+        for entry in &entries {
+            if now < entry.ready_since {
+                continue; // Timestamp taken after `now` on another CPU
+            }
+            
+            let waited = now.duration_since(entry.ready_since);
+            if waited > self.max_ready_wait {
+                starving.push(entry.thread_id);
+                if waited > longest_wait {
+                    longest_wait = waited;
+                }
+            }
+        }
+        
+        let metrics = ComponentMetrics {
+            queue_depth: entries.len(),
+            ..Default::default()
+        };
+        
+        let mut issues = Vec::new();
+        if !starving.is_empty() {
+            let thread_list = starving.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            
+            let mut context = BTreeMap::new();
+            context.insert("starving_threads".to_string(), thread_list);
+            context.insert("longest_wait_us".to_string(), longest_wait.as_micros().to_string());
+            context.insert("max_ready_wait_us".to_string(), self.max_ready_wait.as_micros().to_string());
+            
+            issues.push(HealthIssue {
+                severity: IssueSeverity::Warning,
+                category: IssueCategory::Scheduler,
+                description: format!(
+                    "{} thread(s) ready for longer than {}us without running",
+                    starving.len(),
+                    self.max_ready_wait.as_micros(),
+                ),
+                component: self.name.clone(),
+                detected_at: now,
+                context,
+                affected_threads: starving,
+                remediation: Some("Check for higher-priority threads monopolizing the CPU or adjust priorities".to_string()),
+            });
+        }
+        
+        ComponentHealth {
+            name: self.name.clone(),
+            status: if issues.is_empty() { HealthStatus::Healthy } else { HealthStatus::Warning },
+            metrics,
+            last_check: now,
+            issues,
+        }
+    }
+}
+
+impl HealthChecker for StarvationHealthChecker {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn check_health(&self) -> ComponentHealth {
+        self.check_health_at(Instant::now())
+    }
+}
+
+/// Wake latency SLO checker implementation.
+///
+/// Judges the window of every [registered SLO](wake_latency::register_slo)
+/// since the previous check and flags the breached ones.
+pub struct WakeLatencyHealthChecker {
+    name: String,
+}
+
+impl WakeLatencyHealthChecker {
+    pub fn new() -> Self {
+        Self {
+            name: "wake_latency".to_string(),
+        }
+    }
+}
+
+impl Default for WakeLatencyHealthChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthChecker for WakeLatencyHealthChecker {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn check_health(&self) -> ComponentHealth {
+        let now = Instant::now();
+        let evaluations = wake_latency::evaluate_slos();
+        
+        // The worst percentile latency isn't an average, so it goes in its
+        // own metric rather than `avg_response_time_us`
+        let mut metrics = ComponentMetrics::default();
+        if let Some(worst) = evaluations.iter().map(|e| e.observed.as_micros()).max() {
+            metrics.custom_metrics.insert("max_response_time_us".to_string(), worst as f64);
+        }
+        
+        let mut issues = Vec::new();
+        for evaluation in evaluations.into_iter().filter(|e| e.breached) {
+            let offenders = evaluation.offenders.iter()
+                .map(|(id, latency)| format!("{}:{}us", id, latency.as_micros()))
+                .collect::<Vec<_>>()
+                .join(",");
+            
+            let mut context = BTreeMap::new();
+            context.insert("slo".to_string(), evaluation.name.clone());
+            context.insert("percentile".to_string(), evaluation.percentile.to_string());
+            context.insert("observed_us".to_string(), evaluation.observed.as_micros().to_string());
+            context.insert("target_us".to_string(), evaluation.target.as_micros().to_string());
+            context.insert("samples".to_string(), evaluation.samples.to_string());
+            context.insert("offenders".to_string(), offenders);
+            
+            issues.push(HealthIssue {
+                severity: IssueSeverity::Critical,
+                category: IssueCategory::Scheduler,
+                description: format!(
+                    "wake latency SLO {} breached: p{} {}us over {}us target",
+                    evaluation.name,
+                    evaluation.percentile,
+                    evaluation.observed.as_micros(),
+                    evaluation.target.as_micros(),
+                ),
+                component: self.name.clone(),
+                detected_at: now,
+                context,
+                affected_threads: evaluation.offenders.iter().map(|&(id, _)| id).collect(),
+                remediation: Some("Look for long preemption-off sections and higher-priority threads on the affected CPUs".to_string()),
+            });
+        }
+        
+        ComponentHealth {
+            name: self.name.clone(),
+            status: if issues.is_empty() { HealthStatus::Healthy } else { HealthStatus::Critical },
+            metrics,
+            last_check: now,
+            issues,
+        }
+    }
+}
+
+/// Lost wakeup checker implementation.
+///
+/// Flags every thread whose [lost wakeup alarm](lost_wakeup) fired while
+/// it is still blocked, for as long as it stays blocked. Threads stuck
+/// [waiting for I/O](crate::thread_new::io_wait) are flagged as a hardware
+/// issue instead, naming the device if known.
+pub struct LostWakeupHealthChecker {
+    name: String,
+}
+
+impl LostWakeupHealthChecker {
+    pub fn new() -> Self {
+        Self {
+            name: "lost_wakeup".to_string(),
+        }
+    }
+}
+
+impl Default for LostWakeupHealthChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthChecker for LostWakeupHealthChecker {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn check_health(&self) -> ComponentHealth {
+        let now = Instant::now();
+        let lost = lost_wakeup::fired();
+        
+        let metrics = ComponentMetrics {
+            queue_depth: lost.len(),
+            ..ComponentMetrics::default()
+        };
+        
+        let mut issues = Vec::new();
+        for lost in &lost {
+            let mut context = BTreeMap::new();
+            context.insert("blocked_on".to_string(), lost.blocked_on.to_string());
+            context.insert("blocked_ms".to_string(), lost.blocked_for.as_millis().to_string());
+            if let Some(name) = &lost.name {
+                context.insert("thread_name".to_string(), name.clone());
+            }
+            if let Some(sp) = lost.stack_pointer {
+                context.insert("stack_pointer".to_string(), format!("{:#x}", sp));
+            }
+            if let Some(pc) = lost.program_counter {
+                context.insert("program_counter".to_string(), format!("{:#x}", pc));
+            }
+            if !lost.stack.is_empty() {
+                let words = lost.stack.iter().map(|word| format!("{:#x}", word)).collect::<Vec<_>>();
+                context.insert("stack".to_string(), words.join(" "));
+            }
+            
+            if lost.blocked_on.is_io_wait() {
+                if let Some(device) = lost.blocked_on.device() {
+                    context.insert("device".to_string(), device.to_string());
+                }
+                issues.push(HealthIssue {
+                    severity: IssueSeverity::Warning,
+                    category: IssueCategory::Hardware,
+                    description: format!(
+                        "thread {} waited {}ms for {}, device may be unresponsive",
+                        lost.thread,
+                        lost.blocked_for.as_millis(),
+                        lost.blocked_on,
+                    ),
+                    component: self.name.clone(),
+                    detected_at: now,
+                    context,
+                    affected_threads: vec![lost.thread],
+                    remediation: Some("Check that the device completes requests and raises its interrupt".to_string()),
+                });
+                continue;
+            }
+            
+            issues.push(HealthIssue {
+                severity: IssueSeverity::Warning,
+                category: IssueCategory::Concurrency,
+                description: format!(
+                    "thread {} blocked on {} for {}ms, possible lost wakeup",
+                    lost.thread,
+                    lost.blocked_on,
+                    lost.blocked_for.as_millis(),
+                ),
+                component: self.name.clone(),
+                detected_at: now,
+                context,
+                affected_threads: vec![lost.thread],
+                remediation: Some("Check that every path changing the awaited condition notifies its waiters".to_string()),
+            });
+        }
+        
+        ComponentHealth {
+            name: self.name.clone(),
+            status: if issues.is_empty() { HealthStatus::Healthy } else { HealthStatus::Warning },
+            metrics,
+            last_check: now,
+            issues,
         }
     }
 }
@@ -693,4 +1136,84 @@ pub fn init_health_monitor(interval_ms: u32) -> Result<(), &'static str> {
 /// Cleanup health monitoring.
 pub fn cleanup_health_monitor() {
     HEALTH_MONITOR.enabled.store(false, Ordering::Release);
-}
\ No newline at end of file
+}
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::sched::RoundRobinScheduler;
+    use crate::thread_new::{ReadyRef, Thread};
+
+    #[test]
+    fn test_starving_thread_flagged() {
+        let scheduler: &'static RoundRobinScheduler = Box::leak(Box::new(RoundRobinScheduler::new(1)));
+        let checker = StarvationHealthChecker::new(scheduler, Duration::from_millis(1));
+
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let id = unsafe { ThreadId::new_unchecked(49_727) };
+        let thread = Thread::new(id, stack, || {}, 128).0;
+        scheduler.enqueue(ReadyRef(thread.clone()));
+
+        let queued = thread.ready_since().unwrap();
+        assert!(checker.check_health_at(queued).issues.is_empty());
+
+        // Left in the queue past the bound
+        let health = checker.check_health_at(queued.add(Duration::from_millis(2)));
+        assert_eq!(health.status, HealthStatus::Warning);
+        assert_eq!(health.metrics.queue_depth, 1);
+        assert_eq!(health.issues[0].affected_threads, [id]);
+
+        // Once it runs it no longer counts as queued
+        let next = scheduler.pick_next(0).unwrap();
+        assert_eq!(next.id(), id);
+        let health = checker.check_health_at(queued.add(Duration::from_millis(2)));
+        assert_eq!(health.status, HealthStatus::Healthy);
+        assert_eq!(health.metrics.queue_depth, 0);
+    }
+
+    fn issue(severity: IssueSeverity, thread: ThreadId) -> HealthIssue {
+        HealthIssue {
+            severity,
+            category: IssueCategory::Scheduler,
+            description: "test issue".to_string(),
+            component: "test".to_string(),
+            detected_at: Instant::now(),
+            context: BTreeMap::new(),
+            affected_threads: vec![thread],
+            remediation: None,
+        }
+    }
+
+    #[test]
+    fn test_current_status() {
+        let threads = [49_740, 49_741, 49_742].map(|id| unsafe { ThreadId::new_unchecked(id) });
+        let usage = |max: f64| move |thread: ThreadId| if thread == threads[2] { max } else { 0.1 };
+
+        // Healthy: no issues and quotas below the memory warning threshold
+        let monitor = HealthMonitor::new();
+        monitor.report_issue(issue(IssueSeverity::Info, threads[0]));
+        let summary = monitor.summarize_threads(&threads, usage(0.5));
+        assert_eq!(summary.status, HealthStatus::Healthy);
+        assert_eq!((summary.healthy_threads, summary.total_threads), (3, 3));
+        assert_eq!(summary.resource_usage, 0.5);
+
+        // Degraded: a thread named in a warning, and a quota past 85%
+        monitor.report_issue(issue(IssueSeverity::Warning, threads[1]));
+        let summary = monitor.summarize_threads(&threads, usage(0.5));
+        assert_eq!(summary.status, HealthStatus::Warning);
+        assert_eq!(summary.healthy_threads, 2);
+        let summary = HealthMonitor::new().summarize_threads(&threads, usage(0.9));
+        assert_eq!(summary.status, HealthStatus::Warning);
+        assert_eq!(summary.healthy_threads, 3);
+
+        // Failing: a thread at its quota, and a failed overall status
+        let summary = monitor.summarize_threads(&threads, usage(1.0));
+        assert_eq!(summary.status, HealthStatus::Critical);
+        assert_eq!(summary.healthy_threads, 1);
+        monitor.current_health.lock().overall_status = HealthStatus::Failed;
+        let summary = monitor.summarize_threads(&threads, usage(0.5));
+        assert_eq!(summary.status, HealthStatus::Failed);
+        assert_eq!(summary.healthy_threads, 2);
+    }
+}
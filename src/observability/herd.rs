@@ -0,0 +1,108 @@
+//! Thundering-herd accounting for wait points.
+//!
+//! The crate's blocking primitives count, for every wait point they block
+//! threads on, how many threads each wakeup woke and how many of those
+//! found nothing to do and went back to waiting. A wake-all that wakes ten
+//! consumers for one item shows up as a herd with nine wasted wakeups;
+//! switching that wait point to wake-one removes them.
+//!
+//! Wait points are identified by what their waiters are
+//! [blocked on](crate::thread_new::BlockedOn), so the entries match the
+//! `ps` listing. [`worst_wait_points`] lists the ones wasting the most
+//! wakeups:
+//!
+//! ```ignore
+//! for (point, stats) in herd::worst_wait_points(5) {
+//!     log::warn!("{}: {} of {} wakeups wasted", point, stats.wasted, stats.woken);
+//! }
+//! ```
+
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::BlockedOn;
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Most wait points tracked at once; wakeups on further ones are not
+/// counted.
+pub const MAX_WAIT_POINTS: usize = 256;
+
+/// Wakeup counts of one wait point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WaitPointStats {
+    /// Wakeups that woke at least one thread
+    pub wakes: u64,
+    /// Threads woken in total
+    pub woken: u64,
+    /// Wakeups that woke more than one thread at once
+    pub herds: u64,
+    /// Most threads woken by a single wakeup
+    pub max_woken: u64,
+    /// Woken threads that found their condition unmet and waited again
+    pub wasted: u64,
+}
+
+impl WaitPointStats {
+    /// Get the number of woken threads that went on to make progress.
+    pub fn acquired(&self) -> u64 {
+        self.woken.saturating_sub(self.wasted)
+    }
+}
+
+/// Counts of every tracked wait point.
+static WAIT_POINTS: SpinLockIrqSave<BTreeMap<BlockedOn, WaitPointStats>> = SpinLockIrqSave::new(BTreeMap::new());
+
+/// Update the counts of `point`, if it is or can be tracked.
+fn update(point: BlockedOn, f: impl FnOnce(&mut WaitPointStats)) {
+    let mut points = WAIT_POINTS.lock();
+    if let Some(stats) = points.get_mut(&point) {
+        f(stats);
+    } else if points.len() < MAX_WAIT_POINTS {
+        f(points.entry(point).or_default());
+    }
+}
+
+/// Record that a wakeup on `point` woke `woken` threads.
+pub(crate) fn record_wake(point: BlockedOn, woken: usize) {
+    if woken == 0 {
+        return;
+    }
+    let woken = woken as u64;
+    update(point, |stats| {
+        stats.wakes += 1;
+        stats.woken += woken;
+        if woken > 1 {
+            stats.herds += 1;
+        }
+        stats.max_woken = stats.max_woken.max(woken);
+    });
+}
+
+/// Record that a thread woken on `point` had to wait again.
+pub(crate) fn record_wasted(point: BlockedOn) {
+    update(point, |stats| stats.wasted += 1);
+}
+
+/// Get the counts of `point`, if it is tracked.
+pub fn wait_point_stats(point: BlockedOn) -> Option<WaitPointStats> {
+    WAIT_POINTS.lock().get(&point).copied()
+}
+
+/// Get up to `limit` wait points with wasted wakeups, most wasted first.
+///
+/// Ties are broken by the number of herds.
+pub fn worst_wait_points(limit: usize) -> Vec<(BlockedOn, WaitPointStats)> {
+    let mut points: Vec<_> = WAIT_POINTS
+        .lock()
+        .iter()
+        .filter(|(_, stats)| stats.wasted > 0)
+        .map(|(&point, &stats)| (point, stats))
+        .collect();
+    points.sort_by(|a, b| b.1.wasted.cmp(&a.1.wasted).then(b.1.herds.cmp(&a.1.herds)));
+    points.truncate(limit);
+    points
+}
+
+/// Forget all counts.
+pub fn reset_wait_points() {
+    WAIT_POINTS.lock().clear();
+}
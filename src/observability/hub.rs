@@ -0,0 +1,267 @@
+//! Pluggable thread lifecycle observers.
+//!
+//! The threading core reports lifecycle events to [`OBSERVER_HUB`] only;
+//! the hub fans them out to every registered [`ThreadObserver`]. The
+//! built-in collectors ([`GLOBAL_METRICS`] and [`GLOBAL_PROFILER`]) are
+//! observers like any other: they are registered from the start and can be
+//! detached, and integrators can attach their own telemetry at runtime
+//! without touching the collection points.
+//!
+//! ```ignore
+//! struct Tracer;
+//!
+//! impl ThreadObserver for Tracer {
+//!     fn on_switch(&self, prev: &Thread, next: &Thread) {
+//!         trace_buffer::push(prev.id(), next.id());
+//!     }
+//! }
+//!
+//! let id = OBSERVER_HUB.register(Arc::new(Tracer));
+//! // ...
+//! OBSERVER_HUB.unregister(id);
+//! ```
+//!
+//! Callbacks run on the thread or CPU that caused the event, `on_switch`
+//! with preemption disabled in the middle of a context switch, so they must
+//! be short and must not block.
+
+use super::metrics::{MetricsCollector, GLOBAL_METRICS};
+use super::profiler::{ContextSwitchReason, ThreadProfiler, GLOBAL_PROFILER};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{PriorityChange, Thread, ThreadId, ThreadState};
+extern crate alloc;
+use alloc::{sync::Arc, vec::Vec};
+use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Receiver of thread lifecycle events.
+///
+/// Every method defaults to doing nothing, so observers only implement the
+/// events they care about.
+pub trait ThreadObserver: Send + Sync {
+    /// A thread was created.
+    fn on_spawn(&self, _thread: &Thread) {}
+
+    /// A thread was torn down.
+    fn on_exit(&self, _thread: ThreadId) {}
+
+    /// The CPU is switching from `prev` to `next`.
+    ///
+    /// `prev`'s state tells why: still runnable if it was preempted or
+    /// yielded, blocked or finished otherwise.
+    fn on_switch(&self, _prev: &Thread, _next: &Thread) {}
+
+    /// A thread blocked.
+    fn on_block(&self, _thread: &Thread) {}
+
+    /// A thread's priority, real-time priority or nice value changed.
+    fn on_priority_change(&self, _thread: &Thread, _change: &PriorityChange) {}
+}
+
+/// Handle identifying a registered observer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObserverId(u64);
+
+impl ObserverId {
+    /// The built-in metrics collector.
+    pub const METRICS: Self = Self(0);
+    /// The built-in profiler.
+    pub const PROFILER: Self = Self(1);
+}
+
+/// Runtime-registered observers with their ids.
+type ObserverList = Vec<(ObserverId, Arc<dyn ThreadObserver>)>;
+
+/// Number of built-in observers; registered ids start after them.
+const BUILTINS: usize = 2;
+
+/// Dispatches thread lifecycle events to registered observers.
+pub struct ObserverHub {
+    builtins: [&'static dyn ThreadObserver; BUILTINS],
+    /// Bit `n` set if builtin `n` is detached
+    detached: AtomicU64,
+    /// Runtime-registered observers, replaced wholesale on every change so
+    /// dispatch only has to clone the list
+    observers: SpinLockIrqSave<Option<Arc<ObserverList>>>,
+    /// Number of runtime-registered observers
+    registered: AtomicUsize,
+    next_id: AtomicU64,
+}
+
+impl ObserverHub {
+    const fn new(builtins: [&'static dyn ThreadObserver; BUILTINS]) -> Self {
+        Self {
+            builtins,
+            detached: AtomicU64::new(0),
+            observers: SpinLockIrqSave::new(None),
+            registered: AtomicUsize::new(0),
+            next_id: AtomicU64::new(BUILTINS as u64),
+        }
+    }
+
+    /// Start delivering events to `observer`.
+    pub fn register(&self, observer: Arc<dyn ThreadObserver>) -> ObserverId {
+        let id = ObserverId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut observers = self.observers.lock();
+        let mut list = observers.as_deref().cloned().unwrap_or_default();
+        list.push((id, observer));
+        self.registered.store(list.len(), Ordering::Release);
+        *observers = Some(Arc::new(list));
+        id
+    }
+
+    /// Stop delivering events to the observer registered as `id`.
+    ///
+    /// Built-in observers can be detached and re-attached with
+    /// [`attach`](Self::attach). Returns whether the observer was
+    /// registered.
+    pub fn unregister(&self, id: ObserverId) -> bool {
+        if (id.0 as usize) < BUILTINS {
+            let bit = 1 << id.0;
+            return self.detached.fetch_or(bit, Ordering::AcqRel) & bit == 0;
+        }
+
+        let mut observers = self.observers.lock();
+        let Some(current) = observers.as_deref() else {
+            return false;
+        };
+        let list: Vec<_> = current.iter().filter(|(other, _)| *other != id).cloned().collect();
+        let removed = list.len() != current.len();
+        self.registered.store(list.len(), Ordering::Release);
+        *observers = (!list.is_empty()).then(|| Arc::new(list));
+        removed
+    }
+
+    /// Re-attach a detached built-in observer.
+    pub fn attach(&self, id: ObserverId) {
+        if (id.0 as usize) < BUILTINS {
+            self.detached.fetch_and(!(1 << id.0), Ordering::AcqRel);
+        }
+    }
+
+    /// Check whether the observer registered as `id` receives events.
+    pub fn is_registered(&self, id: ObserverId) -> bool {
+        if (id.0 as usize) < BUILTINS {
+            return self.detached.load(Ordering::Acquire) & (1 << id.0) == 0;
+        }
+        self.observers
+            .lock()
+            .as_deref()
+            .is_some_and(|list| list.iter().any(|(other, _)| *other == id))
+    }
+
+    /// Report that `thread` was created.
+    pub fn spawn(&self, thread: &Thread) {
+        self.each(|observer| observer.on_spawn(thread));
+    }
+
+    /// Report that `thread` was torn down.
+    pub fn exit(&self, thread: ThreadId) {
+        self.each(|observer| observer.on_exit(thread));
+    }
+
+    /// Report a switch from `prev` to `next`.
+    pub fn switch(&self, prev: &Thread, next: &Thread) {
+        self.each(|observer| observer.on_switch(prev, next));
+    }
+
+    /// Report that `thread` blocked.
+    pub fn block(&self, thread: &Thread) {
+        self.each(|observer| observer.on_block(thread));
+    }
+
+    /// Report that `thread`'s scheduling attributes changed.
+    pub fn priority_change(&self, thread: &Thread, change: &PriorityChange) {
+        self.each(|observer| observer.on_priority_change(thread, change));
+    }
+
+    fn each(&self, mut event: impl FnMut(&dyn ThreadObserver)) {
+        let detached = self.detached.load(Ordering::Acquire);
+        for (index, observer) in self.builtins.iter().enumerate() {
+            if detached & (1 << index) == 0 {
+                event(*observer);
+            }
+        }
+
+        if self.registered.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        // Run the callbacks outside the lock, so they may register others
+        let observers = self.observers.lock().clone();
+        for (_, observer) in observers.iter().flat_map(|list| list.iter()) {
+            event(observer.as_ref());
+        }
+    }
+}
+
+/// Hub the threading core reports to.
+pub static OBSERVER_HUB: ObserverHub = ObserverHub::new([&GLOBAL_METRICS, &GLOBAL_PROFILER]);
+
+impl ThreadObserver for MetricsCollector {
+    fn on_spawn(&self, thread: &Thread) {
+        self.register_thread(thread.id());
+    }
+
+    fn on_exit(&self, thread: ThreadId) {
+        self.unregister_thread(thread);
+    }
+}
+
+impl ThreadObserver for ThreadProfiler {
+    fn on_switch(&self, prev: &Thread, next: &Thread) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let reason = match prev.state() {
+            ThreadState::Blocked => ContextSwitchReason::SyncBlock,
+            ThreadState::Finished => ContextSwitchReason::ThreadExit,
+            ThreadState::Suspended => ContextSwitchReason::VoluntaryYield,
+            ThreadState::Ready | ThreadState::Running => ContextSwitchReason::TimeSliceExpired,
+        };
+        self.record_context_switch(prev.id(), next.id(), 0, reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct ExitCounter {
+        exits: AtomicUsize,
+    }
+
+    impl ThreadObserver for ExitCounter {
+        fn on_exit(&self, thread: ThreadId) {
+            if thread == ThreadId::new(48_001) {
+                self.exits.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_observer_registration() {
+        let counter = Arc::new(ExitCounter::default());
+        let id = OBSERVER_HUB.register(counter.clone());
+        assert!(OBSERVER_HUB.is_registered(id));
+
+        let hub = &OBSERVER_HUB;
+        hub.exit(ThreadId::new(48_001));
+        hub.exit(ThreadId::new(48_002));
+        assert_eq!(counter.exits.load(Ordering::Relaxed), 1);
+
+        assert!(hub.unregister(id));
+        assert!(!hub.unregister(id));
+        assert!(!hub.is_registered(id));
+        hub.exit(ThreadId::new(48_001));
+        assert_eq!(counter.exits.load(Ordering::Relaxed), 1);
+
+        // Built-ins can be detached and re-attached
+        let builtins = ObserverHub::new([&GLOBAL_METRICS, &GLOBAL_PROFILER]);
+        assert!(builtins.unregister(ObserverId::METRICS));
+        assert!(!builtins.is_registered(ObserverId::METRICS));
+        assert!(builtins.is_registered(ObserverId::PROFILER));
+        builtins.attach(ObserverId::METRICS);
+        assert!(builtins.is_registered(ObserverId::METRICS));
+    }
+}
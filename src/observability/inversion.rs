@@ -0,0 +1,183 @@
+//! Bounded priority inversion detection.
+//!
+//! A priority inversion is a thread waiting on a lock held by a less
+//! important one: until the owner gets to run and release it, the waiter
+//! is held back by every thread that outranks the owner. Short inversions
+//! come with sharing locks across priorities; the ones worth finding are
+//! those that last. [`Mutex`](crate::safe_api::Mutex) records its owner
+//! and watches every contended wait. Once a waiter has waited longer than
+//! the [threshold](set_threshold) on an owner of lower priority, the
+//! inversion is counted in [`SystemMetrics::priority_inversions`] and
+//! recorded with both threads for the
+//! [metrics report](super::metrics::MetricsReport::priority_inversions):
+//!
+//! ```ignore
+//! inversion::set_threshold(Duration::from_millis(5));
+//! for inversion in GLOBAL_METRICS.generate_report().priority_inversions {
+//!     log::warn!("{}", inversion);
+//! }
+//! ```
+//!
+//! A wait is reported at most once, however long it goes on.
+//!
+//! [`SystemMetrics::priority_inversions`]: super::metrics::SystemMetrics::priority_inversions
+
+use super::metrics::GLOBAL_METRICS;
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{percpu, registry, Thread, ThreadId};
+use crate::time::{Duration, Instant};
+use core::fmt;
+use portable_atomic::{AtomicU64, Ordering};
+extern crate alloc;
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// Wait on a less important owner that counts as an inversion unless set
+/// otherwise (1 ms).
+pub const DEFAULT_THRESHOLD_MS: u64 = 1;
+
+/// Most recent inversions kept for reports; older ones are dropped.
+pub const MAX_RECORDED: usize = 64;
+
+static THRESHOLD_NS: AtomicU64 = AtomicU64::new(DEFAULT_THRESHOLD_MS * 1_000_000);
+
+static RECORDED: SpinLockIrqSave<VecDeque<PriorityInversion>> = SpinLockIrqSave::new(VecDeque::new());
+
+/// A thread that waited too long on a lock held by a less important one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityInversion {
+    /// Address of the lock
+    pub lock: usize,
+    pub waiter: ThreadId,
+    pub waiter_priority: u8,
+    /// Thread holding the lock when the wait crossed the threshold
+    pub owner: ThreadId,
+    pub owner_priority: u8,
+    /// How long the waiter had waited
+    pub waited: Duration,
+    pub detected_at: Instant,
+}
+
+impl fmt::Display for PriorityInversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread {} (priority {}) waited {} ns on mutex {:#x} held by thread {} (priority {})",
+            self.waiter,
+            self.waiter_priority,
+            self.waited.as_nanos(),
+            self.lock,
+            self.owner,
+            self.owner_priority,
+        )
+    }
+}
+
+/// Count waits on a less important owner as inversions once they last
+/// `threshold`.
+pub fn set_threshold(threshold: Duration) {
+    THRESHOLD_NS.store(threshold.as_nanos(), Ordering::Relaxed);
+}
+
+/// Get how long a wait on a less important owner lasts before it counts
+/// as an inversion.
+pub fn threshold() -> Duration {
+    Duration::from_nanos(THRESHOLD_NS.load(Ordering::Relaxed))
+}
+
+/// Get the recorded inversions, oldest first.
+pub fn recorded() -> Vec<PriorityInversion> {
+    RECORDED.lock().iter().copied().collect()
+}
+
+/// Forget the recorded inversions.
+pub(crate) fn clear() {
+    RECORDED.lock().clear();
+}
+
+/// Watch over one contended wait on the lock at `lock`.
+pub(crate) struct InversionWatch {
+    lock: usize,
+    since: Instant,
+    done: bool,
+}
+
+impl InversionWatch {
+    /// Start watching a wait beginning now.
+    pub(crate) fn new(lock: usize) -> Self {
+        Self {
+            lock,
+            since: Instant::now(),
+            done: false,
+        }
+    }
+
+    /// Check the wait so far on the lock's current `owner`, if known.
+    pub(crate) fn poll(&mut self, owner: Option<ThreadId>) {
+        if self.done {
+            return;
+        }
+        let waited = Instant::now().duration_since(self.since);
+        if waited < threshold() {
+            return;
+        }
+        let Some(waiter) = percpu::current() else {
+            // Waits outside any thread have no priority to invert
+            self.done = true;
+            return;
+        };
+        if let Some(owner) = owner.and_then(registry::lookup) {
+            record_if_inverted(self.lock, &waiter, &owner, waited);
+            self.done = true;
+        }
+    }
+}
+
+/// Record an inversion if `waiter`, having waited `waited` on the lock at
+/// `lock`, outranks its `owner`.
+pub(crate) fn record_if_inverted(lock: usize, waiter: &Thread, owner: &Thread, waited: Duration) -> bool {
+    let (waiter_priority, owner_priority) = (waiter.priority(), owner.priority());
+    if owner_priority >= waiter_priority {
+        return false;
+    }
+
+    GLOBAL_METRICS.system_counters().record_priority_inversion();
+    let inversion = PriorityInversion {
+        lock,
+        waiter: waiter.id(),
+        waiter_priority,
+        owner: owner.id(),
+        owner_priority,
+        waited,
+        detected_at: Instant::now(),
+    };
+    let mut recorded = RECORDED.lock();
+    if recorded.len() == MAX_RECORDED {
+        recorded.pop_front();
+    }
+    recorded.push_back(inversion);
+    true
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+
+    #[test]
+    fn test_only_less_important_owners_invert() {
+        let pool = StackPool::new();
+        let (high, _high_join) = Thread::new(ThreadId::new(49_721), pool.allocate(StackSizeClass::Small).unwrap(), || {}, 200);
+        let (low, _low_join) = Thread::new(ThreadId::new(49_722), pool.allocate(StackSizeClass::Small).unwrap(), || {}, 10);
+        let counted = || GLOBAL_METRICS.system_metrics().priority_inversions;
+        let before = counted();
+
+        assert!(!record_if_inverted(0x2000, &low, &high, Duration::from_millis(5)));
+        assert!(record_if_inverted(0x2000, &high, &low, Duration::from_millis(5)));
+        assert_eq!(counted(), before + 1);
+
+        let inversion = recorded().into_iter().rev().find(|inversion| inversion.lock == 0x2000).unwrap();
+        assert_eq!((inversion.waiter, inversion.waiter_priority), (high.id(), 200));
+        assert_eq!((inversion.owner, inversion.owner_priority), (low.id(), 10));
+        assert_eq!(inversion.waited, Duration::from_millis(5));
+    }
+}
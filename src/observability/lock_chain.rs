@@ -0,0 +1,302 @@
+//! Lock-wait chains for deadlock and priority inversion reports.
+//!
+//! Blocking primitives report which thread owns each lock and which lock
+//! each blocked thread is waiting for. Given a blocked thread,
+//! [`LockWaitGraph::wait_chain`] follows that graph from waiter to owner
+//! until it reaches a thread that is not blocked, or comes back to a thread
+//! already on the path, which is a deadlock.
+//!
+//! Along a chain, every owner would have to inherit the highest priority of
+//! the threads waiting behind it for the waiters to make progress; the text
+//! renderer shows that inherited priority next to each thread's own, which
+//! makes inversions easy to spot:
+//!
+//! ```text
+//! thread 7 (prio 200) waits on lock 0x1000
+//!   held by thread 3 (prio 10, inherits 200) waits on lock 0x2000
+//!   held by thread 5 (prio 50, inherits 200) running
+//! ```
+//!
+//! An owner stuck outside the graph, in a join, a channel or a sleep, is
+//! shown with what it is [blocked on](crate::thread_new::BlockedOn) instead
+//! of as running.
+
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{registry, ThreadId};
+extern crate alloc;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+/// Address identifying a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LockAddr(pub usize);
+
+impl LockAddr {
+    /// Identify a lock by its address in memory.
+    pub fn of<T: ?Sized>(lock: &T) -> Self {
+        Self(lock as *const T as *const () as usize)
+    }
+}
+
+impl fmt::Display for LockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// Owner and waiter edges of the wait-for graph.
+struct Edges {
+    /// Current owner of each held lock
+    owners: BTreeMap<LockAddr, ThreadId>,
+    /// Lock each blocked thread is waiting for
+    waiting: BTreeMap<ThreadId, LockAddr>,
+}
+
+/// Wait-for graph between threads and the locks they hold or wait on.
+pub struct LockWaitGraph {
+    edges: SpinLockIrqSave<Edges>,
+}
+
+impl LockWaitGraph {
+    /// Create an empty graph.
+    pub const fn new() -> Self {
+        Self {
+            edges: SpinLockIrqSave::new(Edges {
+                owners: BTreeMap::new(),
+                waiting: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Record that `owner` acquired `lock`.
+    ///
+    /// Also ends any wait by `owner`, since a thread that acquired a lock is
+    /// no longer blocked.
+    pub fn lock_acquired(&self, lock: LockAddr, owner: ThreadId) {
+        let mut edges = self.edges.lock();
+        edges.owners.insert(lock, owner);
+        edges.waiting.remove(&owner);
+    }
+
+    /// Record that `lock` was released.
+    pub fn lock_released(&self, lock: LockAddr) {
+        self.edges.lock().owners.remove(&lock);
+    }
+
+    /// Record that `thread` blocked waiting for `lock`.
+    pub fn wait_started(&self, thread: ThreadId, lock: LockAddr) {
+        self.edges.lock().waiting.insert(thread, lock);
+    }
+
+    /// Record that `thread` stopped waiting without acquiring the lock.
+    pub fn wait_ended(&self, thread: ThreadId) {
+        self.edges.lock().waiting.remove(&thread);
+    }
+
+    /// Forget every edge involving `thread`, e.g. when it exits.
+    pub fn forget_thread(&self, thread: ThreadId) {
+        let mut edges = self.edges.lock();
+        edges.waiting.remove(&thread);
+        edges.owners.retain(|_, owner| *owner != thread);
+    }
+
+    /// Get the current owner of `lock`.
+    pub fn owner(&self, lock: LockAddr) -> Option<ThreadId> {
+        self.edges.lock().owners.get(&lock).copied()
+    }
+
+    /// Get the lock `thread` is waiting for.
+    pub fn waiting_on(&self, thread: ThreadId) -> Option<LockAddr> {
+        self.edges.lock().waiting.get(&thread).copied()
+    }
+
+    /// Follow the wait-for chain starting at `thread`.
+    ///
+    /// Each element is a blocked thread and the lock it waits on; the next
+    /// element starts with that lock's owner. The chain is empty if `thread`
+    /// is not blocked. The owner of the last lock, if any, is not blocked
+    /// itself, unless it already appears on the chain (see
+    /// [`is_deadlock`](Self::is_deadlock)).
+    pub fn wait_chain(&self, thread: ThreadId) -> Vec<(ThreadId, LockAddr)> {
+        let edges = self.edges.lock();
+        Self::walk(&edges, thread)
+    }
+
+    /// Check whether `chain` ends by coming back to one of its own threads.
+    pub fn is_deadlock(&self, chain: &[(ThreadId, LockAddr)]) -> bool {
+        chain.last().map_or(false, |&(_, lock)| {
+            self.owner(lock)
+                .map_or(false, |owner| chain.iter().any(|&(thread, _)| thread == owner))
+        })
+    }
+
+    /// Find every deadlock cycle in the graph.
+    ///
+    /// Each cycle is reported once, starting from its lowest thread ID.
+    pub fn deadlocks(&self) -> Vec<Vec<(ThreadId, LockAddr)>> {
+        let edges = self.edges.lock();
+        let mut cycles = Vec::new();
+
+        for &start in edges.waiting.keys() {
+            let chain = Self::walk(&edges, start);
+            let Some(&(_, last)) = chain.last() else {
+                continue;
+            };
+
+            // Only report the cycle from the walk that starts on it at its
+            // lowest thread, so each one appears exactly once
+            let closes_on_start = edges.owners.get(&last) == Some(&start);
+            if closes_on_start && chain.iter().all(|&(thread, _)| thread >= start) {
+                cycles.push(chain);
+            }
+        }
+
+        cycles
+    }
+
+    fn walk(edges: &Edges, start: ThreadId) -> Vec<(ThreadId, LockAddr)> {
+        let mut chain = Vec::new();
+        let mut thread = start;
+
+        while let Some(&lock) = edges.waiting.get(&thread) {
+            chain.push((thread, lock));
+
+            let Some(&owner) = edges.owners.get(&lock) else {
+                break;
+            };
+            if chain.iter().any(|&(seen, _)| seen == owner) {
+                break; // Deadlock
+            }
+            thread = owner;
+        }
+
+        chain
+    }
+}
+
+impl Default for LockWaitGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global lock-wait graph maintained by blocking primitives.
+pub static LOCK_WAIT_GRAPH: LockWaitGraph = LockWaitGraph::new();
+
+/// Follow the wait-for chain of `thread` in the global graph.
+pub fn wait_chain(thread: ThreadId) -> Vec<(ThreadId, LockAddr)> {
+    LOCK_WAIT_GRAPH.wait_chain(thread)
+}
+
+/// Write a wait-for chain as text, one thread per line.
+///
+/// Thread names and priorities are looked up for threads that are still
+/// alive. The final line names the owner of the last lock, and whether it
+/// closes a deadlock or what else the owner is waiting for.
+pub fn render_chain<W: fmt::Write>(
+    graph: &LockWaitGraph,
+    chain: &[(ThreadId, LockAddr)],
+    out: &mut W,
+) -> fmt::Result {
+    let Some(&(_, last)) = chain.last() else {
+        return Ok(());
+    };
+
+    let mut inherited = 0u8;
+    for (index, &(thread, lock)) in chain.iter().enumerate() {
+        if index > 0 {
+            out.write_str("  held by ")?;
+        }
+        write_thread(out, thread, &mut inherited)?;
+        writeln!(out, " waits on lock {}", lock)?;
+    }
+
+    match graph.owner(last) {
+        Some(owner) if chain.iter().any(|&(thread, _)| thread == owner) => {
+            writeln!(out, "  held by thread {} (deadlock)", owner)
+        }
+        Some(owner) => {
+            out.write_str("  held by ")?;
+            write_thread(out, owner, &mut inherited)?;
+            match registry::lookup(owner).and_then(|thread| thread.blocked_on()) {
+                Some(reason) => writeln!(out, " waits on {}", reason),
+                None => writeln!(out, " running"),
+            }
+        }
+        None => writeln!(out, "  not held"),
+    }
+}
+
+/// Render the wait-for chain of `thread` in the global graph.
+pub fn format_wait_chain(thread: ThreadId) -> String {
+    let chain = wait_chain(thread);
+    let mut out = String::new();
+    let _ = render_chain(&LOCK_WAIT_GRAPH, &chain, &mut out);
+    out
+}
+
+/// Write one thread of a chain, updating the priority it inherits from the
+/// waiters before it.
+fn write_thread<W: fmt::Write>(out: &mut W, id: ThreadId, inherited: &mut u8) -> fmt::Result {
+    let Some(thread) = registry::lookup(id) else {
+        return write!(out, "thread {}", id);
+    };
+
+    write!(out, "thread {}", id)?;
+    if let Some(name) = thread.name() {
+        write!(out, " \"{}\"", name)?;
+    }
+
+    let priority = thread.priority();
+    if *inherited > priority {
+        write!(out, " (prio {}, inherits {})", priority, inherited)?;
+    } else {
+        write!(out, " (prio {})", priority)?;
+        *inherited = priority;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IDs well above those handed out to threads spawned by other tests,
+    // so the registry has no names or priorities for them
+    fn id(n: u64) -> ThreadId {
+        ThreadId::new(40_000 + n)
+    }
+
+    #[test]
+    fn test_wait_chain_and_deadlock() {
+        let graph = LockWaitGraph::new();
+        let (a, b, c) = (LockAddr(0x1000), LockAddr(0x2000), LockAddr(0x3000));
+
+        graph.lock_acquired(a, id(3));
+        graph.lock_acquired(c, id(3));
+        graph.lock_acquired(b, id(5));
+        graph.wait_started(id(7), a);
+        graph.wait_started(id(3), b);
+
+        assert_eq!(graph.wait_chain(id(7)), [(id(7), a), (id(3), b)]);
+        assert!(graph.wait_chain(id(5)).is_empty());
+        assert!(!graph.is_deadlock(&graph.wait_chain(id(7))));
+        assert!(graph.deadlocks().is_empty());
+
+        let mut text = String::new();
+        render_chain(&graph, &graph.wait_chain(id(7)), &mut text).unwrap();
+        assert_eq!(
+            text,
+            "thread 40007 waits on lock 0x1000\n  held by thread 40003 waits on lock 0x2000\n  held by thread 40005 running\n"
+        );
+
+        // Close the cycle 3 -> 5 -> 3
+        graph.wait_started(id(5), c);
+        assert!(graph.is_deadlock(&graph.wait_chain(id(7))));
+        assert_eq!(graph.deadlocks(), [[(id(3), b), (id(5), c)]]);
+
+        graph.forget_thread(id(5));
+        assert_eq!(graph.owner(b), None);
+        assert!(graph.deadlocks().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Lost wakeup alarms.
+//!
+//! A wakeup is lost when nothing ever notifies a blocked thread, such as a
+//! condition variable signalled just before its waiter started waiting.
+//! The thread never notices, it just stays blocked. With alarms enabled,
+//! every blocking operation arms one on the [timer wheel](TIMER_WHEEL) as
+//! it records its [`BlockedOn`] reason, and cancels it when the reason is
+//! cleared. An alarm that fires has caught a thread blocked on one
+//! operation for longer than the timeout, and the health monitor reports
+//! it as a concurrency issue with the reason and a capture of the thread's
+//! stack:
+//!
+//! ```ignore
+//! HEALTH_MONITOR.enable_lost_wakeup_alarms(Duration::from_millis(DEFAULT_TIMEOUT_MS));
+//! ```
+//!
+//! Sleeps are never alarmed, they end on their own deadline. Every other
+//! blocking operation costs a timer wheel entry, so alarms are off unless
+//! enabled, typically in debug builds or while chasing a hang in the field.
+//! Stacks are only captured with the `debug` feature.
+
+#[cfg(feature = "debug")]
+use crate::arch::{Arch, DefaultArch};
+#[cfg(feature = "debug")]
+use crate::debug::{self, DebugCapability};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{registry, BlockedOn, ThreadId};
+use crate::time::{Duration, Instant, Timeout, TIMER_WHEEL};
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+extern crate alloc;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+/// Timeout alarms are armed with unless given another one (10 s).
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+/// Stack words kept in a [`LostWakeup`].
+pub const STACK_CAPTURE_WORDS: usize = 32;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TIMEOUT_NS: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT_MS * 1_000_000);
+
+/// Alarm of a thread blocked on one operation.
+struct Alarm {
+    reason: BlockedOn,
+    since: Instant,
+    /// Cancelled when dropped
+    timeout: Timeout<'static>,
+}
+
+static ALARMS: SpinLockIrqSave<BTreeMap<ThreadId, Alarm>> = SpinLockIrqSave::new(BTreeMap::new());
+
+/// A thread still blocked when its alarm fired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LostWakeup {
+    pub thread: ThreadId,
+    pub name: Option<String>,
+    /// Operation the thread is blocked on
+    pub blocked_on: BlockedOn,
+    /// How long it has been blocked on it
+    pub blocked_for: Duration,
+    /// Stack pointer the thread was switched out with, if it has a context
+    pub stack_pointer: Option<usize>,
+    /// Program counter it resumes at, if the architecture records it
+    pub program_counter: Option<usize>,
+    /// Up to [`STACK_CAPTURE_WORDS`] words upwards from the stack pointer
+    pub stack: Vec<usize>,
+}
+
+/// Have every blocking operation from now on arm an alarm that fires after
+/// `timeout`.
+pub fn enable(timeout: Duration) {
+    TIMEOUT_NS.store(timeout.as_nanos(), Ordering::Release);
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// Stop arming alarms and cancel the armed ones.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+    let alarms = core::mem::take(&mut *ALARMS.lock());
+    // Cancelling takes the timer wheel lock, so not under ours
+    drop(alarms);
+}
+
+/// Check whether blocking operations arm alarms.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Arm the alarm of `thread`, which just blocked on `reason`.
+pub(crate) fn arm(thread: ThreadId, reason: BlockedOn) {
+    if !is_enabled() || matches!(reason, BlockedOn::Sleep(_)) {
+        return;
+    }
+    let timeout = TIMER_WHEEL.arm_for(Duration::from_nanos(TIMEOUT_NS.load(Ordering::Acquire)), None);
+    let alarm = Alarm {
+        reason,
+        since: Instant::now(),
+        timeout,
+    };
+    // A replaced alarm is cancelled once our lock is released
+    let replaced = ALARMS.lock().insert(thread, alarm);
+    drop(replaced);
+}
+
+/// Cancel the alarm of `thread`, which is no longer blocked.
+pub(crate) fn disarm(thread: ThreadId) {
+    if !is_enabled() {
+        return;
+    }
+    let alarm = ALARMS.lock().remove(&thread);
+    drop(alarm);
+}
+
+/// Get the threads whose alarm fired while they are still blocked on the
+/// operation that armed it, capturing their stacks.
+pub fn fired() -> Vec<LostWakeup> {
+    let due: Vec<(ThreadId, BlockedOn, Instant)> = ALARMS
+        .lock()
+        .iter()
+        .filter(|(_, alarm)| alarm.timeout.expired())
+        .map(|(&thread, alarm)| (thread, alarm.reason, alarm.since))
+        .collect();
+
+    let now = Instant::now();
+    due.into_iter()
+        .filter_map(|(id, reason, since)| {
+            let thread = registry::lookup(id)?;
+            if thread.blocked_on() != Some(reason) {
+                return None;
+            }
+            let mut lost = LostWakeup {
+                thread: id,
+                name: thread.name(),
+                blocked_on: reason,
+                blocked_for: now.duration_since(since),
+                stack_pointer: None,
+                program_counter: None,
+                stack: Vec::new(),
+            };
+            capture_stack(&mut lost);
+            Some(lost)
+        })
+        .collect()
+}
+
+/// Fill in the stack of the blocked thread `lost` is about, as far as its
+/// saved context allows.
+#[cfg(feature = "debug")]
+fn capture_stack(lost: &mut LostWakeup) {
+    // Safety: the capture only goes into health reports
+    let capability = unsafe { DebugCapability::new() };
+    let Ok(inspection) = debug::inspect(&capability, lost.thread) else {
+        return;
+    };
+    lost.stack_pointer = inspection.stack_pointer;
+    lost.program_counter = inspection.context.as_ref().and_then(DefaultArch::saved_program_counter);
+    lost.stack = inspection
+        .stack
+        .chunks_exact(core::mem::size_of::<usize>())
+        .take(STACK_CAPTURE_WORDS)
+        .map(|word| {
+            let mut bytes = [0; core::mem::size_of::<usize>()];
+            bytes.copy_from_slice(word);
+            usize::from_ne_bytes(bytes)
+        })
+        .collect();
+}
+
+/// Stacks are read through the in-target debugger, which is left out
+/// without the `debug` feature.
+#[cfg(not(feature = "debug"))]
+fn capture_stack(_lost: &mut LostWakeup) {}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::observability::health::{HealthChecker, IssueCategory, LostWakeupHealthChecker};
+    use crate::thread_new::{BlockedOnGuard, Thread};
+
+    #[test]
+    fn test_alarm_reports_thread_still_blocked() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let (thread, _join) = Thread::new(ThreadId::new(49_720), stack, || {}, 128);
+        let checker = LostWakeupHealthChecker::new();
+        let issue_of = |thread: &Thread| {
+            checker.check_health().issues.into_iter().find(|issue| issue.affected_threads == [thread.id()])
+        };
+
+        // A zero timeout fires as soon as it is armed
+        enable(Duration::from_nanos(0));
+        let blocked = BlockedOnGuard::record(Some(thread.clone()), BlockedOn::Condvar(0x1000));
+        let issue = issue_of(&thread).unwrap();
+        assert_eq!(issue.category, IssueCategory::Concurrency);
+        assert_eq!(issue.context["blocked_on"], "condvar 0x1000");
+
+        // Waking up cancels the alarm
+        drop(blocked);
+        assert!(issue_of(&thread).is_none());
+
+        // Sleeps are never alarmed
+        let _sleeping = BlockedOnGuard::record(Some(thread.clone()), BlockedOn::Sleep(Instant::now()));
+        assert!(issue_of(&thread).is_none());
+        disable();
+    }
+}
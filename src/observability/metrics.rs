@@ -5,7 +5,9 @@
 
 use portable_atomic::{AtomicU64, AtomicU32, AtomicBool, Ordering};
 use crate::time::{Instant, Duration};
-use crate::thread_new::ThreadId;
+use crate::thread_new::{BlockedOn, ThreadId};
+use super::inversion::PriorityInversion;
+use crate::perf::pmu::PmuSample;
 extern crate alloc;
 use alloc::{vec::Vec, collections::BTreeMap};
 use spin::Mutex;
@@ -47,6 +49,18 @@ pub struct ThreadMetrics {
     pub total_memory_allocated: u64,
     /// Current memory usage (bytes)
     pub current_memory_usage: u64,
+    /// Highest memory usage seen (bytes)
+    pub peak_memory_usage: u64,
+    /// Cycles counted by the [PMU](crate::perf::pmu) while running
+    pub cycles: u64,
+    /// Instructions retired while running
+    pub instructions: u64,
+    /// Cache misses while running
+    pub cache_misses: u64,
+    /// Time spent [waiting for I/O](crate::thread_new::io_wait) (nanoseconds)
+    pub io_wait_ns: u64,
+    /// Time spent blocked on anything but I/O (nanoseconds)
+    pub blocked_ns: u64,
 }
 
 impl ThreadMetrics {
@@ -71,6 +85,12 @@ impl ThreadMetrics {
             memory_deallocations: 0,
             total_memory_allocated: 0,
             current_memory_usage: 0,
+            peak_memory_usage: 0,
+            cycles: 0,
+            instructions: 0,
+            cache_misses: 0,
+            io_wait_ns: 0,
+            blocked_ns: 0,
         }
     }
     
@@ -85,6 +105,16 @@ impl ThreadMetrics {
         self.last_active = Instant::now();
     }
     
+    /// Record a run slice of `ran` that ended at `ended_at`.
+    ///
+    /// Run slices are measured by the context switch path and counted as
+    /// user time.
+    pub fn add_run_slice(&mut self, ran: Duration, ended_at: Instant) {
+        self.user_time_ns += ran.as_nanos();
+        self.cpu_time_ns += ran.as_nanos();
+        self.last_active = ended_at;
+    }
+    
     /// Record a context switch.
     pub fn record_context_switch(&mut self, voluntary: bool) {
         self.context_switches += 1;
@@ -109,6 +139,7 @@ impl ThreadMetrics {
         self.memory_allocations += 1;
         self.total_memory_allocated += size;
         self.current_memory_usage += size;
+        self.peak_memory_usage = self.peak_memory_usage.max(self.current_memory_usage);
     }
     
     /// Record memory deallocation.
@@ -119,6 +150,40 @@ impl ThreadMetrics {
         }
     }
     
+    /// Record the PMU counts of a run slice.
+    pub fn add_pmu_sample(&mut self, sample: &PmuSample) {
+        self.cycles += sample.cycles;
+        self.instructions += sample.instructions;
+        self.cache_misses += sample.cache_misses;
+    }
+    
+    /// Record a wait of `waited` while blocked, for I/O if `io`.
+    pub fn add_blocked_time(&mut self, waited: Duration, io: bool) {
+        if io {
+            self.io_wait_ns += waited.as_nanos();
+        } else {
+            self.blocked_ns += waited.as_nanos();
+        }
+    }
+    
+    /// Calculate instructions retired per cycle, 0.0 without PMU samples.
+    pub fn ipc(&self) -> f64 {
+        if self.cycles > 0 {
+            self.instructions as f64 / self.cycles as f64
+        } else {
+            0.0
+        }
+    }
+    
+    /// Calculate cache misses per thousand instructions.
+    pub fn cache_miss_rate(&self) -> f64 {
+        if self.instructions > 0 {
+            self.cache_misses as f64 * 1000.0 / self.instructions as f64
+        } else {
+            0.0
+        }
+    }
+    
     /// Calculate CPU utilization percentage.
     pub fn cpu_utilization(&self) -> f64 {
         let total_time = self.last_active.duration_since(self.created_at).as_nanos() as f64;
@@ -139,48 +204,90 @@ impl ThreadMetrics {
     }
 }
 
-/// System-wide metrics.
-#[derive(Debug)]
+/// Snapshot of system-wide metrics.
+///
+/// ```ignore
+/// let metrics = SystemMetrics::current();
+/// println!("CPU utilization: {:.1}%", metrics.cpu_utilization * 100.0);
+/// println!("Memory usage: {}KB", metrics.memory_usage_kb);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct SystemMetrics {
     /// Total number of threads created
-    pub threads_created: AtomicU64,
+    pub threads_created: u64,
+    /// Total number of threads destroyed
+    pub threads_destroyed: u64,
+    /// Current number of active threads
+    pub active_threads: u64,
+    /// Total context switches across all threads
+    pub total_context_switches: u64,
+    /// Total CPU time across all threads (nanoseconds)
+    pub total_cpu_time_ns: u64,
+    /// Time since metrics collection started (nanoseconds)
+    pub uptime_ns: u64,
+    /// Fraction of the active threads' time spent on a CPU (0.0 to 1.0)
+    pub cpu_utilization: f64,
+    /// Heap memory in use by threads (KiB)
+    pub memory_usage_kb: u64,
+    /// Peak heap memory in use by threads (KiB)
+    pub peak_memory_usage_kb: u64,
+    /// Fraction of scheduler decisions that ended in a context switch
+    /// (0.0 to 1.0)
+    pub scheduler_efficiency: f64,
+    /// Waits on a less important lock owner that outlasted the
+    /// [inversion threshold](super::inversion::threshold)
+    pub priority_inversions: u64,
+    /// Time all threads spent waiting for I/O (nanoseconds)
+    pub total_io_wait_ns: u64,
+    /// Time all threads spent blocked on anything but I/O (nanoseconds)
+    pub total_blocked_ns: u64,
+}
+
+impl SystemMetrics {
+    /// Take a snapshot of the global metrics collector's counters.
+    pub fn current() -> Self {
+        GLOBAL_METRICS.system_metrics()
+    }
+}
+
+/// Atomic system-wide counters behind [`SystemMetrics`].
+#[derive(Debug)]
+pub(crate) struct SystemCounters {
+    /// Total number of threads created
+    pub(crate) threads_created: AtomicU64,
     /// Total number of threads destroyed
-    pub threads_destroyed: AtomicU64,
+    pub(crate) threads_destroyed: AtomicU64,
     /// Current number of active threads
-    pub active_threads: AtomicU64,
+    pub(crate) active_threads: AtomicU64,
     /// Total context switches across all threads
-    pub total_context_switches: AtomicU64,
+    pub(crate) total_context_switches: AtomicU64,
     /// Total CPU time across all threads (nanoseconds)
-    pub total_cpu_time_ns: AtomicU64,
-    /// System uptime (nanoseconds)
-    pub system_uptime_ns: AtomicU64,
-    /// Timer interrupts processed
-    pub timer_interrupts: AtomicU64,
+    pub(crate) total_cpu_time_ns: AtomicU64,
+    /// When counting started (nanoseconds)
+    pub(crate) system_uptime_ns: AtomicU64,
     /// Scheduler decisions made
-    pub scheduler_decisions: AtomicU64,
-    /// Load balancing operations
-    pub load_balance_ops: AtomicU64,
-    /// Memory pool allocations
-    pub pool_allocations: AtomicU64,
-    /// Memory pool deallocations
-    pub pool_deallocations: AtomicU64,
-    /// Stack overflows detected
-    pub stack_overflows: AtomicU64,
-    /// Priority inversions detected
-    pub priority_inversions: AtomicU64,
-    /// Deadlocks detected
-    pub deadlocks_detected: AtomicU64,
-    /// System start time
-    pub system_start_time: Instant,
+    pub(crate) scheduler_decisions: AtomicU64,
     /// Peak memory usage (bytes)
-    pub peak_memory_usage: AtomicU64,
+    pub(crate) peak_memory_usage: AtomicU64,
     /// Current memory usage (bytes)
-    pub current_memory_usage: AtomicU64,
+    pub(crate) current_memory_usage: AtomicU64,
+    /// Outermost preemption-disabled sections entered
+    pub(crate) preempt_disables: AtomicU64,
+    /// Deepest preemption-disable nesting observed
+    pub(crate) max_preempt_depth: AtomicU64,
+    /// Blocking calls made with preemption disabled or in IRQ context
+    pub(crate) might_sleep_violations: AtomicU64,
+    /// Waits on a less important lock owner past the inversion threshold
+    pub(crate) priority_inversions: AtomicU64,
+    /// Time all threads spent waiting for I/O (nanoseconds)
+    pub(crate) total_io_wait_ns: AtomicU64,
+    /// Time all threads spent blocked on anything but I/O (nanoseconds)
+    pub(crate) total_blocked_ns: AtomicU64,
 }
 
-impl SystemMetrics {
-    /// Create new system metrics.
-    pub const fn new() -> Self {
+impl SystemCounters {
+    /// Create zeroed counters.
+    pub(crate) const fn new() -> Self {
         Self {
             threads_created: AtomicU64::new(0),
             threads_destroyed: AtomicU64::new(0),
@@ -188,87 +295,97 @@ impl SystemMetrics {
             total_context_switches: AtomicU64::new(0),
             total_cpu_time_ns: AtomicU64::new(0),
             system_uptime_ns: AtomicU64::new(0),
-            timer_interrupts: AtomicU64::new(0),
             scheduler_decisions: AtomicU64::new(0),
-            load_balance_ops: AtomicU64::new(0),
-            pool_allocations: AtomicU64::new(0),
-            pool_deallocations: AtomicU64::new(0),
-            stack_overflows: AtomicU64::new(0),
-            priority_inversions: AtomicU64::new(0),
-            deadlocks_detected: AtomicU64::new(0),
-            system_start_time: unsafe { core::mem::transmute(0u64) }, // Will be initialized properly
             peak_memory_usage: AtomicU64::new(0),
             current_memory_usage: AtomicU64::new(0),
+            preempt_disables: AtomicU64::new(0),
+            max_preempt_depth: AtomicU64::new(0),
+            might_sleep_violations: AtomicU64::new(0),
+            priority_inversions: AtomicU64::new(0),
+            total_io_wait_ns: AtomicU64::new(0),
+            total_blocked_ns: AtomicU64::new(0),
         }
     }
     
-    /// Initialize system metrics with current time.
-    pub fn init(&self) {
+    /// Start counting uptime from now.
+    pub(crate) fn init(&self) {
         let start_time = Instant::now();
-        // We can't modify system_start_time after const initialization in a safe way,
-        // so we'll track uptime relative to when metrics collection started
         self.system_uptime_ns.store(start_time.as_nanos(), Ordering::Release);
     }
     
     /// Record thread creation.
-    pub fn record_thread_created(&self) {
+    pub(crate) fn record_thread_created(&self) {
         self.threads_created.fetch_add(1, Ordering::AcqRel);
         self.active_threads.fetch_add(1, Ordering::AcqRel);
     }
     
     /// Record thread destruction.
-    pub fn record_thread_destroyed(&self) {
+    pub(crate) fn record_thread_destroyed(&self) {
         self.threads_destroyed.fetch_add(1, Ordering::AcqRel);
         self.active_threads.fetch_sub(1, Ordering::AcqRel);
     }
     
     /// Record a context switch.
-    pub fn record_context_switch(&self) {
+    pub(crate) fn record_context_switch(&self) {
         self.total_context_switches.fetch_add(1, Ordering::AcqRel);
     }
     
     /// Add CPU time to system total.
-    pub fn add_cpu_time(&self, duration: Duration) {
+    pub(crate) fn add_cpu_time(&self, duration: Duration) {
         self.total_cpu_time_ns.fetch_add(duration.as_nanos(), Ordering::AcqRel);
     }
     
-    /// Record timer interrupt.
-    pub fn record_timer_interrupt(&self) {
-        self.timer_interrupts.fetch_add(1, Ordering::AcqRel);
-    }
-    
     /// Record scheduler decision.
-    pub fn record_scheduler_decision(&self) {
+    pub(crate) fn record_scheduler_decision(&self) {
         self.scheduler_decisions.fetch_add(1, Ordering::AcqRel);
     }
     
-    /// Update memory usage.
-    pub fn update_memory_usage(&self, new_usage: u64) {
-        self.current_memory_usage.store(new_usage, Ordering::Release);
-        
-        // Update peak if necessary
-        let current_peak = self.peak_memory_usage.load(Ordering::Acquire);
-        if new_usage > current_peak {
-            let _ = self.peak_memory_usage.compare_exchange_weak(
-                current_peak,
-                new_usage,
-                Ordering::AcqRel,
-                Ordering::Acquire,
-            );
+    /// Record `count` scheduler decisions at once.
+    pub(crate) fn record_scheduler_decisions(&self, count: u64) {
+        self.scheduler_decisions.fetch_add(count, Ordering::AcqRel);
+    }
+    
+    /// Record entry into a preemption-disabled section at the given depth.
+    pub(crate) fn record_preempt_disable(&self, depth: usize) {
+        if depth == 1 {
+            self.preempt_disables.fetch_add(1, Ordering::Relaxed);
         }
+        self.max_preempt_depth.fetch_max(depth as u64, Ordering::Relaxed);
+    }
+    
+    /// Record a blocking call made from atomic context.
+    pub(crate) fn record_might_sleep_violation(&self) {
+        self.might_sleep_violations.fetch_add(1, Ordering::AcqRel);
+    }
+    
+    /// Record a priority inversion that outlasted the threshold.
+    pub(crate) fn record_priority_inversion(&self) {
+        self.priority_inversions.fetch_add(1, Ordering::AcqRel);
+    }
+    
+    /// Add a wait of `waited` while blocked, for I/O if `io`.
+    pub(crate) fn add_blocked_time(&self, waited: Duration, io: bool) {
+        let total = if io { &self.total_io_wait_ns } else { &self.total_blocked_ns };
+        total.fetch_add(waited.as_nanos(), Ordering::AcqRel);
+    }
+    
+    /// Calculate the fraction of the active threads' time they spent on a
+    /// CPU.
+    pub(crate) fn cpu_utilization(&self) -> f64 {
+        self.cpu_utilization_at(Instant::now())
     }
     
-    /// Calculate system CPU utilization.
-    pub fn system_cpu_utilization(&self) -> f64 {
+    /// Calculate the CPU utilization as of `now`.
+    fn cpu_utilization_at(&self, now: Instant) -> f64 {
         let total_cpu = self.total_cpu_time_ns.load(Ordering::Acquire) as f64;
         let uptime_start = self.system_uptime_ns.load(Ordering::Acquire);
-        let current_time = Instant::now().as_nanos();
+        let current_time = now.as_nanos();
         
         if current_time > uptime_start {
             let uptime = (current_time - uptime_start) as f64;
             let active = self.active_threads.load(Ordering::Acquire) as f64;
             if uptime > 0.0 && active > 0.0 {
-                (total_cpu / (uptime * active)) * 100.0
+                (total_cpu / (uptime * active)).min(1.0)
             } else {
                 0.0
             }
@@ -277,8 +394,49 @@ impl SystemMetrics {
         }
     }
     
+    /// Calculate the fraction of scheduler decisions that ended in a
+    /// context switch.
+    pub(crate) fn scheduler_efficiency(&self) -> f64 {
+        let decisions = self.scheduler_decisions.load(Ordering::Acquire);
+        let switches = self.total_context_switches.load(Ordering::Acquire);
+        if decisions > 0 {
+            (switches as f64 / decisions as f64).min(1.0)
+        } else {
+            0.0
+        }
+    }
+    
+    /// Get the time from the start of counting to `now`.
+    fn uptime_ns_at(&self, now: Instant) -> u64 {
+        now.as_nanos().saturating_sub(self.system_uptime_ns.load(Ordering::Acquire))
+    }
+    
+    /// Take a snapshot with the derived values computed.
+    pub(crate) fn snapshot(&self) -> SystemMetrics {
+        self.snapshot_at(Instant::now())
+    }
+    
+    /// Take a snapshot with the derived values computed as of `now`.
+    fn snapshot_at(&self, now: Instant) -> SystemMetrics {
+        SystemMetrics {
+            threads_created: self.threads_created.load(Ordering::Acquire),
+            threads_destroyed: self.threads_destroyed.load(Ordering::Acquire),
+            active_threads: self.active_threads.load(Ordering::Acquire),
+            total_context_switches: self.total_context_switches.load(Ordering::Acquire),
+            total_cpu_time_ns: self.total_cpu_time_ns.load(Ordering::Acquire),
+            uptime_ns: self.uptime_ns_at(now),
+            cpu_utilization: self.cpu_utilization_at(now),
+            memory_usage_kb: self.current_memory_usage.load(Ordering::Acquire) / 1024,
+            peak_memory_usage_kb: self.peak_memory_usage.load(Ordering::Acquire) / 1024,
+            scheduler_efficiency: self.scheduler_efficiency(),
+            priority_inversions: self.priority_inversions.load(Ordering::Acquire),
+            total_io_wait_ns: self.total_io_wait_ns.load(Ordering::Acquire),
+            total_blocked_ns: self.total_blocked_ns.load(Ordering::Acquire),
+        }
+    }
+    
     /// Get average context switches per second.
-    pub fn context_switches_per_second(&self) -> f64 {
+    pub(crate) fn context_switches_per_second(&self) -> f64 {
         let switches = self.total_context_switches.load(Ordering::Acquire) as f64;
         let uptime_start = self.system_uptime_ns.load(Ordering::Acquire);
         let current_time = Instant::now().as_nanos();
@@ -298,10 +456,12 @@ impl SystemMetrics {
 
 /// Metrics collector that aggregates and manages all metrics.
 pub struct MetricsCollector {
-    /// System-wide metrics
-    system_metrics: SystemMetrics,
+    /// System-wide counters
+    system_metrics: SystemCounters,
     /// Per-thread metrics storage
     thread_metrics: Mutex<BTreeMap<ThreadId, ThreadMetrics>>,
+    /// I/O waits attributed to devices, by device ID
+    device_io_waits: Mutex<BTreeMap<u32, DeviceIoWait>>,
     /// Collection enabled flag
     enabled: AtomicBool,
     /// Collection interval
@@ -312,8 +472,9 @@ impl MetricsCollector {
     /// Create a new metrics collector.
     pub const fn new() -> Self {
         Self {
-            system_metrics: SystemMetrics::new(),
+            system_metrics: SystemCounters::new(),
             thread_metrics: Mutex::new(BTreeMap::new()),
+            device_io_waits: Mutex::new(BTreeMap::new()),
             enabled: AtomicBool::new(false),
             collection_interval_ms: AtomicU32::new(1000),
         }
@@ -339,7 +500,12 @@ impl MetricsCollector {
         }
         
         if let Some(mut metrics) = self.thread_metrics.try_lock() {
-            metrics.insert(thread_id, ThreadMetrics::new(thread_id));
+            // Timestamp on the same clock as run slices so that
+            // `cpu_utilization` compares like with like
+            let mut thread_metrics = ThreadMetrics::new(thread_id);
+            thread_metrics.created_at = crate::time::cpu_clock();
+            thread_metrics.last_active = thread_metrics.created_at;
+            metrics.insert(thread_id, thread_metrics);
         }
         
         self.system_metrics.record_thread_created();
@@ -373,6 +539,76 @@ impl MetricsCollector {
         self.system_metrics.add_cpu_time(duration);
     }
     
+    /// Record a run slice measured by the context switch path.
+    pub fn record_run_slice(&self, thread_id: ThreadId, ran: Duration, ended_at: Instant) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        if let Some(mut metrics) = self.thread_metrics.try_lock() {
+            if let Some(thread_metrics) = metrics.get_mut(&thread_id) {
+                thread_metrics.add_run_slice(ran, ended_at);
+            }
+        }
+        
+        self.system_metrics.add_cpu_time(ran);
+    }
+    
+    /// Record the PMU counts of a run slice measured by the context switch
+    /// path.
+    pub fn record_pmu_sample(&self, thread_id: ThreadId, sample: &PmuSample) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        if let Some(mut metrics) = self.thread_metrics.try_lock() {
+            if let Some(thread_metrics) = metrics.get_mut(&thread_id) {
+                thread_metrics.add_pmu_sample(sample);
+            }
+        }
+    }
+    
+    /// Record a wait of `waited` by a thread blocked on `reason`, as I/O
+    /// wait if [it is one](BlockedOn::is_io_wait), attributed to the
+    /// device if the reason names one.
+    pub fn record_blocked_time(&self, thread_id: ThreadId, reason: BlockedOn, waited: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        let io = reason.is_io_wait();
+        if let Some(mut metrics) = self.thread_metrics.try_lock() {
+            if let Some(thread_metrics) = metrics.get_mut(&thread_id) {
+                thread_metrics.add_blocked_time(waited, io);
+            }
+        }
+        
+        if let Some(device) = reason.device() {
+            if let Some(mut waits) = self.device_io_waits.try_lock() {
+                let wait = waits.entry(device).or_insert(DeviceIoWait {
+                    device,
+                    waits: 0,
+                    wait_ns: 0,
+                    max_wait_ns: 0,
+                });
+                wait.waits += 1;
+                wait.wait_ns += waited.as_nanos();
+                wait.max_wait_ns = wait.max_wait_ns.max(waited.as_nanos());
+            }
+        }
+        
+        self.system_metrics.add_blocked_time(waited, io);
+    }
+    
+    /// Get the I/O waits attributed to each device, in device ID order.
+    pub fn device_io_waits(&self) -> Vec<DeviceIoWait> {
+        if let Some(waits) = self.device_io_waits.try_lock() {
+            waits.values().copied().collect()
+        } else {
+            Vec::new()
+        }
+    }
+    
     /// Record a context switch for a thread.
     pub fn record_context_switch(&self, thread_id: ThreadId, voluntary: bool) {
         if !self.is_enabled() {
@@ -388,6 +624,41 @@ impl MetricsCollector {
         self.system_metrics.record_context_switch();
     }
     
+    /// Record a heap allocation made by a thread.
+    pub fn record_memory_allocation(&self, thread_id: ThreadId, size: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        if let Some(mut metrics) = self.thread_metrics.try_lock() {
+            if let Some(thread_metrics) = metrics.get_mut(&thread_id) {
+                thread_metrics.record_allocation(size);
+            }
+        }
+        
+        let current = self.system_metrics.current_memory_usage.fetch_add(size, Ordering::AcqRel) + size;
+        self.system_metrics.peak_memory_usage.fetch_max(current, Ordering::AcqRel);
+    }
+    
+    /// Record a heap block allocated by a thread being freed.
+    pub fn record_memory_deallocation(&self, thread_id: ThreadId, size: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        if let Some(mut metrics) = self.thread_metrics.try_lock() {
+            if let Some(thread_metrics) = metrics.get_mut(&thread_id) {
+                thread_metrics.record_deallocation(size);
+            }
+        }
+        
+        let _ = self.system_metrics.current_memory_usage.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |current| Some(current.saturating_sub(size)),
+        );
+    }
+    
     /// Update stack usage for a thread.
     pub fn update_stack_usage(&self, thread_id: ThreadId, usage: usize) {
         if !self.is_enabled() {
@@ -410,8 +681,13 @@ impl MetricsCollector {
         }
     }
     
-    /// Get system-wide metrics.
-    pub fn get_system_metrics(&self) -> &SystemMetrics {
+    /// Get a snapshot of the system-wide metrics.
+    pub fn system_metrics(&self) -> SystemMetrics {
+        self.system_metrics.snapshot()
+    }
+    
+    /// Get the system-wide counters, for recording into.
+    pub(crate) fn system_counters(&self) -> &SystemCounters {
         &self.system_metrics
     }
     
@@ -429,6 +705,9 @@ impl MetricsCollector {
         if let Some(mut metrics) = self.thread_metrics.try_lock() {
             metrics.clear();
         }
+        if let Some(mut waits) = self.device_io_waits.try_lock() {
+            waits.clear();
+        }
         
         // Reset system metrics (keeping start time)
         let start_time = self.system_metrics.system_uptime_ns.load(Ordering::Acquire);
@@ -437,8 +716,14 @@ impl MetricsCollector {
         self.system_metrics.active_threads.store(0, Ordering::Release);
         self.system_metrics.total_context_switches.store(0, Ordering::Release);
         self.system_metrics.total_cpu_time_ns.store(0, Ordering::Release);
-        self.system_metrics.timer_interrupts.store(0, Ordering::Release);
         self.system_metrics.scheduler_decisions.store(0, Ordering::Release);
+        self.system_metrics.preempt_disables.store(0, Ordering::Release);
+        self.system_metrics.max_preempt_depth.store(0, Ordering::Release);
+        self.system_metrics.might_sleep_violations.store(0, Ordering::Release);
+        self.system_metrics.priority_inversions.store(0, Ordering::Release);
+        self.system_metrics.total_io_wait_ns.store(0, Ordering::Release);
+        self.system_metrics.total_blocked_ns.store(0, Ordering::Release);
+        super::inversion::clear();
         self.system_metrics.system_uptime_ns.store(start_time, Ordering::Release);
     }
     
@@ -450,10 +735,16 @@ impl MetricsCollector {
             active_threads: self.system_metrics.active_threads.load(Ordering::Acquire),
             total_context_switches: self.system_metrics.total_context_switches.load(Ordering::Acquire),
             total_cpu_time_ns: self.system_metrics.total_cpu_time_ns.load(Ordering::Acquire),
-            cpu_utilization: self.system_metrics.system_cpu_utilization(),
+            cpu_utilization: self.system_metrics.cpu_utilization(),
             context_switches_per_second: self.system_metrics.context_switches_per_second(),
             current_memory_usage: self.system_metrics.current_memory_usage.load(Ordering::Acquire),
             peak_memory_usage: self.system_metrics.peak_memory_usage.load(Ordering::Acquire),
+            preempt_disables: self.system_metrics.preempt_disables.load(Ordering::Acquire),
+            max_preempt_depth: self.system_metrics.max_preempt_depth.load(Ordering::Acquire),
+            might_sleep_violations: self.system_metrics.might_sleep_violations.load(Ordering::Acquire),
+            priority_inversions: self.system_metrics.priority_inversions.load(Ordering::Acquire),
+            total_io_wait_ns: self.system_metrics.total_io_wait_ns.load(Ordering::Acquire),
+            total_blocked_ns: self.system_metrics.total_blocked_ns.load(Ordering::Acquire),
         };
         
         let threads = self.get_all_thread_metrics();
@@ -462,6 +753,8 @@ impl MetricsCollector {
             system,
             threads,
             timestamp: Instant::now(),
+            priority_inversions: super::inversion::recorded(),
+            io_wait_by_device: self.device_io_waits(),
         }
     }
 }
@@ -478,6 +771,24 @@ pub struct SystemMetricsSnapshot {
     pub context_switches_per_second: f64,
     pub current_memory_usage: u64,
     pub peak_memory_usage: u64,
+    pub preempt_disables: u64,
+    pub max_preempt_depth: u64,
+    pub might_sleep_violations: u64,
+    pub priority_inversions: u64,
+    pub total_io_wait_ns: u64,
+    pub total_blocked_ns: u64,
+}
+
+/// I/O waits attributed to one device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIoWait {
+    pub device: u32,
+    /// Waits that ended
+    pub waits: u64,
+    /// Time spent in them (nanoseconds)
+    pub wait_ns: u64,
+    /// Longest of them (nanoseconds)
+    pub max_wait_ns: u64,
 }
 
 /// Complete metrics report.
@@ -486,6 +797,10 @@ pub struct MetricsReport {
     pub system: SystemMetricsSnapshot,
     pub threads: Vec<ThreadMetrics>,
     pub timestamp: Instant,
+    /// Most recent inversions, oldest first
+    pub priority_inversions: Vec<PriorityInversion>,
+    /// I/O waits by device, in device ID order
+    pub io_wait_by_device: Vec<DeviceIoWait>,
 }
 
 /// Global metrics collector instance.
@@ -499,4 +814,43 @@ pub fn init_metrics_collector(interval_ms: u32) -> Result<(), &'static str> {
 /// Cleanup metrics collection.
 pub fn cleanup_metrics() {
     GLOBAL_METRICS.enabled.store(false, Ordering::Release);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_metrics_derived_fields() {
+        let counters = SystemCounters::new();
+        counters.system_uptime_ns.store(1_000_000_000, Ordering::Release);
+        counters.active_threads.store(2, Ordering::Release);
+        counters.total_cpu_time_ns.store(1_000_000_000, Ordering::Release);
+        counters.scheduler_decisions.store(8, Ordering::Release);
+        counters.total_context_switches.store(6, Ordering::Release);
+        counters.current_memory_usage.store(5_000, Ordering::Release);
+        counters.peak_memory_usage.store(10_240, Ordering::Release);
+
+        // Two threads over four seconds, one second of it on a CPU
+        let metrics = counters.snapshot_at(Instant::from_nanos(5_000_000_000));
+        assert_eq!(metrics.uptime_ns, 4_000_000_000);
+        assert_eq!(metrics.cpu_utilization, 0.125);
+        assert_eq!(metrics.scheduler_efficiency, 0.75);
+        assert_eq!((metrics.memory_usage_kb, metrics.peak_memory_usage_kb), (4, 10));
+        assert_eq!((metrics.active_threads, metrics.total_context_switches), (2, 6));
+
+        // Both ratios are capped at 1.0
+        counters.total_cpu_time_ns.store(100_000_000_000, Ordering::Release);
+        counters.total_context_switches.store(9, Ordering::Release);
+        let metrics = counters.snapshot_at(Instant::from_nanos(5_000_000_000));
+        assert_eq!((metrics.cpu_utilization, metrics.scheduler_efficiency), (1.0, 1.0));
+
+        // And zero with nothing to divide by
+        counters.active_threads.store(0, Ordering::Release);
+        counters.scheduler_decisions.store(0, Ordering::Release);
+        let metrics = counters.snapshot_at(Instant::from_nanos(5_000_000_000));
+        assert_eq!((metrics.cpu_utilization, metrics.scheduler_efficiency), (0.0, 0.0));
+        let metrics = counters.snapshot_at(Instant::from_nanos(500_000_000));
+        assert_eq!(metrics.uptime_ns, 0);
+    }
+}
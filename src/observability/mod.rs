@@ -7,11 +7,33 @@ pub mod metrics;
 pub mod resource_limits;
 pub mod profiler;
 pub mod health;
+pub mod alloc;
+pub mod export;
+pub mod wire;
+pub mod lock_chain;
+pub mod hub;
+pub mod trace;
+pub mod herd;
+pub mod critical;
+pub mod wake_latency;
+pub mod lost_wakeup;
+pub mod inversion;
 
 pub use metrics::{ThreadMetrics, SystemMetrics, MetricsCollector, GLOBAL_METRICS};
 pub use resource_limits::{ResourceLimiter, ResourceUsage, ResourceQuota, LimitViolation};
 pub use profiler::{ThreadProfiler, ProfileData, ProfilerConfig, GLOBAL_PROFILER};
-pub use health::{HealthMonitor, HealthStatus, SystemHealth, HEALTH_MONITOR};
+pub use health::{HealthMonitor, HealthStatus, HealthSummary, SystemHealth, HEALTH_MONITOR};
+pub use alloc::TrackingAllocator;
+pub use export::{CborEncoder, Export, JsonEncoder, ReportEncoder};
+pub use hub::{ObserverHub, ObserverId, ThreadObserver, OBSERVER_HUB};
+pub use trace::{TraceBuffer, TraceEvent, TraceRecord, Tracepoint, TRACE_BUFFER};
+pub use herd::{worst_wait_points, WaitPointStats};
+pub use critical::{longest_windows, CriticalWindow, SectionKind};
+pub use wake_latency::{LatencyHistogram, SloId, SloScope, WakeLatencySlo};
+pub use lost_wakeup::LostWakeup;
+pub use inversion::PriorityInversion;
+pub use lock_chain::{render_chain, wait_chain, LockAddr, LockWaitGraph, LOCK_WAIT_GRAPH};
+pub use wire::{write_record, RecordHeader, RecordKind, WireError, WireRecord};
 
 /// Global observability configuration.
 #[derive(Debug, Clone)]
@@ -5,11 +5,11 @@
 //! real-time monitoring and violation detection.
 
 use portable_atomic::{AtomicU64, AtomicUsize, AtomicBool, Ordering};
-use crate::time::{Duration, Instant};
-use crate::thread_new::ThreadId;
+use crate::time::{Duration, Instant, RateLimiter};
+use crate::thread_new::{hierarchy, ThreadId};
 use crate::errors::{ResourceError, ThreadError};
 extern crate alloc;
-use alloc::{vec::Vec, collections::BTreeMap};
+use alloc::{vec::Vec, collections::BTreeMap, sync::Arc};
 use spin::Mutex;
 
 /// Resource usage tracking for a single thread.
@@ -186,6 +186,9 @@ pub struct LimitViolation {
     pub suggested_action: ViolationAction,
 }
 
+/// Number of [`ResourceType`] variants.
+const RESOURCE_TYPES: usize = 7;
+
 /// Types of resources that can be limited.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResourceType {
@@ -231,6 +234,10 @@ pub struct ResourceLimiter {
     violations: Mutex<Vec<LimitViolation>>,
     /// Maximum violations to keep in history
     max_violation_history: AtomicUsize,
+    /// Per-resource action overrides, indexed by `ResourceType`
+    actions: Mutex<[Option<ViolationAction>; RESOURCE_TYPES]>,
+    /// Token buckets enforcing per-second quotas, by thread and `ResourceType`
+    rate_limiters: Mutex<BTreeMap<(ThreadId, usize), Arc<RateLimiter>>>,
 }
 
 /// System-wide resource usage tracking.
@@ -283,6 +290,8 @@ impl ResourceLimiter {
             enabled: AtomicBool::new(false),
             violations: Mutex::new(Vec::new()),
             max_violation_history: AtomicUsize::new(1000),
+            actions: Mutex::new([None; RESOURCE_TYPES]),
+            rate_limiters: Mutex::new(BTreeMap::new()),
         }
     }
     
@@ -349,6 +358,9 @@ impl ResourceLimiter {
         if let Some(mut quotas) = self.thread_quotas.try_lock() {
             quotas.remove(&thread_id);
         }
+        if let Some(mut limiters) = self.rate_limiters.try_lock() {
+            limiters.retain(|&(owner, _), _| owner != thread_id);
+        }
         
         self.system_usage.total_threads.fetch_sub(1, Ordering::AcqRel);
     }
@@ -388,25 +400,22 @@ impl ResourceLimiter {
                 limit,
                 hard_limit: quota.hard_limits,
                 timestamp: Instant::now(),
-                suggested_action: if quota.hard_limits {
-                    ViolationAction::Terminate
-                } else {
-                    ViolationAction::Warn
-                },
+                suggested_action: self.violation_action(resource_type, quota.hard_limits),
             };
             
             self.handle_violation(violation.clone());
             
             if quota.hard_limits {
-                match resource_type {
-                    ResourceType::CpuTime => Err(ThreadError::Resource(ResourceError::MaxCpuTime)),
-                    ResourceType::Memory => Err(ThreadError::Resource(ResourceError::MaxMemoryUsage)),
-                    ResourceType::FileDescriptors => Err(ThreadError::Resource(ResourceError::MaxFileDescriptors)),
-                    ResourceType::ChildThreads => Err(ThreadError::Resource(ResourceError::MaxThreadsPerProcess)),
-                    ResourceType::NetworkConnections => Err(ThreadError::Resource(ResourceError::ResourceUnavailable)),
-                    ResourceType::DiskIOPS => Err(ThreadError::Resource(ResourceError::ResourceUnavailable)),
-                    ResourceType::NetworkBandwidth => Err(ThreadError::Resource(ResourceError::ResourceUnavailable)),
-                }
+                let error = match resource_type {
+                    ResourceType::CpuTime => ThreadError::Resource(ResourceError::MaxCpuTime),
+                    ResourceType::Memory => ThreadError::Resource(ResourceError::MaxMemoryUsage),
+                    ResourceType::FileDescriptors => ThreadError::Resource(ResourceError::MaxFileDescriptors),
+                    ResourceType::ChildThreads => ThreadError::Resource(ResourceError::MaxThreadsPerProcess),
+                    ResourceType::NetworkConnections => ThreadError::Resource(ResourceError::ResourceUnavailable),
+                    ResourceType::DiskIOPS => ThreadError::Resource(ResourceError::ResourceUnavailable),
+                    ResourceType::NetworkBandwidth => ThreadError::Resource(ResourceError::ResourceUnavailable),
+                };
+                Err(error.for_thread(thread_id))
             } else {
                 Ok(()) // Soft limit - allow but warn
             }
@@ -415,6 +424,180 @@ impl ResourceLimiter {
         }
     }
     
+    /// Pace `amount` units of a per-second quota.
+    ///
+    /// `max_disk_iops` and `max_network_bw` are enforced with a token bucket
+    /// per thread that allows one second's worth in a burst. Within the
+    /// rate this returns at once. Beyond it, the violation is recorded and
+    /// the configured action decides: [`ViolationAction::Throttle`] sleeps
+    /// until the bucket allows the operation, hard limits fail, and soft
+    /// limits let it through. Other resource types are not rate-limited.
+    pub fn pace(&self, thread_id: ThreadId, resource_type: ResourceType, amount: u64) -> Result<(), ThreadError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        
+        let quota = self.get_thread_quota(thread_id);
+        let rate = match resource_type {
+            ResourceType::DiskIOPS => quota.max_disk_iops,
+            ResourceType::NetworkBandwidth => quota.max_network_bw,
+            _ => 0,
+        };
+        if rate == 0 {
+            return Ok(());
+        }
+        
+        let Some(limiter) = self.rate_limiter(thread_id, resource_type, rate) else {
+            return Ok(());
+        };
+        if limiter.try_acquire(amount) {
+            return Ok(());
+        }
+        
+        let action = self.violation_action(resource_type, quota.hard_limits);
+        self.handle_violation(LimitViolation {
+            thread_id,
+            resource_type,
+            current_usage: amount,
+            limit: rate,
+            hard_limit: quota.hard_limits,
+            timestamp: Instant::now(),
+            suggested_action: action,
+        });
+        
+        if action == ViolationAction::Throttle {
+            limiter.acquire(amount);
+            Ok(())
+        } else if quota.hard_limits {
+            Err(ThreadError::Resource(ResourceError::ResourceUnavailable).for_thread(thread_id))
+        } else {
+            Ok(())
+        }
+    }
+    
+    /// Get the token bucket pacing `resource_type` for a thread at `rate`
+    /// units per second.
+    fn rate_limiter(&self, thread_id: ThreadId, resource_type: ResourceType, rate: u64) -> Option<Arc<RateLimiter>> {
+        let mut limiters = self.rate_limiters.try_lock()?;
+        let limiter = limiters
+            .entry((thread_id, resource_type as usize))
+            .or_insert_with(|| Arc::new(RateLimiter::new(rate, rate)));
+        // Follow quota changes
+        if limiter.rate() != rate {
+            limiter.set_rate(rate, rate);
+        }
+        Some(limiter.clone())
+    }
+    
+    /// Check whether `parent` may spawn another thread.
+    ///
+    /// Fails if `parent` is at a hard `max_child_threads` limit. Children
+    /// are counted from the thread hierarchy, so a slot frees up as soon as
+    /// a child exits.
+    pub fn check_spawn(&self, parent: ThreadId) -> Result<(), ThreadError> {
+        self.check_resource_limit(parent, ResourceType::ChildThreads, 1)
+    }
+    
+    /// Check whether `parent` may spawn `count` more child threads.
+    pub fn check_spawns(&self, parent: ThreadId, count: usize) -> Result<(), ThreadError> {
+        self.check_resource_limit(parent, ResourceType::ChildThreads, count as u64)
+    }
+    
+    /// Charge `elapsed` CPU time to a thread and enforce its CPU-time quota.
+    ///
+    /// Called from the tick path for the running thread. Returns the action
+    /// the caller must carry out if the thread is over quota. The violation
+    /// is recorded once, when the thread first crosses its limit; after that
+    /// only [`ViolationAction::Throttle`] keeps being returned, for as long
+    /// as the thread stays over the limit.
+    pub fn charge_cpu_time(&self, thread_id: ThreadId, elapsed: Duration) -> Option<ViolationAction> {
+        if !self.is_enabled() {
+            return None;
+        }
+        
+        let (before, after) = {
+            let mut usage = self.thread_usage.try_lock()?;
+            let thread_usage = usage.get_mut(&thread_id)?;
+            let before = thread_usage.cpu_time_ns;
+            thread_usage.add_cpu_time(elapsed);
+            (before, thread_usage.cpu_time_ns)
+        };
+        self.system_usage.total_cpu_time_ns.fetch_add(elapsed.as_nanos(), Ordering::AcqRel);
+        
+        let quota = self.get_thread_quota(thread_id);
+        let limit = quota.max_cpu_time_ns;
+        if limit == 0 || after <= limit {
+            return None;
+        }
+        
+        let action = self.violation_action(ResourceType::CpuTime, quota.hard_limits);
+        if before <= limit {
+            self.handle_violation(LimitViolation {
+                thread_id,
+                resource_type: ResourceType::CpuTime,
+                current_usage: after,
+                limit,
+                hard_limit: quota.hard_limits,
+                timestamp: Instant::now(),
+                suggested_action: action,
+            });
+            Some(action)
+        } else if action == ViolationAction::Throttle {
+            Some(action)
+        } else {
+            None
+        }
+    }
+    
+    /// Choose the action taken when `resource_type` exceeds its quota.
+    ///
+    /// Without an override, hard limits terminate the thread and soft limits
+    /// only warn.
+    pub fn set_violation_action(&self, resource_type: ResourceType, action: ViolationAction) {
+        if let Some(mut actions) = self.actions.try_lock() {
+            actions[resource_type as usize] = Some(action);
+        }
+    }
+    
+    /// Action taken when `resource_type` exceeds its quota.
+    pub fn violation_action(&self, resource_type: ResourceType, hard_limit: bool) -> ViolationAction {
+        let configured = self.actions.try_lock().and_then(|actions| actions[resource_type as usize]);
+        configured.unwrap_or(if hard_limit {
+            ViolationAction::Terminate
+        } else {
+            ViolationAction::Warn
+        })
+    }
+    
+    /// Add a heap allocation of `size` bytes to a thread's memory usage.
+    pub fn record_allocation(&self, thread_id: ThreadId, size: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        if let Some(mut usage) = self.thread_usage.try_lock() {
+            if let Some(thread_usage) = usage.get_mut(&thread_id) {
+                thread_usage.update_memory_usage(thread_usage.memory_usage + size);
+                self.system_usage.total_memory_usage.fetch_add(size, Ordering::AcqRel);
+            }
+        }
+    }
+    
+    /// Remove a freed heap block of `size` bytes from a thread's memory usage.
+    pub fn record_deallocation(&self, thread_id: ThreadId, size: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        if let Some(mut usage) = self.thread_usage.try_lock() {
+            if let Some(thread_usage) = usage.get_mut(&thread_id) {
+                let freed = size.min(thread_usage.memory_usage);
+                thread_usage.update_memory_usage(thread_usage.memory_usage - freed);
+                self.system_usage.total_memory_usage.fetch_sub(freed, Ordering::AcqRel);
+            }
+        }
+    }
+    
     /// Update resource usage for a thread.
     pub fn update_resource_usage(&self, thread_id: ThreadId, resource_type: ResourceType, new_value: u64) {
         if !self.is_enabled() {
@@ -467,15 +650,36 @@ impl ResourceLimiter {
     
     /// Get resource usage for a thread.
     fn get_thread_usage(&self, thread_id: ThreadId) -> ResourceUsage {
-        if let Some(usage) = self.thread_usage.try_lock() {
+        let mut usage = if let Some(usage) = self.thread_usage.try_lock() {
             usage.get(&thread_id).cloned().unwrap_or_else(|| ResourceUsage::new(thread_id))
         } else {
             ResourceUsage::new(thread_id)
-        }
+        };
+        usage.child_threads = hierarchy::child_count(thread_id) as u32;
+        usage
+    }
+    
+    /// Get the largest fraction of any of its limited quotas the thread
+    /// uses, or 0.0 if it has no limits. Values of 1.0 and above mean the
+    /// thread is at or over a limit.
+    pub fn quota_utilization(&self, thread_id: ThreadId) -> f64 {
+        let usage = self.get_thread_usage(thread_id);
+        let quota = self.get_thread_quota(thread_id);
+        [
+            (usage.cpu_time_ns, quota.max_cpu_time_ns),
+            (usage.memory_usage, quota.max_memory_bytes),
+            (usage.open_files as u64, quota.max_open_files as u64),
+            (usage.child_threads as u64, quota.max_child_threads as u64),
+            (usage.network_connections as u64, quota.max_network_connections as u64),
+        ]
+        .iter()
+        .filter(|&&(_, limit)| limit > 0)
+        .map(|&(used, limit)| used as f64 / limit as f64)
+        .fold(0.0, f64::max)
     }
     
     /// Get quota for a thread.
-    fn get_thread_quota(&self, thread_id: ThreadId) -> ResourceQuota {
+    pub(crate) fn get_thread_quota(&self, thread_id: ThreadId) -> ResourceQuota {
         if let Some(quotas) = self.thread_quotas.try_lock() {
             if let Some(quota) = quotas.get(&thread_id) {
                 return quota.clone();
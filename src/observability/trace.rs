@@ -0,0 +1,263 @@
+//! Scheduler tracepoints.
+//!
+//! Tracepoints are fixed instrumentation sites in the scheduler, modelled on
+//! Linux tracepoints: each one has a static enable flag, and while it is off
+//! the site costs a relaxed load and a branch. Enabled tracepoints record a
+//! timestamped [`TraceRecord`] into [`TRACE_BUFFER`], a fixed-size ring that
+//! overwrites its oldest records when full.
+//!
+//! The sites are only compiled in with the `tracepoints` feature; without it
+//! the tracepoints exist but never fire.
+//!
+//! | Tracepoint          | Fired when                                     |
+//! |---------------------|------------------------------------------------|
+//! | [`CONTEXT_SWITCH`]  | a CPU switches threads                         |
+//! | [`WAKEUP`]          | a thread is queued on a CPU's wakeup queue     |
+//! | [`MIGRATE`]         | a thread is stolen by another CPU              |
+//! | [`THROTTLE`]        | a thread group exhausts its bandwidth quota    |
+//!
+//! ```ignore
+//! trace::WAKEUP.enable();
+//! trace::CONTEXT_SWITCH.enable();
+//! run_workload();
+//! for record in TRACE_BUFFER.drain() {
+//!     // Wakeup-to-switch latency per thread
+//! }
+//! ```
+
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::ThreadId;
+extern crate alloc;
+use alloc::vec::Vec;
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Number of records [`TRACE_BUFFER`] holds before overwriting.
+pub const TRACE_BUFFER_CAPACITY: usize = 1024;
+
+/// Event recorded by a tracepoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The CPU switched from `prev` to `next`
+    ContextSwitch { prev: ThreadId, next: ThreadId },
+    /// `thread` was queued to run on `target_cpu`
+    Wakeup { thread: ThreadId, target_cpu: usize },
+    /// `thread` moved from `from_cpu`'s queue to `to_cpu`
+    Migrate { thread: ThreadId, from_cpu: usize, to_cpu: usize },
+    /// `group` used up its quota with `runtime_ns` of CPU time this period
+    Throttle { group: u32, runtime_ns: u64 },
+}
+
+/// A tracepoint hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// CPU clock time of the hit (nanoseconds)
+    pub timestamp_ns: u64,
+    /// CPU the tracepoint fired on
+    pub cpu: usize,
+    pub event: TraceEvent,
+}
+
+/// A statically defined instrumentation site.
+pub struct Tracepoint {
+    name: &'static str,
+    enabled: AtomicBool,
+    hits: AtomicU64,
+}
+
+impl Tracepoint {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            enabled: AtomicBool::new(false),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the tracepoint's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Start recording hits.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    /// Stop recording hits.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    /// Check whether hits are recorded.
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of hits recorded while enabled.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Record a hit. Sites check [`is_enabled`](Self::is_enabled) first.
+    #[cold]
+    #[inline(never)]
+    pub fn emit(&self, event: TraceEvent) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        TRACE_BUFFER.push(TraceRecord {
+            timestamp_ns: crate::time::cpu_clock().as_nanos(),
+            cpu: crate::arch::percpu::cpu_id(),
+            event,
+        });
+    }
+}
+
+/// Fired on every context switch.
+pub static CONTEXT_SWITCH: Tracepoint = Tracepoint::new("context_switch");
+/// Fired when a thread is woken onto a CPU.
+pub static WAKEUP: Tracepoint = Tracepoint::new("wakeup");
+/// Fired when a thread is stolen across CPUs.
+pub static MIGRATE: Tracepoint = Tracepoint::new("migrate");
+/// Fired when a thread group is throttled.
+pub static THROTTLE: Tracepoint = Tracepoint::new("throttle");
+
+/// Every tracepoint, for enabling them by name.
+pub static TRACEPOINTS: [&Tracepoint; 4] = [&CONTEXT_SWITCH, &WAKEUP, &MIGRATE, &THROTTLE];
+
+/// Find a tracepoint by name.
+pub fn find(name: &str) -> Option<&'static Tracepoint> {
+    TRACEPOINTS.iter().copied().find(|point| point.name == name)
+}
+
+/// Enable or disable every tracepoint.
+pub fn set_all(enabled: bool) {
+    for point in TRACEPOINTS {
+        point.enabled.store(enabled, Ordering::Release);
+    }
+}
+
+struct Ring {
+    records: Vec<TraceRecord>,
+    /// Index of the oldest record once the ring has wrapped
+    head: usize,
+    /// Records overwritten before they were drained
+    overwritten: u64,
+}
+
+/// Ring of recorded tracepoint hits.
+pub struct TraceBuffer {
+    ring: SpinLockIrqSave<Ring>,
+}
+
+impl TraceBuffer {
+    const fn new() -> Self {
+        Self {
+            ring: SpinLockIrqSave::new(Ring {
+                records: Vec::new(),
+                head: 0,
+                overwritten: 0,
+            }),
+        }
+    }
+
+    fn push(&self, record: TraceRecord) {
+        let mut ring = self.ring.lock();
+        if ring.records.len() < TRACE_BUFFER_CAPACITY {
+            ring.records.push(record);
+        } else {
+            let head = ring.head;
+            ring.records[head] = record;
+            ring.head = (head + 1) % TRACE_BUFFER_CAPACITY;
+            ring.overwritten += 1;
+        }
+    }
+
+    /// Take every buffered record, oldest first.
+    pub fn drain(&self) -> Vec<TraceRecord> {
+        let mut ring = self.ring.lock();
+        let head = ring.head;
+        ring.head = 0;
+        let mut records = core::mem::take(&mut ring.records);
+        records.rotate_left(head);
+        records
+    }
+
+    /// Get the number of buffered records.
+    pub fn len(&self) -> usize {
+        self.ring.lock().records.len()
+    }
+
+    /// Check whether no records are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of records lost to overwriting.
+    pub fn overwritten(&self) -> u64 {
+        self.ring.lock().overwritten
+    }
+}
+
+/// Buffer every tracepoint records into.
+pub static TRACE_BUFFER: TraceBuffer = TraceBuffer::new();
+
+/// Fire a tracepoint: `tracepoint!(WAKEUP, Wakeup { thread: id, target_cpu: cpu })`.
+///
+/// The event is only built when the tracepoint is enabled, and without the
+/// `tracepoints` feature the site compiles to nothing.
+#[cfg(feature = "tracepoints")]
+macro_rules! tracepoint {
+    ($point:ident, $kind:ident { $($field:ident $(: $value:expr)?),* $(,)? }) => {{
+        let point = &$crate::observability::trace::$point;
+        if point.is_enabled() {
+            point.emit($crate::observability::trace::TraceEvent::$kind { $($field $(: $value)?),* });
+        }
+    }};
+}
+
+#[cfg(not(feature = "tracepoints"))]
+macro_rules! tracepoint {
+    ($point:ident, $kind:ident { $($field:ident $(: $value:expr)?),* $(,)? }) => {{
+        // Keep the arguments type checked and their bindings used
+        if false {
+            let _ = $crate::observability::trace::TraceEvent::$kind { $($field $(: $value)?),* };
+        }
+    }};
+}
+
+pub(crate) use tracepoint;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_buffer_overwrites_oldest() {
+        let buffer = TraceBuffer::new();
+        for group in 0..TRACE_BUFFER_CAPACITY as u32 + 3 {
+            buffer.push(TraceRecord {
+                timestamp_ns: group as u64,
+                cpu: 0,
+                event: TraceEvent::Throttle { group, runtime_ns: 0 },
+            });
+        }
+        assert_eq!(buffer.len(), TRACE_BUFFER_CAPACITY);
+        assert_eq!(buffer.overwritten(), 3);
+
+        let records = buffer.drain();
+        assert_eq!(records.first().map(|record| record.timestamp_ns), Some(3));
+        assert!(records.windows(2).all(|pair| pair[0].timestamp_ns < pair[1].timestamp_ns));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_tracepoint_lookup() {
+        let point = find("migrate").unwrap();
+        assert!(core::ptr::eq(point, &MIGRATE));
+        assert!(find("sched_switch").is_none());
+
+        assert!(!MIGRATE.is_enabled());
+        tracepoint!(MIGRATE, Migrate { thread: ThreadId::new(49_001), from_cpu: 0, to_cpu: 1 });
+        assert_eq!(MIGRATE.hits(), 0);
+    }
+}
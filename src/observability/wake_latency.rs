@@ -0,0 +1,371 @@
+//! Wake latency histograms and service level objectives.
+//!
+//! Every time a thread goes from ready to running, the time it spent ready
+//! is recorded in a log2 histogram of all threads' wake latencies, and in
+//! the current window of every registered [`WakeLatencySlo`] whose
+//! [scope](SloScope) covers the thread. An SLO states a latency bound that
+//! a percentile of wakeups must stay under:
+//!
+//! ```ignore
+//! // p99 ready -> running under 200us for threads of the "rt" partition
+//! HEALTH_MONITOR.register_wake_latency_slo(
+//!     WakeLatencySlo::new("rt-wake", 99.0, Duration::from_micros(200))
+//!         .scope(SloScope::Partition(&PARTITIONS, "rt".into())),
+//! );
+//! ```
+//!
+//! [`evaluate_slos`] closes every SLO's window and compares it against the
+//! bound. The health monitor does this on each check and reports a breach
+//! as a critical issue naming the threads with the slowest wakeups.
+//!
+//! Histogram buckets are powers of two, so percentiles are reported as the
+//! upper bound of their bucket and can overstate the latency by up to 2x.
+
+use crate::sched::PartitionedScheduler;
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{Thread, ThreadId};
+use crate::time::Duration;
+use portable_atomic::{AtomicU64, Ordering};
+extern crate alloc;
+use alloc::{string::String, vec::Vec};
+
+/// Number of histogram buckets: bucket 0 holds zero, bucket `n` latencies
+/// of `2^(n-1)` to `2^n - 1` nanoseconds.
+pub const LATENCY_BUCKETS: usize = 65;
+
+/// Threads listed per breached SLO.
+pub const MAX_OFFENDERS: usize = 8;
+
+/// Histogram of wake latencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+    count: u64,
+    total_ns: u64,
+    max_ns: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    /// Create an empty histogram.
+    pub const fn new() -> Self {
+        Self {
+            buckets: [0; LATENCY_BUCKETS],
+            count: 0,
+            total_ns: 0,
+            max_ns: 0,
+        }
+    }
+
+    /// Add a latency of `latency_ns`.
+    pub fn record(&mut self, latency_ns: u64) {
+        self.buckets[(u64::BITS - latency_ns.leading_zeros()) as usize] += 1;
+        self.count += 1;
+        self.total_ns = self.total_ns.saturating_add(latency_ns);
+        self.max_ns = self.max_ns.max(latency_ns);
+    }
+
+    /// Get the number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the longest latency recorded.
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max_ns)
+    }
+
+    /// Get the mean latency.
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.total_ns.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// Get the latency `percentile` (0 to 100) of recorded latencies are
+    /// at or under, rounded up to the bucket's upper bound.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::from_nanos(0);
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64) as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = if bucket == 0 { 0 } else { u64::MAX >> (u64::BITS as usize - bucket) };
+                return Duration::from_nanos(upper.min(self.max_ns));
+            }
+        }
+        self.max()
+    }
+
+    /// Get the number of latencies in each bucket.
+    pub fn buckets(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.buckets
+    }
+}
+
+/// Threads an SLO applies to.
+#[derive(Clone)]
+pub enum SloScope {
+    /// Every thread
+    All,
+    /// Threads of a thread group
+    Group(u32),
+    /// Threads of the groups a partition of a scheduler runs
+    Partition(&'static PartitionedScheduler, String),
+    /// Threads of at least a priority
+    MinPriority(u8),
+}
+
+impl SloScope {
+    fn covers(&self, thread: &Thread) -> bool {
+        match self {
+            SloScope::All => true,
+            SloScope::Group(group) => thread.group_id() == *group,
+            SloScope::Partition(scheduler, name) => {
+                scheduler.partition_of_group(thread.group_id()) == Some(name.as_str())
+            }
+            SloScope::MinPriority(priority) => thread.priority() >= *priority,
+        }
+    }
+}
+
+/// Bound on a percentile of wake latencies.
+#[derive(Clone)]
+pub struct WakeLatencySlo {
+    pub name: String,
+    pub scope: SloScope,
+    /// Percentile, 0 to 100
+    pub percentile: f64,
+    /// Latency the percentile must stay under
+    pub target: Duration,
+    /// Fewest wakeups in a window for it to be judged
+    pub min_samples: u64,
+}
+
+impl WakeLatencySlo {
+    /// Create an SLO for all threads.
+    pub fn new(name: &str, percentile: f64, target: Duration) -> Self {
+        Self {
+            name: String::from(name),
+            scope: SloScope::All,
+            percentile,
+            target,
+            min_samples: 20,
+        }
+    }
+
+    /// Apply the SLO to the threads of `scope` only.
+    pub fn scope(mut self, scope: SloScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Judge windows only once they hold `min_samples` wakeups.
+    pub fn min_samples(mut self, min_samples: u64) -> Self {
+        self.min_samples = min_samples;
+        self
+    }
+}
+
+/// Handle of a registered SLO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SloId(u64);
+
+/// Result of judging one SLO window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SloEvaluation {
+    pub id: SloId,
+    pub name: String,
+    pub percentile: f64,
+    pub target: Duration,
+    /// Wakeups in the window
+    pub samples: u64,
+    /// Latency at the percentile
+    pub observed: Duration,
+    /// Whether the window had enough samples and the percentile was over
+    /// the target
+    pub breached: bool,
+    /// Threads with the slowest wakeups over the target, slowest first
+    pub offenders: Vec<(ThreadId, Duration)>,
+}
+
+struct SloState {
+    id: SloId,
+    slo: WakeLatencySlo,
+    window: LatencyHistogram,
+    /// Slowest wakeups over the target in the window, slowest first
+    offenders: [Option<(ThreadId, u64)>; MAX_OFFENDERS],
+}
+
+impl SloState {
+    fn record(&mut self, thread: ThreadId, latency_ns: u64) {
+        self.window.record(latency_ns);
+        if latency_ns <= self.slo.target.as_nanos() {
+            return;
+        }
+
+        // A thread is listed once, with its slowest wakeup
+        if let Some(index) = self.offenders.iter().position(|entry| matches!(entry, Some((id, _)) if *id == thread)) {
+            if self.offenders[index].is_some_and(|(_, worst)| worst >= latency_ns) {
+                return;
+            }
+            self.offenders.copy_within(index + 1.., index);
+            self.offenders[MAX_OFFENDERS - 1] = None;
+        }
+        let position = self
+            .offenders
+            .iter()
+            .position(|entry| !matches!(entry, Some((_, worst)) if *worst >= latency_ns));
+        if let Some(position) = position {
+            self.offenders.copy_within(position..MAX_OFFENDERS - 1, position + 1);
+            self.offenders[position] = Some((thread, latency_ns));
+        }
+    }
+}
+
+struct Registry {
+    all: LatencyHistogram,
+    slos: Vec<SloState>,
+}
+
+static REGISTRY: SpinLockIrqSave<Registry> = SpinLockIrqSave::new(Registry {
+    all: LatencyHistogram::new(),
+    slos: Vec::new(),
+});
+
+static NEXT_SLO_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Record that `thread` waited `latency_ns` between becoming ready and
+/// running.
+pub(crate) fn record(thread: &Thread, latency_ns: u64) {
+    let mut registry = REGISTRY.lock();
+    registry.all.record(latency_ns);
+    for state in registry.slos.iter_mut() {
+        if state.slo.scope.covers(thread) {
+            state.record(thread.id(), latency_ns);
+        }
+    }
+}
+
+/// Get the histogram of all threads' wake latencies.
+pub fn wake_latency() -> LatencyHistogram {
+    REGISTRY.lock().all
+}
+
+/// Clear the histogram of all threads' wake latencies.
+pub fn reset_wake_latency() {
+    REGISTRY.lock().all = LatencyHistogram::new();
+}
+
+/// Start tracking `slo`. Its first window opens now.
+pub fn register_slo(slo: WakeLatencySlo) -> SloId {
+    let id = SloId(NEXT_SLO_ID.fetch_add(1, Ordering::Relaxed));
+    REGISTRY.lock().slos.push(SloState {
+        id,
+        slo,
+        window: LatencyHistogram::new(),
+        offenders: [None; MAX_OFFENDERS],
+    });
+    id
+}
+
+/// Stop tracking an SLO. Returns `false` if it was not registered.
+pub fn unregister_slo(id: SloId) -> bool {
+    let mut registry = REGISTRY.lock();
+    let before = registry.slos.len();
+    registry.slos.retain(|state| state.id != id);
+    registry.slos.len() != before
+}
+
+/// Judge the current window of every SLO and open new ones.
+pub fn evaluate_slos() -> Vec<SloEvaluation> {
+    let mut registry = REGISTRY.lock();
+    registry
+        .slos
+        .iter_mut()
+        .map(|state| {
+            let window = core::mem::take(&mut state.window);
+            let offenders = core::mem::replace(&mut state.offenders, [None; MAX_OFFENDERS]);
+            let slo = &state.slo;
+            let observed = window.percentile(slo.percentile);
+            let breached = window.count() >= slo.min_samples.max(1) && observed > slo.target;
+            SloEvaluation {
+                id: state.id,
+                name: slo.name.clone(),
+                percentile: slo.percentile,
+                target: slo.target,
+                samples: window.count(),
+                observed,
+                breached,
+                offenders: offenders
+                    .iter()
+                    .flatten()
+                    .map(|&(thread, latency_ns)| (thread, Duration::from_nanos(latency_ns)))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::observability::health::{HealthChecker, HealthStatus, IssueSeverity, WakeLatencyHealthChecker};
+
+    #[test]
+    fn test_slo_breach_names_slowest_threads() {
+        let pool = StackPool::new();
+        let threads: Vec<Thread> = (0..3)
+            .map(|i| {
+                let stack = pool.allocate(StackSizeClass::Small).unwrap();
+                let (thread, _) = Thread::new(ThreadId::new(49_600 + i), stack, || {}, 128);
+                thread.set_group_id(4_960);
+                thread
+            })
+            .collect();
+
+        // Other tests' threads are outside the group and not counted
+        let id = register_slo(
+            WakeLatencySlo::new("test-wake", 90.0, Duration::from_micros(100))
+                .scope(SloScope::Group(4_960))
+                .min_samples(10),
+        );
+        let evaluation = |id| evaluate_slos().into_iter().find(|e| e.id == id).unwrap();
+
+        // 9 of 10 wakeups fast: p90 stays in the 32-64us bucket
+        for _ in 0..9 {
+            record(&threads[0], 50_000);
+        }
+        record(&threads[1], 900_000);
+        let within = evaluation(id);
+        assert_eq!(within.samples, 10);
+        assert_eq!(within.observed, Duration::from_nanos(65_535));
+        assert!(!within.breached);
+        assert_eq!(within.offenders, [(threads[1].id(), Duration::from_micros(900))]);
+
+        // The window was reset; now most wakeups are slow
+        for latency_us in [50, 300, 700, 400, 200, 300, 300, 300, 300, 300] {
+            let thread = if latency_us == 700 { &threads[2] } else { &threads[1] };
+            record(thread, latency_us * 1_000);
+        }
+        let checker = WakeLatencyHealthChecker::new();
+        let health = checker.check_health();
+        let issue = health.issues.iter().find(|issue| issue.description.contains("test-wake")).unwrap();
+        assert_eq!(issue.severity, IssueSeverity::Critical);
+        assert_eq!(health.status, HealthStatus::Critical);
+        assert_eq!(issue.affected_threads, [threads[2].id(), threads[1].id()]);
+        let observed: f64 = issue.context["observed_us"].parse().unwrap();
+        assert!(health.metrics.custom_metrics["max_response_time_us"] >= observed);
+        assert_eq!(health.metrics.avg_response_time_us, 0);
+
+        assert!(unregister_slo(id));
+        assert!(!unregister_slo(id));
+    }
+}
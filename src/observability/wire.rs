@@ -0,0 +1,690 @@
+//! Compact binary encoding of telemetry reports.
+//!
+//! Monitoring agents on a host can consume [`MetricsReport`], [`ProfileData`],
+//! [`SystemHealth`] and [`SchedulerStats`] over UART, USB or shared memory without the target
+//! formatting any strings. Each report is framed as a record:
+//!
+//! | Field   | Size     | Contents                             |
+//! |---------|----------|--------------------------------------|
+//! | magic   | 4 bytes  | `b"PTTM"`                            |
+//! | version | 2 bytes  | [`WIRE_VERSION`], little-endian      |
+//! | kind    | 1 byte   | [`RecordKind`] discriminant          |
+//! | length  | 4 bytes  | body length in bytes, little-endian  |
+//! | body    | variable | encoded report                       |
+//!
+//! The body encodes struct fields in declaration order:
+//!
+//! - unsigned integers, `Instant`, `Duration` (nanoseconds) and `ThreadId`
+//!   as LEB128 varints
+//! - `f32`/`f64` as little-endian IEEE 754 bits
+//! - enums as a one-byte discriminant in declaration order
+//! - `Option` as a one-byte tag (0 = none, 1 = some) followed by the value
+//! - strings, vectors and maps as a varint count followed by the items,
+//!   map entries as key then value
+//!
+//! Hosts should skip records with an unknown kind using the length field.
+//! Fields are only ever appended to the end of a body within one version.
+
+use super::health::{
+    ComponentHealth, ComponentMetrics, HealthHistoryEntry, HealthIssue, HealthStatus,
+    HealthTrend, IssueCategory, IssueSeverity, SystemHealth, TrendDirection,
+};
+use super::inversion::PriorityInversion;
+use super::metrics::{DeviceIoWait, MetricsReport, SystemMetricsSnapshot, ThreadMetrics};
+use super::profiler::{
+    AllocationPattern, AllocationType, CallStack, ContextSwitchProfile, ContextSwitchReason,
+    FunctionProfile, HotSpot, MemoryProfile, ProfileData, SchedulerProfile, ThreadProfileData,
+};
+use super::wake_latency::LatencyHistogram;
+use crate::io::{IoError, Read, Write};
+use crate::sched::tuning::SchedulerStats;
+use crate::thread_new::ThreadId;
+use crate::time::{Duration, Instant};
+extern crate alloc;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+/// Magic bytes at the start of every telemetry record.
+pub const WIRE_MAGIC: [u8; 4] = *b"PTTM";
+
+/// Current telemetry encoding version.
+pub const WIRE_VERSION: u16 = 1;
+
+/// Size of the record header in bytes.
+pub const HEADER_LEN: usize = 11;
+
+/// Type of report carried by a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordKind {
+    /// [`MetricsReport`]
+    MetricsReport = 1,
+    /// [`ProfileData`]
+    ProfileData = 2,
+    /// [`SystemHealth`]
+    SystemHealth = 3,
+    /// [`SchedulerStats`]
+    SchedulerStats = 4,
+}
+
+impl RecordKind {
+    /// Decode a kind byte, if known.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(RecordKind::MetricsReport),
+            2 => Some(RecordKind::ProfileData),
+            3 => Some(RecordKind::SystemHealth),
+            4 => Some(RecordKind::SchedulerStats),
+            _ => None,
+        }
+    }
+}
+
+/// Errors produced while reading a record header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireError {
+    /// Underlying reader or writer failed
+    Io(IoError),
+    /// Input is not a telemetry record
+    BadMagic,
+    /// Record was written by an unsupported encoding version
+    UnsupportedVersion(u16),
+    /// Encoded body does not fit the length field
+    TooLarge,
+}
+
+impl From<IoError> for WireError {
+    fn from(error: IoError) -> Self {
+        WireError::Io(error)
+    }
+}
+
+/// Decoded record header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// Encoding version of the body
+    pub version: u16,
+    /// Raw kind byte; see [`RecordKind::from_u8`]
+    pub kind: u8,
+    /// Body length in bytes
+    pub length: u32,
+}
+
+impl RecordHeader {
+    /// Read and validate a header, leaving `reader` at the start of the body.
+    pub fn read(reader: &mut impl Read) -> Result<Self, WireError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != WIRE_MAGIC {
+            return Err(WireError::BadMagic);
+        }
+        let version = reader.read_u16()?;
+        if version != WIRE_VERSION {
+            return Err(WireError::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            version,
+            kind: reader.read_u8()?,
+            length: reader.read_u32()?,
+        })
+    }
+}
+
+/// Buffer that report bodies are encoded into.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    /// Create an empty encoder.
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Encoded bytes so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Consume the encoder, returning the encoded bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Append a LEB128 varint.
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    /// Append a single byte.
+    pub fn byte(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    /// Append an `f32` as little-endian bits.
+    pub fn f32(&mut self, value: f32) {
+        self.buf.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+
+    /// Append an `f64` as little-endian bits.
+    pub fn f64(&mut self, value: f64) {
+        self.buf.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+
+    /// Append a length-prefixed UTF-8 string.
+    pub fn str(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+
+    /// Append a slice as a count followed by each item.
+    pub fn seq<T: WireEncode>(&mut self, items: &[T]) {
+        self.varint(items.len() as u64);
+        for item in items {
+            item.encode(self);
+        }
+    }
+
+    /// Append a map as a count followed by key/value pairs.
+    pub fn map<K: WireEncode, V: WireEncode>(&mut self, map: &BTreeMap<K, V>) {
+        self.varint(map.len() as u64);
+        for (key, value) in map {
+            key.encode(self);
+            value.encode(self);
+        }
+    }
+}
+
+/// Types with a wire encoding.
+pub trait WireEncode {
+    /// Append this value to `enc`.
+    fn encode(&self, enc: &mut Encoder);
+}
+
+/// Reports that can be framed as a top-level record.
+pub trait WireRecord: WireEncode {
+    /// Kind byte written in the record header.
+    const KIND: RecordKind;
+}
+
+/// Encode `record` into a standalone framed record.
+pub fn encode_record<T: WireRecord>(record: &T) -> Result<Vec<u8>, WireError> {
+    let mut out = Vec::new();
+    write_record(record, &mut out)?;
+    Ok(out)
+}
+
+/// Encode `record` and write it to `writer` as a framed record.
+pub fn write_record<T: WireRecord>(record: &T, writer: &mut impl Write) -> Result<(), WireError> {
+    let mut enc = Encoder::new();
+    record.encode(&mut enc);
+    let body = enc.into_bytes();
+    let length = u32::try_from(body.len()).map_err(|_| WireError::TooLarge)?;
+
+    writer.write_all(&WIRE_MAGIC)?;
+    writer.write_u16(WIRE_VERSION)?;
+    writer.write_u8(T::KIND as u8)?;
+    writer.write_u32(length)?;
+    writer.write_all(&body)?;
+    Ok(())
+}
+
+// Primitive encodings
+
+impl WireEncode for u8 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(*self as u64);
+    }
+}
+
+impl WireEncode for u32 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(*self as u64);
+    }
+}
+
+impl WireEncode for u64 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(*self);
+    }
+}
+
+impl WireEncode for usize {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(*self as u64);
+    }
+}
+
+impl WireEncode for f32 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.f32(*self);
+    }
+}
+
+impl WireEncode for f64 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.f64(*self);
+    }
+}
+
+impl WireEncode for String {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.str(self);
+    }
+}
+
+impl<T: WireEncode> WireEncode for Option<T> {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            Some(value) => {
+                enc.byte(1);
+                value.encode(enc);
+            }
+            None => enc.byte(0),
+        }
+    }
+}
+
+impl<T: WireEncode> WireEncode for Vec<T> {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.seq(self);
+    }
+}
+
+impl<K: WireEncode, V: WireEncode> WireEncode for BTreeMap<K, V> {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.map(self);
+    }
+}
+
+impl WireEncode for Instant {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(self.as_nanos());
+    }
+}
+
+impl WireEncode for Duration {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(self.as_nanos());
+    }
+}
+
+impl WireEncode for ThreadId {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.varint(self.as_u64());
+    }
+}
+
+/// Encode each listed field of `$value` in order.
+macro_rules! encode_fields {
+    ($enc:expr, $value:expr, $($field:ident),+ $(,)?) => {
+        $( $value.$field.encode($enc); )+
+    };
+}
+
+/// Encode fieldless enums as their declaration-order discriminant.
+macro_rules! encode_as_byte {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl WireEncode for $ty {
+                fn encode(&self, enc: &mut Encoder) {
+                    enc.byte(*self as u8);
+                }
+            }
+        )+
+    };
+}
+
+encode_as_byte!(
+    AllocationType,
+    ContextSwitchReason,
+    HealthStatus,
+    IssueSeverity,
+    IssueCategory,
+    TrendDirection,
+);
+
+// Metrics
+
+impl WireEncode for SystemMetricsSnapshot {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            threads_created,
+            threads_destroyed,
+            active_threads,
+            total_context_switches,
+            total_cpu_time_ns,
+            cpu_utilization,
+            context_switches_per_second,
+            current_memory_usage,
+            peak_memory_usage,
+            preempt_disables,
+            max_preempt_depth,
+            might_sleep_violations,
+            priority_inversions,
+            total_io_wait_ns,
+            total_blocked_ns,
+        );
+    }
+}
+
+impl WireEncode for ThreadMetrics {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            thread_id,
+            cpu_time_ns,
+            context_switches,
+            voluntary_yields,
+            involuntary_preemptions,
+            peak_stack_usage,
+            current_stack_usage,
+            page_faults,
+            user_time_ns,
+            kernel_time_ns,
+            created_at,
+            last_active,
+            priority_changes,
+            memory_allocations,
+            memory_deallocations,
+            total_memory_allocated,
+            current_memory_usage,
+            peak_memory_usage,
+            cycles,
+            instructions,
+            cache_misses,
+            io_wait_ns,
+            blocked_ns,
+        );
+    }
+}
+
+impl WireEncode for MetricsReport {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, system, threads, timestamp, priority_inversions, io_wait_by_device);
+    }
+}
+
+impl WireEncode for DeviceIoWait {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, device, waits, wait_ns, max_wait_ns);
+    }
+}
+
+impl WireEncode for PriorityInversion {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, lock, waiter, waiter_priority, owner, owner_priority, waited, detected_at);
+    }
+}
+
+impl WireRecord for MetricsReport {
+    const KIND: RecordKind = RecordKind::MetricsReport;
+}
+
+// Profiling
+
+impl WireEncode for CallStack {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, frames, total_depth);
+    }
+}
+
+impl WireEncode for FunctionProfile {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, address, sample_count, cpu_time_ns, call_count);
+    }
+}
+
+impl WireEncode for ThreadProfileData {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            thread_id,
+            sample_count,
+            total_cpu_time,
+            peak_memory_usage,
+            avg_cpu_usage,
+            context_switches,
+            top_functions,
+        );
+    }
+}
+
+impl WireEncode for HotSpot {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, address, sample_count, sample_percentage, call_patterns);
+    }
+}
+
+impl WireEncode for AllocationPattern {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, allocation_count, avg_size, max_size, min_size, size_stddev);
+    }
+}
+
+impl WireEncode for MemoryProfile {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            total_allocations,
+            total_deallocations,
+            bytes_allocated,
+            bytes_deallocated,
+            allocation_patterns,
+            peak_memory_usage,
+            fragmentation_estimate,
+        );
+    }
+}
+
+impl WireEncode for ContextSwitchProfile {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            total_switches,
+            avg_switch_latency,
+            max_switch_latency,
+            min_switch_latency,
+            switches_by_reason,
+            switches_per_second,
+        );
+    }
+}
+
+impl WireEncode for SchedulerProfile {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            total_decisions,
+            avg_decision_latency,
+            max_decision_latency,
+            avg_ready_queue_length,
+            max_ready_queue_length,
+            load_balance_efficiency,
+        );
+    }
+}
+
+impl WireEncode for ProfileData {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            total_samples,
+            thread_samples,
+            hot_spots,
+            memory_patterns,
+            context_switch_analysis,
+            scheduler_metrics,
+        );
+        self.time_range.0.encode(enc);
+        self.time_range.1.encode(enc);
+    }
+}
+
+impl WireRecord for ProfileData {
+    const KIND: RecordKind = RecordKind::ProfileData;
+}
+
+// Health
+
+impl WireEncode for ComponentMetrics {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            resource_utilization,
+            error_rate,
+            avg_response_time_us,
+            success_rate,
+            queue_depth,
+            custom_metrics,
+        );
+    }
+}
+
+impl WireEncode for HealthIssue {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            severity,
+            category,
+            description,
+            component,
+            detected_at,
+            context,
+            affected_threads,
+            remediation,
+        );
+    }
+}
+
+impl WireEncode for ComponentHealth {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, name, status, metrics, last_check, issues);
+    }
+}
+
+impl WireEncode for HealthHistoryEntry {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, timestamp, health_score, active_issues);
+    }
+}
+
+impl WireEncode for HealthTrend {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, direction, change_rate, confidence, history);
+    }
+}
+
+impl WireEncode for SystemHealth {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(
+            enc,
+            self,
+            overall_status,
+            components,
+            active_issues,
+            timestamp,
+            uptime,
+            trend,
+        );
+    }
+}
+
+impl WireRecord for SystemHealth {
+    const KIND: RecordKind = RecordKind::SystemHealth;
+}
+
+// Scheduler
+
+impl WireEncode for LatencyHistogram {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.seq(self.buckets());
+        self.count().encode(enc);
+        self.mean().encode(enc);
+        self.max().encode(enc);
+    }
+}
+
+impl WireEncode for SchedulerStats {
+    fn encode(&self, enc: &mut Encoder) {
+        encode_fields!(enc, self, since, taken_at);
+        enc.seq(&self.slice_utilization);
+        encode_fields!(enc, self, switch_reasons, wake_to_dispatch);
+    }
+}
+
+impl WireRecord for SchedulerStats {
+    const KIND: RecordKind = RecordKind::SchedulerStats;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_encoding() {
+        let mut enc = Encoder::new();
+        enc.varint(0);
+        enc.varint(127);
+        enc.varint(128);
+        enc.varint(300);
+        assert_eq!(enc.as_bytes(), &[0x00, 0x7f, 0x80, 0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_metrics_record_framing() {
+        let report = MetricsReport {
+            system: SystemMetricsSnapshot {
+                threads_created: 3,
+                threads_destroyed: 1,
+                active_threads: 2,
+                total_context_switches: 1000,
+                total_cpu_time_ns: 0,
+                cpu_utilization: 0.5,
+                context_switches_per_second: 0.0,
+                current_memory_usage: 0,
+                peak_memory_usage: 0,
+                preempt_disables: 0,
+                max_preempt_depth: 0,
+                might_sleep_violations: 0,
+                priority_inversions: 0,
+                total_io_wait_ns: 0,
+                total_blocked_ns: 0,
+            },
+            threads: Vec::new(),
+            timestamp: Instant::from_nanos(42),
+            priority_inversions: Vec::new(),
+            io_wait_by_device: Vec::new(),
+        };
+
+        let record = encode_record(&report).unwrap();
+        let mut input: &[u8] = &record;
+        let header = RecordHeader::read(&mut input).unwrap();
+        assert_eq!(header.version, WIRE_VERSION);
+        assert_eq!(RecordKind::from_u8(header.kind), Some(RecordKind::MetricsReport));
+        assert_eq!(header.length as usize, record.len() - HEADER_LEN);
+        assert_eq!(input.len(), header.length as usize);
+
+        // Counters first, varint-encoded
+        assert_eq!(&input[..5], &[3, 1, 2, 0xe8, 0x07]);
+
+        let mut bad = record.clone();
+        bad[0] = b'X';
+        let mut input: &[u8] = &bad;
+        assert_eq!(RecordHeader::read(&mut input), Err(WireError::BadMagic));
+    }
+}
@@ -4,10 +4,35 @@ use crate::arch::barriers::CacheLinePadded;
 use crate::perf::{PerfConfig, PERF_COUNTERS};
 use crate::thread_new::Thread;
 use crate::sched::CpuId;
+use crate::arch::detection::{CacheTopology, detect_cache_topology};
 use portable_atomic::{AtomicUsize, AtomicU64, AtomicPtr, Ordering};
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+/// Cache topology used for steal ordering, detected on first use.
+static CACHE_TOPOLOGY: spin::Mutex<Option<CacheTopology>> = spin::Mutex::new(None);
+
+/// Get the system cache topology.
+///
+/// The topology is detected through `arch::detection` the first time it is
+/// requested unless one was set with [`configure_topology`].
+pub fn topology() -> CacheTopology {
+    let mut guard = CACHE_TOPOLOGY.lock();
+    if let Some(topology) = guard.as_ref() {
+        return topology.clone();
+    }
+    
+    let topology = detect_cache_topology(crate::perf::get_perf_config().cpu_count.max(1));
+    *guard = Some(topology.clone());
+    topology
+}
+
+/// Override the detected cache topology.
+pub fn configure_topology(topology: CacheTopology) {
+    *CACHE_TOPOLOGY.lock() = Some(topology);
+}
+
 /// Cache-optimized per-CPU scheduler data structure.
 #[repr(align(64))] // Align to cache line
 pub struct CacheOptimizedCpuData {
@@ -145,6 +170,9 @@ pub struct CacheAwareScheduler {
     /// Per-CPU data (cache line aligned)
     cpu_data: Vec<CacheLinePadded<CacheOptimizedCpuData>>,
     
+    /// Victims each CPU tries when stealing, closest cache first
+    steal_order: Box<[Box<[CpuId]>]>,
+    
     /// Global state (cold data, separate cache lines)
     total_threads: AtomicUsize,
     active_cpus: AtomicUsize,
@@ -155,15 +183,19 @@ pub struct CacheAwareScheduler {
 
 impl CacheAwareScheduler {
     pub fn new(config: PerfConfig) -> Self {
+        let topology = topology();
         let mut cpu_data = Vec::with_capacity(config.cpu_count);
+        let mut steal_order = Vec::with_capacity(config.cpu_count);
         for cpu_id in 0..config.cpu_count {
             cpu_data.push(CacheLinePadded::new(
                 CacheOptimizedCpuData::new(cpu_id as CpuId)
             ));
+            steal_order.push(topology.steal_order(cpu_id, config.cpu_count).into_boxed_slice());
         }
         
         Self {
             cpu_data,
+            steal_order: steal_order.into_boxed_slice(),
             total_threads: AtomicUsize::new(0),
             active_cpus: AtomicUsize::new(config.cpu_count),
             config,
@@ -228,24 +260,16 @@ impl CacheAwareScheduler {
     
     /// Work stealing implementation with cache-aware CPU selection.
     fn try_work_stealing(&self, cpu_id: CpuId) -> Option<Arc<Thread>> {
-        let cpu_count = self.cpu_data.len();
+        let order = self.steal_order.get(cpu_id)?;
         
-        // Try nearby CPUs first (better cache locality)
-        for distance in 1..cpu_count {
-            // Check both directions
-            for direction in [1, -1] {
-                let target_cpu = ((cpu_id as isize + direction * distance as isize + cpu_count as isize) 
-                    % cpu_count as isize) as usize;
-                
-                if target_cpu < self.cpu_data.len() {
-                    let target_data = self.cpu_data[target_cpu].get();
-                    
-                    // Only steal if target has enough work
-                    if target_data.local_queue_length() > 1 {
-                        if let Some(stolen_thread) = self.steal_from_cpu(target_data) {
-                            return Some(stolen_thread);
-                        }
-                    }
+        // Try cache-sharing CPUs first (better cache locality)
+        for &target_cpu in order.iter() {
+            let target_data = self.cpu_data[target_cpu].get();
+            
+            // Only steal if target has enough work
+            if target_data.local_queue_length() > 1 {
+                if let Some(stolen_thread) = self.steal_from_cpu(target_data) {
+                    return Some(stolen_thread);
                 }
             }
         }
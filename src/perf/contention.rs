@@ -0,0 +1,251 @@
+//! Cache line contention detection.
+//!
+//! A cache line written from several CPUs moves between their caches on
+//! every write, and when the CPUs write different variables that merely
+//! share the line, the cost is pure false sharing. With detection
+//! [enabled](enable), every `n`th lock acquisition or counter update on a
+//! CPU is sampled: the address, its cache line, the CPU and the source file
+//! of the access go into a table of hot lines. A sample of a line last
+//! sampled on another CPU within the [window](set_window) counts as a bounce
+//! in [`PerfCounters::cache_bounces`] and against the line, and
+//! [`report`] lists the lines that bounced, worst first:
+//!
+//! ```ignore
+//! contention::enable(contention::DEFAULT_SAMPLE_PERIOD);
+//! run_workload();
+//! for line in contention::report().iter().filter(|line| line.is_false_sharing()) {
+//!     log::warn!("{}", line);
+//! }
+//! ```
+//!
+//! [`Mutex`](crate::safe_api::Mutex) and the
+//! [fast paths](super::fast_paths::FastPaths) sample themselves; other hot
+//! variables can be sampled with [`sample`]. Sampling is off by default and
+//! costs one atomic load per access while off.
+//!
+//! [`PerfCounters::cache_bounces`]: super::PerfCounters::cache_bounces
+
+use super::PERF_COUNTERS;
+use crate::arch::detection::detect_cpu_features;
+use crate::arch::percpu::{self, MAX_CPUS};
+use crate::sync::SpinLockIrqSave;
+use crate::time::{Duration, Instant};
+use core::fmt;
+use core::panic::Location;
+use portable_atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Accesses per CPU sampled once unless set otherwise.
+pub const DEFAULT_SAMPLE_PERIOD: u32 = 16;
+
+/// Window a line must be sampled on another CPU within to count as a
+/// bounce unless set otherwise (100 µs).
+pub const DEFAULT_WINDOW_US: u64 = 100;
+
+/// Lines tracked at once; the coldest is dropped to make room.
+pub const MAX_LINES: usize = 256;
+
+/// Addresses and source files kept per line.
+pub const MAX_PER_LINE: usize = 8;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static PERIOD: AtomicU32 = AtomicU32::new(DEFAULT_SAMPLE_PERIOD);
+
+static WINDOW_NS: AtomicU64 = AtomicU64::new(DEFAULT_WINDOW_US * 1_000);
+
+static LINE_SIZE: AtomicUsize = AtomicUsize::new(64);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_ACCESSES: AtomicU32 = AtomicU32::new(0);
+
+/// Accesses seen per CPU, to pick every `PERIOD`th.
+static ACCESSES: [AtomicU32; MAX_CPUS] = [NO_ACCESSES; MAX_CPUS];
+
+/// Samples of one cache line.
+struct Line {
+    last_cpu: usize,
+    last_ns: u64,
+    samples: u64,
+    bounces: u64,
+    /// Bit per CPU that touched the line
+    cpus: u64,
+    addresses: Vec<usize>,
+    modules: Vec<&'static str>,
+}
+
+static LINES: SpinLockIrqSave<BTreeMap<usize, Line>> = SpinLockIrqSave::new(BTreeMap::new());
+
+/// A cache line sampled on different CPUs in quick succession.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContendedLine {
+    /// Address of the start of the line
+    pub line: usize,
+    pub samples: u64,
+    /// Samples that found the line last sampled on another CPU
+    pub bounces: u64,
+    /// Bit per CPU that touched the line
+    pub cpus: u64,
+    /// Distinct addresses sampled in the line, up to [`MAX_PER_LINE`]
+    pub addresses: Vec<usize>,
+    /// Source files of the sampled accesses, up to [`MAX_PER_LINE`]
+    pub modules: Vec<&'static str>,
+}
+
+impl ContendedLine {
+    /// Check whether the line bounces between different variables rather
+    /// than over one shared variable, which padding would fix.
+    pub fn is_false_sharing(&self) -> bool {
+        self.addresses.len() > 1
+    }
+}
+
+impl fmt::Display for ContendedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cache line {:#x}: {} bounces in {} samples on {} CPUs",
+            self.line,
+            self.bounces,
+            self.samples,
+            self.cpus.count_ones(),
+        )?;
+        if self.is_false_sharing() {
+            write!(f, ", likely false sharing between {} addresses", self.addresses.len())?;
+        }
+        write!(f, ", touched by {}", self.modules.join(", "))
+    }
+}
+
+/// Start sampling one in every `period` accesses on each CPU.
+pub fn enable(period: u32) {
+    LINE_SIZE.store((detect_cpu_features().cache_line_size as usize).max(1), Ordering::Relaxed);
+    PERIOD.store(period.max(1), Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// Stop sampling, keeping the samples taken.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+/// Check whether accesses are sampled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Count samples of a line on another CPU as bounces if they are at most
+/// `window` apart.
+pub fn set_window(window: Duration) {
+    WINDOW_NS.store(window.as_nanos(), Ordering::Relaxed);
+}
+
+/// Forget the samples taken.
+pub fn reset() {
+    let lines = core::mem::take(&mut *LINES.lock());
+    drop(lines);
+}
+
+/// Note an access to `addr` from the calling source file, sampling it if
+/// it is this CPU's `period`th.
+#[track_caller]
+#[inline]
+pub fn sample(addr: usize) {
+    if !is_enabled() {
+        return;
+    }
+    let cpu = percpu::cpu_id();
+    let period = PERIOD.load(Ordering::Relaxed);
+    if ACCESSES[cpu].fetch_add(1, Ordering::Relaxed) % period != 0 {
+        return;
+    }
+    record(addr, cpu, Instant::now().as_nanos(), Location::caller().file());
+}
+
+/// Record a sample of `addr` taken on `cpu` at `now_ns` from `module`.
+/// Returns whether it was a bounce.
+fn record(addr: usize, cpu: usize, now_ns: u64, module: &'static str) -> bool {
+    let line_size = LINE_SIZE.load(Ordering::Relaxed);
+    let start = addr - addr % line_size;
+    let window = WINDOW_NS.load(Ordering::Relaxed);
+
+    let mut lines = LINES.lock();
+    if lines.len() == MAX_LINES && !lines.contains_key(&start) {
+        let coldest = lines.iter().min_by_key(|(_, line)| (line.bounces, line.last_ns)).map(|(&start, _)| start);
+        if let Some(coldest) = coldest {
+            lines.remove(&coldest);
+        }
+    }
+    let line = lines.entry(start).or_insert_with(|| Line {
+        last_cpu: cpu,
+        last_ns: now_ns,
+        samples: 0,
+        bounces: 0,
+        cpus: 0,
+        addresses: Vec::new(),
+        modules: Vec::new(),
+    });
+
+    let bounced = line.last_cpu != cpu && now_ns.saturating_sub(line.last_ns) <= window;
+    line.samples += 1;
+    line.bounces += bounced as u64;
+    line.cpus |= 1 << (cpu % 64);
+    line.last_cpu = cpu;
+    line.last_ns = now_ns;
+    if line.addresses.len() < MAX_PER_LINE && !line.addresses.contains(&addr) {
+        line.addresses.push(addr);
+    }
+    if line.modules.len() < MAX_PER_LINE && !line.modules.contains(&module) {
+        line.modules.push(module);
+    }
+    drop(lines);
+
+    if bounced {
+        PERF_COUNTERS.record_cache_bounce();
+    }
+    bounced
+}
+
+/// Get the lines that bounced, the most bounces first.
+pub fn report() -> Vec<ContendedLine> {
+    let mut report: Vec<ContendedLine> = LINES
+        .lock()
+        .iter()
+        .filter(|(_, line)| line.bounces > 0)
+        .map(|(&start, line)| ContendedLine {
+            line: start,
+            samples: line.samples,
+            bounces: line.bounces,
+            cpus: line.cpus,
+            addresses: line.addresses.clone(),
+            modules: line.modules.clone(),
+        })
+        .collect();
+    report.sort_by_key(|line| core::cmp::Reverse(line.bounces));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounces_between_cpus_flag_false_sharing() {
+        let line = 0x7ead_0000;
+        let before = PERF_COUNTERS.cache_bounces.load(Ordering::Relaxed);
+
+        assert!(!record(line, 0, 1_000, "src/a.rs"));
+        // Another variable in the same line, on another CPU shortly after
+        assert!(record(line + 8, 1, 2_000, "src/b.rs"));
+        assert!(record(line, 0, 3_000, "src/a.rs"));
+        // Too long after to have bounced
+        assert!(!record(line, 1, 3_000 + WINDOW_NS.load(Ordering::Relaxed) + 1, "src/a.rs"));
+
+        let contended = report().into_iter().find(|contended| contended.line == line).unwrap();
+        assert_eq!((contended.samples, contended.bounces, contended.cpus), (4, 2, 0b11));
+        assert!(contended.is_false_sharing());
+        assert_eq!(contended.modules, ["src/a.rs", "src/b.rs"]);
+        assert!(PERF_COUNTERS.cache_bounces.load(Ordering::Relaxed) >= before + 2);
+    }
+}
@@ -1,10 +1,263 @@
 //! Context switch optimizations with micro-benchmarking support.
 
 use crate::perf::PERF_COUNTERS;
-use crate::arch::Arch;
+use crate::arch::{Arch, DefaultArch};
+use crate::thread_new::Thread;
 use crate::time::{get_monotonic_time, Duration};
 use portable_atomic::{AtomicU64, AtomicU32, Ordering};
 
+/// Wide-copy helpers for moving saved contexts and FPU/vector register blocks.
+///
+/// Saved contexts are large (an FXSAVE area alone is 512 bytes), so copying
+/// them 16 or 32 bytes at a time is noticeably cheaper than word-at-a-time.
+pub mod simd_copy {
+    use crate::perf::{get_perf_config, PERF_COUNTERS};
+
+    /// Copy `len` bytes one machine word at a time.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be valid for `len` bytes of reads, `dst` for `len` bytes of
+    /// writes, and the two ranges must not overlap.
+    pub unsafe fn copy_scalar(dst: *mut u8, src: *const u8, len: usize) {
+        let words = len / 8;
+        for i in 0..words {
+            unsafe {
+                let word = core::ptr::read_unaligned((src as *const u64).add(i));
+                core::ptr::write_unaligned((dst as *mut u64).add(i), word);
+            }
+        }
+        for i in words * 8..len {
+            unsafe { *dst.add(i) = *src.add(i); }
+        }
+    }
+
+    /// Copy `len` bytes with the widest vector registers available.
+    ///
+    /// Uses AVX (32 bytes) or SSE2 (16 bytes) on x86_64 and NEON (16 bytes)
+    /// on aarch64, falling back to [`copy_scalar`] elsewhere.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`copy_scalar`].
+    pub unsafe fn copy_wide(dst: *mut u8, src: *const u8, len: usize) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if avx_available() {
+                unsafe { copy_avx(dst, src, len) };
+            } else {
+                unsafe { copy_sse2(dst, src, len) };
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        unsafe { copy_neon(dst, src, len) };
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        unsafe { copy_scalar(dst, src, len) };
+    }
+
+    /// Blocks smaller than this are copied with scalar moves; below it the
+    /// vector setup cost outweighs the wider stores.
+    pub const SIMD_COPY_THRESHOLD: usize = 256;
+
+    /// Copy a context block, using SIMD when `PerfConfig::use_simd` is set
+    /// and the block is at least [`SIMD_COPY_THRESHOLD`] bytes.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`copy_scalar`].
+    pub unsafe fn copy_context_block(dst: *mut u8, src: *const u8, len: usize) {
+        if len >= SIMD_COPY_THRESHOLD && get_perf_config().use_simd {
+            PERF_COUNTERS.record_simd_operation();
+            unsafe { copy_wide(dst, src, len) };
+        } else {
+            unsafe { copy_scalar(dst, src, len) };
+        }
+    }
+
+    /// Whether the AVX path may be used.
+    #[cfg(target_arch = "x86_64")]
+    fn avx_available() -> bool {
+        #[cfg(feature = "x86_64")]
+        {
+            crate::arch::detection::detect_cpu_features().supports_avx
+        }
+        #[cfg(not(feature = "x86_64"))]
+        {
+            cfg!(target_feature = "avx")
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    unsafe fn copy_avx(dst: *mut u8, src: *const u8, len: usize) {
+        use core::arch::x86_64::{__m256i, _mm256_loadu_si256, _mm256_storeu_si256};
+
+        let chunks = len / 32;
+        for i in 0..chunks {
+            unsafe {
+                let value = _mm256_loadu_si256((src as *const __m256i).add(i));
+                _mm256_storeu_si256((dst as *mut __m256i).add(i), value);
+            }
+        }
+        unsafe { copy_scalar(dst.add(chunks * 32), src.add(chunks * 32), len - chunks * 32) };
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn copy_sse2(dst: *mut u8, src: *const u8, len: usize) {
+        use core::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_storeu_si128};
+
+        let chunks = len / 16;
+        for i in 0..chunks {
+            unsafe {
+                let value = _mm_loadu_si128((src as *const __m128i).add(i));
+                _mm_storeu_si128((dst as *mut __m128i).add(i), value);
+            }
+        }
+        unsafe { copy_scalar(dst.add(chunks * 16), src.add(chunks * 16), len - chunks * 16) };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn copy_neon(dst: *mut u8, src: *const u8, len: usize) {
+        use core::arch::aarch64::{vld1q_u8, vst1q_u8};
+
+        let chunks = len / 16;
+        for i in 0..chunks {
+            unsafe {
+                let value = vld1q_u8(src.add(i * 16));
+                vst1q_u8(dst.add(i * 16), value);
+            }
+        }
+        unsafe { copy_scalar(dst.add(chunks * 16), src.add(chunks * 16), len - chunks * 16) };
+    }
+}
+
+/// Path taken for a thread-to-thread switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchPath {
+    /// Save and restore FPU/vector state (with `full-fpu`) around the switch
+    Full,
+    /// Sibling threads in one switch domain: general registers only
+    SameDomain,
+}
+
+/// Choose the cheapest safe switch path between two threads.
+///
+/// Siblings still take the full path if either has state only it switches,
+/// like SVE registers, so the other can't see or clobber that state.
+pub fn select_switch_path(prev: &Thread, next: &Thread) -> SwitchPath {
+    if prev.shares_switch_domain(next) && !has_extended_state(prev) && !has_extended_state(next) {
+        SwitchPath::SameDomain
+    } else {
+        SwitchPath::Full
+    }
+}
+
+/// Check whether `thread`'s saved context holds state beyond the general
+/// registers the same-domain path switches.
+fn has_extended_state(thread: &Thread) -> bool {
+    let ctx = thread.context_ptr();
+    // Safety: a thread's saved context lives as long as the thread
+    !ctx.is_null() && DefaultArch::has_extended_state(unsafe { &*ctx })
+}
+
+/// Switch from `prev` to `next` using the path chosen by [`select_switch_path`].
+///
+/// Returns the path taken, or `None` if either thread has no saved context
+/// yet and no switch was performed. Same-domain switches are counted in
+/// `PERF_COUNTERS.optimized_context_switches`.
+///
+/// # Safety
+///
+/// Must be called with preemption disabled, with `prev` being the thread
+/// currently executing on this CPU.
+pub unsafe fn switch_threads(prev: &Thread, next: &Thread) -> Option<SwitchPath> {
+    let prev_ctx = prev.context_ptr();
+    let next_ctx = next.context_ptr();
+    if prev_ctx.is_null() || next_ctx.is_null() {
+        return None;
+    }
+    
+    // Close the outgoing thread's run slice and open the incoming one's
+    let now = crate::time::cpu_clock();
+    if let Some(ran) = prev.account_switch_out(now) {
+        crate::sched::tuning::record_switch(prev, next, ran);
+    }
+    crate::perf::pmu::switch_out(prev.id());
+    next.account_switch_in(now);
+    // Catch an overflow of the outgoing thread's stack before anything
+    // else runs on top of the damage
+    #[cfg(feature = "hardened")]
+    if !prev.check_stack_integrity() {
+        crate::security::handle_security_violation(crate::security::SecurityViolation::StackCanaryViolation);
+    }
+    crate::observability::OBSERVER_HUB.switch(prev, next);
+    crate::observability::trace::tracepoint!(CONTEXT_SWITCH, ContextSwitch { prev: prev.id(), next: next.id() });
+    
+    let path = select_switch_path(prev, next);
+    // Threads outside a shared switch domain don't trust each other
+    if path == SwitchPath::Full && crate::security::speculation_barriers_enabled() {
+        DefaultArch::speculation_barrier();
+    }
+    // Only loads page tables if the CPU has a different space loaded
+    if let Some(space) = next.address_space() {
+        space.activate();
+    }
+    crate::time::VDSO_DATA.set_current_thread(crate::arch::percpu::cpu_id(), Some(next.id()));
+    if next.is_user_thread() {
+        if let Some(top) = next.kernel_stack_top() {
+            // Safety: `next` owns this stack and is about to run on it
+            unsafe { DefaultArch::set_kernel_stack(top) };
+        }
+    }
+    crate::kernel::run_switch_hooks(prev.id(), next.id());
+    unsafe {
+        match path {
+            SwitchPath::Full => {
+                // FPU state is only tracked per thread with full-fpu
+                #[cfg(feature = "full-fpu")]
+                {
+                    DefaultArch::save_fpu(&mut *prev_ctx);
+                    DefaultArch::restore_fpu(&*next_ctx);
+                }
+            }
+            SwitchPath::SameDomain => {
+                PERF_COUNTERS.record_context_switch();
+            }
+        }
+        if let Some(pc) = DefaultArch::saved_program_counter(&*next_ctx) {
+            crate::security::cfi::verify_saved_pc(pc);
+        }
+        DefaultArch::context_switch(prev_ctx, next_ctx);
+    }
+    
+    Some(path)
+}
+
+/// Copy a saved context, including any FPU/vector register block, with
+/// [`copy_context_block`](simd_copy::copy_context_block).
+///
+/// Used where a context is duplicated rather than switched to: debugger
+/// snapshots and checkpoint restore.
+///
+/// # Safety
+///
+/// `src` must point to a valid context, `dst` must be valid for writing
+/// one, and the two must not overlap.
+pub unsafe fn copy_context(
+    dst: *mut <DefaultArch as Arch>::SavedContext,
+    src: *const <DefaultArch as Arch>::SavedContext,
+) {
+    unsafe {
+        simd_copy::copy_context_block(
+            dst as *mut u8,
+            src as *const u8,
+            core::mem::size_of::<<DefaultArch as Arch>::SavedContext>(),
+        );
+    }
+}
+
 /// Context switch optimization techniques.
 pub struct ContextSwitchOptimizer<A: Arch> {
     /// Architecture-specific optimizations
@@ -117,7 +370,6 @@ impl<A: Arch> ContextSwitchOptimizer<A> {
         
         // Record switch
         self.switch_times.total_switches.fetch_add(1, Ordering::Relaxed);
-        PERF_COUNTERS.record_context_switch();
         
         // Perform the actual context switch
         unsafe {
@@ -440,4 +692,51 @@ pub fn get_context_switch_stats() -> Option<ContextSwitchStats> {
     unsafe {
         CONTEXT_SWITCH_OPTIMIZER.as_ref().map(|opt| opt.get_switch_stats())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::simd_copy::{copy_scalar, copy_wide};
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn test_simd_and_scalar_copies_match() {
+        let src: [u8; 1024] = core::array::from_fn(|i| (i * 7 + 3) as u8);
+
+        for src_offset in 0..16 {
+            for dst_offset in 0..16 {
+                for len in [0, 1, 7, 8, 15, 16, 31, 32, 33, 255, 256, 512, 832, 1000] {
+                    let mut scalar = [0u8; 1040];
+                    let mut wide = [0u8; 1040];
+                    let from = src[src_offset..].as_ptr();
+                    unsafe {
+                        copy_scalar(scalar[dst_offset..].as_mut_ptr(), from, len);
+                        copy_wide(wide[dst_offset..].as_mut_ptr(), from, len);
+                    }
+                    assert_eq!(scalar, wide, "offsets {}/{}, len {}", src_offset, dst_offset, len);
+                    assert_eq!(&wide[dst_offset..dst_offset + len], &src[src_offset..src_offset + len]);
+                    // Nothing is written past the end
+                    assert!(wide[dst_offset + len..].iter().all(|&byte| byte == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_copy_context() {
+        type SavedContext = <DefaultArch as Arch>::SavedContext;
+        let size = core::mem::size_of::<SavedContext>();
+
+        let mut src = MaybeUninit::<SavedContext>::uninit();
+        let mut dst = MaybeUninit::<SavedContext>::zeroed();
+        unsafe {
+            for i in 0..size {
+                *(src.as_mut_ptr() as *mut u8).add(i) = i as u8 ^ 0x5A;
+            }
+            copy_context(dst.as_mut_ptr(), src.as_ptr());
+            let src = core::slice::from_raw_parts(src.as_ptr() as *const u8, size);
+            let dst = core::slice::from_raw_parts(dst.as_ptr() as *const u8, size);
+            assert_eq!(src, dst);
+        }
+    }
+}
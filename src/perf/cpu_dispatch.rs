@@ -72,6 +72,8 @@ pub static CPU_DISPATCH: CpuDispatchTable = CpuDispatchTable {
         supports_avx: false,
         #[cfg(feature = "x86_64")]
         supports_avx512: false,
+        #[cfg(feature = "x86_64")]
+        supports_fsgsbase: false,
         #[cfg(feature = "arm64")]
         supports_neon: false,
         #[cfg(feature = "arm64")]
@@ -1,6 +1,13 @@
 //! Lock-free fast paths for common threading operations.
+//!
+//! Every fast path counts in [`PERF_COUNTERS`] whether it completed on the
+//! fast path, fell back to a slow path, or found its target contended.
+//! [`FastPathMetrics::current`] reads those counters back as a snapshot.
+//! Mutex and counter fast paths are also [sampled](contention::sample) for
+//! cache line contention.
 
-use crate::perf::PERF_COUNTERS;
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::perf::{contention, PERF_COUNTERS};
 use crate::thread_new::{Thread, ThreadId, ThreadState};
 use crate::sched::CpuId;
 use portable_atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
@@ -31,9 +38,42 @@ impl FastPaths {
         crate::thread_new::current_thread_id()
     }
     
+    /// Fast path for getting the current thread, read from this CPU's slot
+    /// without locking. Returns `None` before the scheduler has installed a
+    /// thread on this CPU.
+    #[inline(always)]
+    pub fn fast_current_thread() -> Option<Thread> {
+        PERF_COUNTERS.record_fast_path();
+        crate::thread_new::current()
+    }
+    
+    /// Check whether the preemption timer has asked for a reschedule
+    /// (single atomic read).
+    #[inline(always)]
+    pub fn should_yield() -> bool {
+        crate::platform_timer::is_preemption_pending()
+    }
+    
+    /// Yield only if a reschedule is pending. Returns whether the thread
+    /// yielded.
+    ///
+    /// Cheap enough to call on every iteration of a hot loop.
+    #[inline(always)]
+    pub fn fast_yield_check() -> bool {
+        if !Self::should_yield() {
+            PERF_COUNTERS.record_fast_path();
+            return false;
+        }
+        PERF_COUNTERS.record_slow_path();
+        crate::platform_timer::preemption_checkpoint();
+        true
+    }
+    
     /// Fast path for simple mutex lock attempt.
     #[inline(always)]
+    #[track_caller]
     pub fn fast_mutex_try_lock(mutex_state: &AtomicUsize) -> bool {
+        contention::sample(mutex_state as *const AtomicUsize as usize);
         // Try to acquire mutex with single CAS
         let result = mutex_state
             .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
@@ -43,6 +83,7 @@ impl FastPaths {
             PERF_COUNTERS.record_fast_path();
         } else {
             PERF_COUNTERS.record_slow_path();
+            PERF_COUNTERS.record_contention();
         }
         
         result
@@ -57,11 +98,21 @@ impl FastPaths {
     
     /// Fast path for atomic counter increment.
     #[inline(always)]
+    #[track_caller]
     pub fn fast_atomic_increment(counter: &AtomicU64) -> u64 {
+        contention::sample(counter as *const AtomicU64 as usize);
         PERF_COUNTERS.record_lockfree_operation();
         counter.fetch_add(1, Ordering::Relaxed)
     }
     
+    /// Fast path for incrementing a per-CPU counter. Touches only this
+    /// CPU's cache line.
+    #[inline(always)]
+    pub fn fast_per_cpu_increment(counter: &PerCpuCounter) {
+        PERF_COUNTERS.record_lockfree_operation();
+        counter.increment();
+    }
+    
     /// Fast path for thread priority comparison.
     #[inline(always)]
     pub fn fast_priority_compare(thread1: &Thread, thread2: &Thread) -> core::cmp::Ordering {
@@ -73,8 +124,64 @@ impl FastPaths {
     #[inline(always)]
     pub fn fast_affinity_check(thread: &Thread, cpu_id: CpuId) -> bool {
         PERF_COUNTERS.record_fast_path();
-        let affinity = thread.cpu_affinity();
-        (affinity & (1u64 << cpu_id)) != 0
+        thread.cpu_affinity().contains(cpu_id)
+    }
+}
+
+/// One CPU's share of a [`PerCpuCounter`], alone on its cache line.
+#[repr(align(64))]
+struct CpuCount(AtomicU64);
+
+/// Counter split into one cache line per CPU.
+///
+/// Increments only touch the executing CPU's line, so CPUs counting at the
+/// same time do not bounce it between them. Reading the total sums all
+/// lines and may miss increments made while it runs.
+pub struct PerCpuCounter {
+    counts: [CpuCount; MAX_CPUS],
+}
+
+impl Default for PerCpuCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PerCpuCounter {
+    /// Create a counter at zero.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: CpuCount = CpuCount(AtomicU64::new(0));
+        Self { counts: [ZERO; MAX_CPUS] }
+    }
+    
+    /// Add `n` to the executing CPU's count.
+    #[inline(always)]
+    pub fn add(&self, n: u64) {
+        self.counts[cpu_id()].0.fetch_add(n, Ordering::Relaxed);
+    }
+    
+    /// Add one to the executing CPU's count.
+    #[inline(always)]
+    pub fn increment(&self) {
+        self.add(1);
+    }
+    
+    /// Get the count of CPU `cpu`.
+    pub fn get(&self, cpu: usize) -> u64 {
+        self.counts.get(cpu).map_or(0, |count| count.0.load(Ordering::Relaxed))
+    }
+    
+    /// Get the total over all CPUs.
+    pub fn sum(&self) -> u64 {
+        self.counts.iter().map(|count| count.0.load(Ordering::Relaxed)).sum()
+    }
+    
+    /// Reset every CPU's count to zero.
+    pub fn reset(&self) {
+        for count in &self.counts {
+            count.0.store(0, Ordering::Relaxed);
+        }
     }
 }
 
@@ -375,6 +482,7 @@ impl CommonPatterns {
                     return Ok(new_value);
                 }
                 Err(actual) => {
+                    PERF_COUNTERS.record_contention();
                     current = actual;
                     retries += 1;
                     core::hint::spin_loop();
@@ -388,21 +496,36 @@ impl CommonPatterns {
 }
 
 /// Performance metrics for fast path effectiveness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct FastPathMetrics {
+    /// Operations completed on a fast path
     pub fast_path_hits: u64,
+    /// Operations that fell back to a slow path
     pub slow_path_hits: u64,
+    /// Lock-free operations completed
     pub lockfree_operations: u64,
+    /// Fast paths that found their target contended
     pub contention_events: u64,
 }
 
 impl FastPathMetrics {
-    /// Get current fast path metrics.
+    /// Get current fast path metrics from [`PERF_COUNTERS`].
     pub fn current() -> Self {
         Self {
             fast_path_hits: PERF_COUNTERS.fast_path_hits.load(Ordering::Relaxed),
             slow_path_hits: PERF_COUNTERS.slow_path_hits.load(Ordering::Relaxed),
             lockfree_operations: PERF_COUNTERS.lockfree_operations.load(Ordering::Relaxed),
-            contention_events: 0, // Would be collected from various sources
+            contention_events: PERF_COUNTERS.contention_events.load(Ordering::Relaxed),
+        }
+    }
+    
+    /// Get the metrics accumulated since the `earlier` snapshot.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            fast_path_hits: self.fast_path_hits.saturating_sub(earlier.fast_path_hits),
+            slow_path_hits: self.slow_path_hits.saturating_sub(earlier.slow_path_hits),
+            lockfree_operations: self.lockfree_operations.saturating_sub(earlier.lockfree_operations),
+            contention_events: self.contention_events.saturating_sub(earlier.contention_events),
         }
     }
     
@@ -425,4 +548,45 @@ impl FastPathMetrics {
             0.0
         }
     }
-}
\ No newline at end of file
+    
+    /// Calculate the share of fast and slow path operations that found
+    /// their target contended.
+    pub fn contention_ratio(&self) -> f64 {
+        let total = self.fast_path_hits + self.slow_path_hits;
+        if total > 0 {
+            self.contention_events as f64 / total as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_cpu_counter_and_contention_metrics() {
+        static COUNTER: PerCpuCounter = PerCpuCounter::new();
+        for _ in 0..3 {
+            FastPaths::fast_per_cpu_increment(&COUNTER);
+        }
+        COUNTER.add(2);
+        assert_eq!(COUNTER.sum(), 5);
+        assert_eq!(COUNTER.get(MAX_CPUS), 0);
+
+        // A held lock is counted as contention
+        let before = FastPathMetrics::current();
+        let lock = AtomicUsize::new(0);
+        assert!(FastPaths::fast_mutex_try_lock(&lock));
+        assert!(!FastPaths::fast_mutex_try_lock(&lock));
+        FastPaths::fast_mutex_unlock(&lock);
+        let delta = FastPathMetrics::current().since(&before);
+        assert!(delta.contention_events >= 1);
+        assert!(delta.fast_path_hits >= 2 && delta.slow_path_hits >= 1);
+        assert!(delta.contention_ratio() > 0.0 && delta.effectiveness_ratio() < 1.0);
+
+        COUNTER.reset();
+        assert_eq!(COUNTER.sum(), 0);
+    }
+}
@@ -0,0 +1,419 @@
+//! Idle-time memory maintenance.
+//!
+//! An [`IdleMaintenance`] task does memory housekeeping while nothing else
+//! wants the CPU. Each pass it
+//!
+//! - scrubs the free stacks of its stack pools. Their contents are checked
+//!   against [`STACK_POISON`](crate::mem::STACK_POISON) and rewritten, so a
+//!   RAM fault on a long-running device shows up as a scrub fault instead
+//!   of corrupting a thread later. A stack that fails is retired and never
+//!   used again;
+//! - compacts the free lists of the global pool, releasing the capacity
+//!   left behind by bursts of frees;
+//! - decays per-CPU pools that grew above their target level back toward
+//!   it.
+//!
+//! Work is split into chunks that each touch a bounded amount of memory,
+//! and [`IdleMaintenance::run_for`] stops between chunks once its time
+//! budget is spent, so the task never keeps a pool locked or delays a
+//! newly woken thread for long. Run it from the idle thread with
+//! [`spawn_idle_maintenance_thread`], or call `run_for` from a platform's
+//! own idle loop.
+//!
+//! ```ignore
+//! static TASK: Once<IdleMaintenance> = Once::new();
+//! let task = TASK.call_once(|| IdleMaintenance::new(manager, IdleMaintenanceConfig::default()));
+//! spawn_idle_maintenance_thread(&kernel, task)?;
+//! ```
+
+use crate::arch::Arch;
+use crate::kernel::{Kernel, SpawnError};
+use crate::mem::{Stack, StackPool, StackSizeClass};
+use crate::perf::memory_pools::MemoryPoolManager;
+use crate::sched::{priority, Scheduler};
+use crate::thread_new::{JoinHandle, ThreadBuilder};
+use crate::time::{get_monotonic_time, Duration};
+use alloc::{vec, vec::Vec};
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Size classes of [`StackPool`] free lists.
+const STACK_CLASSES: [StackSizeClass; 4] = [
+    StackSizeClass::Small,
+    StackSizeClass::Medium,
+    StackSizeClass::Large,
+    StackSizeClass::ExtraLarge,
+];
+
+/// Size classes with their own per-CPU pool.
+const CPU_POOL_CLASSES: [StackSizeClass; 3] = [StackSizeClass::Small, StackSizeClass::Medium, StackSizeClass::Large];
+
+/// Configuration of an [`IdleMaintenance`] task.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleMaintenanceConfig {
+    /// Scrub free stacks
+    pub scrub: bool,
+    /// Compact the global pool's free lists
+    pub compact: bool,
+    /// Decay per-CPU pools toward their target level
+    pub decay: bool,
+    /// Bytes of stack memory scrubbed per chunk
+    pub scrub_chunk_bytes: usize,
+    /// Most stacks a per-CPU pool size class gives back per chunk
+    pub decay_batch: usize,
+    /// Time the idle loop works for before yielding
+    pub slice: Duration,
+}
+
+impl Default for IdleMaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            scrub: true,
+            compact: true,
+            decay: true,
+            scrub_chunk_bytes: 4096,
+            decay_batch: 8,
+            slice: Duration::from_micros(100),
+        }
+    }
+}
+
+/// Counters of an [`IdleMaintenance`] task.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdleMaintenanceStats {
+    /// Completed passes over all the work
+    pub passes: u64,
+    /// Chunks of work done
+    pub chunks: u64,
+    /// Free stacks scrubbed in full
+    pub stacks_scrubbed: u64,
+    /// Bytes of stack memory scrubbed
+    pub bytes_scrubbed: u64,
+    /// Stacks retired because their memory did not hold the pattern
+    pub scrub_faults: u64,
+    /// Bytes of free list capacity released
+    pub bytes_compacted: u64,
+    /// Stacks per-CPU pools gave back
+    pub stacks_decayed: u64,
+    /// Longest single chunk, in nanoseconds
+    pub longest_chunk_ns: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    passes: AtomicU64,
+    chunks: AtomicU64,
+    stacks_scrubbed: AtomicU64,
+    bytes_scrubbed: AtomicU64,
+    scrub_faults: AtomicU64,
+    bytes_compacted: AtomicU64,
+    stacks_decayed: AtomicU64,
+    longest_chunk_ns: AtomicU64,
+}
+
+/// Part of a pass the task is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// Scrubbing free stacks of `STACK_CLASSES[class]` in `pools[pool]`
+    Scrub { pool: usize, class: usize },
+    /// Compacting free lists of `STACK_CLASSES[class]`
+    Compact { class: usize },
+    /// Decaying `CPU_POOL_CLASSES[class]` of CPU `cpu`'s pool
+    Decay { cpu: usize, class: usize },
+}
+
+/// Free stack being scrubbed.
+struct Scrubbing {
+    stack: Stack,
+    /// Bytes of the usable area scrubbed so far
+    offset: usize,
+}
+
+/// Position of the task in its current pass.
+struct Cursor {
+    step: Step,
+    /// Stacks left to scrub in the current free list, counted when the
+    /// step starts so stacks put back are not scrubbed twice in a pass
+    remaining: Option<usize>,
+    scrubbing: Option<Scrubbing>,
+    /// Set when the last step of the pass was done
+    pass_complete: bool,
+}
+
+const FIRST_STEP: Step = Step::Scrub { pool: 0, class: 0 };
+
+/// Background memory maintenance run while the system is idle.
+pub struct IdleMaintenance {
+    manager: &'static MemoryPoolManager,
+    /// Pools whose free stacks are scrubbed and compacted, the manager's
+    /// backing allocator first
+    pools: Vec<&'static StackPool>,
+    config: IdleMaintenanceConfig,
+    cursor: spin::Mutex<Cursor>,
+    counters: Counters,
+}
+
+impl IdleMaintenance {
+    /// Create a task maintaining `manager`'s pools.
+    pub fn new(manager: &'static MemoryPoolManager, config: IdleMaintenanceConfig) -> Self {
+        Self {
+            manager,
+            pools: vec![manager.stack_source()],
+            config,
+            cursor: spin::Mutex::new(Cursor {
+                step: FIRST_STEP,
+                remaining: None,
+                scrubbing: None,
+                pass_complete: false,
+            }),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Also scrub and compact the free stacks of `pool`, for example a
+    /// kernel's stack pool.
+    pub fn with_stack_pool(mut self, pool: &'static StackPool) -> Self {
+        self.pools.push(pool);
+        self
+    }
+
+    /// Get the task's configuration.
+    pub fn config(&self) -> &IdleMaintenanceConfig {
+        &self.config
+    }
+
+    /// Do chunks of work until `budget` is spent or the current pass ends.
+    /// Returns the number of chunks done.
+    ///
+    /// The budget is checked between chunks, so it is overrun by at most
+    /// one chunk.
+    pub fn run_for(&self, budget: Duration) -> usize {
+        let start = get_monotonic_time();
+        let mut chunks = 0;
+        while get_monotonic_time().duration_since(start) < budget {
+            if !self.run_chunk() {
+                break;
+            }
+            chunks += 1;
+        }
+        chunks
+    }
+
+    /// Do one chunk of work. Returns `false`, without doing any, once the
+    /// current pass is complete; the next call starts a new pass.
+    pub fn run_chunk(&self) -> bool {
+        let start = get_monotonic_time();
+        let mut cursor = self.cursor.lock();
+        if core::mem::take(&mut cursor.pass_complete) {
+            self.counters.passes.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        loop {
+            // Scrubbing takes a chunk per slice of a stack, the other steps
+            // a single chunk
+            let worked = match cursor.step {
+                Step::Scrub { pool, class } => self.config.scrub && self.scrub_chunk(&mut cursor, pool, class),
+                Step::Compact { class } => self.config.compact && self.compact_chunk(class),
+                Step::Decay { cpu, class } => self.config.decay && self.decay_chunk(cpu, class),
+            };
+            if worked && matches!(cursor.step, Step::Scrub { .. }) {
+                break;
+            }
+            if !self.advance(&mut cursor) {
+                if worked {
+                    cursor.pass_complete = true;
+                    break;
+                }
+                self.counters.passes.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            if worked {
+                break;
+            }
+        }
+
+        let elapsed = get_monotonic_time().duration_since(start).as_nanos();
+        self.counters.chunks.fetch_add(1, Ordering::Relaxed);
+        self.counters.longest_chunk_ns.fetch_max(elapsed, Ordering::Relaxed);
+        true
+    }
+
+    /// Body of the idle thread: work for a slice, then yield.
+    pub fn idle_loop(&self) -> ! {
+        loop {
+            self.run_for(self.config.slice);
+            crate::sync::yield_thread();
+        }
+    }
+
+    /// Get the task's counters.
+    pub fn stats(&self) -> IdleMaintenanceStats {
+        let counters = &self.counters;
+        IdleMaintenanceStats {
+            passes: counters.passes.load(Ordering::Relaxed),
+            chunks: counters.chunks.load(Ordering::Relaxed),
+            stacks_scrubbed: counters.stacks_scrubbed.load(Ordering::Relaxed),
+            bytes_scrubbed: counters.bytes_scrubbed.load(Ordering::Relaxed),
+            scrub_faults: counters.scrub_faults.load(Ordering::Relaxed),
+            bytes_compacted: counters.bytes_compacted.load(Ordering::Relaxed),
+            stacks_decayed: counters.stacks_decayed.load(Ordering::Relaxed),
+            longest_chunk_ns: counters.longest_chunk_ns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Move to the next step, wrapping to the first at the end of a pass.
+    /// Returns `false` when a pass ended.
+    fn advance(&self, cursor: &mut Cursor) -> bool {
+        cursor.remaining = None;
+        cursor.step = match cursor.step {
+            Step::Scrub { pool, class } if class + 1 < STACK_CLASSES.len() => Step::Scrub { pool, class: class + 1 },
+            Step::Scrub { pool, .. } if pool + 1 < self.pools.len() => Step::Scrub { pool: pool + 1, class: 0 },
+            Step::Scrub { .. } => Step::Compact { class: 0 },
+            Step::Compact { class } if class + 1 < STACK_CLASSES.len() => Step::Compact { class: class + 1 },
+            Step::Compact { .. } => Step::Decay { cpu: 0, class: 0 },
+            Step::Decay { cpu, class } if class + 1 < CPU_POOL_CLASSES.len() => Step::Decay { cpu, class: class + 1 },
+            Step::Decay { cpu, .. } if self.manager.get_cpu_pool(cpu + 1).is_some() => {
+                Step::Decay { cpu: cpu + 1, class: 0 }
+            }
+            Step::Decay { .. } => {
+                cursor.step = FIRST_STEP;
+                return false;
+            }
+        };
+        true
+    }
+
+    /// Scrub up to `scrub_chunk_bytes` of the next free stack of a list.
+    /// Returns `false` once every stack that was free when the step
+    /// started has been scrubbed.
+    fn scrub_chunk(&self, cursor: &mut Cursor, pool: usize, class: usize) -> bool {
+        let stack_pool = self.pools[pool];
+        let size_class = STACK_CLASSES[class];
+
+        let mut scrubbing = match cursor.scrubbing.take() {
+            Some(scrubbing) => scrubbing,
+            None => {
+                let remaining = *cursor.remaining.get_or_insert_with(|| stack_pool.free_count(size_class));
+                if remaining == 0 {
+                    return false;
+                }
+                // An empty or busy list is picked up again next pass
+                let Some(stack) = stack_pool.take_for_scrub(size_class) else {
+                    return false;
+                };
+                cursor.remaining = Some(remaining - 1);
+                Scrubbing { stack, offset: 0 }
+            }
+        };
+
+        let size = scrubbing.stack.size();
+        if scrubbing.offset < size {
+            let len = self.config.scrub_chunk_bytes.max(8).min(size - scrubbing.offset);
+            let intact = stack_pool.scrub(&scrubbing.stack, scrubbing.offset, len);
+            self.counters.bytes_scrubbed.fetch_add(len as u64, Ordering::Relaxed);
+            if !intact {
+                stack_pool.retire(scrubbing.stack);
+                self.counters.scrub_faults.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+            scrubbing.offset += len;
+            if scrubbing.offset < size {
+                cursor.scrubbing = Some(scrubbing);
+                return true;
+            }
+            self.counters.stacks_scrubbed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Hold on to the stack until its free list is not busy
+        if let Err(stack) = stack_pool.return_scrubbed(scrubbing.stack) {
+            cursor.scrubbing = Some(Scrubbing { stack, offset: size });
+        }
+        true
+    }
+
+    /// Compact the free lists of one size class.
+    fn compact_chunk(&self, class: usize) -> bool {
+        let size_class = STACK_CLASSES[class];
+        let mut released = self.manager.compact_global_pool(size_class);
+        for pool in &self.pools[1..] {
+            released += pool.compact(size_class);
+        }
+        self.counters.bytes_compacted.fetch_add(released as u64, Ordering::Relaxed);
+        true
+    }
+
+    /// Decay one size class of one per-CPU pool.
+    fn decay_chunk(&self, cpu: usize, class: usize) -> bool {
+        let Some(pool) = self.manager.get_cpu_pool(cpu) else {
+            return false;
+        };
+        let decayed = pool.decay(CPU_POOL_CLASSES[class], self.manager.stack_source(), self.config.decay_batch);
+        self.counters.stacks_decayed.fetch_add(decayed as u64, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Spawn the idle thread running `task`.
+///
+/// It runs at [`priority::IDLE`], so it only gets the CPU when no other
+/// thread is ready.
+pub fn spawn_idle_maintenance_thread<A: Arch, S: Scheduler>(
+    kernel: &Kernel<A, S>,
+    task: &'static IdleMaintenance,
+) -> Result<JoinHandle, SpawnError> {
+    kernel.spawn(
+        ThreadBuilder::new().name("idle").priority(priority::IDLE),
+        move || task.idle_loop(),
+    )
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::perf::memory_pools::PoolConfig;
+    use crate::perf::PerfConfig;
+    use alloc::boxed::Box;
+
+    #[test]
+    fn test_idle_pass_scrubs_compacts_and_decays() {
+        let manager = Box::leak(Box::new(MemoryPoolManager::new(PerfConfig {
+            cpu_count: 1,
+            ..PerfConfig::default()
+        })));
+        let pool: &'static StackPool = Box::leak(Box::new(StackPool::new()));
+        let task = IdleMaintenance::new(manager, IdleMaintenanceConfig::default()).with_stack_pool(pool);
+
+        // Two free stacks, one of which takes a bit flip while free
+        let first = pool.allocate(StackSizeClass::Small).unwrap();
+        let second = pool.allocate(StackSizeClass::Small).unwrap();
+        let alias = second.clone();
+        pool.deallocate(first);
+        pool.deallocate(second);
+        unsafe { *(alias.stack_top() as *mut u8).add(100) ^= 0x10 };
+        core::mem::forget(alias);
+
+        // A per-CPU pool four stacks over its target level
+        let target = PoolConfig::default().initial_pool_size;
+        let cpu_pool = manager.get_cpu_pool(0).unwrap();
+        for _ in 0..target + 4 {
+            let stack = manager.stack_source().allocate(StackSizeClass::Small).unwrap();
+            assert!(cpu_pool.small_stack_pool.deallocate(stack).is_ok());
+        }
+
+        let mut chunks = 0;
+        while task.run_chunk() {
+            chunks += 1;
+        }
+        let stats = task.stats();
+        assert_eq!(stats.passes, 1);
+        assert_eq!(stats.chunks, chunks);
+        assert_eq!(stats.stacks_scrubbed, 1);
+        assert_eq!(stats.scrub_faults, 1);
+        assert_eq!(stats.bytes_scrubbed, 2 * StackSizeClass::Small.size() as u64);
+        assert_eq!(stats.stacks_decayed, 1);
+
+        // The faulty stack is gone for good, the other is free again
+        assert_eq!(pool.free_count(StackSizeClass::Small), 1);
+        assert_eq!(cpu_pool.small_stack_pool.available(), target + 3);
+    }
+}
@@ -1,10 +1,18 @@
 //! Per-CPU memory pools for optimized allocation performance.
 
 use crate::perf::{PerfConfig, PERF_COUNTERS};
+use crate::perf::numa::{self, NumaNodeId};
 use crate::sched::CpuId;
-use crate::mem::{Stack, StackSizeClass};
+use crate::mem::{AtomicTaggedPtr, Stack, StackPool, StackSizeClass};
 use crate::arch::barriers::CacheLinePadded;
-use portable_atomic::{AtomicUsize, AtomicPtr, AtomicU64, Ordering};
+use crate::arch::Arch;
+use crate::kernel::{Kernel, SpawnError};
+use crate::sched::{Scheduler, priority};
+use crate::thread_new::{JoinHandle, ThreadBuilder};
+use crate::sync::{WaitQueue, WakeMode};
+use crate::testing::faults::{self, Fault};
+use crate::time::get_monotonic_time;
+use portable_atomic::{AtomicBool, AtomicUsize, AtomicPtr, AtomicU64, Ordering};
 use alloc::vec::Vec;
 use alloc::collections::VecDeque;
 
@@ -14,6 +22,9 @@ pub struct PerCpuMemoryPool {
     /// CPU ID this pool serves
     pub cpu_id: CpuId,
     
+    /// NUMA node of the CPU; refills allocate stacks from this node
+    pub numa_node: NumaNodeId,
+    
     /// Stack pools by size class (frequently accessed)
     pub small_stack_pool: LockFreePool<Stack>,
     pub medium_stack_pool: LockFreePool<Stack>,
@@ -29,10 +40,24 @@ pub struct PerCpuMemoryPool {
     /// Emergency fallback pool pointer (for cross-CPU allocation)
     pub fallback_pool: AtomicPtr<GlobalMemoryPool>,
     
+    /// Set when the pool dropped below the low watermark and is waiting
+    /// for the maintenance thread
+    pub refill_requested: AtomicBool,
+    
     /// Pool configuration
     pub config: PoolConfig,
 }
 
+/// Maintenance threads waiting for a pool to cross a watermark.
+static MAINTENANCE: WaitQueue = WaitQueue::with_mode(WakeMode::All);
+
+/// Wake the maintenance threads, if any is waiting.
+fn wake_maintenance() {
+    if !MAINTENANCE.is_empty() {
+        MAINTENANCE.notify();
+    }
+}
+
 /// Configuration for memory pools.
 #[derive(Debug, Clone, Copy)]
 pub struct PoolConfig {
@@ -61,10 +86,17 @@ impl Default for PoolConfig {
 }
 
 /// Lock-free memory pool implementation using Michael & Scott algorithm.
+///
+/// With the `checked-reclaim` feature, nodes are boxes that are not freed
+/// while the pool lives: a popped node goes to a graveyard instead, so a
+/// racing `allocate` that still reads it never touches freed or reused
+/// memory. Every node also carries a magic value checked when it is popped.
+/// This is meant for running the tests under Miri and sanitizers.
 #[repr(align(64))] // Cache line aligned
 pub struct LockFreePool<T> {
-    /// Head of the free list (ABA-safe pointer with generation counter)
-    head: AtomicPtr<PoolNode<T>>,
+    /// Head of the free list, tagged so a recycled node address cannot
+    /// satisfy a stale CAS
+    head: AtomicTaggedPtr<PoolNode<T>>,
     
     /// Pool size statistics
     available_count: AtomicUsize,
@@ -74,14 +106,58 @@ pub struct LockFreePool<T> {
     allocations: AtomicU64,
     deallocations: AtomicU64,
     contention_events: AtomicU64,
+    
+    /// Popped nodes, kept until the pool is dropped
+    #[cfg(feature = "checked-reclaim")]
+    graveyard: spin::Mutex<Vec<alloc::boxed::Box<PoolNode<T>>>>,
 }
 
 /// Pool node for lock-free linked list.
 #[repr(align(16))] // Prevent false sharing
 struct PoolNode<T> {
     next: AtomicPtr<PoolNode<T>>,
-    data: T,
-    generation: AtomicU64, // ABA prevention
+    /// Moved out when the node is popped
+    data: core::mem::ManuallyDrop<T>,
+    #[cfg(feature = "checked-reclaim")]
+    magic: u64,
+}
+
+/// [`PoolNode::magic`] of a node in a pool's free list.
+#[cfg(feature = "checked-reclaim")]
+const LIVE_NODE: u64 = 0x506F_6F6C_4E6F_6465;
+
+/// [`PoolNode::magic`] of a popped node.
+#[cfg(feature = "checked-reclaim")]
+const DEAD_NODE: u64 = 0xDEAD_DEAD_DEAD_DEAD;
+
+impl<T> PoolNode<T> {
+    fn new(data: T) -> Self {
+        Self {
+            next: AtomicPtr::new(core::ptr::null_mut()),
+            data: core::mem::ManuallyDrop::new(data),
+            #[cfg(feature = "checked-reclaim")]
+            magic: LIVE_NODE,
+        }
+    }
+    
+    /// Allocate a node holding `data`, handing `data` back if out of memory.
+    fn allocate(data: T) -> Result<*mut Self, T> {
+        #[cfg(feature = "checked-reclaim")]
+        {
+            Ok(alloc::boxed::Box::into_raw(alloc::boxed::Box::new(Self::new(data))))
+        }
+        
+        #[cfg(not(feature = "checked-reclaim"))]
+        {
+            let layout = core::alloc::Layout::new::<Self>();
+            let ptr = unsafe { alloc::alloc::alloc(layout) as *mut Self };
+            if ptr.is_null() {
+                return Err(data);
+            }
+            unsafe { core::ptr::write(ptr, Self::new(data)) };
+            Ok(ptr)
+        }
+    }
 }
 
 /// Pool statistics.
@@ -94,6 +170,38 @@ pub struct PoolStats {
     pub cross_cpu_allocations: AtomicU64,
     pub pool_refills: AtomicU64,
     pub memory_reclaimed: AtomicU64,
+    /// Batches of stacks pulled from the backing allocator during refills
+    pub refill_batches: AtomicU64,
+    /// Cumulative time spent refilling, in nanoseconds
+    pub refill_latency_total_ns: AtomicU64,
+    /// Slowest single refill, in nanoseconds
+    pub refill_latency_max_ns: AtomicU64,
+    /// Stacks handed back to the backing allocator by high-watermark trimming
+    pub stacks_trimmed: AtomicU64,
+    /// Stacks handed back to the backing allocator by idle-time decay
+    pub stacks_decayed: AtomicU64,
+}
+
+/// Outcome of a single maintenance pass over one or more pools.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MaintenanceReport {
+    /// Number of stacks added to pools
+    pub stacks_refilled: usize,
+    /// Number of stacks removed from pools
+    pub stacks_trimmed: usize,
+    /// Number of refill batches performed
+    pub refill_batches: usize,
+    /// Time spent refilling, in nanoseconds
+    pub refill_latency_ns: u64,
+}
+
+impl MaintenanceReport {
+    fn merge(&mut self, other: MaintenanceReport) {
+        self.stacks_refilled += other.stacks_refilled;
+        self.stacks_trimmed += other.stacks_trimmed;
+        self.refill_batches += other.refill_batches;
+        self.refill_latency_ns += other.refill_latency_ns;
+    }
 }
 
 /// Placeholder types for different object pools.
@@ -109,12 +217,14 @@ impl<T> LockFreePool<T> {
     /// Create a new lock-free pool.
     pub fn new() -> Self {
         Self {
-            head: AtomicPtr::new(core::ptr::null_mut()),
+            head: AtomicTaggedPtr::null(),
             available_count: AtomicUsize::new(0),
             total_allocated: AtomicUsize::new(0),
             allocations: AtomicU64::new(0),
             deallocations: AtomicU64::new(0),
             contention_events: AtomicU64::new(0),
+            #[cfg(feature = "checked-reclaim")]
+            graveyard: spin::Mutex::new(Vec::new()),
         }
     }
     
@@ -132,23 +242,36 @@ impl<T> LockFreePool<T> {
             
             // Safety: We loaded head with Acquire ordering, so if it's not null,
             // the node is valid until we successfully CAS it out
-            let node = unsafe { &*head };
+            let node = unsafe { &*head.ptr() };
             let next = node.next.load(Ordering::Relaxed);
             
-            // Try to update head to next
-            if self.head
-                .compare_exchange_weak(head, next, Ordering::Release, Ordering::Relaxed)
-                .is_ok()
+            // Try to update head to next; fails if the head was popped and
+            // pushed back since we loaded it, even at the same address
+            if !faults::inject(Fault::CasContention)
+                && self.head
+                    .compare_exchange_weak(head, head.advance(next), Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
             {
                 self.available_count.fetch_sub(1, Ordering::Relaxed);
                 
                 // Extract the data (we now own the node)
-                let data = unsafe { core::ptr::read(&node.data) };
+                let data = unsafe { core::ptr::read(&*node.data) };
                 
                 // Free the node memory
+                #[cfg(not(feature = "checked-reclaim"))]
                 unsafe {
                     let layout = core::alloc::Layout::new::<PoolNode<T>>();
-                    alloc::alloc::dealloc(head as *mut u8, layout);
+                    alloc::alloc::dealloc(head.ptr() as *mut u8, layout);
+                }
+                
+                #[cfg(feature = "checked-reclaim")]
+                {
+                    // Safety: the CAS made this thread the only owner of the
+                    // node, which came from `Box::into_raw`
+                    let mut node = unsafe { alloc::boxed::Box::from_raw(head.ptr()) };
+                    assert_eq!(node.magic, LIVE_NODE, "corrupted or double-popped pool node");
+                    node.magic = DEAD_NODE;
+                    self.graveyard.lock().push(node);
                 }
                 
                 PERF_COUNTERS.record_fast_path();
@@ -166,29 +289,15 @@ impl<T> LockFreePool<T> {
         self.deallocations.fetch_add(1, Ordering::Relaxed);
         
         // Allocate a new node
-        let layout = core::alloc::Layout::new::<PoolNode<T>>();
-        let node_ptr = unsafe { alloc::alloc::alloc(layout) as *mut PoolNode<T> };
-        
-        if node_ptr.is_null() {
-            return Err(item); // Allocation failed
-        }
-        
-        // Initialize the node
-        unsafe {
-            core::ptr::write(node_ptr, PoolNode {
-                next: AtomicPtr::new(core::ptr::null_mut()),
-                data: item,
-                generation: AtomicU64::new(0),
-            });
-        }
+        let node_ptr = PoolNode::allocate(item)?;
         
         // Insert at head of list
         loop {
             let head = self.head.load(Ordering::Relaxed);
-            unsafe { (*node_ptr).next.store(head, Ordering::Relaxed) };
+            unsafe { (*node_ptr).next.store(head.ptr(), Ordering::Relaxed) };
             
             if self.head
-                .compare_exchange_weak(head, node_ptr, Ordering::Release, Ordering::Relaxed)
+                .compare_exchange_weak(head, head.advance(node_ptr), Ordering::Release, Ordering::Relaxed)
                 .is_ok()
             {
                 self.available_count.fetch_add(1, Ordering::Relaxed);
@@ -222,11 +331,30 @@ impl<T> LockFreePool<T> {
     }
 }
 
+/// Checked pools free everything they hold, so leak checkers stay quiet.
+#[cfg(feature = "checked-reclaim")]
+impl<T> Drop for LockFreePool<T> {
+    fn drop(&mut self) {
+        while let Some(item) = self.allocate() {
+            drop(item);
+        }
+        // Graveyard nodes had their data moved out; `ManuallyDrop` keeps it
+        // from being dropped again
+        self.graveyard.get_mut().clear();
+    }
+}
+
 impl PerCpuMemoryPool {
     /// Create a new per-CPU memory pool.
     pub fn new(cpu_id: CpuId, config: PoolConfig) -> Self {
+        Self::new_on_node(cpu_id, 0, config)
+    }
+    
+    /// Create a new per-CPU memory pool whose stacks live on `numa_node`.
+    pub fn new_on_node(cpu_id: CpuId, numa_node: NumaNodeId, config: PoolConfig) -> Self {
         Self {
             cpu_id,
+            numa_node,
             small_stack_pool: LockFreePool::new(),
             medium_stack_pool: LockFreePool::new(),
             large_stack_pool: LockFreePool::new(),
@@ -234,22 +362,33 @@ impl PerCpuMemoryPool {
             sync_object_pool: LockFreePool::new(),
             stats: CacheLinePadded::new(PoolStats::default()),
             fallback_pool: AtomicPtr::new(core::ptr::null_mut()),
+            refill_requested: AtomicBool::new(false),
             config,
         }
     }
     
-    /// Allocate a stack from the appropriate pool.
-    pub fn allocate_stack(&self, size_class: StackSizeClass) -> Option<Stack> {
-        self.stats.get().total_allocations.fetch_add(1, Ordering::Relaxed);
-        
-        let pool = match size_class {
+    /// Get the stack pool serving the given size class.
+    fn stack_pool_for(&self, size_class: StackSizeClass) -> &LockFreePool<Stack> {
+        match size_class {
             StackSizeClass::Small => &self.small_stack_pool,
             StackSizeClass::Medium => &self.medium_stack_pool,
             StackSizeClass::Large => &self.large_stack_pool,
             StackSizeClass::ExtraLarge => &self.large_stack_pool, // Use large pool for extra large
-        };
+        }
+    }
+    
+    /// Allocate a stack from the appropriate pool.
+    pub fn allocate_stack(&self, size_class: StackSizeClass) -> Option<Stack> {
+        self.stats.get().total_allocations.fetch_add(1, Ordering::Relaxed);
+        
+        let pool = self.stack_pool_for(size_class);
+        let result = pool.allocate();
         
-        if let Some(stack) = pool.allocate() {
+        if pool.available() < self.config.low_watermark {
+            self.trigger_refill();
+        }
+        
+        if let Some(stack) = result {
             self.stats.get().cache_hits.fetch_add(1, Ordering::Relaxed);
             Some(stack)
         } else {
@@ -264,16 +403,13 @@ impl PerCpuMemoryPool {
     pub fn deallocate_stack(&self, stack: Stack, size_class: StackSizeClass) {
         self.stats.get().total_deallocations.fetch_add(1, Ordering::Relaxed);
         
-        let pool = match size_class {
-            StackSizeClass::Small => &self.small_stack_pool,
-            StackSizeClass::Medium => &self.medium_stack_pool,
-            StackSizeClass::Large => &self.large_stack_pool,
-            StackSizeClass::ExtraLarge => &self.large_stack_pool, // Use large pool for extra large
-        };
+        let pool = self.stack_pool_for(size_class);
         
-        if pool.deallocate(stack.clone()).is_err() {
+        if let Err(stack) = pool.deallocate(stack) {
             // Pool is full or allocation failed, try fallback
             self.deallocate_to_fallback(stack, size_class);
+        } else if pool.available() > self.config.high_watermark {
+            wake_maintenance();
         }
     }
     
@@ -306,10 +442,161 @@ impl PerCpuMemoryPool {
             || large_available < self.config.low_watermark
     }
     
+    /// Check if any stack pool has grown past the high watermark.
+    pub fn needs_trim(&self) -> bool {
+        self.small_stack_pool.available() > self.config.high_watermark
+            || self.medium_stack_pool.available() > self.config.high_watermark
+            || self.large_stack_pool.available() > self.config.high_watermark
+    }
+    
     /// Trigger background refill of pools.
+    ///
+    /// This only flags the pool and wakes the maintenance thread started
+    /// by [`spawn_maintenance_thread`], which performs the actual refill.
     pub fn trigger_refill(&self) {
-        self.stats.get().pool_refills.fetch_add(1, Ordering::Relaxed);
-        // In real implementation, would signal background thread to refill
+        self.refill_requested.store(true, Ordering::Release);
+        wake_maintenance();
+    }
+    
+    /// Level that refills and trims converge to.
+    ///
+    /// Keeping this strictly between the watermarks gives hysteresis: a pool
+    /// that was just refilled or trimmed won't immediately cross the opposite
+    /// watermark again.
+    fn target_level(&self) -> usize {
+        let low = self.config.low_watermark;
+        let high = self.config.high_watermark.min(self.config.max_pool_size).max(low);
+        self.config.initial_pool_size.clamp(low, high)
+    }
+    
+    /// Refill pools below the low watermark from `source` and trim pools
+    /// above the high watermark, releasing the trimmed stacks' memory.
+    pub fn maintain(&self, source: &StackPool) -> MaintenanceReport {
+        let mut report = MaintenanceReport::default();
+        
+        if self.refill_requested.swap(false, Ordering::AcqRel) || self.needs_refill() {
+            let start = get_monotonic_time();
+            
+            for size_class in [StackSizeClass::Small, StackSizeClass::Medium, StackSizeClass::Large] {
+                report.merge(self.refill_class(size_class, source));
+            }
+            
+            let elapsed = get_monotonic_time().duration_since(start).as_nanos();
+            report.refill_latency_ns = elapsed;
+            
+            if report.stacks_refilled > 0 {
+                let stats = self.stats.get();
+                stats.pool_refills.fetch_add(1, Ordering::Relaxed);
+                stats.refill_batches.fetch_add(report.refill_batches as u64, Ordering::Relaxed);
+                stats.refill_latency_total_ns.fetch_add(elapsed, Ordering::Relaxed);
+                stats.refill_latency_max_ns.fetch_max(elapsed, Ordering::Relaxed);
+            }
+        }
+        
+        if self.needs_trim() {
+            for size_class in [StackSizeClass::Small, StackSizeClass::Medium, StackSizeClass::Large] {
+                report.merge(self.trim_class(size_class, source));
+            }
+            
+            self.stats.get().stacks_trimmed.fetch_add(report.stacks_trimmed as u64, Ordering::Relaxed);
+        }
+        
+        report
+    }
+    
+    /// Refill one size class up to the target level in `batch_size` batches.
+    fn refill_class(&self, size_class: StackSizeClass, source: &StackPool) -> MaintenanceReport {
+        let mut report = MaintenanceReport::default();
+        let pool = self.stack_pool_for(size_class);
+        
+        if pool.available() >= self.config.low_watermark {
+            return report;
+        }
+        
+        let target = self.target_level();
+        let batch_size = self.config.batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+        
+        while pool.available() < target {
+            let wanted = (target - pool.available()).min(batch_size);
+            
+            // Pull the whole batch first so the backing allocator is hit in one burst
+            while batch.len() < wanted {
+                match source.allocate_on_node(size_class, self.numa_node) {
+                    Some(stack) => batch.push(stack),
+                    None => break,
+                }
+            }
+            
+            if batch.is_empty() {
+                break;
+            }
+            
+            report.refill_batches += 1;
+            let exhausted = batch.len() < wanted;
+            
+            for stack in batch.drain(..) {
+                match pool.deallocate(stack) {
+                    Ok(()) => report.stacks_refilled += 1,
+                    Err(stack) => source.deallocate(stack),
+                }
+            }
+            
+            if exhausted {
+                break;
+            }
+        }
+        
+        report
+    }
+    
+    /// Trim one size class down to the target level, freeing the stacks.
+    fn trim_class(&self, size_class: StackSizeClass, source: &StackPool) -> MaintenanceReport {
+        let mut report = MaintenanceReport::default();
+        let pool = self.stack_pool_for(size_class);
+        
+        if pool.available() <= self.config.high_watermark {
+            return report;
+        }
+        
+        let target = self.target_level();
+        while pool.available() > target {
+            match pool.allocate() {
+                Some(stack) => {
+                    source.release(stack);
+                    report.stacks_trimmed += 1;
+                }
+                None => break,
+            }
+        }
+        
+        report
+    }
+    
+    /// Free part of a size class's stacks above the target level through
+    /// `source`. Returns the number of stacks freed.
+    ///
+    /// A quarter of the excess goes per call, at least one stack and at
+    /// most `max`, so a pool that grew in a burst shrinks back over several
+    /// idle periods instead of all at once.
+    pub fn decay(&self, size_class: StackSizeClass, source: &StackPool, max: usize) -> usize {
+        let pool = self.stack_pool_for(size_class);
+        let excess = pool.available().saturating_sub(self.target_level());
+        let count = ((excess + 3) / 4).min(max);
+        
+        let mut decayed = 0;
+        while decayed < count {
+            match pool.allocate() {
+                Some(stack) => {
+                    source.release(stack);
+                    decayed += 1;
+                }
+                None => break,
+            }
+        }
+        
+        self.stats.get().stacks_decayed.fetch_add(decayed as u64, Ordering::Relaxed);
+        decayed
     }
     
     /// Get pool utilization statistics.
@@ -396,6 +683,9 @@ pub struct MemoryPoolManager {
     /// Global fallback pool
     global_pool: GlobalMemoryPool,
     
+    /// Backing allocator used to refill and trim per-CPU pools
+    stack_source: StackPool,
+    
     /// Pool configuration
     config: PerfConfig,
 }
@@ -403,11 +693,13 @@ pub struct MemoryPoolManager {
 impl MemoryPoolManager {
     pub fn new(config: PerfConfig) -> Self {
         let pool_config = PoolConfig::default();
+        let topology = numa::topology();
         let mut per_cpu_pools = Vec::with_capacity(config.cpu_count);
         
         for cpu_id in 0..config.cpu_count {
+            let cpu_id = cpu_id as CpuId;
             per_cpu_pools.push(CacheLinePadded::new(
-                PerCpuMemoryPool::new(cpu_id as CpuId, pool_config)
+                PerCpuMemoryPool::new_on_node(cpu_id, topology.get_cpu_node(cpu_id), pool_config)
             ));
         }
         
@@ -419,6 +711,7 @@ impl MemoryPoolManager {
                 large_stacks: spin::Mutex::new(VecDeque::new()),
                 stats: GlobalPoolStats::default(),
             },
+            stack_source: StackPool::new(),
             config,
         }
     }
@@ -472,12 +765,74 @@ impl MemoryPoolManager {
         queue.lock().push_back(stack);
     }
     
+    /// Get the allocator that per-CPU pools are refilled from and trimmed to.
+    pub fn stack_source(&self) -> &StackPool {
+        &self.stack_source
+    }
+    
+    /// Release the spare capacity of the global pool's free lists for a
+    /// size class, both the fallback queue and the backing allocator's list.
+    /// Returns the bytes released.
+    pub fn compact_global_pool(&self, size_class: StackSizeClass) -> usize {
+        let queue = match size_class {
+            StackSizeClass::Small => &self.global_pool.small_stacks,
+            StackSizeClass::Medium => &self.global_pool.medium_stacks,
+            StackSizeClass::Large => &self.global_pool.large_stacks,
+            StackSizeClass::ExtraLarge => return self.stack_source.compact(size_class),
+        };
+        
+        let mut released = self.stack_source.compact(size_class);
+        if let Some(mut queue) = queue.try_lock() {
+            let capacity = queue.capacity();
+            if capacity > 2 * queue.len() {
+                queue.shrink_to_fit();
+                released += (capacity - queue.capacity()) * core::mem::size_of::<Stack>();
+            }
+        }
+        released
+    }
+    
+    /// Check whether any per-CPU pool is waiting for maintenance.
+    pub fn maintenance_pending(&self) -> bool {
+        self.per_cpu_pools.iter().any(|pool| {
+            let pool = pool.get();
+            pool.refill_requested.load(Ordering::Acquire) || pool.needs_trim()
+        })
+    }
+    
+    /// Run one maintenance pass over every per-CPU pool.
+    pub fn run_maintenance(&self) -> MaintenanceReport {
+        let mut report = MaintenanceReport::default();
+        
+        for pool in &self.per_cpu_pools {
+            report.merge(pool.get().maintain(&self.stack_source));
+        }
+        
+        report
+    }
+    
+    /// Body of the background maintenance thread.
+    ///
+    /// Runs maintenance whenever a pool asks for it and sleeps until one
+    /// crosses a watermark otherwise.
+    pub fn maintenance_loop(&self) -> ! {
+        loop {
+            MAINTENANCE.wait_while(|| !self.maintenance_pending());
+            self.run_maintenance();
+        }
+    }
+    
     /// Get system-wide pool statistics.
     pub fn get_system_stats(&self) -> SystemMemoryStats {
         let mut total_allocations = 0;
         let mut total_deallocations = 0;
         let mut total_cache_hits = 0;
         let mut total_cache_misses = 0;
+        let mut pool_refills = 0;
+        let mut refill_latency_total_ns = 0;
+        let mut refill_latency_max_ns = 0;
+        let mut stacks_trimmed = 0;
+        let mut stacks_decayed = 0;
         
         for pool in &self.per_cpu_pools {
             let utilization = pool.get().get_utilization();
@@ -487,10 +842,20 @@ impl MemoryPoolManager {
             let stats = pool.get().stats.get();
             total_cache_hits += stats.cache_hits.load(Ordering::Relaxed);
             total_cache_misses += stats.cache_misses.load(Ordering::Relaxed);
+            pool_refills += stats.pool_refills.load(Ordering::Relaxed);
+            refill_latency_total_ns += stats.refill_latency_total_ns.load(Ordering::Relaxed);
+            refill_latency_max_ns = refill_latency_max_ns.max(stats.refill_latency_max_ns.load(Ordering::Relaxed));
+            stacks_trimmed += stats.stacks_trimmed.load(Ordering::Relaxed);
+            stacks_decayed += stats.stacks_decayed.load(Ordering::Relaxed);
         }
         
         let global_stats = &self.global_pool.stats;
         
+        #[cfg(feature = "mmu")]
+        let huge_pages = crate::mem::huge_page_stats();
+        #[cfg(not(feature = "mmu"))]
+        let huge_pages = HugePageCounts::default();
+        
         SystemMemoryStats {
             per_cpu_pools: self.per_cpu_pools.len(),
             total_allocations,
@@ -502,6 +867,13 @@ impl MemoryPoolManager {
             },
             cross_cpu_allocations: global_stats.cross_cpu_requests.load(Ordering::Relaxed),
             emergency_allocations: global_stats.emergency_allocations.load(Ordering::Relaxed),
+            pool_refills,
+            avg_refill_latency_ns: refill_latency_total_ns.checked_div(pool_refills).unwrap_or(0),
+            max_refill_latency_ns: refill_latency_max_ns,
+            stacks_trimmed,
+            stacks_decayed,
+            huge_page_hits: huge_pages.hits,
+            huge_page_misses: huge_pages.misses,
         }
     }
 }
@@ -515,6 +887,24 @@ pub struct SystemMemoryStats {
     pub cache_hit_ratio: f64,
     pub cross_cpu_allocations: u64,
     pub emergency_allocations: u64,
+    pub pool_refills: u64,
+    pub avg_refill_latency_ns: u64,
+    pub max_refill_latency_ns: u64,
+    pub stacks_trimmed: u64,
+    /// Stacks given back by idle-time decay
+    pub stacks_decayed: u64,
+    /// Stacks served from huge pages (always zero without `mmu`)
+    pub huge_page_hits: u64,
+    /// Huge page stack allocations that fell back to regular pages
+    pub huge_page_misses: u64,
+}
+
+/// Stand-in for huge page statistics when the `mmu` feature is disabled.
+#[cfg(not(feature = "mmu"))]
+#[derive(Default)]
+struct HugePageCounts {
+    hits: u64,
+    misses: u64,
 }
 
 /// Initialize per-CPU memory pools.
@@ -532,4 +922,82 @@ pub fn init_per_cpu_pools(config: &PerfConfig) -> MemoryPoolManager {
     // Per-CPU memory pools initialized with CPU count and initial pool size
     
     manager
-}
\ No newline at end of file
+}
+
+/// Spawn the low-priority thread that keeps per-CPU pools between their
+/// watermarks.
+pub fn spawn_maintenance_thread<A: Arch, S: Scheduler>(
+    kernel: &Kernel<A, S>,
+    manager: &'static MemoryPoolManager,
+) -> Result<JoinHandle, SpawnError> {
+    kernel.spawn(ThreadBuilder::new().priority(priority::LOW), move || manager.maintenance_loop())
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+
+    const CONFIG: PoolConfig = PoolConfig {
+        initial_pool_size: 4,
+        max_pool_size: 16,
+        batch_size: 3,
+        low_watermark: 2,
+        high_watermark: 6,
+    };
+
+    #[test]
+    fn test_refill_in_batches_to_target() {
+        let source = StackPool::new();
+        let pool = PerCpuMemoryPool::new(0, CONFIG);
+        assert!(pool.needs_refill());
+
+        let report = pool.maintain(&source);
+        // 4 stacks per class in batches of 3 and 1
+        assert_eq!(report.stacks_refilled, 12);
+        assert_eq!(report.refill_batches, 6);
+        assert_eq!(pool.get_utilization().small_pool_size, 4);
+        assert!(!pool.needs_refill());
+        assert_eq!(pool.stats.get().pool_refills.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_trim_frees_stacks() {
+        let source = StackPool::new();
+        let pool = PerCpuMemoryPool::new(0, CONFIG);
+        pool.maintain(&source);
+        for _ in 0..4 {
+            pool.deallocate_stack(source.allocate(StackSizeClass::Small).unwrap(), StackSizeClass::Small);
+        }
+        assert!(pool.needs_trim());
+        let in_use = source.stats().2;
+
+        let report = pool.maintain(&source);
+        assert_eq!(report.stacks_trimmed, 4);
+        assert_eq!(pool.get_utilization().small_pool_size, 4);
+        // Freed, not parked on the source's free list
+        assert_eq!(source.released_count(), 4);
+        assert_eq!(source.free_count(StackSizeClass::Small), 0);
+        assert_eq!(source.stats().2, in_use - 4);
+    }
+
+    #[test]
+    fn test_watermark_hysteresis() {
+        let source = StackPool::new();
+        let pool = PerCpuMemoryPool::new(0, CONFIG);
+        pool.maintain(&source);
+
+        // Draining to just above the low watermark does not refill
+        let stacks: Vec<_> = (0..2).map(|_| pool.allocate_stack(StackSizeClass::Small).unwrap()).collect();
+        assert_eq!(pool.maintain(&source).stacks_refilled, 0);
+
+        // Nor does growing back up to the high watermark trim
+        for stack in stacks {
+            pool.deallocate_stack(stack, StackSizeClass::Small);
+        }
+        for _ in 0..2 {
+            pool.deallocate_stack(source.allocate(StackSizeClass::Small).unwrap(), StackSizeClass::Small);
+        }
+        assert_eq!(pool.get_utilization().small_pool_size, 6);
+        assert_eq!(pool.maintain(&source).stacks_trimmed, 0);
+    }
+}
@@ -5,11 +5,15 @@
 //! and architecture-specific acceleration.
 
 pub mod cache_aware;
+pub mod contention;
 pub mod numa;
 pub mod cpu_dispatch;
 pub mod fast_paths;
 pub mod memory_pools;
+pub mod idle_maintenance;
 pub mod context_switch_opt;
+pub mod selftest;
+pub mod pmu;
 
 use portable_atomic::{AtomicU64, Ordering};
 use crate::arch::detection::{CpuFeatures, detect_cpu_features};
@@ -82,7 +86,8 @@ pub struct PerfCounters {
     pub fast_path_hits: AtomicU64,
     /// Number of slow path hits
     pub slow_path_hits: AtomicU64,
-    /// Number of cache line bounces detected
+    /// Number of cache line bounces detected by
+    /// [contention sampling](contention)
     pub cache_bounces: AtomicU64,
     /// Number of NUMA-optimized allocations
     pub numa_local_allocations: AtomicU64,
@@ -94,6 +99,10 @@ pub struct PerfCounters {
     pub simd_operations: AtomicU64,
     /// Lock-free operations completed
     pub lockfree_operations: AtomicU64,
+    /// Fast paths that found their target contended
+    pub contention_events: AtomicU64,
+    /// Threads stolen from a CPU outside the thief's LLC domain
+    pub cross_llc_steals: AtomicU64,
 }
 
 impl Default for PerfCounters {
@@ -107,6 +116,8 @@ impl Default for PerfCounters {
             optimized_context_switches: AtomicU64::new(0),
             simd_operations: AtomicU64::new(0),
             lockfree_operations: AtomicU64::new(0),
+            contention_events: AtomicU64::new(0),
+            cross_llc_steals: AtomicU64::new(0),
         }
     }
 }
@@ -160,6 +171,18 @@ impl PerfCounters {
         self.lockfree_operations.fetch_add(1, Ordering::Relaxed);
     }
     
+    /// Record a fast path that found its target contended.
+    #[inline(always)]
+    pub fn record_contention(&self) {
+        self.contention_events.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// Record a thread stolen across LLC domains.
+    #[inline(always)]
+    pub fn record_cross_llc_steal(&self) {
+        self.cross_llc_steals.fetch_add(1, Ordering::Relaxed);
+    }
+    
     /// Get fast path hit ratio.
     pub fn fast_path_ratio(&self) -> f64 {
         let fast = self.fast_path_hits.load(Ordering::Relaxed) as f64;
@@ -194,6 +217,8 @@ impl PerfCounters {
         self.optimized_context_switches.store(0, Ordering::Relaxed);
         self.simd_operations.store(0, Ordering::Relaxed);
         self.lockfree_operations.store(0, Ordering::Relaxed);
+        self.contention_events.store(0, Ordering::Relaxed);
+        self.cross_llc_steals.store(0, Ordering::Relaxed);
     }
 }
 
@@ -207,4 +232,6 @@ pub static PERF_COUNTERS: PerfCounters = PerfCounters {
     optimized_context_switches: AtomicU64::new(0),
     simd_operations: AtomicU64::new(0),
     lockfree_operations: AtomicU64::new(0),
+    contention_events: AtomicU64::new(0),
+    cross_llc_steals: AtomicU64::new(0),
 };
\ No newline at end of file
@@ -5,11 +5,103 @@ use crate::thread_new::Thread;
 use crate::sched::CpuId;
 use crate::arch::barriers::CacheLinePadded;
 use portable_atomic::{AtomicUsize, AtomicU64, Ordering};
-use alloc::{vec, vec::Vec};
+use core::ptr::NonNull;
+use alloc::{sync::Arc, vec, vec::Vec};
 
 /// NUMA node identifier.
 pub type NumaNodeId = u16;
 
+/// Explicitly configured topology (takes precedence over discovery).
+static CONFIGURED_TOPOLOGY: spin::Mutex<Option<NumaTopology>> = spin::Mutex::new(None);
+
+/// Topology returned by [`topology`], detected on first use.
+static TOPOLOGY: spin::Mutex<Option<Arc<NumaTopology>>> = spin::Mutex::new(None);
+
+/// Kernel-provided SRAT source used by topology discovery.
+static SRAT_PROVIDER: spin::Mutex<Option<SratProvider>> = spin::Mutex::new(None);
+
+/// Node-local memory allocator registered by the platform.
+static NODE_ALLOCATOR: spin::Mutex<Option<&'static dyn NodeAllocator>> = spin::Mutex::new(None);
+
+/// Round-robin cursor for interleaved placement.
+static INTERLEAVE_CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+/// Affinity entry from the ACPI System Resource Affinity Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SratEntry {
+    /// A processor belonging to a proximity domain
+    Processor {
+        /// Logical CPU the processor is brought up as
+        cpu_id: CpuId,
+        /// Proximity domain (NUMA node)
+        proximity_domain: u32,
+    },
+    /// A memory range belonging to a proximity domain
+    Memory {
+        /// Physical base address of the range
+        base: u64,
+        /// Length of the range in bytes
+        length: u64,
+        /// Proximity domain (NUMA node)
+        proximity_domain: u32,
+    },
+}
+
+/// Callback that returns the parsed SRAT entries of the running system.
+///
+/// Kernels register one via [`register_srat_provider`]; the crate does not
+/// parse ACPI tables itself.
+pub type SratProvider = fn() -> Vec<SratEntry>;
+
+/// Platform hook for allocating memory on a specific NUMA node.
+pub trait NodeAllocator: Send + Sync {
+    /// Allocate `size` bytes aligned to `align` on `node`.
+    fn allocate_on_node(&self, node: NumaNodeId, size: usize, align: usize) -> Option<NonNull<u8>>;
+    
+    /// Free memory returned by [`allocate_on_node`](Self::allocate_on_node).
+    ///
+    /// # Safety
+    ///
+    /// `memory` must come from `allocate_on_node` on this allocator with the
+    /// same `node`, `size` and `align`, and must not be used afterwards.
+    unsafe fn deallocate_on_node(&self, node: NumaNodeId, memory: NonNull<u8>, size: usize, align: usize);
+}
+
+/// Per-thread NUMA policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumaPolicy {
+    /// Allocate on the spawning CPU's node, without binding the thread to it
+    #[default]
+    Local,
+    /// Bind to a specific node
+    Node(NumaNodeId),
+    /// Spread threads round-robin across all nodes
+    Interleave,
+}
+
+impl NumaPolicy {
+    /// Resolve the policy to a concrete node.
+    ///
+    /// Returns `None` for a [`NumaPolicy::Node`] outside `topology`, which
+    /// leaves placement to the default allocator.
+    pub fn resolve(self, topology: &NumaTopology) -> Option<NumaNodeId> {
+        match self {
+            NumaPolicy::Local => Some(topology.get_cpu_node(crate::arch::percpu::cpu_id())),
+            NumaPolicy::Node(node) => {
+                if (node as usize) < topology.node_count {
+                    Some(node)
+                } else {
+                    None
+                }
+            }
+            NumaPolicy::Interleave => {
+                let cursor = INTERLEAVE_CURSOR.fetch_add(1, Ordering::Relaxed);
+                Some((cursor % topology.node_count.max(1)) as NumaNodeId)
+            }
+        }
+    }
+}
+
 /// NUMA topology information.
 #[derive(Debug, Clone)]
 pub struct NumaTopology {
@@ -37,11 +129,106 @@ impl NumaTopology {
         }
     }
     
-    /// Detect system NUMA topology (simplified detection).
+    /// Build a topology from an explicit CPU-to-node map.
+    ///
+    /// Intended for embedded targets whose layout is known at build time.
+    /// Nodes are numbered `0..=max(cpu_to_node)`; distances default to 10
+    /// locally and 20 remotely.
+    pub fn from_cpu_map(cpu_to_node: &[NumaNodeId]) -> Self {
+        let node_count = cpu_to_node.iter().map(|&node| node as usize + 1).max().unwrap_or(1);
+        let mut node_to_cpus = vec![Vec::new(); node_count];
+        
+        for (cpu_id, &node) in cpu_to_node.iter().enumerate() {
+            node_to_cpus[node as usize].push(cpu_id as CpuId);
+        }
+        
+        let distance_matrix = (0..node_count)
+            .map(|from| (0..node_count).map(|to| if from == to { 10 } else { 20 }).collect())
+            .collect();
+        
+        Self {
+            node_count,
+            cpu_to_node: cpu_to_node.to_vec(),
+            node_to_cpus,
+            distance_matrix,
+            node_memory_capacity: vec![u64::MAX; node_count],
+        }
+    }
+    
+    /// Build a topology from ACPI SRAT entries.
+    ///
+    /// Proximity domains are renumbered densely in order of first
+    /// appearance. CPUs not mentioned in the table are placed on node 0.
+    pub fn from_srat(entries: &[SratEntry], cpu_count: usize) -> Self {
+        let mut domains: Vec<u32> = Vec::new();
+        let mut node_of = |domain: u32| -> NumaNodeId {
+            match domains.iter().position(|&d| d == domain) {
+                Some(index) => index as NumaNodeId,
+                None => {
+                    domains.push(domain);
+                    (domains.len() - 1) as NumaNodeId
+                }
+            }
+        };
+        
+        let mut cpu_to_node = vec![0; cpu_count];
+        let mut memory: Vec<(NumaNodeId, u64)> = Vec::new();
+        
+        for entry in entries {
+            match *entry {
+                SratEntry::Processor { cpu_id, proximity_domain } => {
+                    let node = node_of(proximity_domain);
+                    if cpu_id < cpu_count {
+                        cpu_to_node[cpu_id] = node;
+                    }
+                }
+                SratEntry::Memory { length, proximity_domain, .. } => {
+                    memory.push((node_of(proximity_domain), length));
+                }
+            }
+        }
+        
+        let mut topology = Self::from_cpu_map(&cpu_to_node);
+        
+        // Memory-only domains still count as nodes
+        let node_count = topology.node_count.max(domains.len());
+        if node_count > topology.node_count {
+            topology.node_to_cpus.resize(node_count, Vec::new());
+            topology.distance_matrix = (0..node_count)
+                .map(|from| (0..node_count).map(|to| if from == to { 10 } else { 20 }).collect())
+                .collect();
+            topology.node_count = node_count;
+        }
+        
+        if !memory.is_empty() {
+            topology.node_memory_capacity = vec![0; node_count];
+            for (node, length) in memory {
+                topology.node_memory_capacity[node as usize] += length;
+            }
+        }
+        
+        topology
+    }
+    
+    /// Detect system NUMA topology.
+    ///
+    /// Uses, in order: a topology set with [`configure_topology`], the
+    /// registered SRAT provider, or a single node covering all CPUs.
     pub fn detect() -> Self {
-        // In a real implementation, this would query the OS for NUMA information
-        // For now, assume single node
-        Self::single_node(4) // Default to 4 CPUs
+        if let Some(topology) = CONFIGURED_TOPOLOGY.lock().clone() {
+            return topology;
+        }
+        
+        let cpu_count = crate::perf::get_perf_config().cpu_count.max(1);
+        
+        if let Some(provider) = *SRAT_PROVIDER.lock() {
+            let entries = provider();
+            if !entries.is_empty() {
+                return Self::from_srat(&entries, cpu_count);
+            }
+        }
+        
+        Self::single_node(cpu_count)
     }
     
     /// Get NUMA node for a given CPU.
@@ -436,6 +623,66 @@ pub struct NumaEfficiencyMetrics {
     pub memory_efficiency: f64,
 }
 
+/// Set the NUMA topology explicitly, bypassing discovery.
+pub fn configure_topology(topology: NumaTopology) {
+    *CONFIGURED_TOPOLOGY.lock() = Some(topology);
+    *TOPOLOGY.lock() = None;
+}
+
+/// Register the SRAT callback used by topology discovery.
+pub fn register_srat_provider(provider: SratProvider) {
+    *SRAT_PROVIDER.lock() = Some(provider);
+    *TOPOLOGY.lock() = None;
+}
+
+/// Register the platform's node-local memory allocator.
+///
+/// Node-local stacks are freed through the allocator registered when they
+/// are dropped, so this should be done once, before any thread is spawned.
+pub fn set_node_allocator(allocator: &'static dyn NodeAllocator) {
+    *NODE_ALLOCATOR.lock() = Some(allocator);
+}
+
+/// Get the registered node-local memory allocator, if any.
+pub fn node_allocator() -> Option<&'static dyn NodeAllocator> {
+    *NODE_ALLOCATOR.lock()
+}
+
+/// Exclusive use of the node allocator in a test, which all host test
+/// threads share. The previously registered allocator is put back when the
+/// guard drops.
+#[cfg(test)]
+pub(crate) struct NodeAllocatorGuard {
+    saved: Option<&'static dyn NodeAllocator>,
+    _lock: spin::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+static NODE_ALLOCATOR_TEST_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+
+/// Register `allocator` for the duration of a test.
+#[cfg(test)]
+pub(crate) fn hold_node_allocator(allocator: &'static dyn NodeAllocator) -> NodeAllocatorGuard {
+    let lock = NODE_ALLOCATOR_TEST_LOCK.lock();
+    let saved = NODE_ALLOCATOR.lock().replace(allocator);
+    NodeAllocatorGuard { saved, _lock: lock }
+}
+
+#[cfg(test)]
+impl Drop for NodeAllocatorGuard {
+    fn drop(&mut self) {
+        *NODE_ALLOCATOR.lock() = self.saved.take();
+    }
+}
+
+/// Get the current system NUMA topology.
+///
+/// It is detected with [`NumaTopology::detect`] on first use and again only
+/// after [`configure_topology`] or [`register_srat_provider`].
+pub fn topology() -> Arc<NumaTopology> {
+    TOPOLOGY.lock().get_or_insert_with(|| Arc::new(NumaTopology::detect())).clone()
+}
+
 /// Initialize NUMA optimization subsystem.
 pub fn init_numa_optimization(config: &PerfConfig) {
     let topology = if config.numa_nodes > 1 {
@@ -455,4 +702,25 @@ mod num_cpus {
         // Simplified CPU detection - in reality would query OS
         4
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology_cached() {
+        assert!(Arc::ptr_eq(&topology(), &topology()));
+    }
+
+    #[test]
+    fn test_local_policy_resolves_to_current_node() {
+        let cpu = crate::arch::percpu::cpu_id();
+        let mut cpu_to_node = vec![0; cpu + 2];
+        cpu_to_node[cpu] = 1;
+        let topology = NumaTopology::from_cpu_map(&cpu_to_node);
+
+        assert_eq!(NumaPolicy::Local.resolve(&topology), Some(1));
+        assert_eq!(NumaPolicy::Node(1).resolve(&topology), Some(1));
+        assert_eq!(NumaPolicy::Node(2).resolve(&topology), None);
+    }
+}
@@ -0,0 +1,391 @@
+//! Hardware performance counter sampling.
+//!
+//! The PMU counts cycles, retired instructions and cache misses as the CPU
+//! runs. [`enable`] programs these counters on a CPU, and from then on every
+//! context switch reads them and charges what was counted since the last
+//! switch on that CPU to the outgoing thread's
+//! [`ThreadMetrics`](crate::observability::metrics::ThreadMetrics), which
+//! derives its [IPC](crate::observability::metrics::ThreadMetrics::ipc) and
+//! [cache miss rate](crate::observability::metrics::ThreadMetrics::cache_miss_rate):
+//!
+//! ```ignore
+//! // On every CPU during bring-up
+//! if !unsafe { perf::pmu::enable() } {
+//!     log::warn!("no PMU, threads will not have IPC or cache miss rates");
+//! }
+//! ```
+//!
+//! What counts as a cache miss depends on the backend:
+//!
+//! | Backend | Cycles           | Instructions      | Cache misses               |
+//! |---------|------------------|-------------------|----------------------------|
+//! | x86_64  | fixed counter 1  | fixed counter 0   | PMC0, LLC misses           |
+//! | AArch64 | `PMCCNTR_EL0`    | `INST_RETIRED`    | `L1D_CACHE_REFILL`         |
+//! | RISC-V  | `cycle`          | `instret`         | not counted                |
+//!
+//! x86_64 needs architectural performance monitoring version 2 or later.
+//! On RISC-V the counters are free running and need no programming, but
+//! S-mode needs the firmware to set `mcounteren.CY` and `mcounteren.IR`.
+//! Counters are compared at their narrowest width, so a thread running
+//! longer than that wraps in one slice (about a second at 32 bits) is
+//! undercounted.
+
+use crate::arch::percpu::{self, MAX_CPUS};
+use crate::observability::metrics::GLOBAL_METRICS;
+use crate::thread_new::ThreadId;
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Counts read from the PMU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PmuSample {
+    pub cycles: u64,
+    pub instructions: u64,
+    /// Zero if the backend does not count them
+    pub cache_misses: u64,
+}
+
+impl PmuSample {
+    /// Get the counts from `earlier` to this sample, for counters `mask`
+    /// wide that may have wrapped in between.
+    pub fn since(&self, earlier: &PmuSample, mask: u64) -> PmuSample {
+        PmuSample {
+            cycles: self.cycles.wrapping_sub(earlier.cycles) & mask,
+            instructions: self.instructions.wrapping_sub(earlier.instructions) & mask,
+            cache_misses: self.cache_misses.wrapping_sub(earlier.cache_misses) & mask,
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static CACHE_MISSES: AtomicBool = AtomicBool::new(false);
+
+/// Mask of the narrowest counter in use.
+static COUNTER_MASK: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Counts a CPU read at its last switch.
+struct LastReading {
+    cycles: AtomicU64,
+    instructions: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_READING: LastReading = LastReading {
+    cycles: AtomicU64::new(0),
+    instructions: AtomicU64::new(0),
+    cache_misses: AtomicU64::new(0),
+};
+
+static LAST: [LastReading; MAX_CPUS] = [NO_READING; MAX_CPUS];
+
+impl LastReading {
+    /// Replace the reading with `now`, returning the counts in between.
+    fn advance(&self, now: PmuSample) -> PmuSample {
+        // Only this CPU touches its reading
+        let last = PmuSample {
+            cycles: self.cycles.swap(now.cycles, Ordering::Relaxed),
+            instructions: self.instructions.swap(now.instructions, Ordering::Relaxed),
+            cache_misses: self.cache_misses.swap(now.cache_misses, Ordering::Relaxed),
+        };
+        now.since(&last, COUNTER_MASK.load(Ordering::Relaxed))
+    }
+}
+
+/// Program the counters of this CPU and start sampling them at context
+/// switches. Returns `false` if the CPU has no usable PMU.
+///
+/// # Safety
+///
+/// Must be called at the kernel's privilege level, on every CPU threads
+/// run on, and not while the counters are in use for something else.
+pub unsafe fn enable() -> bool {
+    // Safety: per the caller
+    let Some(counters) = (unsafe { backend::enable() }) else {
+        return false;
+    };
+    CACHE_MISSES.store(counters.cache_misses, Ordering::Relaxed);
+    COUNTER_MASK.store(counters.mask, Ordering::Relaxed);
+    LAST[percpu::cpu_id()].advance(backend::read());
+    ENABLED.store(true, Ordering::Release);
+    true
+}
+
+/// Stop sampling the counters at context switches.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+/// Check whether context switches sample the counters.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Check whether the backend counts cache misses.
+pub fn counts_cache_misses() -> bool {
+    CACHE_MISSES.load(Ordering::Relaxed)
+}
+
+/// Read this CPU's counters, if [enabled](enable).
+pub fn read() -> Option<PmuSample> {
+    is_enabled().then(backend::read)
+}
+
+/// Charge the counts since the last switch on this CPU to `prev`, which is
+/// being switched out.
+pub(crate) fn switch_out(prev: ThreadId) {
+    if !is_enabled() {
+        return;
+    }
+    let counted = LAST[percpu::cpu_id()].advance(backend::read());
+    GLOBAL_METRICS.record_pmu_sample(prev, &counted);
+}
+
+/// What a backend's counters cover once programmed.
+#[allow(dead_code)] // Never built without a hardware backend
+struct Counters {
+    cache_misses: bool,
+    mask: u64,
+}
+
+#[cfg(all(feature = "x86_64", target_arch = "x86_64"))]
+mod backend {
+    use super::{Counters, PmuSample};
+    use core::arch::asm;
+    use core::arch::x86_64::__cpuid_count;
+
+    const IA32_PERFEVTSEL0: u32 = 0x186;
+    const IA32_FIXED_CTR_CTRL: u32 = 0x38d;
+    const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+
+    /// `LONGEST_LAT_CACHE.MISS` counted in rings 0 and 3, enabled.
+    const LLC_MISSES: u64 = 0x2e | 0x41 << 8 | 1 << 16 | 1 << 17 | 1 << 22;
+    /// Fixed counters 0 and 1 counting in rings 0 and 3.
+    const FIXED_CTR_0_1: u64 = 0b11 | 0b11 << 4;
+    /// PMC0 and fixed counters 0 and 1.
+    const GLOBAL_CTR_0_1: u64 = 1 | 1 << 32 | 1 << 33;
+
+    /// Set in `rdpmc` indices of fixed counters.
+    const FIXED: u32 = 1 << 30;
+
+    pub(super) unsafe fn enable() -> Option<Counters> {
+        // Safety: CPUID is always available on x86_64
+        let leaf = unsafe {
+            if __cpuid_count(0, 0).eax < 0xa {
+                return None;
+            }
+            __cpuid_count(0xa, 0)
+        };
+        let version = leaf.eax & 0xff;
+        let general = (leaf.eax >> 8) & 0xff;
+        let fixed = leaf.edx & 0x1f;
+        if version < 2 || fixed < 2 {
+            return None;
+        }
+        let general_width = (leaf.eax >> 16) & 0xff;
+        let fixed_width = (leaf.edx >> 5) & 0xff;
+        // EBX lists unavailable events, bit 4 is LLC misses
+        let cache_misses = general > 0 && leaf.ebx & 1 << 4 == 0;
+
+        // Safety: the caller runs in ring 0 and owns the counters
+        unsafe {
+            if cache_misses {
+                wrmsr(IA32_PERFEVTSEL0, LLC_MISSES);
+            }
+            wrmsr(IA32_FIXED_CTR_CTRL, FIXED_CTR_0_1);
+            wrmsr(IA32_PERF_GLOBAL_CTRL, GLOBAL_CTR_0_1);
+        }
+        let width = if cache_misses { general_width.min(fixed_width) } else { fixed_width };
+        Some(Counters {
+            cache_misses,
+            mask: u64::MAX >> (64 - width.clamp(1, 64)),
+        })
+    }
+
+    pub(super) fn read() -> PmuSample {
+        // Safety: only called once `enable` programmed the counters
+        unsafe {
+            PmuSample {
+                cycles: rdpmc(FIXED | 1),
+                instructions: rdpmc(FIXED),
+                cache_misses: if super::counts_cache_misses() { rdpmc(0) } else { 0 },
+            }
+        }
+    }
+
+    unsafe fn rdpmc(counter: u32) -> u64 {
+        let (low, high): (u32, u32);
+        unsafe {
+            asm!("rdpmc", in("ecx") counter, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+        }
+        (high as u64) << 32 | low as u64
+    }
+
+    unsafe fn wrmsr(msr: u32, value: u64) {
+        unsafe {
+            asm!(
+                "wrmsr",
+                in("ecx") msr,
+                in("eax") value as u32,
+                in("edx") (value >> 32) as u32,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+}
+
+#[cfg(all(feature = "arm64", target_arch = "aarch64"))]
+mod backend {
+    use super::{Counters, PmuSample};
+    use core::arch::asm;
+
+    /// `INST_RETIRED` common event.
+    const INST_RETIRED: u64 = 0x08;
+    /// `L1D_CACHE_REFILL` common event.
+    const L1D_CACHE_REFILL: u64 = 0x03;
+
+    /// PMCR_EL0 enable and 64-bit cycle counter.
+    const PMCR_E: u64 = 1;
+    const PMCR_LC: u64 = 1 << 6;
+
+    pub(super) unsafe fn enable() -> Option<Counters> {
+        let dfr0: u64;
+        let pmcr: u64;
+        // Safety: the caller runs at EL1
+        unsafe {
+            asm!("mrs {}, id_aa64dfr0_el1", out(reg) dfr0, options(nomem, nostack, preserves_flags));
+            // PMUVer, bits 11:8: 0 is none, 0xf an implementation defined PMU
+            if matches!((dfr0 >> 8) & 0xf, 0 | 0xf) {
+                return None;
+            }
+            asm!("mrs {}, pmcr_el0", out(reg) pmcr, options(nomem, nostack, preserves_flags));
+        }
+        // PMCR_EL0.N, bits 15:11, event counters implemented
+        if (pmcr >> 11) & 0x1f < 2 {
+            return None;
+        }
+
+        // Safety: the caller runs at EL1 and owns the counters
+        unsafe {
+            asm!(
+                "msr pmevtyper0_el0, {instructions}",
+                "msr pmevtyper1_el0, {misses}",
+                "msr pmccfiltr_el0, xzr",
+                "msr pmcntenset_el0, {counters}",
+                "msr pmcr_el0, {pmcr}",
+                "isb",
+                instructions = in(reg) INST_RETIRED,
+                misses = in(reg) L1D_CACHE_REFILL,
+                counters = in(reg) 1u64 << 31 | 0b11,
+                pmcr = in(reg) pmcr | PMCR_E | PMCR_LC,
+                options(nostack, preserves_flags)
+            );
+        }
+        // Event counters are 32 bits wide before PMUv3.5
+        Some(Counters {
+            cache_misses: true,
+            mask: u32::MAX as u64,
+        })
+    }
+
+    pub(super) fn read() -> PmuSample {
+        let (cycles, instructions, cache_misses): (u64, u64, u64);
+        // Safety: only called once `enable` programmed the counters
+        unsafe {
+            asm!(
+                "mrs {cycles}, pmccntr_el0",
+                "mrs {instructions}, pmevcntr0_el0",
+                "mrs {misses}, pmevcntr1_el0",
+                cycles = out(reg) cycles,
+                instructions = out(reg) instructions,
+                misses = out(reg) cache_misses,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        PmuSample {
+            cycles,
+            instructions,
+            cache_misses,
+        }
+    }
+}
+
+#[cfg(all(feature = "riscv64", target_arch = "riscv64"))]
+mod backend {
+    use super::{Counters, PmuSample};
+    use core::arch::asm;
+
+    /// The counters run freely; cache miss events are platform specific.
+    pub(super) unsafe fn enable() -> Option<Counters> {
+        Some(Counters {
+            cache_misses: false,
+            mask: u64::MAX,
+        })
+    }
+
+    pub(super) fn read() -> PmuSample {
+        let (cycles, instructions): (u64, u64);
+        // Safety: readable in M-mode, and in S-mode once enabled by firmware
+        unsafe {
+            asm!(
+                "rdcycle {cycles}",
+                "rdinstret {instructions}",
+                cycles = out(reg) cycles,
+                instructions = out(reg) instructions,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        PmuSample {
+            cycles,
+            instructions,
+            cache_misses: 0,
+        }
+    }
+}
+
+/// No PMU without a hardware architecture.
+#[cfg(not(any(
+    all(feature = "x86_64", target_arch = "x86_64"),
+    all(feature = "arm64", target_arch = "aarch64"),
+    all(feature = "riscv64", target_arch = "riscv64"),
+)))]
+mod backend {
+    use super::{Counters, PmuSample};
+
+    pub(super) unsafe fn enable() -> Option<Counters> {
+        None
+    }
+
+    pub(super) fn read() -> PmuSample {
+        PmuSample::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::metrics::ThreadMetrics;
+
+    #[test]
+    fn test_samples_attribute_ipc_and_miss_rate() {
+        let earlier = PmuSample {
+            cycles: 0xffff_fff0,
+            instructions: 100,
+            cache_misses: 7,
+        };
+        let now = PmuSample {
+            cycles: 0x0000_0f0f,
+            instructions: 8_100,
+            cache_misses: 15,
+        };
+        let counted = now.since(&earlier, u32::MAX as u64);
+        assert_eq!(counted.cycles, 0xf1f);
+
+        let mut metrics = ThreadMetrics::new(ThreadId::new(49_725));
+        assert_eq!(metrics.ipc(), 0.0);
+        metrics.add_pmu_sample(&PmuSample { cycles: 4_000, ..counted });
+        metrics.add_pmu_sample(&PmuSample { cycles: 4_000, ..counted });
+        assert_eq!(metrics.ipc(), 2.0);
+        assert_eq!(metrics.cache_miss_rate(), 1.0);
+    }
+}
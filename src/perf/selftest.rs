@@ -0,0 +1,319 @@
+//! Boot-time latency self-test.
+//!
+//! [`run`] measures the scheduler's critical latencies on the live hardware
+//! and compares them against configurable [`SelftestBudgets`]. Every budget
+//! that is exceeded is reported to [`HEALTH_MONITOR`] as a critical issue,
+//! which catches misconfigured clocks, a missing timer interrupt or a debug
+//! build shipped to the field before the system takes on work.
+//!
+//! Three paths are measured:
+//!
+//! - **Switch latency**: the register save and restore of a context switch,
+//!   by switching the CPU to its own saved context.
+//! - **Timer jitter**: how late a one-tick timeout fires on the timer wheel.
+//! - **Lock handoff**: releasing a scheduler lock and acquiring it again.
+//!
+//! Each is run in several rounds and the best round is kept, so an
+//! interrupt landing in the middle of a round doesn't fail the test.
+//!
+//! ```ignore
+//! selftest::set_budgets(SelftestBudgets {
+//!     switch_latency: Duration::from_micros(1),
+//!     ..SelftestBudgets::default()
+//! });
+//! let report = selftest::run();
+//! if !report.passed() {
+//!     // Already reported to the health monitor
+//! }
+//! ```
+
+use crate::arch::{Arch, DefaultArch};
+use crate::observability::health::{HealthIssue, IssueCategory, IssueSeverity, HEALTH_MONITOR};
+use crate::sync::SpinLockIrqSave;
+use crate::time::{cpu_clock, Duration, Instant, TIMER_FREQUENCY_HZ, TIMER_WHEEL};
+extern crate alloc;
+use alloc::{collections::BTreeMap, format, string::ToString, vec::Vec};
+use core::mem::MaybeUninit;
+
+/// Rounds per measurement; the best one counts.
+const ROUNDS: u32 = 5;
+
+/// Iterations per round of the switch and lock measurements.
+const ITERATIONS: u32 = 1000;
+
+/// Length of one timer tick (nanoseconds).
+const TICK_NS: u64 = 1_000_000_000 / TIMER_FREQUENCY_HZ as u64;
+
+/// Largest acceptable value of each measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelftestBudgets {
+    /// Per context switch
+    pub switch_latency: Duration,
+    /// Lateness of a timer expiry
+    pub timer_jitter: Duration,
+    /// Per lock release and re-acquire
+    pub lock_handoff: Duration,
+}
+
+impl Default for SelftestBudgets {
+    fn default() -> Self {
+        Self {
+            switch_latency: Duration::from_micros(2),
+            timer_jitter: Duration::from_nanos(TICK_NS / 2),
+            lock_handoff: Duration::from_micros(1),
+        }
+    }
+}
+
+/// A measured latency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelftestCheck {
+    /// Context switch save and restore
+    SwitchLatency,
+    /// Timer expiry lateness
+    TimerJitter,
+    /// Lock release and re-acquire
+    LockHandoff,
+}
+
+impl SelftestCheck {
+    /// Get the check's name.
+    pub fn name(self) -> &'static str {
+        match self {
+            SelftestCheck::SwitchLatency => "switch_latency",
+            SelftestCheck::TimerJitter => "timer_jitter",
+            SelftestCheck::LockHandoff => "lock_handoff",
+        }
+    }
+}
+
+/// A measurement that exceeded its budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetViolation {
+    pub check: SelftestCheck,
+    /// Measured latency (nanoseconds)
+    pub measured_ns: u64,
+    /// Budget it was held to (nanoseconds)
+    pub budget_ns: u64,
+}
+
+/// Results of a self-test run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelftestReport {
+    /// Whether the CPU clock advanced during the test
+    pub clock_running: bool,
+    /// Best mean context switch latency (nanoseconds)
+    pub switch_latency_ns: u64,
+    /// Best timer expiry lateness (nanoseconds)
+    pub timer_jitter_ns: u64,
+    /// Best mean lock handoff latency (nanoseconds)
+    pub lock_handoff_ns: u64,
+    /// Budgets the measurements were compared against
+    pub budgets: SelftestBudgets,
+    /// Measurements over budget
+    pub violations: Vec<BudgetViolation>,
+}
+
+impl SelftestReport {
+    fn new(
+        clock_running: bool,
+        switch_latency_ns: u64,
+        timer_jitter_ns: u64,
+        lock_handoff_ns: u64,
+        budgets: SelftestBudgets,
+    ) -> Self {
+        let violations = [
+            (SelftestCheck::SwitchLatency, switch_latency_ns, budgets.switch_latency),
+            (SelftestCheck::TimerJitter, timer_jitter_ns, budgets.timer_jitter),
+            (SelftestCheck::LockHandoff, lock_handoff_ns, budgets.lock_handoff),
+        ]
+        .into_iter()
+        .filter(|&(_, measured, budget)| measured > budget.as_nanos())
+        .map(|(check, measured_ns, budget)| BudgetViolation {
+            check,
+            measured_ns,
+            budget_ns: budget.as_nanos(),
+        })
+        .collect();
+
+        Self {
+            clock_running,
+            switch_latency_ns,
+            timer_jitter_ns,
+            lock_handoff_ns,
+            budgets,
+            violations,
+        }
+    }
+
+    /// Check whether the clock ran and every measurement was within budget.
+    pub fn passed(&self) -> bool {
+        self.clock_running && self.violations.is_empty()
+    }
+
+    /// Health issues describing the failures.
+    pub fn issues(&self, now: Instant) -> Vec<HealthIssue> {
+        let mut issues = Vec::new();
+
+        if !self.clock_running {
+            issues.push(HealthIssue {
+                severity: IssueSeverity::Critical,
+                category: IssueCategory::Hardware,
+                description: "CPU clock did not advance during the latency self-test".to_string(),
+                component: "selftest".to_string(),
+                detected_at: now,
+                context: BTreeMap::new(),
+                affected_threads: Vec::new(),
+                remediation: Some("Check that a clock source is registered and calibrated".to_string()),
+            });
+        }
+
+        for violation in &self.violations {
+            let mut context = BTreeMap::new();
+            context.insert("measured_ns".to_string(), violation.measured_ns.to_string());
+            context.insert("budget_ns".to_string(), violation.budget_ns.to_string());
+
+            issues.push(HealthIssue {
+                severity: IssueSeverity::Critical,
+                category: IssueCategory::Performance,
+                description: format!(
+                    "{} of {}ns exceeds its {}ns budget",
+                    violation.check.name(),
+                    violation.measured_ns,
+                    violation.budget_ns
+                ),
+                component: "selftest".to_string(),
+                detected_at: now,
+                context,
+                affected_threads: Vec::new(),
+                remediation: Some(
+                    match violation.check {
+                        SelftestCheck::TimerJitter => "Check the timer interrupt configuration and frequency",
+                        _ => "Check for a debug build or CPU frequency scaling",
+                    }
+                    .to_string(),
+                ),
+            });
+        }
+        issues
+    }
+}
+
+/// Budgets set with [`set_budgets`], if any.
+static BUDGETS: spin::Mutex<Option<SelftestBudgets>> = spin::Mutex::new(None);
+
+/// Set the budgets [`run`] compares against.
+pub fn set_budgets(budgets: SelftestBudgets) {
+    *BUDGETS.lock() = Some(budgets);
+}
+
+/// Get the budgets [`run`] compares against.
+pub fn budgets() -> SelftestBudgets {
+    BUDGETS.lock().unwrap_or_default()
+}
+
+/// Measure the scheduler latencies and report budget violations to the
+/// health monitor.
+///
+/// Needs the timer interrupt running: the timer measurement waits for the
+/// timer wheel, giving up after ten ticks. Takes a few milliseconds.
+pub fn run() -> SelftestReport {
+    let budgets = budgets();
+    // Nothing can be timed, and the timer wait would never give up
+    let report = if clock_running() {
+        SelftestReport::new(
+            true,
+            best_of(measure_switch),
+            best_of(measure_timer),
+            best_of(measure_lock),
+            budgets,
+        )
+    } else {
+        SelftestReport::new(false, 0, 0, 0, budgets)
+    };
+
+    for issue in report.issues(Instant::now()) {
+        HEALTH_MONITOR.report_issue(issue);
+    }
+    report
+}
+
+fn best_of(measure: fn() -> u64) -> u64 {
+    (0..ROUNDS).map(|_| measure()).min().unwrap_or(0)
+}
+
+fn clock_running() -> bool {
+    let start = cpu_clock();
+    for _ in 0..ITERATIONS {
+        crate::sync::relax();
+    }
+    cpu_clock() > start
+}
+
+/// Mean time of one context switch.
+fn measure_switch() -> u64 {
+    let mut context = MaybeUninit::<<DefaultArch as Arch>::SavedContext>::zeroed();
+    let context = context.as_mut_ptr();
+
+    let start = cpu_clock();
+    for _ in 0..ITERATIONS {
+        // Safety: the switch saves the CPU state into `context` and then
+        // restores that same state, resuming right after the switch
+        unsafe { DefaultArch::context_switch(context, context) };
+    }
+    cpu_clock().duration_since(start).as_nanos() / ITERATIONS as u64
+}
+
+/// How late a one-tick timeout fires.
+fn measure_timer() -> u64 {
+    let start = cpu_clock();
+    let timeout = TIMER_WHEEL.arm(Duration::from_nanos(TICK_NS));
+    let give_up = start.add(Duration::from_nanos(10 * TICK_NS));
+    while !timeout.expired() && cpu_clock() < give_up {
+        crate::sync::relax();
+    }
+    cpu_clock().duration_since(start).as_nanos().saturating_sub(TICK_NS)
+}
+
+/// Mean time of one lock release and re-acquire.
+fn measure_lock() -> u64 {
+    let lock = SpinLockIrqSave::new(0u64);
+
+    let start = cpu_clock();
+    for _ in 0..ITERATIONS {
+        *lock.lock() += 1;
+    }
+    let elapsed = cpu_clock().duration_since(start).as_nanos();
+    core::hint::black_box(*lock.lock());
+    elapsed / ITERATIONS as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_budget_violations() {
+        let budgets = SelftestBudgets::default();
+        let report = SelftestReport::new(true, 500, 10_000, 50, budgets);
+        assert!(report.passed());
+        assert!(report.issues(Instant::from_nanos(0)).is_empty());
+
+        // A debug build with a stuck timer
+        let report = SelftestReport::new(true, 5_000, 9_000_000, 50, budgets);
+        assert!(!report.passed());
+        let checks: Vec<_> = report.violations.iter().map(|violation| violation.check).collect();
+        assert_eq!(checks, [SelftestCheck::SwitchLatency, SelftestCheck::TimerJitter]);
+        assert_eq!(report.violations[0].budget_ns, 2_000);
+
+        let issues = report.issues(Instant::from_nanos(0));
+        assert_eq!(issues.len(), 2);
+        assert!(issues.iter().all(|issue| issue.severity == IssueSeverity::Critical));
+        assert_eq!(issues[1].context["measured_ns"], "9000000");
+
+        // A clock that never advances fails regardless of the numbers
+        let report = SelftestReport::new(false, 0, 0, 0, budgets);
+        assert!(!report.passed());
+        assert_eq!(report.issues(Instant::from_nanos(0))[0].category, IssueCategory::Hardware);
+    }
+}
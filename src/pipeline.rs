@@ -0,0 +1,498 @@
+//! Multi-stage processing pipelines.
+//!
+//! A pipeline is a chain of stages, each running on its own thread and
+//! connected to the next by a bounded [`spsc`](crate::sync::spsc) ring.
+//! Items pushed into the [`Input`] flow through every stage and come out of
+//! the [`Output`]. A full queue blocks the stage feeding it, so a slow
+//! stage throttles everything upstream instead of letting queues grow.
+//!
+//! ```ignore
+//! let (mut input, mut output, pipeline) = pipeline::Builder::<Frame>::new(8)
+//!     .stage(StageConfig::new("decode").priority(200).cpu_affinity(0b01), decode)
+//!     .stage(StageConfig::new("filter").queue_depth(32), filter)
+//!     .spawn(&kernel)?;
+//!
+//! input.send(frame)?;
+//! let samples = output.recv();
+//! ```
+//!
+//! Shutdown is coordinated through the queues. Closing the input lets every
+//! stage finish the items already queued and exit in turn, after which the
+//! output reports the end of the stream. Dropping the output instead stops
+//! the stages from the last one back. [`Pipeline::abort`] stops all stages
+//! at once, discarding queued items.
+
+use crate::arch::Arch;
+use crate::errors::SpawnError;
+use crate::kernel::Kernel;
+use crate::sched::{CpuSet, Scheduler};
+use crate::sync::spsc::{Consumer, Producer, RingBuffer};
+use crate::thread_new::ThreadBuilder;
+use crate::time::{Duration, TIMER_WHEEL};
+extern crate alloc;
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use portable_atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Queue depth used when a stage doesn't set one.
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+
+/// Work a stage thread runs until its input closes.
+pub type StageBody = Box<dyn FnOnce() + Send>;
+
+/// Thread and queue settings of one stage.
+#[derive(Debug, Clone)]
+pub struct StageConfig {
+    name: String,
+    priority: u8,
+    cpu_affinity: Option<CpuSet>,
+    queue_depth: usize,
+}
+
+impl StageConfig {
+    /// Settings for a stage named `name`, at normal priority, on any CPU.
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            priority: 128,
+            cpu_affinity: None,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+        }
+    }
+
+    /// Set the priority of the stage's thread.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Restrict the stage's thread to the CPUs in `cpus`.
+    pub fn cpu_affinity(mut self, cpus: impl Into<CpuSet>) -> Self {
+        self.cpu_affinity = Some(cpus.into());
+        self
+    }
+
+    /// Set how many of its results the stage can queue ahead of the next
+    /// stage (rounded up to a power of two).
+    pub fn queue_depth(mut self, depth: usize) -> Self {
+        self.queue_depth = depth;
+        self
+    }
+
+    /// Get the stage name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the thread priority.
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// Get the CPUs the stage's thread may run on, if restricted.
+    pub fn get_cpu_affinity(&self) -> Option<CpuSet> {
+        self.cpu_affinity
+    }
+
+    /// Get the output queue depth.
+    pub fn get_queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+}
+
+/// Something that can start stage threads.
+///
+/// Implemented by [`Kernel`]; tests and hosted targets may provide their
+/// own.
+pub trait StageSpawner {
+    /// Run `body` on a new thread configured by `config`.
+    fn spawn_stage(&self, config: &StageConfig, body: StageBody) -> Result<(), SpawnError>;
+}
+
+impl<A: Arch, S: Scheduler> StageSpawner for Kernel<A, S> {
+    fn spawn_stage(&self, config: &StageConfig, body: StageBody) -> Result<(), SpawnError> {
+        let mut builder = ThreadBuilder::new()
+            .name(config.name.clone())
+            .priority(config.priority);
+        if let Some(cpus) = config.cpu_affinity {
+            builder = builder.cpu_affinity(cpus);
+        }
+        self.spawn(builder, body).map(drop)
+    }
+}
+
+/// A queue between two parts of a pipeline, with its item type erased.
+trait Link: Send + Sync {
+    fn close(&self);
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+}
+
+impl<T: Send> Link for RingBuffer<T> {
+    fn close(&self) {
+        RingBuffer::close(self)
+    }
+
+    fn len(&self) -> usize {
+        RingBuffer::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        RingBuffer::capacity(self)
+    }
+}
+
+/// Bookkeeping of one stage.
+struct StageState {
+    name: String,
+    /// Queue the stage reads from
+    input: Arc<dyn Link>,
+    processed: AtomicU64,
+    finished: AtomicBool,
+}
+
+/// State shared by the stages and the [`Pipeline`] handle.
+struct Control {
+    aborted: AtomicBool,
+    /// Stages whose thread hasn't finished yet
+    running: AtomicUsize,
+}
+
+/// Marks a stage finished when its thread leaves the stage loop.
+struct StageExit {
+    control: Arc<Control>,
+    stage: Arc<StageState>,
+}
+
+impl Drop for StageExit {
+    fn drop(&mut self) {
+        self.stage.finished.store(true, Ordering::Release);
+        self.control.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A stage waiting to be spawned.
+struct PendingStage {
+    config: StageConfig,
+    state: Arc<StageState>,
+    body: StageBody,
+}
+
+/// Builder for a pipeline taking `In` items and producing `Out` items.
+pub struct Builder<In, Out = In> {
+    input: Producer<In>,
+    tail: Consumer<Out>,
+    stages: Vec<PendingStage>,
+    control: Arc<Control>,
+}
+
+impl<T: Send + 'static> Builder<T> {
+    /// Start a pipeline whose input queue holds `input_depth` items.
+    pub fn new(input_depth: usize) -> Self {
+        let (input, tail) = RingBuffer::new(input_depth);
+        Self {
+            input,
+            tail,
+            stages: Vec::new(),
+            control: Arc::new(Control {
+                aborted: AtomicBool::new(false),
+                running: AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+impl<In: Send + 'static, Out: Send + 'static> Builder<In, Out> {
+    /// Append a stage applying `process` to each item.
+    pub fn stage<U, F>(self, config: StageConfig, mut process: F) -> Builder<In, U>
+    where
+        U: Send + 'static,
+        F: FnMut(Out) -> U + Send + 'static,
+    {
+        let Self { input, tail: mut rx, mut stages, control } = self;
+        let (mut tx, tail) = RingBuffer::new(config.queue_depth);
+        let state = Arc::new(StageState {
+            name: config.name.clone(),
+            input: rx.shared(),
+            processed: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        });
+
+        let exit = StageExit { control: control.clone(), stage: state.clone() };
+        let body: StageBody = Box::new(move || {
+            let exit = exit;
+            while let Some(item) = rx.pop_blocking() {
+                if exit.control.aborted.load(Ordering::Acquire) {
+                    break;
+                }
+                let result = process(item);
+                exit.stage.processed.fetch_add(1, Ordering::Relaxed);
+                if tx.push_blocking(result).is_err() {
+                    break;
+                }
+            }
+            // Dropping both ends passes the shutdown on to the neighbours
+        });
+
+        stages.push(PendingStage { config, state, body });
+        Builder { input, tail, stages, control }
+    }
+
+    /// Start a thread for every stage.
+    ///
+    /// Returns the ends to feed and drain the pipeline, and a handle to
+    /// watch and stop it. If a stage fails to start, the ones already
+    /// started are shut down and the error is returned.
+    pub fn spawn(self, spawner: &impl StageSpawner) -> Result<(Input<In>, Output<Out>, Pipeline), SpawnError> {
+        let Self { input, tail, stages, control } = self;
+        let pipeline = Pipeline {
+            control,
+            stages: stages.iter().map(|stage| stage.state.clone()).collect(),
+        };
+
+        for stage in stages {
+            pipeline.control.running.fetch_add(1, Ordering::AcqRel);
+            let PendingStage { config, state, body } = stage;
+            if let Err(error) = spawner.spawn_stage(&config, body) {
+                // The body was dropped unrun, so account for it here
+                state.finished.store(true, Ordering::Release);
+                pipeline.control.running.fetch_sub(1, Ordering::AcqRel);
+                pipeline.abort();
+                return Err(error);
+            }
+        }
+
+        Ok((
+            Input { queue: input, control: pipeline.control.clone() },
+            Output { queue: tail },
+            pipeline,
+        ))
+    }
+}
+
+/// Feeding end of a pipeline.
+pub struct Input<T> {
+    queue: Producer<T>,
+    control: Arc<Control>,
+}
+
+impl<T> Input<T> {
+    /// Queue `item`, waiting while the first stage is behind.
+    ///
+    /// Hands the item back if the pipeline stopped.
+    pub fn send(&mut self, item: T) -> Result<(), T> {
+        if self.control.aborted.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        self.queue.push_blocking(item)
+    }
+
+    /// Queue `item` if there is room, handing it back otherwise.
+    pub fn try_send(&mut self, item: T) -> Result<(), T> {
+        if self.queue.is_closed() || self.control.aborted.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        self.queue.push(item)
+    }
+
+    /// Check whether the pipeline stopped accepting items.
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+
+    /// Stop feeding the pipeline; queued items are still processed.
+    pub fn close(self) {}
+}
+
+/// Draining end of a pipeline.
+pub struct Output<T> {
+    queue: Consumer<T>,
+}
+
+impl<T> Output<T> {
+    /// Take the next result, waiting for one.
+    ///
+    /// Returns `None` once the pipeline has shut down and all results were
+    /// taken.
+    pub fn recv(&mut self) -> Option<T> {
+        self.queue.pop_blocking()
+    }
+
+    /// Take the next result, if one is ready.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Check whether the last stage exited.
+    ///
+    /// Results it queued before can still be taken.
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+/// Progress of one stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageStats {
+    /// Stage name
+    pub name: String,
+    /// Items the stage has processed
+    pub processed: u64,
+    /// Items waiting in the stage's input queue
+    pub queued: usize,
+    /// Capacity of the stage's input queue
+    pub queue_capacity: usize,
+    /// Whether the stage's thread has exited
+    pub finished: bool,
+}
+
+/// Handle to watch and stop a running pipeline.
+pub struct Pipeline {
+    control: Arc<Control>,
+    stages: Vec<Arc<StageState>>,
+}
+
+impl Pipeline {
+    /// Get the progress of every stage, in pipeline order.
+    pub fn stats(&self) -> Vec<StageStats> {
+        self.stages
+            .iter()
+            .map(|stage| StageStats {
+                name: stage.name.clone(),
+                processed: stage.processed.load(Ordering::Relaxed),
+                queued: stage.input.len(),
+                queue_capacity: stage.input.capacity(),
+                finished: stage.finished.load(Ordering::Acquire),
+            })
+            .collect()
+    }
+
+    /// Check whether every stage has exited.
+    pub fn is_finished(&self) -> bool {
+        self.control.running.load(Ordering::Acquire) == 0
+    }
+
+    /// Stop all stages, discarding the items still queued.
+    ///
+    /// Blocked sends and receives on the pipeline's ends return.
+    pub fn abort(&self) {
+        self.control.aborted.store(true, Ordering::Release);
+        for stage in &self.stages {
+            stage.input.close();
+        }
+    }
+
+    /// Check whether the pipeline was aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.control.aborted.load(Ordering::Acquire)
+    }
+
+    /// Wait for every stage to exit.
+    ///
+    /// Stages exit once their input is closed and drained, so close the
+    /// [`Input`] (or [`abort`](Self::abort)) first.
+    pub fn join(&self) {
+        crate::might_sleep!();
+
+        while !self.is_finished() {
+            crate::sync::relax();
+        }
+    }
+
+    /// Wait for every stage to exit, giving up after `timeout`.
+    ///
+    /// Returns whether the stages exited in time.
+    pub fn join_timeout(&self, timeout: Duration) -> bool {
+        crate::might_sleep!();
+
+        let deadline = TIMER_WHEEL.arm(timeout);
+        while !self.is_finished() {
+            if deadline.expired() {
+                return false;
+            }
+            crate::sync::relax();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    /// Runs stages on host threads.
+    struct HostSpawner;
+
+    impl StageSpawner for HostSpawner {
+        fn spawn_stage(&self, _config: &StageConfig, body: StageBody) -> Result<(), SpawnError> {
+            std::thread::spawn(body);
+            Ok(())
+        }
+    }
+
+    /// Starts the given number of stages, then fails.
+    struct FailingSpawner(AtomicUsize);
+
+    impl StageSpawner for FailingSpawner {
+        fn spawn_stage(&self, _config: &StageConfig, body: StageBody) -> Result<(), SpawnError> {
+            if self.0.fetch_sub(1, Ordering::Relaxed) == 0 {
+                return Err(SpawnError::TooManyThreads);
+            }
+            std::thread::spawn(body);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline_streams_and_shuts_down() {
+        let (mut input, mut output, pipeline) = Builder::<u32>::new(4)
+            .stage(StageConfig::new("double").queue_depth(2), |x| x * 2)
+            .stage(StageConfig::new("format").priority(200), |x: u32| x.to_string())
+            .spawn(&HostSpawner)
+            .unwrap();
+
+        let feeder = std::thread::spawn(move || {
+            for x in 0..200 {
+                input.send(x).unwrap();
+            }
+            input.close();
+        });
+
+        let mut expected = 0;
+        while let Some(result) = output.recv() {
+            assert_eq!(result, (expected * 2).to_string());
+            expected += 1;
+        }
+        assert_eq!(expected, 200);
+        feeder.join().unwrap();
+
+        pipeline.join();
+        let stats = pipeline.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "double");
+        assert_eq!(stats[1].queue_capacity, 2);
+        assert!(stats.iter().all(|stage| stage.processed == 200 && stage.finished));
+    }
+
+    #[test]
+    fn test_pipeline_abort_and_failed_spawn() {
+        let (mut input, mut output, pipeline) = Builder::<u32>::new(4)
+            .stage(StageConfig::new("pass"), |x| x)
+            .spawn(&HostSpawner)
+            .unwrap();
+        input.send(1).unwrap();
+        assert_eq!(output.recv(), Some(1));
+
+        pipeline.abort();
+        assert!(pipeline.join_timeout(Duration::from_millis(5000)));
+        assert_eq!(input.send(2), Err(2));
+        assert_eq!(output.recv(), None);
+
+        // The started stage is stopped when a later one fails to start
+        let result = Builder::<u32>::new(4)
+            .stage(StageConfig::new("first"), |x| x)
+            .stage(StageConfig::new("second"), |x| x)
+            .spawn(&FailingSpawner(AtomicUsize::new(1)));
+        assert!(matches!(result, Err(SpawnError::TooManyThreads)));
+    }
+}
@@ -27,6 +27,12 @@ pub fn clear_preemption_pending() {
     PREEMPTION_PENDING.store(false, Ordering::Release);
 }
 
+/// Leave a preemption pending for the next checkpoint, when it could not
+/// be carried out right away
+pub(crate) fn set_preemption_pending() {
+    PREEMPTION_PENDING.store(true, Ordering::Release);
+}
+
 /// Get total preemption count for statistics
 pub fn get_preemption_count() -> u64 {
     PREEMPTION_COUNT.load(Ordering::Relaxed)
@@ -107,12 +113,18 @@ pub fn stop_preemption_timer() {
 /// Preemption checkpoint - should be called regularly from normal code
 /// This is where actual scheduling decisions are made, outside signal context
 pub fn preemption_checkpoint() {
-    if is_preemption_pending() {
+    // Park here if another thread has suspended us
+    crate::thread_new::suspend_point();
+    
+    // Left pending while preemption is disabled or deferred
+    if crate::time::preempt_count() > 0 || crate::time::in_interrupt() {
+        return;
+    }
+    if is_preemption_pending() && !crate::kernel::preemption_deferred() {
         clear_preemption_pending();
         
         // Safe to do complex operations here - we're not in signal context
-        // Yield to scheduler - the scheduler is a global static
-        crate::sync::yield_thread();
+        crate::sched::global::yield_current();
     }
 }
 
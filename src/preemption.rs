@@ -1,3 +1,5 @@
+pub mod irq;
+
 #[cfg(target_os = "linux")]
 pub struct Preemption {
     enabled: bool,
@@ -50,7 +52,7 @@ impl Preemption {
             return;
         }
 
-        let result = signal(SIGALRM, timer_handler);
+        let result = unsafe { signal(SIGALRM, timer_handler) };
         if result == -1 {
             return;
         }
@@ -66,7 +68,7 @@ impl Preemption {
             },
         };
 
-        let result = setitimer(ITIMER_REAL, &timer, core::ptr::null_mut());
+        let result = unsafe { setitimer(ITIMER_REAL, &timer, core::ptr::null_mut()) };
         if result == 0 {
             self.enabled = true;
         }
@@ -110,7 +112,7 @@ impl Preemption {
             },
         };
 
-        setitimer(ITIMER_REAL, &timer, core::ptr::null_mut());
+        unsafe { setitimer(ITIMER_REAL, &timer, core::ptr::null_mut()) };
         self.enabled = false;
     }
 }
@@ -125,18 +127,7 @@ extern "C" fn timer_handler(_sig: i32) {
         return;
     }
 
-    unsafe {
-        let scheduler = crate::scheduler::SCHEDULER.get();
-
-        if let Some(current_id) = scheduler.get_current_thread() {
-            if let Some(next_id) = scheduler.schedule() {
-                if current_id != next_id {
-                    scheduler.set_current_thread(Some(next_id));
-                    let _ = scheduler.switch_context(current_id, next_id);
-                }
-            }
-        }
-    }
+    crate::sched::global::timer_tick();
 
     IN_HANDLER.store(false, Ordering::SeqCst);
 }
@@ -0,0 +1,325 @@
+//! Threaded interrupt handlers.
+//!
+//! A threaded IRQ splits interrupt handling in two. The platform's hard
+//! interrupt stub calls [`handle_irq`], which only records that the line
+//! fired and returns, so time spent with interrupts disabled is bounded and
+//! independent of the handler. The handler itself runs in a dedicated
+//! high-priority kernel thread, where it can be preempted and may use
+//! sleeping locks.
+//!
+//! ```ignore
+//! fn uart_rx(_irq: u32) {
+//!     let mut buffer = RX_BUFFER.lock(); // Sleeping lock: fine here
+//!     buffer.drain_fifo();
+//! }
+//!
+//! request_threaded_irq(&kernel, UART_IRQ, uart_rx, priority::HIGH)?;
+//!
+//! // In the platform's interrupt vector:
+//! handle_irq(UART_IRQ);
+//! ```
+//!
+//! Each line also has a CPU affinity. It is applied to the line's
+//! thread, and platform code can read it with [`ThreadedIrqs::affinity`]
+//! to route the interrupt itself to the same CPUs.
+
+use crate::arch::Arch;
+use crate::errors::{InvalidOperationError, ResourceError, ThreadError, ThreadResult};
+use crate::kernel::Kernel;
+use crate::sched::{AtomicCpuSet, CpuSet, Scheduler};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{registry, JoinHandle, ThreadBuilder, ThreadId};
+extern crate alloc;
+use alloc::format;
+use portable_atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Number of interrupt lines that can be threaded.
+pub const MAX_IRQS: usize = 64;
+
+/// Handler run in thread context for each occurrence of an interrupt.
+pub type IrqHandler = fn(irq: u32);
+
+/// Counters for one interrupt line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IrqStats {
+    /// Times the hard stub recorded the interrupt
+    pub raised: u64,
+    /// Times the handler has run
+    pub handled: u64,
+    /// Occurrences recorded but not yet handled
+    pub pending: u32,
+}
+
+struct IrqLine {
+    /// ID of the line's handler thread (0 = not registered)
+    thread: AtomicU64,
+    /// Occurrences not yet handled
+    pending: AtomicU32,
+    raised: AtomicU64,
+    handled: AtomicU64,
+    /// CPUs the line is routed to (all = any CPU)
+    affinity: AtomicCpuSet,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNREGISTERED: IrqLine = IrqLine {
+    thread: AtomicU64::new(0),
+    pending: AtomicU32::new(0),
+    raised: AtomicU64::new(0),
+    handled: AtomicU64::new(0),
+    affinity: AtomicCpuSet::new(CpuSet::all()),
+};
+
+/// Table of threaded interrupt lines.
+pub struct ThreadedIrqs {
+    lines: [IrqLine; MAX_IRQS],
+    /// Handlers, only touched from thread context and registration
+    handlers: SpinLockIrqSave<[Option<IrqHandler>; MAX_IRQS]>,
+}
+
+impl ThreadedIrqs {
+    /// Create a table with no lines registered.
+    pub const fn new() -> Self {
+        Self {
+            lines: [UNREGISTERED; MAX_IRQS],
+            handlers: SpinLockIrqSave::new([None; MAX_IRQS]),
+        }
+    }
+
+    /// Register `handler` for `irq` and spawn its handler thread at
+    /// `priority` on `kernel`.
+    ///
+    /// Fails if `irq` is out of range or already has a handler.
+    pub fn register<A: Arch, S: Scheduler>(
+        &'static self,
+        kernel: &Kernel<A, S>,
+        irq: u32,
+        handler: IrqHandler,
+        priority: u8,
+    ) -> ThreadResult<JoinHandle> {
+        let line = self.line(irq)?;
+        self.install(irq, handler)?;
+
+        let handle = match kernel.spawn(ThreadBuilder::new().priority(priority), move || self.serve(irq)) {
+            Ok(handle) => handle,
+            Err(error) => {
+                self.handlers.lock()[irq as usize] = None;
+                return Err(error.into());
+            }
+        };
+
+        let thread = handle.thread_id();
+        line.thread.store(thread.as_u64(), Ordering::Release);
+        self.apply_affinity(line);
+        Ok(handle)
+    }
+
+    /// Install `handler` for `irq`, allowing it as an indirect call target
+    /// for CFI.
+    fn install(&self, irq: u32, handler: IrqHandler) -> ThreadResult<()> {
+        let mut handlers = self.handlers.lock();
+        let slot = &mut handlers[irq as usize];
+        if slot.is_some() {
+            return Err(ThreadError::Resource(ResourceError::ResourceUnavailable));
+        }
+        *slot = Some(handler);
+        drop(handlers);
+        crate::security::cfi::allow_target(handler as *const ());
+        Ok(())
+    }
+
+    /// Remove the handler for `irq`.
+    ///
+    /// Occurrences still pending are discarded. The handler thread keeps
+    /// waiting but is never woken again.
+    pub fn unregister(&self, irq: u32) -> ThreadResult<()> {
+        let line = self.line(irq)?;
+        line.thread.store(0, Ordering::Release);
+        line.pending.store(0, Ordering::Release);
+        self.handlers.lock()[irq as usize] = None;
+        Ok(())
+    }
+
+    /// Record an occurrence of `irq` from the hard interrupt stub.
+    ///
+    /// Constant time and lock-free. Returns `false` if the line has no
+    /// threaded handler, in which case the platform should handle or mask
+    /// the interrupt itself.
+    pub fn handle_irq(&self, irq: u32) -> bool {
+        let Some(line) = self.lines.get(irq as usize) else {
+            return false;
+        };
+        if line.thread.load(Ordering::Acquire) == 0 {
+            return false;
+        }
+
+        line.raised.fetch_add(1, Ordering::Relaxed);
+        line.pending.fetch_add(1, Ordering::Release);
+        true
+    }
+
+    /// Run the handler once for each pending occurrence of `irq`.
+    ///
+    /// Returns the number of occurrences handled. Called by the handler
+    /// thread; must not be called from interrupt context.
+    pub fn run_pending(&self, irq: u32) -> usize {
+        crate::might_sleep!();
+
+        let Ok(line) = self.line(irq) else {
+            return 0;
+        };
+        let Some(handler) = self.handlers.lock()[irq as usize] else {
+            return 0;
+        };
+
+        let count = line.pending.swap(0, Ordering::Acquire);
+        if count > 0 {
+            crate::security::cfi::verify_forward_edge(handler as *const ());
+        }
+        for _ in 0..count {
+            handler(irq);
+        }
+        line.handled.fetch_add(count as u64, Ordering::Relaxed);
+        count as usize
+    }
+
+    /// Body of the handler thread: wait for occurrences of `irq` and run
+    /// the handler for each, until the line is unregistered.
+    pub fn serve(&self, irq: u32) {
+        let Ok(line) = self.line(irq) else {
+            return;
+        };
+
+        while line.thread.load(Ordering::Acquire) != 0 {
+            if line.pending.load(Ordering::Acquire) == 0 {
+                crate::sync::relax();
+                continue;
+            }
+            self.run_pending(irq);
+        }
+    }
+
+    /// Route `irq` to the CPUs in `cpus`.
+    ///
+    /// The line's handler thread is restricted to the same CPUs.
+    pub fn set_affinity(&self, irq: u32, cpus: impl Into<CpuSet>) -> ThreadResult<()> {
+        let cpus = cpus.into();
+        if cpus.is_empty() {
+            return Err(ThreadError::InvalidOperation(InvalidOperationError::InvalidParameter(
+                format!("empty affinity for irq {}", irq),
+            )));
+        }
+
+        let line = self.line(irq)?;
+        line.affinity.store(cpus);
+        self.apply_affinity(line);
+        Ok(())
+    }
+
+    /// Get the CPUs `irq` should be routed to.
+    pub fn affinity(&self, irq: u32) -> Option<CpuSet> {
+        self.lines.get(irq as usize).map(|line| line.affinity.load())
+    }
+
+    /// Get the handler thread of `irq`, if it is registered.
+    pub fn thread(&self, irq: u32) -> Option<ThreadId> {
+        let id = self.lines.get(irq as usize)?.thread.load(Ordering::Acquire);
+        (id != 0).then(|| ThreadId::new(id))
+    }
+
+    /// Get the counters of `irq`.
+    pub fn stats(&self, irq: u32) -> Option<IrqStats> {
+        self.lines.get(irq as usize).map(|line| IrqStats {
+            raised: line.raised.load(Ordering::Relaxed),
+            handled: line.handled.load(Ordering::Relaxed),
+            pending: line.pending.load(Ordering::Acquire),
+        })
+    }
+
+    fn line(&self, irq: u32) -> ThreadResult<&IrqLine> {
+        self.lines.get(irq as usize).ok_or_else(|| {
+            ThreadError::InvalidOperation(InvalidOperationError::InvalidParameter(format!(
+                "irq {} out of range",
+                irq
+            )))
+        })
+    }
+
+    fn apply_affinity(&self, line: &IrqLine) {
+        let id = line.thread.load(Ordering::Acquire);
+        if id == 0 {
+            return;
+        }
+        if let Some(thread) = registry::lookup(ThreadId::new(id)) {
+            thread.set_cpu_affinity(line.affinity.load());
+        }
+    }
+}
+
+impl Default for ThreadedIrqs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global table of threaded interrupt lines.
+pub static THREADED_IRQS: ThreadedIrqs = ThreadedIrqs::new();
+
+/// Register a threaded handler for `irq` in the global table.
+pub fn request_threaded_irq<A: Arch, S: Scheduler>(
+    kernel: &Kernel<A, S>,
+    irq: u32,
+    handler: IrqHandler,
+    priority: u8,
+) -> ThreadResult<JoinHandle> {
+    THREADED_IRQS.register(kernel, irq, handler, priority)
+}
+
+/// Record an occurrence of `irq` in the global table; call from the
+/// platform's interrupt vector.
+pub fn handle_irq(irq: u32) -> bool {
+    THREADED_IRQS.handle_irq(irq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use portable_atomic::AtomicUsize;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_calls(irq: u32) {
+        assert_eq!(irq, 5);
+        CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_threaded_irq_dispatch() {
+        static IRQS: ThreadedIrqs = ThreadedIrqs::new();
+
+        // Lines without a thread are left to the platform
+        assert!(!IRQS.handle_irq(5));
+        assert!(!IRQS.handle_irq(MAX_IRQS as u32));
+
+        // Register by hand, standing in for the spawned thread
+        IRQS.install(5, count_calls).unwrap();
+        assert!(IRQS.install(5, count_calls).is_err());
+        IRQS.lines[5].thread.store(42_101, Ordering::Release);
+
+        assert!(IRQS.handle_irq(5));
+        assert!(IRQS.handle_irq(5));
+        assert_eq!(IRQS.stats(5).unwrap().pending, 2);
+
+        assert_eq!(IRQS.run_pending(5), 2);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+        assert_eq!(IRQS.stats(5), Some(IrqStats { raised: 2, handled: 2, pending: 0 }));
+
+        assert!(IRQS.set_affinity(5, 0).is_err());
+        IRQS.set_affinity(5, 0b10).unwrap();
+        assert_eq!(IRQS.affinity(5), Some(CpuSet::from(0b10)));
+
+        IRQS.unregister(5).unwrap();
+        assert!(!IRQS.handle_irq(5));
+        assert_eq!(IRQS.thread(5), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! Thread ownership of named hardware resources.
+//!
+//! Threads claim exclusive [`Lease`]s on resources by name ("spi0",
+//! "dma3", ...) before driving them. A second claim on a held resource
+//! fails with the current owner instead of silently sharing the hardware,
+//! and every lease a thread holds is released when the thread exits, so a
+//! thread that died mid-transfer can't keep a peripheral claimed forever.
+//!
+//! ```ignore
+//! let spi = Lease::claim("spi0")?;
+//! spi_transfer(&buffer);
+//! drop(spi); // or let the thread exit
+//! ```
+
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::ThreadId;
+extern crate alloc;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+/// Errors from claiming a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseError {
+    /// The resource is leased to `owner`
+    Held { resource: String, owner: ThreadId },
+    /// Claimed from outside any thread
+    NoCurrentThread,
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeaseError::Held { resource, owner } => {
+                write!(f, "resource {} is held by thread {}", resource, owner.get())
+            }
+            LeaseError::NoCurrentThread => write!(f, "no current thread to hold the lease"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Holder {
+    owner: ThreadId,
+    /// Distinguishes successive leases of the same resource
+    generation: u64,
+}
+
+struct Leases {
+    held: BTreeMap<String, Holder>,
+    next_generation: u64,
+}
+
+/// Registry of resource leases.
+pub struct LeaseRegistry {
+    leases: SpinLockIrqSave<Leases>,
+}
+
+impl LeaseRegistry {
+    const fn new() -> Self {
+        Self {
+            leases: SpinLockIrqSave::new(Leases {
+                held: BTreeMap::new(),
+                next_generation: 0,
+            }),
+        }
+    }
+
+    /// Lease `resource` to `thread`.
+    pub fn claim_for(&'static self, thread: ThreadId, resource: &str) -> Result<Lease, LeaseError> {
+        let mut leases = self.leases.lock();
+        if let Some(holder) = leases.held.get(resource) {
+            return Err(LeaseError::Held {
+                resource: String::from(resource),
+                owner: holder.owner,
+            });
+        }
+
+        let generation = leases.next_generation;
+        leases.next_generation += 1;
+        leases.held.insert(String::from(resource), Holder { owner: thread, generation });
+        Ok(Lease {
+            registry: self,
+            resource: String::from(resource),
+            owner: thread,
+            generation,
+        })
+    }
+
+    /// Get the thread holding `resource`.
+    pub fn owner(&self, resource: &str) -> Option<ThreadId> {
+        self.leases.lock().held.get(resource).map(|holder| holder.owner)
+    }
+
+    /// Get the names of the resources `thread` holds.
+    pub fn held_by(&self, thread: ThreadId) -> Vec<String> {
+        self.leases
+            .lock()
+            .held
+            .iter()
+            .filter(|(_, holder)| holder.owner == thread)
+            .map(|(resource, _)| resource.clone())
+            .collect()
+    }
+
+    /// Release every lease `thread` holds, returning how many there were.
+    ///
+    /// Called when a thread exits.
+    pub fn release_all(&self, thread: ThreadId) -> usize {
+        let mut leases = self.leases.lock();
+        let before = leases.held.len();
+        leases.held.retain(|_, holder| holder.owner != thread);
+        before - leases.held.len()
+    }
+
+    fn release(&self, resource: &str, generation: u64) {
+        let mut leases = self.leases.lock();
+        // The lease may have been released with its thread and claimed again
+        if leases.held.get(resource).is_some_and(|holder| holder.generation == generation) {
+            leases.held.remove(resource);
+        }
+    }
+}
+
+/// The registry of all resource leases.
+pub static LEASES: LeaseRegistry = LeaseRegistry::new();
+
+/// Exclusive ownership of a named resource, released on drop.
+pub struct Lease {
+    registry: &'static LeaseRegistry,
+    resource: String,
+    owner: ThreadId,
+    generation: u64,
+}
+
+impl Lease {
+    /// Lease `resource` to the current thread.
+    pub fn claim(resource: &str) -> Result<Self, LeaseError> {
+        let thread = crate::thread_new::percpu::current_id().ok_or(LeaseError::NoCurrentThread)?;
+        LEASES.claim_for(thread, resource)
+    }
+
+    /// Get the name of the leased resource.
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    /// Get the thread holding the lease.
+    pub fn owner(&self) -> ThreadId {
+        self.owner
+    }
+
+    /// Check whether the lease is still held, i.e. wasn't released when
+    /// its thread exited.
+    pub fn is_held(&self) -> bool {
+        self.registry
+            .leases
+            .lock()
+            .held
+            .get(&self.resource)
+            .is_some_and(|holder| holder.generation == self.generation)
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.registry.release(&self.resource, self.generation);
+    }
+}
+
+impl fmt::Debug for Lease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lease")
+            .field("resource", &self.resource)
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leases_conflict_and_release_on_exit() {
+        let driver = ThreadId::new(49_240);
+        let other = ThreadId::new(49_241);
+
+        let spi = LEASES.claim_for(driver, "test-spi0").unwrap();
+        let dma = LEASES.claim_for(driver, "test-dma3").unwrap();
+        assert_eq!(LEASES.owner("test-spi0"), Some(driver));
+        assert_eq!(
+            LEASES.claim_for(other, "test-spi0").unwrap_err(),
+            LeaseError::Held { resource: String::from("test-spi0"), owner: driver }
+        );
+
+        drop(spi);
+        let spi = LEASES.claim_for(other, "test-spi0").unwrap();
+        assert_eq!(spi.owner(), other);
+
+        // The driver exits still holding the DMA channel
+        assert_eq!(LEASES.held_by(driver), ["test-dma3"]);
+        assert_eq!(LEASES.release_all(driver), 1);
+        assert!(!dma.is_held());
+        let reclaimed = LEASES.claim_for(other, "test-dma3").unwrap();
+
+        // The stale guard must not release the new owner's lease
+        drop(dma);
+        assert!(reclaimed.is_held());
+        assert_eq!(LEASES.owner("test-dma3"), Some(other));
+    }
+}
@@ -1,6 +1,13 @@
-use crate::error::{ThreadError, ThreadResult};
+use crate::errors::{InvalidOperationError, ScheduleError, ThreadError, ThreadResult};
+use crate::testing::faults::{self, Fault};
 use crate::thread::ThreadId;
+use crate::observability::herd;
+use crate::observability::inversion::InversionWatch;
+use crate::sync::WaitQueue;
+use crate::thread_new::{self, blocked_on, percpu, BlockedOn};
+use crate::time::{Duration, Timeout, TIMER_WHEEL};
 use core::marker::PhantomData;
+use portable_atomic::AtomicU64;
 
 /// Safe thread handle that ensures proper cleanup
 pub struct ThreadHandle {
@@ -37,6 +44,8 @@ impl Drop for ThreadHandle {
 }
 
 /// Safe thread builder with compile-time checks
+#[cfg(feature = "compat")]
+#[deprecated(since = "0.5.0", note = "use `thread_new::ThreadBuilder` with `Kernel::spawn`")]
 pub struct ThreadBuilder<'a> {
     stack_size: usize,
     priority: u8,
@@ -44,12 +53,16 @@ pub struct ThreadBuilder<'a> {
     _phantom: PhantomData<&'a ()>,
 }
 
+#[cfg(feature = "compat")]
+#[allow(deprecated)]
 impl<'a> Default for ThreadBuilder<'a> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "compat")]
+#[allow(deprecated)]
 impl<'a> ThreadBuilder<'a> {
     /// Create a new thread builder
     pub fn new() -> Self {
@@ -90,7 +103,7 @@ impl<'a> ThreadBuilder<'a> {
     {
         // In a real implementation, we'd allocate the stack dynamically
         // For now, we return an error since we can't safely do this in no_std
-        Err(ThreadError::NotImplemented)
+        Err(ThreadError::InvalidOperation(InvalidOperationError::NotSupported))
     }
 }
 
@@ -155,11 +168,11 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         if self.active_threads >= self.max_threads {
-            return Err(ThreadError::SchedulerFull);
+            return Err(ThreadError::Schedule(ScheduleError::QueueFull));
         }
 
         // TODO: Implement actual thread pool execution
-        Err(ThreadError::NotImplemented)
+        Err(ThreadError::InvalidOperation(InvalidOperationError::NotSupported))
     }
 
     /// Get the number of active threads
@@ -174,9 +187,15 @@ impl ThreadPool {
 }
 
 /// Safe mutex implementation
+///
+/// Contended waits are watched for
+/// [priority inversions](crate::observability::inversion).
 pub struct Mutex<T> {
     data: core::cell::UnsafeCell<T>,
     locked: core::sync::atomic::AtomicBool,
+    /// Id of the thread holding the lock, 0 if free or held outside any
+    /// thread
+    owner: AtomicU64,
 }
 
 unsafe impl<T: Send> Sync for Mutex<T> {}
@@ -188,45 +207,104 @@ impl<T> Mutex<T> {
         Self {
             data: core::cell::UnsafeCell::new(data),
             locked: core::sync::atomic::AtomicBool::new(false),
+            owner: AtomicU64::new(0),
         }
     }
 
     /// Lock the mutex
-    pub fn lock(&self) -> MutexGuard<T> {
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if let Some(guard) = self.try_lock() {
+            return guard;
+        }
+
         // Spin lock implementation
-        while self
-            .locked
-            .compare_exchange_weak(
-                false,
-                true,
-                core::sync::atomic::Ordering::Acquire,
-                core::sync::atomic::Ordering::Relaxed,
-            )
-            .is_err()
+        let _blocked = blocked_on(BlockedOn::Mutex(self.addr()));
+        let mut watch = InversionWatch::new(self.addr());
+        while faults::inject(Fault::CasContention)
+            || self
+                .locked
+                .compare_exchange_weak(
+                    false,
+                    true,
+                    core::sync::atomic::Ordering::Acquire,
+                    core::sync::atomic::Ordering::Relaxed,
+                )
+                .is_err()
         {
+            watch.poll(self.owner());
             core::hint::spin_loop();
         }
 
-        MutexGuard { mutex: self }
+        self.acquired()
     }
 
     /// Try to lock the mutex
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        if self
-            .locked
-            .compare_exchange(
-                false,
-                true,
-                core::sync::atomic::Ordering::Acquire,
-                core::sync::atomic::Ordering::Relaxed,
-            )
-            .is_ok()
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if !faults::inject(Fault::CasContention)
+            && self
+                .locked
+                .compare_exchange(
+                    false,
+                    true,
+                    core::sync::atomic::Ordering::Acquire,
+                    core::sync::atomic::Ordering::Relaxed,
+                )
+                .is_ok()
         {
-            Some(MutexGuard { mutex: self })
+            Some(self.acquired())
         } else {
             None
         }
     }
+
+    /// Lock the mutex, giving up after `timeout`
+    ///
+    /// Returns [`TimedOut`](ThreadError::TimedOut) if the mutex is still
+    /// held by another thread when the timeout expires.
+    #[track_caller]
+    pub fn lock_timeout(&self, timeout: Duration) -> ThreadResult<MutexGuard<'_, T>> {
+        crate::might_sleep!();
+
+        if let Some(guard) = self.try_lock() {
+            return Ok(guard);
+        }
+
+        let _blocked = blocked_on(BlockedOn::Mutex(self.addr()));
+        let deadline = TIMER_WHEEL.arm(timeout);
+        let mut watch = InversionWatch::new(self.addr());
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Ok(guard);
+            }
+            if deadline.expired() {
+                return Err(ThreadError::TimedOut);
+            }
+            watch.poll(self.owner());
+            crate::sync::relax();
+        }
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Record the calling thread as owner of the just taken lock.
+    #[track_caller]
+    fn acquired(&self) -> MutexGuard<'_, T> {
+        crate::perf::contention::sample(self.addr());
+        let owner = percpu::current_id().map_or(0, |id| id.as_u64());
+        self.owner.store(owner, core::sync::atomic::Ordering::Relaxed);
+        MutexGuard { mutex: self }
+    }
+
+    fn owner(&self) -> Option<thread_new::ThreadId> {
+        match self.owner.load(core::sync::atomic::Ordering::Relaxed) {
+            0 => None,
+            id => Some(thread_new::ThreadId::new(id)),
+        }
+    }
 }
 
 /// RAII guard for mutex
@@ -250,12 +328,119 @@ impl<'a, T> core::ops::DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
+        self.mutex.owner.store(0, core::sync::atomic::Ordering::Relaxed);
         self.mutex
             .locked
             .store(false, core::sync::atomic::Ordering::Release);
     }
 }
 
+/// Condition variable for waiting on a [`Mutex`]-protected condition
+///
+/// As with any condition variable, waits can return spuriously; callers
+/// should re-check their condition in a loop, or use
+/// [`wait_while`](Condvar::wait_while), which also counts wakeups that
+/// find the condition unmet in the [herd](crate::observability::herd)
+/// statistics.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Condvar {
+    /// Create a new condition variable
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release `guard`'s mutex and wait to be notified, then re-lock it
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_until(guard, None).0
+    }
+
+    /// Wait while `condition` holds for the protected data, re-checking it
+    /// after every wakeup
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        if !condition(&mut guard) {
+            return guard;
+        }
+        loop {
+            guard = self.wait(guard);
+            if !condition(&mut guard) {
+                return guard;
+            }
+            herd::record_wasted(self.point());
+        }
+    }
+
+    /// Like [`wait`](Self::wait), but give up after `timeout`
+    ///
+    /// The mutex is re-locked either way; the result is
+    /// [`TimedOut`](ThreadError::TimedOut) if no notification
+    /// arrived in time.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, ThreadResult<()>) {
+        let deadline = TIMER_WHEEL.arm(timeout);
+        let (guard, notified) = self.wait_until(guard, Some(&deadline));
+        let result = if notified {
+            Ok(())
+        } else {
+            Err(ThreadError::TimedOut)
+        };
+        (guard, result)
+    }
+
+    /// Wake the thread that has waited longest
+    ///
+    /// The wakeup is handed to that thread; a thread that starts waiting
+    /// afterwards cannot take it.
+    pub fn notify_one(&self) {
+        self.waiters.wake_one_on(self.point());
+    }
+
+    /// Wake all waiting threads
+    pub fn notify_all(&self) {
+        self.waiters.wake_all_on(self.point());
+    }
+
+    fn point(&self) -> BlockedOn {
+        BlockedOn::Condvar(self as *const Self as usize)
+    }
+
+    fn wait_until<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: Option<&Timeout<'_>>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        crate::might_sleep!();
+
+        // Queue ourselves before unlocking, so a notify issued right after
+        // the unlock is not lost
+        let node = self.waiters.register();
+        let mutex = guard.mutex;
+        drop(guard);
+
+        let blocked = blocked_on(self.point());
+        let notified = self.waiters.block(&node, deadline);
+        drop(blocked);
+        (mutex.lock(), notified)
+    }
+}
+
 /// Safe yield function
 pub fn yield_now() {
     crate::sync::yield_thread();
@@ -190,10 +190,10 @@ mod tests {
         match builder.spawn(|| {
             println!("Hello from thread");
         }) {
-            Err(crate::error::ThreadError::NotImplemented) => {
+            Err(crate::errors::ThreadError::InvalidOperation(crate::errors::InvalidOperationError::NotSupported)) => {
                 // Expected in no_std
             }
-            _ => panic!("Should return NotImplemented"),
+            _ => panic!("Should return NotSupported"),
         }
     }
 }
\ No newline at end of file
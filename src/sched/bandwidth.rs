@@ -0,0 +1,256 @@
+//! CPU bandwidth control for thread groups.
+//!
+//! Each thread group can be given a quota of CPU time per period, in the
+//! style of cgroup `cpu.max` (for example 20ms every 100ms). Running threads
+//! are charged on every tick; once a group has used its quota for the
+//! current period it is throttled, and its threads are parked off the run
+//! queues until the period refills.
+//!
+//! Groups without a configured bandwidth are never throttled. Group 0 holds
+//! every thread that was not assigned to a group.
+
+use super::trait_def::Scheduler;
+use crate::observability::trace::tracepoint;
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::ReadyRef;
+use crate::time::{Duration, Instant};
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Errors from configuring group bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandwidthError {
+    /// Quota and period must both be non-zero
+    ZeroDuration,
+}
+
+/// Throttling statistics for one group, modelled on cgroup `cpu.stat`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupBandwidthStats {
+    /// CPU time allowed per period (nanoseconds)
+    pub quota_ns: u64,
+    /// Length of a period (nanoseconds)
+    pub period_ns: u64,
+    /// CPU time used in the current period (nanoseconds)
+    pub runtime_ns: u64,
+    /// Total CPU time charged to the group (nanoseconds)
+    pub usage_ns: u64,
+    /// Number of periods that have elapsed
+    pub periods: u64,
+    /// Number of periods in which the group was throttled
+    pub throttled_periods: u64,
+    /// Total time spent throttled (nanoseconds)
+    pub throttled_time_ns: u64,
+    /// Whether the group is throttled right now
+    pub throttled: bool,
+    /// Threads currently parked until the next period
+    pub parked_threads: usize,
+}
+
+/// Bandwidth state for one group.
+struct GroupBandwidth {
+    stats: GroupBandwidthStats,
+    /// Start of the current period
+    period_start: u64,
+    /// When the group was last throttled
+    throttled_at: u64,
+    /// Threads held back while the group is throttled
+    parked: Vec<ReadyRef>,
+}
+
+/// Per-group CPU bandwidth controller.
+pub struct BandwidthController {
+    groups: SpinLockIrqSave<BTreeMap<u32, GroupBandwidth>>,
+}
+
+impl BandwidthController {
+    /// Create a controller with no groups configured.
+    pub const fn new() -> Self {
+        Self {
+            groups: SpinLockIrqSave::new(BTreeMap::new()),
+        }
+    }
+
+    /// Limit `group` to `quota` of CPU time every `period`, starting at `now`.
+    ///
+    /// Reconfiguring a group keeps its statistics and starts a new period.
+    pub fn set_bandwidth(
+        &self,
+        group: u32,
+        quota: Duration,
+        period: Duration,
+        now: Instant,
+    ) -> Result<(), BandwidthError> {
+        if quota.as_nanos() == 0 || period.as_nanos() == 0 {
+            return Err(BandwidthError::ZeroDuration);
+        }
+
+        let mut groups = self.groups.lock();
+        let entry = groups.entry(group).or_insert_with(|| GroupBandwidth {
+            stats: GroupBandwidthStats::default(),
+            period_start: now.as_nanos(),
+            throttled_at: 0,
+            parked: Vec::new(),
+        });
+        entry.stats.quota_ns = quota.as_nanos();
+        entry.stats.period_ns = period.as_nanos();
+        entry.stats.runtime_ns = 0;
+        entry.period_start = now.as_nanos();
+        Ok(())
+    }
+
+    /// Remove the limit on `group`, returning any parked threads to `scheduler`.
+    pub fn clear_bandwidth<S: Scheduler + ?Sized>(&self, group: u32, scheduler: &S) {
+        let removed = self.groups.lock().remove(&group);
+        if let Some(removed) = removed {
+            for thread in removed.parked {
+                scheduler.enqueue(thread);
+            }
+        }
+    }
+
+    /// Check whether `group` has used up its quota for the current period.
+    pub fn is_throttled(&self, group: u32) -> bool {
+        self.groups
+            .lock()
+            .get(&group)
+            .map_or(false, |entry| entry.stats.throttled)
+    }
+
+    /// Get throttling statistics for `group`, if it has a bandwidth limit.
+    pub fn stats(&self, group: u32) -> Option<GroupBandwidthStats> {
+        self.groups.lock().get(&group).map(|entry| GroupBandwidthStats {
+            parked_threads: entry.parked.len(),
+            ..entry.stats
+        })
+    }
+
+    /// Charge `ran` of CPU time to `group`.
+    ///
+    /// Called from the tick path for the running thread's group. Returns
+    /// `true` if the group is throttled and the thread must give up the CPU.
+    pub fn charge(&self, group: u32, ran: Duration, now: Instant) -> bool {
+        let mut groups = self.groups.lock();
+        let Some(entry) = groups.get_mut(&group) else {
+            return false;
+        };
+
+        entry.stats.usage_ns += ran.as_nanos();
+        entry.stats.runtime_ns += ran.as_nanos();
+
+        if !entry.stats.throttled && entry.stats.runtime_ns >= entry.stats.quota_ns {
+            entry.stats.throttled = true;
+            entry.stats.throttled_periods += 1;
+            entry.throttled_at = now.as_nanos();
+            tracepoint!(THROTTLE, Throttle { group, runtime_ns: entry.stats.runtime_ns });
+        }
+        entry.stats.throttled
+    }
+
+    /// Hold `thread` back if its group is throttled.
+    ///
+    /// Returns the thread if it may run now; otherwise it stays parked
+    /// until [`refill`](Self::refill) starts its group's next period.
+    pub fn park_if_throttled(&self, thread: ReadyRef) -> Option<ReadyRef> {
+        let mut groups = self.groups.lock();
+        match groups.get_mut(&thread.0.group_id()) {
+            Some(entry) if entry.stats.throttled => {
+                entry.parked.push(thread);
+                None
+            }
+            _ => Some(thread),
+        }
+    }
+
+    /// Start new periods for groups whose period has elapsed by `now`.
+    ///
+    /// Throttled groups are unthrottled and their parked threads are handed
+    /// back to `scheduler`.
+    pub fn refill<S: Scheduler + ?Sized>(&self, now: Instant, scheduler: &S) {
+        let now = now.as_nanos();
+        let mut released = Vec::new();
+
+        {
+            let mut groups = self.groups.lock();
+            for entry in groups.values_mut() {
+                let elapsed = now.saturating_sub(entry.period_start);
+                if elapsed < entry.stats.period_ns {
+                    continue;
+                }
+
+                let periods = elapsed / entry.stats.period_ns;
+                entry.stats.periods += periods;
+                entry.period_start += periods * entry.stats.period_ns;
+                entry.stats.runtime_ns = 0;
+
+                if entry.stats.throttled {
+                    entry.stats.throttled = false;
+                    entry.stats.throttled_time_ns += now.saturating_sub(entry.throttled_at);
+                    released.append(&mut entry.parked);
+                }
+            }
+        }
+
+        // Enqueue outside the lock; schedulers may take their own locks
+        for thread in released {
+            scheduler.enqueue(thread);
+        }
+    }
+}
+
+impl Default for BandwidthController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global CPU bandwidth controller used by the kernel's tick path.
+pub static CPU_BANDWIDTH: BandwidthController = BandwidthController::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sched::RoundRobinScheduler;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_group_throttles_and_refills() {
+        let controller = BandwidthController::new();
+        let scheduler = RoundRobinScheduler::new(1);
+        let tick = Duration::from_nanos(MS);
+
+        assert_eq!(
+            controller.set_bandwidth(7, Duration::from_nanos(0), tick, Instant::from_nanos(0)),
+            Err(BandwidthError::ZeroDuration)
+        );
+        controller
+            .set_bandwidth(7, Duration::from_nanos(20 * MS), Duration::from_nanos(100 * MS), Instant::from_nanos(0))
+            .unwrap();
+
+        // Unlimited groups are never throttled
+        assert!(!controller.charge(1, tick, Instant::from_nanos(MS)));
+
+        for ms in 1..20 {
+            assert!(!controller.charge(7, tick, Instant::from_nanos(ms * MS)));
+        }
+        assert!(controller.charge(7, tick, Instant::from_nanos(20 * MS)));
+        assert!(controller.is_throttled(7));
+
+        // Nothing changes until the period is over
+        controller.refill(Instant::from_nanos(99 * MS), &scheduler);
+        assert!(controller.is_throttled(7));
+
+        controller.refill(Instant::from_nanos(100 * MS), &scheduler);
+        let stats = controller.stats(7).unwrap();
+        assert!(!stats.throttled);
+        assert_eq!(stats.periods, 1);
+        assert_eq!(stats.throttled_periods, 1);
+        assert_eq!(stats.throttled_time_ns, 80 * MS);
+        assert_eq!(stats.runtime_ns, 0);
+        assert_eq!(stats.usage_ns, 20 * MS);
+
+        controller.clear_bandwidth(7, &scheduler);
+        assert!(controller.stats(7).is_none());
+    }
+}
@@ -0,0 +1,195 @@
+//! Run-to-completion cooperative scheduling.
+//!
+//! [`CooperativeScheduler`] wraps another scheduler and turns off
+//! preemption: a thread keeps its CPU until it yields, blocks or exits, and
+//! the wrapped scheduler only decides who runs next. Because
+//! [`is_preemptive`](Scheduler::is_preemptive) is `false`, a kernel using it
+//! doesn't start a preemption timer, so very small single-core targets get
+//! the thread, timer and sync APIs without paying for periodic interrupts.
+//! Timeouts and sleeps still need something to advance the timer wheel.
+//!
+//! Use it for the whole system by handing it to the kernel, or for one
+//! partition of a [`PartitionedScheduler`](super::PartitionedScheduler).
+//!
+//! A thread that never yields stalls everything else on its CPU, so threads
+//! can declare the longest they run at a time with
+//! [`ThreadBuilder::max_run_length`](crate::ThreadBuilder::max_run_length).
+//! Runs longer than that are reported to the health monitor, from the next
+//! yield or block, or from the tick if there is one.
+//!
+//! ```ignore
+//! let kernel = Kernel::<DefaultArch, _>::new(CooperativeScheduler::new(RoundRobinScheduler::new(1)));
+//! ```
+
+use super::trait_def::{CpuId, RunQueueEntry, Scheduler};
+use crate::observability::health::{HealthIssue, IssueCategory, IssueSeverity, HEALTH_MONITOR};
+use crate::sync::{IrqSafe, SpinLockIrqSave};
+use crate::thread_new::{ReadyRef, RunningRef, ThreadId};
+use crate::time::Instant;
+extern crate alloc;
+use alloc::{collections::BTreeMap, format, string::ToString, vec, vec::Vec};
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Scheduler adapter that never preempts.
+pub struct CooperativeScheduler<S: Scheduler> {
+    inner: S,
+    /// Runs that exceeded the thread's declared maximum
+    overruns: AtomicU64,
+    /// Thread and slice start of the last reported overrun, so a run is
+    /// reported once
+    last_overrun: SpinLockIrqSave<Option<(ThreadId, Instant)>>,
+}
+
+// Safety: the wrapped scheduler is IRQ safe and the remaining state is
+// atomic or behind an IRQ-saving lock.
+unsafe impl<S: Scheduler> IrqSafe for CooperativeScheduler<S> {}
+
+impl<S: Scheduler> CooperativeScheduler<S> {
+    /// Run the threads picked by `inner` to completion.
+    pub const fn new(inner: S) -> Self {
+        Self {
+            inner,
+            overruns: AtomicU64::new(0),
+            last_overrun: SpinLockIrqSave::new(None),
+        }
+    }
+
+    /// Get the wrapped scheduler.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get the number of runs that exceeded their thread's declared
+    /// maximum.
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Report the current run of `current` if it has gone on longer than the
+    /// thread declared. Returns whether it was reported.
+    fn check_run_length(&self, current: &RunningRef, now: Instant) -> bool {
+        let thread = &current.0;
+        let (Some(max), Some(start)) = (thread.max_run_length(), current.time_slice().slice_start()) else {
+            return false;
+        };
+        let ran = now.as_nanos().saturating_sub(start.as_nanos());
+        if ran <= max.as_nanos() {
+            return false;
+        }
+
+        {
+            let mut last = self.last_overrun.lock();
+            if *last == Some((thread.id(), start)) {
+                return false;
+            }
+            *last = Some((thread.id(), start));
+        }
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+
+        let mut context = BTreeMap::new();
+        context.insert("ran_ns".to_string(), ran.to_string());
+        context.insert("max_run_ns".to_string(), max.as_nanos().to_string());
+        HEALTH_MONITOR.report_issue(HealthIssue {
+            severity: IssueSeverity::Warning,
+            category: IssueCategory::Scheduler,
+            description: format!(
+                "Thread {} ran {}ns without yielding, over its {}ns maximum",
+                thread.id().get(),
+                ran,
+                max.as_nanos()
+            ),
+            component: "cooperative".to_string(),
+            detected_at: now,
+            context,
+            affected_threads: vec![thread.id()],
+            remediation: Some("Add yield points to the thread or raise its max_run_length".to_string()),
+        });
+        true
+    }
+}
+
+impl<S: Scheduler> Scheduler for CooperativeScheduler<S> {
+    fn enqueue(&self, thread: ReadyRef) {
+        self.inner.enqueue(thread);
+    }
+
+    fn enqueue_batch(&self, threads: Vec<ReadyRef>) {
+        self.inner.enqueue_batch(threads);
+    }
+
+    fn pick_next(&self, cpu_id: CpuId) -> Option<ReadyRef> {
+        self.inner.pick_next(cpu_id)
+    }
+
+    fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+        // Never preempt, only watch for runaway threads
+        self.check_run_length(current, Instant::now());
+        None
+    }
+
+    fn set_priority(&self, thread_id: ThreadId, priority: u8) {
+        self.inner.set_priority(thread_id, priority);
+    }
+
+    fn on_yield(&self, current: RunningRef) {
+        self.check_run_length(&current, Instant::now());
+        self.inner.on_yield(current);
+    }
+
+    fn on_block(&self, current: RunningRef) {
+        self.check_run_length(&current, Instant::now());
+        self.inner.on_block(current);
+    }
+
+    fn wake_up(&self, thread: ReadyRef) {
+        self.inner.wake_up(thread);
+    }
+
+    fn is_preemptive(&self) -> bool {
+        false
+    }
+
+    fn stats(&self) -> (usize, usize, usize) {
+        self.inner.stats()
+    }
+
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        self.inner.run_queue_snapshot()
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::sched::RoundRobinScheduler;
+    use crate::thread_new::Thread;
+    use crate::time::Duration;
+
+    #[test]
+    fn test_cooperative_never_preempts_and_watches_run_length() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let id = unsafe { ThreadId::new_unchecked(49_210) };
+        let thread = Thread::new(id, stack, || {}, 128).0;
+        thread.set_max_run_length(Duration::from_millis(2));
+
+        let scheduler = CooperativeScheduler::new(RoundRobinScheduler::new(1));
+        assert!(!scheduler.is_preemptive());
+        let running = ReadyRef(thread).start_running();
+        running.time_slice().start_slice(Instant::from_nanos(1_000_000));
+
+        // Within its declared run length
+        assert!(!scheduler.check_run_length(&running, Instant::from_nanos(2_500_000)));
+        assert!(scheduler.on_tick(&running).is_none());
+
+        // An overrun is reported once per run
+        assert!(scheduler.check_run_length(&running, Instant::from_nanos(3_500_000)));
+        assert!(!scheduler.check_run_length(&running, Instant::from_nanos(9_000_000)));
+        assert_eq!(scheduler.overruns(), 1);
+
+        running.time_slice().start_slice(Instant::from_nanos(10_000_000));
+        assert!(scheduler.check_run_length(&running, Instant::from_nanos(20_000_000)));
+        assert_eq!(scheduler.overruns(), 2);
+    }
+}
@@ -0,0 +1,415 @@
+//! Sets of CPUs for thread affinity.
+//!
+//! A [`CpuSet`] names the CPUs a thread, partition or interrupt line may
+//! run on. It holds up to [`CpuSet::CAPACITY`] CPUs, more than fit in the
+//! `u64` masks it replaces; those masks still convert with `From<u64>`, so
+//! `.cpu_affinity(0b0110)` keeps working. Sets can be built from the
+//! configured topology:
+//!
+//! ```ignore
+//! // One hardware thread per core on the first NUMA node
+//! let cpus = CpuSet::numa_node(0) & CpuSet::physical_cores();
+//! ThreadBuilder::new().cpu_affinity(cpus)
+//! ```
+
+use super::trait_def::CpuId;
+use crate::perf::numa::NumaNodeId;
+use core::fmt;
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Sub};
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Number of 64-bit words in a set.
+const WORDS: usize = 4;
+
+/// Set of CPU numbers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CpuSet {
+    words: [u64; WORDS],
+}
+
+impl CpuSet {
+    /// Highest CPU number a set can hold, plus one.
+    pub const CAPACITY: usize = WORDS * 64;
+
+    /// Create an empty set.
+    pub const fn new() -> Self {
+        Self { words: [0; WORDS] }
+    }
+
+    /// Create a set of every CPU below [`CAPACITY`](Self::CAPACITY).
+    pub const fn all() -> Self {
+        Self { words: [u64::MAX; WORDS] }
+    }
+
+    /// Create a set holding only `cpu`.
+    pub fn single(cpu: CpuId) -> Self {
+        let mut set = Self::new();
+        set.insert(cpu);
+        set
+    }
+
+    /// Create a set of the CPUs in `range`.
+    pub fn range(range: core::ops::Range<CpuId>) -> Self {
+        range.collect()
+    }
+
+    /// Create a set from a mask of CPUs 0 to 63.
+    pub const fn from_mask(mask: u64) -> Self {
+        let mut words = [0; WORDS];
+        words[0] = mask;
+        Self { words }
+    }
+
+    /// Create a set from its words, CPU `64 * i + b` being bit `b` of word `i`.
+    pub const fn from_words(words: [u64; WORDS]) -> Self {
+        Self { words }
+    }
+
+    /// Get the set's words, as taken by [`from_words`](Self::from_words).
+    pub const fn words(&self) -> [u64; WORDS] {
+        self.words
+    }
+
+    /// Get the mask of CPUs 0 to 63 in the set.
+    pub const fn low_mask(&self) -> u64 {
+        self.words[0]
+    }
+
+    /// Create a set of the CPUs of NUMA node `node` in the
+    /// [configured topology](crate::perf::numa::topology).
+    pub fn numa_node(node: NumaNodeId) -> Self {
+        crate::perf::numa::topology().get_node_cpus(node).iter().copied().collect()
+    }
+
+    /// Create a set with the first hardware thread of every physical core
+    /// in the [cache topology](crate::perf::cache_aware::topology).
+    pub fn physical_cores() -> Self {
+        let topology = crate::perf::cache_aware::topology();
+        (0..topology.cpu_count())
+            .filter(|&cpu| topology.smt_siblings(cpu).iter().all(|&sibling| sibling > cpu))
+            .collect()
+    }
+
+    /// Add `cpu`. Returns whether it was not in the set yet.
+    ///
+    /// # Panics
+    ///
+    /// If `cpu` is not below [`CAPACITY`](Self::CAPACITY).
+    pub fn insert(&mut self, cpu: CpuId) -> bool {
+        assert!(cpu < Self::CAPACITY, "CPU {} beyond CpuSet capacity", cpu);
+        let (word, bit) = (cpu / 64, 1 << (cpu % 64));
+        let added = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        added
+    }
+
+    /// Remove `cpu`. Returns whether it was in the set.
+    pub fn remove(&mut self, cpu: CpuId) -> bool {
+        if cpu >= Self::CAPACITY {
+            return false;
+        }
+        let (word, bit) = (cpu / 64, 1 << (cpu % 64));
+        let removed = self.words[word] & bit != 0;
+        self.words[word] &= !bit;
+        removed
+    }
+
+    /// Check whether `cpu` is in the set.
+    pub fn contains(&self, cpu: CpuId) -> bool {
+        cpu < Self::CAPACITY && self.words[cpu / 64] & (1 << (cpu % 64)) != 0
+    }
+
+    /// Get the number of CPUs in the set.
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Check whether the set holds no CPU.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Get the lowest CPU in the set.
+    pub fn first(&self) -> Option<CpuId> {
+        self.iter().next()
+    }
+
+    /// Get the `n`th lowest CPU in the set.
+    pub fn nth(&self, n: usize) -> Option<CpuId> {
+        self.iter().nth(n)
+    }
+
+    /// Get the number of CPUs in the set below `cpu`.
+    pub fn rank(&self, cpu: CpuId) -> usize {
+        self.iter().take_while(|&member| member < cpu).count()
+    }
+
+    /// Get the CPUs in either set.
+    pub fn union(&self, other: &Self) -> Self {
+        *self | *other
+    }
+
+    /// Get the CPUs in both sets.
+    pub fn intersection(&self, other: &Self) -> Self {
+        *self & *other
+    }
+
+    /// Get the CPUs in this set but not in `other`.
+    pub fn difference(&self, other: &Self) -> Self {
+        *self - *other
+    }
+
+    /// Check whether every CPU of this set is in `other`.
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    /// Check whether the sets share no CPU.
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.intersection(other).is_empty()
+    }
+
+    /// Iterate over the CPUs in the set in ascending order.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            words: self.words,
+            word: 0,
+        }
+    }
+}
+
+impl From<u64> for CpuSet {
+    fn from(mask: u64) -> Self {
+        Self::from_mask(mask)
+    }
+}
+
+impl FromIterator<CpuId> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = CpuId>>(cpus: I) -> Self {
+        let mut set = Self::new();
+        set.extend(cpus);
+        set
+    }
+}
+
+impl Extend<CpuId> for CpuSet {
+    /// Add the CPUs of `cpus`, skipping any beyond the capacity.
+    fn extend<I: IntoIterator<Item = CpuId>>(&mut self, cpus: I) {
+        for cpu in cpus.into_iter().filter(|&cpu| cpu < Self::CAPACITY) {
+            self.insert(cpu);
+        }
+    }
+}
+
+impl IntoIterator for CpuSet {
+    type Item = CpuId;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for &CpuSet {
+    type Item = CpuId;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl BitOr for CpuSet {
+    type Output = Self;
+
+    fn bitor(mut self, other: Self) -> Self {
+        self |= other;
+        self
+    }
+}
+
+impl BitOrAssign for CpuSet {
+    fn bitor_assign(&mut self, other: Self) {
+        for (word, other) in self.words.iter_mut().zip(other.words) {
+            *word |= other;
+        }
+    }
+}
+
+impl BitAnd for CpuSet {
+    type Output = Self;
+
+    fn bitand(mut self, other: Self) -> Self {
+        self &= other;
+        self
+    }
+}
+
+impl BitAndAssign for CpuSet {
+    fn bitand_assign(&mut self, other: Self) {
+        for (word, other) in self.words.iter_mut().zip(other.words) {
+            *word &= other;
+        }
+    }
+}
+
+impl Sub for CpuSet {
+    type Output = Self;
+
+    fn sub(mut self, other: Self) -> Self {
+        for (word, other) in self.words.iter_mut().zip(other.words) {
+            *word &= !other;
+        }
+        self
+    }
+}
+
+impl Not for CpuSet {
+    type Output = Self;
+
+    fn not(mut self) -> Self {
+        for word in &mut self.words {
+            *word = !*word;
+        }
+        self
+    }
+}
+
+/// Lists the CPUs as ranges, e.g. `0-3,8`.
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cpus = self.iter().peekable();
+        let mut first = true;
+        while let Some(start) = cpus.next() {
+            let mut end = start;
+            while cpus.peek() == Some(&(end + 1)) {
+                end = cpus.next().unwrap_or(end);
+            }
+            if !first {
+                f.write_str(",")?;
+            }
+            first = false;
+            if start == end {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}-{}", start, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CpuSet({})", self)
+    }
+}
+
+/// Iterator over the CPUs of a [`CpuSet`].
+#[derive(Debug, Clone)]
+pub struct Iter {
+    /// Words not yet iterated, with returned CPUs cleared
+    words: [u64; WORDS],
+    word: usize,
+}
+
+impl Iterator for Iter {
+    type Item = CpuId;
+
+    fn next(&mut self) -> Option<CpuId> {
+        while self.word < WORDS {
+            let bits = self.words[self.word];
+            if bits != 0 {
+                self.words[self.word] = bits & (bits - 1);
+                return Some(self.word * 64 + bits.trailing_zeros() as usize);
+            }
+            self.word += 1;
+        }
+        None
+    }
+}
+
+/// [`CpuSet`] that can be shared between threads.
+///
+/// Each word is read and written atomically, but a set is not: a reader
+/// racing with [`store`](Self::store) may see a mix of both sets.
+pub struct AtomicCpuSet {
+    words: [AtomicU64; WORDS],
+}
+
+impl AtomicCpuSet {
+    /// Create an atomic set holding `set`.
+    pub const fn new(set: CpuSet) -> Self {
+        Self {
+            words: [
+                AtomicU64::new(set.words[0]),
+                AtomicU64::new(set.words[1]),
+                AtomicU64::new(set.words[2]),
+                AtomicU64::new(set.words[3]),
+            ],
+        }
+    }
+
+    /// Get the set.
+    pub fn load(&self) -> CpuSet {
+        let mut words = [0; WORDS];
+        for (word, atomic) in words.iter_mut().zip(&self.words) {
+            *word = atomic.load(Ordering::Acquire);
+        }
+        CpuSet { words }
+    }
+
+    /// Replace the set with `set`.
+    pub fn store(&self, set: CpuSet) {
+        for (atomic, word) in self.words.iter().zip(set.words) {
+            atomic.store(word, Ordering::Release);
+        }
+    }
+
+    /// Check whether `cpu` is in the set, reading only its word.
+    pub fn contains(&self, cpu: CpuId) -> bool {
+        cpu < CpuSet::CAPACITY && self.words[cpu / 64].load(Ordering::Acquire) & (1 << (cpu % 64)) != 0
+    }
+
+    /// Check whether the set holds no CPU.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| word.load(Ordering::Acquire) == 0)
+    }
+}
+
+impl fmt::Debug for AtomicCpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate alloc;
+    use alloc::{format, vec::Vec};
+
+    #[test]
+    fn test_cpu_set_operations() {
+        let low = CpuSet::from(0b1111);
+        let high: CpuSet = [2, 3, 70, 200].into_iter().collect();
+        assert_eq!(high.len(), 4);
+        assert!(high.contains(200) && !high.contains(201) && !high.contains(10_000));
+
+        assert_eq!((low & high).iter().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!((low | high).len(), 6);
+        assert_eq!((high - low).iter().collect::<Vec<_>>(), [70, 200]);
+        assert!(CpuSet::range(2..4).is_subset(&low));
+        assert!((high - low).is_disjoint(&low));
+        assert_eq!((high.first(), high.nth(2), high.rank(70)), (Some(2), Some(70), 2));
+        assert_eq!(CpuSet::from_words(high.words()), high);
+        assert_eq!(format!("{}", low | high), "0-3,70,200");
+        assert_eq!(format!("{:?}", CpuSet::new()), "CpuSet()");
+
+        let shared = AtomicCpuSet::new(CpuSet::new());
+        assert!(shared.is_empty());
+        shared.store(high);
+        assert!(shared.contains(200) && !shared.contains(0));
+        assert_eq!(shared.load(), high);
+    }
+}
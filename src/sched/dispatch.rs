@@ -0,0 +1,182 @@
+//! Dispatch rounds.
+//!
+//! Every switch through the [global scheduler](super::global) runs as a
+//! dispatch round: interrupts go off once, the CPU is handed over, and they
+//! are only restored once the thread that started the round runs again. If
+//! wakeups came in while it was switched out and the scheduler now has a
+//! thread that outranks it, the round goes on and hands the CPU over again
+//! right away, instead of returning to re-enable interrupts only to take
+//! another interrupt for the next switch. Such back-to-back switches count
+//! in [`PerfCounters::optimized_context_switches`].
+//!
+//! A round also re-arms the preemption timer, once, before the first
+//! switch, so the thread switched to is preempted in time. Architectures
+//! with a one-shot timer register how to re-arm it with
+//! [`set_timer_rearm`] and call [`rearm_timer`] on the way out of their
+//! timer interrupt, which re-arms it unless a round already did:
+//!
+//! ```ignore
+//! dispatch::set_timer_rearm(|| {
+//!     let _ = unsafe { setup_preemption_timer(1000) };
+//! });
+//! ```
+//!
+//! [`PerfCounters::optimized_context_switches`]: crate::perf::PerfCounters::optimized_context_switches
+
+use super::trait_def::Scheduler;
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{RunningRef, Thread};
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static TIMER_REARM: SpinLockIrqSave<Option<fn()>> = SpinLockIrqSave::new(None);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_FIRED: AtomicBool = AtomicBool::new(false);
+
+/// Per CPU, whether the timer fired and has not been re-armed since.
+static TIMER_FIRED: [AtomicBool; MAX_CPUS] = [NOT_FIRED; MAX_CPUS];
+
+/// Per CPU, wakeups of threads last run there not yet dispatched.
+struct Wakeups {
+    pending: [AtomicUsize; MAX_CPUS],
+}
+
+impl Wakeups {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NONE: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            pending: [NONE; MAX_CPUS],
+        }
+    }
+
+    fn note(&self, cpu: usize) {
+        self.pending[cpu % MAX_CPUS].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take one pending wakeup on `cpu`, if there is one.
+    fn take(&self, cpu: usize) -> bool {
+        self.pending[cpu % MAX_CPUS]
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| pending.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Drop the wakeups pending on `cpu`.
+    fn clear(&self, cpu: usize) {
+        self.pending[cpu % MAX_CPUS].store(0, Ordering::Relaxed);
+    }
+}
+
+static PENDING_WAKEUPS: Wakeups = Wakeups::new();
+
+/// Re-arm the preemption timer with `rearm` after it fires.
+pub fn set_timer_rearm(rearm: fn()) {
+    *TIMER_REARM.lock() = Some(rearm);
+}
+
+/// Note that this CPU's timer fired and needs re-arming.
+pub(crate) fn timer_fired() {
+    TIMER_FIRED[cpu_id()].store(true, Ordering::Relaxed);
+}
+
+/// Re-arm this CPU's timer if it fired and was not re-armed since.
+pub fn rearm_timer() {
+    if !TIMER_FIRED[cpu_id()].swap(false, Ordering::Relaxed) {
+        return;
+    }
+    // Copied out so the timer is not programmed under the lock
+    let rearm = *TIMER_REARM.lock();
+    if let Some(rearm) = rearm {
+        rearm();
+    }
+}
+
+/// Note a wakeup of a thread last run on `cpu`.
+pub(crate) fn note_wakeup(cpu: usize) {
+    PENDING_WAKEUPS.note(cpu);
+}
+
+/// End the round on this CPU.
+///
+/// Wakeups are only taken inside rounds, so those left over are dropped
+/// rather than piling up; the threads they readied are queued anyway.
+pub(crate) fn end_round() {
+    PENDING_WAKEUPS.clear(cpu_id());
+}
+
+/// Pick the thread to switch to back to back from `prev`, which just got
+/// the CPU back in a round, if a wakeup readied one that outranks it.
+///
+/// `prev` goes back on the run queue if one is returned.
+pub(crate) fn next_woken(prev: &Thread, scheduler: &dyn Scheduler) -> Option<Thread> {
+    if !PENDING_WAKEUPS.take(cpu_id()) {
+        return None;
+    }
+    // Asked before picking, so threads that do not outrank `prev` keep
+    // their place in the queue
+    if !scheduler.has_higher_priority_work(cpu_id(), prev.priority()) {
+        return None;
+    }
+    let next = scheduler.pick_next(cpu_id())?;
+    scheduler.enqueue(RunningRef(prev.clone()).stop_running());
+    Some(next.start_running().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static REARMED: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_timer_rearmed_once_per_firing() {
+        set_timer_rearm(|| {
+            REARMED.fetch_add(1, Ordering::Relaxed);
+        });
+
+        timer_fired();
+        // The round's re-arm, then the interrupt handler's on its way out
+        rearm_timer();
+        rearm_timer();
+        assert_eq!(REARMED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_wakeups_taken_once_and_dropped_at_round_end() {
+        let wakeups = Wakeups::new();
+        wakeups.note(3);
+        assert!(wakeups.take(3));
+        assert!(!wakeups.take(3));
+
+        wakeups.note(3);
+        wakeups.note(3);
+        wakeups.clear(3);
+        assert!(!wakeups.take(3));
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_woken_thread_that_does_not_outrank_keeps_its_place() {
+        use crate::mem::{StackPool, StackSizeClass};
+        use crate::sched::RoundRobinScheduler;
+        use crate::thread_new::{ReadyRef, ThreadId};
+
+        let pool = StackPool::new();
+        let thread = |id, priority| {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            Thread::new(unsafe { ThreadId::new_unchecked(id) }, stack, || {}, priority).0
+        };
+        let scheduler = RoundRobinScheduler::new(1);
+        let prev = thread(49_731, 100);
+        let first = thread(49_732, 100);
+        let second = thread(49_733, 100);
+        scheduler.enqueue(ReadyRef(first.clone()));
+        scheduler.enqueue(ReadyRef(second.clone()));
+
+        note_wakeup(cpu_id());
+        assert!(next_woken(&prev, &scheduler).is_none());
+        assert_eq!(scheduler.pick_next(0).map(|next| next.id()), Some(first.id()));
+        assert_eq!(scheduler.pick_next(0).map(|next| next.id()), Some(second.id()));
+    }
+}
@@ -0,0 +1,285 @@
+//! The scheduler behind preemption checkpoints and timer ticks.
+//!
+//! Code that switches threads without a [`Kernel`](crate::Kernel) at hand,
+//! such as [`preemption_checkpoint`](crate::platform_timer::preemption_checkpoint),
+//! the architecture timer interrupt handlers and the [`crate::scheduler`]
+//! API, goes through one global [`Scheduler`] trait object. Its run queues
+//! are the only ones those paths use. It is
+//! [`ATOMIC_SCHEDULER`](crate::ATOMIC_SCHEDULER) unless another one is
+//! installed with [`set_global_scheduler`], for example a kernel's own with
+//! [`Kernel::install_global_scheduler`](crate::Kernel::install_global_scheduler).
+//!
+//! Switches run as [dispatch rounds](super::dispatch), which go on switching
+//! while wakeups keep readying threads that outrank the one switched back to.
+
+use super::dispatch;
+use super::trait_def::Scheduler;
+use crate::arch::percpu::cpu_id;
+use crate::atomic_scheduler::ATOMIC_SCHEDULER;
+use crate::perf::context_switch_opt::SwitchPath;
+use crate::perf::PERF_COUNTERS;
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{self, percpu, ExitStatus, RunningRef, Thread, ThreadState};
+use crate::time::timer::IrqGuard;
+use crate::time::{in_interrupt, preempt_count};
+
+/// Installed scheduler, `None` for the default.
+static GLOBAL_SCHEDULER: SpinLockIrqSave<Option<&'static dyn Scheduler>> = SpinLockIrqSave::new(None);
+
+/// Install `scheduler` as the global scheduler.
+///
+/// Threads queued on the previous scheduler stay there, so this should be
+/// done before any thread is started.
+pub fn set_global_scheduler(scheduler: &'static dyn Scheduler) {
+    *GLOBAL_SCHEDULER.lock() = Some(scheduler);
+}
+
+/// Get the global scheduler.
+pub fn global_scheduler() -> &'static dyn Scheduler {
+    GLOBAL_SCHEDULER.lock().unwrap_or(&ATOMIC_SCHEDULER)
+}
+
+/// Yield the current thread to the global scheduler. Returns whether
+/// another thread was switched to.
+///
+/// Does nothing when no thread is running on this CPU.
+pub fn yield_current() -> bool {
+    let Some(current) = thread_new::current() else {
+        return false;
+    };
+    let scheduler = global_scheduler();
+    scheduler.on_yield(RunningRef(current.clone()));
+    switch_from(&current, scheduler)
+}
+
+/// Account a timer tick to the current thread, switching away from it if
+/// the global scheduler preempts it. Returns whether another thread was
+/// switched to.
+///
+/// The tick is held back while [preemption is
+/// deferred](crate::kernel::preemption_deferred). A thread holding a
+/// [`PreemptGuard`](crate::PreemptGuard) is not switched away from; the
+/// preemption is left pending for its next
+/// [`preemption_checkpoint`](crate::platform_timer::preemption_checkpoint).
+///
+/// Called from the timer interrupt handlers.
+pub fn timer_tick() -> bool {
+    dispatch::timer_fired();
+    if crate::kernel::defer_tick() {
+        return false;
+    }
+    tick(global_scheduler())
+}
+
+/// Account a tick to the current thread on `scheduler`, the global one
+/// outside tests.
+fn tick(scheduler: &dyn Scheduler) -> bool {
+    let Some(current) = thread_new::current() else {
+        return false;
+    };
+    let Some(preempted) = scheduler.on_tick(&RunningRef(current.clone())) else {
+        return false;
+    };
+    if preempt_count() > 0 || in_interrupt() {
+        // It keeps running until it can be switched away from
+        preempted.0.set_state(ThreadState::Running);
+        crate::platform_timer::set_preemption_pending();
+        return false;
+    }
+    scheduler.enqueue(preempted);
+    switch_from(&current, scheduler)
+}
+
+/// Switch from `prev` to the next thread of `scheduler`, the global one
+/// outside tests.
+///
+/// Interrupts stay off until `prev` runs again and no woken thread
+/// outranks it, however many switches that takes.
+fn switch_from(prev: &Thread, scheduler: &dyn Scheduler) -> bool {
+    let Some(next) = scheduler.pick_next(cpu_id()) else {
+        return false;
+    };
+    let mut next = next.start_running().0;
+    if next.id() == prev.id() {
+        return false;
+    }
+
+    let _irq = IrqGuard::enter();
+    dispatch::rearm_timer();
+    let mut back_to_back = false;
+    loop {
+        let path = switch_to(prev, next);
+        // Same-domain switches count themselves
+        if back_to_back && path == Some(SwitchPath::Full) {
+            PERF_COUNTERS.record_context_switch();
+        }
+        match dispatch::next_woken(prev, scheduler) {
+            Some(woken) => {
+                next = woken;
+                back_to_back = true;
+            }
+            None => {
+                dispatch::end_round();
+                return true;
+            }
+        }
+    }
+}
+
+/// Switch from `prev` to `next`, just made running, returning the path
+/// taken once `prev` is switched back to.
+fn switch_to(prev: &Thread, next: Thread) -> Option<SwitchPath> {
+    // Only the current slot owns `next`: a handle left on this stack would
+    // leak if `prev` is exiting and never resumes
+    let raw = next.into_raw();
+    // Safety: `raw` comes from `into_raw` and is reclaimed once, here
+    percpu::set_current(unsafe { Thread::from_raw(raw) });
+    // Safety: the current slot keeps the thread alive across the switch
+    let next = core::mem::ManuallyDrop::new(unsafe { Thread::from_raw(raw) });
+    // Safety: `next` was just taken off the run queue and published as
+    // this CPU's current thread
+    unsafe { crate::perf::context_switch_opt::switch_threads(prev, &next) }
+}
+
+/// Exit the current thread with `status` and switch to the next thread of
+/// the global scheduler for good.
+///
+/// Where a thread started by [`Arch::init_context`](crate::arch::Arch::init_context)
+/// ends up once its entry point is done.
+pub(crate) fn exit_current(status: ExitStatus) -> ! {
+    if let Some(current) = thread_new::current() {
+        // A handle left on this stack would never be dropped. The exited
+        // list holds the thread until it is reaped, which does not happen
+        // before another thread runs, so a view of it will do.
+        let raw = current.clone().into_raw();
+        // Safety: `raw` comes from `into_raw` and is reclaimed once, here
+        drop(unsafe { Thread::from_raw(raw) });
+        RunningRef(current).exit(status);
+        // Safety: see above; the view is never dropped
+        let current = core::mem::ManuallyDrop::new(unsafe { Thread::from_raw(raw) });
+        while !switch_from(&current, global_scheduler()) {
+            core::hint::spin_loop();
+        }
+    }
+    // Only reached without a current thread, or on an architecture that
+    // does not switch
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::arch::{Arch, DefaultArch};
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::sched::RoundRobinScheduler;
+    use crate::thread_new::{ReadyRef, ThreadId};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use portable_atomic::Ordering;
+
+    /// Switches into the test's threads, with whether interrupts were on.
+    static SWITCHES: spin::Mutex<Vec<(usize, bool)>> = spin::Mutex::new(Vec::new());
+
+    fn record_switch(_prev: ThreadId, next: ThreadId) {
+        if (49_729..=49_730).contains(&next.get()) {
+            SWITCHES.lock().push((next.get(), DefaultArch::interrupts_enabled()));
+        }
+    }
+
+    /// A thread with a saved context, which `NoOpArch` does not create
+    fn thread(pool: &StackPool, id: usize, priority: u8) -> Thread {
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let context = Box::into_raw(Box::default());
+        Thread::with_context(unsafe { ThreadId::new_unchecked(id) }, stack, None, priority, Some(context)).0
+    }
+
+    // SimArch never turns interrupts off
+    #[cfg(not(feature = "sim"))]
+    #[test]
+    fn test_back_to_back_switch() {
+        let scheduler: &'static RoundRobinScheduler = Box::leak(Box::new(RoundRobinScheduler::new(1)));
+        let pool = StackPool::new();
+        let prev = ReadyRef(thread(&pool, 49_728, 10)).start_running().0;
+        let first = thread(&pool, 49_729, 200);
+        let woken = thread(&pool, 49_730, 100);
+        scheduler.enqueue(ReadyRef(first.clone()));
+        // Woken while `first` runs, and outranks `prev`
+        scheduler.wake_up(ReadyRef(woken.clone()));
+        dispatch::note_wakeup(cpu_id());
+
+        let hook = crate::kernel::register_switch_hook(record_switch).unwrap();
+        let slot = percpu::hold_slot();
+        let optimized = PERF_COUNTERS.optimized_context_switches.load(Ordering::Relaxed);
+        assert!(switch_from(&prev, scheduler));
+        let current = thread_new::current().map(|thread| thread.id());
+        drop(slot);
+        crate::kernel::unregister_switch_hook(hook);
+
+        // Both switches ran in one round with interrupts off, and they are
+        // back on after it
+        assert_eq!(*SWITCHES.lock(), [(49_729, false), (49_730, false)]);
+        assert!(DefaultArch::interrupts_enabled());
+        assert_eq!(current, Some(woken.id()));
+        assert!(PERF_COUNTERS.optimized_context_switches.load(Ordering::Relaxed) > optimized);
+
+        // `prev` went back on the run queue for the second switch
+        assert_eq!(scheduler.pick_next(0).map(|next| next.id()), Some(prev.id()));
+        assert!(scheduler.pick_next(0).is_none());
+    }
+
+    /// Preempts the current thread on every tick.
+    struct AlwaysPreempt(RoundRobinScheduler);
+
+    // Safety: only forwards to the round-robin scheduler's queues
+    unsafe impl crate::sync::IrqSafe for AlwaysPreempt {}
+
+    impl Scheduler for AlwaysPreempt {
+        fn enqueue(&self, thread: ReadyRef) {
+            self.0.enqueue(thread)
+        }
+
+        fn pick_next(&self, cpu_id: crate::sched::CpuId) -> Option<ReadyRef> {
+            self.0.pick_next(cpu_id)
+        }
+
+        fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+            Some(current.prepare_preemption())
+        }
+
+        fn set_priority(&self, thread_id: ThreadId, priority: u8) {
+            self.0.set_priority(thread_id, priority)
+        }
+    }
+
+    #[test]
+    fn test_tick_under_preempt_guard() {
+        let scheduler = AlwaysPreempt(RoundRobinScheduler::new(1));
+        let pool = StackPool::new();
+        let running = ReadyRef(thread(&pool, 49_731, 10)).start_running().0;
+        let other = thread(&pool, 49_732, 200);
+        scheduler.enqueue(ReadyRef(other.clone()));
+
+        let slot = percpu::hold_slot();
+        percpu::set_current(running.clone());
+        let guard = crate::time::PreemptGuard::enter();
+        let switched = tick(&scheduler);
+        let current = thread_new::current().map(|thread| thread.id());
+        let pending = crate::platform_timer::is_preemption_pending();
+        // Still held back at a checkpoint under the guard
+        crate::platform_timer::preemption_checkpoint();
+        let still_pending = crate::platform_timer::is_preemption_pending();
+        drop(guard);
+        crate::platform_timer::clear_preemption_pending();
+        drop(slot);
+
+        assert!(!switched);
+        assert_eq!(current, Some(running.id()));
+        assert_eq!(running.state(), ThreadState::Running);
+        assert!(pending && still_pending);
+        // `other` was not picked
+        assert_eq!(scheduler.pick_next(0).map(|next| next.id()), Some(other.id()));
+        assert!(scheduler.pick_next(0).is_none());
+    }
+}
@@ -0,0 +1,426 @@
+//! Intrusive pairing heap of threads keyed by `u64`.
+//!
+//! Deadline (EDF) and virtual-runtime (CFS-style) backends need a
+//! min-priority queue whose keys change while threads are queued. This heap
+//! threads its nodes through a [`HeapLink`] embedded in every thread, so
+//! queueing never allocates, and supports:
+//!
+//! | operation         | cost               |
+//! |-------------------|--------------------|
+//! | `push`, `peek`    | O(1)               |
+//! | `pop`, `remove`   | O(log n) amortized |
+//! | `decrease_key`    | O(log n) amortized |
+//!
+//! The heap is not synchronized; backends keep it behind their run-queue
+//! lock. A thread can be in at most one heap at a time, and the heap holds
+//! a reference to each queued thread. Threads with equal keys come out in
+//! no particular order.
+
+use crate::thread_new::{Thread, ThreadId};
+use core::cell::UnsafeCell;
+use core::ptr;
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Heap node embedded in every thread.
+pub struct HeapLink {
+    /// ID of the heap the thread is queued in (0 = none). Claimed with a
+    /// compare-exchange, after which only that heap touches `links`.
+    heap: AtomicU64,
+    links: UnsafeCell<Links>,
+}
+
+struct Links {
+    key: u64,
+    /// First child
+    child: *const HeapLink,
+    /// Next sibling
+    next: *const HeapLink,
+    /// Previous sibling, or the parent if this is the first child
+    prev: *const HeapLink,
+    /// Reference held by the heap, from `Thread::into_raw`
+    owner: *const (),
+    /// ID of the queued thread
+    thread: Option<ThreadId>,
+}
+
+// Safety: `links` is only accessed by the heap that claimed the node
+// through `heap`, under that heap's `&mut self`
+unsafe impl Sync for HeapLink {}
+unsafe impl Send for HeapLink {}
+
+impl HeapLink {
+    /// Create an unqueued node.
+    pub const fn new() -> Self {
+        Self {
+            heap: AtomicU64::new(0),
+            links: UnsafeCell::new(Links {
+                key: 0,
+                child: ptr::null(),
+                next: ptr::null(),
+                prev: ptr::null(),
+                owner: ptr::null(),
+                thread: None,
+            }),
+        }
+    }
+
+    /// Check whether the thread is queued in any heap.
+    pub fn is_queued(&self) -> bool {
+        self.heap.load(Ordering::Acquire) != 0
+    }
+}
+
+impl Default for HeapLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Source of heap IDs.
+static NEXT_HEAP_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Min-heap of threads keyed by `u64`.
+pub struct PairingHeap {
+    /// Assigned on first push (0 = not yet assigned)
+    id: u64,
+    root: *const HeapLink,
+    len: usize,
+}
+
+// Safety: the heap owns references to its threads, which are `Send`
+unsafe impl Send for PairingHeap {}
+
+/// Access a node's links.
+///
+/// # Safety
+///
+/// `link` must be queued in the heap making the call.
+unsafe fn links(link: *const HeapLink) -> *mut Links {
+    unsafe { (*link).links.get() }
+}
+
+impl PairingHeap {
+    /// Create an empty heap.
+    pub const fn new() -> Self {
+        Self {
+            id: 0,
+            root: ptr::null(),
+            len: 0,
+        }
+    }
+
+    /// Get the number of queued threads.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether the heap is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Queue `thread` under `key`.
+    ///
+    /// Hands the thread back if it is already queued in a heap.
+    pub fn push(&mut self, thread: Thread, key: u64) -> Result<(), Thread> {
+        if self.id == 0 {
+            self.id = NEXT_HEAP_ID.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let link: *const HeapLink = thread.heap_link();
+        // Safety: `link` points into the thread, which is alive here
+        let claimed = unsafe { &*link }
+            .heap
+            .compare_exchange(0, self.id, Ordering::AcqRel, Ordering::Acquire);
+        if claimed.is_err() {
+            return Err(thread);
+        }
+
+        // Safety: claimed above; the thread stays alive while the heap owns
+        // the reference stored in `owner`
+        unsafe {
+            *links(link) = Links {
+                key,
+                child: ptr::null(),
+                next: ptr::null(),
+                prev: ptr::null(),
+                thread: Some(thread.id()),
+                owner: thread.into_raw(),
+            };
+            self.root = self.meld(self.root, link);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Get the thread with the smallest key, and the key.
+    pub fn peek(&self) -> Option<(ThreadId, u64)> {
+        if self.root.is_null() {
+            return None;
+        }
+
+        // Safety: the root is queued here
+        unsafe {
+            let root = &*links(self.root);
+            root.thread.map(|id| (id, root.key))
+        }
+    }
+
+    /// Remove and return the thread with the smallest key, and the key.
+    pub fn pop(&mut self) -> Option<(Thread, u64)> {
+        let root = self.root;
+        if root.is_null() {
+            return None;
+        }
+
+        // Safety: the root is queued here
+        unsafe {
+            self.root = self.merge_pairs((*links(root)).child);
+            Some(self.release(root))
+        }
+    }
+
+    /// Lower `thread`'s key to `key`.
+    ///
+    /// Returns `false`, leaving the heap unchanged, if the thread is not in
+    /// this heap or `key` is larger than its current key.
+    pub fn decrease_key(&mut self, thread: &Thread, key: u64) -> bool {
+        let link: *const HeapLink = thread.heap_link();
+        if !self.contains_link(link) {
+            return false;
+        }
+
+        // Safety: `link` is queued here
+        unsafe {
+            if key > (*links(link)).key {
+                return false;
+            }
+            (*links(link)).key = key;
+
+            if link != self.root {
+                self.detach(link);
+                self.root = self.meld(self.root, link);
+            }
+        }
+        true
+    }
+
+    /// Remove `thread` from the heap, returning the heap's reference to it.
+    pub fn remove(&mut self, thread: &Thread) -> Option<Thread> {
+        let link: *const HeapLink = thread.heap_link();
+        if !self.contains_link(link) {
+            return None;
+        }
+
+        // Safety: `link` is queued here
+        unsafe {
+            if link == self.root {
+                return self.pop().map(|(thread, _)| thread);
+            }
+
+            self.detach(link);
+            let children = self.merge_pairs((*links(link)).child);
+            self.root = self.meld(self.root, children);
+            Some(self.release(link).0)
+        }
+    }
+
+    /// Get the key `thread` is queued under in this heap.
+    pub fn key_of(&self, thread: &Thread) -> Option<u64> {
+        let link: *const HeapLink = thread.heap_link();
+        // Safety: only read once the link is known to be queued here
+        self.contains_link(link)
+            .then(|| unsafe { (*links(link)).key })
+    }
+
+    fn contains_link(&self, link: *const HeapLink) -> bool {
+        self.id != 0 && unsafe { &*link }.heap.load(Ordering::Acquire) == self.id
+    }
+
+    /// Unlink a node that has been taken out of the tree and give back the
+    /// heap's reference to its thread.
+    ///
+    /// # Safety
+    ///
+    /// `link` must be queued here and no longer reachable from the root.
+    unsafe fn release(&mut self, link: *const HeapLink) -> (Thread, u64) {
+        unsafe {
+            let Links { key, owner, .. } = core::mem::replace(&mut *links(link), Links {
+                key: 0,
+                child: ptr::null(),
+                next: ptr::null(),
+                prev: ptr::null(),
+                owner: ptr::null(),
+                thread: None,
+            });
+            (*link).heap.store(0, Ordering::Release);
+            self.len -= 1;
+            (Thread::from_raw(owner), key)
+        }
+    }
+
+    /// Make the root with the larger key the first child of the other.
+    ///
+    /// # Safety
+    ///
+    /// Both nodes must be null or detached roots queued here.
+    unsafe fn meld(&self, a: *const HeapLink, b: *const HeapLink) -> *const HeapLink {
+        if a.is_null() {
+            return b;
+        }
+        if b.is_null() {
+            return a;
+        }
+
+        unsafe {
+            let (parent, child) = if (*links(b)).key < (*links(a)).key { (b, a) } else { (a, b) };
+            let first = (*links(parent)).child;
+
+            (*links(child)).prev = parent;
+            (*links(child)).next = first;
+            if !first.is_null() {
+                (*links(first)).prev = child;
+            }
+            (*links(parent)).child = child;
+            parent
+        }
+    }
+
+    /// Cut a non-root node, with its subtree, out of its parent's children.
+    ///
+    /// # Safety
+    ///
+    /// `link` must be queued here and not be the root.
+    unsafe fn detach(&self, link: *const HeapLink) {
+        unsafe {
+            let prev = (*links(link)).prev;
+            let next = (*links(link)).next;
+
+            if (*links(prev)).child == link {
+                (*links(prev)).child = next;
+            } else {
+                (*links(prev)).next = next;
+            }
+            if !next.is_null() {
+                (*links(next)).prev = prev;
+            }
+
+            (*links(link)).prev = ptr::null();
+            (*links(link)).next = ptr::null();
+        }
+    }
+
+    /// Merge a list of sibling subtrees into one with the standard two-pass
+    /// pairing: meld neighbours left to right, then fold the pairs right to
+    /// left.
+    ///
+    /// # Safety
+    ///
+    /// `first` must be null or the first of a sibling list queued here.
+    unsafe fn merge_pairs(&self, first: *const HeapLink) -> *const HeapLink {
+        unsafe {
+            // First pass; melded pairs are chained through `next` in reverse
+            let mut pairs: *const HeapLink = ptr::null();
+            let mut current = first;
+            while !current.is_null() {
+                let a = current;
+                let b = (*links(a)).next;
+                (*links(a)).next = ptr::null();
+                (*links(a)).prev = ptr::null();
+
+                let pair = if b.is_null() {
+                    current = ptr::null();
+                    a
+                } else {
+                    current = (*links(b)).next;
+                    (*links(b)).next = ptr::null();
+                    (*links(b)).prev = ptr::null();
+                    self.meld(a, b)
+                };
+
+                (*links(pair)).next = pairs;
+                pairs = pair;
+            }
+
+            // Second pass, starting from the last pair
+            let mut merged: *const HeapLink = ptr::null();
+            while !pairs.is_null() {
+                let next = (*links(pairs)).next;
+                (*links(pairs)).next = ptr::null();
+                merged = self.meld(merged, pairs);
+                pairs = next;
+            }
+            merged
+        }
+    }
+}
+
+impl Default for PairingHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PairingHeap {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_pairing_heap_order_and_decrease_key() {
+        let pool = StackPool::new();
+        let threads: Vec<Thread> = (0..6)
+            .map(|n| {
+                let stack = pool.allocate(StackSizeClass::Small).unwrap();
+                let id = unsafe { ThreadId::new_unchecked(43_000 + n) };
+                Thread::new(id, stack, || {}, 128).0
+            })
+            .collect();
+
+        let mut heap = PairingHeap::new();
+        for (thread, key) in threads.iter().zip([50, 10, 40, 30, 60, 20]) {
+            assert!(heap.push(thread.clone(), key).is_ok());
+        }
+        assert_eq!(heap.len(), 6);
+        assert_eq!(heap.peek(), Some((threads[1].id(), 10)));
+
+        // A queued thread cannot join a second heap
+        let mut other = PairingHeap::new();
+        assert!(other.push(threads[0].clone(), 1).is_err());
+        assert!(!other.decrease_key(&threads[0], 1));
+
+        assert!(heap.decrease_key(&threads[4], 5));
+        assert!(!heap.decrease_key(&threads[2], 45));
+        assert_eq!(heap.key_of(&threads[2]), Some(40));
+
+        let removed = heap.remove(&threads[3]).unwrap();
+        assert_eq!(removed.id(), threads[3].id());
+        assert!(!threads[3].heap_link().is_queued());
+
+        let order: Vec<(ThreadId, u64)> = core::iter::from_fn(|| heap.pop())
+            .map(|(thread, key)| (thread.id(), key))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (threads[4].id(), 5),
+                (threads[1].id(), 10),
+                (threads[5].id(), 20),
+                (threads[2].id(), 40),
+                (threads[0].id(), 50),
+            ]
+        );
+
+        // Released threads can be queued again
+        assert!(heap.push(threads[0].clone(), 7).is_ok());
+        drop(heap);
+        assert!(!threads[0].heap_link().is_queued());
+    }
+}
@@ -0,0 +1,101 @@
+//! Per-CPU load prediction.
+//!
+//! A [`CpuLoad`] keeps exponentially weighted moving averages of a CPU's
+//! run queue depth and of how often it was found running a thread. Their
+//! sum, the [predicted load](CpuLoad::predicted), is the number of threads
+//! the CPU can be expected to have on its hands, smoothed so a single
+//! burst of wakeups shows up within a few samples while one stray sample
+//! does not send threads across the machine.
+//!
+//! Values are fixed point with [`LOAD_SCALE`] units per thread.
+
+use portable_atomic::{AtomicU32, Ordering};
+
+/// Fixed-point units per thread.
+pub const LOAD_SCALE: u32 = 256;
+
+/// Each sample moves the averages `1 / 2^DECAY_SHIFT` of the way to it.
+const DECAY_SHIFT: u32 = 3;
+
+/// Deepest queue a sample counts, keeping scaled values within `u32`.
+const MAX_DEPTH: usize = 1 << 16;
+
+/// Load estimate of one CPU.
+///
+/// Only the CPU the estimate belongs to samples it; any CPU may read it.
+#[derive(Debug, Default)]
+pub struct CpuLoad {
+    /// Average queue depth
+    queue_depth: AtomicU32,
+    /// Average share of samples taken with a thread running, up to
+    /// `LOAD_SCALE`
+    busy: AtomicU32,
+}
+
+impl CpuLoad {
+    /// Create an estimate of an idle CPU.
+    pub const fn new() -> Self {
+        Self {
+            queue_depth: AtomicU32::new(0),
+            busy: AtomicU32::new(0),
+        }
+    }
+
+    /// Fold in a sample of `queue_depth` threads waiting, with or without
+    /// one `running`.
+    pub fn sample(&self, queue_depth: usize, running: bool) {
+        let depth = queue_depth.min(MAX_DEPTH) as u32 * LOAD_SCALE;
+        let busy = if running { LOAD_SCALE } else { 0 };
+        self.queue_depth.store(ewma(self.queue_depth.load(Ordering::Relaxed), depth), Ordering::Relaxed);
+        self.busy.store(ewma(self.busy.load(Ordering::Relaxed), busy), Ordering::Relaxed);
+    }
+
+    /// Get the average queue depth.
+    pub fn queue_depth(&self) -> u32 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Get the average share of time spent running a thread, out of
+    /// [`LOAD_SCALE`].
+    pub fn busy(&self) -> u32 {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// Get the expected number of threads on the CPU, running or waiting.
+    pub fn predicted(&self) -> u32 {
+        self.queue_depth() + self.busy()
+    }
+}
+
+/// Move `average` towards `sample`, rounding away from `average` so it
+/// settles on `sample` exactly.
+fn ewma(average: u32, sample: u32) -> u32 {
+    let round = (1 << DECAY_SHIFT) - 1;
+    if sample >= average {
+        average + ((sample - average + round) >> DECAY_SHIFT)
+    } else {
+        average - ((average - sample + round) >> DECAY_SHIFT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_follows_samples() {
+        let load = CpuLoad::new();
+        load.sample(8, true);
+        assert_eq!((load.queue_depth(), load.busy()), (8 * LOAD_SCALE / 8, LOAD_SCALE / 8));
+
+        for _ in 0..100 {
+            load.sample(8, true);
+        }
+        assert_eq!(load.predicted(), 9 * LOAD_SCALE);
+
+        for _ in 0..100 {
+            load.sample(0, false);
+        }
+        assert_eq!(load.predicted(), 0);
+    }
+}
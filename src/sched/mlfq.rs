@@ -0,0 +1,339 @@
+//! Multi-level feedback queue scheduling.
+//!
+//! [`MlfqScheduler`] keeps a FIFO queue per level, level 0 first, and always
+//! runs the front thread of the highest non-empty level. Threads start at
+//! level 0 and their level follows their behaviour rather than a static
+//! priority:
+//!
+//! - a thread that uses up its time slice is demoted one level, and lower
+//!   levels get longer slices (twice the one above), so CPU-bound work
+//!   sinks and runs in long, rare bursts
+//! - a thread that blocks, typically on I/O or an input event, is promoted
+//!   one level, so interactive threads stay near the top and respond quickly
+//! - every boost interval all threads are moved back to level 0, so sunk
+//!   threads can't starve behind a steady stream of interactive ones
+//!
+//! This sits between [`RoundRobinScheduler`](super::RoundRobinScheduler),
+//! which never adapts, and fair-share schedulers that track every thread's
+//! runtime, and suits interactive embedded UIs. The queues are shared by all
+//! CPUs and thread priorities are ignored.
+//!
+//! ```ignore
+//! let config = MlfqConfig::new(4)
+//!     .base_quantum(Duration::from_millis(2))
+//!     .boost_interval(Duration::from_millis(200));
+//! let kernel = Kernel::<DefaultArch, _>::new(MlfqScheduler::new(config));
+//! ```
+
+use super::trait_def::{CpuId, RunQueueEntry, Scheduler};
+use crate::sync::{IrqSafe, SpinLockIrqSave};
+use crate::thread_new::{ReadyRef, RunningRef, ThreadId};
+use crate::time::{Duration, Instant};
+extern crate alloc;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Configuration of an [`MlfqScheduler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MlfqConfig {
+    levels: usize,
+    base_quantum: Duration,
+    boost_interval: Duration,
+}
+
+impl MlfqConfig {
+    /// Use `levels` queues (at least one), with a 1ms slice at level 0 and
+    /// a boost every 100ms.
+    pub fn new(levels: usize) -> Self {
+        Self {
+            levels: levels.max(1),
+            base_quantum: Duration::from_millis(1),
+            boost_interval: Duration::from_millis(100),
+        }
+    }
+
+    /// Set the time slice of level 0; each level below gets twice the
+    /// slice of the one above.
+    pub fn base_quantum(mut self, quantum: Duration) -> Self {
+        self.base_quantum = quantum;
+        self
+    }
+
+    /// Set how often all threads are moved back to level 0.
+    pub fn boost_interval(mut self, interval: Duration) -> Self {
+        self.boost_interval = interval;
+        self
+    }
+
+    /// Get the number of levels.
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    /// Get the time slice of threads at `level`.
+    pub fn quantum(&self, level: usize) -> Duration {
+        let shift = level.min(self.levels - 1).min(16) as u32;
+        Duration::from_nanos(self.base_quantum.as_nanos().saturating_mul(1 << shift))
+    }
+}
+
+struct MlfqState {
+    /// Ready threads of every level, level 0 first
+    queues: Vec<VecDeque<ReadyRef>>,
+    /// Level of every thread below level 0
+    levels: BTreeMap<ThreadId, usize>,
+    /// Time of the last boost
+    last_boost: Instant,
+}
+
+impl MlfqState {
+    fn level_of(&self, thread_id: ThreadId) -> usize {
+        self.levels.get(&thread_id).copied().unwrap_or(0)
+    }
+
+    fn set_level(&mut self, thread_id: ThreadId, level: usize) {
+        if level == 0 {
+            self.levels.remove(&thread_id);
+        } else {
+            self.levels.insert(thread_id, level);
+        }
+    }
+
+    fn highest_queued(&self) -> Option<usize> {
+        self.queues.iter().position(|queue| !queue.is_empty())
+    }
+
+    fn queued(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// Multi-level feedback queue scheduler.
+pub struct MlfqScheduler {
+    config: MlfqConfig,
+    state: SpinLockIrqSave<MlfqState>,
+    demotions: AtomicU64,
+    promotions: AtomicU64,
+    boosts: AtomicU64,
+}
+
+// Safety: all state is atomic or behind an IRQ-saving lock
+unsafe impl IrqSafe for MlfqScheduler {}
+
+impl MlfqScheduler {
+    /// Create a scheduler with the given configuration.
+    pub fn new(config: MlfqConfig) -> Self {
+        Self {
+            config,
+            state: SpinLockIrqSave::new(MlfqState {
+                queues: (0..config.levels).map(|_| VecDeque::new()).collect(),
+                levels: BTreeMap::new(),
+                last_boost: Instant::from_nanos(0),
+            }),
+            demotions: AtomicU64::new(0),
+            promotions: AtomicU64::new(0),
+            boosts: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the configuration.
+    pub fn config(&self) -> &MlfqConfig {
+        &self.config
+    }
+
+    /// Get the level of a thread.
+    pub fn level_of(&self, thread_id: ThreadId) -> usize {
+        self.state.lock().level_of(thread_id)
+    }
+
+    /// Get the number of demotions, promotions and boosts so far.
+    pub fn feedback_stats(&self) -> (u64, u64, u64) {
+        (
+            self.demotions.load(Ordering::Relaxed),
+            self.promotions.load(Ordering::Relaxed),
+            self.boosts.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Move every thread back to level 0 if the boost interval has passed.
+    fn maybe_boost(&self, state: &mut MlfqState, now: Instant) {
+        let since = now.as_nanos().saturating_sub(state.last_boost.as_nanos());
+        if since < self.config.boost_interval.as_nanos() {
+            return;
+        }
+
+        state.last_boost = now;
+        state.levels.clear();
+        let (top, lower) = state.queues.split_at_mut(1);
+        for queue in lower {
+            top[0].append(queue);
+        }
+        self.boosts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn pick_at(&self, now: Instant) -> Option<ReadyRef> {
+        let mut state = self.state.lock();
+        self.maybe_boost(&mut state, now);
+
+        let level = state.highest_queued()?;
+        let thread = state.queues[level].pop_front()?;
+        thread.0.set_time_slice(self.config.quantum(level));
+        Some(thread)
+    }
+
+    fn tick_at(&self, current: &RunningRef, now: Instant) -> Option<ReadyRef> {
+        let mut state = self.state.lock();
+        self.maybe_boost(&mut state, now);
+
+        let thread_id = current.id();
+        let mut level = state.level_of(thread_id);
+        let waiting = state.highest_queued();
+
+        if current.time_slice().update_vruntime(now) {
+            // Used up its slice: sink a level and start a longer one
+            if level + 1 < self.config.levels {
+                level += 1;
+                state.set_level(thread_id, level);
+                self.demotions.fetch_add(1, Ordering::Relaxed);
+            }
+            current.0.set_time_slice(self.config.quantum(level));
+            current.time_slice().start_slice(now);
+            if waiting.is_some_and(|waiting| waiting <= level) {
+                return Some(current.prepare_preemption());
+            }
+        } else if waiting.is_some_and(|waiting| waiting < level) {
+            // A thread of a higher level is ready
+            return Some(current.prepare_preemption());
+        }
+
+        None
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn enqueue(&self, thread: ReadyRef) {
+        let mut state = self.state.lock();
+        let level = state.level_of(thread.id());
+        state.queues[level].push_back(thread);
+    }
+
+    fn pick_next(&self, _cpu_id: CpuId) -> Option<ReadyRef> {
+        self.pick_at(Instant::now())
+    }
+
+    fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+        self.tick_at(current, Instant::now())
+    }
+
+    fn set_priority(&self, thread_id: ThreadId, priority: u8) {
+        // Levels come from behaviour, not priority
+        let _ = (thread_id, priority);
+    }
+
+    fn on_yield(&self, current: RunningRef) {
+        // Giving up the CPU early keeps the thread's level
+        self.enqueue(current.stop_running());
+    }
+
+    fn on_block(&self, current: RunningRef) {
+        let thread_id = current.id();
+        current.block();
+
+        let mut state = self.state.lock();
+        let level = state.level_of(thread_id);
+        if level > 0 {
+            state.set_level(thread_id, level - 1);
+            self.promotions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn wake_up(&self, thread: ReadyRef) {
+        self.enqueue(thread);
+    }
+
+    fn stats(&self) -> (usize, usize, usize) {
+        let queued = self.state.lock().queued();
+        (queued, queued, 0)
+    }
+
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        let state = self.state.lock();
+        state
+            .queues
+            .iter()
+            .flatten()
+            .map(|thread| RunQueueEntry {
+                thread_id: thread.id(),
+                priority: thread.priority(),
+                cpu: None,
+                ready_since: thread.ready_since(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::thread_new::Thread;
+
+    fn thread(pool: &StackPool, id: usize) -> ReadyRef {
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        ReadyRef(Thread::new(unsafe { ThreadId::new_unchecked(id) }, stack, || {}, 128).0)
+    }
+
+    fn ms(millis: u64) -> Instant {
+        Instant::from_nanos(millis * 1_000_000)
+    }
+
+    #[test]
+    fn test_mlfq_demotes_promotes_and_boosts() {
+        let pool = StackPool::new();
+        let config = MlfqConfig::new(3)
+            .base_quantum(Duration::from_millis(1))
+            .boost_interval(Duration::from_millis(100));
+        let scheduler = MlfqScheduler::new(config);
+        assert_eq!(config.quantum(2), Duration::from_millis(4));
+
+        let (cpu_hog, ui) = (thread(&pool, 49_250), thread(&pool, 49_251));
+        let (hog_id, ui_id) = (cpu_hog.id(), ui.id());
+        scheduler.enqueue(cpu_hog);
+        scheduler.enqueue(ui);
+
+        // The hog uses up its slice and sinks below the UI thread
+        let running = scheduler.pick_at(ms(1)).unwrap().start_running();
+        assert_eq!(running.id(), hog_id);
+        running.time_slice().start_slice(ms(1));
+        assert!(scheduler.tick_at(&running, ms(1)).is_none());
+        let preempted = scheduler.tick_at(&running, ms(3)).unwrap();
+        assert_eq!(scheduler.level_of(hog_id), 1);
+        scheduler.enqueue(preempted);
+
+        // The UI thread runs first and climbs back up when it blocks
+        let running = scheduler.pick_at(ms(3)).unwrap().start_running();
+        assert_eq!(running.id(), ui_id);
+        running.time_slice().start_slice(ms(3));
+        scheduler.enqueue(scheduler.tick_at(&running, ms(5)).unwrap());
+        assert_eq!(scheduler.level_of(ui_id), 1);
+        let running = scheduler.pick_at(ms(5)).unwrap().start_running();
+        assert_eq!(running.id(), hog_id);
+        running.time_slice().start_slice(ms(5));
+        scheduler.enqueue(scheduler.tick_at(&running, ms(8)).unwrap());
+        assert_eq!(scheduler.level_of(hog_id), 2);
+
+        let running = scheduler.pick_at(ms(8)).unwrap().start_running();
+        assert_eq!(running.id(), ui_id);
+        scheduler.on_block(running);
+        assert_eq!(scheduler.level_of(ui_id), 0);
+
+        // The boost lifts the hog back to the top
+        let boosted = scheduler.pick_at(ms(150)).unwrap();
+        assert_eq!(boosted.id(), hog_id);
+        assert_eq!(scheduler.level_of(hog_id), 0);
+        assert_eq!(scheduler.feedback_stats(), (3, 1, 1));
+    }
+}
@@ -5,11 +5,35 @@
 
 pub mod trait_def;
 pub mod rr;
+pub mod bandwidth;
+pub mod cooperative;
+pub mod cpuset;
+pub mod dispatch;
+pub mod global;
+pub mod heap;
+pub mod load;
+pub mod mlfq;
+pub mod mpsc;
+pub mod smt;
+pub mod stride;
+pub mod partition;
+pub mod tuning;
 #[cfg(feature = "work-stealing")]
 pub mod worksteal;
 
-pub use trait_def::{Scheduler, CpuId, priority};
+pub use trait_def::{Scheduler, CpuId, RunQueueEntry, priority};
 pub use rr::RoundRobinScheduler;
+pub use bandwidth::{BandwidthController, BandwidthError, GroupBandwidthStats, CPU_BANDWIDTH};
+pub use cooperative::CooperativeScheduler;
+pub use cpuset::{AtomicCpuSet, CpuSet};
+pub use global::{global_scheduler, set_global_scheduler};
+pub use heap::{HeapLink, PairingHeap};
+pub use load::{CpuLoad, LOAD_SCALE};
+pub use mlfq::{MlfqConfig, MlfqScheduler};
+pub use mpsc::{MpscLink, MpscQueue};
+pub use partition::{PartitionConfig, PartitionError, PartitionedScheduler};
+pub use smt::{Occupancy, SmtPolicy, SMT_POLICY};
+pub use stride::StrideScheduler;
 
 #[cfg(feature = "work-stealing")]
 pub use worksteal::WorkStealingScheduler;
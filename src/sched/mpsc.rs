@@ -0,0 +1,412 @@
+//! Intrusive lock-free MPSC queue of threads.
+//!
+//! Waking a thread that belongs to another CPU's run queue must not take a
+//! spinlock or allocate, because the waker may be an interrupt handler. The
+//! schedulers give every CPU an [`MpscQueue`] that remote wakers push onto
+//! and that the owning CPU drains into its run queue in `pick_next`.
+//!
+//! The queue is Dmitry Vyukov's intrusive MPSC queue. Producers link their
+//! node with one atomic swap, so `push` is wait-free; the consumer side is
+//! lock-free. Nodes are the [`MpscLink`] embedded in every thread, and a
+//! thread can be in at most one such queue at a time.
+//!
+//! `pop` can report an empty queue while a producer is between its two
+//! stores; the pushed thread is returned by a later `pop`.
+//!
+//! The algorithm is model-checked with [loom](https://docs.rs/loom):
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --lib --release sched::mpsc
+//! ```
+
+use crate::thread_new::Thread;
+extern crate alloc;
+use alloc::boxed::Box;
+use core::ptr;
+
+#[cfg(all(test, loom))]
+use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+#[cfg(not(all(test, loom)))]
+use portable_atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// Queue node embedded in every thread.
+pub struct MpscLink {
+    next: AtomicPtr<MpscLink>,
+    /// Reference held by the queue, from `Thread::into_raw`
+    owner: AtomicPtr<()>,
+    /// Set while the thread is in a queue
+    queued: AtomicBool,
+}
+
+impl MpscLink {
+    /// Create an unqueued node.
+    pub fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            owner: AtomicPtr::new(ptr::null_mut()),
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    /// Check whether the thread is in an MPSC queue.
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+impl Default for MpscLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The Vyukov queue over bare nodes.
+struct RawQueue {
+    /// Most recently pushed node; producers swap themselves in here
+    head: AtomicPtr<MpscLink>,
+    /// Oldest node; only touched by the consumer
+    tail: AtomicPtr<MpscLink>,
+    /// Placeholder that keeps the list non-empty
+    stub: Box<MpscLink>,
+}
+
+impl RawQueue {
+    fn new() -> Self {
+        let stub = Box::new(MpscLink::new());
+        let stub_ptr = &*stub as *const MpscLink as *mut MpscLink;
+        Self {
+            head: AtomicPtr::new(stub_ptr),
+            tail: AtomicPtr::new(stub_ptr),
+            stub,
+        }
+    }
+
+    fn stub(&self) -> *mut MpscLink {
+        &*self.stub as *const MpscLink as *mut MpscLink
+    }
+
+    /// Append `link`.
+    ///
+    /// # Safety
+    ///
+    /// `link` must stay valid and not be pushed again until it is popped.
+    unsafe fn push(&self, link: *mut MpscLink) {
+        unsafe {
+            (*link).next.store(ptr::null_mut(), Ordering::Relaxed);
+            let prev = self.head.swap(link, Ordering::AcqRel);
+            // Until this store the consumer cannot see `link` or anything
+            // pushed after it
+            crate::handoff_store!((*prev).next, link, Ordering::Release);
+        }
+    }
+
+    /// Remove the oldest node.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may pop at a time.
+    unsafe fn pop(&self) -> Option<*mut MpscLink> {
+        let stub = self.stub();
+
+        // Safety: every node reachable from `tail` is valid until popped
+        unsafe {
+            let mut tail = self.tail.load(Ordering::Relaxed);
+            let mut next = crate::handoff_load!((*tail).next, Ordering::Acquire);
+
+            if tail == stub {
+                if next.is_null() {
+                    return None;
+                }
+                self.tail.store(next, Ordering::Relaxed);
+                tail = next;
+                next = crate::handoff_load!((*next).next, Ordering::Acquire);
+            }
+
+            if !next.is_null() {
+                self.tail.store(next, Ordering::Relaxed);
+                return Some(tail);
+            }
+
+            // `tail` is the last linked node. If it is not also the head, a
+            // producer has swapped in a newer node but not linked it yet.
+            if tail != self.head.load(Ordering::Acquire) {
+                return None;
+            }
+
+            // Re-insert the stub behind `tail` so `tail` can be detached
+            self.push(stub);
+            next = crate::handoff_load!((*tail).next, Ordering::Acquire);
+            if !next.is_null() {
+                self.tail.store(next, Ordering::Relaxed);
+                return Some(tail);
+            }
+            None
+        }
+    }
+}
+
+/// Lock-free multi-producer, single-consumer queue of threads.
+pub struct MpscQueue {
+    raw: RawQueue,
+    /// Set while a consumer is in `pop`
+    consuming: AtomicBool,
+    len: AtomicUsize,
+}
+
+// Safety: the queue owns references to its threads, which are `Send`;
+// `consuming` keeps the consumer side to one thread at a time
+unsafe impl Send for MpscQueue {}
+unsafe impl Sync for MpscQueue {}
+
+impl MpscQueue {
+    /// Create an empty queue.
+    pub fn new() -> Self {
+        Self {
+            raw: RawQueue::new(),
+            consuming: AtomicBool::new(false),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the number of queued threads.
+    ///
+    /// Includes pushes still in progress.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Check whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append `thread`. Safe to call from any CPU and from interrupt
+    /// context.
+    ///
+    /// Hands the thread back if it is already in an MPSC queue.
+    pub fn push(&self, thread: Thread) -> Result<(), Thread> {
+        let link = thread.mpsc_link();
+        if link
+            .queued
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(thread);
+        }
+
+        let link = link as *const MpscLink as *mut MpscLink;
+        thread.set_run_queue(self as *const Self as usize);
+        self.len.fetch_add(1, Ordering::AcqRel);
+        // Safety: claimed above; the queue's reference in `owner` keeps the
+        // thread, and so `link`, alive until it is popped
+        unsafe {
+            (*link).owner.store(thread.into_raw() as *mut (), Ordering::Relaxed);
+            self.raw.push(link);
+        }
+        Ok(())
+    }
+
+    /// Remove the oldest thread.
+    ///
+    /// Intended for the CPU that owns the queue. Returns `None` if another
+    /// consumer is already popping.
+    pub fn pop(&self) -> Option<Thread> {
+        if self.consuming.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        // Safety: `consuming` makes this the only consumer
+        let link = unsafe { self.raw.pop() };
+        self.consuming.store(false, Ordering::Release);
+
+        let link = link?;
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        // Safety: the node was popped, so the queue no longer touches it and
+        // its `owner` is the reference stored by `push`
+        unsafe {
+            let owner = (*link).owner.swap(ptr::null_mut(), Ordering::Relaxed);
+            (*link).queued.store(false, Ordering::Release);
+            let thread = Thread::from_raw(owner);
+            thread.set_run_queue(0);
+            Some(thread)
+        }
+    }
+}
+
+impl Default for MpscQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MpscQueue {
+    fn drop(&mut self) {
+        // With `&mut self` no push is in progress, so this drains everything
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(all(test, feature = "std-shim", not(loom)))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::thread_new::ThreadId;
+    use alloc::vec::Vec;
+    use std::sync::Arc;
+
+    fn threads(base: usize, count: usize) -> Vec<Thread> {
+        let pool = StackPool::new();
+        (0..count)
+            .map(|n| {
+                let stack = pool.allocate(StackSizeClass::Small).unwrap();
+                let id = unsafe { ThreadId::new_unchecked(base + n) };
+                Thread::new(id, stack, || {}, 128).0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mpsc_fifo() {
+        let threads = threads(44_000, 3);
+        let queue = MpscQueue::new();
+        assert!(queue.pop().is_none());
+
+        for thread in &threads {
+            assert!(queue.push(thread.clone()).is_ok());
+        }
+        // A queued thread cannot be pushed twice
+        assert!(queue.push(threads[0].clone()).is_err());
+        assert_eq!(queue.len(), 3);
+
+        let order: Vec<ThreadId> = core::iter::from_fn(|| queue.pop()).map(|t| t.id()).collect();
+        assert_eq!(order, threads.iter().map(|t| t.id()).collect::<Vec<_>>());
+        assert!(!threads[0].mpsc_link().is_queued());
+
+        // Dropping the queue releases what is still queued
+        assert!(queue.push(threads[1].clone()).is_ok());
+        drop(queue);
+        assert!(!threads[1].mpsc_link().is_queued());
+    }
+
+    #[test]
+    fn test_mpsc_stress() {
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 64;
+        const ROUNDS: usize = 50;
+
+        let queue = Arc::new(MpscQueue::new());
+        let batches: Vec<Vec<Thread>> = (0..PRODUCERS)
+            .map(|p| threads(44_100 + p * PER_PRODUCER, PER_PRODUCER))
+            .collect();
+
+        for _ in 0..ROUNDS {
+            let producers: Vec<_> = batches
+                .iter()
+                .cloned()
+                .map(|batch| {
+                    let queue = queue.clone();
+                    std::thread::spawn(move || {
+                        for thread in batch {
+                            assert!(queue.push(thread).is_ok());
+                        }
+                    })
+                })
+                .collect();
+
+            // Per producer, threads come out in push order
+            let mut last = [None::<ThreadId>; PRODUCERS];
+            let mut popped = 0;
+            while popped < PRODUCERS * PER_PRODUCER {
+                let Some(thread) = queue.pop() else {
+                    std::thread::yield_now();
+                    continue;
+                };
+                let producer = (thread.id().as_u64() as usize - 44_100) / PER_PRODUCER;
+                assert!(last[producer].map_or(true, |prev| prev < thread.id()));
+                last[producer] = Some(thread.id());
+                popped += 1;
+            }
+
+            for producer in producers {
+                producer.join().unwrap();
+            }
+            assert!(queue.is_empty());
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    /// Node with the link first, so link pointers identify the node.
+    #[repr(C)]
+    struct Node {
+        link: MpscLink,
+        value: usize,
+    }
+
+    fn node(value: usize) -> *mut MpscLink {
+        Box::into_raw(Box::new(Node { link: MpscLink::new(), value })) as *mut MpscLink
+    }
+
+    fn pop(queue: &RawQueue) -> Option<usize> {
+        unsafe { queue.pop() }.map(|link| unsafe { Box::from_raw(link as *mut Node) }.value)
+    }
+
+    /// Pop up to `attempts` times while producers run, then join them and
+    /// drain the rest, which must not miss anything once pushes are done.
+    fn consume(queue: &RawQueue, attempts: usize, producers: Vec<thread::JoinHandle<()>>) -> Vec<usize> {
+        let mut values: Vec<usize> = (0..attempts).filter_map(|_| pop(queue)).collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        values.extend(core::iter::from_fn(|| pop(queue)));
+        values
+    }
+
+    #[test]
+    fn loom_two_producers() {
+        loom::model(|| {
+            let queue = Arc::new(RawQueue::new());
+
+            let producers = [[1, 2], [3, 4]]
+                .into_iter()
+                .map(|values| {
+                    let queue = queue.clone();
+                    thread::spawn(move || {
+                        for value in values {
+                            unsafe { queue.push(node(value)) };
+                        }
+                    })
+                })
+                .collect();
+
+            // Everything arrives once, in order per producer
+            let values = consume(&queue, 2, producers);
+            assert_eq!(values.len(), 4);
+            let position = |v| values.iter().position(|&x| x == v).unwrap();
+            assert!(position(1) < position(2));
+            assert!(position(3) < position(4));
+        });
+    }
+
+    #[test]
+    fn loom_push_during_pop() {
+        loom::model(|| {
+            let queue = Arc::new(RawQueue::new());
+            unsafe { queue.push(node(1)) };
+
+            let producer = {
+                let queue = queue.clone();
+                thread::spawn(move || unsafe { queue.push(node(2)) })
+            };
+
+            // Popping the last node races with the second push and the
+            // stub re-insertion
+            assert_eq!(consume(&queue, 2, alloc::vec![producer]), [1, 2]);
+        });
+    }
+}
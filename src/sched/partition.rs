@@ -0,0 +1,387 @@
+//! Static CPU partitioning.
+//!
+//! A [`PartitionedScheduler`] splits the CPUs into named partitions, for
+//! example `"rt"` on CPUs 0-1 and `"best-effort"` on CPUs 2-3. Each
+//! partition has its own scheduler instance and, optionally, a CPU time
+//! quota shared by everything running in it. Thread groups are assigned to
+//! partitions and their threads only ever run on the partition's CPUs, so
+//! a misbehaving tenant can't take CPU time from another one. This is the
+//! usual arrangement for mixed-criticality systems.
+//!
+//! Threads of groups that were not assigned anywhere run in the first
+//! partition. Partition schedulers number their CPUs from zero: CPU 2 of a
+//! partition on CPUs 2-3 is CPU 0 to its scheduler.
+//!
+//! ```ignore
+//! let mut partitions = PartitionedScheduler::new();
+//! partitions.add(PartitionConfig::new("rt", 0b0011), Box::new(RoundRobinScheduler::new(2)))?;
+//! partitions.add(
+//!     PartitionConfig::new("best-effort", 0b1100).quota(Duration::from_millis(50), Duration::from_millis(100)),
+//!     Box::new(RoundRobinScheduler::new(2)),
+//! )?;
+//! partitions.assign_group(CONTROL_GROUP, "rt")?;
+//! ```
+
+use super::bandwidth::{BandwidthController, GroupBandwidthStats};
+use super::cpuset::CpuSet;
+use super::trait_def::{CpuId, RunQueueEntry, Scheduler};
+use crate::arch::percpu::MAX_CPUS;
+use crate::sync::{IrqSafe, SpinLockIrqSave};
+use crate::thread_new::{ReadyRef, RunningRef, ThreadId};
+use crate::time::tick::GLOBAL_TICK_COUNTER;
+use crate::time::Duration;
+extern crate alloc;
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+
+/// Errors from configuring partitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionError {
+    /// The partition has no CPUs
+    NoCpus,
+    /// The CPU is beyond [`MAX_CPUS`] or already in another partition
+    CpuUnavailable(CpuId),
+    /// A partition with this name already exists
+    DuplicateName(String),
+    /// No partition has this name
+    UnknownPartition(String),
+    /// Quota and period must both be non-zero
+    ZeroDuration,
+}
+
+/// Configuration of one partition.
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    name: String,
+    cpus: CpuSet,
+    quota: Option<(Duration, Duration)>,
+}
+
+impl PartitionConfig {
+    /// Create a partition named `name` on the CPUs in `cpus`.
+    pub fn new(name: &str, cpus: impl Into<CpuSet>) -> Self {
+        Self {
+            name: String::from(name),
+            cpus: cpus.into(),
+            quota: None,
+        }
+    }
+
+    /// Limit the partition to `quota` of CPU time every `period`, summed
+    /// over all of its CPUs.
+    pub fn quota(mut self, quota: Duration, period: Duration) -> Self {
+        self.quota = Some((quota, period));
+        self
+    }
+
+    /// Get the partition name.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the CPUs in the partition.
+    pub fn get_cpus(&self) -> CpuSet {
+        self.cpus
+    }
+}
+
+/// Bandwidth controller key for a partition's whole quota.
+const QUOTA_GROUP: u32 = 0;
+
+struct Partition {
+    config: PartitionConfig,
+    scheduler: Box<dyn Scheduler>,
+    /// Enforces the partition quota, if one is set
+    bandwidth: BandwidthController,
+}
+
+impl Partition {
+    /// Index of global CPU `cpu` within the partition.
+    fn local_cpu(&self, cpu: CpuId) -> CpuId {
+        self.config.cpus.rank(cpu)
+    }
+
+    /// Global CPU number of the partition's CPU `local`.
+    fn global_cpu(&self, local: CpuId) -> Option<CpuId> {
+        self.config.cpus.nth(local)
+    }
+
+    fn has_quota(&self) -> bool {
+        self.config.quota.is_some()
+    }
+}
+
+/// Scheduler dispatching to one scheduler instance per CPU partition.
+pub struct PartitionedScheduler {
+    partitions: Vec<Partition>,
+    /// Partition index of every CPU, `u8::MAX` for CPUs in none
+    cpu_partition: [u8; MAX_CPUS],
+    /// Partition index of every assigned thread group
+    groups: SpinLockIrqSave<BTreeMap<u32, usize>>,
+}
+
+// Safety: partition schedulers are `Scheduler`s, so IRQ safe themselves,
+// and the group map is behind an IRQ-saving lock.
+unsafe impl IrqSafe for PartitionedScheduler {}
+
+impl PartitionedScheduler {
+    /// Create a scheduler with no partitions.
+    ///
+    /// At least one partition must be [added](Self::add) before threads are
+    /// enqueued.
+    pub fn new() -> Self {
+        Self {
+            partitions: Vec::new(),
+            cpu_partition: [u8::MAX; MAX_CPUS],
+            groups: SpinLockIrqSave::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add a partition scheduled by `scheduler`.
+    ///
+    /// `scheduler` must have been created for as many CPUs as the partition
+    /// has.
+    pub fn add(&mut self, config: PartitionConfig, scheduler: Box<dyn Scheduler>) -> Result<(), PartitionError> {
+        if config.cpus.is_empty() {
+            return Err(PartitionError::NoCpus);
+        }
+        if let Some(cpu) = config.cpus.iter().find(|&cpu| cpu >= MAX_CPUS || self.cpu_partition[cpu] != u8::MAX) {
+            return Err(PartitionError::CpuUnavailable(cpu));
+        }
+        if self.index(&config.name).is_ok() {
+            return Err(PartitionError::DuplicateName(config.name));
+        }
+
+        let bandwidth = BandwidthController::new();
+        if let Some((quota, period)) = config.quota {
+            bandwidth
+                .set_bandwidth(QUOTA_GROUP, quota, period, GLOBAL_TICK_COUNTER.now())
+                .map_err(|_| PartitionError::ZeroDuration)?;
+        }
+
+        let index = self.partitions.len() as u8;
+        for cpu in config.cpus.iter() {
+            self.cpu_partition[cpu] = index;
+        }
+        self.partitions.push(Partition {
+            config,
+            scheduler,
+            bandwidth,
+        });
+        Ok(())
+    }
+
+    /// Run the threads of `group` in the partition named `partition`.
+    ///
+    /// Threads already queued stay where they are until they next block
+    /// or are preempted.
+    pub fn assign_group(&self, group: u32, partition: &str) -> Result<(), PartitionError> {
+        let index = self.index(partition)?;
+        self.groups.lock().insert(group, index);
+        Ok(())
+    }
+
+    /// Get the name of the partition running the threads of `group`.
+    pub fn partition_of_group(&self, group: u32) -> Option<&str> {
+        let index = self.groups.lock().get(&group).copied().unwrap_or(0);
+        self.partitions.get(index).map(|partition| partition.config.get_name())
+    }
+
+    /// Get the name of the partition `cpu` belongs to.
+    pub fn partition_of_cpu(&self, cpu: CpuId) -> Option<&str> {
+        let index = *self.cpu_partition.get(cpu)? as usize;
+        self.partitions.get(index).map(|partition| partition.config.get_name())
+    }
+
+    /// Get the quota usage of the partition named `partition`, if it has a
+    /// quota.
+    pub fn quota_stats(&self, partition: &str) -> Option<GroupBandwidthStats> {
+        let index = self.index(partition).ok()?;
+        self.partitions[index].bandwidth.stats(QUOTA_GROUP)
+    }
+
+    fn index(&self, name: &str) -> Result<usize, PartitionError> {
+        self.partitions
+            .iter()
+            .position(|partition| partition.config.name == name)
+            .ok_or_else(|| PartitionError::UnknownPartition(String::from(name)))
+    }
+
+    /// Partition `thread` runs in.
+    fn partition_for(&self, thread: &ReadyRef) -> &Partition {
+        let index = self.groups.lock().get(&thread.0.group_id()).copied().unwrap_or(0);
+        &self.partitions[index]
+    }
+
+    fn partition_of(&self, cpu: CpuId) -> Option<&Partition> {
+        self.partitions.get(*self.cpu_partition.get(cpu)? as usize)
+    }
+}
+
+impl Default for PartitionedScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for PartitionedScheduler {
+    fn enqueue(&self, thread: ReadyRef) {
+        self.partition_for(&thread).scheduler.enqueue(thread);
+    }
+
+    fn pick_next(&self, cpu_id: CpuId) -> Option<ReadyRef> {
+        let partition = self.partition_of(cpu_id)?;
+        if partition.has_quota() {
+            partition.bandwidth.refill(GLOBAL_TICK_COUNTER.now(), partition.scheduler.as_ref());
+            if partition.bandwidth.is_throttled(QUOTA_GROUP) {
+                return None;
+            }
+        }
+        partition.scheduler.pick_next(partition.local_cpu(cpu_id))
+    }
+
+    fn has_higher_priority_work(&self, cpu_id: CpuId, priority: u8) -> bool {
+        self.partition_of(cpu_id).is_some_and(|partition| {
+            partition.scheduler.has_higher_priority_work(partition.local_cpu(cpu_id), priority)
+        })
+    }
+
+    fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+        let partition = self.partition_of(crate::arch::percpu::cpu_id())?;
+        if partition.has_quota() {
+            let now = GLOBAL_TICK_COUNTER.now();
+            let tick = Duration::from_nanos(GLOBAL_TICK_COUNTER.ticks_to_nanos(1));
+            partition.bandwidth.refill(now, partition.scheduler.as_ref());
+            if partition.bandwidth.charge(QUOTA_GROUP, tick, now) {
+                // Out of quota: the partition's CPUs idle until it refills
+                return Some(current.prepare_preemption());
+            }
+        }
+        partition.scheduler.on_tick(current)
+    }
+
+    fn set_priority(&self, thread_id: ThreadId, priority: u8) {
+        for partition in &self.partitions {
+            partition.scheduler.set_priority(thread_id, priority);
+        }
+    }
+
+    fn on_yield(&self, current: RunningRef) {
+        let ready = current.stop_running();
+        self.enqueue(ready);
+    }
+
+    fn on_block(&self, current: RunningRef) {
+        let partition = self.partition_for(&ReadyRef(current.0.clone()));
+        partition.scheduler.on_block(current);
+    }
+
+    fn wake_up(&self, thread: ReadyRef) {
+        self.partition_for(&thread).scheduler.wake_up(thread);
+    }
+
+    fn is_preemptive(&self) -> bool {
+        self.partitions.iter().any(|partition| partition.scheduler.is_preemptive())
+    }
+
+    fn stats(&self) -> (usize, usize, usize) {
+        self.partitions.iter().fold((0, 0, 0), |(total, runnable, blocked), partition| {
+            let (t, r, b) = partition.scheduler.stats();
+            (total + t, runnable + r, blocked + b)
+        })
+    }
+
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        let mut entries = Vec::new();
+        for partition in &self.partitions {
+            entries.extend(partition.scheduler.run_queue_snapshot().into_iter().map(|mut entry| {
+                entry.cpu = entry.cpu.and_then(|local| partition.global_cpu(local));
+                entry
+            }));
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sched::RoundRobinScheduler;
+
+    fn round_robin(cpus: usize) -> Box<dyn Scheduler> {
+        Box::new(RoundRobinScheduler::new(cpus))
+    }
+
+    #[test]
+    fn test_partition_configuration() {
+        let mut partitions = PartitionedScheduler::new();
+        partitions.add(PartitionConfig::new("rt", 0b0011), round_robin(2)).unwrap();
+        partitions
+            .add(
+                PartitionConfig::new("best-effort", 0b1100).quota(Duration::from_millis(5), Duration::from_millis(10)),
+                round_robin(2),
+            )
+            .unwrap();
+
+        assert_eq!(
+            partitions.add(PartitionConfig::new("overlap", 0b0110), round_robin(2)),
+            Err(PartitionError::CpuUnavailable(1))
+        );
+        assert_eq!(
+            partitions.add(PartitionConfig::new("rt", 0b10000), round_robin(1)),
+            Err(PartitionError::DuplicateName(String::from("rt")))
+        );
+        assert_eq!(partitions.add(PartitionConfig::new("none", 0), round_robin(1)), Err(PartitionError::NoCpus));
+
+        assert_eq!(partitions.partition_of_cpu(1), Some("rt"));
+        assert_eq!(partitions.partition_of_cpu(3), Some("best-effort"));
+        assert_eq!(partitions.partition_of_cpu(4), None);
+        assert_eq!(partitions.partitions[1].local_cpu(3), 1);
+        assert_eq!(partitions.partitions[1].global_cpu(1), Some(3));
+
+        // Unassigned groups run in the first partition
+        assert_eq!(partitions.partition_of_group(9), Some("rt"));
+        partitions.assign_group(9, "best-effort").unwrap();
+        assert_eq!(partitions.partition_of_group(9), Some("best-effort"));
+        assert_eq!(
+            partitions.assign_group(9, "batch"),
+            Err(PartitionError::UnknownPartition(String::from("batch")))
+        );
+        assert!(partitions.quota_stats("rt").is_none());
+        assert_eq!(partitions.quota_stats("best-effort").unwrap().quota_ns, 5_000_000);
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_groups_run_on_their_partition() {
+        use crate::mem::{StackPool, StackSizeClass};
+        use crate::thread_new::Thread;
+
+        let pool = StackPool::new();
+        let thread = |id: usize, group: u32| {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            let id = unsafe { ThreadId::new_unchecked(id) };
+            let thread = Thread::new(id, stack, || {}, 128).0;
+            thread.set_group_id(group);
+            thread
+        };
+
+        let mut partitions = PartitionedScheduler::new();
+        partitions.add(PartitionConfig::new("rt", 0b01), round_robin(1)).unwrap();
+        partitions.add(PartitionConfig::new("best-effort", 0b10), round_robin(1)).unwrap();
+        partitions.assign_group(2, "best-effort").unwrap();
+
+        let control = thread(49_201, 1);
+        let batch = thread(49_202, 2);
+        partitions.enqueue(ReadyRef(batch.clone()));
+        partitions.enqueue(ReadyRef(control.clone()));
+
+        let entries = partitions.run_queue_snapshot();
+        assert!(entries.iter().any(|entry| entry.thread_id == batch.id() && entry.cpu == Some(1)));
+
+        // Each CPU only sees its own partition's threads
+        assert_eq!(partitions.pick_next(0).map(|next| next.0.id()), Some(control.id()));
+        assert!(partitions.pick_next(0).is_none());
+        assert_eq!(partitions.pick_next(1).map(|next| next.0.id()), Some(batch.id()));
+        assert!(partitions.pick_next(2).is_none());
+    }
+}
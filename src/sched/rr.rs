@@ -1,12 +1,18 @@
 //! Round-robin scheduler implementation with lock-free queues.
 
-use super::trait_def::{Scheduler, CpuId};
+use super::trait_def::{Scheduler, CpuId, RunQueueEntry};
+use super::mpsc::MpscQueue;
+use super::smt::SMT_POLICY;
+use crate::mem::AtomicTaggedPtr;
+use crate::sync::IrqSafe;
 use crate::thread_new::{ReadyRef, RunningRef, ThreadId};
 use crate::observability::metrics::GLOBAL_METRICS;
+use crate::observability::trace::tracepoint;
 use portable_atomic::{AtomicUsize, AtomicPtr, Ordering};
+use core::mem::MaybeUninit;
 use core::ptr;
 extern crate alloc;
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 
 /// Lock-free round-robin scheduler.
 ///
@@ -33,6 +39,9 @@ struct CpuRunQueue {
     low_priority: LockFreeQueue,
     /// Idle priority queue (0)
     idle_priority: LockFreeQueue,
+    /// Threads woken by other CPUs or interrupts, not yet sorted into the
+    /// priority queues
+    remote: MpscQueue,
     /// Current queue position for round-robin within priority level
     current_pos: AtomicUsize,
     /// Thread count for load balancing
@@ -40,14 +49,18 @@ struct CpuRunQueue {
 }
 
 /// Lock-free MPMC queue implementation using Michael & Scott algorithm.
+///
+/// `head` and `tail` are tagged, so a node freed and reallocated at the same
+/// address cannot satisfy a stale CAS.
 struct LockFreeQueue {
-    head: AtomicPtr<QueueNode>,
-    tail: AtomicPtr<QueueNode>,
+    head: AtomicTaggedPtr<QueueNode>,
+    tail: AtomicTaggedPtr<QueueNode>,
 }
 
 /// Queue node for lock-free linked list.
 struct QueueNode {
-    thread: Option<ReadyRef>,
+    /// Moved out by the pop that turns this node into the dummy
+    thread: MaybeUninit<ReadyRef>,
     next: AtomicPtr<QueueNode>,
 }
 
@@ -126,25 +139,52 @@ impl RoundRobinScheduler {
     }
 }
 
+// Safety: all run queue operations are lock-free
+unsafe impl IrqSafe for RoundRobinScheduler {}
+
 impl Scheduler for RoundRobinScheduler {
     fn enqueue(&self, thread: ReadyRef) {
-        let priority = thread.priority();
-        let cpu_id = self.select_cpu();
+        let cpu_id = SMT_POLICY.place(&thread.0, self.select_cpu(), self.num_cpus);
         let queue = &self.run_queues[cpu_id];
         
-        let priority_queue = match Self::priority_level(priority) {
-            PriorityLevel::High => &queue.high_priority,
-            PriorityLevel::Normal => &queue.normal_priority,
-            PriorityLevel::Low => &queue.low_priority,
-            PriorityLevel::Idle => &queue.idle_priority,
-        };
-
-        priority_queue.push(thread);
+        queue.push_local(thread);
         queue.thread_count.fetch_add(1, Ordering::AcqRel);
         self.runnable_threads.fetch_add(1, Ordering::AcqRel);
         
         // Record scheduler decision
-        GLOBAL_METRICS.get_system_metrics().record_scheduler_decision();
+        GLOBAL_METRICS.system_counters().record_scheduler_decision();
+    }
+
+    fn enqueue_batch(&self, threads: Vec<ReadyRef>) {
+        // Balance against a snapshot of the queue lengths, counting the
+        // batch as it is placed, and publish the counts once at the end
+        let mut loads: Vec<usize> = self
+            .run_queues
+            .iter()
+            .map(|queue| queue.thread_count.load(Ordering::Acquire))
+            .collect();
+        let mut added = vec![0; self.num_cpus];
+        let total = threads.len();
+
+        for thread in threads {
+            let (least_loaded, _) = loads
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, load)| *load)
+                .unwrap_or((0, &0));
+            let cpu_id = SMT_POLICY.place(&thread.0, least_loaded, self.num_cpus);
+            self.run_queues[cpu_id].push_local(thread);
+            loads[cpu_id] += 1;
+            added[cpu_id] += 1;
+        }
+
+        for (queue, count) in self.run_queues.iter().zip(added) {
+            if count > 0 {
+                queue.thread_count.fetch_add(count, Ordering::AcqRel);
+            }
+        }
+        self.runnable_threads.fetch_add(total, Ordering::AcqRel);
+        GLOBAL_METRICS.system_counters().record_scheduler_decisions(total as u64);
     }
 
     fn pick_next(&self, cpu_id: CpuId) -> Option<ReadyRef> {
@@ -153,6 +193,7 @@ impl Scheduler for RoundRobinScheduler {
         }
 
         let queue = &self.run_queues[cpu_id];
+        queue.drain_remote();
 
         // Try priority queues in order: high -> normal -> low -> idle
         if let Some(thread) = queue.high_priority.try_pop() {
@@ -188,6 +229,22 @@ impl Scheduler for RoundRobinScheduler {
         None
     }
 
+    fn has_higher_priority_work(&self, cpu_id: CpuId, priority: u8) -> bool {
+        if cpu_id >= self.num_cpus {
+            return false;
+        }
+
+        let queue = &self.run_queues[cpu_id];
+        queue.drain_remote();
+        let higher: &[&LockFreeQueue] = match Self::priority_level(priority) {
+            PriorityLevel::Idle => &[&queue.low_priority, &queue.normal_priority, &queue.high_priority],
+            PriorityLevel::Low => &[&queue.normal_priority, &queue.high_priority],
+            PriorityLevel::Normal => &[&queue.high_priority],
+            PriorityLevel::High => &[],
+        };
+        higher.iter().any(|priority_queue| priority_queue.peek().is_some())
+    }
+
     fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
         // Check if the current thread's time slice is expired
         if current.time_slice().should_preempt() {
@@ -257,7 +314,19 @@ impl Scheduler for RoundRobinScheduler {
     }
 
     fn wake_up(&self, thread: ReadyRef) {
-        self.enqueue(thread);
+        // Queue on the target CPU's wakeup queue: no allocation or lock, so
+        // this is safe from any CPU or interrupt handler
+        let cpu_id = SMT_POLICY.place(&thread.0, self.select_cpu(), self.num_cpus);
+        let queue = &self.run_queues[cpu_id];
+        let thread_id = thread.0.id();
+
+        // A thread already in a wakeup queue has a wakeup pending
+        if queue.remote.push(thread.0).is_ok() {
+            queue.thread_count.fetch_add(1, Ordering::AcqRel);
+            self.runnable_threads.fetch_add(1, Ordering::AcqRel);
+            GLOBAL_METRICS.system_counters().record_scheduler_decision();
+            tracepoint!(WAKEUP, Wakeup { thread: thread_id, target_cpu: cpu_id });
+        }
     }
 
     fn stats(&self) -> (usize, usize, usize) {
@@ -266,6 +335,22 @@ impl Scheduler for RoundRobinScheduler {
         let blocked = total.saturating_sub(runnable);
         (total, runnable, blocked)
     }
+
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        let mut queues = Vec::with_capacity(self.num_cpus * 5);
+
+        for (cpu_id, queue) in self.run_queues.iter().enumerate() {
+            queues.extend([
+                (queue.high_priority.address(), Some(cpu_id)),
+                (queue.normal_priority.address(), Some(cpu_id)),
+                (queue.low_priority.address(), Some(cpu_id)),
+                (queue.idle_priority.address(), Some(cpu_id)),
+                (&queue.remote as *const MpscQueue as usize, Some(cpu_id)),
+            ]);
+        }
+
+        RunQueueEntry::collect(&queues)
+    }
 }
 
 impl CpuRunQueue {
@@ -275,130 +360,158 @@ impl CpuRunQueue {
             normal_priority: LockFreeQueue::new(),
             low_priority: LockFreeQueue::new(),
             idle_priority: LockFreeQueue::new(),
+            remote: MpscQueue::new(),
             current_pos: AtomicUsize::new(0),
             thread_count: AtomicUsize::new(0),
         }
     }
+
+    /// Add `thread` to the priority queue for its priority.
+    fn push_local(&self, thread: ReadyRef) {
+        let priority_queue = match RoundRobinScheduler::priority_level(thread.priority()) {
+            PriorityLevel::High => &self.high_priority,
+            PriorityLevel::Normal => &self.normal_priority,
+            PriorityLevel::Low => &self.low_priority,
+            PriorityLevel::Idle => &self.idle_priority,
+        };
+        priority_queue.push(thread);
+    }
+
+    /// Move remotely woken threads into the priority queues. Already
+    /// counted in `thread_count` by `wake_up`.
+    fn drain_remote(&self) {
+        while let Some(thread) = self.remote.pop() {
+            self.push_local(ReadyRef(thread));
+        }
+    }
 }
 
 impl LockFreeQueue {
     fn new() -> Self {
         let dummy = Box::into_raw(Box::new(QueueNode {
-            thread: None,
+            thread: MaybeUninit::uninit(),
             next: AtomicPtr::new(ptr::null_mut()),
         }));
 
         Self {
-            head: AtomicPtr::new(dummy),
-            tail: AtomicPtr::new(dummy),
+            head: AtomicTaggedPtr::new(dummy),
+            tail: AtomicTaggedPtr::new(dummy),
         }
     }
 
+    /// Address threads in this queue record as theirs.
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+
     fn push(&self, thread: ReadyRef) {
+        thread.0.set_run_queue(self.address());
         let new_node = Box::into_raw(Box::new(QueueNode {
-            thread: Some(thread),
+            thread: MaybeUninit::new(thread),
             next: AtomicPtr::new(ptr::null_mut()),
         }));
 
         loop {
             let tail = self.tail.load(Ordering::Acquire);
-            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+            let next = unsafe { (*tail.ptr()).next.load(Ordering::Acquire) };
 
-            // Double-check that tail hasn't changed (ABA prevention)
-            if tail == self.tail.load(Ordering::Acquire) {
-                if next.is_null() {
-                    // Try to link new node at the end of the list
-                    if unsafe { (*tail).next.compare_exchange_weak(
-                        ptr::null_mut(),
-                        new_node,
-                        Ordering::Release,  // Success: synchronizes with acquire in try_pop
-                        Ordering::Relaxed   // Failure: just retry
-                    ).is_ok() } {
-                        break;
-                    }
-                } else {
-                    // Tail was lagging, try to advance it
-                    let _ = self.tail.compare_exchange_weak(
+            // Re-check that tail hasn't moved while we read its next pointer
+            if tail != self.tail.load(Ordering::Acquire) {
+                continue;
+            }
+
+            if next.is_null() {
+                // Try to link new node at the end of the list
+                if unsafe { (*tail.ptr()).next.compare_exchange_weak(
+                    ptr::null_mut(),
+                    new_node,
+                    Ordering::Release,  // Success: synchronizes with acquire in try_pop
+                    Ordering::Relaxed   // Failure: just retry
+                ).is_ok() } {
+                    // Swing tail to the new node; if this fails another
+                    // thread already helped it along
+                    let _ = self.tail.compare_exchange(
                         tail,
-                        next,
-                        Ordering::Release,  // Success: make new tail visible
-                        Ordering::Relaxed   // Failure: someone else advanced it
+                        tail.advance(new_node),
+                        Ordering::Release,
+                        Ordering::Relaxed
                     );
+                    break;
                 }
+            } else {
+                // Tail was lagging, try to advance it
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    tail.advance(next),
+                    Ordering::Release,
+                    Ordering::Relaxed
+                );
             }
         }
-
-        // Try to advance tail to point to the new node
-        // This may fail if another thread already advanced it, which is fine
-        let _ = self.tail.compare_exchange_weak(
-            self.tail.load(Ordering::Acquire),
-            new_node,
-            Ordering::Release,  // Make the new tail visible to other threads
-            Ordering::Relaxed
-        );
     }
 
     fn try_pop(&self) -> Option<ReadyRef> {
         loop {
             let head = self.head.load(Ordering::Acquire);
             let tail = self.tail.load(Ordering::Acquire);
-            let next = unsafe { (*head).next.load(Ordering::Acquire) };
-
-            // Double-check head consistency (ABA prevention)
-            if head == self.head.load(Ordering::Acquire) {
-                if head == tail {
-                    if next.is_null() {
-                        return None; // Queue is definitely empty
-                    }
-                    // Queue appears empty but tail is lagging, help advance tail
-                    let _ = self.tail.compare_exchange_weak(
-                        tail,
-                        next,
-                        Ordering::Release,  // Make tail advancement visible
-                        Ordering::Relaxed
-                    );
-                } else {
-                    if next.is_null() {
-                        continue; // Inconsistent state, retry
-                    }
-
-                    // Speculatively read the thread from next node
-                    // This must be done before the CAS to avoid races
-                    let thread = unsafe { (*next).thread.take() };
-                    
-                    // Try to advance head to next node
-                    if self.head.compare_exchange_weak(
-                        head,
-                        next,
-                        Ordering::Release,  // Success: make new head visible
-                        Ordering::Relaxed   // Failure: retry
-                    ).is_ok() {
-                        // Successfully advanced head, safe to free old head
-                        unsafe {
-                            drop(Box::from_raw(head));
-                        }
-                        return thread;
-                    } else {
-                        // CAS failed, put thread back (if we got one)
-                        if let Some(t) = thread {
-                            unsafe {
-                                (*next).thread = Some(t);
-                            }
-                        }
-                    }
+            let next = unsafe { (*head.ptr()).next.load(Ordering::Acquire) };
+
+            // Re-check that head hasn't moved while we read its next pointer
+            if head != self.head.load(Ordering::Acquire) {
+                continue;
+            }
+
+            if head.ptr() == tail.ptr() {
+                if next.is_null() {
+                    return None; // Queue is definitely empty
+                }
+                // Queue appears empty but tail is lagging, help advance tail
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    tail.advance(next),
+                    Ordering::Release,
+                    Ordering::Relaxed
+                );
+                continue;
+            }
+
+            if next.is_null() {
+                continue; // Inconsistent state, retry
+            }
+
+            // Copy the thread out before the CAS: afterwards `next` is the
+            // dummy and another pop may free it. The copy only becomes ours
+            // if the CAS succeeds; the tag makes it fail if `head` was
+            // popped and recycled in the meantime.
+            let thread = unsafe { ptr::read((*next).thread.as_ptr()) };
+
+            if self.head.compare_exchange(
+                head,
+                head.advance(next),
+                Ordering::AcqRel,
+                Ordering::Relaxed
+            ).is_ok() {
+                // The old dummy is unreachable now
+                unsafe {
+                    drop(Box::from_raw(head.ptr()));
                 }
+                thread.0.set_run_queue(0);
+                return Some(thread);
             }
+
+            // Another pop took this thread
+            core::mem::forget(thread);
         }
     }
 
     fn peek(&self) -> Option<&ReadyRef> {
         let head = self.head.load(Ordering::Acquire);
-        let next = unsafe { (*head).next.load(Ordering::Acquire) };
+        let next = unsafe { (*head.ptr()).next.load(Ordering::Acquire) };
         
         if next.is_null() {
             None
         } else {
-            unsafe { (*next).thread.as_ref() }
+            unsafe { Some((*next).thread.assume_init_ref()) }
         }
     }
 }
@@ -407,10 +520,11 @@ impl Drop for LockFreeQueue {
     fn drop(&mut self) {
         while self.try_pop().is_some() {}
         
+        // The dummy's thread has been moved out, so only the node is freed
         let head = self.head.load(Ordering::Acquire);
         if !head.is_null() {
             unsafe {
-                drop(Box::from_raw(head));
+                drop(Box::from_raw(head.ptr()));
             }
         }
     }
@@ -456,4 +570,10 @@ mod tests {
         assert!(queue.try_pop().is_none());
         assert!(queue.peek().is_none());
     }
+
+    #[test]
+    fn test_empty_run_queue_snapshot() {
+        let scheduler = RoundRobinScheduler::new(2);
+        assert!(scheduler.run_queue_snapshot().is_empty());
+    }
 }
\ No newline at end of file
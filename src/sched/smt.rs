@@ -0,0 +1,257 @@
+//! SMT-aware thread placement.
+//!
+//! Hardware threads of the same physical core share its execution units,
+//! so two CPU-bound threads on sibling CPUs slow each other down. When
+//! enabled, the policy steers newly enqueued threads away from such
+//! pairings as long as a physical core with no busy hardware thread is
+//! available:
+//!
+//! - a CPU-bound real-time thread is not placed next to another one
+//! - a thread with the [`no_smt`](crate::thread_new::ThreadBuilder::no_smt)
+//!   hint is not placed next to any busy CPU
+//! - nothing is placed next to a running `no_smt` thread
+//!
+//! A thread counts as CPU-bound if it was preempted at the end of its last
+//! time slice, and as real-time if its priority is in the
+//! [`HIGH`](super::priority::HIGH) band or it has a real-time priority.
+//! When every core is busy, placement falls back to the scheduler's own
+//! choice.
+//!
+//! The policy is off until configured with a topology, e.g.
+//! `SMT_POLICY.configure(&perf::cache_aware::topology())`.
+
+use super::trait_def::{priority, CpuId};
+use crate::arch::detection::CacheTopology;
+use crate::arch::percpu::MAX_CPUS;
+use crate::thread_new::Thread;
+use portable_atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// What a CPU is currently running, as far as placement is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Occupancy {
+    /// Nothing is running
+    Idle = 0,
+    /// An ordinary thread is running
+    Busy = 1,
+    /// A CPU-bound real-time thread is running
+    CpuBoundRt = 2,
+    /// A thread with the `no_smt` hint is running
+    NoSmt = 3,
+}
+
+impl Occupancy {
+    /// Classify `thread` for placement.
+    pub fn of(thread: &Thread) -> Self {
+        if thread.is_no_smt() {
+            Occupancy::NoSmt
+        } else if thread.is_cpu_bound()
+            && (thread.priority() >= priority::HIGH || thread.realtime_priority() > 0)
+        {
+            Occupancy::CpuBoundRt
+        } else {
+            Occupancy::Busy
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Occupancy::Busy,
+            2 => Occupancy::CpuBoundRt,
+            3 => Occupancy::NoSmt,
+            _ => Occupancy::Idle,
+        }
+    }
+
+    /// Check whether a thread of class `self` should avoid running next to
+    /// a sibling in state `sibling`.
+    fn conflicts_with(self, sibling: Occupancy) -> bool {
+        match (self, sibling) {
+            (_, Occupancy::NoSmt) => true,
+            (Occupancy::NoSmt, other) => other != Occupancy::Idle,
+            (Occupancy::CpuBoundRt, Occupancy::CpuBoundRt) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Placement policy that keeps interfering threads off SMT siblings.
+pub struct SmtPolicy {
+    enabled: AtomicBool,
+    /// Number of CPUs in the configured topology
+    cpu_count: AtomicUsize,
+    /// Per-CPU mask of its SMT siblings
+    siblings: [AtomicU64; MAX_CPUS],
+    /// Per-CPU `Occupancy`
+    occupancy: [AtomicU8; MAX_CPUS],
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SIBLINGS: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const IDLE: AtomicU8 = AtomicU8::new(Occupancy::Idle as u8);
+
+impl SmtPolicy {
+    /// Create a disabled policy.
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            cpu_count: AtomicUsize::new(0),
+            siblings: [NO_SIBLINGS; MAX_CPUS],
+            occupancy: [IDLE; MAX_CPUS],
+        }
+    }
+
+    /// Load the sibling relationships of `topology` and enable the policy.
+    ///
+    /// The policy stays disabled if the topology has no SMT.
+    pub fn configure(&self, topology: &CacheTopology) {
+        let cpu_count = topology.cpu_count().min(MAX_CPUS);
+        for cpu in 0..MAX_CPUS {
+            let mask = if cpu < cpu_count {
+                topology
+                    .smt_siblings(cpu)
+                    .into_iter()
+                    .filter(|&sibling| sibling < MAX_CPUS)
+                    .fold(0u64, |mask, sibling| mask | 1 << sibling)
+            } else {
+                0
+            };
+            self.siblings[cpu].store(mask, Ordering::Relaxed);
+        }
+
+        self.cpu_count.store(cpu_count, Ordering::Relaxed);
+        self.enabled.store(topology.has_smt(), Ordering::Release);
+    }
+
+    /// Stop steering placement.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    /// Check whether the policy is steering placement.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Get what `cpu` is running, as last reported by the context switch.
+    pub fn occupancy(&self, cpu: CpuId) -> Occupancy {
+        self.occupancy
+            .get(cpu)
+            .map_or(Occupancy::Idle, |state| Occupancy::from_u8(state.load(Ordering::Acquire)))
+    }
+
+    /// Record that `thread` was switched in on `cpu`.
+    pub(crate) fn note_switch_in(&self, cpu: CpuId, thread: &Thread) {
+        if let Some(state) = self.occupancy.get(cpu) {
+            state.store(Occupancy::of(thread) as u8, Ordering::Release);
+        }
+    }
+
+    /// Record that the thread on `cpu` was switched out.
+    pub(crate) fn note_switch_out(&self, cpu: CpuId) {
+        if let Some(state) = self.occupancy.get(cpu) {
+            state.store(Occupancy::Idle as u8, Ordering::Release);
+        }
+    }
+
+    /// Choose the CPU to queue `thread` on, given the scheduler's
+    /// `preferred` choice among the first `num_cpus` CPUs.
+    ///
+    /// Returns `preferred` unless it would pair `thread` with an
+    /// interfering sibling and a fully idle core exists.
+    pub fn place(&self, thread: &Thread, preferred: CpuId, num_cpus: usize) -> CpuId {
+        let limit = num_cpus.min(self.cpu_count.load(Ordering::Relaxed));
+        if !self.is_enabled() || preferred >= limit {
+            return preferred;
+        }
+
+        let class = Occupancy::of(thread);
+        if !self.has_conflict(preferred, class) {
+            return preferred;
+        }
+
+        // First CPU after `preferred` on a core with nothing running
+        (1..limit)
+            .map(|offset| (preferred + offset) % limit)
+            .find(|&cpu| self.core_idle(cpu))
+            .unwrap_or(preferred)
+    }
+
+    fn sibling_states(&self, cpu: CpuId) -> impl Iterator<Item = Occupancy> + '_ {
+        let mask = self.siblings[cpu].load(Ordering::Relaxed);
+        (0..MAX_CPUS)
+            .filter(move |&sibling| mask & (1 << sibling) != 0)
+            .map(move |sibling| self.occupancy(sibling))
+    }
+
+    fn has_conflict(&self, cpu: CpuId, class: Occupancy) -> bool {
+        self.sibling_states(cpu).any(|sibling| class.conflicts_with(sibling))
+    }
+
+    fn core_idle(&self, cpu: CpuId) -> bool {
+        self.occupancy(cpu) == Occupancy::Idle
+            && self.sibling_states(cpu).all(|sibling| sibling == Occupancy::Idle)
+    }
+}
+
+impl Default for SmtPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global SMT placement policy consulted by the schedulers' `enqueue`.
+pub static SMT_POLICY: SmtPolicy = SmtPolicy::new();
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::thread_new::ThreadId;
+
+    #[test]
+    fn test_smt_placement() {
+        let pool = StackPool::new();
+        let thread = |id: usize, priority: u8| {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            let id = unsafe { ThreadId::new_unchecked(id) };
+            Thread::new(id, stack, || {}, priority).0
+        };
+
+        // Four CPUs on two cores: {0, 1} and {2, 3}
+        let policy = SmtPolicy::new();
+        policy.configure(&CacheTopology::uniform(4, 2, 4));
+        assert!(policy.is_enabled());
+
+        let rt = thread(42_001, priority::HIGH);
+        let rt_running = thread(42_002, priority::HIGH);
+        let normal = thread(42_003, priority::NORMAL);
+        let latency = thread(42_004, priority::NORMAL);
+        latency.set_no_smt(true);
+
+        // Both RT threads have used up a time slice
+        for cpu_bound in [&rt, &rt_running] {
+            let running = crate::thread_new::ReadyRef(cpu_bound.clone()).start_running();
+            running.prepare_preemption();
+        }
+        policy.note_switch_in(0, &rt_running);
+
+        // CPU-bound RT next to another one moves to the idle core
+        assert_eq!(policy.place(&rt, 1, 4), 2);
+        assert_eq!(policy.place(&normal, 1, 4), 1);
+
+        // With both cores busy, the scheduler's choice stands
+        policy.note_switch_in(3, &normal);
+        assert_eq!(policy.place(&rt, 1, 4), 1);
+        assert_eq!(policy.place(&latency, 2, 4), 2);
+
+        // Nothing is queued next to a running no_smt thread
+        policy.note_switch_out(3);
+        policy.note_switch_in(0, &latency);
+        assert_eq!(policy.place(&normal, 1, 4), 2);
+
+        policy.disable();
+        assert_eq!(policy.place(&normal, 1, 4), 1);
+    }
+}
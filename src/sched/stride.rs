@@ -0,0 +1,206 @@
+//! Proportional-share stride scheduling.
+//!
+//! Every thread holds tickets, set with
+//! [`ThreadBuilder::tickets`](crate::ThreadBuilder::tickets) or derived from
+//! its nice value, and over time gets CPU time in proportion to them: a
+//! thread with 300 tickets runs three times as long as one with 100. Each
+//! thread has a pass, advanced by the CPU time it uses divided by its
+//! tickets, and the ready thread with the lowest pass runs next. Unlike
+//! lottery scheduling the shares hold exactly, not just on average, which
+//! makes it a good fit for hosts that run plugins of several tenants and
+//! promise each a share of the CPU.
+//!
+//! Threads that sleep don't bank credit: a thread rejoining the queue
+//! starts no further behind than the thread that last started running.
+//! The queue is shared by all CPUs and thread priorities are ignored.
+//!
+//! ```ignore
+//! let kernel = Kernel::<DefaultArch, _>::new(StrideScheduler::new());
+//! kernel.spawn(ThreadBuilder::new().tickets(300), plugin_main)?;
+//! ```
+
+use super::trait_def::{CpuId, RunQueueEntry, Scheduler};
+use crate::sync::{IrqSafe, SpinLockIrqSave};
+use crate::thread_new::{ReadyRef, RunningRef, Thread, ThreadId};
+use crate::time::Instant;
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Pass charged per microsecond of CPU time to a thread with one ticket.
+const STRIDE1: u64 = 1 << 20;
+
+struct StrideQueue {
+    /// Ready threads by pass, ties broken by arrival order
+    ready: BTreeMap<(u64, u64), ReadyRef>,
+    next_seq: u64,
+    /// Pass of the thread that last started running
+    global_pass: u64,
+}
+
+/// Stride scheduler.
+pub struct StrideScheduler {
+    queue: SpinLockIrqSave<StrideQueue>,
+}
+
+// Safety: all state is behind an IRQ-saving lock
+unsafe impl IrqSafe for StrideScheduler {}
+
+impl StrideScheduler {
+    /// Create an empty stride scheduler.
+    pub const fn new() -> Self {
+        Self {
+            queue: SpinLockIrqSave::new(StrideQueue {
+                ready: BTreeMap::new(),
+                next_seq: 0,
+                global_pass: 0,
+            }),
+        }
+    }
+
+    /// Get the pass advance per microsecond of CPU time for `thread`.
+    fn stride(thread: &Thread) -> u64 {
+        (STRIDE1 / thread.tickets().max(1) as u64).max(1)
+    }
+
+    /// Charge the running thread for the CPU time it used since it was last
+    /// charged, returning its new pass.
+    fn charge(current: &RunningRef, now: Instant) -> u64 {
+        let pass = current.0.stride_pass();
+        let Some(start) = current.time_slice().slice_start() else {
+            return pass;
+        };
+
+        let micros = now.as_nanos().saturating_sub(start.as_nanos()) / 1000;
+        let pass = pass.saturating_add(micros.saturating_mul(Self::stride(&current.0)));
+        current.0.set_stride_pass(pass);
+        current.time_slice().start_slice(now);
+        pass
+    }
+
+    fn tick_at(&self, current: &RunningRef, now: Instant) -> Option<ReadyRef> {
+        let pass = Self::charge(current, now);
+        let queue = self.queue.lock();
+        let behind = queue.ready.keys().next().is_some_and(|&(lowest, _)| lowest < pass);
+        drop(queue);
+
+        if behind {
+            Some(current.prepare_preemption())
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for StrideScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn enqueue(&self, thread: ReadyRef) {
+        let mut queue = self.queue.lock();
+        let pass = thread.0.stride_pass().max(queue.global_pass);
+        thread.0.set_stride_pass(pass);
+
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.ready.insert((pass, seq), thread);
+    }
+
+    fn pick_next(&self, _cpu_id: CpuId) -> Option<ReadyRef> {
+        let mut queue = self.queue.lock();
+        let (key, _) = queue.ready.first_key_value()?;
+        let key = *key;
+        let thread = queue.ready.remove(&key)?;
+        queue.global_pass = queue.global_pass.max(key.0);
+        Some(thread)
+    }
+
+    fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+        self.tick_at(current, Instant::now())
+    }
+
+    fn set_priority(&self, thread_id: ThreadId, priority: u8) {
+        // Shares come from tickets, not priority
+        let _ = (thread_id, priority);
+    }
+
+    fn on_yield(&self, current: RunningRef) {
+        Self::charge(&current, Instant::now());
+        self.enqueue(current.stop_running());
+    }
+
+    fn on_block(&self, current: RunningRef) {
+        Self::charge(&current, Instant::now());
+        current.block();
+    }
+
+    fn wake_up(&self, thread: ReadyRef) {
+        self.enqueue(thread);
+    }
+
+    fn stats(&self) -> (usize, usize, usize) {
+        let queued = self.queue.lock().ready.len();
+        (queued, queued, 0)
+    }
+
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        self.queue
+            .lock()
+            .ready
+            .values()
+            .map(|thread| RunQueueEntry {
+                thread_id: thread.id(),
+                priority: thread.priority(),
+                cpu: None,
+                ready_since: thread.ready_since(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+
+    fn thread(pool: &StackPool, id: usize) -> Thread {
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        Thread::new(unsafe { ThreadId::new_unchecked(id) }, stack, || {}, 128).0
+    }
+
+    #[test]
+    fn test_stride_shares_follow_tickets() {
+        let pool = StackPool::new();
+        let (tenant_a, tenant_b) = (thread(&pool, 49_260), thread(&pool, 49_261));
+        tenant_a.set_tickets(300);
+        tenant_b.set_nice_value(5).unwrap();
+        assert_eq!(tenant_b.tickets(), 335);
+        tenant_b.set_tickets(100);
+
+        let scheduler = StrideScheduler::new();
+        scheduler.enqueue(ReadyRef(tenant_a.clone()));
+        scheduler.enqueue(ReadyRef(tenant_b.clone()));
+
+        // Run 400 1ms ticks
+        let mut ran = BTreeMap::new();
+        let mut now = Instant::from_nanos(1_000_000);
+        let mut running = scheduler.pick_next(0).unwrap().start_running();
+        running.time_slice().start_slice(now);
+        for _ in 0..400 {
+            now = Instant::from_nanos(now.as_nanos() + 1_000_000);
+            *ran.entry(running.id()).or_insert(0) += 1;
+            if let Some(preempted) = scheduler.tick_at(&running, now) {
+                scheduler.enqueue(preempted);
+                running = scheduler.pick_next(0).unwrap().start_running();
+                running.time_slice().start_slice(now);
+            }
+        }
+
+        let a = ran[&tenant_a.id()];
+        let b = ran[&tenant_b.id()];
+        assert_eq!(a + b, 400);
+        assert!((298..=302).contains(&a), "tenant A ran {a} of 400 ticks");
+    }
+}
@@ -1,15 +1,64 @@
 //! Scheduler trait definition for the new lock-free scheduler architecture.
 
-use crate::thread_new::{ReadyRef, RunningRef, ThreadId};
+use crate::thread_new::{registry, ReadyRef, RunningRef, ThreadId};
+use crate::sync::IrqSafe;
+use crate::time::Instant;
+extern crate alloc;
+use alloc::vec::Vec;
 
 /// CPU identifier type.
 pub type CpuId = usize;
 
+/// Snapshot of a thread waiting in a scheduler run queue.
+///
+/// Returned by [`Scheduler::run_queue_snapshot`] for monitoring and
+/// debugging purposes. The snapshot is best-effort: queues may change
+/// while it is being taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunQueueEntry {
+    /// ID of the queued thread
+    pub thread_id: ThreadId,
+    /// Priority of the queued thread
+    pub priority: u8,
+    /// CPU whose run queue holds the thread, `None` for global queues
+    pub cpu: Option<CpuId>,
+    /// Time at which the thread became ready to run
+    pub ready_since: Instant,
+}
+
+impl RunQueueEntry {
+    /// Snapshot the ready threads held by `queues`, each given by address
+    /// along with the CPU it belongs to.
+    ///
+    /// Threads record which queue holds them, so this walks the thread
+    /// registry instead of the queues: their nodes can be freed by a
+    /// concurrent pop on another CPU.
+    pub(crate) fn collect(queues: &[(usize, Option<CpuId>)]) -> Vec<RunQueueEntry> {
+        registry::ids()
+            .into_iter()
+            .filter_map(registry::lookup)
+            .filter_map(|thread| {
+                let queue = thread.run_queue();
+                let &(_, cpu) = queues.iter().find(|&&(address, _)| address == queue)?;
+                Some(RunQueueEntry {
+                    thread_id: thread.id(),
+                    priority: thread.priority(),
+                    cpu,
+                    ready_since: thread.ready_since()?,
+                })
+            })
+            .collect()
+    }
+}
+
 /// New scheduler trait for lock-free implementations.
 ///
 /// This trait defines the interface that all scheduler implementations must
 /// provide. It's designed to support lock-free operation and per-CPU scheduling.
-pub trait Scheduler: Send + Sync {
+///
+/// Schedulers are driven from the timer interrupt, so implementations must
+/// be [`IrqSafe`]: any locks they take must disable interrupts.
+pub trait Scheduler: Send + Sync + IrqSafe {
     /// Enqueue a thread that is ready to run.
     ///
     /// This is called when a thread becomes ready to run (either newly created,
@@ -20,6 +69,17 @@ pub trait Scheduler: Send + Sync {
     /// * `thread` - Ready thread to enqueue
     fn enqueue(&self, thread: ReadyRef);
     
+    /// Enqueue a batch of newly created threads.
+    ///
+    /// Used when many threads are spawned at once. Schedulers can override
+    /// this to place the whole batch in one pass instead of paying for each
+    /// enqueue separately.
+    fn enqueue_batch(&self, threads: Vec<ReadyRef>) {
+        for thread in threads {
+            self.enqueue(thread);
+        }
+    }
+    
     /// Pick the next thread to run on the given CPU.
     ///
     /// This is called by the scheduler when a CPU needs a new thread to run.
@@ -35,6 +95,17 @@ pub trait Scheduler: Send + Sync {
     /// The next thread to run, or `None` if no threads are ready.
     fn pick_next(&self, cpu_id: CpuId) -> Option<ReadyRef>;
     
+    /// Check whether a thread ready on the given CPU outranks one running at
+    /// `priority`, without taking it off its queue.
+    ///
+    /// Lets a [dispatch round](super::dispatch) decide whether to switch on
+    /// after a wakeup. Schedulers that cannot tell cheaply return `false`,
+    /// which only costs them those back-to-back switches.
+    fn has_higher_priority_work(&self, cpu_id: CpuId, priority: u8) -> bool {
+        let _ = (cpu_id, priority);
+        false
+    }
+    
     /// Handle a scheduler tick for the currently running thread.
     ///
     /// This is called periodically from timer interrupts to allow the scheduler
@@ -103,6 +174,14 @@ pub trait Scheduler: Send + Sync {
         self.enqueue(thread);
     }
     
+    /// Check whether the scheduler preempts threads from the timer tick.
+    ///
+    /// Schedulers that only switch threads when they yield or block return
+    /// `false`, and the kernel then runs without a preemption timer.
+    fn is_preemptive(&self) -> bool {
+        true
+    }
+    
     /// Get scheduler statistics.
     ///
     /// Returns various metrics about the scheduler state for monitoring
@@ -115,6 +194,19 @@ pub trait Scheduler: Send + Sync {
         // Default implementation returns zeros
         (0, 0, 0)
     }
+    
+    /// Take a snapshot of all threads currently waiting in run queues.
+    ///
+    /// This is the run-queue introspection API used by health monitoring
+    /// to detect starvation. It is not intended for hot paths.
+    ///
+    /// # Returns
+    ///
+    /// One entry per queued thread. Schedulers that do not support
+    /// introspection return an empty vector.
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        Vec::new()
+    }
 }
 
 /// Priority levels for threads.
@@ -0,0 +1,200 @@
+//! Scheduler statistics for offline tuning.
+//!
+//! Every context switch records how much of its time slice the outgoing
+//! thread used and why it gave up the CPU. Together with the histogram of
+//! [wakeup-to-dispatch latencies](crate::observability::wake_latency),
+//! these distributions describe a workload well enough for an offline tool
+//! to pick a time slice length and a scheduler for it: threads that mostly
+//! block early in their slices want short slices and fast wakeups, threads
+//! that mostly run their slices out want longer ones. [`dump`] writes them
+//! as a [telemetry record](crate::observability::wire):
+//!
+//! ```ignore
+//! sched::tuning::reset();
+//! run_workload();
+//! sched::tuning::dump(&mut uart)?;
+//! ```
+//!
+//! # Schema
+//!
+//! The record kind is [`RecordKind::SchedulerStats`], and the body is a
+//! [`SchedulerStats`] in the wire encoding:
+//!
+//! | Field               | Encoding                                          |
+//! |---------------------|---------------------------------------------------|
+//! | `since`             | varint, ns the statistics were gathered from      |
+//! | `taken_at`          | varint, ns the snapshot was taken at              |
+//! | `slice_utilization` | varint count ([`UTILIZATION_BUCKETS`]), varints   |
+//! | `switch_reasons`    | varint count, then reason byte and varint count   |
+//! | `wake_to_dispatch`  | varint count ([`LATENCY_BUCKETS`]), varints, then |
+//! |                     | sample count, mean ns and max ns as varints       |
+//!
+//! Slice utilization bucket `n` counts slices that used `n * 10%` up to
+//! `(n + 1) * 10%` of their quantum; the last bucket holds slices that used
+//! it all. Switch reasons are [`ContextSwitchReason`] discriminants in
+//! declaration order, and only reasons seen are listed. Latency bucket `n`
+//! counts wakeups dispatched within `2^(n-1)` to `2^n - 1` ns.
+//!
+//! [`RecordKind::SchedulerStats`]: crate::observability::wire::RecordKind::SchedulerStats
+//! [`LATENCY_BUCKETS`]: crate::observability::wake_latency::LATENCY_BUCKETS
+
+use crate::io::Write;
+use crate::observability::profiler::ContextSwitchReason;
+use crate::observability::wake_latency::{self, LatencyHistogram};
+use crate::observability::wire::{self, WireError};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{BlockedOn, Thread, ThreadState};
+use crate::time::{Duration, Instant};
+extern crate alloc;
+use alloc::collections::BTreeMap;
+
+/// Slice utilization buckets, 10% wide, the last one for used up slices.
+pub const UTILIZATION_BUCKETS: usize = 11;
+
+/// Reasons in discriminant order.
+const REASONS: [ContextSwitchReason; 7] = [
+    ContextSwitchReason::TimeSliceExpired,
+    ContextSwitchReason::VoluntaryYield,
+    ContextSwitchReason::IOBlock,
+    ContextSwitchReason::SyncBlock,
+    ContextSwitchReason::ThreadExit,
+    ContextSwitchReason::PriorityPreemption,
+    ContextSwitchReason::LoadBalance,
+];
+
+struct Counters {
+    since_ns: u64,
+    slice_utilization: [u64; UTILIZATION_BUCKETS],
+    switch_reasons: [u64; REASONS.len()],
+}
+
+static COUNTERS: SpinLockIrqSave<Counters> = SpinLockIrqSave::new(Counters {
+    since_ns: 0,
+    slice_utilization: [0; UTILIZATION_BUCKETS],
+    switch_reasons: [0; REASONS.len()],
+});
+
+/// Scheduling distributions gathered since the last [`reset`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerStats {
+    /// When gathering started
+    pub since: Instant,
+    pub taken_at: Instant,
+    /// Slices ended, by tenths of their quantum used
+    pub slice_utilization: [u64; UTILIZATION_BUCKETS],
+    /// Switches away from a thread, by why it gave up the CPU
+    pub switch_reasons: BTreeMap<ContextSwitchReason, u64>,
+    /// Time threads spent ready before they were dispatched
+    pub wake_to_dispatch: LatencyHistogram,
+}
+
+/// Record a switch from `prev`, whose slice ran for `ran`, to `next`.
+pub(crate) fn record_switch(prev: &Thread, next: &Thread, ran: Duration) {
+    let quantum = prev.time_slice_quantum();
+    let reason = switch_reason(prev, next, ran, quantum);
+    let bucket = match quantum.as_nanos() {
+        0 => UTILIZATION_BUCKETS - 1,
+        quantum => (ran.as_nanos().saturating_mul(10) / quantum).min(10) as usize,
+    };
+
+    let mut counters = COUNTERS.lock();
+    counters.slice_utilization[bucket] += 1;
+    counters.switch_reasons[reason as usize] += 1;
+}
+
+/// Tell why `prev` gave up the CPU to `next` after running `ran` of its
+/// `quantum`.
+fn switch_reason(prev: &Thread, next: &Thread, ran: Duration, quantum: Duration) -> ContextSwitchReason {
+    match prev.state() {
+        ThreadState::Blocked => match prev.blocked_on() {
+            Some(reason) if reason.is_io_wait() => ContextSwitchReason::IOBlock,
+            Some(BlockedOn::Sleep(_)) => ContextSwitchReason::VoluntaryYield,
+            _ => ContextSwitchReason::SyncBlock,
+        },
+        ThreadState::Finished => ContextSwitchReason::ThreadExit,
+        ThreadState::Suspended => ContextSwitchReason::VoluntaryYield,
+        ThreadState::Ready | ThreadState::Running => {
+            if ran >= quantum {
+                ContextSwitchReason::TimeSliceExpired
+            } else if next.priority() > prev.priority() {
+                ContextSwitchReason::PriorityPreemption
+            } else {
+                ContextSwitchReason::VoluntaryYield
+            }
+        }
+    }
+}
+
+/// Take a snapshot of the statistics.
+pub fn snapshot() -> SchedulerStats {
+    let counters = COUNTERS.lock();
+    SchedulerStats {
+        since: Instant::from_nanos(counters.since_ns),
+        taken_at: Instant::now(),
+        slice_utilization: counters.slice_utilization,
+        switch_reasons: REASONS
+            .iter()
+            .zip(counters.switch_reasons)
+            .filter(|&(_, count)| count > 0)
+            .map(|(&reason, count)| (reason, count))
+            .collect(),
+        wake_to_dispatch: wake_latency::wake_latency(),
+    }
+}
+
+/// Start gathering afresh, clearing the
+/// [wake latency histogram](wake_latency::reset_wake_latency) too.
+pub fn reset() {
+    let mut counters = COUNTERS.lock();
+    counters.since_ns = Instant::now().as_nanos();
+    counters.slice_utilization = [0; UTILIZATION_BUCKETS];
+    counters.switch_reasons = [0; REASONS.len()];
+    drop(counters);
+    wake_latency::reset_wake_latency();
+}
+
+/// Write a snapshot of the statistics to `writer` as a framed telemetry
+/// record.
+pub fn dump(writer: &mut impl Write) -> Result<(), WireError> {
+    wire::write_record(&snapshot(), writer)
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::observability::wire::{RecordHeader, RecordKind};
+    use crate::thread_new::ThreadId;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_switches_fill_distributions() {
+        let pool = StackPool::new();
+        let (prev, _prev_join) = Thread::new(ThreadId::new(49_723), pool.allocate(StackSizeClass::Small).unwrap(), || {}, 128);
+        let (next, _next_join) = Thread::new(ThreadId::new(49_724), pool.allocate(StackSizeClass::Small).unwrap(), || {}, 200);
+        prev.set_time_slice(Duration::from_millis(1));
+        let before = snapshot();
+
+        prev.set_state(ThreadState::Ready);
+        record_switch(&prev, &next, Duration::from_millis(2));
+        record_switch(&prev, &next, Duration::from_micros(250));
+        prev.set_state(ThreadState::Finished);
+        record_switch(&prev, &next, Duration::from_micros(999));
+
+        let after = snapshot();
+        let delta = |reason| after.switch_reasons.get(&reason).unwrap_or(&0) - before.switch_reasons.get(&reason).unwrap_or(&0);
+        assert_eq!(delta(ContextSwitchReason::TimeSliceExpired), 1);
+        assert_eq!(delta(ContextSwitchReason::PriorityPreemption), 1);
+        assert_eq!(delta(ContextSwitchReason::ThreadExit), 1);
+        for (bucket, added) in [(2, 1), (9, 1), (10, 1)] {
+            assert_eq!(after.slice_utilization[bucket] - before.slice_utilization[bucket], added);
+        }
+
+        let mut out = Vec::new();
+        dump(&mut out).unwrap();
+        let mut input: &[u8] = &out;
+        let header = RecordHeader::read(&mut input).unwrap();
+        assert_eq!(RecordKind::from_u8(header.kind), Some(RecordKind::SchedulerStats));
+        assert_eq!(input.len(), header.length as usize);
+    }
+}
@@ -1,8 +1,18 @@
 //! Work-stealing scheduler implementation with lock-free deques.
 
-use super::trait_def::{Scheduler, CpuId};
+use super::trait_def::{Scheduler, CpuId, RunQueueEntry};
+use super::load::{CpuLoad, LOAD_SCALE};
+use super::mpsc::MpscQueue;
+use super::smt::SMT_POLICY;
+use crate::arch::detection::{CacheDistance, CacheTopology};
+use crate::arch::percpu::cpu_id;
+use crate::perf::{cache_aware, PERF_COUNTERS};
+use crate::observability::trace::tracepoint;
+use crate::mem::AtomicTaggedPtr;
+use crate::sync::IrqSafe;
 use crate::thread_new::{ReadyRef, RunningRef, ThreadId};
 use portable_atomic::{AtomicUsize, AtomicPtr, AtomicIsize, Ordering};
+use core::mem::MaybeUninit;
 use core::ptr;
 extern crate alloc;
 use alloc::{boxed::Box, vec::Vec};
@@ -12,13 +22,29 @@ use alloc::{boxed::Box, vec::Vec};
 /// This scheduler uses lock-free double-ended queues (deques) per CPU.
 /// Local threads are pushed/popped from one end (LIFO for better cache locality),
 /// while work stealing occurs from the other end (FIFO to avoid conflicts).
+///
+/// Besides idle CPUs stealing, every tick compares the CPU's
+/// [predicted load](CpuLoad) with the other CPUs' and pushes surplus
+/// threads to the least loaded one, so a burst is spread before the
+/// threads queued behind it have waited long.
 pub struct WorkStealingScheduler {
     /// Number of CPUs in the system
     num_cpus: usize,
     /// Per-CPU work-stealing deques
     work_deques: Box<[WorkStealingDeque]>,
+    /// Per-CPU queues of threads woken by other CPUs or interrupts, drained
+    /// into the deque by its owner
+    remote_queues: Box<[MpscQueue]>,
+    /// Per-CPU victim order: SMT siblings, then same-LLC, then remote CPUs
+    steal_order: Box<[Box<[CpuId]>]>,
+    /// CPUs in each `steal_order` entry that share a cache with the thief
+    cache_local_victims: Box<[usize]>,
+    /// Per-CPU load estimates
+    loads: Box<[CpuLoad]>,
     /// Global overflow queue for load balancing
     global_queue: LockFreeQueue,
+    /// Threads pushed away by overloaded CPUs
+    pushed_threads: AtomicUsize,
     /// Global statistics
     total_threads: AtomicUsize,
     runnable_threads: AtomicUsize,
@@ -42,15 +68,19 @@ struct WorkStealingDeque {
 }
 
 /// Lock-free MPMC queue for global overflow.
+///
+/// `head` and `tail` are tagged, so a node freed and reallocated at the same
+/// address cannot satisfy a stale CAS.
 struct LockFreeQueue {
-    head: AtomicPtr<QueueNode>,
-    tail: AtomicPtr<QueueNode>,
+    head: AtomicTaggedPtr<QueueNode>,
+    tail: AtomicTaggedPtr<QueueNode>,
     size: AtomicUsize,
 }
 
 /// Queue node for the overflow queue.
 struct QueueNode {
-    thread: Option<ReadyRef>,
+    /// Moved out by the pop that turns this node into the dummy
+    thread: MaybeUninit<ReadyRef>,
     next: AtomicPtr<QueueNode>,
 }
 
@@ -66,16 +96,43 @@ enum StealResult {
 
 impl WorkStealingScheduler {
     /// Create a new work-stealing scheduler for the given number of CPUs.
+    ///
+    /// Steal order follows the system cache topology from
+    /// [`perf::cache_aware::topology`](crate::perf::cache_aware::topology).
     pub fn new(num_cpus: usize) -> Self {
+        Self::with_topology(num_cpus, &cache_aware::topology())
+    }
+
+    /// Create a new work-stealing scheduler using an explicit cache topology.
+    pub fn with_topology(num_cpus: usize, topology: &CacheTopology) -> Self {
         let mut work_deques = Vec::with_capacity(num_cpus);
-        for _ in 0..num_cpus {
+        let mut remote_queues = Vec::with_capacity(num_cpus);
+        let mut steal_order = Vec::with_capacity(num_cpus);
+        let mut cache_local_victims = Vec::with_capacity(num_cpus);
+        let mut loads = Vec::with_capacity(num_cpus);
+        for cpu in 0..num_cpus {
             work_deques.push(WorkStealingDeque::new());
+            loads.push(CpuLoad::new());
+            remote_queues.push(MpscQueue::new());
+
+            let order = topology.steal_order(cpu, num_cpus);
+            cache_local_victims.push(
+                order.iter()
+                    .filter(|&&victim| topology.distance(cpu, victim) < CacheDistance::Remote)
+                    .count()
+            );
+            steal_order.push(order.into_boxed_slice());
         }
 
         Self {
             num_cpus,
             work_deques: work_deques.into_boxed_slice(),
+            remote_queues: remote_queues.into_boxed_slice(),
+            steal_order: steal_order.into_boxed_slice(),
+            cache_local_victims: cache_local_victims.into_boxed_slice(),
+            loads: loads.into_boxed_slice(),
             global_queue: LockFreeQueue::new(),
+            pushed_threads: AtomicUsize::new(0),
             total_threads: AtomicUsize::new(0),
             runnable_threads: AtomicUsize::new(0),
         }
@@ -94,28 +151,40 @@ impl WorkStealingScheduler {
     }
 
     /// Attempt to steal work from other CPUs.
+    ///
+    /// Victims are tried closest-cache first so stolen threads are more
+    /// likely to find their working set still warm.
     fn try_steal_work(&self, requesting_cpu: CpuId) -> Option<ReadyRef> {
-        // Try stealing from 2 * num_cpus attempts to increase success rate
-        let attempts = self.num_cpus * 2;
-        
-        for i in 0..attempts {
-            let victim_cpu = (requesting_cpu + i + 1) % self.num_cpus;
-            if victim_cpu == requesting_cpu {
-                continue; // Don't steal from ourselves
-            }
+        let order = &self.steal_order[requesting_cpu];
+        let cache_local = self.cache_local_victims[requesting_cpu];
+
+        // Two passes over the victim list to increase success rate
+        for _ in 0..2 {
+            for (rank, &victim_cpu) in order.iter().enumerate() {
+                let stolen = match self.work_deques[victim_cpu].steal() {
+                    StealResult::Success(thread) => Some(thread),
+                    StealResult::Empty => None,
+                    StealResult::Abort => {
+                        // Retry the same victim on abort
+                        match self.work_deques[victim_cpu].steal() {
+                            StealResult::Success(thread) => Some(thread),
+                            _ => None,
+                        }
+                    },
+                };
 
-            match self.work_deques[victim_cpu].steal() {
-                StealResult::Success(thread) => {
-                    return Some(thread);
-                },
-                StealResult::Empty => continue,
-                StealResult::Abort => {
-                    // Retry the same victim on abort
-                    match self.work_deques[victim_cpu].steal() {
-                        StealResult::Success(thread) => return Some(thread),
-                        _ => continue,
+                if let Some(thread) = stolen {
+                    if rank >= cache_local {
+                        // Stolen across LLC domains, working set must move
+                        PERF_COUNTERS.record_cross_llc_steal();
                     }
-                },
+                    tracepoint!(MIGRATE, Migrate {
+                        thread: thread.0.id(),
+                        from_cpu: victim_cpu,
+                        to_cpu: requesting_cpu,
+                    });
+                    return Some(thread);
+                }
             }
         }
 
@@ -123,6 +192,65 @@ impl WorkStealingScheduler {
         self.global_queue.try_pop()
     }
 
+    /// Get the load estimate of `cpu`.
+    pub fn load(&self, cpu: CpuId) -> Option<&CpuLoad> {
+        self.loads.get(cpu)
+    }
+
+    /// Get the number of threads overloaded CPUs pushed to others.
+    pub fn pushed_threads(&self) -> usize {
+        self.pushed_threads.load(Ordering::Relaxed)
+    }
+
+    /// Sample `cpu`'s load and push threads to the least loaded CPU if
+    /// `cpu` is predicted to have at least [`PUSH_THRESHOLD`] threads more.
+    ///
+    /// Must run on `cpu`, which owns the deque threads are taken from.
+    fn balance_tick(&self, cpu: CpuId) {
+        let queued = self.work_deques[cpu].size.load(Ordering::Acquire) + self.remote_queues[cpu].len();
+        self.loads[cpu].sample(queued, true);
+
+        let load = self.loads[cpu].predicted();
+        // Ties go to the nearest CPU in cache terms
+        let Some((target, target_load)) = self.steal_order[cpu]
+            .iter()
+            .map(|&victim| (victim, self.loads[victim].predicted()))
+            .min_by_key(|&(_, load)| load)
+        else {
+            return;
+        };
+        if load < target_load + PUSH_THRESHOLD {
+            return;
+        }
+
+        // Even out half the predicted gap, but never more than the queues
+        // hold now: the averages lag the threads already pushed
+        let target_queued = self.work_deques[target].size.load(Ordering::Acquire) + self.remote_queues[target].len();
+        let surplus = (((load - target_load) / LOAD_SCALE / 2) as usize)
+            .min(queued.saturating_sub(target_queued) / 2)
+            .min(MAX_PUSH);
+        // Coldest threads first
+        let deque = &self.work_deques[cpu];
+        for _ in 0..surplus {
+            let StealResult::Success(thread) = deque.steal() else {
+                break;
+            };
+            let thread_id = thread.0.id();
+            if let Err(thread) = self.remote_queues[target].push(thread.0) {
+                if !deque.push(ReadyRef(thread.clone())) {
+                    self.global_queue.push(ReadyRef(thread));
+                }
+                break;
+            }
+            self.pushed_threads.fetch_add(1, Ordering::Relaxed);
+            tracepoint!(MIGRATE, Migrate {
+                thread: thread_id,
+                from_cpu: cpu,
+                to_cpu: target,
+            });
+        }
+    }
+
     /// Balance load by moving threads to global queue.
     fn balance_load(&self, cpu_id: CpuId) {
         let deque = &self.work_deques[cpu_id];
@@ -142,9 +270,19 @@ impl WorkStealingScheduler {
     }
 }
 
+/// Predicted load gap, in [`LOAD_SCALE`] units, at which a CPU pushes
+/// threads to the least loaded one.
+const PUSH_THRESHOLD: u32 = 2 * LOAD_SCALE;
+
+/// Most threads pushed per tick.
+const MAX_PUSH: usize = 4;
+
+// Safety: local queues and steal paths are lock-free
+unsafe impl IrqSafe for WorkStealingScheduler {}
+
 impl Scheduler for WorkStealingScheduler {
     fn enqueue(&self, thread: ReadyRef) {
-        let cpu_id = self.select_cpu();
+        let cpu_id = SMT_POLICY.place(&thread.0, self.select_cpu(), self.num_cpus);
         let deque = &self.work_deques[cpu_id];
         
         // Try to push to local deque first
@@ -166,8 +304,16 @@ impl Scheduler for WorkStealingScheduler {
             return None;
         }
 
-        // First try local deque (LIFO for cache locality)
+        // Move remote wakeups into the local deque; only the owner may push
         let deque = &self.work_deques[cpu_id];
+        while let Some(thread) = self.remote_queues[cpu_id].pop() {
+            let thread = ReadyRef(thread);
+            if !deque.push(thread.clone()) {
+                self.global_queue.push(thread);
+            }
+        }
+
+        // First try local deque (LIFO for cache locality)
         if let Some(thread) = deque.pop() {
             self.runnable_threads.fetch_sub(1, Ordering::AcqRel);
             return Some(thread);
@@ -185,10 +331,16 @@ impl Scheduler for WorkStealingScheduler {
             return Some(thread);
         }
 
+        self.loads[cpu_id].sample(0, false);
         None
     }
 
     fn on_tick(&self, current: &RunningRef) -> Option<ReadyRef> {
+        let cpu = cpu_id();
+        if cpu < self.num_cpus {
+            self.balance_tick(cpu);
+        }
+
         // Work-stealing scheduler uses shorter time slices to improve responsiveness
         if current.time_slice().should_preempt() {
             Some(current.prepare_preemption())
@@ -212,7 +364,16 @@ impl Scheduler for WorkStealingScheduler {
     }
 
     fn wake_up(&self, thread: ReadyRef) {
-        self.enqueue(thread);
+        // Deques only accept pushes from their owner, and the overflow queue
+        // allocates; the wakeup queue does neither, so this is safe from any
+        // CPU or interrupt handler. A thread already in a wakeup queue has a
+        // wakeup pending.
+        let cpu_id = SMT_POLICY.place(&thread.0, self.select_cpu(), self.num_cpus);
+        let thread_id = thread.0.id();
+        if self.remote_queues[cpu_id].push(thread.0).is_ok() {
+            self.runnable_threads.fetch_add(1, Ordering::AcqRel);
+            tracepoint!(WAKEUP, Wakeup { thread: thread_id, target_cpu: cpu_id });
+        }
     }
 
     fn stats(&self) -> (usize, usize, usize) {
@@ -221,6 +382,18 @@ impl Scheduler for WorkStealingScheduler {
         let blocked = total.saturating_sub(runnable);
         (total, runnable, blocked)
     }
+
+    fn run_queue_snapshot(&self) -> Vec<RunQueueEntry> {
+        let mut queues = Vec::with_capacity(self.num_cpus * 2 + 1);
+
+        for cpu_id in 0..self.num_cpus {
+            queues.push((self.work_deques[cpu_id].address(), Some(cpu_id)));
+            queues.push((&self.remote_queues[cpu_id] as *const MpscQueue as usize, Some(cpu_id)));
+        }
+        queues.push((self.global_queue.address(), None));
+
+        RunQueueEntry::collect(&queues)
+    }
 }
 
 impl WorkStealingDeque {
@@ -257,6 +430,7 @@ impl WorkStealingDeque {
         let index = bottom & (capacity - 1);
         
         // Store the thread in the buffer
+        thread.0.set_run_queue(self.address());
         unsafe {
             *buffer.add(index) = Box::into_raw(Box::new(thread));
         }
@@ -300,7 +474,7 @@ impl WorkStealingDeque {
         if (new_bottom as isize) > top {
             // More than one element, pop is successful (no race with steal)
             self.size.fetch_sub(1, Ordering::AcqRel);
-            return Some(unsafe { *Box::from_raw(thread_ptr) });
+            return Some(Self::take(thread_ptr));
         }
 
         // Exactly one element, compete with steal using sequential consistency
@@ -318,7 +492,7 @@ impl WorkStealingDeque {
         // Won the race, restore bottom and return the thread
         self.bottom.store(bottom, Ordering::Relaxed);
         self.size.fetch_sub(1, Ordering::AcqRel);
-        Some(unsafe { *Box::from_raw(thread_ptr) })
+        Some(Self::take(thread_ptr))
     }
 
     /// Steal a thread from the top of the deque (thief operation).
@@ -353,62 +527,86 @@ impl WorkStealingDeque {
 
         // Successfully stole the thread
         self.size.fetch_sub(1, Ordering::AcqRel);
-        StealResult::Success(unsafe { *Box::from_raw(thread_ptr) })
+        StealResult::Success(Self::take(thread_ptr))
+    }
+
+    /// Take ownership of a thread removed from the buffer.
+    fn take(thread_ptr: *mut ReadyRef) -> ReadyRef {
+        let thread = unsafe { *Box::from_raw(thread_ptr) };
+        thread.0.set_run_queue(0);
+        thread
+    }
+
+    /// Address threads in this deque record as theirs.
+    fn address(&self) -> usize {
+        self as *const Self as usize
     }
 }
 
 impl LockFreeQueue {
     fn new() -> Self {
         let dummy = Box::into_raw(Box::new(QueueNode {
-            thread: None,
+            thread: MaybeUninit::uninit(),
             next: AtomicPtr::new(ptr::null_mut()),
         }));
 
         Self {
-            head: AtomicPtr::new(dummy),
-            tail: AtomicPtr::new(dummy),
+            head: AtomicTaggedPtr::new(dummy),
+            tail: AtomicTaggedPtr::new(dummy),
             size: AtomicUsize::new(0),
         }
     }
 
+    /// Address threads in this queue record as theirs.
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+
     fn push(&self, thread: ReadyRef) {
+        thread.0.set_run_queue(self.address());
         let new_node = Box::into_raw(Box::new(QueueNode {
-            thread: Some(thread),
+            thread: MaybeUninit::new(thread),
             next: AtomicPtr::new(ptr::null_mut()),
         }));
 
         loop {
             let tail = self.tail.load(Ordering::Acquire);
-            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+            let next = unsafe { (*tail.ptr()).next.load(Ordering::Acquire) };
 
-            if tail == self.tail.load(Ordering::Acquire) {
-                if next.is_null() {
-                    if unsafe { (*tail).next.compare_exchange_weak(
-                        ptr::null_mut(),
-                        new_node,
-                        Ordering::Release,
-                        Ordering::Relaxed
-                    ).is_ok() } {
-                        break;
-                    }
-                } else {
-                    let _ = self.tail.compare_exchange_weak(
+            // Re-check that tail hasn't moved while we read its next pointer
+            if tail != self.tail.load(Ordering::Acquire) {
+                continue;
+            }
+
+            if next.is_null() {
+                // Try to link new node at the end of the list
+                if unsafe { (*tail.ptr()).next.compare_exchange_weak(
+                    ptr::null_mut(),
+                    new_node,
+                    Ordering::Release,  // Success: synchronizes with acquire in try_pop
+                    Ordering::Relaxed   // Failure: just retry
+                ).is_ok() } {
+                    // Swing tail to the new node; if this fails another
+                    // thread already helped it along
+                    let _ = self.tail.compare_exchange(
                         tail,
-                        next,
+                        tail.advance(new_node),
                         Ordering::Release,
                         Ordering::Relaxed
                     );
+                    break;
                 }
+            } else {
+                // Tail was lagging, try to advance it
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    tail.advance(next),
+                    Ordering::Release,
+                    Ordering::Relaxed
+                );
             }
         }
 
-        let _ = self.tail.compare_exchange_weak(
-            self.tail.load(Ordering::Acquire),
-            new_node,
-            Ordering::Release,
-            Ordering::Relaxed
-        );
-        
         self.size.fetch_add(1, Ordering::AcqRel);
     }
 
@@ -416,40 +614,54 @@ impl LockFreeQueue {
         loop {
             let head = self.head.load(Ordering::Acquire);
             let tail = self.tail.load(Ordering::Acquire);
-            let next = unsafe { (*head).next.load(Ordering::Acquire) };
+            let next = unsafe { (*head.ptr()).next.load(Ordering::Acquire) };
 
-            if head == self.head.load(Ordering::Acquire) {
-                if head == tail {
-                    if next.is_null() {
-                        return None;
-                    }
-                    let _ = self.tail.compare_exchange_weak(
-                        tail,
-                        next,
-                        Ordering::Release,
-                        Ordering::Relaxed
-                    );
-                } else {
-                    if next.is_null() {
-                        continue;
-                    }
+            // Re-check that head hasn't moved while we read its next pointer
+            if head != self.head.load(Ordering::Acquire) {
+                continue;
+            }
 
-                    let thread = unsafe { (*next).thread.take() };
-                    
-                    if self.head.compare_exchange_weak(
-                        head,
-                        next,
-                        Ordering::Release,
-                        Ordering::Relaxed
-                    ).is_ok() {
-                        unsafe {
-                            drop(Box::from_raw(head));
-                        }
-                        self.size.fetch_sub(1, Ordering::AcqRel);
-                        return thread;
-                    }
+            if head.ptr() == tail.ptr() {
+                if next.is_null() {
+                    return None; // Queue is definitely empty
                 }
+                // Queue appears empty but tail is lagging, help advance tail
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    tail.advance(next),
+                    Ordering::Release,
+                    Ordering::Relaxed
+                );
+                continue;
             }
+
+            if next.is_null() {
+                continue; // Inconsistent state, retry
+            }
+
+            // Copy the thread out before the CAS: afterwards `next` is the
+            // dummy and another pop may free it. The copy only becomes ours
+            // if the CAS succeeds; the tag makes it fail if `head` was
+            // popped and recycled in the meantime.
+            let thread = unsafe { ptr::read((*next).thread.as_ptr()) };
+
+            if self.head.compare_exchange(
+                head,
+                head.advance(next),
+                Ordering::AcqRel,
+                Ordering::Relaxed
+            ).is_ok() {
+                // The old dummy is unreachable now
+                unsafe {
+                    drop(Box::from_raw(head.ptr()));
+                }
+                self.size.fetch_sub(1, Ordering::AcqRel);
+                thread.0.set_run_queue(0);
+                return Some(thread);
+            }
+
+            // Another pop took this thread
+            core::mem::forget(thread);
         }
     }
 }
@@ -473,10 +685,11 @@ impl Drop for LockFreeQueue {
     fn drop(&mut self) {
         while self.try_pop().is_some() {}
         
+        // The dummy's thread has been moved out, so only the node is freed
         let head = self.head.load(Ordering::Acquire);
         if !head.is_null() {
             unsafe {
-                drop(Box::from_raw(head));
+                drop(Box::from_raw(head.ptr()));
             }
         }
     }
@@ -500,6 +713,17 @@ mod tests {
         assert_eq!(blocked, 0);
     }
 
+    #[test]
+    fn test_cache_aware_steal_order() {
+        // 8 CPUs, 2 threads per core, 4 CPUs per LLC
+        let topology = CacheTopology::uniform(8, 2, 4);
+        let scheduler = WorkStealingScheduler::with_topology(8, &topology);
+
+        assert_eq!(&*scheduler.steal_order[0], &[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(&*scheduler.steal_order[5], &[4, 6, 7, 0, 1, 2, 3]);
+        assert_eq!(scheduler.cache_local_victims[5], 3);
+    }
+
     #[test]
     fn test_deque_creation() {
         let deque = WorkStealingDeque::new();
@@ -510,4 +734,56 @@ mod tests {
             _ => panic!("Expected empty deque"),
         }
     }
+
+    #[test]
+    fn test_empty_run_queue_snapshot() {
+        let scheduler = WorkStealingScheduler::new(2);
+        assert!(scheduler.run_queue_snapshot().is_empty());
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_overloaded_cpu_pushes_on_tick() {
+        use crate::mem::{StackPool, StackSizeClass};
+        use crate::thread_new::Thread;
+
+        let scheduler = WorkStealingScheduler::with_topology(2, &CacheTopology::uniform(2, 1, 2));
+        let pool = StackPool::new();
+        for id in 49_710..49_718 {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            let (thread, _) = Thread::new(ThreadId::new(id), stack, || {}, 128);
+            assert!(scheduler.work_deques[0].push(ReadyRef(thread)));
+        }
+
+        // The burst has to show in the average before anything moves
+        scheduler.balance_tick(0);
+        assert_eq!(scheduler.pushed_threads(), 0);
+        for _ in 0..20 {
+            scheduler.balance_tick(0);
+        }
+        // Until the queues are even, although the averages still differ
+        assert_eq!(scheduler.pushed_threads(), 4);
+        assert_eq!(scheduler.remote_queues[1].len(), 4);
+        assert_eq!(scheduler.work_deques[0].size.load(Ordering::Acquire), 4);
+        assert!(scheduler.load(0).unwrap().predicted() > scheduler.load(1).unwrap().predicted());
+        assert!(scheduler.pick_next(1).is_some());
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_cross_llc_steal_counted() {
+        use crate::mem::{StackPool, StackSizeClass};
+        use crate::thread_new::Thread;
+
+        // Each CPU in its own LLC domain
+        let scheduler = WorkStealingScheduler::with_topology(2, &CacheTopology::uniform(2, 1, 1));
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let (thread, _) = Thread::new(ThreadId::new(49_734), stack, || {}, 128);
+        assert!(scheduler.work_deques[1].push(ReadyRef(thread)));
+
+        let before = PERF_COUNTERS.cross_llc_steals.load(Ordering::Relaxed);
+        assert!(scheduler.try_steal_work(0).is_some());
+        assert_eq!(PERF_COUNTERS.cross_llc_steals.load(Ordering::Relaxed), before + 1);
+    }
 }
\ No newline at end of file
@@ -1,17 +1,31 @@
-use crate::error::{ThreadError, ThreadResult};
-use crate::thread::{Thread, ThreadId, ThreadState};
+//! Original global scheduler API, backed by [`sched`](crate::sched).
+//!
+//! [`SCHEDULER`] keeps the calls of the first scheduler: threads are
+//! spawned onto it by index, [`Scheduler::schedule`] picks the highest
+//! priority one and [`Scheduler::switch_context`] switches to it. Ready threads queue in the
+//! [global scheduler](crate::sched::global), the same run queues that
+//! preemption checkpoints and timer ticks use, and switches take the same
+//! path as the kernel's. The current thread is the one published in the
+//! CPU's slot, so [`thread_new::current`] works from threads started here
+//! and a switch made by a timer tick is seen here too. This needs the
+//! `std-shim` feature to allocate those threads; without it this module is
+//! the original implementation from [`legacy`](crate::legacy).
+
+use crate::arch::percpu::cpu_id;
+use crate::errors::{InvalidOperationError, MemoryError, ScheduleError, SpawnError, ThreadError, ThreadResult};
+use crate::sched::global_scheduler;
+use crate::thread::{from_new_id, Thread, ThreadId, ThreadState};
+use crate::thread_new::{self, ReadyRef, RunningRef};
 use core::cell::UnsafeCell;
+use core::cmp::Reverse;
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
 
 const MAX_THREADS: usize = 32;
 
 pub struct Scheduler {
-    threads: [Option<Thread>; MAX_THREADS],
-    current_thread: Option<ThreadId>,
+    threads: BTreeMap<ThreadId, Thread>,
     next_thread_id: ThreadId,
-    run_queue: [Option<ThreadId>; MAX_THREADS],
-    run_queue_head: usize,
-    run_queue_tail: usize,
-    run_queue_count: usize,
 }
 
 pub struct SchedulerCell(UnsafeCell<Scheduler>);
@@ -48,13 +62,8 @@ impl Default for Scheduler {
 impl Scheduler {
     pub const fn new() -> Self {
         Scheduler {
-            threads: [const { None }; MAX_THREADS],
-            current_thread: None,
+            threads: BTreeMap::new(),
             next_thread_id: 0,
-            run_queue: [None; MAX_THREADS],
-            run_queue_head: 0,
-            run_queue_tail: 0,
-            run_queue_count: 0,
         }
     }
 
@@ -67,214 +76,202 @@ impl Scheduler {
         let thread_id = self.next_thread_id;
 
         if thread_id >= MAX_THREADS {
-            return Err(ThreadError::MaxThreadsReached);
+            return Err(ThreadError::Spawn(SpawnError::TooManyThreads));
         }
 
         let thread = Thread::new(thread_id, stack, entry_point, priority);
-        self.threads[thread_id] = Some(thread);
+        let ready = ReadyRef(thread.inner().clone());
+        self.threads.insert(thread_id, thread);
         self.next_thread_id += 1;
 
-        self.enqueue_thread(thread_id)?;
+        global_scheduler().enqueue(ready);
         Ok(thread_id)
     }
 
-    fn enqueue_thread(&mut self, thread_id: ThreadId) -> ThreadResult<()> {
-        if self.run_queue_count >= MAX_THREADS {
-            return Err(ThreadError::SchedulerFull);
+    fn enqueue_thread(&mut self, thread_id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&thread_id) {
+            thread.set_state(ThreadState::Ready);
+            global_scheduler().enqueue(ReadyRef(thread.inner().clone()));
         }
-
-        self.run_queue[self.run_queue_tail] = Some(thread_id);
-        self.run_queue_tail = (self.run_queue_tail + 1) % MAX_THREADS;
-        self.run_queue_count += 1;
-        Ok(())
     }
 
     pub fn schedule(&mut self) -> Option<ThreadId> {
-        if let Some(current) = self.current_thread {
-            if let Some(thread) = &mut self.threads[current] {
-                if thread.state == ThreadState::Running {
-                    // Set back to Ready when yielding
-                    thread.state = ThreadState::Ready;
-                    let _ = self.enqueue_thread(current);
-                }
-            }
+        // Take in states assigned to threads and switches made elsewhere
+        for thread in self.threads.values_mut() {
+            thread.sync_state();
         }
 
-        self.schedule_with_priority()
-    }
-
-    fn schedule_with_priority(&mut self) -> Option<ThreadId> {
-        if self.run_queue_count == 0 {
-            return None;
-        }
-
-        let mut best_thread = None;
-        let mut highest_priority = 0u8;
-        let mut best_index = None;
-
-        // First pass: find the highest priority
-        for i in 0..self.run_queue_count {
-            let queue_index = (self.run_queue_head + i) % MAX_THREADS;
-
-            if let Some(thread_id) = self.run_queue[queue_index] {
-                if let Some(thread) = &self.threads[thread_id] {
-                    if thread.is_runnable() && thread.priority > highest_priority {
-                        highest_priority = thread.priority;
-                    }
-                }
+        if let Some(current) = self.get_current_thread() {
+            if self.threads.get(&current).map(|thread| thread.state) == Some(ThreadState::Running) {
+                // Set back to Ready when yielding
+                self.enqueue_thread(current);
             }
         }
 
-        // Second pass: find the first thread with highest priority (round-robin for equal priorities)
-        for i in 0..self.run_queue_count {
-            let queue_index = (self.run_queue_head + i) % MAX_THREADS;
-
-            if let Some(thread_id) = self.run_queue[queue_index] {
-                if let Some(thread) = &self.threads[thread_id] {
-                    if thread.is_runnable() && thread.priority == highest_priority {
-                        best_thread = Some(thread_id);
-                        best_index = Some(queue_index);
-                        break; // Take the first one we find for round-robin
-                    }
-                }
+        // Like the original scheduler, run the highest priority thread,
+        // the first queued among equals. Threads that stopped being
+        // runnable while queued are dropped, and the rest go back in the
+        // order they were queued, including threads not started here.
+        let scheduler = global_scheduler();
+        let mut queued = Vec::new();
+        while let Some(ready) = scheduler.pick_next(cpu_id()) {
+            match self.own_thread(&ready.0) {
+                Some(thread) if !thread.is_runnable() => {}
+                _ => queued.push(ready),
             }
         }
-
-        if let (Some(thread_id), Some(index)) = (best_thread, best_index) {
-            // Remove from queue and compact
-            self.run_queue[index] = None;
-
-            let mut read_pos = (index + 1) % MAX_THREADS;
-            let mut write_pos = index;
-
-            while read_pos != self.run_queue_tail {
-                self.run_queue[write_pos] = self.run_queue[read_pos];
-                self.run_queue[read_pos] = None;
-                write_pos = (write_pos + 1) % MAX_THREADS;
-                read_pos = (read_pos + 1) % MAX_THREADS;
-            }
-
-            self.run_queue_tail = write_pos;
-            self.run_queue_count -= 1;
-
-            return Some(thread_id);
+        let best = queued
+            .iter()
+            .enumerate()
+            .filter_map(|(index, ready)| Some((index, self.own_thread(&ready.0)?)))
+            .max_by_key(|&(index, thread)| (thread.priority, Reverse(index)))
+            .map(|(index, thread)| (index, thread.id));
+        if let Some((index, _)) = best {
+            queued.remove(index);
+        }
+        for ready in queued {
+            scheduler.enqueue(ready);
         }
+        best.map(|(_, id)| id)
+    }
 
-        None
+    /// Get the thread started through this scheduler that `thread` is
+    /// backed by. Threads of other schedulers with the same index aren't.
+    fn own_thread(&self, thread: &thread_new::Thread) -> Option<&Thread> {
+        from_new_id(thread.id())
+            .and_then(|id| self.threads.get(&id))
+            .filter(|own| own.inner().ptr_eq(thread))
     }
 
     pub fn get_current_thread(&self) -> Option<ThreadId> {
-        self.current_thread
+        thread_new::current().and_then(|current| self.own_thread(&current).map(|thread| thread.id))
     }
 
     pub fn set_current_thread(&mut self, thread_id: Option<ThreadId>) {
-        if let Some(old_id) = self.current_thread {
-            if let Some(thread) = &mut self.threads[old_id] {
+        if let Some(old_id) = self.get_current_thread() {
+            if let Some(thread) = self.threads.get_mut(&old_id) {
+                thread.sync_state();
                 if thread.state == ThreadState::Running {
-                    thread.state = ThreadState::Ready;
+                    thread.set_state(ThreadState::Ready);
                 }
             }
         }
 
-        self.current_thread = thread_id;
-
-        if let Some(new_id) = thread_id {
-            if let Some(thread) = &mut self.threads[new_id] {
-                thread.state = ThreadState::Running;
+        match thread_id.and_then(|id| self.threads.get_mut(&id)) {
+            Some(thread) => {
+                let running = ReadyRef(thread.inner().clone()).start_running();
+                thread.set_state(ThreadState::Running);
+                thread_new::percpu::set_current(running.0);
             }
+            None => thread_new::percpu::clear_current(),
         }
     }
 
     pub fn exit_current_thread(&mut self) {
-        if let Some(current) = self.current_thread {
-            let mut waiters_to_wake = [None; 4];
-
-            if let Some(thread) = &mut self.threads[current] {
-                thread.state = ThreadState::Finished;
-                waiters_to_wake = thread.join_waiters;
+        let Some(current) = self.get_current_thread() else {
+            return;
+        };
+        let waiters = match self.threads.get_mut(&current) {
+            Some(thread) => {
+                RunningRef(thread.inner().clone()).finish();
+                thread.set_state(ThreadState::Finished);
+                thread.join_waiters
             }
+            None => return,
+        };
 
-            // Wake up any threads waiting to join this one
-            for waiter in waiters_to_wake.iter().flatten() {
-                if let Some(waiter_thread) = &mut self.threads[*waiter] {
-                    if waiter_thread.state == ThreadState::Blocked {
-                        waiter_thread.state = ThreadState::Ready;
-                        let _ = self.enqueue_thread(*waiter);
-                    }
-                }
+        // Wake up any threads waiting to join this one
+        for waiter in waiters.iter().flatten() {
+            let Some(thread) = self.threads.get_mut(waiter) else {
+                continue;
+            };
+            thread.sync_state();
+            if thread.state == ThreadState::Blocked {
+                self.enqueue_thread(*waiter);
             }
         }
     }
 
     pub fn join_thread(&mut self, target_id: ThreadId, current_id: ThreadId) -> ThreadResult<()> {
-        if target_id >= MAX_THREADS {
-            return Err(ThreadError::InvalidThreadId);
+        let Some(target_thread) = self.threads.get_mut(&target_id) else {
+            return Err(ThreadError::InvalidOperation(InvalidOperationError::InvalidThreadId));
+        };
+        target_thread.sync_state();
+        if target_thread.state == ThreadState::Finished {
+            return Ok(()); // Already finished
         }
 
-        if let Some(target_thread) = &mut self.threads[target_id] {
-            if target_thread.state == ThreadState::Finished {
-                return Ok(()); // Already finished
-            }
-
-            // Add current thread to join waiters
-            for slot in &mut target_thread.join_waiters {
-                if slot.is_none() {
-                    *slot = Some(current_id);
-
-                    // Block current thread
-                    if let Some(current_thread) = &mut self.threads[current_id] {
-                        current_thread.state = ThreadState::Blocked;
-                    }
-
-                    return Ok(());
-                }
-            }
+        // Add current thread to join waiters
+        let Some(slot) = target_thread.join_waiters.iter_mut().find(|slot| slot.is_none()) else {
+            return Err(ThreadError::Schedule(ScheduleError::QueueFull));
+        };
+        *slot = Some(current_id);
 
-            Err(ThreadError::SchedulerFull)
-        } else {
-            Err(ThreadError::InvalidThreadId)
+        // Block current thread
+        if let Some(current_thread) = self.threads.get_mut(&current_id) {
+            current_thread.set_state(ThreadState::Blocked);
         }
+        Ok(())
     }
 
     pub fn get_thread(&self, thread_id: ThreadId) -> Option<&Thread> {
-        if thread_id >= MAX_THREADS {
-            return None;
-        }
-        self.threads[thread_id].as_ref()
+        self.threads.get(&thread_id)
     }
 
     pub fn get_thread_mut(&mut self, thread_id: ThreadId) -> Option<&mut Thread> {
-        if thread_id >= MAX_THREADS {
-            return None;
-        }
-        self.threads[thread_id].as_mut()
+        self.threads.get_mut(&thread_id)
     }
 
     pub fn switch_context(&mut self, from_id: ThreadId, to_id: ThreadId) -> ThreadResult<()> {
-        if let Some(from_thread) = self.get_thread(from_id) {
-            if from_thread.check_stack_overflow() {
-                return Err(ThreadError::StackOverflow);
-            }
-        }
-
-        let from_thread = self.get_thread_mut(from_id);
-        let from_context = if let Some(thread) = from_thread {
-            &mut thread.context as *mut _
-        } else {
-            return Err(ThreadError::InvalidThreadId);
-        };
-
-        let to_thread = self.get_thread_mut(to_id);
-        let to_context = if let Some(thread) = to_thread {
-            &thread.context as *const _
-        } else {
-            return Err(ThreadError::InvalidThreadId);
+        let (Some(from_thread), Some(to_thread)) = (self.threads.get(&from_id), self.threads.get(&to_id)) else {
+            return Err(ThreadError::InvalidOperation(InvalidOperationError::InvalidThreadId));
         };
+        if from_thread.check_stack_overflow() {
+            return Err(ThreadError::Memory(MemoryError::StackOverflow));
+        }
 
         unsafe {
-            crate::context::switch_context(from_context, to_context);
+            crate::perf::context_switch_opt::switch_threads(from_thread.inner(), to_thread.inner());
         }
 
         Ok(())
     }
 }
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, vec};
+
+    fn stack() -> &'static mut [u8] {
+        Box::leak(vec![0u8; 16 * 1024].into_boxed_slice())
+    }
+
+    #[test]
+    fn test_legacy_api_on_new_internals() {
+        let _slot = thread_new::percpu::hold_slot();
+        let mut scheduler = Scheduler::new();
+        let first = scheduler.spawn_thread(stack(), || {}, 128).unwrap();
+        let second = scheduler.spawn_thread(stack(), || {}, 128).unwrap();
+        assert_eq!((first, second), (0, 1));
+
+        // Threads are backed by registered thread_new threads
+        let inner_id = scheduler.get_thread(first).unwrap().inner().id();
+        assert_eq!(from_new_id(inner_id), Some(first));
+        assert!(thread_new::registry::lookup(inner_id).is_some());
+
+        assert_eq!(scheduler.schedule(), Some(first));
+        scheduler.set_current_thread(Some(first));
+        assert_eq!(scheduler.get_thread(first).unwrap().state, ThreadState::Running);
+        assert_eq!(thread_new::current().map(|t| t.id()), Some(inner_id));
+
+        // The first thread waits for the second, which runs until it exits
+        scheduler.join_thread(second, first).unwrap();
+        assert_eq!(scheduler.schedule(), Some(second));
+        scheduler.set_current_thread(Some(second));
+        scheduler.exit_current_thread();
+        assert_eq!(scheduler.get_thread(second).unwrap().state, ThreadState::Finished);
+        assert_eq!(scheduler.schedule(), Some(first));
+        assert_eq!(scheduler.join_thread(second, first), Ok(()));
+    }
+}
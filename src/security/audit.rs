@@ -3,6 +3,7 @@
 use crate::errors::ThreadError;
 use crate::security::{SecurityConfig, SecurityViolation};
 use crate::thread_new::ThreadId;
+use crate::time::SystemTime;
 use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
 use alloc::{collections::VecDeque, string::String, vec::Vec, format};
 use core::fmt::Write;
@@ -218,6 +219,7 @@ impl AuditLogger {
     fn get_current_context(&self) -> AuditContext {
         AuditContext {
             timestamp: crate::time::get_monotonic_time().as_nanos() as u64,
+            wall_time: crate::time::WALL_CLOCK.now(),
             current_thread: crate::thread_new::current_thread_id(),
             cpu_id: 0, // Would be determined from current CPU
             interrupt_context: false, // Would check if in interrupt handler
@@ -294,9 +296,14 @@ impl AuditEvent {
     
     /// Convert event to JSON format.
     fn to_json(&self) -> String {
+        let wall_time = match self.context.wall_time {
+            Some(time) => format!(",\"wall_time\":\"{}\"", time),
+            None => String::new(),
+        };
         format!(
-            "{{\"timestamp\":{},\"level\":\"{:?}\",\"thread_id\":{},\"event\":\"{}\"}}",
+            "{{\"timestamp\":{}{},\"level\":\"{:?}\",\"thread_id\":{},\"event\":\"{}\"}}",
             self.context.timestamp,
+            wall_time,
             self.level,
             self.context.current_thread,
             self.event_type.description()
@@ -417,6 +424,8 @@ pub enum AuditLevel {
 #[derive(Debug, Clone)]
 pub struct AuditContext {
     pub timestamp: u64,
+    /// Real-world time of the event, if the wall clock has been set
+    pub wall_time: Option<SystemTime>,
     pub current_thread: ThreadId,
     pub cpu_id: u32,
     pub interrupt_context: bool,
@@ -444,6 +453,10 @@ pub enum MemoryOperation {
     Map,
     Unmap,
     Access,
+    /// Access to shared memory granted to a domain
+    Grant,
+    /// Access to shared memory revoked from a domain
+    Revoke,
 }
 
 /// Scheduler event types.
@@ -638,6 +651,14 @@ pub fn log_memory_event(operation: MemoryOperation, address: usize, size: usize)
     }
 }
 
+/// Log a change of `domain`'s access to the memory at `address`.
+pub fn log_grant_event(operation: MemoryOperation, address: usize, size: usize, domain: ThreadId) {
+    // Safety: as for the other loggers of `AUDIT_LOGGER`
+    if let Some(logger) = unsafe { &mut *core::ptr::addr_of_mut!(AUDIT_LOGGER) } {
+        logger.log_memory_event(operation, address, size, Some(domain));
+    }
+}
+
 /// Log scheduler event.
 pub fn log_scheduler_event(event_type: SchedulerEventType, thread_id: Option<ThreadId>, details: &str) {
     unsafe {
@@ -1,10 +1,45 @@
 //! Control Flow Integrity (CFI) implementation for hardened execution.
+//!
+//! Forward edges: thread entry points and interrupt handlers are recorded
+//! in a table of allowed targets when they are installed, and checked
+//! against it right before the runtime jumps to them, so a function
+//! pointer overwritten in between is caught. Saved program counters are
+//! checked against the registered code regions when a context is restored.
+//!
+//! Checks only run while CFI is enabled in `SECURITY_STATE`.
 
 use crate::errors::ThreadError;
-use crate::security::{SecurityConfig, SecurityViolation, handle_security_violation};
+use crate::security::{SecurityConfig, SecurityViolation, SECURITY_STATE, handle_security_violation};
 use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::arch::asm;
 
+/// Log2 of the number of slots in the allowed-target table.
+const TARGET_TABLE_BITS: u32 = 10;
+/// Number of slots in the allowed-target table.
+pub const MAX_CFI_TARGETS: usize = 1 << TARGET_TABLE_BITS;
+/// Number of code regions that can be registered.
+pub const MAX_CODE_REGIONS: usize = 16;
+
+/// Target table slot that was never used; ends a probe sequence.
+const EMPTY: usize = 0;
+/// Target table slot whose target was unregistered; probes continue past it.
+const REMOVED: usize = 1;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: AtomicUsize = AtomicUsize::new(EMPTY);
+
+/// A `[start, end)` range of executable addresses (`end == 0` = unused).
+struct CodeRegion {
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNUSED_REGION: CodeRegion = CodeRegion {
+    start: AtomicUsize::new(0),
+    end: AtomicUsize::new(0),
+};
+
 /// CFI protection implementation.
 pub struct CfiProtection {
     /// CFI violations detected
@@ -13,6 +48,14 @@ pub struct CfiProtection {
     calls_verified: AtomicU64,
     /// Indirect calls protected
     indirect_calls_protected: AtomicU64,
+    /// Allowed forward-edge targets, open addressing with linear probing
+    targets: [AtomicUsize; MAX_CFI_TARGETS],
+    /// Registered targets
+    target_count: AtomicUsize,
+    /// Executable regions saved program counters must fall in
+    regions: [CodeRegion; MAX_CODE_REGIONS],
+    /// Region slots handed out
+    region_count: AtomicUsize,
 }
 
 impl CfiProtection {
@@ -21,6 +64,146 @@ impl CfiProtection {
             violations_detected: AtomicUsize::new(0),
             calls_verified: AtomicU64::new(0),
             indirect_calls_protected: AtomicU64::new(0),
+            targets: [EMPTY_SLOT; MAX_CFI_TARGETS],
+            target_count: AtomicUsize::new(0),
+            regions: [UNUSED_REGION; MAX_CODE_REGIONS],
+            region_count: AtomicUsize::new(0),
+        }
+    }
+    
+    /// First slot to probe for `addr`.
+    fn home_slot(addr: usize) -> usize {
+        ((addr as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - TARGET_TABLE_BITS)) as usize
+    }
+    
+    /// Allow `target` as a forward-edge target.
+    ///
+    /// Registering the same target twice is a no-op. Fails if the table is
+    /// full.
+    pub fn register_target(&self, target: *const ()) -> Result<(), ThreadError> {
+        let addr = target as usize;
+        if addr <= REMOVED {
+            return Err(ThreadError::InvalidState());
+        }
+        
+        let home = Self::home_slot(addr);
+        for probe in 0..MAX_CFI_TARGETS {
+            let slot = &self.targets[(home + probe) % MAX_CFI_TARGETS];
+            let mut current = slot.load(Ordering::Acquire);
+            if current == EMPTY {
+                match slot.compare_exchange(EMPTY, addr, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => {
+                        self.target_count.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    // Lost the slot; it may have gone to the same target
+                    Err(winner) => current = winner,
+                }
+            }
+            if current == addr {
+                return Ok(());
+            }
+        }
+        
+        Err(ThreadError::ResourceExhaustion())
+    }
+    
+    /// Stop allowing `target`. Returns `false` if it was not registered.
+    pub fn unregister_target(&self, target: *const ()) -> bool {
+        let Some(slot) = self.find_target(target as usize) else {
+            return false;
+        };
+        if slot.compare_exchange(target as usize, REMOVED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return false;
+        }
+        self.target_count.fetch_sub(1, Ordering::Relaxed);
+        true
+    }
+    
+    /// Check whether `target` is a registered forward-edge target.
+    pub fn is_registered_target(&self, target: *const ()) -> bool {
+        self.find_target(target as usize).is_some()
+    }
+    
+    /// Get the number of registered targets.
+    pub fn target_count(&self) -> usize {
+        self.target_count.load(Ordering::Relaxed)
+    }
+    
+    fn find_target(&self, addr: usize) -> Option<&AtomicUsize> {
+        if addr <= REMOVED {
+            return None;
+        }
+        
+        let home = Self::home_slot(addr);
+        for probe in 0..MAX_CFI_TARGETS {
+            let slot = &self.targets[(home + probe) % MAX_CFI_TARGETS];
+            match slot.load(Ordering::Acquire) {
+                EMPTY => return None,
+                current if current == addr => return Some(slot),
+                _ => {}
+            }
+        }
+        None
+    }
+    
+    /// Register `[start, end)` as executable code.
+    pub fn register_code_region(&self, start: usize, end: usize) -> Result<(), ThreadError> {
+        if start >= end {
+            return Err(ThreadError::InvalidState());
+        }
+        
+        let index = self.region_count.fetch_add(1, Ordering::AcqRel);
+        let Some(region) = self.regions.get(index) else {
+            self.region_count.fetch_sub(1, Ordering::AcqRel);
+            return Err(ThreadError::ResourceExhaustion());
+        };
+        
+        // `end` publishes the region
+        region.start.store(start, Ordering::Relaxed);
+        region.end.store(end, Ordering::Release);
+        Ok(())
+    }
+    
+    /// Check whether `addr` lies in a registered code region.
+    ///
+    /// Always `true` while no region is registered, since the code layout
+    /// is then unknown.
+    pub fn in_code_region(&self, addr: usize) -> bool {
+        let mut any = false;
+        for region in &self.regions {
+            let end = region.end.load(Ordering::Acquire);
+            if end == 0 {
+                continue;
+            }
+            any = true;
+            if addr >= region.start.load(Ordering::Relaxed) && addr < end {
+                return true;
+            }
+        }
+        !any
+    }
+    
+    /// Check a forward-edge target before jumping to it, counting a
+    /// violation on failure.
+    pub fn check_forward_edge(&self, target: *const ()) -> bool {
+        if self.is_registered_target(target) && self.in_code_region(target as usize) {
+            self.calls_verified.fetch_add(1, Ordering::Relaxed);
+            true
+        } else {
+            self.violations_detected.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+    
+    /// Check a saved program counter before restoring it, counting a
+    /// violation on failure.
+    pub fn check_saved_pc(&self, pc: usize) -> bool {
+        if pc != 0 && self.in_code_region(pc) {
+            true
+        } else {
+            self.violations_detected.fetch_add(1, Ordering::Relaxed);
+            false
         }
     }
 }
@@ -386,12 +569,7 @@ fn is_valid_code_address(addr: *const ()) -> bool {
         return false;
     }
     
-    // In a real implementation, this would check:
-    // - If address is in executable memory region
-    // - If address is in valid code segment
-    // - If address is not in data/heap/stack segments
-    
-    true
+    CFI_PROTECTION.in_code_region(address)
 }
 
 /// Verify return address is valid for given caller.
@@ -420,6 +598,7 @@ pub struct CfiStats {
     pub violations_detected: usize,
     pub calls_verified: u64,
     pub indirect_calls_protected: u64,
+    pub registered_targets: usize,
     pub protection_enabled: bool,
 }
 
@@ -435,13 +614,63 @@ pub fn init_cfi_protection(_config: SecurityConfig) -> Result<(), ThreadError> {
     Ok(())
 }
 
+/// Whether forward-edge and restore checks are enforced.
+fn cfi_enforced() -> bool {
+    SECURITY_STATE.cfi_enabled.load(Ordering::Relaxed)
+}
+
+/// Allow `target` as a thread entry point or callback.
+pub fn register_cfi_target(target: *const ()) -> Result<(), ThreadError> {
+    CFI_PROTECTION.register_target(target)
+}
+
+/// Stop allowing `target`.
+pub fn unregister_cfi_target(target: *const ()) -> bool {
+    CFI_PROTECTION.unregister_target(target)
+}
+
+/// Register `[start, end)` as executable code for saved-PC checks.
+pub fn register_code_region(start: usize, end: usize) -> Result<(), ThreadError> {
+    CFI_PROTECTION.register_code_region(start, end)
+}
+
+/// Register `target` as trusted at the point it is installed.
+///
+/// Done regardless of enforcement, so enabling CFI later does not reject
+/// entry points installed before. A full table is not fatal here; the
+/// check before the jump reports it.
+pub(crate) fn allow_target(target: *const ()) {
+    let _ = CFI_PROTECTION.register_target(target);
+}
+
+/// Verify a thread entry point or callback right before jumping to it.
+///
+/// Raises a CFI violation if CFI is enforced and `target` was never
+/// registered.
+pub fn verify_forward_edge(target: *const ()) {
+    if cfi_enforced() && !CFI_PROTECTION.check_forward_edge(target) {
+        handle_security_violation(SecurityViolation::CfiViolation);
+    }
+}
+
+/// Verify the program counter a context is about to resume at.
+///
+/// Raises a CFI violation if CFI is enforced and `pc` is outside every
+/// registered code region.
+pub fn verify_saved_pc(pc: usize) {
+    if cfi_enforced() && !CFI_PROTECTION.check_saved_pc(pc) {
+        handle_security_violation(SecurityViolation::CfiViolation);
+    }
+}
+
 /// Get CFI protection statistics.
 pub fn get_cfi_stats() -> CfiStats {
     CfiStats {
         violations_detected: CFI_PROTECTION.violations_detected.load(Ordering::Relaxed),
         calls_verified: CFI_PROTECTION.calls_verified.load(Ordering::Relaxed),
         indirect_calls_protected: CFI_PROTECTION.indirect_calls_protected.load(Ordering::Relaxed),
-        protection_enabled: true, // Would check actual CFI state
+        registered_targets: CFI_PROTECTION.target_count(),
+        protection_enabled: cfi_enforced(),
     }
 }
 
@@ -465,4 +694,43 @@ macro_rules! return_address_guard {
     ($caller:expr) => {
         let _guard = crate::security::cfi::ReturnAddressGuard::new($caller as *const ());
     };
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn entry_a() {}
+    fn entry_b() {}
+    
+    #[test]
+    fn test_forward_edge_table() {
+        let cfi = CfiProtection::new();
+        let a = entry_a as fn() as *const ();
+        let b = entry_b as fn() as *const ();
+        
+        assert!(!cfi.check_forward_edge(a));
+        cfi.register_target(a).unwrap();
+        cfi.register_target(a).unwrap();
+        assert_eq!(cfi.target_count(), 1);
+        assert!(cfi.check_forward_edge(a));
+        assert!(!cfi.check_forward_edge(b));
+        
+        // Removed slots keep later probes reachable
+        cfi.register_target(b).unwrap();
+        assert!(cfi.unregister_target(a));
+        assert!(!cfi.is_registered_target(a));
+        assert!(cfi.is_registered_target(b));
+        assert!(!cfi.unregister_target(a));
+        
+        // Without regions any non-null PC passes; with them, only inside
+        assert!(cfi.check_saved_pc(0x4000));
+        assert!(!cfi.check_saved_pc(0));
+        cfi.register_code_region(0x1000, 0x2000).unwrap();
+        assert!(cfi.check_saved_pc(0x1800));
+        assert!(!cfi.check_saved_pc(0x4000));
+        assert!(!cfi.check_forward_edge(b));
+        assert!(cfi.register_code_region(0x3000, 0x3000).is_err());
+        
+        assert_eq!(cfi.violations_detected.load(Ordering::Relaxed), 5);
+    }
+}
@@ -0,0 +1,564 @@
+//! Message-passing IPC between isolation domains.
+//!
+//! A domain that offers a service binds an [`IpcEndpoint`]. Clients
+//! [`connect`](IpcEndpoint::connect) to it and the owner
+//! [`accept`](IpcEndpoint::accept)s the connection; both steps check the
+//! isolation policy of the side initiating them, so neither domain can be
+//! reached without its own configuration and the other's allowing IPC.
+//! Each connection is a pair of [`CrossDomainChannel`] ends.
+//!
+//! Channels never share memory between domains: messages are copied into a
+//! bounded queue on send and out of it on receive. Queue storage is
+//! allocated when the connection is made, so sending and receiving never
+//! allocate and only take interrupt-safe spin locks. The non-blocking
+//! `try_*` calls are therefore usable from softirq and threaded interrupt
+//! context; the blocking ones must only be called where the caller may
+//! sleep.
+//!
+//! ```ignore
+//! static SERVICE: IpcEndpoint = ...;
+//!
+//! // Client domain
+//! let channel = SERVICE.connect(client_id)?;
+//! channel.send(b"ping")?;
+//!
+//! // Service domain
+//! let channel = SERVICE.accept()?;
+//! let mut buffer = [0; 64];
+//! let len = channel.recv(&mut buffer)?;
+//! ```
+
+use super::isolation::check_ipc_access;
+use super::{SecurityViolation, SECURITY_STATE};
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{self, BlockedOn, ThreadId};
+use crate::time::{Duration, TIMER_WHEEL};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Errors returned by IPC operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// The isolation policy of one of the domains forbids the connection
+    PermissionDenied,
+    /// The queue is full (send) or empty (receive)
+    WouldBlock,
+    /// The message is larger than the channel's maximum message size
+    MessageTooLarge,
+    /// The receive buffer is too small; holds the size of the next message,
+    /// which stays queued
+    BufferTooSmall(usize),
+    /// The other end was dropped, or the endpoint closed
+    Disconnected,
+    /// A blocking operation's timeout expired
+    TimedOut,
+    /// Capacity or message size is zero or too large
+    InvalidConfig,
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::PermissionDenied => write!(f, "IPC denied by isolation policy"),
+            IpcError::WouldBlock => write!(f, "operation would block"),
+            IpcError::MessageTooLarge => write!(f, "message exceeds channel maximum"),
+            IpcError::BufferTooSmall(needed) => write!(f, "receive buffer too small, need {} bytes", needed),
+            IpcError::Disconnected => write!(f, "channel disconnected"),
+            IpcError::TimedOut => write!(f, "operation timed out"),
+            IpcError::InvalidConfig => write!(f, "invalid channel configuration"),
+        }
+    }
+}
+
+/// Size of the queues of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    /// Messages each direction can hold
+    pub capacity: usize,
+    /// Largest message in bytes
+    pub max_message: usize,
+}
+
+impl ChannelConfig {
+    /// 16 messages of up to 256 bytes each way.
+    pub const DEFAULT: Self = Self {
+        capacity: 16,
+        max_message: 256,
+    };
+
+    fn storage(&self) -> Result<usize, IpcError> {
+        if self.capacity == 0 || self.max_message == 0 {
+            return Err(IpcError::InvalidConfig);
+        }
+        self.capacity.checked_mul(self.max_message).ok_or(IpcError::InvalidConfig)
+    }
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Traffic counters of one connection, both directions together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// Messages queued by either end
+    pub messages_sent: u64,
+    /// Messages taken by either end
+    pub messages_received: u64,
+    /// Payload bytes queued
+    pub bytes_sent: u64,
+    /// Payload bytes taken
+    pub bytes_received: u64,
+    /// Sends that found the queue full
+    pub full_rejections: u64,
+    /// Receives that found the queue empty
+    pub empty_polls: u64,
+    /// Blocking sends that had to wait
+    pub blocked_sends: u64,
+    /// Blocking receives that had to wait
+    pub blocked_receives: u64,
+}
+
+/// Fixed-slot ring of messages.
+struct MessageQueue {
+    data: Vec<u8>,
+    lens: Vec<usize>,
+    slot: usize,
+    head: usize,
+    len: usize,
+}
+
+impl MessageQueue {
+    fn new(config: ChannelConfig, storage: usize) -> Self {
+        Self {
+            data: vec![0; storage],
+            lens: vec![0; config.capacity],
+            slot: config.max_message,
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, message: &[u8]) -> bool {
+        if self.len == self.lens.len() {
+            return false;
+        }
+        let index = (self.head + self.len) % self.lens.len();
+        let start = index * self.slot;
+        self.data[start..start + message.len()].copy_from_slice(message);
+        self.lens[index] = message.len();
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self, buffer: &mut [u8]) -> Result<usize, IpcError> {
+        if self.len == 0 {
+            return Err(IpcError::WouldBlock);
+        }
+        let len = self.lens[self.head];
+        if buffer.len() < len {
+            return Err(IpcError::BufferTooSmall(len));
+        }
+        let start = self.head * self.slot;
+        buffer[..len].copy_from_slice(&self.data[start..start + len]);
+        self.head = (self.head + 1) % self.lens.len();
+        self.len -= 1;
+        Ok(len)
+    }
+}
+
+#[derive(Default)]
+struct StatsCounters {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    full_rejections: AtomicU64,
+    empty_polls: AtomicU64,
+    blocked_sends: AtomicU64,
+    blocked_receives: AtomicU64,
+}
+
+/// State shared by both ends of a connection.
+struct Connection {
+    /// Queue written by end `i`
+    queues: [SpinLockIrqSave<MessageQueue>; 2],
+    /// Domains of the two ends
+    domains: [ThreadId; 2],
+    max_message: usize,
+    closed: AtomicBool,
+    stats: StatsCounters,
+}
+
+/// One end of a connection between two isolation domains.
+///
+/// Dropping either end disconnects both; messages already queued for the
+/// other end can still be received.
+pub struct CrossDomainChannel {
+    connection: Arc<Connection>,
+    side: usize,
+}
+
+impl CrossDomainChannel {
+    /// Connect `sender` and `receiver` directly, without an endpoint.
+    ///
+    /// Both domains' policies must allow IPC with the other. Returns the
+    /// ends of `sender` and `receiver`, in that order.
+    pub fn pair(sender: ThreadId, receiver: ThreadId, config: ChannelConfig) -> Result<(Self, Self), IpcError> {
+        authorize(sender, receiver)?;
+        authorize(receiver, sender)?;
+        Self::connect_unchecked(sender, receiver, config)
+    }
+
+    fn connect_unchecked(first: ThreadId, second: ThreadId, config: ChannelConfig) -> Result<(Self, Self), IpcError> {
+        let storage = config.storage()?;
+        let connection = Arc::new(Connection {
+            queues: [
+                SpinLockIrqSave::new(MessageQueue::new(config, storage)),
+                SpinLockIrqSave::new(MessageQueue::new(config, storage)),
+            ],
+            domains: [first, second],
+            max_message: config.max_message,
+            closed: AtomicBool::new(false),
+            stats: StatsCounters::default(),
+        });
+        let other = Self {
+            connection: connection.clone(),
+            side: 1,
+        };
+        Ok((Self { connection, side: 0 }, other))
+    }
+
+    /// Get an identifier shared by both ends, as shown when a thread is
+    /// blocked on the connection.
+    pub fn id(&self) -> u64 {
+        Arc::as_ptr(&self.connection) as usize as u64
+    }
+
+    /// Get the domain this end belongs to.
+    pub fn local_domain(&self) -> ThreadId {
+        self.connection.domains[self.side]
+    }
+
+    /// Get the domain at the other end.
+    pub fn peer_domain(&self) -> ThreadId {
+        self.connection.domains[1 - self.side]
+    }
+
+    /// Check whether the other end is still there.
+    pub fn is_connected(&self) -> bool {
+        !self.connection.closed.load(Ordering::Acquire)
+    }
+
+    /// Get the largest message the channel carries.
+    pub fn max_message(&self) -> usize {
+        self.connection.max_message
+    }
+
+    /// Copy `message` into the queue without waiting.
+    pub fn try_send(&self, message: &[u8]) -> Result<(), IpcError> {
+        let result = self.push(message);
+        if result == Err(IpcError::WouldBlock) {
+            self.connection.stats.full_rejections.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Copy `message` into the queue, waiting for room.
+    pub fn send(&self, message: &[u8]) -> Result<(), IpcError> {
+        block_on(self.id(), None, &self.connection.stats.blocked_sends, || self.push(message))
+    }
+
+    /// Like [`send`](Self::send), giving up after `timeout`.
+    pub fn send_timeout(&self, message: &[u8], timeout: Duration) -> Result<(), IpcError> {
+        block_on(self.id(), Some(timeout), &self.connection.stats.blocked_sends, || self.push(message))
+    }
+
+    /// Copy the next message into `buffer` without waiting.
+    ///
+    /// Returns the length of the message.
+    pub fn try_recv(&self, buffer: &mut [u8]) -> Result<usize, IpcError> {
+        let result = self.pop(buffer);
+        if result == Err(IpcError::WouldBlock) {
+            self.connection.stats.empty_polls.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Copy the next message into `buffer`, waiting for one.
+    pub fn recv(&self, buffer: &mut [u8]) -> Result<usize, IpcError> {
+        block_on(self.id(), None, &self.connection.stats.blocked_receives, || self.pop(buffer))
+    }
+
+    /// Like [`recv`](Self::recv), giving up after `timeout`.
+    pub fn recv_timeout(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize, IpcError> {
+        block_on(self.id(), Some(timeout), &self.connection.stats.blocked_receives, || self.pop(buffer))
+    }
+
+    /// Get the connection's traffic counters.
+    pub fn stats(&self) -> ChannelStats {
+        let stats = &self.connection.stats;
+        ChannelStats {
+            messages_sent: stats.messages_sent.load(Ordering::Relaxed),
+            messages_received: stats.messages_received.load(Ordering::Relaxed),
+            bytes_sent: stats.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: stats.bytes_received.load(Ordering::Relaxed),
+            full_rejections: stats.full_rejections.load(Ordering::Relaxed),
+            empty_polls: stats.empty_polls.load(Ordering::Relaxed),
+            blocked_sends: stats.blocked_sends.load(Ordering::Relaxed),
+            blocked_receives: stats.blocked_receives.load(Ordering::Relaxed),
+        }
+    }
+
+    fn push(&self, message: &[u8]) -> Result<(), IpcError> {
+        if message.len() > self.connection.max_message {
+            return Err(IpcError::MessageTooLarge);
+        }
+        if !self.is_connected() {
+            return Err(IpcError::Disconnected);
+        }
+        if !self.connection.queues[self.side].lock().push(message) {
+            return Err(IpcError::WouldBlock);
+        }
+        let stats = &self.connection.stats;
+        stats.messages_sent.fetch_add(1, Ordering::Relaxed);
+        stats.bytes_sent.fetch_add(message.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn pop(&self, buffer: &mut [u8]) -> Result<usize, IpcError> {
+        // Check before popping so a message queued just before the close
+        // is never reported as a disconnect
+        let closed = !self.is_connected();
+        match self.connection.queues[1 - self.side].lock().pop(buffer) {
+            Ok(len) => {
+                let stats = &self.connection.stats;
+                stats.messages_received.fetch_add(1, Ordering::Relaxed);
+                stats.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+                Ok(len)
+            }
+            Err(IpcError::WouldBlock) if closed => Err(IpcError::Disconnected),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl Drop for CrossDomainChannel {
+    fn drop(&mut self) {
+        self.connection.closed.store(true, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for CrossDomainChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CrossDomainChannel")
+            .field("local", &self.local_domain())
+            .field("peer", &self.peer_domain())
+            .field("connected", &self.is_connected())
+            .finish()
+    }
+}
+
+/// Service endpoint that domains connect to.
+pub struct IpcEndpoint {
+    owner: ThreadId,
+    config: ChannelConfig,
+    /// Owner ends of connections not accepted yet
+    backlog: SpinLockIrqSave<VecDeque<CrossDomainChannel>>,
+    backlog_limit: usize,
+    closed: AtomicBool,
+}
+
+impl IpcEndpoint {
+    /// Create an endpoint owned by the domain of `owner`.
+    ///
+    /// Connections get queues sized by `config`; at most `backlog`
+    /// connections wait to be accepted.
+    pub fn new(owner: ThreadId, config: ChannelConfig, backlog: usize) -> Result<Self, IpcError> {
+        config.storage()?;
+        if backlog == 0 {
+            return Err(IpcError::InvalidConfig);
+        }
+        Ok(Self {
+            owner,
+            config,
+            backlog: SpinLockIrqSave::new(VecDeque::with_capacity(backlog)),
+            backlog_limit: backlog,
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Get the domain that owns the endpoint.
+    pub fn owner(&self) -> ThreadId {
+        self.owner
+    }
+
+    /// Get the number of connections waiting to be accepted.
+    pub fn pending(&self) -> usize {
+        self.backlog.lock().len()
+    }
+
+    /// Connect the domain of `client` to this endpoint.
+    ///
+    /// Fails with [`PermissionDenied`](IpcError::PermissionDenied), and
+    /// records an isolation violation against `client`, if the client's
+    /// policy forbids IPC with the owner. Allocates the queues, so it must
+    /// be called from thread context.
+    pub fn connect(&self, client: ThreadId) -> Result<CrossDomainChannel, IpcError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(IpcError::Disconnected);
+        }
+        authorize(client, self.owner)?;
+
+        let (client_end, owner_end) = CrossDomainChannel::connect_unchecked(client, self.owner, self.config)?;
+        let mut backlog = self.backlog.lock();
+        if backlog.len() >= self.backlog_limit {
+            return Err(IpcError::WouldBlock);
+        }
+        backlog.push_back(owner_end);
+        Ok(client_end)
+    }
+
+    /// Take the next pending connection without waiting.
+    ///
+    /// Connections the owner's policy forbids are dropped, which
+    /// disconnects the client, and reported as
+    /// [`PermissionDenied`](IpcError::PermissionDenied).
+    pub fn try_accept(&self) -> Result<CrossDomainChannel, IpcError> {
+        let channel = self.backlog.lock().pop_front().ok_or(IpcError::WouldBlock)?;
+        authorize(self.owner, channel.peer_domain())?;
+        Ok(channel)
+    }
+
+    /// Get an identifier for the endpoint, as shown when a thread is
+    /// blocked accepting on it.
+    pub fn id(&self) -> u64 {
+        self as *const Self as usize as u64
+    }
+
+    /// Take the next pending connection, waiting for one.
+    pub fn accept(&self) -> Result<CrossDomainChannel, IpcError> {
+        let blocked = AtomicU64::new(0);
+        block_on(self.id(), None, &blocked, || self.try_accept())
+    }
+
+    /// Like [`accept`](Self::accept), giving up after `timeout`.
+    pub fn accept_timeout(&self, timeout: Duration) -> Result<CrossDomainChannel, IpcError> {
+        let blocked = AtomicU64::new(0);
+        block_on(self.id(), Some(timeout), &blocked, || self.try_accept())
+    }
+
+    /// Refuse new connections and disconnect the pending ones.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.backlog.lock().clear();
+    }
+}
+
+/// Check that `from`'s isolation policy allows IPC with `to`.
+fn authorize(from: ThreadId, to: ThreadId) -> Result<(), IpcError> {
+    if check_ipc_access(from, to) {
+        Ok(())
+    } else {
+        SECURITY_STATE.record_violation_by(SecurityViolation::IsolationViolation, from);
+        Err(IpcError::PermissionDenied)
+    }
+}
+
+/// Retry `attempt` while it would block on `channel`, counting the wait in
+/// `blocked`.
+fn block_on<R>(
+    channel: u64,
+    timeout: Option<Duration>,
+    blocked: &AtomicU64,
+    mut attempt: impl FnMut() -> Result<R, IpcError>,
+) -> Result<R, IpcError> {
+    crate::might_sleep!();
+
+    match attempt() {
+        Err(IpcError::WouldBlock) => {}
+        result => return result,
+    }
+    blocked.fetch_add(1, Ordering::Relaxed);
+
+    let _blocked = thread_new::blocked_on(BlockedOn::Channel(channel));
+    let deadline = timeout.map(|timeout| TIMER_WHEEL.arm(timeout));
+    loop {
+        match attempt() {
+            Err(IpcError::WouldBlock) => {}
+            result => return result,
+        }
+        if deadline.as_ref().is_some_and(|deadline| deadline.expired()) {
+            return Err(IpcError::TimedOut);
+        }
+        crate::sync::relax();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipc_copy_in_copy_out() {
+        let client = ThreadId::new(46_001);
+        let server = ThreadId::new(46_002);
+        let config = ChannelConfig {
+            capacity: 2,
+            max_message: 8,
+        };
+        let endpoint = IpcEndpoint::new(server, config, 1).unwrap();
+        assert_eq!(endpoint.try_accept().unwrap_err(), IpcError::WouldBlock);
+
+        let client_end = endpoint.connect(client).unwrap();
+        // The backlog holds one connection
+        assert_eq!(endpoint.connect(client).unwrap_err(), IpcError::WouldBlock);
+        let server_end = endpoint.accept().unwrap();
+        assert_eq!((server_end.local_domain(), server_end.peer_domain()), (server, client));
+
+        // The sender's buffer can be reused as soon as send returns
+        let mut message = *b"ping";
+        client_end.try_send(&message).unwrap();
+        message.copy_from_slice(b"pong");
+        client_end.try_send(&message).unwrap();
+        assert_eq!(client_end.try_send(b"full").unwrap_err(), IpcError::WouldBlock);
+        assert_eq!(client_end.try_send(b"too large").unwrap_err(), IpcError::MessageTooLarge);
+
+        let mut small = [0u8; 2];
+        assert_eq!(server_end.try_recv(&mut small).unwrap_err(), IpcError::BufferTooSmall(4));
+        let mut buffer = [0u8; 8];
+        assert_eq!(server_end.recv(&mut buffer), Ok(4));
+        assert_eq!(&buffer[..4], b"ping");
+        assert_eq!(server_end.recv_timeout(&mut buffer, Duration::from_millis(1)), Ok(4));
+        assert_eq!(&buffer[..4], b"pong");
+        assert_eq!(server_end.try_recv(&mut buffer).unwrap_err(), IpcError::WouldBlock);
+
+        // Replies go the other way
+        server_end.send(b"ok").unwrap();
+        assert_eq!(client_end.try_recv(&mut buffer), Ok(2));
+
+        let stats = client_end.stats();
+        assert_eq!(stats, server_end.stats());
+        assert_eq!((stats.messages_sent, stats.messages_received), (3, 3));
+        assert_eq!((stats.bytes_sent, stats.bytes_received), (10, 10));
+        assert_eq!((stats.full_rejections, stats.empty_polls), (1, 1));
+
+        // Queued messages survive a disconnect
+        server_end.try_send(b"bye").unwrap();
+        drop(server_end);
+        assert!(!client_end.is_connected());
+        assert_eq!(client_end.try_send(b"x").unwrap_err(), IpcError::Disconnected);
+        assert_eq!(client_end.try_recv(&mut buffer), Ok(3));
+        assert_eq!(client_end.recv(&mut buffer).unwrap_err(), IpcError::Disconnected);
+
+        endpoint.close();
+        assert_eq!(endpoint.connect(client).unwrap_err(), IpcError::Disconnected);
+        assert!(IpcEndpoint::new(server, ChannelConfig { capacity: 0, max_message: 8 }, 1).is_err());
+    }
+}
@@ -6,6 +6,8 @@ use crate::thread_new::ThreadId;
 use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
 use alloc::{collections::BTreeMap, vec, vec::Vec};
 
+pub use super::ipc::CrossDomainChannel;
+
 /// Thread isolation boundaries and sandboxing.
 pub struct ThreadIsolation {
     /// Isolated thread domains
@@ -406,41 +408,6 @@ impl MemoryAccessGuard {
     }
 }
 
-/// Cross-domain communication channel.
-pub struct CrossDomainChannel {
-    sender_domain: ThreadId,
-    receiver_domain: ThreadId,
-    allowed: bool,
-}
-
-impl CrossDomainChannel {
-    /// Create new cross-domain communication channel.
-    pub fn new(sender: ThreadId, receiver: ThreadId) -> Result<Self, ThreadError> {
-        let allowed = check_cross_domain_access(sender, receiver, AccessType::Ipc);
-        
-        if !allowed {
-            return Err(ThreadError::PermissionDenied());
-        }
-        
-        Ok(Self {
-            sender_domain: sender,
-            receiver_domain: receiver,
-            allowed,
-        })
-    }
-    
-    /// Send message through channel.
-    pub fn send<T>(&self, message: T) -> Result<(), ThreadError> {
-        if !self.allowed {
-            return Err(ThreadError::PermissionDenied());
-        }
-        
-        // In real implementation, this would use secure IPC mechanism
-        // For now, just validate the operation is allowed
-        Ok(())
-    }
-}
-
 /// Helper functions for isolation management.
 
 /// Initialize thread isolation subsystem.
@@ -482,6 +449,30 @@ pub fn check_cross_domain_access(
     }
 }
 
+/// Check whether the domain of `from` may exchange messages with `to`.
+///
+/// An [`IpcPolicy::Blocked`] domain can't use IPC at all and an
+/// [`IpcPolicy::Unrestricted`] one may reach any domain; the others
+/// need [`AccessType::Ipc`] to pass [`check_cross_domain_access`].
+pub fn check_ipc_access(from: ThreadId, to: ThreadId) -> bool {
+    // Safety: as for the other accessors of `THREAD_ISOLATION`
+    let isolation = unsafe { &*core::ptr::addr_of!(THREAD_ISOLATION) };
+    let policy = isolation
+        .as_ref()
+        .and_then(|isolation| isolation.domains.get(&from))
+        .map(|domain| domain.config.ipc_policy);
+    match policy {
+        Some(IpcPolicy::Blocked) => {
+            if let Some(isolation) = isolation {
+                isolation.violations_detected.fetch_add(1, Ordering::Relaxed);
+            }
+            false
+        }
+        Some(IpcPolicy::Unrestricted) => true,
+        _ => check_cross_domain_access(from, to, AccessType::Ipc),
+    }
+}
+
 /// Check memory access permission for domain.
 pub fn check_memory_access(
     domain_id: ThreadId,
@@ -489,6 +480,11 @@ pub fn check_memory_access(
     size: usize,
     write: bool,
 ) -> bool {
+    // Shared memory is governed by its grants
+    if let Some(allowed) = super::shm::check_access(domain_id, address, size, write) {
+        return allowed;
+    }
+    
     unsafe {
         if let Some(isolation) = &THREAD_ISOLATION {
             if let Some(domain) = isolation.domains.get(&domain_id) {
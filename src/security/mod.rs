@@ -6,12 +6,18 @@
 pub mod stack_protection;
 pub mod cfi;
 pub mod isolation;
+pub mod ipc;
+pub mod shm;
 pub mod crypto_rng;
 pub mod aslr;
 pub mod audit;
+#[cfg(feature = "mmu")]
+pub mod wx;
 
-use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+use portable_atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use crate::errors::ThreadError;
+use crate::thread_new::ThreadId;
+use spin::Mutex;
 
 /// Global security configuration.
 #[derive(Debug, Clone, Copy)]
@@ -30,7 +36,13 @@ pub struct SecurityConfig {
     pub enable_audit_logging: bool,
     /// Cryptographically secure RNG for security features
     pub use_secure_rng: bool,
+    /// Flush branch predictor state when switching between threads in
+    /// different isolation domains
+    pub enable_speculation_barriers: bool,
     /// Panic on security violations
+    ///
+    /// Seeds the per-violation policy table; use [`set_violation_policy`]
+    /// to change the response to one kind of violation at runtime.
     pub panic_on_violation: bool,
 }
 
@@ -44,6 +56,7 @@ impl Default for SecurityConfig {
             enable_aslr: cfg!(feature = "hardened"),
             enable_audit_logging: cfg!(debug_assertions),
             use_secure_rng: true,
+            enable_speculation_barriers: false, // Expensive, opt-in only
             panic_on_violation: cfg!(debug_assertions),
         }
     }
@@ -68,6 +81,38 @@ pub enum SecurityViolation {
     CryptoViolation,
 }
 
+impl SecurityViolation {
+    /// Number of violation kinds.
+    pub const COUNT: usize = 7;
+
+    /// All violation kinds, in table order.
+    pub const ALL: [SecurityViolation; Self::COUNT] = [
+        SecurityViolation::StackCanaryViolation,
+        SecurityViolation::GuardPageViolation,
+        SecurityViolation::CfiViolation,
+        SecurityViolation::IsolationViolation,
+        SecurityViolation::MemoryViolation,
+        SecurityViolation::ResourceViolation,
+        SecurityViolation::CryptoViolation,
+    ];
+
+    /// Index of this kind in the policy table.
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Response used when `panic_on_violation` is off: corrupted stacks and
+    /// hijacked control flow take the thread down, the rest are reported.
+    pub const fn default_policy(self) -> ViolationPolicy {
+        match self {
+            SecurityViolation::StackCanaryViolation |
+            SecurityViolation::GuardPageViolation |
+            SecurityViolation::CfiViolation => ViolationPolicy::TerminateThread,
+            _ => ViolationPolicy::Continue,
+        }
+    }
+}
+
 /// Security violation handler result.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ViolationResponse {
@@ -79,6 +124,54 @@ pub enum ViolationResponse {
     Panic,
 }
 
+/// Configured response to one kind of violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ViolationPolicy {
+    /// Record the violation and carry on
+    Continue = 0,
+    /// Terminate the violating thread
+    TerminateThread = 1,
+    /// Panic the entire system
+    Panic = 2,
+    /// Let the violation callback choose the response
+    ///
+    /// Falls back to the kind's [default
+    /// policy](SecurityViolation::default_policy) if no callback is set.
+    Callback = 3,
+}
+
+impl ViolationPolicy {
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ViolationPolicy::Continue,
+            1 => ViolationPolicy::TerminateThread,
+            3 => ViolationPolicy::Callback,
+            _ => ViolationPolicy::Panic,
+        }
+    }
+}
+
+/// What the violation callback is told about a violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViolationContext {
+    /// Kind of violation
+    pub violation: SecurityViolation,
+    /// Thread running when the violation was detected
+    pub thread: ThreadId,
+    /// Policy configured for this kind
+    pub policy: ViolationPolicy,
+    /// Violations of this kind so far, including this one
+    pub count: u64,
+}
+
+/// Hook run for every violation, before the response is carried out.
+///
+/// The returned response is only used for kinds whose policy is
+/// [`ViolationPolicy::Callback`]; for the others the callback is notified
+/// and the configured response is taken regardless.
+pub type ViolationCallback = fn(&ViolationContext) -> ViolationResponse;
+
 /// Global security state and statistics.
 #[repr(align(64))] // Cache line aligned
 pub struct SecurityState {
@@ -100,9 +193,13 @@ pub struct SecurityState {
     pub isolation_enabled: AtomicBool,
     pub aslr_enabled: AtomicBool,
     pub audit_enabled: AtomicBool,
+    pub speculation_barriers_enabled: AtomicBool,
     
-    /// Configuration
-    config: SecurityConfig,
+    /// Response to each kind of violation, indexed by
+    /// `SecurityViolation::index`
+    policies: [AtomicU8; SecurityViolation::COUNT],
+    /// Hook run before the response is carried out
+    callback: Mutex<Option<ViolationCallback>>,
 }
 
 impl SecurityState {
@@ -122,54 +219,98 @@ impl SecurityState {
             isolation_enabled: AtomicBool::new(config.enable_thread_isolation),
             aslr_enabled: AtomicBool::new(config.enable_aslr),
             audit_enabled: AtomicBool::new(config.enable_audit_logging),
-            config,
+            speculation_barriers_enabled: AtomicBool::new(config.enable_speculation_barriers),
+            policies: Self::seed_policies(config),
+            callback: Mutex::new(None),
+        }
+    }
+    
+    /// Build the initial policy table from `config.panic_on_violation`.
+    const fn seed_policies(config: SecurityConfig) -> [AtomicU8; SecurityViolation::COUNT] {
+        let mut policies = [0u8; SecurityViolation::COUNT];
+        let mut index = 0;
+        while index < SecurityViolation::COUNT {
+            policies[index] = if config.panic_on_violation {
+                ViolationPolicy::Panic as u8
+            } else {
+                SecurityViolation::ALL[index].default_policy() as u8
+            };
+            index += 1;
         }
+        
+        [
+            AtomicU8::new(policies[0]),
+            AtomicU8::new(policies[1]),
+            AtomicU8::new(policies[2]),
+            AtomicU8::new(policies[3]),
+            AtomicU8::new(policies[4]),
+            AtomicU8::new(policies[5]),
+            AtomicU8::new(policies[6]),
+        ]
+    }
+    
+    /// Set the response to `violation`.
+    pub fn set_policy(&self, violation: SecurityViolation, policy: ViolationPolicy) {
+        self.policies[violation.index()].store(policy as u8, Ordering::Relaxed);
+    }
+    
+    /// Get the response to `violation`.
+    pub fn policy(&self, violation: SecurityViolation) -> ViolationPolicy {
+        ViolationPolicy::from_u8(self.policies[violation.index()].load(Ordering::Relaxed))
     }
     
-    /// Record a security violation.
+    /// Install the violation callback, replacing any previous one.
+    pub fn set_callback(&self, callback: Option<ViolationCallback>) {
+        *self.callback.lock() = callback;
+    }
+    
+    /// Record a security violation by the current thread.
     pub fn record_violation(&self, violation: SecurityViolation) -> ViolationResponse {
+        self.record_violation_by(violation, crate::thread_new::current_thread_id())
+    }
+    
+    /// Record a security violation by `thread` and decide the response.
+    ///
+    /// The callback, if any, runs before the response is returned.
+    pub fn record_violation_by(&self, violation: SecurityViolation, thread: ThreadId) -> ViolationResponse {
         // Update statistics
         self.total_violations.fetch_add(1, Ordering::Relaxed);
         
-        match violation {
-            SecurityViolation::StackCanaryViolation => {
-                self.stack_violations.fetch_add(1, Ordering::Relaxed);
-            }
-            SecurityViolation::GuardPageViolation => {
-                self.guard_violations.fetch_add(1, Ordering::Relaxed);
-            }
-            SecurityViolation::CfiViolation => {
-                self.cfi_violations.fetch_add(1, Ordering::Relaxed);
-            }
-            SecurityViolation::IsolationViolation => {
-                self.isolation_violations.fetch_add(1, Ordering::Relaxed);
-            }
-            SecurityViolation::MemoryViolation => {
-                self.memory_violations.fetch_add(1, Ordering::Relaxed);
-            }
-            SecurityViolation::ResourceViolation => {
-                self.resource_violations.fetch_add(1, Ordering::Relaxed);
-            }
-            SecurityViolation::CryptoViolation => {
-                self.crypto_violations.fetch_add(1, Ordering::Relaxed);
-            }
-        }
+        let counter = match violation {
+            SecurityViolation::StackCanaryViolation => &self.stack_violations,
+            SecurityViolation::GuardPageViolation => &self.guard_violations,
+            SecurityViolation::CfiViolation => &self.cfi_violations,
+            SecurityViolation::IsolationViolation => &self.isolation_violations,
+            SecurityViolation::MemoryViolation => &self.memory_violations,
+            SecurityViolation::ResourceViolation => &self.resource_violations,
+            SecurityViolation::CryptoViolation => &self.crypto_violations,
+        };
+        let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
         
         // Log violation if audit is enabled
         if self.audit_enabled.load(Ordering::Relaxed) {
             audit::log_security_violation(violation);
         }
         
+        let policy = self.policy(violation);
+        // Copy the hook out so it can reconfigure the table without deadlock
+        let callback = *self.callback.lock();
+        let chosen = callback.map(|callback| callback(&ViolationContext {
+            violation,
+            thread,
+            policy,
+            count,
+        }));
+        
         // Determine response
-        if self.config.panic_on_violation {
-            ViolationResponse::Panic
-        } else {
-            match violation {
-                SecurityViolation::StackCanaryViolation |
-                SecurityViolation::GuardPageViolation |
-                SecurityViolation::CfiViolation => ViolationResponse::TerminateThread,
+        match policy {
+            ViolationPolicy::Continue => ViolationResponse::Continue,
+            ViolationPolicy::TerminateThread => ViolationResponse::TerminateThread,
+            ViolationPolicy::Panic => ViolationResponse::Panic,
+            ViolationPolicy::Callback => chosen.unwrap_or(match violation.default_policy() {
+                ViolationPolicy::TerminateThread => ViolationResponse::TerminateThread,
                 _ => ViolationResponse::Continue,
-            }
+            }),
         }
     }
     
@@ -191,6 +332,7 @@ impl SecurityState {
                 isolation: self.isolation_enabled.load(Ordering::Relaxed),
                 aslr: self.aslr_enabled.load(Ordering::Relaxed),
                 audit: self.audit_enabled.load(Ordering::Relaxed),
+                speculation_barriers: self.speculation_barriers_enabled.load(Ordering::Relaxed),
             },
         }
     }
@@ -218,6 +360,7 @@ pub struct FeaturesEnabled {
     pub isolation: bool,
     pub aslr: bool,
     pub audit: bool,
+    pub speculation_barriers: bool,
 }
 
 /// Global security state instance.
@@ -229,6 +372,7 @@ pub static SECURITY_STATE: SecurityState = SecurityState::new(SecurityConfig {
     enable_aslr: cfg!(feature = "hardened"),
     enable_audit_logging: cfg!(debug_assertions),
     use_secure_rng: true,
+    enable_speculation_barriers: false,
     panic_on_violation: cfg!(debug_assertions),
 });
 
@@ -264,6 +408,9 @@ pub fn init_security(config: SecurityConfig) -> Result<(), ThreadError> {
         audit::init_audit_logging(config)?;
     }
     
+    // Applied directly from the switch path, so only the flag is needed
+    SECURITY_STATE.speculation_barriers_enabled.store(config.enable_speculation_barriers, Ordering::Relaxed);
+    
     // Security subsystem initialized with feature count based on config
     
     Ok(())
@@ -279,18 +426,19 @@ fn count_enabled_features(config: &SecurityConfig) -> usize {
     if config.enable_aslr { count += 1; }
     if config.enable_audit_logging { count += 1; }
     if config.use_secure_rng { count += 1; }
+    if config.enable_speculation_barriers { count += 1; }
     count
 }
 
 /// Security violation handler (called from various subsystems).
-pub fn handle_security_violation(violation: SecurityViolation) -> ! {
+///
+/// Carries out the response configured for `violation`; returns only if it
+/// is [`ViolationResponse::Continue`].
+pub fn handle_security_violation(violation: SecurityViolation) {
     let response = SECURITY_STATE.record_violation(violation);
     
     match response {
-        ViolationResponse::Continue => {
-            // This shouldn't happen for serious violations
-            panic!("Security violation handler requested continue for serious violation: {:?}", violation);
-        }
+        ViolationResponse::Continue => {}
         ViolationResponse::TerminateThread => {
             // Terminate current thread
             crate::exit_thread();
@@ -302,6 +450,28 @@ pub fn handle_security_violation(violation: SecurityViolation) -> ! {
     }
 }
 
+/// Set the response to `violation` at runtime.
+pub fn set_violation_policy(violation: SecurityViolation, policy: ViolationPolicy) {
+    SECURITY_STATE.set_policy(violation, policy);
+}
+
+/// Get the response configured for `violation`.
+pub fn violation_policy(violation: SecurityViolation) -> ViolationPolicy {
+    SECURITY_STATE.policy(violation)
+}
+
+/// Install a hook run for every violation before its response is carried
+/// out, or remove it with `None`.
+pub fn set_violation_callback(callback: Option<ViolationCallback>) {
+    SECURITY_STATE.set_callback(callback);
+}
+
+/// Whether switches between isolation domains issue speculation barriers.
+#[inline]
+pub fn speculation_barriers_enabled() -> bool {
+    SECURITY_STATE.speculation_barriers_enabled.load(Ordering::Relaxed)
+}
+
 /// Get current security statistics.
 pub fn get_security_stats() -> SecurityStats {
     SECURITY_STATE.get_stats()
@@ -328,6 +498,9 @@ pub fn configure_security_feature(feature: SecurityFeature, enabled: bool) {
         SecurityFeature::Audit => {
             SECURITY_STATE.audit_enabled.store(enabled, Ordering::Relaxed);
         }
+        SecurityFeature::SpeculationBarriers => {
+            SECURITY_STATE.speculation_barriers_enabled.store(enabled, Ordering::Relaxed);
+        }
     }
 }
 
@@ -340,4 +513,49 @@ pub enum SecurityFeature {
     Isolation,
     Aslr,
     Audit,
-}
\ No newline at end of file
+    SpeculationBarriers,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use portable_atomic::AtomicUsize;
+
+    static SEEN: AtomicUsize = AtomicUsize::new(0);
+
+    fn escalate(context: &ViolationContext) -> ViolationResponse {
+        assert_eq!(context.thread, ThreadId::new(42_201));
+        SEEN.fetch_add(1, Ordering::Relaxed);
+        ViolationResponse::Panic
+    }
+
+    #[test]
+    fn test_violation_policy_table() {
+        let config = SecurityConfig {
+            enable_audit_logging: false,
+            panic_on_violation: false,
+            ..SecurityConfig::default()
+        };
+        let state = SecurityState::new(config);
+        let thread = ThreadId::new(42_201);
+
+        // Seeded from `panic_on_violation`
+        assert_eq!(state.policy(SecurityViolation::CfiViolation), ViolationPolicy::TerminateThread);
+        assert_eq!(state.record_violation_by(SecurityViolation::ResourceViolation, thread), ViolationResponse::Continue);
+        assert!(SecurityViolation::ALL.iter().all(|&kind| {
+            SecurityState::new(SecurityConfig { panic_on_violation: true, ..config }).policy(kind) == ViolationPolicy::Panic
+        }));
+
+        // Without a callback, `Callback` falls back to the default policy
+        state.set_policy(SecurityViolation::MemoryViolation, ViolationPolicy::Callback);
+        assert_eq!(state.record_violation_by(SecurityViolation::MemoryViolation, thread), ViolationResponse::Continue);
+
+        // The callback sees every violation but only decides for `Callback`
+        state.set_callback(Some(escalate));
+        assert_eq!(state.record_violation_by(SecurityViolation::MemoryViolation, thread), ViolationResponse::Panic);
+        assert_eq!(state.record_violation_by(SecurityViolation::CfiViolation, thread), ViolationResponse::TerminateThread);
+        assert_eq!(SEEN.load(Ordering::Relaxed), 2);
+
+        let stats = state.get_stats();
+        assert_eq!((stats.total_violations, stats.memory_violations), (4, 2));
+    }
+}
@@ -0,0 +1,500 @@
+//! Named shared memory regions with revocable grants.
+//!
+//! A region is created by one domain, its owner, under a unique name.
+//! The owner grants other isolation domains read or read-write access and
+//! can revoke it at any time. Domains holding a grant [`map`] the region
+//! and exchange data in place, without the copies of
+//! [IPC channels](super::ipc).
+//!
+//! Mappings remember the epoch of the grant they were made under. Revoking
+//! or downgrading a grant retires its epoch, so a revoked mapping stays
+//! dead even if access is granted again later. The checked accessors ([`ShmMapping::read`],
+//! [`ShmMapping::write`]) fail as soon as the grant is gone. Raw pointers
+//! from a mapping are only cut off by the hardware: with the `mmu` feature
+//! grants and revocations are applied through the registered
+//! [`PageMapper`](crate::mem::page_mapper::PageMapper) and revocation
+//! shoots down the stale TLB entries of the domain's address space.
+//!
+//! Grants, revocations and unlinks are written to the audit log.
+//!
+//! [`map`]: SharedRegion::map
+
+use super::audit::{self, MemoryOperation};
+use super::isolation::{check_cross_domain_access, AccessType};
+use super::{SecurityViolation, SECURITY_STATE};
+use crate::thread_new::ThreadId;
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt;
+use core::ptr::NonNull;
+use portable_atomic::{AtomicU64, Ordering};
+
+/// Regions are allocated in whole pages.
+pub const SHM_PAGE_SIZE: usize = 4096;
+
+/// Access a domain has to a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShmAccess {
+    /// Read only
+    Read,
+    /// Read and write
+    ReadWrite,
+}
+
+impl ShmAccess {
+    /// Check whether this access includes `other`.
+    pub fn covers(self, other: ShmAccess) -> bool {
+        self >= other
+    }
+}
+
+/// Errors returned by shared memory operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmError {
+    /// A region with this name already exists
+    AlreadyExists,
+    /// No region has this name
+    NotFound,
+    /// Size is zero or too large
+    InvalidSize,
+    /// Out of memory for the region
+    OutOfMemory,
+    /// Only the owner may grant, revoke or unlink
+    NotOwner,
+    /// The domain holds no grant, or a weaker one than needed
+    NotGranted,
+    /// The owner's isolation policy forbids sharing with the domain
+    PermissionDenied,
+    /// The access falls outside the region
+    OutOfBounds,
+}
+
+impl fmt::Display for ShmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShmError::AlreadyExists => write!(f, "shared memory region already exists"),
+            ShmError::NotFound => write!(f, "shared memory region not found"),
+            ShmError::InvalidSize => write!(f, "invalid shared memory region size"),
+            ShmError::OutOfMemory => write!(f, "out of memory for shared memory region"),
+            ShmError::NotOwner => write!(f, "only the owner can manage the region"),
+            ShmError::NotGranted => write!(f, "no sufficient grant for the region"),
+            ShmError::PermissionDenied => write!(f, "sharing denied by isolation policy"),
+            ShmError::OutOfBounds => write!(f, "access outside the region"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Grant {
+    access: ShmAccess,
+    epoch: u64,
+}
+
+/// A named region of shared memory.
+pub struct SharedRegion {
+    name: String,
+    owner: ThreadId,
+    memory: NonNull<u8>,
+    layout: Layout,
+    size: usize,
+    grants: spin::Mutex<BTreeMap<ThreadId, Grant>>,
+    next_epoch: AtomicU64,
+}
+
+// Safety: the region only hands out raw pointers; synchronizing access to
+// the memory is up to the domains sharing it
+unsafe impl Send for SharedRegion {}
+unsafe impl Sync for SharedRegion {}
+
+impl SharedRegion {
+    fn new(name: &str, size: usize, owner: ThreadId) -> Result<Self, ShmError> {
+        if size == 0 {
+            return Err(ShmError::InvalidSize);
+        }
+        let rounded = size.checked_add(SHM_PAGE_SIZE - 1).ok_or(ShmError::InvalidSize)? & !(SHM_PAGE_SIZE - 1);
+        let layout = Layout::from_size_align(rounded, SHM_PAGE_SIZE).map_err(|_| ShmError::InvalidSize)?;
+        // Safety: the layout has a non-zero size
+        let memory = NonNull::new(unsafe { alloc_zeroed(layout) }).ok_or(ShmError::OutOfMemory)?;
+
+        let mut grants = BTreeMap::new();
+        grants.insert(owner, Grant {
+            access: ShmAccess::ReadWrite,
+            epoch: 0,
+        });
+        Ok(Self {
+            name: String::from(name),
+            owner,
+            memory,
+            layout,
+            size,
+            grants: spin::Mutex::new(grants),
+            next_epoch: AtomicU64::new(1),
+        })
+    }
+
+    /// Get the region's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the domain that created the region.
+    pub fn owner(&self) -> ThreadId {
+        self.owner
+    }
+
+    /// Get the usable size in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Get the start address of the region.
+    pub fn start(&self) -> usize {
+        self.memory.as_ptr() as usize
+    }
+
+    /// Check whether `[address, address + len)` lies in the region.
+    pub fn contains(&self, address: usize, len: usize) -> bool {
+        address >= self.start() && address.saturating_add(len) <= self.start() + self.size
+    }
+
+    /// Get the access `domain` currently has.
+    pub fn access(&self, domain: ThreadId) -> Option<ShmAccess> {
+        self.grants.lock().get(&domain).map(|grant| grant.access)
+    }
+
+    /// Get the domains with a grant, the owner included.
+    pub fn grants(&self) -> alloc::vec::Vec<(ThreadId, ShmAccess)> {
+        self.grants.lock().iter().map(|(&domain, grant)| (domain, grant.access)).collect()
+    }
+
+    /// Grant `domain` access to the region, replacing any earlier grant.
+    ///
+    /// Only the owner may grant, and the owner's isolation policy must
+    /// allow [`AccessType::ResourceShare`] with `domain`. Mappings made
+    /// under an earlier grant stay valid only if the new one covers them.
+    pub fn grant(&self, caller: ThreadId, domain: ThreadId, access: ShmAccess) -> Result<(), ShmError> {
+        self.check_owner(caller)?;
+        if domain == self.owner {
+            return Ok(());
+        }
+        if !check_cross_domain_access(self.owner, domain, AccessType::ResourceShare) {
+            SECURITY_STATE.record_violation_by(SecurityViolation::IsolationViolation, caller);
+            return Err(ShmError::PermissionDenied);
+        }
+
+        {
+            let mut grants = self.grants.lock();
+            let epoch = match grants.get(&domain) {
+                // Upgrades and same-level grants keep existing mappings
+                Some(previous) if access.covers(previous.access) => previous.epoch,
+                _ => self.next_epoch.fetch_add(1, Ordering::Relaxed),
+            };
+            grants.insert(domain, Grant { access, epoch });
+        }
+
+        #[cfg(feature = "mmu")]
+        self.apply_mapping(domain, Some(access));
+        audit::log_grant_event(MemoryOperation::Grant, self.start(), self.size, domain);
+        Ok(())
+    }
+
+    /// Take away `domain`'s access to the region.
+    ///
+    /// Existing mappings of the domain stop working right away. Returns
+    /// [`NotGranted`](ShmError::NotGranted) if it had no grant.
+    pub fn revoke(&self, caller: ThreadId, domain: ThreadId) -> Result<(), ShmError> {
+        self.check_owner(caller)?;
+        if domain == self.owner || self.grants.lock().remove(&domain).is_none() {
+            return Err(ShmError::NotGranted);
+        }
+
+        #[cfg(feature = "mmu")]
+        self.apply_mapping(domain, None);
+        audit::log_grant_event(MemoryOperation::Revoke, self.start(), self.size, domain);
+        Ok(())
+    }
+
+    /// Map the region for `domain`.
+    ///
+    /// Fails with [`NotGranted`](ShmError::NotGranted) if the domain has no
+    /// grant. The mapping carries the access of the current grant.
+    pub fn map(self: &Arc<Self>, domain: ThreadId) -> Result<ShmMapping, ShmError> {
+        let grant = *self.grants.lock().get(&domain).ok_or(ShmError::NotGranted)?;
+        Ok(ShmMapping {
+            region: self.clone(),
+            domain,
+            access: grant.access,
+            epoch: grant.epoch,
+        })
+    }
+
+    fn check_owner(&self, caller: ThreadId) -> Result<(), ShmError> {
+        if caller == self.owner {
+            Ok(())
+        } else {
+            SECURITY_STATE.record_violation_by(SecurityViolation::IsolationViolation, caller);
+            Err(ShmError::NotOwner)
+        }
+    }
+
+    fn grant_epoch(&self, domain: ThreadId, access: ShmAccess) -> Option<u64> {
+        self.grants
+            .lock()
+            .get(&domain)
+            .filter(|grant| grant.access.covers(access))
+            .map(|grant| grant.epoch)
+    }
+
+    /// Apply `access` to the domain's page tables and flush stale entries.
+    #[cfg(feature = "mmu")]
+    fn apply_mapping(&self, domain: ThreadId, access: Option<ShmAccess>) {
+        use crate::arch::{Arch, DefaultArch};
+        use crate::mem::page_mapper::{page_mapper, PageProtection};
+
+        let Some(mapper) = page_mapper() else {
+            return;
+        };
+        let space = crate::thread_new::registry::lookup(domain).and_then(|thread| thread.address_space());
+        let protection = access.map(|access| PageProtection {
+            read: true,
+            write: access == ShmAccess::ReadWrite,
+            execute: false,
+        });
+        if !mapper.map_shared(space, self.start(), self.layout.size(), protection) {
+            return;
+        }
+
+        // Only a lost or reduced right leaves dangerous TLB entries behind
+        if access != Some(ShmAccess::ReadWrite) {
+            match space {
+                Some(space) => {
+                    space.shootdown();
+                }
+                // Safety: a TLB flush only costs refills
+                None => unsafe { DefaultArch::flush_tlb(None) },
+            }
+        }
+    }
+}
+
+impl Drop for SharedRegion {
+    fn drop(&mut self) {
+        // Safety: allocated in `new` with this layout
+        unsafe { dealloc(self.memory.as_ptr(), self.layout) };
+    }
+}
+
+impl fmt::Debug for SharedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedRegion")
+            .field("name", &self.name)
+            .field("owner", &self.owner)
+            .field("start", &self.memory)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// A domain's view of a region.
+///
+/// Keeps the region alive even after it is unlinked.
+pub struct ShmMapping {
+    region: Arc<SharedRegion>,
+    domain: ThreadId,
+    access: ShmAccess,
+    epoch: u64,
+}
+
+impl ShmMapping {
+    /// Get the mapped region.
+    pub fn region(&self) -> &Arc<SharedRegion> {
+        &self.region
+    }
+
+    /// Get the access the mapping was made with.
+    pub fn access(&self) -> ShmAccess {
+        self.access
+    }
+
+    /// Check whether the grant the mapping was made under still holds.
+    pub fn is_valid(&self) -> bool {
+        self.region.grant_epoch(self.domain, self.access) == Some(self.epoch)
+    }
+
+    /// Get the size of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.region.size
+    }
+
+    /// Check whether the mapping is empty (never true).
+    pub fn is_empty(&self) -> bool {
+        self.region.size == 0
+    }
+
+    /// Get a pointer to the start of the region, for zero-copy access.
+    ///
+    /// Without the `mmu` feature nothing stops the pointer from being
+    /// used after the grant is revoked; check [`is_valid`](Self::is_valid).
+    pub fn as_ptr(&self) -> *const u8 {
+        self.region.memory.as_ptr()
+    }
+
+    /// Get a writable pointer to the start of the region.
+    pub fn as_mut_ptr(&self) -> Result<*mut u8, ShmError> {
+        self.check(ShmAccess::ReadWrite, 0, 0)?;
+        Ok(self.region.memory.as_ptr())
+    }
+
+    /// Copy `buffer.len()` bytes at `offset` out of the region.
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), ShmError> {
+        self.check(ShmAccess::Read, offset, buffer.len())?;
+        // Safety: the range was checked against the region; concurrent
+        // writers only make the data torn, which sharing domains accept
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.as_ptr().add(offset), buffer.as_mut_ptr(), buffer.len());
+        }
+        Ok(())
+    }
+
+    /// Copy `data` into the region at `offset`.
+    pub fn write(&self, offset: usize, data: &[u8]) -> Result<(), ShmError> {
+        self.check(ShmAccess::ReadWrite, offset, data.len())?;
+        // Safety: as in `read`, with write access checked
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), self.region.memory.as_ptr().add(offset), data.len());
+        }
+        Ok(())
+    }
+
+    fn check(&self, access: ShmAccess, offset: usize, len: usize) -> Result<(), ShmError> {
+        if !self.access.covers(access) || !self.is_valid() {
+            return Err(ShmError::NotGranted);
+        }
+        match offset.checked_add(len) {
+            Some(end) if end <= self.region.size => Ok(()),
+            _ => Err(ShmError::OutOfBounds),
+        }
+    }
+}
+
+impl fmt::Debug for ShmMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShmMapping")
+            .field("region", &self.region.name)
+            .field("domain", &self.domain)
+            .field("access", &self.access)
+            .field("valid", &self.is_valid())
+            .finish()
+    }
+}
+
+/// Regions by name.
+static REGIONS: spin::Mutex<BTreeMap<String, Arc<SharedRegion>>> = spin::Mutex::new(BTreeMap::new());
+
+/// Create a region of at least `size` bytes named `name`, owned by `owner`.
+///
+/// The memory is zeroed. The owner always has read-write access.
+pub fn create(name: &str, size: usize, owner: ThreadId) -> Result<Arc<SharedRegion>, ShmError> {
+    let mut regions = REGIONS.lock();
+    if regions.contains_key(name) {
+        return Err(ShmError::AlreadyExists);
+    }
+    let region = Arc::new(SharedRegion::new(name, size, owner)?);
+    regions.insert(String::from(name), region.clone());
+    Ok(region)
+}
+
+/// Look up the region named `name`.
+pub fn open(name: &str) -> Result<Arc<SharedRegion>, ShmError> {
+    REGIONS.lock().get(name).cloned().ok_or(ShmError::NotFound)
+}
+
+/// Remove the name `name`, revoking every grant.
+///
+/// Only the owner may unlink. The memory is freed once the last mapping
+/// is dropped.
+pub fn unlink(name: &str, caller: ThreadId) -> Result<(), ShmError> {
+    let region = open(name)?;
+    region.check_owner(caller)?;
+    for (domain, _) in region.grants() {
+        if domain != region.owner {
+            region.revoke(caller, domain)?;
+        }
+    }
+    REGIONS.lock().remove(name);
+    audit::log_grant_event(MemoryOperation::Unmap, region.start(), region.size, caller);
+    Ok(())
+}
+
+/// Decide a memory access by `domain` that falls in a shared region.
+///
+/// Returns `None` if `[address, address + len)` is in no region, and
+/// otherwise whether the domain's grant allows it.
+pub fn check_access(domain: ThreadId, address: usize, len: usize, write: bool) -> Option<bool> {
+    let regions = REGIONS.lock();
+    let region = regions.values().find(|region| region.contains(address, len))?;
+    let needed = if write { ShmAccess::ReadWrite } else { ShmAccess::Read };
+    Some(region.access(domain).is_some_and(|access| access.covers(needed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shm_grant_and_revoke() {
+        let owner = ThreadId::new(47_001);
+        let reader = ThreadId::new(47_002);
+        let writer = ThreadId::new(47_003);
+
+        let region = create("test-shm-grants", 100, owner).unwrap();
+        assert_eq!(create("test-shm-grants", 100, owner).unwrap_err(), ShmError::AlreadyExists);
+        assert_eq!(region.start() % SHM_PAGE_SIZE, 0);
+        assert!(Arc::ptr_eq(&open("test-shm-grants").unwrap(), &region));
+
+        // Only the owner hands out access
+        assert_eq!(region.grant(reader, writer, ShmAccess::ReadWrite).unwrap_err(), ShmError::NotOwner);
+        assert_eq!(region.map(reader).unwrap_err(), ShmError::NotGranted);
+        region.grant(owner, reader, ShmAccess::Read).unwrap();
+        region.grant(owner, writer, ShmAccess::ReadWrite).unwrap();
+
+        let writes = region.map(writer).unwrap();
+        let reads = region.map(reader).unwrap();
+        writes.write(10, b"shared").unwrap();
+        let mut buffer = [0u8; 6];
+        reads.read(10, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"shared");
+        // Zero-copy: both views are the same memory
+        assert_eq!(reads.as_ptr(), writes.as_mut_ptr().unwrap() as *const u8);
+
+        assert_eq!(reads.write(0, b"x").unwrap_err(), ShmError::NotGranted);
+        assert_eq!(reads.read(99, &mut buffer).unwrap_err(), ShmError::OutOfBounds);
+        assert_eq!(check_access(reader, region.start() + 10, 6, false), Some(true));
+        assert_eq!(check_access(reader, region.start() + 10, 6, true), Some(false));
+        assert_eq!(check_access(ThreadId::new(47_004), region.start(), 1, false), Some(false));
+
+        // Revocation kills existing mappings, even after a new grant
+        region.revoke(owner, reader).unwrap();
+        assert!(!reads.is_valid());
+        assert_eq!(reads.read(10, &mut buffer).unwrap_err(), ShmError::NotGranted);
+        assert_eq!(region.revoke(owner, reader).unwrap_err(), ShmError::NotGranted);
+        region.grant(owner, reader, ShmAccess::Read).unwrap();
+        assert!(!reads.is_valid());
+        assert!(region.map(reader).unwrap().is_valid());
+
+        // Downgrades invalidate, upgrades don't
+        region.grant(owner, writer, ShmAccess::Read).unwrap();
+        assert!(!writes.is_valid());
+        let reads = region.map(reader).unwrap();
+        region.grant(owner, reader, ShmAccess::ReadWrite).unwrap();
+        assert!(reads.is_valid());
+
+        assert_eq!(unlink("test-shm-grants", reader).unwrap_err(), ShmError::NotOwner);
+        unlink("test-shm-grants", owner).unwrap();
+        assert_eq!(open("test-shm-grants").unwrap_err(), ShmError::NotFound);
+        assert!(!reads.is_valid());
+        assert_eq!(region.grants(), alloc::vec![(owner, ShmAccess::ReadWrite)]);
+    }
+}
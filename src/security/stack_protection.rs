@@ -3,6 +3,7 @@
 use crate::errors::ThreadError;
 use crate::security::{SecurityConfig, SecurityViolation, SECURITY_STATE, handle_security_violation};
 use crate::mem::Stack;
+use crate::security::crypto_rng::secure_random_u64;
 use portable_atomic::{AtomicUsize, Ordering};
 use alloc::alloc;
 
@@ -32,6 +33,25 @@ impl StackProtection {
 /// Global stack protection instance.
 static STACK_PROTECTION: StackProtection = StackProtection::new();
 
+/// Generate the stack canary of a new thread.
+///
+/// Canaries come from the secure RNG. Until it is initialized they fall back
+/// to mixing the thread ID with the CPU clock, which still differs between
+/// threads but is predictable. The lowest byte is always zero so that an
+/// overflowing string copy stops at the canary instead of reproducing it.
+pub fn generate_canary(thread_id: u64) -> u64 {
+    let canary = secure_random_u64().unwrap_or_else(|_| {
+        // SplitMix64 finalizer
+        let mut x = thread_id ^ crate::time::cpu_clock().as_nanos() ^ STACK_CANARY_MAGIC;
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+        x ^ (x >> 31)
+    });
+    STACK_PROTECTION.canaries_placed.fetch_add(1, Ordering::Relaxed);
+    // Never zero, which marks a thread without a canary
+    (canary & !0xFF).max(0x100)
+}
+
 /// Stack canary for overflow detection.
 #[repr(C)]
 #[derive(Clone, Copy)]
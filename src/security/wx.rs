@@ -0,0 +1,90 @@
+//! W^X (write xor execute) checks for thread stacks and pool memory.
+//!
+//! Stacks and pool memory are written at runtime, so they must never be
+//! executable: an overflow that plants code there could otherwise jump
+//! into it. Mappings are queried through the registered
+//! [`PageMapper`](crate::mem::page_mapper::PageMapper); pages it can't
+//! describe are reported as [`WxStatus::Unknown`] and not rejected.
+
+use crate::errors::{PermissionError, ThreadError};
+use crate::mem::page_mapper::{page_mapper, PageMapper};
+use crate::mem::Stack;
+use crate::security::{SecurityViolation, SECURITY_STATE};
+use crate::thread_new::ThreadId;
+
+/// Granularity of protection queries.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Outcome of a W^X check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WxStatus {
+    /// Every page in the range is mapped non-executable
+    NonExecutable,
+    /// The page at this address is executable
+    Executable(usize),
+    /// No mapper is registered, or it could not describe some page
+    Unknown,
+}
+
+/// Check `[start, start + len)` against `mapper`.
+pub fn check_region_with(mapper: &dyn PageMapper, start: usize, len: usize) -> WxStatus {
+    if len == 0 {
+        return WxStatus::NonExecutable;
+    }
+
+    let mut status = WxStatus::NonExecutable;
+    let mut page = start & !(PAGE_SIZE - 1);
+    let end = start.saturating_add(len);
+    while page < end {
+        match mapper.protection(page) {
+            Some(protection) if protection.execute => return WxStatus::Executable(page),
+            Some(_) => {}
+            None => status = WxStatus::Unknown,
+        }
+        page = match page.checked_add(PAGE_SIZE) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    status
+}
+
+/// Check `[start, start + len)` against the registered page mapper.
+pub fn check_region(start: usize, len: usize) -> WxStatus {
+    match page_mapper() {
+        Some(mapper) => check_region_with(mapper, start, len),
+        None => WxStatus::Unknown,
+    }
+}
+
+/// Check the whole of a stack, guard pages excluded.
+pub fn check_stack(stack: &Stack) -> WxStatus {
+    check_region(stack.bottom() as usize, stack.size())
+}
+
+/// Assert that `[start, start + len)` is not executable.
+///
+/// For integrators to verify memory they hand to the runtime. An
+/// executable page records a memory violation against the current thread
+/// and fails with a permission error; unknown mappings pass.
+pub fn assert_non_executable(start: usize, len: usize) -> Result<(), ThreadError> {
+    reject_executable(check_region(start, len), crate::thread_new::current_thread_id())
+}
+
+/// Verify the stack of `thread` before it is started.
+///
+/// An executable stack records a memory violation against `thread` and
+/// fails, so the thread is never run.
+pub fn verify_stack(stack: &Stack, thread: ThreadId) -> Result<(), ThreadError> {
+    reject_executable(check_stack(stack), thread)
+}
+
+fn reject_executable(status: WxStatus, thread: ThreadId) -> Result<(), ThreadError> {
+    match status {
+        WxStatus::Executable(_) => {
+            SECURITY_STATE.record_violation_by(SecurityViolation::MemoryViolation, thread);
+            Err(ThreadError::Permission(PermissionError::SecurityViolation))
+        }
+        WxStatus::NonExecutable | WxStatus::Unknown => Ok(()),
+    }
+}
@@ -0,0 +1,452 @@
+//! Deterministic scheduler simulation in virtual time.
+//!
+//! [`Simulator`] runs a real [`Scheduler`] and a [`BandwidthController`]
+//! against modelled threads on the [`SimArch`](crate::arch::sim::SimArch)
+//! backend. Each simulated thread has a [`Workload`] instead of code; the
+//! simulator replays what the kernel's tick path would do with it: charging
+//! bandwidth, asking the scheduler about preemption, blocking threads whose
+//! work is done and waking them at their next release.
+//!
+//! Time only moves when the simulator advances the virtual clock, and it
+//! jumps straight to the next event: a tick, a release or a thread finishing
+//! its work. Timers on the [`TIMER_WHEEL`] fire as virtual time passes
+//! them. An hour of scheduling runs in well under a second, and the same
+//! inputs always produce the same [`SimThreadStats`].
+//!
+//! ```ignore
+//! let scheduler = RoundRobinScheduler::new(1);
+//! let mut sim = Simulator::new(&scheduler, 1).tick(Duration::from_millis(10));
+//! let control = sim.spawn(ThreadId::new(1), priority::HIGH, Workload::Periodic {
+//!     period: Duration::from_millis(100),
+//!     runtime: Duration::from_millis(20),
+//! })?;
+//! sim.run_for(Duration::from_millis(3_600_000));
+//! assert_eq!(sim.thread_stats(control.id()).unwrap().deadline_misses, 0);
+//! ```
+//!
+//! The virtual clock is global, so only one simulator exists at a time;
+//! [`Simulator::new`] waits for the previous one to be dropped.
+
+use crate::arch::sim::VIRTUAL_CLOCK;
+use crate::errors::SpawnError;
+use crate::mem::{StackPool, StackSizeClass};
+use crate::sched::{BandwidthController, BandwidthError, CpuId, Scheduler};
+use crate::thread_new::{ReadyRef, RunningRef, Thread, ThreadId};
+use crate::time::{Duration, Instant, TIMER_FREQUENCY_HZ, TIMER_WHEEL};
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Length of a kernel timer tick, which is also the timer wheel's tick.
+const KERNEL_TICK_NS: u64 = 1_000_000_000 / TIMER_FREQUENCY_HZ as u64;
+
+/// Held by the live simulator.
+static ACTIVE: spin::Mutex<()> = spin::Mutex::new(());
+
+/// What a simulated thread does with the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// Always wants the CPU.
+    CpuBound,
+    /// Needs `runtime` of CPU time every `period`. Each job is released at
+    /// the start of its period and due at the end of it.
+    Periodic { period: Duration, runtime: Duration },
+}
+
+/// What happened to a simulated thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SimThreadStats {
+    /// CPU time received (nanoseconds)
+    pub runtime_ns: u64,
+    /// Number of times the thread was switched in
+    pub switches: u64,
+    /// Periodic jobs completed
+    pub jobs: u64,
+    /// Jobs completed after their deadline
+    pub deadline_misses: u64,
+    /// Largest completion time past a deadline (nanoseconds)
+    pub max_lateness_ns: u64,
+    /// Largest delay from a release to the job first running (nanoseconds)
+    pub max_wakeup_latency_ns: u64,
+}
+
+/// How a simulated CPU spent its time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SimCpuStats {
+    /// Time spent running threads (nanoseconds)
+    pub busy_ns: u64,
+    /// Time spent switching (nanoseconds)
+    pub switch_ns: u64,
+    /// Time with nothing to run (nanoseconds)
+    pub idle_ns: u64,
+    /// Number of threads switched in
+    pub switches: u64,
+}
+
+struct SimThread {
+    thread: Thread,
+    workload: Workload,
+    /// CPU time the current job still needs (nanoseconds)
+    remaining: u64,
+    /// Release of the current job, or of the next one while sleeping
+    release: u64,
+    /// Waiting for `release`
+    sleeping: bool,
+    /// The current job hasn't run since its release
+    unstarted: bool,
+    stats: SimThreadStats,
+}
+
+impl SimThread {
+    fn period(&self) -> Option<(u64, u64)> {
+        match self.workload {
+            Workload::CpuBound => None,
+            Workload::Periodic { period, runtime } => Some((period.as_nanos(), runtime.as_nanos())),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SimCpu {
+    current: Option<RunningRef>,
+    /// Switch cost still to be paid before `current` runs
+    overhead: u64,
+    /// CPU time `current` received since it was last charged
+    uncharged: u64,
+    stats: SimCpuStats,
+}
+
+/// Discrete-event simulation of a scheduler in virtual time.
+pub struct Simulator<'a, S: Scheduler> {
+    scheduler: &'a S,
+    bandwidth: BandwidthController,
+    stacks: StackPool,
+    threads: BTreeMap<ThreadId, SimThread>,
+    cpus: Vec<SimCpu>,
+    tick_ns: u64,
+    switch_cost_ns: u64,
+    now: u64,
+    next_tick: u64,
+    _active: spin::MutexGuard<'static, ()>,
+}
+
+impl<'a, S: Scheduler> Simulator<'a, S> {
+    /// Create a simulation of `cpus` CPUs scheduled by `scheduler`.
+    ///
+    /// The tick defaults to the kernel's timer frequency and switches are
+    /// free until [`switch_cost`](Self::switch_cost) is set.
+    pub fn new(scheduler: &'a S, cpus: usize) -> Self {
+        let active = ACTIVE.lock();
+        let tick_ns = KERNEL_TICK_NS;
+        // A time slice starting at 0 reads as not started
+        VIRTUAL_CLOCK.advance_to(Instant::from_nanos(tick_ns));
+        let now = VIRTUAL_CLOCK.now().as_nanos();
+
+        Self {
+            scheduler,
+            bandwidth: BandwidthController::new(),
+            stacks: StackPool::new(),
+            threads: BTreeMap::new(),
+            cpus: (0..cpus.max(1)).map(|_| SimCpu::default()).collect(),
+            tick_ns,
+            switch_cost_ns: 0,
+            now,
+            next_tick: now + tick_ns,
+            _active: active,
+        }
+    }
+
+    /// Set the interval between scheduler ticks.
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.tick_ns = tick.as_nanos().max(1);
+        self.next_tick = self.now + self.tick_ns;
+        self
+    }
+
+    /// Set the time every context switch costs the CPU.
+    pub fn switch_cost(mut self, cost: Duration) -> Self {
+        self.switch_cost_ns = cost.as_nanos();
+        VIRTUAL_CLOCK.set_switch_cost(cost);
+        self
+    }
+
+    /// Get the current virtual time.
+    pub fn now(&self) -> Instant {
+        Instant::from_nanos(self.now)
+    }
+
+    /// Add a thread running `workload`, ready to run right away.
+    ///
+    /// The thread is in group 0; move it with [`Thread::set_group_id`].
+    pub fn spawn(&mut self, id: ThreadId, priority: u8, workload: Workload) -> Result<Thread, SpawnError> {
+        let stack = self
+            .stacks
+            .allocate(StackSizeClass::Small)
+            .ok_or(SpawnError::OutOfMemory)?;
+        let (thread, _) = Thread::new(id, stack, || {}, priority);
+
+        let remaining = match workload {
+            Workload::CpuBound => u64::MAX,
+            Workload::Periodic { runtime, .. } => runtime.as_nanos(),
+        };
+        self.threads.insert(
+            id,
+            SimThread {
+                thread: thread.clone(),
+                workload,
+                remaining,
+                release: self.now,
+                sleeping: false,
+                unstarted: true,
+                stats: SimThreadStats::default(),
+            },
+        );
+        self.scheduler.enqueue(ReadyRef(thread.clone()));
+        Ok(thread)
+    }
+
+    /// Limit thread group `group` to `quota` of CPU time every `period`.
+    pub fn set_bandwidth(&self, group: u32, quota: Duration, period: Duration) -> Result<(), BandwidthError> {
+        self.bandwidth.set_bandwidth(group, quota, period, self.now())
+    }
+
+    /// Get the bandwidth controller the simulated ticks charge.
+    pub fn bandwidth(&self) -> &BandwidthController {
+        &self.bandwidth
+    }
+
+    /// Get what happened to the thread `id` so far.
+    pub fn thread_stats(&self, id: ThreadId) -> Option<SimThreadStats> {
+        self.threads.get(&id).map(|thread| thread.stats)
+    }
+
+    /// Get how CPU `cpu` spent its time so far.
+    pub fn cpu_stats(&self, cpu: CpuId) -> Option<SimCpuStats> {
+        self.cpus.get(cpu).map(|cpu| cpu.stats)
+    }
+
+    /// Run the simulation for `duration` of virtual time.
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.now + duration.as_nanos();
+        loop {
+            self.release_due();
+            while self.next_tick <= self.now {
+                self.on_tick();
+                self.next_tick += self.tick_ns;
+            }
+            if self.now >= end {
+                break;
+            }
+            self.dispatch();
+
+            let next = self.next_event(end);
+            self.advance(next - self.now);
+            self.now = next;
+            VIRTUAL_CLOCK.advance_to(self.now());
+            TIMER_WHEEL.advance_to(self.now / KERNEL_TICK_NS);
+            self.complete_jobs();
+        }
+    }
+
+    /// Wake sleeping threads whose next job has been released.
+    fn release_due(&mut self) {
+        for thread in self.threads.values_mut() {
+            if thread.sleeping && thread.release <= self.now {
+                thread.sleeping = false;
+                thread.unstarted = true;
+                self.scheduler.wake_up(ReadyRef(thread.thread.clone()));
+            }
+        }
+    }
+
+    /// Replay the kernel's tick path on every CPU.
+    fn on_tick(&mut self) {
+        let now = self.now();
+        self.bandwidth.refill(now, self.scheduler);
+
+        for cpu in 0..self.cpus.len() {
+            let ran = core::mem::take(&mut self.cpus[cpu].uncharged);
+            let Some(current) = self.cpus[cpu].current.as_ref() else {
+                continue;
+            };
+            let throttled = self.bandwidth.charge(current.0.group_id(), Duration::from_nanos(ran), now);
+            let preempt = if throttled {
+                Some(current.prepare_preemption())
+            } else {
+                self.scheduler.on_tick(current)
+            };
+
+            if let Some(ready) = preempt {
+                self.cpus[cpu].current = None;
+                if let Some(ready) = self.bandwidth.park_if_throttled(ready) {
+                    self.scheduler.enqueue(ready);
+                }
+            }
+        }
+    }
+
+    /// Give idle CPUs the next runnable thread.
+    fn dispatch(&mut self) {
+        for cpu in 0..self.cpus.len() {
+            if self.cpus[cpu].current.is_some() {
+                continue;
+            }
+            let Some(next) = self.pick_runnable(cpu) else {
+                continue;
+            };
+
+            let running = next.start_running();
+            if let Some(thread) = self.threads.get_mut(&running.0.id()) {
+                thread.stats.switches += 1;
+                if thread.unstarted {
+                    thread.unstarted = false;
+                    let latency = self.now.saturating_sub(thread.release);
+                    thread.stats.max_wakeup_latency_ns = thread.stats.max_wakeup_latency_ns.max(latency);
+                }
+            }
+            let cpu = &mut self.cpus[cpu];
+            cpu.current = Some(running);
+            cpu.overhead = self.switch_cost_ns;
+            cpu.stats.switches += 1;
+        }
+    }
+
+    fn pick_runnable(&self, cpu: CpuId) -> Option<ReadyRef> {
+        while let Some(next) = self.scheduler.pick_next(cpu) {
+            if let Some(next) = self.bandwidth.park_if_throttled(next) {
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    /// Time of the next tick, release or job completion, at most `end`.
+    fn next_event(&self, end: u64) -> u64 {
+        let releases = self
+            .threads
+            .values()
+            .filter(|thread| thread.sleeping)
+            .map(|thread| thread.release);
+        let completions = self.cpus.iter().filter_map(|cpu| {
+            let thread = self.threads.get(&cpu.current.as_ref()?.0.id())?;
+            Some(self.now.saturating_add(cpu.overhead).saturating_add(thread.remaining))
+        });
+
+        releases
+            .chain(completions)
+            .fold(end.min(self.next_tick), u64::min)
+            .max(self.now + 1)
+    }
+
+    /// Run every CPU for `step` nanoseconds.
+    fn advance(&mut self, step: u64) {
+        for cpu in &mut self.cpus {
+            let Some(current) = cpu.current.as_ref() else {
+                cpu.stats.idle_ns += step;
+                continue;
+            };
+
+            let switching = cpu.overhead.min(step);
+            cpu.overhead -= switching;
+            cpu.stats.switch_ns += switching;
+
+            let ran = step - switching;
+            cpu.stats.busy_ns += ran;
+            cpu.uncharged += ran;
+            if let Some(thread) = self.threads.get_mut(&current.0.id()) {
+                thread.remaining = thread.remaining.saturating_sub(ran);
+                thread.stats.runtime_ns += ran;
+            }
+        }
+    }
+
+    /// Block threads that finished their job until its next release.
+    fn complete_jobs(&mut self) {
+        let now = self.now;
+        for cpu in &mut self.cpus {
+            let Some(current) = cpu.current.as_ref() else {
+                continue;
+            };
+            let Some(thread) = self.threads.get_mut(&current.0.id()) else {
+                continue;
+            };
+            let Some((period, runtime)) = thread.period() else {
+                continue;
+            };
+            if thread.remaining > 0 {
+                continue;
+            }
+
+            let deadline = thread.release + period;
+            thread.stats.jobs += 1;
+            if now > deadline {
+                thread.stats.deadline_misses += 1;
+                thread.stats.max_lateness_ns = thread.stats.max_lateness_ns.max(now - deadline);
+            }
+            thread.release = deadline;
+            thread.remaining = runtime;
+
+            // A late thread starts on its next job right away
+            if deadline > now {
+                thread.sleeping = true;
+                let ran = core::mem::take(&mut cpu.uncharged);
+                self.bandwidth.charge(current.0.group_id(), Duration::from_nanos(ran), Instant::from_nanos(now));
+                if let Some(current) = cpu.current.take() {
+                    current.block();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::sched::{priority, RoundRobinScheduler};
+
+    /// An hour of a periodic control loop next to a throttled batch job.
+    fn control_and_batch() -> (SimThreadStats, SimThreadStats, SimCpuStats, Duration) {
+        let scheduler = RoundRobinScheduler::new(1);
+        let mut sim = Simulator::new(&scheduler, 1)
+            .tick(Duration::from_millis(10))
+            .switch_cost(Duration::from_micros(5));
+        let start = sim.now();
+
+        let control = sim
+            .spawn(ThreadId::new(49_101), priority::HIGH, Workload::Periodic {
+                period: Duration::from_millis(100),
+                runtime: Duration::from_millis(20),
+            })
+            .unwrap();
+        let batch = sim.spawn(ThreadId::new(49_102), priority::NORMAL, Workload::CpuBound).unwrap();
+        batch.set_group_id(7);
+        sim.set_bandwidth(7, Duration::from_millis(30), Duration::from_millis(100)).unwrap();
+
+        sim.run_for(Duration::from_millis(3_600_000));
+        assert_eq!(sim.bandwidth().stats(7).unwrap().throttled_periods, 36_000);
+        (
+            sim.thread_stats(control.id()).unwrap(),
+            sim.thread_stats(batch.id()).unwrap(),
+            sim.cpu_stats(0).unwrap(),
+            sim.now().duration_since(start),
+        )
+    }
+
+    #[test]
+    fn test_simulated_hour_is_deterministic() {
+        let (control, batch, cpu, elapsed) = control_and_batch();
+        assert_eq!(elapsed, Duration::from_millis(3_600_000));
+        assert_eq!(control.jobs, 36_000);
+        assert_eq!(control.deadline_misses, 0);
+
+        // Throttling is checked on ticks, so the batch job overruns its 30%
+        // quota by up to a tick per period
+        let share = batch.runtime_ns * 100 / elapsed.as_nanos();
+        assert!((30..40).contains(&share), "batch share {share}%");
+        assert_eq!(cpu.busy_ns + cpu.switch_ns + cpu.idle_ns, elapsed.as_nanos());
+        // The last switch may still be in progress
+        assert!(cpu.switches * 5_000 - cpu.switch_ns < 5_000);
+
+        // Replaying the same inputs gives the same results
+        assert_eq!(control_and_batch(), (control, batch, cpu, elapsed));
+    }
+}
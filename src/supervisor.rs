@@ -0,0 +1,567 @@
+//! Supervision trees.
+//!
+//! A [`Supervisor`] owns the specs of a set of child threads, starts them,
+//! and starts them again when they exit according to each child's
+//! [`Restart`] policy, in the style of Erlang/OTP supervisors. A child
+//! counts as crashed when it is terminated or cancelled instead of
+//! returning from its entry point, or when it misses its watchdog: a child
+//! with [`ChildSpec::watchdog`] must call [`heartbeat`] at least that often,
+//! or the supervisor cancels it and treats it as crashed.
+//!
+//! Restarts are delayed by the child's backoff, which doubles with every
+//! consecutive crash. If children are restarted more than the supervisor's
+//! intensity allows within its period, it gives up: it cancels every
+//! remaining child and stops supervising. Crashes, watchdog expiries,
+//! restarts and giving up are all reported to the
+//! [health monitor](crate::observability::health::HEALTH_MONITOR).
+//!
+//! The supervisor runs on a thread of its own, so its children are listed
+//! as that thread's children in the thread hierarchy:
+//!
+//! ```ignore
+//! fn supervise() {
+//!     let mut supervisor = Supervisor::new("net")
+//!         .intensity(5, Duration::from_secs(10))
+//!         .child(ChildSpec::new("rx", rx_loop).watchdog(Duration::from_millis(100)))
+//!         .child(ChildSpec::new("tx", tx_loop).backoff(Duration::from_millis(10), Duration::from_secs(1)));
+//!     if supervisor.start(kernel()).is_ok() {
+//!         supervisor.run(kernel(), Duration::from_millis(20));
+//!     }
+//! }
+//!
+//! fn rx_loop() {
+//!     loop {
+//!         supervisor::heartbeat();
+//!         poll_rx_ring();
+//!     }
+//! }
+//! ```
+
+use crate::arch::Arch;
+use crate::errors::SpawnError;
+use crate::kernel::Kernel;
+use crate::observability::health::{HealthIssue, IssueCategory, IssueSeverity, HEALTH_MONITOR};
+use crate::sched::Scheduler;
+use crate::sync::SpinLockIrqSave;
+use crate::thread_new::{self, percpu, ExitStatus, Thread, ThreadBuilder, ThreadId};
+use crate::time::{Duration, Instant};
+extern crate alloc;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// Restarts allowed per period when [`Supervisor::intensity`] isn't set.
+pub const DEFAULT_MAX_RESTARTS: u32 = 3;
+
+/// Period over which restarts are counted when [`Supervisor::intensity`]
+/// isn't set (nanoseconds).
+pub const DEFAULT_PERIOD_NS: u64 = 5_000_000_000;
+
+/// When a child is restarted after it exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// Always restart the child, even when it returns normally
+    Permanent,
+    /// Restart the child only when it crashes
+    Transient,
+    /// Never restart the child
+    Temporary,
+}
+
+/// Description of a child the supervisor starts and restarts.
+#[derive(Debug, Clone)]
+pub struct ChildSpec {
+    name: String,
+    entry: fn(),
+    restart: Restart,
+    priority: u8,
+    backoff_initial: Duration,
+    backoff_max: Duration,
+    watchdog: Option<Duration>,
+}
+
+impl ChildSpec {
+    /// A permanent child named `name` running `entry` at normal priority,
+    /// restarted without delay and without a watchdog.
+    pub fn new<T: Into<String>>(name: T, entry: fn()) -> Self {
+        Self {
+            name: name.into(),
+            entry,
+            restart: Restart::Permanent,
+            priority: 128,
+            backoff_initial: Duration::from_nanos(0),
+            backoff_max: Duration::from_nanos(0),
+            watchdog: None,
+        }
+    }
+
+    /// Set when the child is restarted.
+    pub fn restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Set the priority of the child's thread.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Wait `initial` before restarting the child, doubling the wait after
+    /// every consecutive crash up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff_initial = initial;
+        self.backoff_max = max;
+        self
+    }
+
+    /// Require the child to call [`heartbeat`] at least once every
+    /// `timeout`.
+    pub fn watchdog(mut self, timeout: Duration) -> Self {
+        self.watchdog = Some(timeout);
+        self
+    }
+
+    /// Get the child's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the child's entry point.
+    pub fn entry(&self) -> fn() {
+        self.entry
+    }
+
+    /// Get the child's priority.
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// Get the delay before the restart following `crashes` consecutive
+    /// crashes.
+    fn restart_delay(&self, crashes: u32) -> Duration {
+        if crashes == 0 {
+            return self.backoff_initial;
+        }
+        let factor = 1u64 << (crashes - 1).min(32);
+        let delay = self.backoff_initial.as_nanos().saturating_mul(factor);
+        Duration::from_nanos(delay.min(self.backoff_max.as_nanos().max(self.backoff_initial.as_nanos())))
+    }
+}
+
+/// Something that can start child threads.
+///
+/// Implemented by [`Kernel`]; tests and hosted targets may provide their
+/// own.
+pub trait ChildSpawner {
+    /// Start a thread for `spec` and return it.
+    fn spawn_child(&self, spec: &ChildSpec) -> Result<Thread, SpawnError>;
+}
+
+impl<A: Arch, S: Scheduler> ChildSpawner for Kernel<A, S> {
+    fn spawn_child(&self, spec: &ChildSpec) -> Result<Thread, SpawnError> {
+        let builder = ThreadBuilder::new()
+            .name(spec.name.clone())
+            .priority(spec.priority);
+        // The supervisor watches the thread itself, so the handle is not kept
+        let handle = self.spawn(builder, spec.entry)?;
+        Ok(handle.thread())
+    }
+}
+
+/// Last heartbeat of every running child with a watchdog.
+static HEARTBEATS: SpinLockIrqSave<BTreeMap<ThreadId, Instant>> = SpinLockIrqSave::new(BTreeMap::new());
+
+/// Tell the supervisor that the current thread is still making progress.
+///
+/// Does nothing on threads without a watchdog.
+pub fn heartbeat() {
+    let Some(id) = percpu::current_id() else {
+        return;
+    };
+    if let Some(last) = HEARTBEATS.lock().get_mut(&id) {
+        *last = Instant::now();
+    }
+}
+
+/// Snapshot of one child of a supervisor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildInfo {
+    /// Name from the child's spec
+    pub name: String,
+    /// Thread currently running the child, if any
+    pub thread: Option<ThreadId>,
+    /// Number of times the child has been restarted
+    pub restarts: u64,
+    /// How the child's last thread exited
+    pub last_exit: Option<ExitStatus>,
+}
+
+/// A child and the thread running it.
+struct Child {
+    spec: ChildSpec,
+    thread: Option<Thread>,
+    /// When to start the child again, if it is waiting for a restart
+    restart_at: Option<Instant>,
+    /// Crashes since the child last returned normally
+    crashes: u32,
+    restarts: u64,
+    last_exit: Option<ExitStatus>,
+    /// Whether the watchdog cancelled the current thread
+    watchdog_fired: bool,
+}
+
+impl Child {
+    fn start(&mut self, spawner: &impl ChildSpawner, now: Instant) -> Result<(), SpawnError> {
+        let thread = spawner.spawn_child(&self.spec)?;
+        if self.spec.watchdog.is_some() {
+            HEARTBEATS.lock().insert(thread.id(), now);
+        }
+        self.thread = Some(thread);
+        self.restart_at = None;
+        self.watchdog_fired = false;
+        Ok(())
+    }
+}
+
+/// Starts, watches and restarts a set of child threads.
+pub struct Supervisor {
+    name: String,
+    children: Vec<Child>,
+    max_restarts: u32,
+    period: Duration,
+    /// Times of the restarts within the current period
+    recent_restarts: VecDeque<Instant>,
+    failed: bool,
+}
+
+impl Supervisor {
+    /// A supervisor named `name`, without children.
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            children: Vec::new(),
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            period: Duration::from_nanos(DEFAULT_PERIOD_NS),
+            recent_restarts: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// Give up once more than `max_restarts` restarts happen within
+    /// `period`.
+    pub fn intensity(mut self, max_restarts: u32, period: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.period = period;
+        self
+    }
+
+    /// Add a child. Children are started in the order they are added.
+    pub fn child(mut self, spec: ChildSpec) -> Self {
+        self.children.push(Child {
+            spec,
+            thread: None,
+            restart_at: None,
+            crashes: 0,
+            restarts: 0,
+            last_exit: None,
+            watchdog_fired: false,
+        });
+        self
+    }
+
+    /// Get the supervisor's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check whether the supervisor gave up after too many restarts.
+    pub fn has_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Start every child.
+    ///
+    /// If a child fails to start, the ones already started are cancelled
+    /// and the error is returned.
+    pub fn start(&mut self, spawner: &impl ChildSpawner) -> Result<(), SpawnError> {
+        self.start_at(spawner, Instant::now())
+    }
+
+    /// [`start`](Self::start) as of `now`.
+    pub fn start_at(&mut self, spawner: &impl ChildSpawner, now: Instant) -> Result<(), SpawnError> {
+        for index in 0..self.children.len() {
+            if let Err(error) = self.children[index].start(spawner, now) {
+                self.shutdown();
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle exited children, expired watchdogs and due restarts.
+    ///
+    /// Returns `false` once the supervisor has given up.
+    pub fn poll(&mut self, spawner: &impl ChildSpawner) -> bool {
+        self.poll_at(spawner, Instant::now())
+    }
+
+    /// [`poll`](Self::poll) as of `now`.
+    pub fn poll_at(&mut self, spawner: &impl ChildSpawner, now: Instant) -> bool {
+        if self.failed {
+            return false;
+        }
+
+        for index in 0..self.children.len() {
+            self.check_watchdog(index, now);
+            self.check_exit(index, now);
+            if self.failed {
+                return false;
+            }
+
+            let child = &mut self.children[index];
+            if child.restart_at.is_some_and(|at| at <= now) {
+                match child.start(spawner, now) {
+                    Ok(()) => {
+                        child.restarts += 1;
+                        let thread = child.thread.as_ref().map(Thread::id);
+                        let description = format!("Restarted child {} (restart {})", child.spec.name, child.restarts);
+                        self.report(IssueSeverity::Info, IssueCategory::Other, description, thread, now);
+                    }
+                    Err(error) => {
+                        // A failed restart counts as another crash
+                        let description = format!("Failed to restart child {}: {}", child.spec.name, error);
+                        self.report(IssueSeverity::Warning, IssueCategory::Resource, description, None, now);
+                        self.schedule_restart(index, true, now);
+                        if self.failed {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Supervise the children until the supervisor gives up, polling every
+    /// `interval`.
+    pub fn run(&mut self, spawner: &impl ChildSpawner, interval: Duration) {
+        while self.poll(spawner) {
+            thread_new::park_timeout(interval);
+        }
+    }
+
+    /// Cancel every running child, with everything it spawned, and stop
+    /// restarting them. Returns the number of threads cancelled.
+    pub fn shutdown(&mut self) -> usize {
+        let mut cancelled = 0;
+        for child in &mut self.children {
+            child.restart_at = None;
+            if let Some(thread) = &child.thread {
+                if thread.exit_status().is_none() {
+                    cancelled += thread.cancel_subtree();
+                }
+            }
+        }
+        cancelled
+    }
+
+    /// Get a snapshot of every child, in the order they were added.
+    pub fn children(&self) -> Vec<ChildInfo> {
+        self.children
+            .iter()
+            .map(|child| ChildInfo {
+                name: child.spec.name.clone(),
+                thread: child.thread.as_ref().filter(|thread| thread.exit_status().is_none()).map(Thread::id),
+                restarts: child.restarts,
+                last_exit: child.last_exit,
+            })
+            .collect()
+    }
+
+    /// Cancel child `index` if it has gone longer than its watchdog without
+    /// a heartbeat.
+    fn check_watchdog(&mut self, index: usize, now: Instant) {
+        let child = &mut self.children[index];
+        let (Some(timeout), Some(thread)) = (child.spec.watchdog, &child.thread) else {
+            return;
+        };
+        if child.watchdog_fired || thread.exit_status().is_some() {
+            return;
+        }
+        let Some(last) = HEARTBEATS.lock().get(&thread.id()).copied() else {
+            return;
+        };
+        let silent = now.as_nanos().saturating_sub(last.as_nanos());
+        if silent <= timeout.as_nanos() {
+            return;
+        }
+
+        child.watchdog_fired = true;
+        thread.cancel_subtree();
+        let thread = thread.id();
+        let description = format!(
+            "Child {} sent no heartbeat for {}ns, over its {}ns watchdog",
+            child.spec.name,
+            silent,
+            timeout.as_nanos()
+        );
+        self.report(IssueSeverity::Warning, IssueCategory::Scheduler, description, Some(thread), now);
+    }
+
+    /// Handle the exit of child `index`'s thread, if it has exited.
+    fn check_exit(&mut self, index: usize, now: Instant) {
+        let child = &mut self.children[index];
+        let Some(status) = child.thread.as_ref().and_then(Thread::exit_status) else {
+            return;
+        };
+        let thread = child.thread.take().map(|thread| thread.id());
+        if let Some(thread) = thread {
+            HEARTBEATS.lock().remove(&thread);
+        }
+        child.last_exit = Some(status);
+
+        let crashed = status != ExitStatus::Completed || child.watchdog_fired;
+        if crashed {
+            let description = format!("Child {} exited abnormally ({:?})", child.spec.name, status);
+            self.report(IssueSeverity::Warning, IssueCategory::Other, description, thread, now);
+        }
+
+        let restart = match self.children[index].spec.restart {
+            Restart::Permanent => true,
+            Restart::Transient => crashed,
+            Restart::Temporary => false,
+        };
+        if restart {
+            self.schedule_restart(index, crashed, now);
+        }
+    }
+
+    /// Schedule a restart of child `index` after its backoff, giving up if
+    /// that exceeds the restart intensity.
+    fn schedule_restart(&mut self, index: usize, crashed: bool, now: Instant) {
+        let window_start = now.as_nanos().saturating_sub(self.period.as_nanos());
+        while self.recent_restarts.front().is_some_and(|at| at.as_nanos() < window_start) {
+            self.recent_restarts.pop_front();
+        }
+        self.recent_restarts.push_back(now);
+        if self.recent_restarts.len() > self.max_restarts as usize {
+            self.give_up(now);
+            return;
+        }
+
+        let child = &mut self.children[index];
+        child.crashes = if crashed { child.crashes.saturating_add(1) } else { 0 };
+        child.restart_at = Some(now.add(child.spec.restart_delay(child.crashes)));
+    }
+
+    /// Stop supervising after too many restarts.
+    fn give_up(&mut self, now: Instant) {
+        self.failed = true;
+        self.shutdown();
+        let description = format!(
+            "Supervisor {} gave up after more than {} restarts within {}ns",
+            self.name,
+            self.max_restarts,
+            self.period.as_nanos()
+        );
+        self.report(IssueSeverity::Critical, IssueCategory::Other, description, None, now);
+    }
+
+    fn report(&self, severity: IssueSeverity, category: IssueCategory, description: String, thread: Option<ThreadId>, now: Instant) {
+        let mut context = BTreeMap::new();
+        context.insert("supervisor".to_string(), self.name.clone());
+        HEALTH_MONITOR.report_issue(HealthIssue {
+            severity,
+            category,
+            description,
+            component: "supervisor".to_string(),
+            detected_at: now,
+            context,
+            affected_threads: thread.map_or_else(Vec::new, |thread| vec![thread]),
+            remediation: None,
+        });
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::thread_new::ReadyRef;
+    use portable_atomic::{AtomicUsize, Ordering};
+
+    /// Creates threads without running them, so the test decides how they
+    /// exit.
+    struct TestSpawner {
+        pool: StackPool,
+        next_id: AtomicUsize,
+        spawned: spin::Mutex<Vec<Thread>>,
+    }
+
+    impl ChildSpawner for TestSpawner {
+        fn spawn_child(&self, spec: &ChildSpec) -> Result<Thread, SpawnError> {
+            let stack = self.pool.allocate(StackSizeClass::Small).ok_or(SpawnError::OutOfMemory)?;
+            let id = unsafe { ThreadId::new_unchecked(self.next_id.fetch_add(1, Ordering::Relaxed)) };
+            // Dropping the handle keeps the threads away from `join_any`
+            let thread = Thread::new(id, stack, spec.entry(), spec.get_priority()).0;
+            self.spawned.lock().push(thread.clone());
+            Ok(thread)
+        }
+    }
+
+    fn exit(thread: Option<ThreadId>, spawner: &TestSpawner, status: ExitStatus) {
+        let thread = spawner.spawned.lock().iter().find(|t| Some(t.id()) == thread).unwrap().clone();
+        ReadyRef(thread).start_running().exit(status);
+    }
+
+    #[test]
+    fn test_supervisor_restarts() {
+        let spawner = TestSpawner {
+            pool: StackPool::new(),
+            next_id: AtomicUsize::new(49_280),
+            spawned: spin::Mutex::new(Vec::new()),
+        };
+        let ms = |n: u64| Duration::from_millis(n);
+        let t0 = Instant::now();
+        let mut supervisor = Supervisor::new("test")
+            .intensity(1, ms(1000))
+            .child(ChildSpec::new("worker", || {}).backoff(ms(10), ms(40)))
+            .child(ChildSpec::new("job", || {}).restart(Restart::Transient))
+            .child(ChildSpec::new("once", || {}).restart(Restart::Temporary).watchdog(ms(5)));
+        supervisor.start_at(&spawner, t0).unwrap();
+        let children = supervisor.children();
+        let first_worker = children[0].thread;
+
+        // A transient child that returns normally stays stopped
+        exit(children[1].thread, &spawner, ExitStatus::Completed);
+        // A crashed permanent child is restarted after its backoff
+        exit(children[0].thread, &spawner, ExitStatus::Terminated);
+        assert!(supervisor.poll_at(&spawner, t0));
+        assert_eq!(supervisor.children()[0].thread, None);
+        assert_eq!(supervisor.children()[1].last_exit, Some(ExitStatus::Completed));
+
+        // By then the silent child has missed its watchdog
+        assert!(supervisor.poll_at(&spawner, t0.add(ms(10))));
+        let children = supervisor.children();
+        assert_eq!(children[0].restarts, 1);
+        assert!(children[0].thread.is_some() && children[0].thread != first_worker);
+        let once = spawner.spawned.lock().iter().find(|t| Some(t.id()) == children[2].thread).unwrap().clone();
+        assert!(once.is_cancelled());
+        exit(children[2].thread, &spawner, ExitStatus::Cancelled);
+        assert!(supervisor.poll_at(&spawner, t0.add(ms(10))));
+        assert_eq!(supervisor.children()[2].thread, None);
+        assert_eq!(supervisor.children()[2].restarts, 0);
+
+        // A second restart within the period is one too many
+        let worker = supervisor.children()[0].thread;
+        exit(worker, &spawner, ExitStatus::Terminated);
+        assert!(!supervisor.poll_at(&spawner, t0.add(ms(20))));
+        assert!(supervisor.has_failed());
+        assert_eq!(supervisor.children()[0].thread, None);
+    }
+}
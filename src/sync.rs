@@ -1,5 +1,12 @@
 use crate::scheduler::SCHEDULER;
 
+pub mod irq;
+pub mod spsc;
+pub mod wait_queue;
+
+pub use irq::{IrqSafe, SpinLockIrqSave, SpinLockIrqSaveGuard};
+pub use wait_queue::{WaitQueue, WakeMode};
+
 pub fn yield_thread() {
     unsafe {
         let scheduler = SCHEDULER.get();
@@ -40,6 +47,24 @@ pub fn exit_thread() -> ! {
     }
 }
 
-pub fn sleep_ms(_ms: u64) {
+/// Back off while polling for a condition in a blocking operation.
+pub(crate) fn relax() {
+    #[cfg(feature = "std-shim")]
+    {
+        extern crate std;
+        std::thread::yield_now();
+    }
+    
+    #[cfg(not(feature = "std-shim"))]
+    {
+        // In a real kernel implementation, we'd yield to scheduler
+        core::hint::spin_loop();
+    }
+}
+
+pub fn sleep_ms(ms: u64) {
+    crate::might_sleep!();
+    let until = crate::time::Instant::now().add(crate::time::Duration::from_millis(ms));
+    let _blocked = crate::thread_new::blocked_on(crate::thread_new::BlockedOn::Sleep(until));
     yield_thread();
 }
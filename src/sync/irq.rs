@@ -0,0 +1,147 @@
+//! Interrupt-safe locking primitives.
+//!
+//! A plain spin lock taken from thread context deadlocks the CPU if the
+//! timer interrupt fires while the lock is held and the handler tries to
+//! take the same lock. [`SpinLockIrqSave`] disables interrupts for as long
+//! as the lock is held, restoring the previous interrupt state on release.
+//!
+//! The [`IrqSafe`] marker identifies types that may be touched from both
+//! interrupt and thread context. State shared with the timer interrupt
+//! path (such as the scheduler) is required to be `IrqSafe`.
+
+use crate::time::IrqGuard;
+use core::ops::{Deref, DerefMut};
+
+/// Marker for types that can be safely accessed from interrupt context.
+///
+/// # Safety
+///
+/// Implementors must guarantee that every operation reachable through a
+/// shared reference either completes without taking a lock, or takes locks
+/// only with interrupts disabled. Interrupt handlers rely on this to never
+/// spin on a lock held by the thread they interrupted.
+pub unsafe trait IrqSafe {}
+
+unsafe impl IrqSafe for portable_atomic::AtomicBool {}
+unsafe impl IrqSafe for portable_atomic::AtomicUsize {}
+unsafe impl IrqSafe for portable_atomic::AtomicU32 {}
+unsafe impl IrqSafe for portable_atomic::AtomicU64 {}
+unsafe impl<T> IrqSafe for portable_atomic::AtomicPtr<T> {}
+unsafe impl<T: IrqSafe + ?Sized> IrqSafe for &T {}
+unsafe impl<T: IrqSafe + ?Sized> IrqSafe for alloc::boxed::Box<T> {}
+
+/// Spin lock that disables interrupts while held.
+///
+/// Interrupts are disabled before spinning and restored to their previous
+/// state after the lock is released, so nested use and use from interrupt
+/// handlers (where interrupts are already off) both behave correctly.
+pub struct SpinLockIrqSave<T: ?Sized> {
+    inner: spin::Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Send> IrqSafe for SpinLockIrqSave<T> {}
+
+impl<T> SpinLockIrqSave<T> {
+    /// Create a new unlocked lock.
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: spin::Mutex::new(data),
+        }
+    }
+
+    /// Consume the lock and return the protected data.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLockIrqSave<T> {
+    /// Disable interrupts and acquire the lock, spinning until available.
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockIrqSaveGuard<'_, T> {
+        let irq = IrqGuard::enter();
+        let guard = self.inner.lock();
+        SpinLockIrqSaveGuard { guard, _irq: irq }
+    }
+
+    /// Disable interrupts and try to acquire the lock without spinning.
+    ///
+    /// Interrupt state is restored immediately if the lock is contended.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockIrqSaveGuard<'_, T>> {
+        let irq = IrqGuard::enter();
+        let guard = self.inner.try_lock()?;
+        Some(SpinLockIrqSaveGuard { guard, _irq: irq })
+    }
+
+    /// Check whether the lock is currently held.
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Get mutable access to the data without locking.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for SpinLockIrqSave<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// RAII guard for [`SpinLockIrqSave`].
+///
+/// Releases the lock first, then restores the saved interrupt state.
+pub struct SpinLockIrqSaveGuard<'a, T: ?Sized> {
+    // Field order matters: the lock must be released before interrupts
+    // are re-enabled
+    guard: spin::MutexGuard<'a, T>,
+    _irq: IrqGuard,
+}
+
+impl<'a, T: ?Sized> Deref for SpinLockIrqSaveGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for SpinLockIrqSaveGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// Compile-time check that a value may be shared with interrupt handlers.
+#[inline(always)]
+pub(crate) fn assert_irq_safe<T: IrqSafe + ?Sized>(_: &T) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_and_release() {
+        let lock = SpinLockIrqSave::new(5u32);
+
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            assert!(lock.is_locked());
+            assert!(lock.try_lock().is_none());
+        }
+
+        assert!(!lock.is_locked());
+        assert_eq!(*lock.try_lock().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_irq_safe_marker() {
+        let lock = SpinLockIrqSave::new(0usize);
+        assert_irq_safe(&lock);
+        assert_irq_safe(&portable_atomic::AtomicUsize::new(0));
+    }
+}
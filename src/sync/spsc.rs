@@ -0,0 +1,632 @@
+//! Single-producer single-consumer ring buffer.
+//!
+//! [`RingBuffer`] moves items from one thread to another without locks or
+//! per-item allocation. The producer and consumer indices sit on separate
+//! cache lines, and each side caches the other's index, so in the steady
+//! state a transfer touches only one shared cache line per batch.
+//!
+//! Batches are transferred in place: [`Producer::write_slice`] reserves a
+//! run of free slots to fill directly and publishes them when the
+//! [`WriteSlice`] is dropped; [`Consumer::read_slice`] lends out a run of
+//! queued items and frees them when the [`ReadSlice`] is dropped. For
+//! `Copy` items [`Producer::write`] and [`Consumer::read`] copy whole
+//! slices across the wrap-around.
+//!
+//! The blocking variants park the waiting thread and are unparked by the
+//! other side, so an idle consumer costs nothing while the producer only
+//! pays for a wakeup when the consumer is actually waiting.
+//!
+//! ```ignore
+//! let (mut tx, mut rx) = RingBuffer::<i16>::new(1024);
+//!
+//! // Audio capture thread
+//! tx.write_all(&samples)?;
+//!
+//! // Processing thread
+//! let batch = rx.read_slice(256);
+//! process(&batch);
+//! drop(batch); // Frees the slots
+//! ```
+
+use super::WaitQueue;
+use crate::thread_new::BlockedOn;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Keeps a value on its own cache line.
+#[repr(align(64))] // Cache line aligned
+struct CachePadded<T>(T);
+
+/// Bounded lock-free queue between one producer and one consumer.
+///
+/// Created split into its [`Producer`] and [`Consumer`] ends with
+/// [`RingBuffer::new`].
+pub struct RingBuffer<T> {
+    /// Position of the next item to read; written by the consumer
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next slot to write; written by the producer
+    tail: CachePadded<AtomicUsize>,
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    producer_alive: AtomicBool,
+    consumer_alive: AtomicBool,
+    /// Consumer waiting for items
+    readers: WaitQueue,
+    /// Producer waiting for room
+    writers: WaitQueue,
+}
+
+// Safety: items are moved between the two ends, each slot being owned by
+// exactly one of them at any time as tracked by `head` and `tail`
+unsafe impl<T: Send> Send for RingBuffer<T> {}
+unsafe impl<T: Send> Sync for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    /// Create a buffer for at least `capacity` items, rounded up to a power
+    /// of two, and return its two ends.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(capacity: usize) -> (Producer<T>, Consumer<T>) {
+        let capacity = capacity.max(1).next_power_of_two();
+        let slots = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+        let ring = Arc::new(Self {
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            slots,
+            mask: capacity - 1,
+            producer_alive: AtomicBool::new(true),
+            consumer_alive: AtomicBool::new(true),
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+        });
+        let producer = Producer {
+            ring: ring.clone(),
+            tail: 0,
+            cached_head: 0,
+        };
+        let consumer = Consumer {
+            ring,
+            head: 0,
+            cached_tail: 0,
+        };
+        (producer, consumer)
+    }
+
+    /// Get the number of items the buffer holds.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Get the number of queued items.
+    pub fn len(&self) -> usize {
+        self.tail.0.load(Ordering::Acquire).wrapping_sub(self.head.0.load(Ordering::Acquire))
+    }
+
+    /// Check whether no items are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Disconnect both ends.
+    ///
+    /// Blocked calls on either end return as if the other end had been
+    /// dropped; items already queued can still be read.
+    pub fn close(&self) {
+        self.producer_alive.store(false, Ordering::Release);
+        self.consumer_alive.store(false, Ordering::Release);
+        self.readers.wake_one_on(self.channel());
+        self.writers.wake_one_on(self.channel());
+    }
+
+    /// Blocking reason of a thread waiting on either end.
+    fn channel(&self) -> BlockedOn {
+        BlockedOn::Channel(self as *const Self as usize as u64)
+    }
+
+    /// Pointer to the slot at `position`.
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        UnsafeCell::raw_get(&self.slots[position & self.mask])
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        let mut position = *self.head.0.get_mut();
+        let tail = *self.tail.0.get_mut();
+        while position != tail {
+            // Safety: slots between head and tail hold initialized items
+            unsafe { (*self.slot(position)).assume_init_drop() };
+            position = position.wrapping_add(1);
+        }
+    }
+}
+
+/// Writing end of a [`RingBuffer`].
+pub struct Producer<T> {
+    ring: Arc<RingBuffer<T>>,
+    /// Local copy of the ring's tail
+    tail: usize,
+    /// Last head seen; the consumer only moves it forward
+    cached_head: usize,
+}
+
+impl<T> Producer<T> {
+    /// Get the buffer this end writes to.
+    pub fn ring(&self) -> &RingBuffer<T> {
+        &self.ring
+    }
+
+    /// Check whether the consumer was dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.ring.consumer_alive.load(Ordering::Acquire)
+    }
+
+    /// Get the number of free slots.
+    pub fn free_len(&mut self) -> usize {
+        self.writable(usize::MAX)
+    }
+
+    /// Get the number of free slots, only re-reading the consumer's head
+    /// if fewer than `wanted` are known to be free.
+    fn writable(&mut self, wanted: usize) -> usize {
+        let capacity = self.ring.capacity();
+        let mut free = capacity - self.tail.wrapping_sub(self.cached_head);
+        if free < wanted {
+            self.cached_head = self.ring.head.0.load(Ordering::Acquire);
+            free = capacity - self.tail.wrapping_sub(self.cached_head);
+        }
+        free
+    }
+
+    /// Queue `value`, handing it back if the buffer is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        self.write_slice(1).push(value)
+    }
+
+    /// Queue `value`, waiting for room.
+    ///
+    /// Hands the value back if the consumer is dropped meanwhile.
+    pub fn push_blocking(&mut self, mut value: T) -> Result<(), T> {
+        loop {
+            if self.is_closed() {
+                return Err(value);
+            }
+            value = match self.push(value) {
+                Ok(()) => return Ok(()),
+                Err(value) => value,
+            };
+            self.wait_for_room(1);
+        }
+    }
+
+    /// Reserve up to `max` contiguous free slots.
+    ///
+    /// The reservation may be shorter than `max`, or empty, if the buffer
+    /// is nearly full or the free run wraps around the end. Items pushed
+    /// into it are published when it is dropped.
+    pub fn write_slice(&mut self, max: usize) -> WriteSlice<'_, T> {
+        let start = self.tail & self.ring.mask;
+        let max = max.min(self.ring.capacity() - start);
+        let len = self.writable(max).min(max);
+        WriteSlice {
+            producer: self,
+            len,
+            filled: 0,
+        }
+    }
+
+    /// Publish `count` slots filled from the current tail.
+    fn commit(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.tail = self.tail.wrapping_add(count);
+        self.ring.tail.0.store(self.tail, Ordering::Release);
+        self.ring.readers.wake_one_on(self.ring.channel());
+    }
+
+    fn wait_for_room(&mut self, needed: usize) {
+        let ring = &self.ring;
+        let tail = self.tail;
+        ring.writers.wait_while_on(ring.channel(), || {
+            let free = ring.capacity() - tail.wrapping_sub(ring.head.0.load(Ordering::Acquire));
+            free < needed && ring.consumer_alive.load(Ordering::Acquire)
+        }, None);
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    /// Copy as much of `items` as fits, returning how many were queued.
+    pub fn write(&mut self, items: &[T]) -> usize {
+        let mut written = 0;
+        // The free space wraps around at most once
+        for _ in 0..2 {
+            let mut slice = self.write_slice(items.len() - written);
+            written += slice.copy_from_slice(&items[written..]);
+        }
+        written
+    }
+
+    /// Copy all of `items`, waiting for room as needed.
+    ///
+    /// Fails with the number of items queued if the consumer is dropped
+    /// first.
+    pub fn write_all(&mut self, items: &[T]) -> Result<(), usize> {
+        let mut written = 0;
+        while written < items.len() {
+            if self.is_closed() {
+                return Err(written);
+            }
+            written += self.write(&items[written..]);
+            if written < items.len() {
+                self.wait_for_room(1);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.producer_alive.store(false, Ordering::Release);
+        self.ring.readers.wake_one_on(self.ring.channel());
+    }
+}
+
+/// Free slots reserved by [`Producer::write_slice`].
+///
+/// Filled slots are published when the reservation is dropped.
+pub struct WriteSlice<'a, T> {
+    producer: &'a mut Producer<T>,
+    len: usize,
+    filled: usize,
+}
+
+impl<T> WriteSlice<'_, T> {
+    /// Get the number of reserved slots.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether no slots could be reserved.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the number of slots filled so far.
+    pub fn filled(&self) -> usize {
+        self.filled
+    }
+
+    /// Fill the next reserved slot, handing `value` back if all are full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.filled == self.len {
+            return Err(value);
+        }
+        let position = self.producer.tail.wrapping_add(self.filled);
+        // Safety: the slot is reserved, so the consumer doesn't touch it
+        unsafe { (*self.producer.ring.slot(position)).write(value) };
+        self.filled += 1;
+        Ok(())
+    }
+
+    /// Get the unfilled reserved slots, to write items in place.
+    ///
+    /// Call [`assume_filled`](Self::assume_filled) after initializing them.
+    pub fn as_uninit(&mut self) -> &mut [MaybeUninit<T>] {
+        let position = self.producer.tail.wrapping_add(self.filled);
+        // Safety: the reserved run is contiguous and owned by the producer
+        unsafe { core::slice::from_raw_parts_mut(self.producer.ring.slot(position), self.len - self.filled) }
+    }
+
+    /// Mark the first `count` slots of [`as_uninit`](Self::as_uninit) as
+    /// filled.
+    ///
+    /// # Safety
+    ///
+    /// Those slots must have been initialized.
+    pub unsafe fn assume_filled(&mut self, count: usize) {
+        assert!(count <= self.len - self.filled, "more slots filled than reserved");
+        self.filled += count;
+    }
+
+    /// Publish the filled slots now.
+    pub fn commit(self) {}
+}
+
+impl<T: Copy> WriteSlice<'_, T> {
+    /// Fill slots from `items`, returning how many were copied.
+    pub fn copy_from_slice(&mut self, items: &[T]) -> usize {
+        let slots = self.as_uninit();
+        let count = slots.len().min(items.len());
+        for (slot, item) in slots.iter_mut().zip(&items[..count]) {
+            slot.write(*item);
+        }
+        self.filled += count;
+        count
+    }
+}
+
+impl<T> Drop for WriteSlice<'_, T> {
+    fn drop(&mut self) {
+        self.producer.commit(self.filled);
+    }
+}
+
+/// Reading end of a [`RingBuffer`].
+pub struct Consumer<T> {
+    ring: Arc<RingBuffer<T>>,
+    /// Local copy of the ring's head
+    head: usize,
+    /// Last tail seen; the producer only moves it forward
+    cached_tail: usize,
+}
+
+impl<T> Consumer<T> {
+    /// Get the buffer this end reads from.
+    pub fn ring(&self) -> &RingBuffer<T> {
+        &self.ring
+    }
+
+    /// Get a shared reference to the buffer, outliving this end.
+    pub(crate) fn shared(&self) -> Arc<RingBuffer<T>> {
+        self.ring.clone()
+    }
+
+    /// Check whether the producer was dropped.
+    ///
+    /// Items it queued before can still be read.
+    pub fn is_closed(&self) -> bool {
+        !self.ring.producer_alive.load(Ordering::Acquire)
+    }
+
+    /// Get the number of queued items.
+    pub fn available(&mut self) -> usize {
+        self.readable(usize::MAX)
+    }
+
+    /// Get the number of queued items, only re-reading the producer's tail
+    /// if fewer than `wanted` are known to be queued.
+    fn readable(&mut self, wanted: usize) -> usize {
+        let mut queued = self.cached_tail.wrapping_sub(self.head);
+        if queued < wanted {
+            self.cached_tail = self.ring.tail.0.load(Ordering::Acquire);
+            queued = self.cached_tail.wrapping_sub(self.head);
+        }
+        queued
+    }
+
+    /// Take the next item, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.readable(1) == 0 {
+            return None;
+        }
+        // Safety: the slot holds an initialized item owned by the consumer
+        let value = unsafe { (*self.ring.slot(self.head)).assume_init_read() };
+        self.release(1);
+        Some(value)
+    }
+
+    /// Take the next item, waiting for one.
+    ///
+    /// Returns `None` once the producer is dropped and the buffer drained.
+    pub fn pop_blocking(&mut self) -> Option<T> {
+        loop {
+            if let Some(value) = self.pop() {
+                return Some(value);
+            }
+            if self.is_closed() {
+                // The producer may have pushed right before leaving
+                return self.pop();
+            }
+            self.wait_for_items(1);
+        }
+    }
+
+    /// Borrow up to `max` contiguous queued items.
+    ///
+    /// The run may be shorter than `max`, or empty, if fewer items are
+    /// queued or they wrap around the end. The items are dropped and their
+    /// slots freed when the [`ReadSlice`] is dropped.
+    pub fn read_slice(&mut self, max: usize) -> ReadSlice<'_, T> {
+        let start = self.head & self.ring.mask;
+        let max = max.min(self.ring.capacity() - start);
+        let len = self.readable(max).min(max);
+        ReadSlice {
+            consumer: self,
+            len,
+            release: len,
+        }
+    }
+
+    /// Free `count` slots from the current head.
+    fn release(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.head = self.head.wrapping_add(count);
+        self.ring.head.0.store(self.head, Ordering::Release);
+        self.ring.writers.wake_one_on(self.ring.channel());
+    }
+
+    fn wait_for_items(&mut self, needed: usize) {
+        let ring = &self.ring;
+        let head = self.head;
+        ring.readers.wait_while_on(ring.channel(), || {
+            ring.tail.0.load(Ordering::Acquire).wrapping_sub(head) < needed
+                && ring.producer_alive.load(Ordering::Acquire)
+        }, None);
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Copy queued items into `buffer`, returning how many were taken.
+    pub fn read(&mut self, buffer: &mut [T]) -> usize {
+        let mut read = 0;
+        // The queued items wrap around at most once
+        for _ in 0..2 {
+            let slice = self.read_slice(buffer.len() - read);
+            buffer[read..read + slice.len()].copy_from_slice(&slice);
+            read += slice.len();
+        }
+        read
+    }
+
+    /// Fill all of `buffer`, waiting for items as needed.
+    ///
+    /// Fails with the number of items taken if the producer is dropped
+    /// before enough were queued.
+    pub fn read_exact(&mut self, buffer: &mut [T]) -> Result<(), usize> {
+        let mut read = 0;
+        while read < buffer.len() {
+            read += self.read(&mut buffer[read..]);
+            if read < buffer.len() {
+                if self.is_closed() && self.available() == 0 {
+                    return Err(read);
+                }
+                self.wait_for_items(1);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.ring.consumer_alive.store(false, Ordering::Release);
+        self.ring.writers.wake_one_on(self.ring.channel());
+    }
+}
+
+/// Queued items lent out by [`Consumer::read_slice`].
+///
+/// Dereferences to the items. They are dropped and their slots freed when
+/// the slice is dropped, unless fewer are released with
+/// [`release`](Self::release).
+pub struct ReadSlice<'a, T> {
+    consumer: &'a mut Consumer<T>,
+    len: usize,
+    release: usize,
+}
+
+impl<T> ReadSlice<'_, T> {
+    /// Free only the first `count` items, leaving the rest queued.
+    pub fn release(mut self, count: usize) {
+        self.release = count.min(self.len);
+    }
+}
+
+impl<T> Deref for ReadSlice<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        let first = self.consumer.ring.slot(self.consumer.head) as *const T;
+        // Safety: the run is contiguous, initialized and owned by the
+        // consumer until released
+        unsafe { core::slice::from_raw_parts(first, self.len) }
+    }
+}
+
+impl<T> Drop for ReadSlice<'_, T> {
+    fn drop(&mut self) {
+        let head = self.consumer.head;
+        for offset in 0..self.release {
+            // Safety: released items are initialized and never read again
+            unsafe { (*self.consumer.ring.slot(head.wrapping_add(offset))).assume_init_drop() };
+        }
+        self.consumer.release(self.release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_spsc_batches_wrap_around() {
+        let (mut tx, mut rx) = RingBuffer::<u32>::new(6);
+        assert_eq!(tx.ring().capacity(), 8);
+
+        assert_eq!(tx.write(&[1, 2, 3, 4, 5, 6]), 6);
+        let mut out = [0u32; 4];
+        assert_eq!(rx.read(&mut out), 4);
+        assert_eq!(out, [1, 2, 3, 4]);
+
+        // 6 free slots, but only 2 before the end of the buffer
+        {
+            let mut slice = tx.write_slice(10);
+            assert_eq!(slice.len(), 2);
+            assert_eq!(slice.copy_from_slice(&[7, 8, 9]), 2);
+        }
+        assert_eq!(tx.write(&[9, 10, 11, 12, 13]), 4);
+        assert_eq!(tx.free_len(), 0);
+        assert_eq!(tx.push(99), Err(99));
+
+        // Zero-copy read up to the end, keeping part of it queued
+        let slice = rx.read_slice(usize::MAX);
+        assert_eq!(&*slice, &[5, 6, 7, 8]);
+        slice.release(1);
+        assert_eq!(rx.pop(), Some(6));
+
+        let mut rest = Vec::new();
+        while let Some(value) = rx.pop() {
+            rest.push(value);
+        }
+        assert_eq!(rest, [7, 8, 9, 10, 11, 12]);
+        assert!(rx.ring().is_empty());
+
+        drop(tx);
+        assert!(rx.is_closed());
+        assert_eq!(rx.pop_blocking(), None);
+    }
+
+    #[test]
+    fn test_spsc_drops_items_once() {
+        let counter = Arc::new(());
+        let (mut tx, mut rx) = RingBuffer::new(4);
+        for _ in 0..3 {
+            assert!(tx.push(counter.clone()).is_ok());
+        }
+        drop(rx.read_slice(1));
+        assert!(rx.pop().is_some());
+        assert_eq!(Arc::strong_count(&counter), 2);
+
+        // Items still queued are dropped with the buffer
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_spsc_blocking_transfer() {
+        const ITEMS: u32 = 2_000;
+        let (mut tx, mut rx) = RingBuffer::<u32>::new(16);
+
+        let producer = std::thread::spawn(move || {
+            let items: Vec<u32> = (0..ITEMS).collect();
+            for chunk in items.chunks(7) {
+                tx.write_all(chunk).unwrap();
+            }
+        });
+
+        let mut buffer = [0u32; 5];
+        let mut expected = 0;
+        while expected < ITEMS - ITEMS % 5 {
+            rx.read_exact(&mut buffer).unwrap();
+            for value in buffer {
+                assert_eq!(value, expected);
+                expected += 1;
+            }
+        }
+        while let Some(value) = rx.pop_blocking() {
+            assert_eq!(value, expected);
+            expected += 1;
+        }
+        assert_eq!(expected, ITEMS);
+        producer.join().unwrap();
+    }
+}
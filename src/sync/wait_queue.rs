@@ -0,0 +1,328 @@
+//! FIFO queues of blocked threads.
+//!
+//! A [`WaitQueue`] parks threads until another thread wakes them. Waking
+//! is a handoff: [`wake_one`](WaitQueue::wake_one) picks the thread that
+//! has waited longest and marks that thread woken, so a thread that starts
+//! waiting afterwards cannot take the wakeup, and it is never lost to a
+//! waiter that timed out in the meantime.
+//!
+//! Whether [`notify`](WaitQueue::notify) wakes one waiter or all of them is
+//! set per queue with [`WakeMode`]. Waking everyone for a resource only one
+//! of them can take makes the rest wake up for nothing; those wakeups are
+//! counted per wait point in [`herd`](crate::observability::herd).
+//!
+//! ```ignore
+//! static ITEMS: WaitQueue = WaitQueue::new();
+//!
+//! // Consumer
+//! ITEMS.wait_while(|| queue.is_empty());
+//!
+//! // Producer
+//! queue.push(item);
+//! ITEMS.notify();
+//! ```
+
+use crate::observability::herd;
+use crate::thread_new::{self, blocked_on, BlockedOn, Thread};
+use crate::time::{Duration, Timeout, TIMER_WHEEL};
+extern crate alloc;
+use alloc::{collections::VecDeque, sync::Arc};
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// How many waiters [`WaitQueue::notify`] wakes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeMode {
+    /// Hand the wakeup to the longest-waiting thread
+    One,
+    /// Wake every waiting thread
+    All,
+}
+
+/// One blocked thread.
+pub(crate) struct WaitNode {
+    thread: Option<Thread>,
+    woken: AtomicBool,
+}
+
+/// Queue of threads waiting for an event.
+pub struct WaitQueue {
+    waiters: spin::Mutex<VecDeque<Arc<WaitNode>>>,
+    /// Number of queued waiters, readable without the lock
+    len: AtomicUsize,
+    mode: WakeMode,
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitQueue {
+    /// Create an empty queue that wakes one waiter per notification.
+    pub const fn new() -> Self {
+        Self::with_mode(WakeMode::One)
+    }
+
+    /// Create an empty queue whose notifications wake as set by `mode`.
+    pub const fn with_mode(mode: WakeMode) -> Self {
+        Self {
+            waiters: spin::Mutex::new(VecDeque::new()),
+            len: AtomicUsize::new(0),
+            mode,
+        }
+    }
+
+    /// Get how many waiters a notification wakes.
+    pub fn mode(&self) -> WakeMode {
+        self.mode
+    }
+
+    /// Get the number of waiting threads.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Check whether no thread is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Block until woken.
+    pub fn wait(&self) {
+        let node = self.register();
+        let _blocked = blocked_on(self.point());
+        self.block(&node, None);
+    }
+
+    /// Block until woken or until `timeout` passes. Returns whether the
+    /// thread was woken.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = TIMER_WHEEL.arm(timeout);
+        let node = self.register();
+        let _blocked = blocked_on(self.point());
+        self.block(&node, Some(&deadline))
+    }
+
+    /// Block while `condition` holds, re-checking it after every wakeup.
+    pub fn wait_while(&self, condition: impl FnMut() -> bool) {
+        self.wait_while_on(self.point(), condition, None);
+    }
+
+    /// Like [`wait_while`](Self::wait_while), but give up after `timeout`.
+    /// Returns whether the condition stopped holding.
+    pub fn wait_while_timeout(&self, condition: impl FnMut() -> bool, timeout: Duration) -> bool {
+        let deadline = TIMER_WHEEL.arm(timeout);
+        self.wait_while_on(self.point(), condition, Some(&deadline))
+    }
+
+    /// Wake waiters as set by the queue's [`WakeMode`]. Returns the number
+    /// woken.
+    pub fn notify(&self) -> usize {
+        match self.mode {
+            WakeMode::One => self.wake_one() as usize,
+            WakeMode::All => self.wake_all(),
+        }
+    }
+
+    /// Hand a wakeup to the longest-waiting thread. Returns whether there
+    /// was one.
+    pub fn wake_one(&self) -> bool {
+        self.wake_one_on(self.point())
+    }
+
+    /// Wake every waiting thread. Returns the number woken.
+    pub fn wake_all(&self) -> usize {
+        self.wake_all_on(self.point())
+    }
+
+    /// Wait point of the queue's own waiters.
+    fn point(&self) -> BlockedOn {
+        BlockedOn::WaitQueue(self as *const Self as usize)
+    }
+
+    /// Queue the current thread as a waiter.
+    ///
+    /// Callers register before their last check of the condition they wait
+    /// for, so a wakeup sent after that check reaches them.
+    pub(crate) fn register(&self) -> Arc<WaitNode> {
+        let node = Arc::new(WaitNode {
+            thread: thread_new::current(),
+            woken: AtomicBool::new(false),
+        });
+        self.waiters.lock().push_back(node.clone());
+        self.len.fetch_add(1, Ordering::SeqCst);
+        node
+    }
+
+    /// Block until `node` is woken or `deadline` expires. Returns whether
+    /// it was woken; a node that times out is removed from the queue.
+    pub(crate) fn block(&self, node: &Arc<WaitNode>, deadline: Option<&Timeout<'_>>) -> bool {
+        crate::might_sleep!();
+
+        loop {
+            if node.woken.load(Ordering::Acquire) {
+                return true;
+            }
+            match deadline {
+                Some(deadline) if deadline.expired() => return self.unregister(node),
+                Some(_) => crate::sync::relax(),
+                None => thread_new::park(),
+            }
+        }
+    }
+
+    /// Remove `node` from the queue. Returns whether it had already been
+    /// taken off by a waker, in which case it is woken or about to be.
+    fn unregister(&self, node: &Arc<WaitNode>) -> bool {
+        let mut waiters = self.waiters.lock();
+        match waiters.iter().position(|queued| Arc::ptr_eq(queued, node)) {
+            Some(index) => {
+                waiters.remove(index);
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Block while `condition` holds, counting wakeups that find it still
+    /// holding as wasted on `point`. Returns whether the condition stopped
+    /// holding before `deadline`.
+    pub(crate) fn wait_while_on(
+        &self,
+        point: BlockedOn,
+        mut condition: impl FnMut() -> bool,
+        deadline: Option<&Timeout<'_>>,
+    ) -> bool {
+        if !condition() {
+            return true;
+        }
+
+        let _blocked = blocked_on(point);
+        loop {
+            let node = self.register();
+            // Re-check after registering, so a wakeup sent in between is
+            // not lost
+            if !condition() {
+                if self.unregister(&node) {
+                    // Pass on the wakeup we no longer need
+                    self.wake_one_on(point);
+                }
+                return true;
+            }
+            if !self.block(&node, deadline) {
+                return !condition();
+            }
+            if !condition() {
+                return true;
+            }
+            herd::record_wasted(point);
+        }
+    }
+
+    /// [`wake_one`](Self::wake_one), counted on `point`.
+    pub(crate) fn wake_one_on(&self, point: BlockedOn) -> bool {
+        portable_atomic::fence(Ordering::SeqCst);
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return false;
+        }
+
+        let node = {
+            let mut waiters = self.waiters.lock();
+            let node = waiters.pop_front();
+            if node.is_some() {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+            }
+            node
+        };
+        let Some(node) = node else {
+            return false;
+        };
+        node.wake();
+        herd::record_wake(point, 1);
+        true
+    }
+
+    /// [`wake_all`](Self::wake_all), counted on `point`.
+    pub(crate) fn wake_all_on(&self, point: BlockedOn) -> usize {
+        portable_atomic::fence(Ordering::SeqCst);
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return 0;
+        }
+
+        let nodes = {
+            let mut waiters = self.waiters.lock();
+            self.len.store(0, Ordering::Release);
+            core::mem::take(&mut *waiters)
+        };
+        for node in &nodes {
+            node.wake();
+        }
+        herd::record_wake(point, nodes.len());
+        nodes.len()
+    }
+}
+
+impl WaitNode {
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(thread) = &self.thread {
+            thread.unpark();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::observability::herd::wait_point_stats;
+    extern crate std;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_wake_one_handoff_and_herd_counts() {
+        static QUEUE: WaitQueue = WaitQueue::with_mode(WakeMode::All);
+        static ITEMS: AtomicUsize = AtomicUsize::new(0);
+        let point = QUEUE.point();
+
+        // Three consumers wait for one item each
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    QUEUE.wait_while(|| {
+                        ITEMS
+                            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |items| items.checked_sub(1))
+                            .is_err()
+                    })
+                })
+            })
+            .collect();
+        while QUEUE.len() < 3 {
+            std::thread::yield_now();
+        }
+
+        // Waking everyone for one item sends two of them back to sleep
+        ITEMS.store(1, Ordering::Release);
+        assert_eq!(QUEUE.notify(), 3);
+        while QUEUE.len() < 2 {
+            std::thread::yield_now();
+        }
+        let stats = wait_point_stats(point).unwrap();
+        assert_eq!((stats.herds, stats.woken, stats.wasted, stats.acquired()), (1, 3, 2, 1));
+        assert!(crate::observability::herd::worst_wait_points(usize::MAX).iter().any(|&(p, _)| p == point));
+
+        // Handing off one wakeup per item wastes none
+        for _ in 0..2 {
+            ITEMS.fetch_add(1, Ordering::AcqRel);
+            assert!(QUEUE.wake_one());
+        }
+        for consumer in consumers {
+            consumer.join().unwrap();
+        }
+        let stats = wait_point_stats(point).unwrap();
+        assert_eq!((stats.wakes, stats.woken, stats.wasted), (3, 5, 2));
+        assert!(!QUEUE.wait_timeout(Duration::from_nanos(0)));
+        assert!(QUEUE.is_empty());
+    }
+}
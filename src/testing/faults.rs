@@ -0,0 +1,252 @@
+//! Deterministic fault injection.
+//!
+//! Error paths around spawning and synchronization are hard to reach on
+//! purpose: stacks rarely run out, CAS loops rarely lose a race and timers
+//! rarely miss. With the `fault-injection` feature, the crate checks a
+//! [`Fault`] at each of these points and fails it on demand, so tests can
+//! drive their error handling without contriving out-of-memory conditions.
+//!
+//! | Fault                          | Injected as                                  |
+//! |--------------------------------|----------------------------------------------|
+//! | [`Fault::StackAllocation`]     | `StackPool::allocate` returns `None`         |
+//! | [`Fault::CasContention`]       | a lock or lock-free pool CAS fails and retries |
+//! | [`Fault::TimerMiss`]           | the timer wheel skips advancing for a tick   |
+//! | [`Fault::SpuriousWakeup`]      | `park` returns without being unparked        |
+//!
+//! Faults fire either on every nth check or at random, and random faults
+//! come from a generator seeded with [`seed`], so a failing run replays
+//! exactly by reusing its seed. Without the feature the checks compile to
+//! nothing and configuring faults has no effect.
+//!
+//! ```ignore
+//! faults::seed(0x5eed);
+//! faults::configure(Fault::StackAllocation, FaultConfig::one_in(4).limit(10));
+//! faults::configure(Fault::SpuriousWakeup, FaultConfig::every(2));
+//! run_workload();
+//! assert!(faults::injected(Fault::StackAllocation) > 0);
+//! faults::reset();
+//! ```
+
+use crate::sync::SpinLockIrqSave;
+use portable_atomic::{AtomicBool, Ordering};
+
+/// Point at which a failure can be injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fault {
+    /// Stack allocation fails as if memory were exhausted
+    StackAllocation,
+    /// A compare-and-swap fails as if another CPU won the race
+    CasContention,
+    /// A timer tick is missed and its timeouts expire a tick late
+    TimerMiss,
+    /// A parked thread wakes up without being unparked
+    SpuriousWakeup,
+}
+
+impl Fault {
+    /// Every injectable fault.
+    pub const ALL: [Fault; 4] = [
+        Fault::StackAllocation,
+        Fault::CasContention,
+        Fault::TimerMiss,
+        Fault::SpuriousWakeup,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// When a fault fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultConfig {
+    trigger: Trigger,
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    /// On every nth check
+    Every(u64),
+    /// With probability 1/n on each check
+    OneIn(u64),
+}
+
+impl FaultConfig {
+    /// Fire on every `n`th check; `every(1)` fires on all of them, which
+    /// turns [`Fault::CasContention`] into a contention storm.
+    pub fn every(n: u64) -> Self {
+        Self {
+            trigger: Trigger::Every(n.max(1)),
+            limit: None,
+        }
+    }
+
+    /// Fire at random, with probability `1/n` on each check.
+    pub fn one_in(n: u64) -> Self {
+        Self {
+            trigger: Trigger::OneIn(n.max(1)),
+            limit: None,
+        }
+    }
+
+    /// Stop firing after `max` injections.
+    pub fn limit(mut self, max: u64) -> Self {
+        self.limit = Some(max);
+        self
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FaultState {
+    config: Option<FaultConfig>,
+    checks: u64,
+    injected: u64,
+    /// SplitMix64 state, so each fault's stream is independent of how
+    /// often the others are checked
+    rng: u64,
+}
+
+impl FaultState {
+    const fn new() -> Self {
+        Self {
+            config: None,
+            checks: 0,
+            injected: 0,
+            rng: 0,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn check(&mut self) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+        if config.limit.is_some_and(|limit| self.injected >= limit) {
+            return false;
+        }
+
+        self.checks += 1;
+        let fire = match config.trigger {
+            Trigger::Every(n) => self.checks % n == 0,
+            Trigger::OneIn(n) => self.next_random() % n == 0,
+        };
+        if fire {
+            self.injected += 1;
+        }
+        fire
+    }
+}
+
+struct Injector {
+    seed: u64,
+    faults: [FaultState; 4],
+}
+
+impl Injector {
+    fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        for (index, state) in self.faults.iter_mut().enumerate() {
+            state.rng = seed ^ (index as u64).wrapping_mul(0xd1b5_4a32_d192_ed03);
+            state.checks = 0;
+            state.injected = 0;
+        }
+    }
+}
+
+static INJECTOR: SpinLockIrqSave<Injector> = SpinLockIrqSave::new(Injector {
+    seed: 0,
+    faults: [FaultState::new(); 4],
+});
+
+/// Set while any fault is configured, so idle checks skip the lock.
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Restart every fault's random stream from `seed` and zero the counts.
+pub fn seed(seed: u64) {
+    INJECTOR.lock().reseed(seed);
+}
+
+/// Inject `fault` as described by `config`, replacing any earlier setting.
+pub fn configure(fault: Fault, config: FaultConfig) {
+    let mut injector = INJECTOR.lock();
+    injector.faults[fault.index()].config = Some(config);
+    ARMED.store(true, Ordering::Release);
+}
+
+/// Stop injecting `fault`. Its count is kept.
+pub fn clear(fault: Fault) {
+    let mut injector = INJECTOR.lock();
+    injector.faults[fault.index()].config = None;
+    let armed = injector.faults.iter().any(|state| state.config.is_some());
+    ARMED.store(armed, Ordering::Release);
+}
+
+/// Stop injecting all faults and restart from the current seed.
+pub fn reset() {
+    let mut injector = INJECTOR.lock();
+    for state in injector.faults.iter_mut() {
+        state.config = None;
+    }
+    let seed = injector.seed;
+    injector.reseed(seed);
+    ARMED.store(false, Ordering::Release);
+}
+
+/// Get the number of times `fault` has been injected.
+pub fn injected(fault: Fault) -> u64 {
+    INJECTOR.lock().faults[fault.index()].injected
+}
+
+/// Check whether to inject `fault` at this point.
+///
+/// Always `false` without the `fault-injection` feature.
+#[inline(always)]
+pub(crate) fn inject(fault: Fault) -> bool {
+    if !cfg!(feature = "fault-injection") || !ARMED.load(Ordering::Relaxed) {
+        return false;
+    }
+    INJECTOR.lock().faults[fault.index()].check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A private injector, so tests running alongside don't see the faults
+    fn injector(seed: u64, config: FaultConfig) -> Injector {
+        let mut injector = Injector {
+            seed: 0,
+            faults: [FaultState::new(); 4],
+        };
+        injector.reseed(seed);
+        injector.faults[Fault::TimerMiss.index()].config = Some(config);
+        injector
+    }
+
+    fn pattern(injector: &mut Injector, checks: usize) -> u64 {
+        let state = &mut injector.faults[Fault::TimerMiss.index()];
+        (0..checks).fold(0, |bits, bit| bits | (state.check() as u64) << bit)
+    }
+
+    #[test]
+    fn test_faults_replay_from_seed() {
+        let first = pattern(&mut injector(0x5eed, FaultConfig::one_in(3)), 64);
+        assert_ne!(first, 0);
+        // Same seed, same faults
+        assert_eq!(pattern(&mut injector(0x5eed, FaultConfig::one_in(3)), 64), first);
+
+        let mut every = injector(0x5eed, FaultConfig::every(4).limit(3));
+        assert_eq!(pattern(&mut every, 16), 0b1000_1000_1000);
+        assert_eq!(every.faults[Fault::TimerMiss.index()].injected, 3);
+        // Other faults are untouched
+        assert!(!every.faults[Fault::SpuriousWakeup.index()].check());
+    }
+}
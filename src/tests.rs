@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod tests {
     use crate::{error::*, scheduler::*, thread::*};
-    use std::{vec, vec::Vec};
+    use std::{string::ToString, vec, vec::Vec};
 
     #[test]
     fn test_thread_creation() {
@@ -92,7 +92,7 @@ mod tests {
         let stack: &'static mut [u8] = unsafe { std::mem::transmute(stacks[32].as_mut_slice()) };
         let result = scheduler.spawn_thread(stack, test_thread, 1);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), ThreadError::MaxThreadsReached);
+        assert_eq!(result.unwrap_err(), ThreadError::Spawn(crate::errors::SpawnError::TooManyThreads));
     }
 
     #[test]
@@ -128,26 +128,40 @@ mod tests {
 
     #[test]
     fn test_error_types() {
+        use crate::errors::{InvalidOperationError, MemoryError, ScheduleError, SpawnError};
+
         assert_eq!(
-            ThreadError::MaxThreadsReached.as_str(),
+            SpawnError::TooManyThreads.to_string(),
             "Maximum number of threads reached"
         );
         assert_eq!(
-            ThreadError::InvalidThreadId.as_str(),
+            InvalidOperationError::InvalidThreadId.to_string(),
             "Invalid thread ID provided"
         );
         assert_eq!(
-            ThreadError::ThreadNotRunnable.as_str(),
-            "Thread is not in a runnable state"
+            InvalidOperationError::WrongState.to_string(),
+            "Operation called in wrong state"
         );
         assert_eq!(
-            ThreadError::StackOverflow.as_str(),
+            MemoryError::StackOverflow.to_string(),
             "Stack overflow detected"
         );
         assert_eq!(
-            ThreadError::SchedulerFull.as_str(),
-            "Scheduler queue is full"
+            ThreadError::from(ScheduleError::QueueFull).to_string(),
+            "Scheduling error: Scheduler queue is full"
         );
+
+        let error = ThreadError::from(crate::errors::SpawnError::TooManyThreads);
+        assert_eq!(error.kind(), ErrorKind::LimitExceeded);
+        assert_eq!(error.subsystem(), Subsystem::Spawn);
+        assert_eq!(error.thread_id(), None);
+
+        let thread = crate::thread_new::ThreadId::new(7);
+        let error = ThreadError::TimedOut.for_thread(thread);
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(error.thread_id(), Some(thread));
+        assert_eq!(error.root(), &ThreadError::TimedOut);
+        assert_eq!(error.to_string(), "Thread 7: Operation timed out");
     }
 
     #[test]
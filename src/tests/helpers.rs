@@ -94,7 +94,7 @@ impl TestThreadFactory {
     
     pub fn create_thread(&self) -> Arc<Thread> {
         let thread = Arc::new(Thread::new_test_thread());
-        thread.set_priority(self.default_priority);
+        thread.set_priority(self.default_priority).unwrap();
         thread
     }
     
@@ -117,7 +117,7 @@ impl TestThreadFactory {
         let mut threads = Vec::with_capacity(priorities.len());
         for (i, &priority) in priorities.iter().enumerate() {
             let thread = self.create_named_thread(&format!("priority_thread_{}", i));
-            thread.set_priority(priority);
+            thread.set_priority(priority).unwrap();
             threads.push(thread);
         }
         threads
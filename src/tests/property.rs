@@ -107,7 +107,7 @@ mod property_tests {
         // Create test threads
         for i in 0..thread_count {
             let thread = Arc::new(crate::thread_new::Thread::new_test_thread());
-            thread.set_priority(5); // Same priority for fairness test
+            thread.set_priority(5).unwrap(); // Same priority for fairness test
             threads.push(thread.clone());
             scheduler.schedule(thread);
         }
@@ -403,7 +403,7 @@ mod property_tests {
         for i in 0..thread_count {
             let priority = rng.gen_range(1, 11) as u8;
             let thread = Arc::new(crate::thread_new::Thread::new_test_thread());
-            thread.set_priority(priority);
+            thread.set_priority(priority).unwrap();
             
             scheduler.schedule(thread.clone());
             expected_order.push((priority, thread.id()));
@@ -56,11 +56,11 @@ mod thread_tests {
         assert_eq!(thread.priority(), 5);
         
         // Set new priority
-        thread.set_priority(10);
+        thread.set_priority(10).unwrap();
         assert_eq!(thread.priority(), 10);
         
         // Priority should be clamped to MAX_PRIORITY
-        thread.set_priority(255);
+        thread.set_priority(255).unwrap();
         assert_eq!(thread.priority(), crate::thread_new::MAX_PRIORITY);
     }
     
@@ -203,10 +203,10 @@ mod scheduler_tests {
         
         // Create threads with different priorities
         let low_prio = Arc::new(Thread::new_test_thread());
-        low_prio.set_priority(1);
+        low_prio.set_priority(1).unwrap();
         
         let high_prio = Arc::new(Thread::new_test_thread());
-        high_prio.set_priority(10);
+        high_prio.set_priority(10).unwrap();
         
         // Schedule both threads
         scheduler.schedule(low_prio.clone());
@@ -1,3 +1,17 @@
+//! Original thread API, backed by [`thread_new`].
+//!
+//! Threads are identified by small indices and created from a stack buffer
+//! the caller owns, as they were before [`thread_new`] existed. With the
+//! `std-shim` feature each one is a [`thread_new::Thread`] underneath, so
+//! it shows up in the thread table, observability and scheduler run queues
+//! like any other thread. Without it there is no allocator for those, and
+//! [`Thread`] is the original implementation from [`legacy`](crate::legacy),
+//! which is also available on its own with the `legacy` feature enabled.
+
+#[cfg(feature = "std-shim")]
+use crate::mem::Stack;
+use crate::thread_new;
+
 pub type ThreadId = usize;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -8,6 +22,17 @@ pub enum ThreadState {
     Finished,
 }
 
+impl From<thread_new::ThreadState> for ThreadState {
+    fn from(state: thread_new::ThreadState) -> Self {
+        match state {
+            thread_new::ThreadState::Ready => ThreadState::Ready,
+            thread_new::ThreadState::Running => ThreadState::Running,
+            thread_new::ThreadState::Blocked | thread_new::ThreadState::Suspended => ThreadState::Blocked,
+            thread_new::ThreadState::Finished => ThreadState::Finished,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct ThreadContext {
     pub rsp: u64,
@@ -21,6 +46,44 @@ pub struct ThreadContext {
     pub rip: u64,
 }
 
+#[cfg(not(feature = "std-shim"))]
+pub use crate::legacy::thread::Thread;
+
+/// IDs of threads created through this API start here, above the range
+/// kernels hand out.
+#[cfg(feature = "std-shim")]
+const ID_BASE: u64 = 1 << 32;
+
+/// Get the [`thread_new`] ID of the thread with index `id`.
+#[cfg(feature = "std-shim")]
+pub(crate) fn to_new_id(id: ThreadId) -> thread_new::ThreadId {
+    thread_new::ThreadId::new(ID_BASE + id as u64)
+}
+
+/// Get the index of the thread with [`thread_new`] ID `id`, if it was
+/// created through this API.
+#[cfg(feature = "std-shim")]
+pub(crate) fn from_new_id(id: thread_new::ThreadId) -> Option<ThreadId> {
+    id.as_u64().checked_sub(ID_BASE).map(|id| id as ThreadId)
+}
+
+/// Guard written at the lowest address of a stack whose thread has no
+/// canary of its own.
+#[cfg(feature = "std-shim")]
+const STACK_GUARD: u64 = 0xDEADBEEFCAFEBABE;
+
+/// Bytes left free at the top of a stack for the frame the
+/// [`thread_new`] context starts from, below which the frame for
+/// [`Thread::context`] is built.
+#[cfg(feature = "std-shim")]
+const NEW_CONTEXT_RESERVE: usize = 16;
+
+/// A thread of the original API.
+///
+/// `state` and `priority` are what [`crate::scheduler`] schedules by.
+/// Assigning `state` takes effect on the [`thread_new`] thread at the
+/// scheduler's next call; switches made elsewhere show up in it then too.
+#[cfg(feature = "std-shim")]
 pub struct Thread {
     pub id: ThreadId,
     pub state: ThreadState,
@@ -32,16 +95,27 @@ pub struct Thread {
     pub priority: u8,
     pub stack_guard: u64,
     pub join_waiters: [Option<ThreadId>; 4],
+    inner: thread_new::Thread,
+    /// `state` as of the last sync with `inner`
+    synced_state: ThreadState,
+    /// Kept so the thread isn't treated as detached
+    _handle: thread_new::JoinHandle,
 }
 
+#[cfg(feature = "std-shim")]
 impl Thread {
     pub const STACK_SIZE: usize = 64 * 1024;
 
     pub fn new(id: ThreadId, stack: &'static mut [u8], entry_point: fn(), priority: u8) -> Self {
         let stack_top = unsafe { stack.as_mut_ptr().add(stack.len()) };
         let stack_bottom = stack.as_mut_ptr();
-        let stack_guard = 0xDEADBEEFCAFEBABE;
+        // Safety: the thread runs on the stack the `stack` field refers
+        // to, as it always did
+        let memory = unsafe { core::slice::from_raw_parts_mut(stack_bottom, stack.len()) };
+        let (inner, handle) = thread_new::Thread::new(to_new_id(id), Stack::borrowed(memory), entry_point, priority);
 
+        // A hardened thread's canary already sits where the guard goes
+        let stack_guard = inner.stack_canary().unwrap_or(STACK_GUARD);
         unsafe {
             core::ptr::write(stack_bottom as *mut u64, stack_guard);
         }
@@ -67,15 +141,21 @@ impl Thread {
             priority,
             stack_guard,
             join_waiters: [None; 4],
+            inner,
+            synced_state: ThreadState::Ready,
+            _handle: handle,
         };
 
         thread.initialize_stack();
         thread
     }
 
+    /// Build the frame [`context`](Self::context) starts from, below the
+    /// one the [`thread_new`] context uses.
     fn initialize_stack(&mut self) {
         unsafe {
-            let stack_ptr = self.stack_top as *mut u64;
+            let frame_top = (self.stack_top as usize & !15) - NEW_CONTEXT_RESERVE;
+            let stack_ptr = frame_top as *mut u64;
 
             let stack_ptr = stack_ptr.sub(1);
             *stack_ptr = thread_wrapper as usize as u64;
@@ -112,6 +192,35 @@ impl Thread {
         }
     }
 
+    /// Get the [`thread_new::Thread`] this thread is backed by.
+    pub fn inner(&self) -> &thread_new::Thread {
+        &self.inner
+    }
+
+    /// Set the state of the thread and its [`thread_new`] thread.
+    pub(crate) fn set_state(&mut self, state: ThreadState) {
+        self.state = state;
+        self.synced_state = state;
+        self.inner.set_state(match state {
+            ThreadState::Ready => thread_new::ThreadState::Ready,
+            ThreadState::Running => thread_new::ThreadState::Running,
+            ThreadState::Blocked => thread_new::ThreadState::Blocked,
+            ThreadState::Finished => thread_new::ThreadState::Finished,
+        });
+    }
+
+    /// Bring `state` and the [`thread_new`] thread's state in line: a
+    /// state assigned to the field since the last sync wins, otherwise
+    /// the field takes the thread's.
+    pub(crate) fn sync_state(&mut self) {
+        if self.state != self.synced_state {
+            self.set_state(self.state);
+        } else {
+            self.state = self.inner.state().into();
+            self.synced_state = self.state;
+        }
+    }
+
     pub fn is_runnable(&self) -> bool {
         self.state == ThreadState::Ready || self.state == ThreadState::Running
     }
@@ -124,6 +233,7 @@ impl Thread {
     }
 }
 
+#[cfg(feature = "std-shim")]
 extern "C" fn thread_entry() {
     unsafe {
         let scheduler = crate::scheduler::SCHEDULER.get();
@@ -136,6 +246,7 @@ extern "C" fn thread_entry() {
     crate::sync::exit_thread();
 }
 
+#[cfg(feature = "std-shim")]
 extern "C" fn thread_wrapper() -> ! {
     thread_entry();
     crate::sync::exit_thread();
@@ -0,0 +1,142 @@
+//! What a blocked thread is waiting for.
+//!
+//! Every blocking primitive in the crate records a [`BlockedOn`] reason on
+//! the waiting thread for as long as it waits, so the `ps` listing and the
+//! deadlock reports can say why a thread is stuck rather than only that it
+//! is. Code that blocks outside the crate's primitives, such as a driver
+//! waiting for its interrupt, records its own reason with [`blocked_on`]:
+//!
+//! ```ignore
+//! let _blocked = thread_new::blocked_on(BlockedOn::Irq(UART_IRQ));
+//! while !uart.rx_ready() {
+//!     thread_new::park();
+//! }
+//! ```
+//!
+//! Only the outermost reason is kept, so a channel that parks while it
+//! waits shows up as a channel wait rather than a bare park.
+//!
+//! The time a thread spends blocked is added to its
+//! [metrics](crate::observability::metrics::ThreadMetrics) when the reason
+//! is cleared, as I/O wait if it waited for an interrupt or marked itself
+//! with [`io_wait`], and as blocked time otherwise. I/O waits with a device
+//! ID are also attributed to the device:
+//!
+//! ```ignore
+//! let _io = thread_new::io_wait(Some(DISK0));
+//! disk.submit(request);
+//! while !request.done() {
+//!     thread_new::park();
+//! }
+//! ```
+
+use super::{current, Thread, ThreadId};
+use crate::observability::metrics::GLOBAL_METRICS;
+use crate::time::Instant;
+use core::fmt;
+
+/// Resource a blocked thread is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockedOn {
+    /// Waiting to lock the mutex at this address
+    Mutex(usize),
+    /// Waiting for the condition variable at this address to be notified
+    Condvar(usize),
+    /// Waiting to send to or receive from a channel; the crate's own
+    /// channels are identified by the address of their shared state
+    Channel(u64),
+    /// Waiting on the [`WaitQueue`](crate::sync::WaitQueue) at this address
+    WaitQueue(usize),
+    /// Waiting for a thread to finish
+    Join(ThreadId),
+    /// Sleeping until this time
+    Sleep(Instant),
+    /// Waiting for this interrupt
+    Irq(u32),
+    /// Waiting for I/O to complete, on the device with this ID if the
+    /// driver gave one
+    Io(Option<u32>),
+    /// Parked until unparked, for no more specific reason
+    Park,
+}
+
+impl BlockedOn {
+    /// Check whether this is a wait for I/O rather than for another thread
+    /// or for time to pass.
+    pub fn is_io_wait(&self) -> bool {
+        matches!(self, BlockedOn::Irq(_) | BlockedOn::Io(_))
+    }
+    
+    /// Get the ID of the device waited for, if any.
+    pub fn device(&self) -> Option<u32> {
+        match *self {
+            BlockedOn::Io(device) => device,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BlockedOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockedOn::Mutex(addr) => write!(f, "mutex {:#x}", addr),
+            BlockedOn::Condvar(addr) => write!(f, "condvar {:#x}", addr),
+            BlockedOn::Channel(id) => write!(f, "channel {:#x}", id),
+            BlockedOn::WaitQueue(addr) => write!(f, "wait queue {:#x}", addr),
+            BlockedOn::Join(thread) => write!(f, "join of thread {}", thread),
+            BlockedOn::Sleep(until) => write!(f, "sleep until {}ns", until.as_nanos()),
+            BlockedOn::Irq(irq) => write!(f, "irq {}", irq),
+            BlockedOn::Io(Some(device)) => write!(f, "I/O on device {}", device),
+            BlockedOn::Io(None) => f.write_str("I/O"),
+            BlockedOn::Park => f.write_str("park"),
+        }
+    }
+}
+
+/// Record that the current thread is about to block on `reason`.
+///
+/// The reason is cleared when the returned guard is dropped. If the thread
+/// already has a reason recorded, or there is no current thread, nothing
+/// is recorded.
+pub fn blocked_on(reason: BlockedOn) -> BlockedOnGuard {
+    BlockedOnGuard::record(current(), reason)
+}
+
+/// Mark the current thread as waiting for I/O on `device`, if known, until
+/// the returned guard is dropped.
+///
+/// For drivers that block outside the crate's primitives, like
+/// [`blocked_on`] with [`BlockedOn::Io`].
+pub fn io_wait(device: Option<u32>) -> BlockedOnGuard {
+    blocked_on(BlockedOn::Io(device))
+}
+
+/// Clears the reason recorded by [`blocked_on`] when dropped.
+#[must_use = "the reason is cleared as soon as the guard is dropped"]
+pub struct BlockedOnGuard {
+    /// Thread the reason was recorded on, if it was
+    thread: Option<Thread>,
+    reason: BlockedOn,
+    since: Instant,
+}
+
+impl BlockedOnGuard {
+    /// Record `reason` on `thread`, which must be the current thread.
+    pub(crate) fn record(thread: Option<Thread>, reason: BlockedOn) -> Self {
+        Self {
+            thread: thread.filter(|thread| thread.record_blocked_on(reason)),
+            reason,
+            since: Instant::now(),
+        }
+    }
+}
+
+impl Drop for BlockedOnGuard {
+    fn drop(&mut self) {
+        if let Some(thread) = &self.thread {
+            thread.clear_blocked_on();
+            let waited = Instant::now().duration_since(self.since);
+            GLOBAL_METRICS.record_blocked_time(thread.id(), self.reason, waited);
+        }
+    }
+}
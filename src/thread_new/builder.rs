@@ -1,11 +1,16 @@
 //! Thread builder for configuring thread creation.
 
-use super::{Thread, JoinHandle, ThreadId};
-use crate::mem::{StackPool, StackSizeClass};
+use super::{hierarchy, ExitCallback, Thread, JoinHandle, ReadyRef, ThreadId};
+use crate::arch::Arch;
+use crate::kernel::Kernel;
+use crate::mem::{Stack, StackPool, StackSizeClass};
 use crate::errors::SpawnError;
+use crate::sched::{CpuSet, Scheduler};
+use crate::observability::resource_limits::GLOBAL_RESOURCE_LIMITER;
 use crate::time::Duration;
+use crate::perf::numa::{self, NumaNodeId, NumaPolicy, NumaTopology};
 extern crate alloc;
-use alloc::string::String;
+use alloc::{string::String, vec::Vec};
 
 /// Builder for configuring and creating new threads.
 ///
@@ -20,10 +25,14 @@ pub struct ThreadBuilder {
     priority: u8,
     /// Thread name (for debugging and profiling)
     name: Option<String>,
-    /// CPU affinity mask (bitfield of allowed CPUs)
-    cpu_affinity: Option<u64>,
+    /// CPUs the thread may run on
+    cpu_affinity: Option<CpuSet>,
     /// Thread group ID for resource accounting
     group_id: Option<u32>,
+    /// NUMA placement policy
+    numa_policy: NumaPolicy,
+    /// Switch domain for sibling fast switches (0 = none)
+    switch_domain: u32,
     /// Whether to enable stack guard pages
     stack_guard_pages: bool,
     /// Whether to enable stack canary protection
@@ -32,14 +41,26 @@ pub struct ThreadBuilder {
     custom_canary: Option<u64>,
     /// Time slice duration override
     time_slice: Option<Duration>,
+    /// Longest the thread runs between yields, for the cooperative watchdog
+    max_run_length: Option<Duration>,
+    /// Proportional-share tickets, for the stride scheduler
+    tickets: Option<u32>,
+    /// How late the thread's timeouts may expire
+    timer_slack: Option<Duration>,
     /// Whether this thread is critical (affects scheduling)
     critical: bool,
     /// Whether this thread can be preempted
     preemptible: bool,
+    /// Whether to keep SMT siblings idle while this thread runs
+    no_smt: bool,
     /// Thread-local storage size reservation
     tls_size: Option<usize>,
     /// Whether to enable detailed debugging info
     debug_info: bool,
+    /// Whether the thread is spawned detached
+    detached: bool,
+    /// Callback to run once the thread has exited
+    on_exit: Option<ExitCallback>,
     /// Custom thread attributes
     attributes: ThreadAttributes,
 }
@@ -82,14 +103,22 @@ impl ThreadBuilder {
             name: None,
             cpu_affinity: None,
             group_id: None,
+            numa_policy: NumaPolicy::Local,
+            switch_domain: 0,
             stack_guard_pages: cfg!(feature = "mmu"),
             stack_canary: true,
             custom_canary: None,
             time_slice: None,
+            max_run_length: None,
+            tickets: None,
+            timer_slack: None,
             critical: false,
+            no_smt: false,
             preemptible: true,
             tls_size: None,
             debug_info: cfg!(debug_assertions),
+            detached: false,
+            on_exit: None,
             attributes: ThreadAttributes::default(),
         }
     }
@@ -115,14 +144,18 @@ impl ThreadBuilder {
     }
     
     /// Set the thread name for debugging purposes.
+    ///
+    /// If another live thread already has this name, the spawned thread is
+    /// named with a `-2`, `-3`, ... suffix instead, so names stay unique.
     pub fn name<T: Into<String>>(mut self, name: T) -> Self {
         self.name = Some(name.into());
         self
     }
     
-    /// Set CPU affinity mask (which CPUs this thread can run on).
-    pub fn cpu_affinity(mut self, mask: u64) -> Self {
-        self.cpu_affinity = Some(mask);
+    /// Set the CPUs this thread can run on, as a [`CpuSet`] or a `u64`
+    /// mask of CPUs 0 to 63.
+    pub fn cpu_affinity(mut self, cpus: impl Into<CpuSet>) -> Self {
+        self.cpu_affinity = Some(cpus.into());
         self
     }
     
@@ -132,6 +165,32 @@ impl ThreadBuilder {
         self
     }
     
+    /// Bind the thread and its stack to a NUMA node.
+    pub fn numa_node(mut self, node: NumaNodeId) -> Self {
+        self.numa_policy = NumaPolicy::Node(node);
+        self
+    }
+    
+    /// Spread threads round-robin across NUMA nodes.
+    pub fn numa_interleave(mut self) -> Self {
+        self.numa_policy = NumaPolicy::Interleave;
+        self
+    }
+    
+    /// Allocate the thread's stack on the spawning CPU's node (the default).
+    pub fn numa_local(mut self) -> Self {
+        self.numa_policy = NumaPolicy::Local;
+        self
+    }
+    
+    /// Place the thread in a switch domain with its siblings.
+    ///
+    /// See [`Thread::set_switch_domain`] for what sharing a domain implies.
+    pub fn switch_domain(mut self, domain: u32) -> Self {
+        self.switch_domain = domain;
+        self
+    }
+    
     /// Enable or disable stack guard pages (requires MMU feature).
     pub fn stack_guard_pages(mut self, enabled: bool) -> Self {
         self.stack_guard_pages = enabled;
@@ -157,6 +216,33 @@ impl ThreadBuilder {
         self
     }
     
+    /// Declare the longest the thread runs before it yields or blocks.
+    ///
+    /// Used as a watchdog under a
+    /// [`CooperativeScheduler`](crate::sched::CooperativeScheduler).
+    pub fn max_run_length(mut self, duration: Duration) -> Self {
+        self.max_run_length = Some(duration);
+        self
+    }
+    
+    /// Give the thread `tickets` shares of CPU time instead of the number
+    /// derived from its nice value.
+    ///
+    /// Used by the [`StrideScheduler`](crate::sched::StrideScheduler).
+    pub fn tickets(mut self, tickets: u32) -> Self {
+        self.tickets = Some(tickets);
+        self
+    }
+    
+    /// Let the thread's sleeps and timeouts expire up to `slack` late, so
+    /// the timer wheel can expire them together with others.
+    ///
+    /// Real-time threads always get exact expiry.
+    pub fn timer_slack(mut self, slack: Duration) -> Self {
+        self.timer_slack = Some(slack);
+        self
+    }
+    
     /// Mark this thread as critical (affects scheduling priority).
     pub fn critical(mut self, critical: bool) -> Self {
         self.critical = critical;
@@ -169,6 +255,15 @@ impl ThreadBuilder {
         self
     }
     
+    /// Ask the scheduler to keep this thread off cores whose other hardware
+    /// threads are busy, when an idle core is available.
+    ///
+    /// Meant for latency-critical threads; see [`crate::sched::smt`].
+    pub fn no_smt(mut self, no_smt: bool) -> Self {
+        self.no_smt = no_smt;
+        self
+    }
+    
     /// Reserve space for thread-local storage.
     pub fn tls_size(mut self, size: usize) -> Self {
         self.tls_size = Some(size);
@@ -181,13 +276,35 @@ impl ThreadBuilder {
         self
     }
     
+    /// Spawn the thread detached, for fire-and-forget workers.
+    ///
+    /// The returned [`JoinHandle`] can be dropped right away; the thread is
+    /// never returned by [`join_any`](crate::kernel::join_any) and its stack
+    /// goes back to the pool as soon as it exits. Combine with
+    /// [`on_exit`](Self::on_exit) to still learn how it exited.
+    pub fn detached(mut self) -> Self {
+        self.detached = true;
+        self
+    }
+    
+    /// Run `callback` with the thread's ID and
+    /// [`ExitStatus`](super::ExitStatus) once it has exited.
+    ///
+    /// The callback runs from [`Kernel::reap`], on the next thread that
+    /// spawns, yields or reaps, not on the exiting thread.
+    pub fn on_exit(mut self, callback: ExitCallback) -> Self {
+        self.on_exit = Some(callback);
+        self
+    }
+    
     /// Set custom thread attributes.
     pub fn attributes(mut self, attributes: ThreadAttributes) -> Self {
         self.attributes = attributes;
         self
     }
     
-    /// Spawn a new thread with the configured parameters.
+    /// Create a thread with the configured parameters, without handing it
+    /// to a scheduler.
     ///
     /// # Arguments
     ///
@@ -199,13 +316,158 @@ impl ThreadBuilder {
     ///
     /// A tuple of (Thread, JoinHandle) if successful, or an error if
     /// thread creation fails.
+    #[cfg(feature = "compat")]
+    #[deprecated(since = "0.5.0", note = "use `Kernel::spawn`, which also enqueues the thread")]
     pub fn spawn(
         self,
         thread_id: ThreadId,
         stack_pool: &StackPool,
         entry_point: fn(),
     ) -> Result<(Thread, JoinHandle), SpawnError> {
-        // Validate configuration parameters
+        self.build(thread_id, stack_pool, entry_point)
+    }
+    
+    /// Create a thread with the configured parameters, for
+    /// [`Kernel::spawn`] to enqueue.
+    ///
+    /// Spawning from a thread that is at a hard child-thread quota fails
+    /// with [`SpawnError::TooManyThreads`]. The stack goes back to the pool
+    /// if the thread cannot be created.
+    pub(crate) fn build(
+        self,
+        thread_id: ThreadId,
+        stack_pool: &StackPool,
+        entry_point: fn(),
+    ) -> Result<(Thread, JoinHandle), SpawnError> {
+        self.validate()?;
+        
+        // The spawning thread must have room left in its child quota
+        let parent = super::percpu::current_id();
+        if let Some(parent) = parent {
+            hierarchy::check_spawn(parent, 1)?;
+            GLOBAL_RESOURCE_LIMITER.check_spawn(parent)
+                .map_err(|_| SpawnError::TooManyThreads)?;
+        }
+        
+        let size_class = self.resolve_size_class()?;
+        
+        // Place the stack on the node chosen by the NUMA policy
+        let topology = numa::topology();
+        let numa_node = self.numa_policy.resolve(&topology);
+        let stack = match numa_node {
+            Some(node) => stack_pool.allocate_on_node(size_class, node),
+            None => stack_pool.allocate(size_class),
+        }.ok_or(SpawnError::OutOfMemory)?;
+        
+        // Never run a thread on an executable stack
+        #[cfg(feature = "mmu")]
+        if crate::security::wx::verify_stack(&stack, thread_id).is_err() {
+            stack_pool.deallocate(stack);
+            return Err(SpawnError::ExecutableStack);
+        }
+        
+        // Create the thread with all configuration
+        let (thread, join_handle) = Thread::new(
+            thread_id,
+            stack,
+            entry_point,
+            self.priority,
+        );
+        self.configure(&thread, numa_node, &topology);
+        
+        if let Some(parent) = parent {
+            thread.set_parent(parent);
+            hierarchy::adopt(parent, &[thread_id]);
+        }
+        
+        Ok((thread, join_handle))
+    }
+    
+    /// Spawn `count` threads with the configured parameters, all running
+    /// `entry_point` with their index in the batch.
+    ///
+    /// Meant for bringing up many worker threads at once: the stacks are
+    /// reserved from the kernel's pool up front, the spawning thread's
+    /// child quota is checked and charged once, and the threads are handed
+    /// to the scheduler in a single [`enqueue_batch`](Scheduler::enqueue_batch).
+    /// Either all threads are spawned or none are. A configured name gets
+    /// a unique suffix per thread, and each thread can read its index back
+    /// with [`Thread::spawn_index`].
+    ///
+    /// ```ignore
+    /// let workers = ThreadBuilder::new()
+    ///     .name("worker")
+    ///     .spawn_many(&kernel, 256, |index| serve_queue(index))?;
+    /// ```
+    pub fn spawn_many<A: Arch, S: Scheduler>(
+        self,
+        kernel: &Kernel<A, S>,
+        count: usize,
+        entry_point: fn(usize),
+    ) -> Result<Vec<JoinHandle>, SpawnError> {
+        if !kernel.is_initialized() {
+            return Err(SpawnError::NotInitialized);
+        }
+        self.validate()?;
+        kernel.reap();
+        
+        let parent = super::percpu::current_id();
+        if let Some(parent) = parent {
+            hierarchy::check_spawn(parent, count)?;
+            GLOBAL_RESOURCE_LIMITER.check_spawns(parent, count)
+                .map_err(|_| SpawnError::TooManyThreads)?;
+        }
+        
+        // Resolve placement per thread, so interleaving still spreads the
+        // batch across nodes
+        let size_class = self.resolve_size_class()?;
+        let topology = numa::topology();
+        let nodes: Vec<Option<NumaNodeId>> = (0..count)
+            .map(|_| self.numa_policy.resolve(&topology))
+            .collect();
+        let stacks = Self::reserve_stacks(kernel.stack_pool(), size_class, &nodes)?;
+        let thread_ids: Vec<ThreadId> = (0..count).map(|_| kernel.next_thread_id()).collect();
+        
+        // Never run a thread on an executable stack
+        #[cfg(feature = "mmu")]
+        if stacks.iter().zip(&thread_ids)
+            .any(|(stack, &thread_id)| crate::security::wx::verify_stack(stack, thread_id).is_err())
+        {
+            for stack in stacks {
+                kernel.stack_pool().deallocate(stack);
+            }
+            return Err(SpawnError::ExecutableStack);
+        }
+        
+        let mut ready = Vec::with_capacity(count);
+        let mut handles = Vec::with_capacity(count);
+        for (index, ((stack, node), thread_id)) in stacks.into_iter().zip(nodes).zip(thread_ids).enumerate() {
+            let (thread, join_handle) = Thread::new(
+                thread_id,
+                stack,
+                run_batch_entry,
+                self.priority,
+            );
+            thread.set_batch_entry(entry_point, index);
+            self.configure(&thread, node, &topology);
+            ready.push(ReadyRef(thread));
+            handles.push(join_handle);
+        }
+        
+        if let Some(parent) = parent {
+            for thread in &ready {
+                thread.0.set_parent(parent);
+            }
+            let children: Vec<ThreadId> = ready.iter().map(|thread| thread.0.id()).collect();
+            hierarchy::adopt(parent, &children);
+        }
+        
+        kernel.scheduler().enqueue_batch(ready);
+        Ok(handles)
+    }
+    
+    /// Check the parameters that don't depend on the thread being spawned.
+    fn validate(&self) -> Result<(), SpawnError> {
         if let Some(name) = &self.name {
             if name.len() > 64 {
                 return Err(SpawnError::InvalidName(name.clone()));
@@ -213,83 +475,101 @@ impl ThreadBuilder {
         }
         
         if let Some(affinity) = self.cpu_affinity {
-            if affinity == 0 {
+            if affinity.is_empty() {
                 return Err(SpawnError::InvalidAffinity(affinity));
             }
         }
         
-        // Determine stack size
-        let stack_size = if let Some(custom_size) = self.custom_stack_size {
-            if custom_size < 4096 || custom_size > 16 * 1024 * 1024 {
-                return Err(SpawnError::InvalidStackSize(custom_size));
-            }
-            custom_size
-        } else {
-            let size_class = self.stack_size_class.unwrap_or(StackSizeClass::Small);
-            match size_class {
-                StackSizeClass::Small => 16384,
-                StackSizeClass::Medium => 65536,
-                StackSizeClass::Large => 262144,
-                StackSizeClass::ExtraLarge => 1048576, // 1MB
-            }
+        Ok(())
+    }
+    
+    /// Get the size class to allocate the stack from.
+    fn resolve_size_class(&self) -> Result<StackSizeClass, SpawnError> {
+        let Some(custom_size) = self.custom_stack_size else {
+            return Ok(self.stack_size_class.unwrap_or(StackSizeClass::Small));
         };
+        if custom_size < 4096 || custom_size > 16 * 1024 * 1024 {
+            return Err(SpawnError::InvalidStackSize(custom_size));
+        }
         
-        // Allocate stack based on size class or custom size
-        let stack = if let Some(_custom_size) = self.custom_stack_size {
-            // For custom sizes, we'll still use the size class system but pick the closest match
-            let size_class = if stack_size <= 16384 {
-                StackSizeClass::Small
-            } else if stack_size <= 65536 {
-                StackSizeClass::Medium
-            } else if stack_size <= 262144 {
-                StackSizeClass::Large
-            } else {
-                StackSizeClass::ExtraLarge
-            };
-            stack_pool.allocate(size_class).ok_or(SpawnError::OutOfMemory)?
+        // For custom sizes, we'll still use the size class system but pick the closest match
+        Ok(if custom_size <= 16384 {
+            StackSizeClass::Small
+        } else if custom_size <= 65536 {
+            StackSizeClass::Medium
+        } else if custom_size <= 262144 {
+            StackSizeClass::Large
         } else {
-            let size_class = self.stack_size_class.unwrap_or(StackSizeClass::Small);
-            stack_pool.allocate(size_class).ok_or(SpawnError::OutOfMemory)?
-        };
+            StackSizeClass::ExtraLarge
+        })
+    }
+    
+    /// Reserve one stack per entry of `nodes`: those without a node in one
+    /// batch from the pool, the rest on their node. Nothing is kept if any
+    /// allocation fails.
+    fn reserve_stacks(
+        stack_pool: &StackPool,
+        size_class: StackSizeClass,
+        nodes: &[Option<NumaNodeId>],
+    ) -> Result<Vec<Stack>, SpawnError> {
+        let local_count = nodes.iter().filter(|node| node.is_none()).count();
+        let mut local = stack_pool.allocate_many(size_class, local_count)
+            .ok_or(SpawnError::OutOfMemory)?
+            .into_iter();
         
+        let mut stacks = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let stack = match node {
+                Some(node) => stack_pool.allocate_on_node(size_class, *node),
+                None => local.next(),
+            };
+            match stack {
+                Some(stack) => stacks.push(stack),
+                None => {
+                    for stack in stacks.into_iter().chain(local) {
+                        stack_pool.deallocate(stack);
+                    }
+                    return Err(SpawnError::OutOfMemory);
+                }
+            }
+        }
+        Ok(stacks)
+    }
+    
+    /// Apply the configured parameters to a newly created thread.
+    fn configure(&self, thread: &Thread, numa_node: Option<NumaNodeId>, topology: &NumaTopology) {
         // Set up stack protection if enabled
         if self.stack_guard_pages && cfg!(feature = "mmu") {
             // In a real implementation, we would configure MMU guard pages here
             // This would involve platform-specific memory protection calls
         }
         
-        // Set up stack canary if enabled
-        if self.stack_canary {
-            let canary_value = self.custom_canary.unwrap_or_else(|| {
-                // Generate a random canary value
-                // In a real implementation, we would use a proper CSPRNG
-                0xDEADBEEFCAFEBABE
-            });
-            
-            // Store canary at the bottom of the stack
-            unsafe {
-                let stack_bottom = stack.stack_bottom() as *mut u64;
-                *stack_bottom = canary_value;
-            }
+        // Threads get a random stack canary unless told otherwise
+        if !self.stack_canary {
+            thread.set_stack_canary(None);
+        } else if let Some(canary) = self.custom_canary {
+            thread.set_stack_canary(Some(canary));
         }
         
-        // Create the thread with all configuration
-        let (thread, join_handle) = Thread::new(
-            thread_id,
-            stack,
-            entry_point,
-            self.priority,
-        );
-        
         // Apply additional configuration
-        if let Some(name) = self.name {
-            thread.set_name(name);
+        if let Some(name) = &self.name {
+            thread.set_unique_name(name);
         }
         
         if let Some(affinity) = self.cpu_affinity {
             thread.set_cpu_affinity(affinity);
+        } else if let Some(node) = numa_node.filter(|_| self.numa_policy != NumaPolicy::Local) {
+            // Keep the thread next to its stack unless affinity was given
+            // explicitly; local threads may still run anywhere
+            let cpus: CpuSet = topology.get_node_cpus(node).iter().copied().collect();
+            if !cpus.is_empty() {
+                thread.set_cpu_affinity(cpus);
+            }
         }
         
+        thread.set_numa_policy(self.numa_policy);
+        thread.set_switch_domain(self.switch_domain);
+        
         if let Some(group_id) = self.group_id {
             thread.set_group_id(group_id);
         }
@@ -298,8 +578,29 @@ impl ThreadBuilder {
             thread.set_time_slice(time_slice);
         }
         
+        if let Some(max_run_length) = self.max_run_length {
+            thread.set_max_run_length(max_run_length);
+        }
+        
+        if let Some(tickets) = self.tickets {
+            thread.set_tickets(tickets);
+        }
+        
+        if let Some(slack) = self.timer_slack {
+            thread.set_timer_slack(slack);
+        }
+        
+        if self.detached {
+            thread.detach();
+        }
+        
+        if let Some(on_exit) = self.on_exit {
+            thread.set_on_exit(on_exit);
+        }
+        
         thread.set_critical(self.critical);
         thread.set_preemptible(self.preemptible);
+        thread.set_no_smt(self.no_smt);
         
         if let Some(tls_size) = self.tls_size {
             thread.reserve_tls(tls_size);
@@ -308,15 +609,11 @@ impl ThreadBuilder {
         thread.set_debug_info(self.debug_info);
         
         // Apply thread attributes
-        if let Some(rt_priority) = self.attributes.rt_priority {
-            thread.set_realtime_priority(rt_priority);
-        }
-        
-        thread.set_nice_value(self.attributes.nice_value);
+        thread.init_scheduling_attributes(self.attributes.rt_priority, self.attributes.nice_value);
         thread.set_inherit_signal_mask(self.attributes.inherit_signal_mask);
         
-        if let Some(env) = self.attributes.environment {
-            thread.set_environment(env);
+        if let Some(env) = &self.attributes.environment {
+            thread.set_environment(env.clone());
         }
         
         // Apply resource limits
@@ -337,7 +634,28 @@ impl ThreadBuilder {
             thread.set_max_children(max_children);
         }
         
-        Ok((thread, join_handle))
+        // Explicit limits are enforced as hard limits
+        if limits.max_cpu_time.is_some()
+            || limits.max_memory.is_some()
+            || limits.max_files.is_some()
+            || limits.max_children.is_some()
+        {
+            let thread_id = thread.id();
+            let mut quota = GLOBAL_RESOURCE_LIMITER.get_thread_quota(thread_id);
+            quota.max_cpu_time_ns = limits.max_cpu_time.unwrap_or(quota.max_cpu_time_ns);
+            quota.max_memory_bytes = limits.max_memory.map_or(quota.max_memory_bytes, |max| max as u64);
+            quota.max_open_files = limits.max_files.unwrap_or(quota.max_open_files);
+            quota.max_child_threads = limits.max_children.unwrap_or(quota.max_child_threads);
+            quota.hard_limits = true;
+            GLOBAL_RESOURCE_LIMITER.set_thread_quota(thread_id, quota);
+        }
+    }
+}
+
+/// Entry point of threads spawned by [`ThreadBuilder::spawn_many`].
+fn run_batch_entry() {
+    if let Some(thread) = super::current() {
+        thread.run_batch_entry();
     }
 }
 
@@ -386,7 +704,7 @@ mod tests {
             .priority(200)
             .name("test-thread");
         
-        let result = builder.spawn(thread_id, &pool, || {
+        let result = builder.build(thread_id, &pool, || {
             // Thread code here
         });
         
@@ -397,6 +715,52 @@ mod tests {
         assert_eq!(thread.priority(), 200);
     }
     
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_thread_builder_unique_names() {
+        let pool = StackPool::new();
+        
+        let (first, _first_handle) = ThreadBuilder::new()
+            .name("name-registry")
+            .build(ThreadId::new(101), &pool, || {})
+            .unwrap();
+        let (second, _second_handle) = ThreadBuilder::new()
+            .name("name-registry")
+            .build(ThreadId::new(102), &pool, || {})
+            .unwrap();
+        
+        assert_eq!(first.name().as_deref(), Some("name-registry"));
+        assert_eq!(second.name().as_deref(), Some("name-registry-2"));
+        assert_eq!(crate::thread_new::find_by_name("name-registry-2").map(|t| t.id()), Some(second.id()));
+        assert!(crate::thread_new::named_threads().iter().any(|(name, t)| name == "name-registry" && t.id() == first.id()));
+        
+        // Names are released with the thread
+        drop((second, _second_handle));
+        assert!(crate::thread_new::find_by_name("name-registry-2").is_none());
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_thread_builder_numa_node() {
+        let pool = StackPool::new();
+        let thread_id = unsafe { ThreadId::new_unchecked(1) };
+        
+        let (thread, _join_handle) = ThreadBuilder::new()
+            .numa_node(0)
+            .build(thread_id, &pool, || {})
+            .unwrap();
+        
+        assert_eq!(thread.numa_policy(), NumaPolicy::Node(0));
+        assert!(!thread.cpu_affinity().is_empty());
+        
+        // Local threads get a local stack but may run anywhere
+        let (local, _local_handle) = ThreadBuilder::new()
+            .numa_local()
+            .build(ThreadId::new(49_735), &pool, || {})
+            .unwrap();
+        assert!(local.cpu_affinity().is_empty());
+    }
+    
     #[cfg(feature = "std-shim")]
     #[test]
     fn test_thread_builder_stack_size() {
@@ -411,4 +775,84 @@ mod tests {
         let _ = builder1;
         let _ = builder2;
     }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_thread_builder_spawn_many() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+        use portable_atomic::{AtomicUsize, Ordering};
+        
+        static RAN: AtomicUsize = AtomicUsize::new(0);
+        fn worker(index: usize) {
+            RAN.fetch_or(1 << index, Ordering::AcqRel);
+        }
+        
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(2));
+        assert_eq!(
+            ThreadBuilder::new().spawn_many(&kernel, 4, worker).err(),
+            Some(SpawnError::NotInitialized)
+        );
+        kernel.init().unwrap();
+        
+        let handles = ThreadBuilder::new().priority(100).spawn_many(&kernel, 4, worker).unwrap();
+        assert_eq!(handles.len(), 4);
+        assert_eq!(kernel.stack_pool().stats().2, 4);
+        
+        // The batch was spread over both CPUs
+        let queued = kernel.scheduler().run_queue_snapshot();
+        for cpu in 0..2 {
+            assert_eq!(queued.iter().filter(|entry| entry.cpu == Some(cpu)).count(), 2);
+        }
+        
+        let mut indices = Vec::new();
+        while let Some(ready) = kernel.scheduler().pick_next(0) {
+            assert_eq!(ready.0.priority(), 100);
+            indices.push(ready.0.spawn_index().unwrap());
+            ready.0.run_batch_entry();
+        }
+        indices.sort_unstable();
+        assert_eq!(indices, [0, 1, 2, 3]);
+        assert_eq!(RAN.load(Ordering::Acquire), 0b1111);
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_detached_spawn_with_exit_callback() {
+        use crate::arch::DefaultArch;
+        use crate::sched::RoundRobinScheduler;
+        use crate::thread_new::ExitStatus;
+        use portable_atomic::{AtomicUsize, Ordering};
+        
+        static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+        static TERMINATED: AtomicUsize = AtomicUsize::new(0);
+        fn exited(_thread: ThreadId, status: ExitStatus) {
+            match status {
+                ExitStatus::Completed => COMPLETED.fetch_add(1, Ordering::AcqRel),
+                ExitStatus::Terminated | ExitStatus::Cancelled | ExitStatus::Panicked => TERMINATED.fetch_add(1, Ordering::AcqRel),
+            };
+        }
+        
+        let kernel = Kernel::<DefaultArch, _>::new(RoundRobinScheduler::new(1));
+        kernel.init().unwrap();
+        let handles = ThreadBuilder::new()
+            .detached()
+            .on_exit(exited)
+            .spawn_many(&kernel, 3, |_| {})
+            .unwrap();
+        drop(handles);
+        
+        let mut first = true;
+        while let Some(ready) = kernel.scheduler().pick_next(0) {
+            let status = if first { ExitStatus::Terminated } else { ExitStatus::Completed };
+            first = false;
+            ready.start_running().exit(status);
+        }
+        
+        // Callbacks run once each, and all stacks go back to the pool
+        assert_eq!(kernel.reap(), 3);
+        assert_eq!(kernel.reap(), 0);
+        assert_eq!((COMPLETED.load(Ordering::Acquire), TERMINATED.load(Ordering::Acquire)), (2, 1));
+        assert_eq!(kernel.stack_pool().stats().2, 0);
+    }
 }
\ No newline at end of file
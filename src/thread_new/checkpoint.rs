@@ -0,0 +1,416 @@
+//! Checkpoint and restore of suspended threads.
+//!
+//! A checkpoint captures a suspended thread's metadata, saved register
+//! context and live stack contents in a versioned binary format, so it can
+//! be written to flash and restored later or on another machine of the same
+//! architecture.
+//!
+//! Restoring copies the stack into a freshly allocated stack and rebases
+//! the stack and frame pointers. Other pointers held on the stack (to heap
+//! objects, kernel handles or other stack slots) are not translated, so
+//! restore is only meaningful for threads whose state is self-contained.
+
+use super::{registry, Thread, ThreadId, ThreadState};
+use crate::arch::{Arch, DefaultArch};
+use crate::io::{IoError, Read, Write};
+use crate::mem::{StackPool, StackSizeClass};
+use crate::perf::context_switch_opt::copy_context;
+use crate::sched::CpuSet;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::{size_of, ManuallyDrop, MaybeUninit};
+use portable_atomic::Ordering;
+
+/// Magic bytes at the start of every checkpoint.
+pub const CHECKPOINT_MAGIC: [u8; 4] = *b"PTCK";
+
+/// Current checkpoint format version.
+pub const CHECKPOINT_VERSION: u16 = 2;
+
+/// Marker for an absent optional field.
+const ABSENT: u32 = u32::MAX;
+
+type SavedContext = <DefaultArch as Arch>::SavedContext;
+
+/// Errors produced while writing or reading a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointError {
+    /// Underlying reader or writer failed
+    Io(IoError),
+    /// Only suspended threads can be checkpointed
+    NotSuspended(ThreadState),
+    /// Input is not a checkpoint
+    BadMagic,
+    /// Checkpoint was written by an unsupported format version
+    UnsupportedVersion(u16),
+    /// Checkpoint was taken on a different architecture
+    ArchMismatch,
+    /// A live thread already uses the checkpointed thread ID
+    IdInUse(ThreadId),
+    /// Thread has no stack to save
+    NoStack,
+    /// Saved stack pointer lies outside the thread's stack
+    InvalidStackPointer,
+    /// No stack of the required size could be allocated
+    StackAllocationFailed,
+    /// Checkpoint contents are inconsistent
+    Corrupt,
+}
+
+impl From<IoError> for CheckpointError {
+    fn from(error: IoError) -> Self {
+        CheckpointError::Io(error)
+    }
+}
+
+/// Identifies the context layout a checkpoint was written with.
+fn arch_tag() -> u8 {
+    if cfg!(all(target_arch = "x86_64", feature = "x86_64")) {
+        1
+    } else if cfg!(all(target_arch = "aarch64", feature = "arm64")) {
+        2
+    } else if cfg!(all(target_arch = "riscv64", feature = "riscv64")) {
+        3
+    } else {
+        0
+    }
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<(), CheckpointError> {
+    let len = u32::try_from(bytes.len()).map_err(|_| CheckpointError::Corrupt)?;
+    writer.write_u32(len)?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes(reader: &mut impl Read, limit: usize) -> Result<Vec<u8>, CheckpointError> {
+    let len = reader.read_u32()? as usize;
+    if len > limit {
+        return Err(CheckpointError::Corrupt);
+    }
+    let mut bytes = alloc::vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, CheckpointError> {
+    String::from_utf8(read_bytes(reader, u16::MAX as usize)?).map_err(|_| CheckpointError::Corrupt)
+}
+
+impl Thread {
+    /// Serialize this suspended thread into `writer`.
+    ///
+    /// The thread must be parked in [`ThreadState::Suspended`] so that its
+    /// saved context and stack are stable while they are copied.
+    pub fn checkpoint(&self, writer: &mut impl Write) -> Result<(), CheckpointError> {
+        let state = self.state();
+        if state != ThreadState::Suspended {
+            return Err(CheckpointError::NotSuspended(state));
+        }
+
+        let stack = self.inner.stack.as_ref().ok_or(CheckpointError::NoStack)?;
+        let low = stack.stack_top() as usize;
+        let high = stack.stack_bottom() as usize;
+
+        let context = self.inner.context.filter(|ptr| !ptr.is_null());
+
+        // Header
+        writer.write_all(&CHECKPOINT_MAGIC)?;
+        writer.write_u16(CHECKPOINT_VERSION)?;
+        writer.write_u8(arch_tag())?;
+        writer.write_u32(size_of::<SavedContext>() as u32)?;
+
+        // Metadata
+        let inner = &self.inner;
+        writer.write_u64(self.id().as_u64())?;
+        writer.write_u8(self.priority())?;
+        writer.write_u8(self.realtime_priority())?;
+        writer.write_u8(self.nice_value() as u8)?;
+        let flags = (self.is_critical() as u8)
+            | (self.is_preemptible() as u8) << 1
+            | (self.debug_info_enabled() as u8) << 2
+            | (self.inherits_signal_mask() as u8) << 3;
+        writer.write_u8(flags)?;
+        for word in self.cpu_affinity().words() {
+            writer.write_u64(word)?;
+        }
+        writer.write_u64(inner.group_id.load(Ordering::Acquire))?;
+        writer.write_u64(inner.switch_domain.load(Ordering::Acquire))?;
+        writer.write_u64(self.tls_size() as u64)?;
+        writer.write_u64(self.max_cpu_time())?;
+        writer.write_u64(self.max_memory() as u64)?;
+        writer.write_u64(inner.max_files.load(Ordering::Acquire))?;
+        writer.write_u64(inner.max_children.load(Ordering::Acquire))?;
+
+        match self.name() {
+            Some(name) => write_bytes(writer, name.as_bytes())?,
+            None => writer.write_u32(ABSENT)?,
+        }
+
+        match self.environment() {
+            Some(env) => {
+                writer.write_u32(env.len() as u32)?;
+                for (key, value) in &env {
+                    write_bytes(writer, key.as_bytes())?;
+                    write_bytes(writer, value.as_bytes())?;
+                }
+            }
+            None => writer.write_u32(ABSENT)?,
+        }
+
+        // Register context and live stack
+        writer.write_u32(stack.size_class() as u32)?;
+        writer.write_u64(low as u64)?;
+        writer.write_u64(high as u64)?;
+
+        match context {
+            Some(ptr) => {
+                // Safety: the thread is suspended, so its context is not
+                // being written, and saved contexts are plain register data
+                let ctx = unsafe { &*ptr };
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(ptr as *const u8, size_of::<SavedContext>())
+                };
+                writer.write_u8(1)?;
+                writer.write_all(bytes)?;
+
+                let sp = DefaultArch::saved_stack_pointer(ctx).unwrap_or(high);
+                if sp < low || sp > high {
+                    return Err(CheckpointError::InvalidStackPointer);
+                }
+                // Safety: [sp, high) lies within the thread's stack
+                let live = unsafe { core::slice::from_raw_parts(sp as *const u8, high - sp) };
+                writer.write_u64(live.len() as u64)?;
+                writer.write_all(live)?;
+            }
+            None => {
+                // Never switched out: nothing on the stack is live yet
+                writer.write_u8(0)?;
+                writer.write_u64(0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recreate a thread from a checkpoint produced by [`Thread::checkpoint`].
+    ///
+    /// The new thread keeps its original ID, which must not be in use, and
+    /// gets a fresh stack from `pool`. It is returned suspended: enqueue it
+    /// with a scheduler and call [`resume`](Thread::resume) to let it run.
+    pub fn restore(
+        reader: &mut impl Read,
+        pool: &StackPool,
+    ) -> Result<(Thread, super::JoinHandle), CheckpointError> {
+        // Header
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != CHECKPOINT_MAGIC {
+            return Err(CheckpointError::BadMagic);
+        }
+        let version = reader.read_u16()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+        if reader.read_u8()? != arch_tag() || reader.read_u32()? as usize != size_of::<SavedContext>() {
+            return Err(CheckpointError::ArchMismatch);
+        }
+
+        // Metadata
+        let id = ThreadId::new(reader.read_u64()?);
+        if registry::lookup(id).is_some() {
+            return Err(CheckpointError::IdInUse(id));
+        }
+        let priority = reader.read_u8()?;
+        let rt_priority = reader.read_u8()?;
+        let nice = reader.read_u8()? as i8;
+        let flags = reader.read_u8()?;
+        let mut cpu_affinity = [0; 4];
+        for word in &mut cpu_affinity {
+            *word = reader.read_u64()?;
+        }
+        let group_id = reader.read_u64()?;
+        let switch_domain = reader.read_u64()?;
+        let tls_size = reader.read_u64()?;
+        let max_cpu_time = reader.read_u64()?;
+        let max_memory = reader.read_u64()?;
+        let max_files = reader.read_u64()?;
+        let max_children = reader.read_u64()?;
+
+        let name = match reader.read_u32()? {
+            ABSENT => None,
+            len => {
+                let mut bytes = alloc::vec![0; len as usize];
+                reader.read_exact(&mut bytes)?;
+                Some(String::from_utf8(bytes).map_err(|_| CheckpointError::Corrupt)?)
+            }
+        };
+
+        let environment = match reader.read_u32()? {
+            ABSENT => None,
+            count => {
+                let mut env = BTreeMap::new();
+                for _ in 0..count {
+                    let key = read_string(reader)?;
+                    let value = read_string(reader)?;
+                    env.insert(key, value);
+                }
+                Some(env)
+            }
+        };
+
+        // Register context and live stack
+        let size_class = StackSizeClass::for_size(reader.read_u32()? as usize)
+            .ok_or(CheckpointError::Corrupt)?;
+        let old_low = reader.read_u64()? as usize;
+        let old_high = reader.read_u64()? as usize;
+
+        let context = if reader.read_u8()? != 0 {
+            let mut ctx = MaybeUninit::<SavedContext>::uninit();
+            // Safety: saved contexts are plain register data, valid for any
+            // bit pattern, and the slice covers exactly the context
+            let bytes = unsafe {
+                core::slice::from_raw_parts_mut(ctx.as_mut_ptr() as *mut u8, size_of::<SavedContext>())
+            };
+            reader.read_exact(bytes)?;
+            Some(unsafe { ctx.assume_init() })
+        } else {
+            None
+        };
+
+        let live = read_bytes_u64(reader, size_class.size())?;
+
+        let stack = pool.allocate(size_class).ok_or(CheckpointError::StackAllocationFailed)?;
+        let new_high = stack.stack_bottom() as usize;
+        let new_top = stack.stack_top() as usize;
+        if live.len() > new_high - new_top {
+            pool.deallocate(stack);
+            return Err(CheckpointError::Corrupt);
+        }
+
+        // Safety: the destination lies within the freshly allocated stack
+        unsafe {
+            core::ptr::copy_nonoverlapping(live.as_ptr(), (new_high - live.len()) as *mut u8, live.len());
+        }
+
+        let context = context.map(|mut ctx| {
+            // Keep the same offsets from the high end of the stack
+            let new_low = new_high - (old_high - old_low);
+            DefaultArch::relocate_stack(&mut ctx, old_low, old_high, new_low);
+            let ctx = ManuallyDrop::new(ctx);
+            let slot = Box::into_raw(Box::new(MaybeUninit::<SavedContext>::uninit())) as *mut SavedContext;
+            // Safety: `slot` is a fresh allocation sized for a context, and
+            // `ctx` is moved into it, so it is not dropped here
+            unsafe { copy_context(slot, &*ctx) };
+            slot
+        });
+
+        let (thread, join_handle) = Thread::with_context(id, stack, None, priority, context);
+
+        thread.init_scheduling_attributes(Some(rt_priority), nice);
+        thread.set_critical(flags & 1 != 0);
+        thread.set_preemptible(flags & 2 != 0);
+        thread.set_debug_info(flags & 4 != 0);
+        thread.set_inherit_signal_mask(flags & 8 != 0);
+        thread.set_cpu_affinity(CpuSet::from_words(cpu_affinity));
+        thread.inner.group_id.store(group_id, Ordering::Release);
+        thread.inner.switch_domain.store(switch_domain, Ordering::Release);
+        thread.reserve_tls(tls_size as usize);
+        thread.set_max_cpu_time(max_cpu_time);
+        thread.set_max_memory(max_memory as usize);
+        thread.inner.max_files.store(max_files, Ordering::Release);
+        thread.inner.max_children.store(max_children, Ordering::Release);
+        if let Some(name) = name {
+            thread.set_name(name);
+        }
+        if let Some(env) = environment {
+            thread.set_environment(env);
+        }
+
+        // Come back the way the thread was saved: parked and suspended once
+        thread.inner.suspend_depth.store(1, Ordering::Release);
+        thread.set_state(ThreadState::Suspended);
+
+        Ok((thread, join_handle))
+    }
+}
+
+fn read_bytes_u64(reader: &mut impl Read, limit: usize) -> Result<Vec<u8>, CheckpointError> {
+    let len = reader.read_u64()? as usize;
+    if len > limit {
+        return Err(CheckpointError::Corrupt);
+    }
+    let mut bytes = alloc::vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_rejects_bad_header() {
+        let pool = StackPool::new();
+
+        let mut input: &[u8] = b"nope";
+        assert_eq!(Thread::restore(&mut input, &pool).err(), Some(CheckpointError::BadMagic));
+
+        let mut header = CHECKPOINT_MAGIC.to_vec();
+        header.extend_from_slice(&99u16.to_le_bytes());
+        let mut input: &[u8] = &header;
+        assert_eq!(
+            Thread::restore(&mut input, &pool).err(),
+            Some(CheckpointError::UnsupportedVersion(99))
+        );
+
+        let mut input: &[u8] = &CHECKPOINT_MAGIC;
+        assert_eq!(
+            Thread::restore(&mut input, &pool).err(),
+            Some(CheckpointError::Io(IoError::UnexpectedEof))
+        );
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = unsafe { ThreadId::new_unchecked(9001) };
+
+        let (thread, join_handle) = Thread::new(thread_id, stack, || {}, 64);
+        thread.set_name(String::from("worker"));
+        thread.set_cpu_affinity(0b1010);
+
+        // Only suspended threads can be saved
+        assert_eq!(
+            thread.checkpoint(&mut Vec::new()),
+            Err(CheckpointError::NotSuspended(ThreadState::Ready))
+        );
+
+        thread.set_state(ThreadState::Suspended);
+        let mut image = Vec::new();
+        thread.checkpoint(&mut image).unwrap();
+
+        // The ID is still taken while the original is alive
+        let mut input: &[u8] = &image;
+        assert_eq!(
+            Thread::restore(&mut input, &pool).err(),
+            Some(CheckpointError::IdInUse(thread_id))
+        );
+
+        drop(thread);
+        drop(join_handle);
+
+        let mut input: &[u8] = &image;
+        let (restored, _join_handle) = Thread::restore(&mut input, &pool).unwrap();
+        assert_eq!(restored.id(), thread_id);
+        assert_eq!(restored.priority(), 64);
+        assert_eq!(restored.name().as_deref(), Some("worker"));
+        assert_eq!(restored.cpu_affinity(), CpuSet::from(0b1010));
+        assert!(restored.is_suspended());
+        assert_eq!(restored.suspend_depth(), 1);
+    }
+}
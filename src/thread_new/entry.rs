@@ -0,0 +1,53 @@
+//! How a thread starts and ends.
+//!
+//! A thread created with an entry point gets a saved context from
+//! [`Arch::init_context`] that starts it in a trampoline on its own stack.
+//! The trampoline turns interrupts back on, as the switch that starts the
+//! thread leaves them off, runs the entry point and exits the thread, so an
+//! entry point is an ordinary function that returns when it is done. With
+//! `std`, a panicking entry point exits the thread with
+//! [`ExitStatus::Panicked`] instead of unwinding off the top of its stack.
+//!
+//! On architectures without contexts of their own, such as the no-op one,
+//! no context is created and whoever picks the thread runs its entry point.
+
+use super::ExitStatus;
+use crate::arch::{Arch, DefaultArch};
+use crate::mem::Stack;
+extern crate alloc;
+use alloc::boxed::Box;
+
+/// Create the saved context of a thread that runs `entry_point` on `stack`.
+///
+/// Returns `None` if the default architecture does not build contexts.
+pub(crate) fn new_context(stack: &Stack, entry_point: fn()) -> Option<*mut <DefaultArch as Arch>::SavedContext> {
+    // Safety: the thread owns `stack` for as long as it owns the context
+    unsafe { DefaultArch::init_context(stack.stack_bottom(), thread_start, entry_point as usize) }
+        .map(|context| Box::into_raw(Box::new(context)))
+}
+
+/// Trampoline a new context starts in, with the entry point as argument.
+extern "C" fn thread_start(entry_point: usize) -> ! {
+    // Safety: `new_context` passes an `fn()`
+    let entry_point = unsafe { core::mem::transmute::<usize, fn()>(entry_point) };
+    DefaultArch::enable_interrupts();
+    crate::security::cfi::verify_forward_edge(entry_point as *const ());
+    crate::sched::global::exit_current(run(entry_point))
+}
+
+/// Run `entry_point`, catching a panic.
+#[cfg(feature = "std")]
+fn run(entry_point: fn()) -> ExitStatus {
+    extern crate std;
+    match std::panic::catch_unwind(entry_point) {
+        Ok(()) => ExitStatus::Completed,
+        Err(_) => ExitStatus::Panicked,
+    }
+}
+
+/// Run `entry_point`; without `std` a panic does not unwind.
+#[cfg(not(feature = "std"))]
+fn run(entry_point: fn()) -> ExitStatus {
+    entry_point();
+    ExitStatus::Completed
+}
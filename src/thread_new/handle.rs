@@ -1,7 +1,10 @@
 //! Join handle implementation for waiting on thread completion.
 
-use super::{ThreadInner, ThreadState};
+use super::{blocked_on, BlockedOn, ThreadInner, ThreadState};
+use crate::errors::{JoinError, ThreadError, ThreadResult};
 use crate::mem::ArcLite;
+use crate::observability::metrics::ThreadMetrics;
+use crate::time::{Duration, TIMER_WHEEL};
 
 /// A handle that can be used to wait for a thread to complete.
 ///
@@ -25,6 +28,9 @@ impl JoinHandle {
     /// `Ok(())` when the thread completes successfully, or `Err(())` 
     /// if the thread panicked or could not be joined.
     pub fn join(self) -> Result<(), ()> {
+        crate::might_sleep!();
+        let _blocked = blocked_on(BlockedOn::Join(self.inner.id));
+        
         // Spin wait for the thread to finish
         // In a real implementation, we'd want to use a more efficient
         // wait mechanism like a condition variable or park/unpark
@@ -35,18 +41,7 @@ impl JoinHandle {
             }
             
             // Yield CPU to avoid busy waiting
-            #[cfg(feature = "std-shim")]
-            {
-                extern crate std;
-                std::thread::yield_now();
-            }
-            
-            #[cfg(not(feature = "std-shim"))]
-            {
-                // In a real kernel implementation, we'd yield to scheduler
-                // For now, just continue the loop
-                core::hint::spin_loop();
-            }
+            crate::sync::relax();
         }
         
         // Check if we have a result
@@ -61,6 +56,34 @@ impl JoinHandle {
         }
     }
     
+    /// Wait for the thread to complete, giving up after `timeout`.
+    ///
+    /// Unlike [`join`](Self::join) this keeps the handle, so the caller can
+    /// retry or recover after a timeout.
+    ///
+    /// # Errors
+    ///
+    /// [`ThreadError::TimedOut`] if the thread is still running when the
+    /// timeout expires, or a [`JoinError`] if the thread did not finish
+    /// successfully. Either way the error names the joined thread.
+    pub fn join_timeout(&self, timeout: Duration) -> ThreadResult<()> {
+        crate::might_sleep!();
+        let _blocked = blocked_on(BlockedOn::Join(self.inner.id));
+        
+        let deadline = TIMER_WHEEL.arm(timeout);
+        loop {
+            if let Some(result) = self.try_join() {
+                return result.map_err(|()| {
+                    ThreadError::Join(JoinError::ThreadPanicked).for_thread(self.thread_id())
+                });
+            }
+            if deadline.expired() {
+                return Err(ThreadError::TimedOut.for_thread(self.thread_id()));
+            }
+            crate::sync::relax();
+        }
+    }
+    
     /// Check if the thread has finished without blocking.
     ///
     /// # Returns
@@ -91,6 +114,27 @@ impl JoinHandle {
         self.inner.id
     }
     
+    /// Get a handle to the thread itself.
+    pub fn thread(&self) -> super::Thread {
+        super::Thread {
+            inner: self.inner.clone(),
+        }
+    }
+    
+    /// Get the thread's run statistics as they stood when it exited.
+    ///
+    /// They outlive the thread's entry in
+    /// [`GLOBAL_METRICS`](crate::observability::metrics::GLOBAL_METRICS), so
+    /// they can be read after the thread is gone. [`join`](Self::join)
+    /// consumes the handle, so join with [`join_timeout`](Self::join_timeout)
+    /// to read them afterwards.
+    ///
+    /// `None` while the thread runs, or if metrics collection was disabled
+    /// when it exited.
+    pub fn stats(&self) -> Option<ThreadMetrics> {
+        self.inner.final_metrics.lock().clone()
+    }
+    
     /// Check if the associated thread is still alive.
     ///
     /// # Returns
@@ -103,6 +147,14 @@ impl JoinHandle {
     }
 }
 
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        // Nobody can join the thread any more, so once it finishes the
+        // kernel may reclaim its stack
+        self.inner.detached.store(true, portable_atomic::Ordering::Release);
+    }
+}
+
 unsafe impl Send for JoinHandle {}
 unsafe impl Sync for JoinHandle {}
 
@@ -129,6 +181,9 @@ mod tests {
         assert_eq!(join_handle.thread_id(), thread_id);
         assert!(join_handle.is_alive());
         assert!(join_handle.try_join().is_none()); // Thread not finished
+        let error = join_handle.join_timeout(Duration::from_nanos(0)).unwrap_err();
+        assert_eq!(error.root(), &ThreadError::TimedOut);
+        assert_eq!(error.thread_id(), Some(thread_id));
         
         // Simulate thread completion
         thread.set_state(ThreadState::Finished);
@@ -138,5 +193,34 @@ mod tests {
         
         assert!(!join_handle.is_alive());
         assert_eq!(join_handle.try_join(), Some(Ok(())));
+        assert_eq!(join_handle.join_timeout(Duration::from_millis(10)), Ok(()));
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_stats_outlive_thread_metrics() {
+        use crate::observability::metrics::GLOBAL_METRICS;
+        use crate::thread_new::ReadyRef;
+        
+        let _ = GLOBAL_METRICS.init(1000);
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = ThreadId::new(49_500);
+        let (thread, join_handle) = Thread::new(thread_id, stack, || {}, 128);
+        
+        let running = ReadyRef(thread).start_running();
+        GLOBAL_METRICS.record_memory_allocation(thread_id, 4096);
+        GLOBAL_METRICS.record_memory_deallocation(thread_id, 4096);
+        assert!(join_handle.stats().is_none());
+        running.finish();
+        
+        // The thread is gone from the global metrics, its handle keeps them
+        GLOBAL_METRICS.unregister_thread(thread_id);
+        assert!(GLOBAL_METRICS.get_thread_metrics(thread_id).is_none());
+        assert_eq!(join_handle.join_timeout(Duration::from_millis(10)), Ok(()));
+        let stats = join_handle.stats().unwrap();
+        assert_eq!(stats.thread_id, thread_id);
+        assert!(stats.context_switches >= 1);
+        assert_eq!((stats.peak_memory_usage, stats.current_memory_usage), (4096, 0));
     }
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+//! Parent/child relationships between threads.
+//!
+//! A thread spawned from another thread records it as its parent, and the
+//! table here lists the live children of every parent. Supervisors use it
+//! to tear down a whole worker tree with [`Thread::cancel_subtree`] and
+//! [`Thread::join_subtree`], and child-thread limits count the children
+//! listed here.
+//!
+//! A child is removed from its parent's list when it exits. Children that
+//! outlive their parent are not re-parented; they stay listed under the
+//! parent's ID until they exit.
+
+use super::{registry, Thread, ThreadId};
+use crate::errors::SpawnError;
+use crate::sync::SpinLockIrqSave;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Live children of each thread that has any.
+static CHILDREN: SpinLockIrqSave<BTreeMap<ThreadId, Vec<ThreadId>>> = SpinLockIrqSave::new(BTreeMap::new());
+
+/// Check whether `parent` may spawn `count` more children under its
+/// [`max_children`](Thread::max_children) limit.
+pub(crate) fn check_spawn(parent: ThreadId, count: usize) -> Result<(), SpawnError> {
+    let limit = registry::lookup(parent).map_or(0, |parent| parent.max_children()) as usize;
+    if limit > 0 && child_count(parent) + count > limit {
+        return Err(SpawnError::TooManyThreads);
+    }
+    Ok(())
+}
+
+/// List `children` under `parent`.
+pub(crate) fn adopt(parent: ThreadId, children: &[ThreadId]) {
+    CHILDREN.lock().entry(parent).or_default().extend_from_slice(children);
+}
+
+/// Remove `child` from `parent`'s list, if it is still on it.
+pub(crate) fn release(parent: ThreadId, child: ThreadId) {
+    let mut table = CHILDREN.lock();
+    if let Some(children) = table.get_mut(&parent) {
+        children.retain(|&id| id != child);
+        if children.is_empty() {
+            table.remove(&parent);
+        }
+    }
+}
+
+/// Get the live children of `parent`, in spawn order.
+pub(crate) fn children(parent: ThreadId) -> Vec<ThreadId> {
+    CHILDREN.lock().get(&parent).cloned().unwrap_or_default()
+}
+
+/// Get the number of live children of `parent`.
+pub(crate) fn child_count(parent: ThreadId) -> usize {
+    CHILDREN.lock().get(&parent).map_or(0, Vec::len)
+}
+
+/// Get `root` and every live descendant of it, parents before children.
+pub(crate) fn subtree(root: &Thread) -> Vec<Thread> {
+    let mut threads = alloc::vec![root.clone()];
+    let mut next = 0;
+    while next < threads.len() {
+        let parent = threads[next].id();
+        threads.extend(children(parent).into_iter().filter_map(registry::lookup));
+        next += 1;
+    }
+    threads
+}
@@ -3,31 +3,74 @@
 //! This module provides the new thread implementation that uses RAII
 //! for resource management and eliminates manual memory management.
 
-use crate::mem::{ArcLite, Stack};
+use crate::mem::{AddressSpace, ArcLite, Stack, HAZARD_DOMAIN, STACK_WATERMARKS};
 use crate::arch::Arch;
 use crate::time::{TimeSlice, Instant, Duration};
-use crate::observability::metrics::GLOBAL_METRICS;
+use crate::observability::hub::OBSERVER_HUB;
+use crate::observability::metrics::{ThreadMetrics, GLOBAL_METRICS};
 use crate::observability::resource_limits::GLOBAL_RESOURCE_LIMITER;
+use crate::observability::lock_chain::LOCK_WAIT_GRAPH;
+use crate::perf::numa::NumaPolicy;
+use crate::sched::cpuset::{AtomicCpuSet, CpuSet};
+use crate::sched::heap::HeapLink;
+use crate::sched::mpsc::MpscLink;
+use crate::testing::faults::{self, Fault};
 // PhantomData and AtomicUsize imports not needed yet
 // use core::marker::PhantomData;
-use portable_atomic::{AtomicU8, AtomicU64, AtomicUsize, AtomicBool, Ordering};
+use portable_atomic::{AtomicU8, AtomicU32, AtomicU64, AtomicUsize, AtomicBool, AtomicPtr, Ordering};
 extern crate alloc;
 use alloc::string::String;
+use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 
 pub mod handle;
 pub mod inner;
 pub mod builder;
+pub mod percpu;
+pub(crate) mod registry;
+pub mod checkpoint;
+pub mod user;
+pub mod blocked;
+pub mod priority;
+pub(crate) mod hierarchy;
+pub(crate) mod entry;
 
 pub use handle::JoinHandle;
 pub use builder::ThreadBuilder;
+pub use checkpoint::CheckpointError;
+pub use blocked::{blocked_on, io_wait, BlockedOn, BlockedOnGuard};
+pub use priority::{set_priority_policy, PriorityChange, PriorityDenied, PriorityField, PriorityPolicy};
 
 static CURRENT_THREAD_ID: portable_atomic::AtomicU64 = portable_atomic::AtomicU64::new(1);
 
-/// Get current thread ID (placeholder implementation).
+/// Find a live thread by name.
+pub fn find_by_name(name: &str) -> Option<Thread> {
+    registry::lookup_name(name)
+}
+
+/// Get every live thread that has a name, in name order.
+pub fn named_threads() -> Vec<(String, Thread)> {
+    registry::names()
+        .into_iter()
+        .filter_map(|(name, id)| registry::lookup(id).map(|thread| (name, thread)))
+        .collect()
+}
+
+/// Get the ID of the thread running on the current CPU.
+///
+/// Before the scheduler has installed a thread on this CPU, this returns
+/// the boot thread's ID.
 pub fn current_thread_id() -> ThreadId {
-    let id = CURRENT_THREAD_ID.load(portable_atomic::Ordering::Relaxed);
-    ThreadId::new(id)
+    percpu::current_id().unwrap_or_else(|| {
+        ThreadId::new(CURRENT_THREAD_ID.load(portable_atomic::Ordering::Relaxed))
+    })
+}
+
+/// Get a handle to the thread running on the current CPU.
+///
+/// Returns `None` before the scheduler has installed a thread on this CPU.
+pub fn current() -> Option<Thread> {
+    percpu::current()
 }
 
 /// Unique identifier for threads.
@@ -85,6 +128,132 @@ pub enum ThreadState {
     Blocked = 2,
     /// Thread has finished execution
     Finished = 3,
+    /// Thread is parked at a preemption checkpoint until resumed
+    Suspended = 4,
+}
+
+/// How a thread exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Entry point returned
+    Completed,
+    /// Killed by the kernel, e.g. for going over its CPU-time quota
+    Terminated,
+    /// Stopped by [`Thread::cancel`]
+    Cancelled,
+    /// Entry point panicked
+    Panicked,
+}
+
+/// Callback run after a thread exits, with its ID and how it exited.
+///
+/// See [`ThreadBuilder::on_exit`].
+pub type ExitCallback = fn(ThreadId, ExitStatus);
+
+/// Register `hook` to run when the current thread exits.
+///
+/// Hooks run in reverse order of registration, so a driver that claims a
+/// peripheral and then a DMA channel releases the channel first. They run
+/// from [`Kernel::reap`](crate::Kernel::reap) on the reaping thread, once
+/// the thread has exited however it exited, and before its stack is
+/// reclaimed or its [exit callback](ThreadBuilder::on_exit) runs.
+///
+/// The crate runs no thread-local storage destructors: a thread's TLS
+/// block is released with its stack, after the hooks. As hooks do not run
+/// on the exited thread, they cannot reach its thread-locals and should
+/// only touch shared state.
+///
+/// Returns `false`, registering nothing, without a current thread.
+pub fn at_exit(hook: fn()) -> bool {
+    match current() {
+        Some(thread) => {
+            thread.at_exit(hook);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Errors returned by [`Thread::suspend`] and [`Thread::resume`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendError {
+    /// Thread has already finished
+    Finished,
+    /// Thread has no outstanding suspension to resume
+    NotSuspended,
+    /// Suspension depth would overflow
+    Overflow,
+}
+
+/// Park the current thread while it has outstanding suspensions.
+///
+/// This is the quiescence point for [`Thread::suspend`]; it is called from
+/// [`preemption_checkpoint`](crate::preemption_checkpoint). A thread is
+/// never parked with preemption disabled or from interrupt context, so it
+/// never stops while holding a lock or in the middle of a critical section.
+pub fn suspend_point() {
+    if crate::time::preempt_count() > 0 || crate::time::in_interrupt() {
+        return;
+    }
+    
+    if let Some(thread) = current() {
+        park_while_suspended(&thread);
+    }
+}
+
+/// Block the current thread until it is [unparked](Thread::unpark).
+///
+/// An `unpark` issued before the call makes it return right away. It may
+/// also return spuriously, so callers re-check their condition in a loop.
+/// Without a current thread this only backs off once.
+pub fn park() {
+    park_until(None, BlockedOn::Park);
+}
+
+/// Like [`park`], giving up after `timeout`.
+pub fn park_timeout(timeout: Duration) {
+    let until = Instant::now().add(timeout);
+    park_until(Some(crate::time::TIMER_WHEEL.arm(timeout)), BlockedOn::Sleep(until));
+}
+
+fn park_until(deadline: Option<crate::time::Timeout<'_>>, reason: BlockedOn) {
+    crate::might_sleep!();
+    
+    let Some(thread) = current() else {
+        crate::sync::relax();
+        return;
+    };
+    if thread.inner.unpark_token.swap(false, Ordering::Acquire) {
+        return;
+    }
+    
+    let _blocked = BlockedOnGuard::record(Some(thread.clone()), reason);
+    thread.set_state(ThreadState::Blocked);
+    while !thread.inner.unpark_token.swap(false, Ordering::Acquire) {
+        if faults::inject(Fault::SpuriousWakeup) {
+            break;
+        }
+        if deadline.as_ref().is_some_and(|deadline| deadline.expired()) {
+            break;
+        }
+        crate::sync::relax();
+    }
+    thread.set_state(ThreadState::Running);
+}
+
+/// Park `thread`, which must be the current thread, until its suspension
+/// depth drops to zero.
+pub(crate) fn park_while_suspended(thread: &Thread) {
+    if thread.inner.suspend_depth.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    
+    thread.set_state(ThreadState::Suspended);
+    while thread.inner.suspend_depth.load(Ordering::Acquire) > 0 {
+        crate::sync::yield_thread();
+        core::hint::spin_loop();
+    }
+    thread.set_state(ThreadState::Running);
 }
 
 /// Main thread handle with RAII resource management.
@@ -97,6 +266,24 @@ pub struct Thread {
     inner: ArcLite<ThreadInner>,
 }
 
+/// `switched_in_at` value of a thread that is not on a CPU.
+const NOT_ON_CPU: u64 = u64::MAX;
+
+/// `hazard_record` value of a thread without a hazard record.
+const NO_HAZARD_RECORD: usize = usize::MAX;
+
+/// Tickets of nice values -20 to 19, the Linux CFS weight table.
+const NICE_TO_TICKETS: [u32; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
 /// Internal thread data shared between Thread and JoinHandle.
 pub struct ThreadInner {
     /// Unique thread identifier
@@ -111,20 +298,92 @@ pub struct ThreadInner {
     pub context: Option<*mut <crate::arch::DefaultArch as Arch>::SavedContext>,
     /// Entry point function (simplified for now)
     pub entry_point: Option<fn()>,
+    /// Entry point and index of a thread spawned by
+    /// [`ThreadBuilder::spawn_many`]
+    pub batch_entry: spin::Mutex<Option<(fn(usize), usize)>>,
     /// Join result storage
     pub join_result: spin::Mutex<Option<()>>, // TODO: Support return values
     /// Time slice tracking for scheduling
     pub time_slice: TimeSlice,
+    /// Time (in nanoseconds) at which the thread last became ready
+    pub ready_since: AtomicU64,
+    /// Address of the run queue holding the thread (0 = none)
+    pub run_queue: AtomicUsize,
     /// Thread name for debugging
     pub name: spin::Mutex<Option<String>>,
-    /// CPU affinity mask
-    pub cpu_affinity: AtomicU64,
+    /// Name the thread was given, before any suffix that made it unique;
+    /// keys the thread's stack watermark history
+    pub base_name: spin::Mutex<Option<String>>,
+    /// CPUs the thread may run on (empty = no affinity)
+    pub cpu_affinity: AtomicCpuSet,
     /// Thread group ID
     pub group_id: AtomicU64,
+    /// NUMA placement policy
+    pub numa_policy: spin::Mutex<NumaPolicy>,
+    /// Switch domain shared with sibling threads (0 = none)
+    pub switch_domain: AtomicU64,
+    /// Outstanding suspend requests; the thread parks while non-zero
+    pub suspend_depth: AtomicU32,
+    /// Set by [`Thread::unpark`], consumed by [`park`]
+    pub unpark_token: AtomicBool,
+    /// What the thread is blocked on, while it waits in a blocking primitive
+    pub blocked_on: spin::Mutex<Option<BlockedOn>>,
+    /// Set once the thread's [`JoinHandle`] is dropped, or at spawn for
+    /// threads spawned detached
+    pub detached: AtomicBool,
+    /// How the thread exited, once it has
+    pub exit_status: spin::Mutex<Option<ExitStatus>>,
+    /// The thread's metrics as they stood when it exited
+    pub final_metrics: spin::Mutex<Option<ThreadMetrics>>,
+    /// Callback to run once the thread has exited
+    pub on_exit: spin::Mutex<Option<ExitCallback>>,
+    /// Hooks registered with [`at_exit`], in registration order
+    pub exit_hooks: spin::Mutex<Vec<fn()>>,
+    /// ID of the thread that spawned this one (0 = none)
+    pub parent: AtomicU64,
+    /// Set by [`Thread::cancel`]; the kernel stops the thread the next time
+    /// it is picked or ticked
+    pub cancel_requested: AtomicBool,
+    /// CPU clock reading when the thread was last switched in
+    /// (`NOT_ON_CPU` while it is not running)
+    pub switched_in_at: AtomicU64,
+    /// CPU time accumulated over completed run slices (nanoseconds)
+    pub cpu_time_ns: AtomicU64,
+    /// CPU the thread was last switched in on
+    pub last_cpu: AtomicUsize,
+    /// Node for the intrusive run-queue heap
+    pub heap_link: HeapLink,
+    /// Node for the remote wakeup queue
+    pub mpsc_link: MpscLink,
+    /// Address space the thread runs in (null = whichever is loaded)
+    pub address_space: AtomicPtr<AddressSpace>,
+    /// Lowest address of the user-mode stack (0 = kernel thread)
+    pub user_stack_base: AtomicUsize,
+    /// Size of the user-mode stack in bytes
+    pub user_stack_size: AtomicUsize,
+    /// Index of the thread's record in `HAZARD_DOMAIN` (`NO_HAZARD_RECORD`
+    /// if it has none)
+    pub hazard_record: AtomicUsize,
+    /// Value guarding the low end of the stack (0 = no canary)
+    pub canary: AtomicU64,
+    /// Declared longest run between yields (nanoseconds, 0 = undeclared)
+    pub max_run_ns: AtomicU64,
+    /// How late the thread's timeouts may expire so they can be coalesced
+    /// (nanoseconds)
+    pub timer_slack_ns: AtomicU64,
+    /// Proportional-share tickets (0 = derived from the nice value)
+    pub tickets: AtomicU32,
+    /// Stride scheduler pass: virtual time charged for the CPU time used
+    pub stride_pass: AtomicU64,
     /// Whether this thread is critical
     pub critical: AtomicBool,
     /// Whether this thread can be preempted
     pub preemptible: AtomicBool,
+    /// Hint that this thread should not share a core with SMT siblings
+    pub no_smt: AtomicBool,
+    /// Whether the last run slice ended in preemption rather than a
+    /// voluntary switch
+    pub cpu_bound: AtomicBool,
     /// Reserved TLS size
     pub tls_size: AtomicUsize,
     /// Debug info enabled
@@ -163,20 +422,70 @@ impl Thread {
         entry_point: fn(),
         priority: u8,
     ) -> (Self, JoinHandle) {
+        let context = entry::new_context(&stack, entry_point);
+        Self::with_context(id, stack, Some(entry_point), priority, context)
+    }
+    
+    /// Create a thread with an already populated saved context.
+    ///
+    /// The thread takes ownership of `context`, which must come from
+    /// `Box::into_raw`, and frees it when the last reference is dropped.
+    pub(crate) fn with_context(
+        id: ThreadId,
+        stack: Stack,
+        entry_point: Option<fn()>,
+        priority: u8,
+        context: Option<*mut <crate::arch::DefaultArch as Arch>::SavedContext>,
+    ) -> (Self, JoinHandle) {
+        let canary = crate::security::stack_protection::generate_canary(id.as_u64());
+        stack.install_canary(canary);
+        
         let inner = ThreadInner {
             id,
             state: AtomicU8::new(ThreadState::Ready as u8),
             priority: AtomicU8::new(priority),
             stack: Some(stack),
-            context: None, // Will be initialized when first context switch occurs
-            entry_point: Some(entry_point),
+            context,
+            entry_point,
+            batch_entry: spin::Mutex::new(None),
             join_result: spin::Mutex::new(None),
             time_slice: TimeSlice::new(priority),
+            ready_since: AtomicU64::new(Instant::now().as_nanos()),
+            run_queue: AtomicUsize::new(0),
             name: spin::Mutex::new(None),
-            cpu_affinity: AtomicU64::new(0), // 0 means no affinity
+            base_name: spin::Mutex::new(None),
+            cpu_affinity: AtomicCpuSet::new(CpuSet::new()),
             group_id: AtomicU64::new(0),
+            numa_policy: spin::Mutex::new(NumaPolicy::Local),
+            switch_domain: AtomicU64::new(0),
+            suspend_depth: AtomicU32::new(0),
+            unpark_token: AtomicBool::new(false),
+            blocked_on: spin::Mutex::new(None),
+            detached: AtomicBool::new(false),
+            exit_status: spin::Mutex::new(None),
+            final_metrics: spin::Mutex::new(None),
+            on_exit: spin::Mutex::new(None),
+            exit_hooks: spin::Mutex::new(Vec::new()),
+            parent: AtomicU64::new(0),
+            cancel_requested: AtomicBool::new(false),
+            switched_in_at: AtomicU64::new(NOT_ON_CPU),
+            cpu_time_ns: AtomicU64::new(0),
+            last_cpu: AtomicUsize::new(0),
+            heap_link: HeapLink::new(),
+            mpsc_link: MpscLink::new(),
+            address_space: AtomicPtr::new(core::ptr::null_mut()),
+            user_stack_base: AtomicUsize::new(0),
+            user_stack_size: AtomicUsize::new(0),
+            hazard_record: AtomicUsize::new(NO_HAZARD_RECORD),
+            canary: AtomicU64::new(canary),
+            max_run_ns: AtomicU64::new(0),
+            timer_slack_ns: AtomicU64::new(0),
+            tickets: AtomicU32::new(0),
+            stride_pass: AtomicU64::new(0),
             critical: AtomicBool::new(false),
             preemptible: AtomicBool::new(true),
+            no_smt: AtomicBool::new(false),
+            cpu_bound: AtomicBool::new(false),
             tls_size: AtomicUsize::new(0),
             debug_info: AtomicBool::new(cfg!(debug_assertions)),
             rt_priority: AtomicU8::new(0),
@@ -189,7 +498,16 @@ impl Thread {
             max_children: AtomicU64::new(0),
         };
         
+        if let Some(entry_point) = entry_point {
+            crate::security::cfi::allow_target(entry_point as *const ());
+        }
+        
+        if let Some(record) = HAZARD_DOMAIN.acquire(id.as_u64()) {
+            inner.hazard_record.store(HAZARD_DOMAIN.index_of(record), Ordering::Release);
+        }
+        
         let inner_arc = ArcLite::new(inner);
+        registry::register(id, ArcLite::as_raw(&inner_arc), &*inner_arc);
         
         let thread = Self {
             inner: inner_arc.clone(),
@@ -200,7 +518,7 @@ impl Thread {
         };
         
         // Register thread with observability systems
-        GLOBAL_METRICS.register_thread(id);
+        OBSERVER_HUB.spawn(&thread);
         GLOBAL_RESOURCE_LIMITER.register_thread(id);
         
         (thread, join_handle)
@@ -219,6 +537,7 @@ impl Thread {
             1 => ThreadState::Running,
             2 => ThreadState::Blocked,
             3 => ThreadState::Finished,
+            4 => ThreadState::Suspended,
             _ => ThreadState::Ready, // Default fallback
         }
     }
@@ -229,7 +548,43 @@ impl Thread {
     ///
     /// * `new_state` - The new state to set
     pub fn set_state(&self, new_state: ThreadState) {
+        if new_state == ThreadState::Ready {
+            // Stamp before publishing the state so introspection never sees
+            // a Ready thread with a stale timestamp
+            self.inner.ready_since.store(Instant::now().as_nanos(), Ordering::Release);
+        }
         self.inner.state.store(new_state as u8, Ordering::Release);
+        if new_state == ThreadState::Blocked {
+            OBSERVER_HUB.block(self);
+        }
+    }
+    
+    /// Get the time at which this thread last became ready to run.
+    ///
+    /// # Returns
+    ///
+    /// `Some(instant)` if the thread is currently waiting in a run queue,
+    /// `None` if it is running, blocked, or finished.
+    pub fn ready_since(&self) -> Option<Instant> {
+        if self.state() == ThreadState::Ready {
+            Some(Instant::from_nanos(self.inner.ready_since.load(Ordering::Acquire)))
+        } else {
+            None
+        }
+    }
+    
+    /// Record that the run queue at address `queue` holds this thread, or
+    /// that none does if `queue` is 0.
+    ///
+    /// Schedulers set this as they queue and dequeue the thread so run
+    /// queue snapshots can be built without walking their queues.
+    pub(crate) fn set_run_queue(&self, queue: usize) {
+        self.inner.run_queue.store(queue, Ordering::Release);
+    }
+    
+    /// Get the address of the run queue holding this thread (0 = none).
+    pub(crate) fn run_queue(&self) -> usize {
+        self.inner.run_queue.load(Ordering::Acquire)
     }
     
     /// Get the thread's priority.
@@ -239,17 +594,18 @@ impl Thread {
     
     /// Set the thread's priority.
     ///
+    /// The change is put to the [priority policy](priority), which may
+    /// refuse it.
+    ///
     /// # Arguments
     ///
     /// * `new_priority` - The new priority (0-255, higher = more important)
-    pub fn set_priority(&self, new_priority: u8) {
+    pub fn set_priority(&self, new_priority: u8) -> Result<(), PriorityDenied> {
+        let change = priority::authorize(self.id(), PriorityField::Priority, self.priority() as i16, new_priority as i16)?;
         self.inner.priority.store(new_priority, Ordering::Release);
         self.inner.time_slice.set_priority(new_priority);
-        
-        // Record priority change in metrics
-        if GLOBAL_METRICS.is_enabled() {
-            // Priority changes are tracked automatically in thread metrics
-        }
+        priority::applied(self, &change);
+        Ok(())
     }
     
     /// Check if this thread is runnable (ready or running).
@@ -263,9 +619,7 @@ impl Thread {
     ///
     /// A pointer to the saved context, or null if not initialized.
     pub fn context_ptr(&self) -> *mut <crate::arch::DefaultArch as Arch>::SavedContext {
-        // TODO: This is unsafe and needs proper synchronization
-        // For now, return a null pointer as a placeholder
-        core::ptr::null_mut()
+        self.inner.context.unwrap_or(core::ptr::null_mut())
     }
     
     /// Get the thread's stack bottom (initial stack pointer).
@@ -273,15 +627,42 @@ impl Thread {
         self.inner.stack.as_ref().map(|stack| stack.stack_bottom())
     }
     
+    /// Get the thread's stack top (lowest usable address).
+    pub fn stack_top(&self) -> Option<*const u8> {
+        self.inner.stack.as_ref().map(|stack| stack.stack_top())
+    }
+    
+    /// Get the size of the thread's stack in bytes (0 if it has none).
+    pub fn stack_size(&self) -> usize {
+        self.inner.stack.as_ref().map_or(0, |stack| stack.size())
+    }
+    
     /// Check if the thread's stack canary is intact (stack overflow detection).
+    ///
+    /// Threads without a stack canary have nothing to check and pass.
     pub fn check_stack_integrity(&self) -> bool {
-        if let Some(ref stack) = self.inner.stack {
-            // Use a fixed canary value for now
-            let canary = 0xDEADBEEFCAFEBABE;
-            stack.check_canary(canary)
-        } else {
-            false
+        match (&self.inner.stack, self.stack_canary()) {
+            (Some(stack), Some(canary)) => stack.check_canary(canary),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+    
+    /// Get the canary guarding the thread's stack, if it has one.
+    pub fn stack_canary(&self) -> Option<u64> {
+        match self.inner.canary.load(Ordering::Acquire) {
+            0 => None,
+            canary => Some(canary),
+        }
+    }
+    
+    /// Guard the thread's stack with `canary`, or remove its canary with
+    /// `None`. Only for threads that haven't started running.
+    pub(crate) fn set_stack_canary(&self, canary: Option<u64>) {
+        if let (Some(stack), Some(canary)) = (&self.inner.stack, canary) {
+            stack.install_canary(canary);
         }
+        self.inner.canary.store(canary.unwrap_or(0), Ordering::Release);
     }
     
     /// Start a new time slice for this thread.
@@ -320,10 +701,27 @@ impl Thread {
     }
     
     /// Set the thread name for debugging purposes.
+    ///
+    /// The thread can then be found with [`find_by_name`]. Unlike names
+    /// given through [`ThreadBuilder::name`], the name is used as is even
+    /// if another thread already has it.
     pub fn set_name(&self, name: String) {
         if let Some(mut thread_name) = self.inner.name.try_lock() {
-            *thread_name = Some(name);
+            *thread_name = Some(name.clone());
+            *self.inner.base_name.lock() = Some(name.clone());
+            registry::set_name(self.inner.id, &self.inner, Some(name));
+        }
+    }
+    
+    /// Name the thread `base`, adding a `-2`, `-3`, ... suffix if another
+    /// live thread already has that name. Returns the name given.
+    pub(crate) fn set_unique_name(&self, base: &str) -> String {
+        let name = registry::set_unique_name(self.inner.id, &self.inner, base);
+        if let Some(mut thread_name) = self.inner.name.try_lock() {
+            *thread_name = Some(name.clone());
         }
+        *self.inner.base_name.lock() = Some(String::from(base));
+        name
     }
     
     /// Get the thread name.
@@ -331,14 +729,15 @@ impl Thread {
         self.inner.name.try_lock().and_then(|name| name.clone())
     }
     
-    /// Set CPU affinity mask.
-    pub fn set_cpu_affinity(&self, affinity: u64) {
-        self.inner.cpu_affinity.store(affinity, Ordering::Release);
+    /// Restrict the thread to the CPUs in `cpus`; an empty set lifts the
+    /// restriction.
+    pub fn set_cpu_affinity(&self, cpus: impl Into<CpuSet>) {
+        self.inner.cpu_affinity.store(cpus.into());
     }
     
-    /// Get CPU affinity mask.
-    pub fn cpu_affinity(&self) -> u64 {
-        self.inner.cpu_affinity.load(Ordering::Acquire)
+    /// Get the CPUs the thread may run on, empty if it has no affinity.
+    pub fn cpu_affinity(&self) -> CpuSet {
+        self.inner.cpu_affinity.load()
     }
     
     /// Set thread group ID.
@@ -351,11 +750,380 @@ impl Thread {
         self.inner.group_id.load(Ordering::Acquire) as u32
     }
     
+    /// Set NUMA placement policy.
+    pub fn set_numa_policy(&self, policy: NumaPolicy) {
+        *self.inner.numa_policy.lock() = policy;
+    }
+    
+    /// Get NUMA placement policy.
+    pub fn numa_policy(&self) -> NumaPolicy {
+        *self.inner.numa_policy.lock()
+    }
+    
+    /// Set the switch domain.
+    ///
+    /// Threads in the same non-zero domain share an address space and do not
+    /// rely on FPU/vector state surviving a switch to one another, so the
+    /// scheduler switches between them without address-space or FPU handling.
+    pub fn set_switch_domain(&self, domain: u32) {
+        self.inner.switch_domain.store(domain as u64, Ordering::Release);
+    }
+    
+    /// Get the switch domain, or `None` if the thread isn't in one.
+    pub fn switch_domain(&self) -> Option<u32> {
+        match self.inner.switch_domain.load(Ordering::Acquire) {
+            0 => None,
+            domain => Some(domain as u32),
+        }
+    }
+    
+    /// Run this thread in `space`, or in whichever space is loaded if
+    /// `None`.
+    ///
+    /// Takes effect the next time the thread is switched in.
+    pub fn set_address_space(&self, space: Option<&'static AddressSpace>) {
+        let space = space.map_or(core::ptr::null_mut(), |space| space as *const AddressSpace as *mut AddressSpace);
+        self.inner.address_space.store(space, Ordering::Release);
+    }
+    
+    /// Get the address space this thread runs in, if it has its own.
+    pub fn address_space(&self) -> Option<&'static AddressSpace> {
+        // Safety: only `&'static` spaces are stored
+        unsafe { self.inner.address_space.load(Ordering::Acquire).as_ref() }
+    }
+    
+    /// Give this thread a user-mode stack of `size` bytes at `base`.
+    ///
+    /// The thread's own stack stays its kernel stack, used for traps and
+    /// syscalls from user mode. The user stack is owned by the caller and
+    /// must be mapped user-accessible in the thread's address space.
+    pub fn set_user_stack(&self, base: usize, size: usize) {
+        self.inner.user_stack_size.store(size, Ordering::Relaxed);
+        self.inner.user_stack_base.store(base, Ordering::Release);
+    }
+    
+    /// Get the base and size of the user-mode stack, if the thread has one.
+    pub fn user_stack(&self) -> Option<(usize, usize)> {
+        match self.inner.user_stack_base.load(Ordering::Acquire) {
+            0 => None,
+            base => Some((base, self.inner.user_stack_size.load(Ordering::Relaxed))),
+        }
+    }
+    
+    /// Check whether this thread runs code in user mode.
+    pub fn is_user_thread(&self) -> bool {
+        self.inner.user_stack_base.load(Ordering::Acquire) != 0
+    }
+    
+    /// Get the top of the kernel stack, where traps from user mode land.
+    pub fn kernel_stack_top(&self) -> Option<usize> {
+        self.stack_bottom().map(|top| top as usize)
+    }
+    
+    /// Check whether this thread and `other` are in the same switch domain.
+    pub fn shares_switch_domain(&self, other: &Thread) -> bool {
+        self.switch_domain().is_some() && self.switch_domain() == other.switch_domain()
+    }
+    
+    /// Request that this thread stop at its next preemption checkpoint.
+    ///
+    /// Suspensions nest: the thread keeps running only after every
+    /// `suspend` has been matched by a [`resume`](Self::resume). The thread
+    /// is not stopped asynchronously; it parks the next time it passes a
+    /// preemption checkpoint with preemption enabled. A suspended thread
+    /// cannot finish, so joining it waits until it is resumed.
+    ///
+    /// # Returns
+    ///
+    /// The new suspension depth.
+    pub fn suspend(&self) -> Result<u32, SuspendError> {
+        if self.state() == ThreadState::Finished {
+            return Err(SuspendError::Finished);
+        }
+        
+        self.inner.suspend_depth
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| depth.checked_add(1))
+            .map(|previous| previous + 1)
+            .map_err(|_| SuspendError::Overflow)
+    }
+    
+    /// Undo one [`suspend`](Self::suspend).
+    ///
+    /// When the depth drops to zero a parked thread continues from its
+    /// checkpoint.
+    ///
+    /// # Returns
+    ///
+    /// The remaining suspension depth.
+    pub fn resume(&self) -> Result<u32, SuspendError> {
+        self.inner.suspend_depth
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| depth.checked_sub(1))
+            .map(|previous| previous - 1)
+            .map_err(|_| SuspendError::NotSuspended)
+    }
+    
+    /// Get the number of outstanding suspend requests.
+    pub fn suspend_depth(&self) -> u32 {
+        self.inner.suspend_depth.load(Ordering::Acquire)
+    }
+    
+    /// Check if the thread is currently parked at a checkpoint.
+    pub fn is_suspended(&self) -> bool {
+        self.state() == ThreadState::Suspended
+    }
+    
+    /// Wake the thread from [`park`], or make its next `park` return
+    /// immediately if it isn't parked.
+    pub fn unpark(&self) {
+        self.inner.unpark_token.store(true, Ordering::Release);
+        crate::sched::dispatch::note_wakeup(self.last_cpu());
+    }
+    
+    /// Check whether the thread's [`JoinHandle`] has been dropped.
+    pub fn is_detached(&self) -> bool {
+        self.inner.detached.load(Ordering::Acquire)
+    }
+    
+    /// Mark the thread as detached: it is never returned by
+    /// [`join_any`](crate::kernel::join_any), and its stack is reclaimed as
+    /// soon as it exits.
+    pub(crate) fn detach(&self) {
+        self.inner.detached.store(true, Ordering::Release);
+    }
+    
+    /// Get how the thread exited, if it has.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        *self.inner.exit_status.lock()
+    }
+    
+    /// Set the callback to run once the thread has exited.
+    pub(crate) fn set_on_exit(&self, callback: ExitCallback) {
+        *self.inner.on_exit.lock() = Some(callback);
+    }
+    
+    /// Take the exit callback, so it runs only once.
+    pub(crate) fn take_on_exit(&self) -> Option<ExitCallback> {
+        self.inner.on_exit.lock().take()
+    }
+    
+    /// Register `hook` to run when this thread exits. See [`at_exit`].
+    pub fn at_exit(&self, hook: fn()) {
+        self.inner.exit_hooks.lock().push(hook);
+    }
+    
+    /// Take the exit hooks in the order they run, so they run only once.
+    pub(crate) fn take_exit_hooks(&self) -> Vec<fn()> {
+        let mut hooks = core::mem::take(&mut *self.inner.exit_hooks.lock());
+        hooks.reverse();
+        hooks
+    }
+    
+    /// Get the ID of the thread that spawned this one, if any.
+    pub fn parent(&self) -> Option<ThreadId> {
+        match self.inner.parent.load(Ordering::Acquire) {
+            0 => None,
+            parent => Some(ThreadId::new(parent)),
+        }
+    }
+    
+    /// Record the thread that spawned this one.
+    ///
+    /// The parent's list of children is updated separately, with
+    /// `hierarchy::adopt`, so a batch of children is added at once.
+    pub(crate) fn set_parent(&self, parent: ThreadId) {
+        self.inner.parent.store(parent.as_u64(), Ordering::Release);
+    }
+    
+    /// Get the live threads this one spawned, in spawn order.
+    pub fn children(&self) -> Vec<Thread> {
+        hierarchy::children(self.id())
+            .into_iter()
+            .filter_map(registry::lookup)
+            .collect()
+    }
+    
+    /// Ask the kernel to stop the thread.
+    ///
+    /// A ready or blocked thread exits with [`ExitStatus::Cancelled`] the
+    /// next time it is picked to run, a running one at the next timer tick.
+    /// A parked thread is unparked so it gets picked.
+    pub fn cancel(&self) {
+        self.inner.cancel_requested.store(true, Ordering::Release);
+        self.unpark();
+    }
+    
+    /// Check whether the thread has been asked to stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancel_requested.load(Ordering::Acquire)
+    }
+    
+    /// [Cancel](Self::cancel) the thread and every live thread descended
+    /// from it.
+    ///
+    /// # Returns
+    ///
+    /// The number of threads cancelled, including this one.
+    pub fn cancel_subtree(&self) -> usize {
+        let subtree = hierarchy::subtree(self);
+        for thread in &subtree {
+            thread.cancel();
+        }
+        subtree.len()
+    }
+    
+    /// Wait for the thread and every thread descended from it to finish,
+    /// giving up after `timeout`.
+    ///
+    /// Threads spawned into the tree while waiting are waited for too. Must
+    /// not be called from inside the tree, which could never finish.
+    ///
+    /// # Errors
+    ///
+    /// [`ThreadError::TimedOut`](crate::errors::ThreadError::TimedOut),
+    /// naming this thread, if part of the tree is still alive when the
+    /// timeout expires.
+    pub fn join_subtree(&self, timeout: Duration) -> crate::errors::ThreadResult<()> {
+        crate::might_sleep!();
+        let _blocked = blocked_on(BlockedOn::Join(self.id()));
+        
+        let deadline = crate::time::TIMER_WHEEL.arm(timeout);
+        loop {
+            let alive = hierarchy::subtree(self)
+                .iter()
+                .any(|thread| thread.state() != ThreadState::Finished);
+            if !alive {
+                return Ok(());
+            }
+            if deadline.expired() {
+                return Err(crate::errors::ThreadError::TimedOut.for_thread(self.id()));
+            }
+            crate::sync::relax();
+        }
+    }
+    
+    /// Get the thread's stack.
+    pub(crate) fn stack(&self) -> Option<&Stack> {
+        self.inner.stack.as_ref()
+    }
+    
+    /// Drop the thread and take back its stack, if nothing else refers to
+    /// the thread any more.
+    ///
+    /// Hands the thread back if other references remain.
+    pub(crate) fn try_reclaim_stack(self) -> Result<Option<Stack>, Thread> {
+        let mut stack = None;
+        ArcLite::try_drop_with(self.inner, |inner| {
+            inner.record_stack_watermark();
+            stack = inner.stack.take();
+        })
+        .map(|()| stack)
+        .map_err(|inner| Thread { inner })
+    }
+    
+    /// Get what the thread is waiting for, if it is in a blocking primitive.
+    pub fn blocked_on(&self) -> Option<BlockedOn> {
+        *self.inner.blocked_on.lock()
+    }
+    
+    /// Record `reason` unless another reason is recorded already.
+    ///
+    /// Returns whether it was recorded.
+    pub(crate) fn record_blocked_on(&self, reason: BlockedOn) -> bool {
+        let mut blocked_on = self.inner.blocked_on.lock();
+        if blocked_on.is_some() {
+            return false;
+        }
+        *blocked_on = Some(reason);
+        drop(blocked_on);
+        crate::observability::lost_wakeup::arm(self.id(), reason);
+        true
+    }
+    
+    /// Clear the recorded blocking reason.
+    pub(crate) fn clear_blocked_on(&self) {
+        *self.inner.blocked_on.lock() = None;
+        crate::observability::lost_wakeup::disarm(self.id());
+    }
+    
     /// Set custom time slice duration.
     pub fn set_time_slice(&self, duration: Duration) {
         self.inner.time_slice.set_custom_duration(duration);
     }
     
+    /// Get the length of the thread's time slices.
+    pub fn time_slice_quantum(&self) -> Duration {
+        self.inner.time_slice.quantum()
+    }
+    
+    /// Declare the longest the thread runs before it yields or blocks.
+    pub fn set_max_run_length(&self, duration: Duration) {
+        self.inner.max_run_ns.store(duration.as_nanos(), Ordering::Release);
+    }
+    
+    /// Get the declared longest run between yields, if any.
+    pub fn max_run_length(&self) -> Option<Duration> {
+        match self.inner.max_run_ns.load(Ordering::Acquire) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+    
+    /// Let the thread's timeouts expire up to `slack` late, so they can be
+    /// coalesced with other timeouts. Ignored for real-time threads.
+    pub fn set_timer_slack(&self, slack: Duration) {
+        self.inner.timer_slack_ns.store(slack.as_nanos(), Ordering::Release);
+    }
+    
+    /// Get how late the thread's timeouts may expire.
+    pub fn timer_slack(&self) -> Duration {
+        Duration::from_nanos(self.inner.timer_slack_ns.load(Ordering::Acquire))
+    }
+    
+    /// Give the thread an indexed entry point as one of a batch.
+    pub(crate) fn set_batch_entry(&self, entry_point: fn(usize), index: usize) {
+        *self.inner.batch_entry.lock() = Some((entry_point, index));
+    }
+    
+    /// Get the thread's index in its [`ThreadBuilder::spawn_many`] batch.
+    pub fn spawn_index(&self) -> Option<usize> {
+        self.inner.batch_entry.lock().map(|(_, index)| index)
+    }
+    
+    /// Run the indexed entry point of a thread spawned in a batch.
+    pub(crate) fn run_batch_entry(&self) {
+        let batch_entry = *self.inner.batch_entry.lock();
+        if let Some((entry_point, index)) = batch_entry {
+            entry_point(index);
+        }
+    }
+    
+    /// Give the thread `tickets` shares of CPU time under proportional-share
+    /// scheduling; 0 goes back to deriving them from the nice value.
+    pub fn set_tickets(&self, tickets: u32) {
+        self.inner.tickets.store(tickets, Ordering::Release);
+    }
+    
+    /// Get the thread's proportional-share tickets.
+    ///
+    /// Unless set explicitly they follow the nice value, with the weights
+    /// Linux uses: 1024 at nice 0 and about 25% more CPU per step down.
+    pub fn tickets(&self) -> u32 {
+        match self.inner.tickets.load(Ordering::Acquire) {
+            0 => NICE_TO_TICKETS[(self.nice_value().clamp(-20, 19) + 20) as usize],
+            tickets => tickets,
+        }
+    }
+    
+    /// Get the thread's stride scheduler pass.
+    pub(crate) fn stride_pass(&self) -> u64 {
+        self.inner.stride_pass.load(Ordering::Acquire)
+    }
+    
+    /// Set the thread's stride scheduler pass.
+    pub(crate) fn set_stride_pass(&self, pass: u64) {
+        self.inner.stride_pass.store(pass, Ordering::Release);
+    }
+    
     /// Set whether this thread is critical.
     pub fn set_critical(&self, critical: bool) {
         self.inner.critical.store(critical, Ordering::Release);
@@ -376,6 +1144,23 @@ impl Thread {
         self.inner.preemptible.load(Ordering::Acquire)
     }
     
+    /// Set whether this thread should avoid sharing a physical core with
+    /// other busy hardware threads. See [`crate::sched::smt`].
+    pub fn set_no_smt(&self, no_smt: bool) {
+        self.inner.no_smt.store(no_smt, Ordering::Release);
+    }
+    
+    /// Check if this thread avoids sharing a physical core.
+    pub fn is_no_smt(&self) -> bool {
+        self.inner.no_smt.load(Ordering::Acquire)
+    }
+    
+    /// Check if this thread used up its last time slice, i.e. was
+    /// preempted rather than yielding or blocking.
+    pub fn is_cpu_bound(&self) -> bool {
+        self.inner.cpu_bound.load(Ordering::Acquire)
+    }
+    
     /// Reserve thread-local storage space.
     pub fn reserve_tls(&self, size: usize) {
         self.inner.tls_size.store(size, Ordering::Release);
@@ -397,8 +1182,14 @@ impl Thread {
     }
     
     /// Set real-time priority.
-    pub fn set_realtime_priority(&self, rt_priority: u8) {
+    ///
+    /// The change is put to the [priority policy](priority), which may
+    /// refuse it.
+    pub fn set_realtime_priority(&self, rt_priority: u8) -> Result<(), PriorityDenied> {
+        let change = priority::authorize(self.id(), PriorityField::Realtime, self.realtime_priority() as i16, rt_priority as i16)?;
         self.inner.rt_priority.store(rt_priority, Ordering::Release);
+        priority::applied(self, &change);
+        Ok(())
     }
     
     /// Get real-time priority.
@@ -407,7 +1198,22 @@ impl Thread {
     }
     
     /// Set nice value for process priority.
-    pub fn set_nice_value(&self, nice: i8) {
+    ///
+    /// The change is put to the [priority policy](priority), which may
+    /// refuse it.
+    pub fn set_nice_value(&self, nice: i8) -> Result<(), PriorityDenied> {
+        let change = priority::authorize(self.id(), PriorityField::Nice, self.nice_value() as i16, nice as i16)?;
+        self.inner.nice_value.store(nice, Ordering::Release);
+        priority::applied(self, &change);
+        Ok(())
+    }
+    
+    /// Set the real-time priority and nice value of a thread that is not
+    /// running yet, bypassing the priority policy.
+    pub(crate) fn init_scheduling_attributes(&self, rt_priority: Option<u8>, nice: i8) {
+        if let Some(rt_priority) = rt_priority {
+            self.inner.rt_priority.store(rt_priority, Ordering::Release);
+        }
         self.inner.nice_value.store(nice, Ordering::Release);
     }
     
@@ -479,6 +1285,9 @@ impl Thread {
     }
     
     /// Record memory allocation for this thread.
+    ///
+    /// Not needed when [`TrackingAllocator`](crate::observability::TrackingAllocator)
+    /// is the global allocator, which records heap usage automatically.
     pub fn record_memory_allocation(&self, size: u64) {
         use crate::observability::resource_limits::{ResourceType, GLOBAL_RESOURCE_LIMITER};
         
@@ -492,32 +1301,15 @@ impl Thread {
         }
         
         // Update resource usage
-        GLOBAL_RESOURCE_LIMITER.update_resource_usage(
-            self.id(),
-            ResourceType::Memory,
-            size,
-        );
+        crate::observability::alloc::record_allocation(self.id(), size);
     }
     
     /// Record memory deallocation for this thread.
+    ///
+    /// Not needed when [`TrackingAllocator`](crate::observability::TrackingAllocator)
+    /// is the global allocator, which records heap usage automatically.
     pub fn record_memory_deallocation(&self, size: u64) {
-        use crate::observability::resource_limits::{ResourceType, GLOBAL_RESOURCE_LIMITER};
-        
-        // Update resource usage (subtract from current usage)
-        let current_usage = GLOBAL_RESOURCE_LIMITER.get_system_usage()
-            .total_memory_usage.load(Ordering::Acquire);
-        
-        let new_usage = if current_usage >= size {
-            current_usage - size
-        } else {
-            0
-        };
-        
-        GLOBAL_RESOURCE_LIMITER.update_resource_usage(
-            self.id(),
-            ResourceType::Memory,
-            new_usage,
-        );
+        crate::observability::alloc::record_deallocation(self.id(), size);
     }
     
     /// Record CPU time usage for this thread.
@@ -538,6 +1330,111 @@ impl Thread {
     pub fn update_stack_usage_metrics(&self, current_usage: usize) {
         GLOBAL_METRICS.update_stack_usage(self.id(), current_usage);
     }
+    
+    /// Total CPU time this thread has run, including its current run slice.
+    ///
+    /// Measured automatically on every context switch.
+    pub fn cpu_time(&self) -> Duration {
+        let mut total = self.inner.cpu_time_ns.load(Ordering::Acquire);
+        let start = self.inner.switched_in_at.load(Ordering::Acquire);
+        if start != NOT_ON_CPU {
+            total += crate::time::cpu_clock().as_nanos().saturating_sub(start);
+        }
+        Duration::from_nanos(total)
+    }
+    
+    /// Get the thread's node for [`PairingHeap`](crate::sched::heap::PairingHeap).
+    pub(crate) fn heap_link(&self) -> &HeapLink {
+        &self.inner.heap_link
+    }
+    
+    /// Get the index of the thread's record in
+    /// [`HAZARD_DOMAIN`](crate::mem::HAZARD_DOMAIN).
+    ///
+    /// `None` once the thread has finished, or if every record was taken
+    /// when it was created; its hazard pointers then use the shared record.
+    pub fn hazard_record(&self) -> Option<usize> {
+        match self.inner.hazard_record.load(Ordering::Acquire) {
+            NO_HAZARD_RECORD => None,
+            index => Some(index),
+        }
+    }
+    
+    /// Give back the thread's hazard record, freeing what it retired.
+    fn release_hazard_record(inner: &ThreadInner) {
+        let index = inner.hazard_record.swap(NO_HAZARD_RECORD, Ordering::AcqRel);
+        if let Some(record) = HAZARD_DOMAIN.record(index) {
+            HAZARD_DOMAIN.release(record);
+        }
+    }
+    
+    /// Get the thread's node for [`MpscQueue`](crate::sched::mpsc::MpscQueue).
+    pub(crate) fn mpsc_link(&self) -> &MpscLink {
+        &self.inner.mpsc_link
+    }
+    
+    /// Get the CPU this thread last ran on (0 if it has never run).
+    pub fn last_cpu(&self) -> usize {
+        self.inner.last_cpu.load(Ordering::Acquire)
+    }
+    
+    /// Start a run slice; called when the thread is switched in at `now`.
+    pub(crate) fn account_switch_in(&self, now: Instant) {
+        let cpu = crate::arch::percpu::cpu_id();
+        self.inner.last_cpu.store(cpu, Ordering::Release);
+        crate::sched::smt::SMT_POLICY.note_switch_in(cpu, self);
+        self.inner.switched_in_at.store(now.as_nanos(), Ordering::Release);
+    }
+    
+    /// End the current run slice at `now` and charge it to this thread.
+    /// Returns how long the slice ran, if one was open.
+    pub(crate) fn account_switch_out(&self, now: Instant) -> Option<Duration> {
+        let start = self.inner.switched_in_at.swap(NOT_ON_CPU, Ordering::AcqRel);
+        if start == NOT_ON_CPU {
+            return None;
+        }
+        crate::sched::smt::SMT_POLICY.note_switch_out(self.last_cpu());
+        
+        let ran = now.as_nanos().saturating_sub(start);
+        self.inner.cpu_time_ns.fetch_add(ran, Ordering::AcqRel);
+        GLOBAL_METRICS.record_run_slice(self.id(), Duration::from_nanos(ran), now);
+        Some(Duration::from_nanos(ran))
+    }
+}
+
+impl Thread {
+    /// Check whether `self` and `other` are handles to the same thread.
+    ///
+    /// Unlike comparing IDs, this tells apart threads created with the
+    /// same ID.
+    pub fn ptr_eq(&self, other: &Thread) -> bool {
+        ArcLite::as_raw(&self.inner) == ArcLite::as_raw(&other.inner)
+    }
+
+    /// Consume the handle, returning an opaque pointer owning its reference.
+    pub(crate) fn into_raw(self) -> *const () {
+        ArcLite::into_raw(self.inner)
+    }
+    
+    /// Take a new handle from a pointer to a registered thread allocation.
+    ///
+    /// # Safety
+    ///
+    /// See [`ArcLite::upgrade_raw`].
+    pub(crate) unsafe fn upgrade_raw(ptr: *const ()) -> Option<Self> {
+        unsafe { ArcLite::upgrade_raw(ptr) }.map(|inner| Self { inner })
+    }
+    
+    /// Reconstruct a handle from [`Thread::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Thread::into_raw` and be reclaimed only once.
+    pub(crate) unsafe fn from_raw(ptr: *const ()) -> Self {
+        Self {
+            inner: unsafe { ArcLite::from_raw(ptr) },
+        }
+    }
 }
 
 impl Clone for Thread {
@@ -554,11 +1451,38 @@ unsafe impl Sync for Thread {}
 unsafe impl Send for ThreadInner {}
 unsafe impl Sync for ThreadInner {}
 
+impl ThreadInner {
+    /// Keep the stack high-water mark while the metrics still have it.
+    fn record_stack_watermark(&mut self) {
+        if let (Some(name), Some(stack)) = (self.base_name.get_mut().as_deref(), &self.stack) {
+            if let Some(metrics) = GLOBAL_METRICS.get_thread_metrics(self.id) {
+                STACK_WATERMARKS.record(name, metrics.peak_stack_usage, stack.size_class().size());
+            }
+        }
+    }
+}
+
 impl Drop for ThreadInner {
     fn drop(&mut self) {
+        self.record_stack_watermark();
+        
         // Unregister thread from observability systems
-        GLOBAL_METRICS.unregister_thread(self.id);
+        OBSERVER_HUB.exit(self.id);
         GLOBAL_RESOURCE_LIMITER.unregister_thread(self.id);
+        LOCK_WAIT_GRAPH.forget_thread(self.id);
+        match *self.parent.get_mut() {
+            0 => {}
+            parent => hierarchy::release(ThreadId::new(parent), self.id),
+        }
+        crate::resources::LEASES.release_all(self.id);
+        Thread::release_hazard_record(self);
+        
+        registry::unregister(self.id, self);
+        
+        if let Some(context) = self.context.take() {
+            // Safety: contexts stored in a thread are owned boxes
+            drop(unsafe { alloc::boxed::Box::from_raw(context) });
+        }
     }
 }
 
@@ -580,6 +1504,8 @@ impl ReadyRef {
     ///
     /// This should be called when the scheduler selects this thread to run.
     pub fn start_running(self) -> RunningRef {
+        let ready_ns = Instant::now().as_nanos().saturating_sub(self.0.inner.ready_since.load(Ordering::Acquire));
+        crate::observability::wake_latency::record(&self.0, ready_ns);
         self.0.set_state(ThreadState::Running);
         self.0.start_time_slice();
         RunningRef(self.0)
@@ -594,6 +1520,11 @@ impl ReadyRef {
     pub fn id(&self) -> ThreadId {
         self.0.id()
     }
+    
+    /// Get the time at which this thread became ready to run.
+    pub fn ready_since(&self) -> Instant {
+        Instant::from_nanos(self.0.inner.ready_since.load(Ordering::Acquire))
+    }
 }
 
 impl RunningRef {
@@ -601,6 +1532,7 @@ impl RunningRef {
     ///
     /// This should be called when the thread is preempted or yields.
     pub fn stop_running(self) -> ReadyRef {
+        self.0.inner.cpu_bound.store(false, Ordering::Release);
         self.0.set_state(ThreadState::Ready);
         ReadyRef(self.0)
     }
@@ -617,6 +1549,7 @@ impl RunningRef {
     ///
     /// This should be called when the thread blocks on I/O or synchronization.
     pub fn block(self) {
+        self.0.inner.cpu_bound.store(false, Ordering::Release);
         self.0.set_state(ThreadState::Blocked);
     }
     
@@ -624,12 +1557,34 @@ impl RunningRef {
     ///
     /// This should be called when the thread's entry point returns.
     pub fn finish(self) {
+        self.exit(ExitStatus::Completed);
+    }
+    
+    /// Mark this thread as finished with `status`.
+    ///
+    /// Joining a thread that did not complete fails.
+    pub fn exit(self, status: ExitStatus) {
+        // Keep the metrics for the join handle, as they are dropped with
+        // the thread
+        if let Some(mut metrics) = GLOBAL_METRICS.get_thread_metrics(self.0.id()) {
+            metrics.cpu_time_ns = metrics.cpu_time_ns.max(self.0.cpu_time().as_nanos());
+            *self.0.inner.final_metrics.lock() = Some(metrics);
+        }
         self.0.set_state(ThreadState::Finished);
+        Thread::release_hazard_record(&self.0.inner);
+        crate::resources::LEASES.release_all(self.0.id());
+        *self.0.inner.exit_status.lock() = Some(status);
+        if let Some(parent) = self.0.parent() {
+            hierarchy::release(parent, self.0.id());
+        }
         
         // Signal any joiners that we're done
-        if let Some(mut join_result) = self.0.inner.join_result.try_lock() {
-            *join_result = Some(());
+        if status == ExitStatus::Completed {
+            if let Some(mut join_result) = self.0.inner.join_result.try_lock() {
+                *join_result = Some(());
+            }
         }
+        crate::kernel::record_exit(self.0);
     }
     
     /// Prepare this thread for preemption.
@@ -637,6 +1592,7 @@ impl RunningRef {
     /// This saves the current state and returns a ReadyRef that can be re-enqueued.
     pub fn prepare_preemption(&self) -> ReadyRef {
         let ready = ReadyRef(self.0.clone());
+        ready.0.inner.cpu_bound.store(true, Ordering::Release);
         ready.0.set_state(ThreadState::Ready);
         ready
     }
@@ -652,11 +1608,8 @@ impl RunningRef {
     }
     
     /// Get the CPU this thread last ran on.
-    ///
-    /// For now, return 0 as a placeholder. In a real implementation,
-    /// this would track the actual CPU assignment.
     pub fn last_cpu(&self) -> usize {
-        0 // TODO: Track actual CPU assignment
+        self.0.last_cpu()
     }
     
     /// Get access to the thread's time slice for scheduler decisions.
@@ -691,6 +1644,51 @@ mod tests {
         assert!(thread.is_runnable());
     }
     
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_current_thread_slot() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = unsafe { ThreadId::new_unchecked(42) };
+        
+        let (thread, _join_handle) = Thread::new(thread_id, stack, || {}, 128);
+        
+        let _slot = percpu::hold_slot();
+        percpu::set_current(thread.clone());
+        assert_eq!(current().map(|t| t.id()), Some(thread_id));
+        assert_eq!(current_thread_id(), thread_id);
+        
+        percpu::clear_current();
+        assert!(current().is_none());
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_per_thread_stack_canary() {
+        let pool = StackPool::new();
+        let spawn = |id: usize| {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            let thread_id = unsafe { ThreadId::new_unchecked(id) };
+            Thread::new(thread_id, stack, || {}, 128).0
+        };
+        let first = spawn(49_220);
+        let second = spawn(49_221);
+        
+        let canary = first.stack_canary().unwrap();
+        assert_ne!(Some(canary), second.stack_canary());
+        assert_eq!(canary & 0xFF, 0);
+        assert!(first.check_stack_integrity());
+        
+        // Overflow into the lowest word of the stack
+        let stack = first.inner.stack.as_ref().unwrap();
+        stack.install_canary(!canary);
+        assert!(!first.check_stack_integrity());
+        assert!(second.check_stack_integrity());
+        
+        first.set_stack_canary(None);
+        assert!(first.check_stack_integrity());
+    }
+    
     #[cfg(feature = "std-shim")]
     #[test]
     fn test_thread_state_transitions() {
@@ -719,4 +1717,185 @@ mod tests {
         assert_eq!(thread.state(), ThreadState::Finished);
         assert!(!thread.is_runnable());
     }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_suspend_resume_nesting() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = unsafe { ThreadId::new_unchecked(7) };
+        
+        let (thread, _join_handle) = Thread::new(thread_id, stack, || {}, 128);
+        
+        assert_eq!(thread.resume(), Err(SuspendError::NotSuspended));
+        assert_eq!(thread.suspend(), Ok(1));
+        assert_eq!(thread.suspend(), Ok(2));
+        assert_eq!(thread.resume(), Ok(1));
+        assert_eq!(thread.suspend_depth(), 1);
+        
+        // Not parked until it reaches a checkpoint
+        assert!(!thread.is_suspended());
+        
+        assert_eq!(thread.resume(), Ok(0));
+        
+        thread.set_state(ThreadState::Finished);
+        assert_eq!(thread.suspend(), Err(SuspendError::Finished));
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_cpu_time_from_switches() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = unsafe { ThreadId::new_unchecked(8) };
+        
+        let (thread, _join_handle) = Thread::new(thread_id, stack, || {}, 128);
+        assert_eq!(thread.cpu_time().as_nanos(), 0);
+        
+        // Switching out without having been switched in charges nothing
+        thread.account_switch_out(Instant::from_nanos(50));
+        
+        thread.account_switch_in(Instant::from_nanos(100));
+        thread.account_switch_out(Instant::from_nanos(350));
+        thread.account_switch_in(Instant::from_nanos(1_000));
+        thread.account_switch_out(Instant::from_nanos(1_050));
+        assert_eq!(thread.cpu_time().as_nanos(), 300);
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_thread_listing() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = unsafe { ThreadId::new_unchecked(41_001) };
+        
+        let (thread, _join_handle) = Thread::new(thread_id, stack, || {}, 77);
+        thread.set_name(String::from("ps-listed"));
+        thread.account_switch_in(Instant::from_nanos(0));
+        thread.account_switch_out(Instant::from_nanos(2_500_000));
+        
+        let info = crate::kernel::threads()
+            .into_iter()
+            .find(|info| info.id == thread_id)
+            .unwrap();
+        assert_eq!(info.name.as_deref(), Some("ps-listed"));
+        assert_eq!(info.state, ThreadState::Ready);
+        assert_eq!(info.priority, 77);
+        assert_eq!(info.cpu, crate::arch::percpu::cpu_id());
+        assert_eq!(info.stack_size, thread.stack_size());
+        assert_eq!(info.cpu_time, Duration::from_nanos(2_500_000));
+        
+        let table = crate::kernel::format_threads();
+        assert!(table.starts_with(crate::kernel::ThreadInfo::HEADER));
+        assert!(table.contains("  41001 Ready        77"));
+        assert!(table.contains("0.002500s ps-listed\n"));
+        
+        drop(_join_handle);
+        drop(thread);
+        assert!(crate::kernel::threads().iter().all(|info| info.id != thread_id));
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_blocked_on_reason() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = unsafe { ThreadId::new_unchecked(49_270) };
+        let joined = unsafe { ThreadId::new_unchecked(49_271) };
+        
+        let (thread, _join_handle) = Thread::new(thread_id, stack, || {}, 128);
+        thread.set_name(String::from("waiter"));
+        assert_eq!(thread.blocked_on(), None);
+        
+        let outer = BlockedOnGuard::record(Some(thread.clone()), BlockedOn::Join(joined));
+        {
+            // A primitive used inside another keeps the outer reason
+            let _inner = BlockedOnGuard::record(Some(thread.clone()), BlockedOn::Park);
+            assert_eq!(thread.blocked_on(), Some(BlockedOn::Join(joined)));
+        }
+        assert_eq!(thread.blocked_on(), Some(BlockedOn::Join(joined)));
+        
+        let table = crate::kernel::format_threads();
+        assert!(table.contains("waiter (waiting on join of thread 49271)\n"));
+        
+        drop(outer);
+        assert_eq!(thread.blocked_on(), None);
+        let _irq = BlockedOnGuard::record(Some(thread.clone()), BlockedOn::Irq(5));
+        let reason = thread.blocked_on().unwrap();
+        assert_eq!(alloc::format!("{}", reason), "irq 5");
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_io_wait_accounting() {
+        use crate::observability::metrics::GLOBAL_METRICS;
+        
+        let _ = GLOBAL_METRICS.init(1000);
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let thread_id = ThreadId::new(49_726);
+        let (thread, _join_handle) = Thread::new(thread_id, stack, || {}, 128);
+        GLOBAL_METRICS.register_thread(thread_id);
+        let device = 49_726;
+        
+        let io = BlockedOnGuard::record(Some(thread.clone()), BlockedOn::Io(Some(device)));
+        assert_eq!(alloc::format!("{}", thread.blocked_on().unwrap()), "I/O on device 49726");
+        drop(io);
+        GLOBAL_METRICS.record_blocked_time(thread_id, BlockedOn::Io(Some(device)), Duration::from_micros(300));
+        GLOBAL_METRICS.record_blocked_time(thread_id, BlockedOn::Irq(5), Duration::from_micros(200));
+        GLOBAL_METRICS.record_blocked_time(thread_id, BlockedOn::Park, Duration::from_micros(50));
+        
+        let metrics = GLOBAL_METRICS.get_thread_metrics(thread_id).unwrap();
+        assert!(metrics.io_wait_ns >= 500_000);
+        assert_eq!(metrics.blocked_ns, 50_000);
+        
+        // Only waits naming the device are attributed to it
+        let report = GLOBAL_METRICS.generate_report();
+        let wait = report.io_wait_by_device.iter().find(|wait| wait.device == device).unwrap();
+        assert_eq!(wait.waits, 2);
+        assert!(wait.wait_ns >= 300_000);
+        assert!(wait.max_wait_ns >= 300_000 && wait.max_wait_ns <= wait.wait_ns);
+        GLOBAL_METRICS.unregister_thread(thread_id);
+    }
+    
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_thread_hierarchy() {
+        let pool = StackPool::new();
+        let spawn = |id: usize, parent: Option<&Thread>| {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            let thread_id = unsafe { ThreadId::new_unchecked(id) };
+            // Dropping the handle keeps the threads away from `join_any`
+            let thread = Thread::new(thread_id, stack, || {}, 128).0;
+            if let Some(parent) = parent {
+                thread.set_parent(parent.id());
+                hierarchy::adopt(parent.id(), &[thread_id]);
+            }
+            thread
+        };
+        let root = spawn(49_272, None);
+        let first = spawn(49_273, Some(&root));
+        let second = spawn(49_274, Some(&root));
+        let grandchild = spawn(49_275, Some(&first));
+        
+        assert_eq!(first.parent(), Some(root.id()));
+        let children: Vec<ThreadId> = root.children().iter().map(Thread::id).collect();
+        assert_eq!(children, [first.id(), second.id()]);
+        
+        // The child limit counts live children
+        root.set_max_children(2);
+        assert_eq!(hierarchy::check_spawn(root.id(), 1), Err(crate::errors::SpawnError::TooManyThreads));
+        
+        assert_eq!(root.cancel_subtree(), 4);
+        assert!(grandchild.is_cancelled() && second.is_cancelled());
+        assert!(root.join_subtree(Duration::from_nanos(0)).is_err());
+        
+        for thread in [&grandchild, &first, &second, &root] {
+            ReadyRef(thread.clone()).start_running().exit(ExitStatus::Cancelled);
+        }
+        assert!(root.children().is_empty());
+        assert_eq!(hierarchy::check_spawn(root.id(), 2), Ok(()));
+        assert_eq!(root.join_subtree(Duration::from_nanos(0)), Ok(()));
+        assert_eq!(first.exit_status(), Some(ExitStatus::Cancelled));
+    }
 }
\ No newline at end of file
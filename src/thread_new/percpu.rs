@@ -0,0 +1,111 @@
+//! Per-CPU current-thread slots.
+//!
+//! The context switcher installs the thread it switches to in the slot of
+//! the CPU it runs on, so [`current`](super::current) and
+//! [`current_thread_id`](super::current_thread_id) reflect the thread that
+//! is actually executing rather than a global guess.
+
+use super::{Thread, ThreadId};
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use portable_atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Current-thread slot for one CPU.
+#[repr(align(64))] // Cache line aligned, written on every switch
+struct CpuSlot {
+    /// Raw handle owning one reference to the running thread
+    thread: AtomicPtr<()>,
+    /// Id of the running thread (0 = none), readable without touching the handle
+    thread_id: AtomicUsize,
+}
+
+impl CpuSlot {
+    const fn new() -> Self {
+        Self {
+            thread: AtomicPtr::new(core::ptr::null_mut()),
+            thread_id: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: CpuSlot = CpuSlot::new();
+
+static SLOTS: [CpuSlot; MAX_CPUS] = [EMPTY_SLOT; MAX_CPUS];
+
+/// Install `thread` as the running thread on the current CPU.
+///
+/// Called by the context switcher with preemption disabled, right before
+/// switching to `thread`.
+pub fn set_current(thread: Thread) {
+    let slot = &SLOTS[cpu_id()];
+    slot.thread_id.store(thread.id().get(), Ordering::Release);
+
+    let old = slot.thread.swap(thread.into_raw() as *mut (), Ordering::AcqRel);
+    if !old.is_null() {
+        // Safety: the slot owned this reference
+        drop(unsafe { Thread::from_raw(old) });
+    }
+}
+
+/// Clear the running thread on the current CPU (e.g. when it goes idle).
+pub fn clear_current() {
+    let slot = &SLOTS[cpu_id()];
+    slot.thread_id.store(0, Ordering::Release);
+
+    let old = slot.thread.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    if !old.is_null() {
+        // Safety: the slot owned this reference
+        drop(unsafe { Thread::from_raw(old) });
+    }
+}
+
+/// Get a handle to the thread running on the current CPU.
+pub fn current() -> Option<Thread> {
+    let raw = SLOTS[cpu_id()].thread.load(Ordering::Acquire);
+    if raw.is_null() {
+        return None;
+    }
+
+    // Safety: only this CPU writes its own slot, and it does so while
+    // switching away from the caller. Whatever the caller read is therefore
+    // the caller's own thread, which stays alive while it runs.
+    let slot_ref = core::mem::ManuallyDrop::new(unsafe { Thread::from_raw(raw) });
+    Some(Thread::clone(&slot_ref))
+}
+
+/// Get the id of the thread running on the current CPU.
+pub fn current_id() -> Option<ThreadId> {
+    match SLOTS[cpu_id()].thread_id.load(Ordering::Acquire) {
+        0 => None,
+        // Safety: 0 is filtered out above
+        id => Some(unsafe { ThreadId::new_unchecked(id) }),
+    }
+}
+
+/// Exclusive use of this CPU's slot in a test, which all host test threads
+/// share. The thread the slot held is put back when the guard drops.
+#[cfg(test)]
+pub(crate) struct SlotGuard {
+    saved: Option<Thread>,
+    _lock: spin::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+static SLOT_TEST_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+
+/// Take this CPU's slot for the duration of a test.
+#[cfg(test)]
+pub(crate) fn hold_slot() -> SlotGuard {
+    let lock = SLOT_TEST_LOCK.lock();
+    SlotGuard { saved: current(), _lock: lock }
+}
+
+#[cfg(test)]
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        match self.saved.take() {
+            Some(thread) => set_current(thread),
+            None => clear_current(),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Priority change notification and the policy guarding it.
+//!
+//! Every change of a live thread's priority, real-time priority or nice
+//! value through [`Thread::set_priority`],
+//! [`Thread::set_realtime_priority`] or [`Thread::set_nice_value`] is first
+//! put to the installed [`PriorityPolicy`], which may veto it. Changes that
+//! go through are reported to the
+//! [observers](crate::observability::ThreadObserver::on_priority_change)
+//! and, like vetoed ones, written to the audit log.
+//!
+//! The policy sees who asked for the change, so it is where an access
+//! control scheme decides who may do what:
+//!
+//! ```ignore
+//! static SUPERVISOR: AtomicU64 = AtomicU64::new(0);
+//!
+//! fn only_supervisor_raises_rt(change: &PriorityChange) -> Result<(), &'static str> {
+//!     let by_supervisor = change.changed_by.is_some_and(|id| id.as_u64() == SUPERVISOR.load(Ordering::Relaxed));
+//!     if change.field == PriorityField::Realtime && change.is_raise() && !by_supervisor {
+//!         return Err("only the supervisor may raise real-time priority");
+//!     }
+//!     Ok(())
+//! }
+//!
+//! set_priority_policy(Some(only_supervisor_raises_rt));
+//! ```
+//!
+//! Attributes a thread is spawned or restored with are not changes and do
+//! not go through the policy.
+
+use super::{percpu, Thread, ThreadId};
+use crate::observability::hub::OBSERVER_HUB;
+use crate::security::audit::{self, SchedulerEventType};
+use crate::sync::SpinLockIrqSave;
+use core::fmt;
+extern crate alloc;
+use alloc::format;
+
+/// Scheduling attribute a change applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PriorityField {
+    /// Scheduling priority, higher is more important
+    Priority,
+    /// Real-time priority, higher is more important
+    Realtime,
+    /// Nice value, lower is more important
+    Nice,
+}
+
+impl fmt::Display for PriorityField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriorityField::Priority => f.write_str("priority"),
+            PriorityField::Realtime => f.write_str("rt_priority"),
+            PriorityField::Nice => f.write_str("nice"),
+        }
+    }
+}
+
+/// A change of one of a thread's scheduling attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityChange {
+    /// Thread whose attribute changes
+    pub thread: ThreadId,
+    /// Thread asking for the change, `None` outside any thread
+    pub changed_by: Option<ThreadId>,
+    pub field: PriorityField,
+    pub old: i16,
+    pub new: i16,
+}
+
+impl PriorityChange {
+    fn new(thread: ThreadId, field: PriorityField, old: i16, new: i16) -> Self {
+        Self {
+            thread,
+            changed_by: percpu::current_id(),
+            field,
+            old,
+            new,
+        }
+    }
+
+    /// Check whether the change makes the thread more important.
+    pub fn is_raise(&self) -> bool {
+        match self.field {
+            PriorityField::Priority | PriorityField::Realtime => self.new > self.old,
+            PriorityField::Nice => self.new < self.old,
+        }
+    }
+}
+
+impl fmt::Display for PriorityChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread {} {} {} -> {}", self.thread, self.field, self.old, self.new)?;
+        match self.changed_by {
+            Some(by) => write!(f, " by thread {}", by),
+            None => f.write_str(" by kernel"),
+        }
+    }
+}
+
+/// A change refused by the [`PriorityPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityDenied {
+    pub change: PriorityChange,
+    /// Why the policy refused it
+    pub reason: &'static str,
+}
+
+impl fmt::Display for PriorityDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} denied: {}", self.change, self.reason)
+    }
+}
+
+/// Decides whether a change may go through, giving the reason if not.
+///
+/// Runs on the thread asking for the change, before it is applied, so it
+/// must be short and must not block.
+pub type PriorityPolicy = fn(&PriorityChange) -> Result<(), &'static str>;
+
+static POLICY: SpinLockIrqSave<Option<PriorityPolicy>> = SpinLockIrqSave::new(None);
+
+/// Install the policy vetting priority changes, replacing any previous
+/// one, or allow every change with `None`.
+pub fn set_priority_policy(policy: Option<PriorityPolicy>) {
+    *POLICY.lock() = policy;
+}
+
+/// Put a change of `thread`'s `field` to the policy, returning the change
+/// to pass to [`applied`] if it may go through.
+pub(crate) fn authorize(thread: ThreadId, field: PriorityField, old: i16, new: i16) -> Result<PriorityChange, PriorityDenied> {
+    let change = PriorityChange::new(thread, field, old, new);
+    let policy = *POLICY.lock();
+    if let Some(Err(reason)) = policy.map(|policy| policy(&change)) {
+        audit::log_scheduler_event(
+            SchedulerEventType::PriorityChange,
+            Some(thread),
+            &format!("{} denied: {}", change, reason),
+        );
+        return Err(PriorityDenied { change, reason });
+    }
+    Ok(change)
+}
+
+/// Report an authorized change once `thread` carries it.
+pub(crate) fn applied(thread: &Thread, change: &PriorityChange) {
+    if change.old != change.new {
+        OBSERVER_HUB.priority_change(thread, change);
+    }
+    audit::log_scheduler_event(SchedulerEventType::PriorityChange, Some(change.thread), &format!("{}", change));
+}
+
+#[cfg(all(test, feature = "std-shim"))]
+mod tests {
+    use super::*;
+    use crate::mem::{StackPool, StackSizeClass};
+    use crate::observability::ThreadObserver;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    const GUARDED: u64 = 49_700;
+
+    fn no_rt_raise_for_guarded(change: &PriorityChange) -> Result<(), &'static str> {
+        if change.thread == ThreadId::new(GUARDED) && change.field == PriorityField::Realtime && change.is_raise() {
+            return Err("real-time raise not allowed");
+        }
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        changes: spin::Mutex<Vec<PriorityChange>>,
+    }
+
+    impl ThreadObserver for Recorder {
+        fn on_priority_change(&self, _thread: &Thread, change: &PriorityChange) {
+            if change.thread == ThreadId::new(GUARDED) {
+                self.changes.lock().push(*change);
+            }
+        }
+    }
+
+    #[test]
+    fn test_policy_vetoes_and_observers_see_changes() {
+        let pool = StackPool::new();
+        let stack = pool.allocate(StackSizeClass::Small).unwrap();
+        let (thread, _join) = Thread::new(ThreadId::new(GUARDED), stack, || {}, 10);
+        let recorder = Arc::new(Recorder::default());
+        let id = OBSERVER_HUB.register(recorder.clone());
+        set_priority_policy(Some(no_rt_raise_for_guarded));
+
+        let denied = thread.set_realtime_priority(50).unwrap_err();
+        assert_eq!(denied.reason, "real-time raise not allowed");
+        assert_eq!((denied.change.old, denied.change.new), (0, 50));
+        assert_eq!(thread.realtime_priority(), 0);
+
+        thread.set_priority(20).unwrap();
+        thread.set_nice_value(-5).unwrap();
+        assert_eq!((thread.priority(), thread.nice_value()), (20, -5));
+
+        set_priority_policy(None);
+        OBSERVER_HUB.unregister(id);
+
+        let changes = recorder.changes.lock();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].field, changes[0].old, changes[0].new), (PriorityField::Priority, 10, 20));
+        assert_eq!((changes[1].field, changes[1].old, changes[1].new), (PriorityField::Nice, 0, -5));
+        assert!(changes[1].is_raise());
+    }
+}
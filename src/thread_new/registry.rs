@@ -0,0 +1,142 @@
+//! Table of live threads, indexed by ID and by name.
+//!
+//! The table does not keep threads alive: entries are weak pointers that
+//! are removed when the thread's last reference is dropped, and lookups
+//! only succeed while a strong reference still exists.
+
+use super::{Thread, ThreadId, ThreadInner};
+use crate::sync::SpinLockIrqSave;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Registered thread allocation.
+struct Entry {
+    /// Pointer from `ArcLite::as_raw`
+    raw: usize,
+    /// Address of the `ThreadInner`, used to match the entry on drop
+    inner: usize,
+    /// Name the thread is indexed under
+    name: Option<String>,
+}
+
+struct Table {
+    by_id: BTreeMap<ThreadId, Entry>,
+    by_name: BTreeMap<String, ThreadId>,
+}
+
+impl Table {
+    /// Get the entry for `id` if it still refers to `inner`.
+    ///
+    /// IDs can be reused by callers constructing threads directly, so
+    /// updates must only touch the entry of the same allocation.
+    fn entry_mut<'a>(
+        by_id: &'a mut BTreeMap<ThreadId, Entry>,
+        id: ThreadId,
+        inner: &ThreadInner,
+    ) -> Option<&'a mut Entry> {
+        by_id
+            .get_mut(&id)
+            .filter(|entry| entry.inner == inner as *const ThreadInner as usize)
+    }
+
+    /// Point `entry`'s name at a new value, dropping its old index entry.
+    fn rename(by_name: &mut BTreeMap<String, ThreadId>, id: ThreadId, entry: &mut Entry, name: Option<String>) {
+        if let Some(old) = entry.name.take() {
+            if by_name.get(&old) == Some(&id) {
+                by_name.remove(&old);
+            }
+        }
+        if let Some(name) = &name {
+            by_name.insert(name.clone(), id);
+        }
+        entry.name = name;
+    }
+}
+
+/// Live threads. Taken with interrupts disabled because threads may be
+/// dropped from the timer interrupt path.
+static THREADS: SpinLockIrqSave<Table> = SpinLockIrqSave::new(Table {
+    by_id: BTreeMap::new(),
+    by_name: BTreeMap::new(),
+});
+
+/// Record a newly created thread.
+pub(crate) fn register(id: ThreadId, raw: *const (), inner: &ThreadInner) {
+    THREADS.lock().by_id.insert(id, Entry {
+        raw: raw as usize,
+        inner: inner as *const ThreadInner as usize,
+        name: None,
+    });
+}
+
+/// Remove a thread whose last reference is being dropped.
+pub(crate) fn unregister(id: ThreadId, inner: &ThreadInner) {
+    let mut threads = THREADS.lock();
+    let table = &mut *threads;
+
+    if let Some(entry) = Table::entry_mut(&mut table.by_id, id, inner) {
+        Table::rename(&mut table.by_name, id, entry, None);
+        table.by_id.remove(&id);
+    }
+}
+
+/// Index a thread under `name`, replacing its previous name.
+pub(crate) fn set_name(id: ThreadId, inner: &ThreadInner, name: Option<String>) {
+    let mut threads = THREADS.lock();
+    let table = &mut *threads;
+
+    if let Some(entry) = Table::entry_mut(&mut table.by_id, id, inner) {
+        Table::rename(&mut table.by_name, id, entry, name);
+    }
+}
+
+/// Index a thread under `base`, or under `base-2`, `base-3`, ... if that
+/// name is taken by another live thread. Returns the name used.
+pub(crate) fn set_unique_name(id: ThreadId, inner: &ThreadInner, base: &str) -> String {
+    let mut threads = THREADS.lock();
+    let table = &mut *threads;
+
+    let mut name = String::from(base);
+    let mut suffix = 2;
+    while table.by_name.get(&name).map_or(false, |&owner| owner != id) {
+        name = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+
+    if let Some(entry) = Table::entry_mut(&mut table.by_id, id, inner) {
+        Table::rename(&mut table.by_name, id, entry, Some(name.clone()));
+    }
+    name
+}
+
+/// Get the IDs of all registered threads, in ascending order.
+pub(crate) fn ids() -> Vec<ThreadId> {
+    THREADS.lock().by_id.keys().copied().collect()
+}
+
+/// Get the names of all named threads and their IDs, in name order.
+pub(crate) fn names() -> Vec<(String, ThreadId)> {
+    THREADS.lock().by_name.iter().map(|(name, &id)| (name.clone(), id)).collect()
+}
+
+/// Get a handle to a live thread by ID.
+pub(crate) fn lookup(id: ThreadId) -> Option<Thread> {
+    let threads = THREADS.lock();
+    let entry = threads.by_id.get(&id)?;
+
+    // Safety: entries are removed under this lock before their memory is
+    // freed, so the allocation is still valid while we hold it
+    unsafe { Thread::upgrade_raw(entry.raw as *const ()) }
+}
+
+/// Get a handle to a live thread by name.
+pub(crate) fn lookup_name(name: &str) -> Option<Thread> {
+    let threads = THREADS.lock();
+    let id = threads.by_name.get(name)?;
+    let entry = threads.by_id.get(id)?;
+
+    // Safety: as in `lookup`
+    unsafe { Thread::upgrade_raw(entry.raw as *const ()) }
+}
@@ -0,0 +1,58 @@
+//! User-mode threads.
+//!
+//! A user thread keeps the stack it was spawned with as its kernel stack,
+//! for syscalls and exceptions taken from user mode, and gets a second
+//! stack for user code with [`Thread::set_user_stack`]. The scheduler
+//! points the CPU's trap stack at the kernel stack whenever a user thread
+//! is switched in. Trap entry is the platform's business; once it has
+//! handled a syscall or exception it resumes user code with
+//! [`return_to_user`].
+
+use super::Thread;
+use crate::arch::{Arch, DefaultArch, UserFrame};
+use crate::errors::{ThreadError, ThreadResult};
+use core::convert::Infallible;
+
+/// Alignment of the initial user stack pointer.
+const USER_STACK_ALIGN: usize = 16;
+
+/// Drop the current thread to user mode, starting at `entry`.
+///
+/// Runs `entry` on the current thread's user stack. Only returns if the
+/// current thread is not a user thread.
+///
+/// # Safety
+///
+/// `entry` and the user stack must be mapped user-accessible in the
+/// current address space, and the platform's trap entry must be set up to
+/// switch to the kernel stack, and on x86_64 to `swapgs` back to the
+/// kernel's GS base.
+pub unsafe fn enter_user_mode(entry: usize) -> ThreadResult<Infallible> {
+    let thread = super::current().ok_or_else(ThreadError::InvalidState)?;
+    let (base, size) = thread.user_stack().ok_or_else(ThreadError::InvalidState)?;
+    let sp = base.checked_add(size).ok_or_else(ThreadError::InvalidState)? & !(USER_STACK_ALIGN - 1);
+
+    enter_kernel_stack(&thread);
+    drop(thread);
+    // Safety: forwarded from the caller
+    unsafe { DefaultArch::enter_user_mode(entry, sp) }
+}
+
+/// Resume the current thread's user code after a syscall or exception.
+///
+/// # Safety
+///
+/// As for [`Arch::return_to_user`]; `frame` must be the state saved on
+/// trap entry, with `ret` set to the syscall result if any.
+pub unsafe fn return_to_user(frame: &UserFrame) -> ! {
+    // Safety: forwarded from the caller
+    unsafe { DefaultArch::return_to_user(frame) }
+}
+
+/// Point the CPU's trap stack at `thread`'s kernel stack.
+fn enter_kernel_stack(thread: &Thread) {
+    if let Some(top) = thread.kernel_stack_top() {
+        // Safety: the stack belongs to `thread`, which is running
+        unsafe { DefaultArch::set_kernel_stack(top) };
+    }
+}
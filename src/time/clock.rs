@@ -0,0 +1,375 @@
+//! Calibrated monotonic clock built on a registered counter.
+//!
+//! Architecture counters such as the TSC run at a frequency that is not
+//! always known up front, may drift from the platform timer, and on some
+//! systems are not synchronized between CPUs. A [`ClockSource`] only has to
+//! provide raw counts; [`MonotonicClock`] calibrates each CPU's view of the
+//! counter against the timer tick and turns it into nanoseconds:
+//!
+//! - Every tick, each CPU compares the counter against the tick time. Once a
+//!   calibration window has passed, the measured frequency is folded into
+//!   the CPU's estimate, which compensates for drift.
+//! - Each CPU converts counts relative to the last tick it saw, so offsets
+//!   between unsynchronized counters never show up in the result.
+//! - Readings are clamped to the latest value handed out on any CPU, so
+//!   [`Instant`]s taken on different CPUs are ordered.
+//!
+//! Once a source is registered and calibrated, [`Instant::now`] reads the
+//! global [`CLOCK`].
+
+use super::{Duration, Instant};
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::arch::{Arch, DefaultArch};
+use crate::time::PreemptGuard;
+extern crate alloc;
+use alloc::boxed::Box;
+use portable_atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// A free-running hardware counter.
+pub trait ClockSource: Send + Sync {
+    /// Name of the source, for diagnostics.
+    fn name(&self) -> &'static str;
+
+    /// Read the raw counter value.
+    fn read(&self) -> u64;
+
+    /// Nominal counter frequency in Hz, if known before calibration.
+    fn frequency_hint(&self) -> Option<u64> {
+        None
+    }
+
+    /// Preference among registered sources; the highest rating is used.
+    fn rating(&self) -> u32;
+}
+
+/// Conversion between counter cycles and nanoseconds at a fixed frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockScale {
+    hz: u64,
+}
+
+impl ClockScale {
+    /// Create a scale for a counter running at `hz`.
+    ///
+    /// Returns `None` if `hz` is zero.
+    pub fn new(hz: u64) -> Option<Self> {
+        if hz == 0 {
+            None
+        } else {
+            Some(Self { hz })
+        }
+    }
+
+    /// Get the counter frequency in Hz.
+    pub fn frequency(self) -> u64 {
+        self.hz
+    }
+
+    /// Convert a number of cycles to nanoseconds.
+    pub fn cycles_to_nanos(self, cycles: u64) -> u64 {
+        (cycles as u128 * NANOS_PER_SEC / self.hz as u128) as u64
+    }
+
+    /// Convert nanoseconds to a number of cycles.
+    pub fn nanos_to_cycles(self, nanos: u64) -> u64 {
+        (nanos as u128 * self.hz as u128 / NANOS_PER_SEC) as u64
+    }
+
+    /// Convert a number of cycles to a duration.
+    pub fn cycles_to_duration(self, cycles: u64) -> Duration {
+        Duration::from_nanos(self.cycles_to_nanos(cycles))
+    }
+
+    /// Convert a duration to a number of cycles.
+    pub fn duration_to_cycles(self, duration: Duration) -> u64 {
+        self.nanos_to_cycles(duration.as_nanos())
+    }
+}
+
+/// The architecture cycle counter, as exposed by [`Arch::cycle_counter`].
+pub struct ArchCycleCounter;
+
+impl ClockSource for ArchCycleCounter {
+    fn name(&self) -> &'static str {
+        "arch-cycles"
+    }
+
+    fn read(&self) -> u64 {
+        DefaultArch::cycle_counter().map_or(0, |(cycles, _)| cycles)
+    }
+
+    fn frequency_hint(&self) -> Option<u64> {
+        DefaultArch::cycle_counter().map(|(_, hz)| hz)
+    }
+
+    fn rating(&self) -> u32 {
+        300
+    }
+}
+
+/// Calibration state of one CPU, published with a sequence counter.
+struct CpuCalibration {
+    /// Odd while the CPU's state is being written
+    seq: AtomicU32,
+    /// Counter value at the last tick
+    base_raw: AtomicU64,
+    /// Reference time at the last tick (nanoseconds)
+    base_ns: AtomicU64,
+    /// Estimated counter frequency, or 0 before calibration
+    hz: AtomicU64,
+    /// Counter value at the start of the calibration window
+    window_raw: AtomicU64,
+    /// Reference time at the start of the calibration window (nanoseconds),
+    /// or `u64::MAX` if the CPU has not seen a tick yet
+    window_ns: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNCALIBRATED: CpuCalibration = CpuCalibration {
+    seq: AtomicU32::new(0),
+    base_raw: AtomicU64::new(0),
+    base_ns: AtomicU64::new(0),
+    hz: AtomicU64::new(0),
+    window_raw: AtomicU64::new(0),
+    window_ns: AtomicU64::new(u64::MAX),
+};
+
+impl CpuCalibration {
+    /// Run `f` with the state marked as being written.
+    ///
+    /// Returns `None` without running `f` if another writer holds it.
+    fn write<R>(&self, f: impl FnOnce() -> R) -> Option<R> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        if seq & 1 != 0
+            || self
+                .seq
+                .compare_exchange(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            return None;
+        }
+        let result = f();
+        self.seq.store(seq + 2, Ordering::Release);
+        Some(result)
+    }
+
+    /// Take a consistent `(base_raw, base_ns, hz)` snapshot.
+    fn read(&self) -> (u64, u64, u64) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                let snapshot = (
+                    self.base_raw.load(Ordering::Acquire),
+                    self.base_ns.load(Ordering::Acquire),
+                    self.hz.load(Ordering::Acquire),
+                );
+                if self.seq.load(Ordering::Acquire) == seq {
+                    return snapshot;
+                }
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn reset(&self) {
+        while self
+            .write(|| {
+                self.hz.store(0, Ordering::Release);
+                self.window_ns.store(u64::MAX, Ordering::Release);
+            })
+            .is_none()
+        {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Monotonic clock calibrated per CPU against the timer tick.
+pub struct MonotonicClock {
+    /// Active source, as a leaked box holding the trait object
+    source: AtomicPtr<&'static dyn ClockSource>,
+    /// Length of a calibration window (nanoseconds)
+    window_ns: u64,
+    /// Latest time returned on any CPU
+    last: AtomicU64,
+    cpus: [CpuCalibration; MAX_CPUS],
+}
+
+impl MonotonicClock {
+    /// Create a clock with no source that recalibrates every `window_ns`
+    /// nanoseconds.
+    pub const fn new(window_ns: u64) -> Self {
+        Self {
+            source: AtomicPtr::new(core::ptr::null_mut()),
+            window_ns,
+            last: AtomicU64::new(0),
+            cpus: [UNCALIBRATED; MAX_CPUS],
+        }
+    }
+
+    /// Register `source`, switching to it if it is rated above the active one.
+    ///
+    /// Returns whether `source` became active. Switching sources discards
+    /// the calibration of every CPU.
+    pub fn register(&self, source: &'static dyn ClockSource) -> bool {
+        if self.source().map_or(false, |active| active.rating() >= source.rating()) {
+            return false;
+        }
+
+        // The previous box is leaked on purpose: readers on other CPUs may
+        // still hold the old source
+        let boxed = Box::into_raw(Box::new(source));
+        self.source.store(boxed, Ordering::Release);
+        for cpu in &self.cpus {
+            cpu.reset();
+        }
+        true
+    }
+
+    /// Get the active source.
+    pub fn source(&self) -> Option<&'static dyn ClockSource> {
+        let ptr = self.source.load(Ordering::Acquire);
+        // Safety: non-null pointers come from a leaked box in `register`
+        unsafe { ptr.as_ref().copied() }
+    }
+
+    /// Get this CPU's current frequency estimate.
+    pub fn scale(&self) -> Option<ClockScale> {
+        let _preempt = PreemptGuard::enter();
+        ClockScale::new(self.cpus[cpu_id()].read().2)
+    }
+
+    /// Check whether this CPU has calibrated the active source.
+    pub fn is_calibrated(&self) -> bool {
+        self.scale().is_some()
+    }
+
+    /// Calibrate this CPU against `reference`, the current tick time.
+    ///
+    /// Called on every CPU from its timer interrupt. The frequency
+    /// hint, if any, is used until the first window has been measured;
+    /// later windows are averaged in to follow drift.
+    pub fn on_tick(&self, reference: Instant) {
+        let Some(source) = self.source() else {
+            return;
+        };
+        let _preempt = PreemptGuard::enter();
+        let cpu = &self.cpus[cpu_id()];
+        let raw = source.read();
+        let now_ns = reference.as_nanos();
+
+        cpu.write(|| {
+            let window_ns = cpu.window_ns.load(Ordering::Acquire);
+            if window_ns == u64::MAX {
+                cpu.hz.store(source.frequency_hint().unwrap_or(0), Ordering::Release);
+                cpu.window_raw.store(raw, Ordering::Release);
+                cpu.window_ns.store(now_ns, Ordering::Release);
+            } else {
+                let elapsed = now_ns.saturating_sub(window_ns);
+                if elapsed >= self.window_ns && elapsed > 0 {
+                    let cycles = raw.wrapping_sub(cpu.window_raw.load(Ordering::Acquire));
+                    let measured = (cycles as u128 * NANOS_PER_SEC / elapsed as u128) as u64;
+                    let hz = match cpu.hz.load(Ordering::Acquire) {
+                        0 => measured,
+                        previous => (previous * 3 + measured) / 4,
+                    };
+                    cpu.hz.store(hz, Ordering::Release);
+                    cpu.window_raw.store(raw, Ordering::Release);
+                    cpu.window_ns.store(now_ns, Ordering::Release);
+                }
+            }
+
+            // Convert relative to this tick from now on
+            cpu.base_raw.store(raw, Ordering::Release);
+            cpu.base_ns.store(now_ns, Ordering::Release);
+        });
+    }
+
+    /// Read the clock.
+    ///
+    /// Returns `None` until a source is registered and this CPU has
+    /// calibrated it.
+    pub fn now(&self) -> Option<Instant> {
+        let source = self.source()?;
+        let _preempt = PreemptGuard::enter();
+        let (base_raw, base_ns, hz) = self.cpus[cpu_id()].read();
+        let scale = ClockScale::new(hz)?;
+
+        let elapsed = scale.cycles_to_nanos(source.read().wrapping_sub(base_raw));
+        let nanos = base_ns.saturating_add(elapsed);
+
+        // Never go behind a value already handed out on another CPU
+        let previous = self.last.fetch_max(nanos, Ordering::AcqRel);
+        Some(Instant::from_nanos(nanos.max(previous)))
+    }
+}
+
+/// Global monotonic clock, recalibrated every 100ms.
+pub static CLOCK: MonotonicClock = MonotonicClock::new(100_000_000);
+
+/// Register a clock source with the global clock.
+///
+/// Returns whether the source became active.
+pub fn register_clock_source(source: &'static dyn ClockSource) -> bool {
+    CLOCK.register(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeCounter(AtomicU64);
+
+    impl ClockSource for FakeCounter {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn read(&self) -> u64 {
+            self.0.load(Ordering::Acquire)
+        }
+
+        fn rating(&self) -> u32 {
+            100
+        }
+    }
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_calibration_and_drift() {
+        static COUNTER: FakeCounter = FakeCounter(AtomicU64::new(0));
+        let clock = MonotonicClock::new(MS);
+
+        assert!(clock.now().is_none());
+        assert!(clock.register(&COUNTER));
+        assert!(!clock.register(&COUNTER));
+
+        // No hint: uncalibrated until a full window has been measured
+        clock.on_tick(Instant::from_nanos(0));
+        assert!(clock.now().is_none());
+
+        // 2 GHz
+        COUNTER.0.store(2_000_000, Ordering::Release);
+        clock.on_tick(Instant::from_nanos(MS));
+        assert_eq!(clock.scale().unwrap().frequency(), 2_000_000_000);
+
+        COUNTER.0.store(3_000_000, Ordering::Release);
+        assert_eq!(clock.now(), Some(Instant::from_nanos(MS + MS / 2)));
+
+        // The counter speeds up to 2.2 GHz; the estimate moves towards it
+        COUNTER.0.store(4_200_000, Ordering::Release);
+        clock.on_tick(Instant::from_nanos(2 * MS));
+        assert_eq!(clock.scale().unwrap().frequency(), 2_050_000_000);
+
+        // Conversion restarts from the latest tick
+        assert_eq!(clock.now(), Some(Instant::from_nanos(2 * MS)));
+
+        let scale = ClockScale::new(2_000_000_000).unwrap();
+        assert_eq!(scale.cycles_to_nanos(3_000), 1_500);
+        assert_eq!(scale.nanos_to_cycles(1_500), 3_000);
+        assert!(ClockScale::new(0).is_none());
+    }
+}
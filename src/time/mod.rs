@@ -3,20 +3,50 @@
 //! This module provides timer interrupt handling, time slice accounting,
 //! and preemption support for the threading system.
 
+pub mod clock;
+pub mod periodic;
+pub mod rate;
 pub mod tick;
 pub mod timer;
+pub mod vdso;
+pub mod wall;
+pub mod wheel;
 
 #[cfg(feature = "x86_64")]
 pub mod x86_64_timer;
 
+pub use clock::{register_clock_source, ClockScale, ClockSource, MonotonicClock, CLOCK};
+pub use periodic::{OverrunPolicy, PeriodicPool, PeriodicStats, PeriodicTask};
+pub use rate::RateLimiter;
 pub use tick::{TickCounter, TimeSlice};
-pub use timer::{Timer, TimerConfig, TimerError, PreemptGuard, IrqGuard};
+pub use vdso::{data_page, VdsoData, VDSO_DATA};
+#[cfg(feature = "vdso")]
+pub use vdso::Vdso;
+pub use wheel::{Timeout, TimerWheel, TIMER_WHEEL};
+pub use wall::{register_rtc, set_time, RtcDriver, SystemTime, WallClock, UNIX_EPOCH, WALL_CLOCK};
+pub use timer::{Timer, TimerConfig, TimerError, PreemptGuard, IrqGuard, preempt_count, in_interrupt, irq_enter, irq_exit};
 
 /// Get monotonic time - alias for Instant::now() for compatibility
 pub fn get_monotonic_time() -> Instant {
     Instant::now()
 }
 
+/// Clock used for per-thread CPU time accounting.
+///
+/// Reads the architecture cycle counter when its frequency is known and
+/// falls back to the tick counter otherwise, so it advances on every
+/// platform that takes timer interrupts.
+pub fn cpu_clock() -> Instant {
+    use crate::arch::{Arch, DefaultArch};
+    
+    match DefaultArch::cycle_counter() {
+        Some((cycles, hz)) => {
+            Instant::from_nanos((cycles as u128 * 1_000_000_000 / hz as u128) as u64)
+        }
+        None => tick::GLOBAL_TICK_COUNTER.now(),
+    }
+}
+
 /// Nanoseconds since some arbitrary epoch.
 ///
 /// This is used for high-resolution timing and scheduling decisions.
@@ -42,14 +72,24 @@ impl Instant {
     
     /// Get the current instant.
     ///
-    /// This reads the current time from the architecture-specific timer.
+    /// This reads the registered [`ClockSource`] once it is calibrated,
+    /// and the architecture-specific timer before that.
     pub fn now() -> Self {
+        if let Some(now) = CLOCK.now() {
+            return now;
+        }
+        
         #[cfg(feature = "x86_64")]
         {
             x86_64_timer::read_tsc()
         }
         
-        #[cfg(not(feature = "x86_64"))]
+        #[cfg(all(feature = "sim", not(feature = "x86_64")))]
+        {
+            crate::arch::sim::VIRTUAL_CLOCK.now()
+        }
+        
+        #[cfg(not(any(feature = "x86_64", feature = "sim")))]
         {
             // Fallback for other architectures
             Self(0)
@@ -0,0 +1,438 @@
+//! Periodic tasks.
+//!
+//! A [`PeriodicTask`] runs a closure once every period, either on a thread
+//! of its own ([`PeriodicTask::spawn`]) or on a [`PeriodicPool`] thread
+//! shared with other tasks. Releases are scheduled against absolute times
+//! (`start + n * period`), so the phase doesn't drift with execution time.
+//!
+//! When a run starts after the next release has already passed, the
+//! task's [`OverrunPolicy`] decides what happens to the missed releases.
+//! Each task keeps [`PeriodicStats`] on its release jitter, overruns and
+//! skipped releases.
+//!
+//! ```ignore
+//! let task = PeriodicTask::new(Duration::from_millis(10), OverrunPolicy::Skip, || {
+//!     sample_sensors();
+//! });
+//! task.spawn(&kernel, StageConfig::new("sensors").priority(220))?;
+//!
+//! // Later
+//! let stats = task.stats();
+//! task.cancel();
+//! ```
+
+use super::{Duration, Instant};
+use crate::errors::SpawnError;
+use crate::pipeline::{StageConfig, StageSpawner};
+use crate::thread_new::{self, Thread};
+extern crate alloc;
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// What to do with releases missed while a task was late.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrunPolicy {
+    /// Drop missed releases and run once for the latest one; later runs
+    /// stay on the original phase.
+    Skip,
+    /// Run once for every missed release, back to back, until the task is
+    /// back on schedule.
+    CatchUp,
+    /// Like [`CatchUp`](Self::CatchUp), but keep at most this many missed
+    /// releases; older ones are dropped.
+    Queue(u32),
+}
+
+/// Timing statistics of a periodic task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeriodicStats {
+    /// Number of runs
+    pub runs: u64,
+    /// Runs that were still going when the next release passed
+    pub overruns: u64,
+    /// Releases dropped by the overrun policy
+    pub skipped: u64,
+    /// Delay between release and start of the last run (nanoseconds)
+    pub last_jitter_ns: u64,
+    /// Largest release-to-start delay seen (nanoseconds)
+    pub max_jitter_ns: u64,
+    /// Mean release-to-start delay (nanoseconds)
+    pub mean_jitter_ns: u64,
+    /// Longest run (nanoseconds)
+    pub max_runtime_ns: u64,
+}
+
+/// State of one task, shared by its handle and the pool running it.
+struct TaskState {
+    period: u64,
+    policy: OverrunPolicy,
+    body: spin::Mutex<Box<dyn FnMut() + Send>>,
+    /// Time of the next release (nanoseconds)
+    next_release: AtomicU64,
+    cancelled: AtomicBool,
+    /// Pool the task was added to
+    pool: spin::Mutex<Weak<PoolState>>,
+    runs: AtomicU64,
+    overruns: AtomicU64,
+    skipped: AtomicU64,
+    last_jitter: AtomicU64,
+    max_jitter: AtomicU64,
+    total_jitter: AtomicU64,
+    max_runtime: AtomicU64,
+}
+
+impl TaskState {
+    /// Run the task if its release has passed at `now`.
+    ///
+    /// Returns the next release time.
+    fn run_due(&self, now: Instant, clock: fn() -> Instant) -> u64 {
+        let now = now.as_nanos();
+        let mut release = self.next_release.load(Ordering::Acquire);
+        if now < release {
+            return release;
+        }
+
+        // Releases after this one that have passed as well
+        let missed = (now - release) / self.period;
+        let dropped = match self.policy {
+            OverrunPolicy::Skip => missed,
+            OverrunPolicy::CatchUp => 0,
+            OverrunPolicy::Queue(limit) => missed.saturating_sub(limit as u64),
+        };
+        release += dropped * self.period;
+        let next = release + self.period;
+        self.next_release.store(next, Ordering::Release);
+
+        let jitter = now - release;
+        (self.body.lock())();
+        let end = clock().as_nanos().max(now);
+
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.skipped.fetch_add(dropped, Ordering::Relaxed);
+        if end > next {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.last_jitter.store(jitter, Ordering::Relaxed);
+        self.max_jitter.fetch_max(jitter, Ordering::Relaxed);
+        self.total_jitter.fetch_add(jitter, Ordering::Relaxed);
+        self.max_runtime.fetch_max(end - now, Ordering::Relaxed);
+        next
+    }
+}
+
+/// A closure run once every period.
+///
+/// The task does nothing until it is [spawned](Self::spawn) on its own
+/// thread or [added](PeriodicPool::add) to a pool. Dropping the handle
+/// doesn't stop it; use [`cancel`](Self::cancel).
+#[derive(Clone)]
+pub struct PeriodicTask {
+    state: Arc<TaskState>,
+}
+
+impl PeriodicTask {
+    /// Create a task running `body` every `period`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn new<F>(period: Duration, policy: OverrunPolicy, body: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        assert!(period.as_nanos() > 0, "period must not be zero");
+        Self {
+            state: Arc::new(TaskState {
+                period: period.as_nanos(),
+                policy,
+                body: spin::Mutex::new(Box::new(body)),
+                next_release: AtomicU64::new(0),
+                cancelled: AtomicBool::new(false),
+                pool: spin::Mutex::new(Weak::new()),
+                runs: AtomicU64::new(0),
+                overruns: AtomicU64::new(0),
+                skipped: AtomicU64::new(0),
+                last_jitter: AtomicU64::new(0),
+                max_jitter: AtomicU64::new(0),
+                total_jitter: AtomicU64::new(0),
+                max_runtime: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Run the task on a dedicated thread configured by `config`.
+    ///
+    /// The first release is one period from now. The thread exits once
+    /// the task is cancelled.
+    pub fn spawn(&self, spawner: &impl StageSpawner, config: StageConfig) -> Result<(), SpawnError> {
+        let pool = PeriodicPool::dedicated();
+        pool.add(self);
+        pool.spawn(spawner, config)
+    }
+
+    /// Get the period.
+    pub fn period(&self) -> Duration {
+        Duration::from_nanos(self.state.period)
+    }
+
+    /// Get the overrun policy.
+    pub fn policy(&self) -> OverrunPolicy {
+        self.state.policy
+    }
+
+    /// Get the time of the next release.
+    pub fn next_release(&self) -> Instant {
+        Instant::from_nanos(self.state.next_release.load(Ordering::Acquire))
+    }
+
+    /// Get the task's timing statistics.
+    pub fn stats(&self) -> PeriodicStats {
+        let state = &self.state;
+        let runs = state.runs.load(Ordering::Relaxed);
+        PeriodicStats {
+            runs,
+            overruns: state.overruns.load(Ordering::Relaxed),
+            skipped: state.skipped.load(Ordering::Relaxed),
+            last_jitter_ns: state.last_jitter.load(Ordering::Relaxed),
+            max_jitter_ns: state.max_jitter.load(Ordering::Relaxed),
+            mean_jitter_ns: state.total_jitter.load(Ordering::Relaxed).checked_div(runs).unwrap_or(0),
+            max_runtime_ns: state.max_runtime.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stop running the task.
+    ///
+    /// A run already in progress completes.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+        if let Some(pool) = self.state.pool.lock().upgrade() {
+            pool.wake();
+        }
+    }
+
+    /// Check whether the task was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// State shared by a pool's handles and its thread.
+struct PoolState {
+    tasks: spin::Mutex<Vec<Arc<TaskState>>>,
+    stopped: AtomicBool,
+    /// Stop once the last task is cancelled
+    dedicated: bool,
+    /// Thread running the pool
+    thread: spin::Mutex<Option<Thread>>,
+}
+
+impl PoolState {
+    fn wake(&self) {
+        if let Some(thread) = self.thread.lock().as_ref() {
+            thread.unpark();
+        }
+    }
+}
+
+/// A set of periodic tasks run by one thread.
+///
+/// Tasks run one at a time in release order, so a long run delays the
+/// others sharing the pool; give tasks with tight deadlines a thread of
+/// their own.
+#[derive(Clone)]
+pub struct PeriodicPool {
+    state: Arc<PoolState>,
+}
+
+impl PeriodicPool {
+    /// Create an empty pool.
+    pub fn new() -> Self {
+        Self::with_mode(false)
+    }
+
+    fn dedicated() -> Self {
+        Self::with_mode(true)
+    }
+
+    fn with_mode(dedicated: bool) -> Self {
+        Self {
+            state: Arc::new(PoolState {
+                tasks: spin::Mutex::new(Vec::new()),
+                stopped: AtomicBool::new(false),
+                dedicated,
+                thread: spin::Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Add `task`, with its first release one period from now.
+    pub fn add(&self, task: &PeriodicTask) {
+        self.add_at(task, Instant::now().add(task.period()));
+    }
+
+    /// Add `task`, with its first release at `first`.
+    pub fn add_at(&self, task: &PeriodicTask, first: Instant) {
+        task.state.next_release.store(first.as_nanos(), Ordering::Release);
+        *task.state.pool.lock() = Arc::downgrade(&self.state);
+        self.state.tasks.lock().push(task.state.clone());
+        self.state.wake();
+    }
+
+    /// Get the number of tasks in the pool.
+    pub fn len(&self) -> usize {
+        self.state.tasks.lock().len()
+    }
+
+    /// Check whether the pool has no tasks.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run every task whose release has passed at `now`, once.
+    ///
+    /// Cancelled tasks are removed. Returns the earliest upcoming release,
+    /// or `None` if the pool has no tasks left.
+    pub fn run_due(&self, now: Instant) -> Option<Instant> {
+        self.run_due_with(now, Instant::now)
+    }
+
+    fn run_due_with(&self, now: Instant, clock: fn() -> Instant) -> Option<Instant> {
+        // Run outside the lock, so tasks may add or cancel others
+        let tasks = {
+            let mut tasks = self.state.tasks.lock();
+            tasks.retain(|task| !task.cancelled.load(Ordering::Acquire));
+            tasks.clone()
+        };
+
+        tasks
+            .iter()
+            .filter(|task| !task.cancelled.load(Ordering::Acquire))
+            .map(|task| task.run_due(now, clock))
+            .min()
+            .map(Instant::from_nanos)
+    }
+
+    /// Run the pool's tasks on the calling thread until [`stop`](Self::stop)
+    /// is called.
+    ///
+    /// The thread parks between releases.
+    pub fn run(&self) {
+        crate::might_sleep!();
+
+        *self.state.thread.lock() = thread_new::current();
+        while !self.state.stopped.load(Ordering::Acquire) {
+            let now = Instant::now();
+            match self.run_due(now) {
+                Some(next) if next > now => thread_new::park_timeout(next.duration_since(now)),
+                Some(_) => {} // Catching up
+                None if self.state.dedicated => break,
+                None => thread_new::park(),
+            }
+        }
+        *self.state.thread.lock() = None;
+    }
+
+    /// Run the pool on a new thread configured by `config`.
+    pub fn spawn(&self, spawner: &impl StageSpawner, config: StageConfig) -> Result<(), SpawnError> {
+        let pool = self.clone();
+        spawner.spawn_stage(&config, Box::new(move || pool.run()))
+    }
+
+    /// Make the thread running the pool return.
+    ///
+    /// Tasks stay in the pool and can be run again.
+    pub fn stop(&self) {
+        self.state.stopped.store(true, Ordering::Release);
+        self.state.wake();
+    }
+}
+
+impl Default for PeriodicPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use portable_atomic::AtomicUsize;
+
+    const MS: u64 = 1_000_000;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_nanos(ms * MS)
+    }
+
+    fn counting_task(policy: OverrunPolicy) -> (PeriodicTask, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let task = PeriodicTask::new(Duration::from_millis(10), policy, move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        (task, count)
+    }
+
+    #[test]
+    fn test_periodic_overrun_policies() {
+        let pool = PeriodicPool::new();
+        let (skip, skip_runs) = counting_task(OverrunPolicy::Skip);
+        let (catch_up, catch_up_runs) = counting_task(OverrunPolicy::CatchUp);
+        let (queue, queue_runs) = counting_task(OverrunPolicy::Queue(1));
+        for task in [&skip, &catch_up, &queue] {
+            pool.add_at(task, at(10));
+        }
+
+        // Nothing is due before the first release
+        assert_eq!(pool.run_due_with(at(5), || at(5)), Some(at(10)));
+        assert_eq!(skip_runs.load(Ordering::Relaxed), 0);
+
+        // On time, with 2ms of release jitter
+        assert_eq!(pool.run_due_with(at(12), || at(12)), Some(at(20)));
+        assert_eq!(skip.stats().last_jitter_ns, 2 * MS);
+
+        // 35ms late: releases at 20, 30, 40 and 50 have passed
+        pool.run_due_with(at(55), || at(55));
+        assert_eq!(skip.next_release(), at(60));
+        assert_eq!(skip.stats().skipped, 3);
+        assert_eq!(catch_up.next_release(), at(30));
+        assert_eq!(queue.next_release(), at(50));
+        assert_eq!(queue.stats().skipped, 2);
+
+        // Catching up runs the missed releases back to back
+        while catch_up.next_release() <= at(55) {
+            pool.run_due_with(at(55), || at(55));
+        }
+        assert_eq!(skip_runs.load(Ordering::Relaxed), 2);
+        assert_eq!(catch_up_runs.load(Ordering::Relaxed), 5);
+        assert_eq!(queue_runs.load(Ordering::Relaxed), 3);
+        assert_eq!(catch_up.stats().skipped, 0);
+        assert_eq!(catch_up.stats().max_jitter_ns, 35 * MS);
+
+        catch_up.cancel();
+        assert!(catch_up.is_cancelled());
+        pool.run_due_with(at(60), || at(60));
+        assert_eq!(pool.len(), 2);
+        assert_eq!(catch_up_runs.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn test_periodic_overrun_stats() {
+        let pool = PeriodicPool::new();
+        let (task, _) = counting_task(OverrunPolicy::Skip);
+        pool.add_at(&task, at(10));
+
+        // Runs for 15ms, past the next release
+        pool.run_due_with(at(10), || at(25));
+        pool.run_due_with(at(25), || at(26));
+        let stats = task.stats();
+        assert_eq!(stats.runs, 2);
+        assert_eq!(stats.overruns, 1);
+        assert_eq!(stats.max_runtime_ns, 15 * MS);
+        assert_eq!(stats.mean_jitter_ns, 5 * MS / 2);
+    }
+}
@@ -0,0 +1,243 @@
+//! Rate limiting.
+//!
+//! [`RateLimiter`] is a token bucket: tokens accrue at a fixed rate up to a
+//! burst size, and each operation takes as many tokens as it costs. With a
+//! burst of one it becomes a leaky bucket that spaces operations evenly.
+//!
+//! The bucket is kept as the time at which it will next be full (the
+//! generic cell rate algorithm), so refilling needs no periodic work and
+//! no fractional tokens are lost. Blocking acquires reserve their tokens
+//! up front and then sleep on the timer wheel until the reservation is due,
+//! so waiters are served in order and never spin on the bucket.
+//!
+//! ```ignore
+//! // 2 MB/s with bursts of up to 64 KB
+//! static UPLINK: RateLimiter = RateLimiter::new(2 * 1024 * 1024, 64 * 1024);
+//!
+//! UPLINK.acquire(packet.len() as u64);
+//! send(packet);
+//! ```
+
+use super::{Duration, Instant};
+use crate::sync::SpinLockIrqSave;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+struct Bucket {
+    /// Tokens per second (0 = unlimited)
+    rate: u64,
+    /// Largest number of tokens that can accumulate
+    burst: u64,
+    /// Time at which all tokens taken so far will have been replenished,
+    /// in nanoseconds times `rate`
+    full_at: u128,
+}
+
+impl Bucket {
+    /// Take `tokens` at `now`, if the bucket allows it by `now + slack`.
+    ///
+    /// Returns how long to wait for the tokens, or `Err` with the wait that
+    /// would be needed if it exceeds `slack`.
+    fn take(&mut self, tokens: u64, now: Instant, slack: Duration) -> Result<Duration, Duration> {
+        if self.rate == 0 {
+            return Ok(Duration::from_nanos(0));
+        }
+
+        let rate = self.rate as u128;
+        let now = now.as_nanos() as u128 * rate;
+        let start = self.full_at.max(now);
+        // Oversized requests go through once the bucket is full
+        let cost = tokens.min(self.burst) as u128 * NANOS_PER_SEC;
+        let tolerance = self.burst as u128 * NANOS_PER_SEC;
+
+        let ready = (start + cost).saturating_sub(tolerance);
+        let wait = Duration::from_nanos(((ready.saturating_sub(now) + rate - 1) / rate) as u64);
+        if wait > slack {
+            return Err(wait);
+        }
+
+        self.full_at = start + tokens as u128 * NANOS_PER_SEC;
+        Ok(wait)
+    }
+
+    /// Tokens that could be taken at `now` without waiting.
+    fn available(&self, now: Instant) -> u64 {
+        if self.rate == 0 {
+            return u64::MAX;
+        }
+
+        let now = now.as_nanos() as u128 * self.rate as u128;
+        let owed = (self.full_at.saturating_sub(now) + NANOS_PER_SEC - 1) / NANOS_PER_SEC;
+        self.burst.saturating_sub(owed.min(u64::MAX as u128) as u64)
+    }
+}
+
+/// Token bucket limiting how fast a resource is used.
+pub struct RateLimiter {
+    bucket: SpinLockIrqSave<Bucket>,
+}
+
+impl RateLimiter {
+    /// Create a limiter refilling `rate` tokens per second, holding at most
+    /// `burst` tokens. It starts full.
+    ///
+    /// A rate of zero doesn't limit anything.
+    pub const fn new(rate: u64, burst: u64) -> Self {
+        Self {
+            bucket: SpinLockIrqSave::new(Bucket {
+                rate,
+                burst: if burst == 0 { 1 } else { burst },
+                full_at: 0,
+            }),
+        }
+    }
+
+    /// Create a leaky bucket letting `rate` tokens per second through,
+    /// evenly spaced.
+    pub const fn leaky_bucket(rate: u64) -> Self {
+        Self::new(rate, 1)
+    }
+
+    /// Get the refill rate in tokens per second.
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().rate
+    }
+
+    /// Get the bucket size.
+    pub fn burst(&self) -> u64 {
+        self.bucket.lock().burst
+    }
+
+    /// Change the rate and bucket size.
+    ///
+    /// Tokens already taken stay owed at the old rate's cost.
+    pub fn set_rate(&self, rate: u64, burst: u64) {
+        let mut bucket = self.bucket.lock();
+        if rate != bucket.rate {
+            // Rescale the owed time to the new unit
+            bucket.full_at = if bucket.rate == 0 {
+                0
+            } else {
+                bucket.full_at / bucket.rate as u128 * rate as u128
+            };
+        }
+        bucket.rate = rate;
+        bucket.burst = burst.max(1);
+    }
+
+    /// Get the number of tokens that can be taken right now.
+    pub fn available(&self) -> u64 {
+        self.bucket.lock().available(Instant::now())
+    }
+
+    /// Take `tokens` if they are available now.
+    ///
+    /// A request larger than the bucket succeeds once the bucket is full,
+    /// and later requests wait until the excess has been paid back.
+    pub fn try_acquire(&self, tokens: u64) -> bool {
+        self.try_acquire_at(tokens, Instant::now()).is_ok()
+    }
+
+    /// Take `tokens` if they are available at `now`.
+    ///
+    /// Otherwise returns how long until they will be.
+    pub fn try_acquire_at(&self, tokens: u64, now: Instant) -> Result<(), Duration> {
+        self.bucket
+            .lock()
+            .take(tokens, now, Duration::from_nanos(0))
+            .map(|_| ())
+    }
+
+    /// Take `tokens`, sleeping until they are available.
+    pub fn acquire(&self, tokens: u64) {
+        crate::might_sleep!();
+
+        let reserved = self.bucket.lock().take(tokens, Instant::now(), Duration::from_nanos(u64::MAX));
+        if let Ok(wait) = reserved {
+            sleep(wait);
+        }
+    }
+
+    /// Take `tokens`, sleeping until they are available, unless that takes
+    /// longer than `timeout`.
+    ///
+    /// Returns whether the tokens were taken; nothing is taken on timeout.
+    pub fn acquire_timeout(&self, tokens: u64, timeout: Duration) -> bool {
+        crate::might_sleep!();
+
+        match self.bucket.lock().take(tokens, Instant::now(), timeout) {
+            Ok(wait) => {
+                sleep(wait);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Reserve `tokens` at `now` regardless of availability.
+    ///
+    /// Returns how long the caller should wait before using them.
+    pub fn reserve_at(&self, tokens: u64, now: Instant) -> Duration {
+        self.bucket
+            .lock()
+            .take(tokens, now, Duration::from_nanos(u64::MAX))
+            .unwrap_or_else(|wait| wait)
+    }
+}
+
+/// Sleep for `duration` on the timer wheel.
+fn sleep(duration: Duration) {
+    if duration.as_nanos() == 0 {
+        return;
+    }
+
+    let deadline = super::TIMER_WHEEL.arm(duration);
+    while !deadline.expired() {
+        crate::thread_new::park_timeout(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_nanos(ms * MS)
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        // 100 tokens per second, bursts of 10
+        let limiter = RateLimiter::new(100, 10);
+        assert_eq!(limiter.bucket.lock().available(at(1000)), 10);
+
+        assert_eq!(limiter.try_acquire_at(10, at(1000)), Ok(()));
+        assert_eq!(limiter.try_acquire_at(1, at(1000)), Err(Duration::from_millis(10)));
+        assert_eq!(limiter.try_acquire_at(1, at(1010)), Ok(()));
+        assert_eq!(limiter.bucket.lock().available(at(1050)), 4);
+
+        // Oversized requests wait for a full bucket, then go into debt
+        assert_eq!(limiter.try_acquire_at(25, at(1050)), Err(Duration::from_millis(60)));
+        assert_eq!(limiter.try_acquire_at(25, at(1110)), Ok(()));
+        assert_eq!(limiter.try_acquire_at(1, at(1110)), Err(Duration::from_millis(160)));
+
+        // Reservations queue up behind each other
+        assert_eq!(limiter.reserve_at(1, at(1360)), Duration::from_nanos(0));
+        assert_eq!(limiter.reserve_at(10, at(1360)), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_leaky_bucket_and_unlimited() {
+        let limiter = RateLimiter::leaky_bucket(1000);
+        assert_eq!(limiter.try_acquire_at(1, at(5)), Ok(()));
+        assert_eq!(limiter.try_acquire_at(1, at(5)), Err(Duration::from_millis(1)));
+        assert_eq!(limiter.reserve_at(1, at(5)), Duration::from_millis(1));
+        assert_eq!(limiter.reserve_at(1, at(5)), Duration::from_millis(2));
+
+        limiter.set_rate(0, 0);
+        assert!(limiter.try_acquire(u64::MAX));
+        assert_eq!(limiter.available(), u64::MAX);
+    }
+}
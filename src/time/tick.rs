@@ -137,6 +137,14 @@ impl TimeSlice {
         elapsed >= quantum
     }
     
+    /// Get the time the current slice started, if one has.
+    pub fn slice_start(&self) -> Option<Instant> {
+        match self.slice_start.load(Ordering::Acquire) {
+            0 => None,
+            start => Some(Instant::from_nanos(start)),
+        }
+    }
+    
     /// Get current virtual runtime.
     pub fn vruntime(&self) -> u64 {
         self.vruntime.load(Ordering::Acquire)
@@ -162,6 +170,11 @@ impl TimeSlice {
         self.quantum.store(duration.as_nanos(), Ordering::Release);
     }
     
+    /// Get the length of the time slice.
+    pub fn quantum(&self) -> Duration {
+        Duration::from_nanos(self.quantum.load(Ordering::Acquire))
+    }
+    
     /// Get current priority.
     pub fn priority(&self) -> u8 {
         self.priority.load(Ordering::Acquire) as u8
@@ -1,7 +1,14 @@
 //! Timer configuration and interrupt management.
 
 use super::Duration;
-use crate::arch::Arch;
+use crate::arch::{Arch, DefaultArch};
+#[cfg(not(test))]
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::observability::critical;
+use crate::observability::metrics::GLOBAL_METRICS;
+use core::marker::PhantomData;
+use core::panic::Location;
+use portable_atomic::{AtomicUsize, Ordering};
 
 /// Timer configuration for preemptive scheduling.
 #[derive(Debug, Clone)]
@@ -84,9 +91,14 @@ pub enum TimerError {
 ///
 /// This allows critical sections that need to prevent preemption but still
 /// allow interrupt handling (e.g., for device drivers).
+///
+/// Guards nest: each one increments the current CPU's preempt count and
+/// preemption is only re-enabled once the outermost guard is dropped. The
+/// time from the outermost guard's creation to then is recorded by the
+/// [critical section profiler](crate::observability::critical).
 pub struct PreemptGuard {
-    /// Previous preemption state
-    was_enabled: bool,
+    /// The count is per-CPU, so the guard must stay on this CPU
+    _not_send: PhantomData<*const ()>,
 }
 
 impl PreemptGuard {
@@ -95,32 +107,42 @@ impl PreemptGuard {
     /// # Returns
     ///
     /// A guard that will re-enable preemption when dropped.
+    #[track_caller]
     pub fn enter() -> Self {
-        let was_enabled = disable_preemption();
-        Self { was_enabled }
+        preempt_disable(Location::caller());
+        Self { _not_send: PhantomData }
     }
     
     /// Check if preemption is currently disabled.
     pub fn is_disabled() -> bool {
         !is_preemption_enabled()
     }
+    
+    /// Current preemption-disable nesting depth on this CPU.
+    pub fn depth() -> usize {
+        preempt_count()
+    }
 }
 
 impl Drop for PreemptGuard {
     fn drop(&mut self) {
-        if self.was_enabled {
-            enable_preemption();
-        }
+        preempt_enable();
     }
 }
 
 /// Interrupt guard for disabling all interrupts.
 ///
 /// This provides a critical section where no interrupts can occur,
-/// used for the most critical kernel operations.
+/// used for the most critical kernel operations. Guards that disabled
+/// interrupts are timed by the
+/// [critical section profiler](crate::observability::critical).
 pub struct IrqGuard {
     /// Previous interrupt state  
     was_enabled: bool,
+    /// Where the guard was created
+    site: &'static Location<'static>,
+    /// Profiler start time, if interrupts were disabled and profiling is on
+    start: Option<u64>,
 }
 
 impl IrqGuard {
@@ -129,52 +151,155 @@ impl IrqGuard {
     /// # Returns
     ///
     /// A guard that will restore interrupt state when dropped.
+    #[track_caller]
     pub fn enter() -> Self {
         let was_enabled = crate::arch::DefaultArch::interrupts_enabled();
         crate::arch::DefaultArch::disable_interrupts();
-        Self { was_enabled }
+        Self {
+            was_enabled,
+            site: Location::caller(),
+            start: if was_enabled { critical::window_start() } else { None },
+        }
     }
 }
 
 impl Drop for IrqGuard {
     fn drop(&mut self) {
+        // Recorded before interrupts are back on, so no handler nests in
+        if let Some(start) = self.start {
+            critical::irq_on(self.site, start);
+        }
         if self.was_enabled {
             crate::arch::DefaultArch::enable_interrupts();
         }
     }
 }
 
-/// Per-CPU preemption state.
-///
-/// This tracks whether preemption is enabled for the current CPU.
-/// We use thread-local storage or per-CPU data structures for this.
-static mut PREEMPTION_ENABLED: bool = true;
+/// Per-CPU preemption-disable depth.
+#[cfg(not(test))]
+static PREEMPT_COUNT: [AtomicUsize; MAX_CPUS] = [ZERO; MAX_CPUS];
 
-/// Disable preemption on the current CPU.
-///
-/// # Returns
-///
-/// Previous preemption state.
-fn disable_preemption() -> bool {
-    // TODO: This should be per-CPU and atomic
-    // For now, use a simple global flag
-    unsafe {
-        let was_enabled = PREEMPTION_ENABLED;
-        PREEMPTION_ENABLED = false;
-        was_enabled
+/// Per-CPU interrupt handler nesting depth.
+#[cfg(not(test))]
+static IRQ_DEPTH: [AtomicUsize; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+#[cfg(not(test))]
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(test)]
+std::thread_local! {
+    /// Depths of each test thread, which all run on CPU 0, so tests can
+    /// hold guards without tripping each other's checks.
+    static TEST_PREEMPT_COUNT: AtomicUsize = const { AtomicUsize::new(0) };
+    static TEST_IRQ_DEPTH: AtomicUsize = const { AtomicUsize::new(0) };
+}
+
+/// Run `f` on the current CPU's preempt count.
+fn with_preempt_count<R>(f: impl FnOnce(&AtomicUsize) -> R) -> R {
+    #[cfg(test)]
+    return TEST_PREEMPT_COUNT.with(f);
+    #[cfg(not(test))]
+    f(&PREEMPT_COUNT[cpu_id()])
+}
+
+/// Run `f` on the current CPU's interrupt handler depth.
+fn with_irq_depth<R>(f: impl FnOnce(&AtomicUsize) -> R) -> R {
+    #[cfg(test)]
+    return TEST_IRQ_DEPTH.with(f);
+    #[cfg(not(test))]
+    f(&IRQ_DEPTH[cpu_id()])
+}
+
+/// Increment the current CPU's preempt count, timing the window from
+/// `site` if it was zero.
+fn preempt_disable(site: &'static Location<'static>) {
+    // A tick between reading the CPU and raising its count could migrate
+    // the thread, leaving the count raised on a CPU it no longer runs on
+    let was_enabled = DefaultArch::interrupts_enabled();
+    DefaultArch::disable_interrupts();
+    let depth = with_preempt_count(|count| count.fetch_add(1, Ordering::AcqRel)) + 1;
+    if was_enabled {
+        DefaultArch::enable_interrupts();
+    }
+    GLOBAL_METRICS.system_counters().record_preempt_disable(depth);
+    if depth == 1 {
+        critical::preempt_off(site);
     }
 }
 
-/// Enable preemption on the current CPU.
-fn enable_preemption() {
-    unsafe {
-        PREEMPTION_ENABLED = true;
+/// Decrement the current CPU's preempt count.
+fn preempt_enable() {
+    // Until the count drops the thread stays on this CPU, so reading the
+    // CPU needs no interrupts off here
+    let previous = with_preempt_count(|count| count.fetch_sub(1, Ordering::AcqRel));
+    debug_assert!(previous > 0, "unbalanced preempt_enable");
+    if previous == 1 {
+        critical::preempt_on();
     }
 }
 
+/// Get the current CPU's preemption-disable depth.
+pub fn preempt_count() -> usize {
+    with_preempt_count(|count| count.load(Ordering::Acquire))
+}
+
 /// Check if preemption is enabled on the current CPU.
 fn is_preemption_enabled() -> bool {
-    unsafe { PREEMPTION_ENABLED }
+    preempt_count() == 0
+}
+
+/// Mark entry into an interrupt handler on the current CPU.
+///
+/// Architecture interrupt entry code calls this before running any
+/// handler logic so that [`in_interrupt`] reports correctly.
+pub fn irq_enter() {
+    with_irq_depth(|depth| depth.fetch_add(1, Ordering::AcqRel));
+}
+
+/// Mark exit from an interrupt handler on the current CPU.
+pub fn irq_exit() {
+    let previous = with_irq_depth(|depth| depth.fetch_sub(1, Ordering::AcqRel));
+    debug_assert!(previous > 0, "unbalanced irq_exit");
+}
+
+/// Check whether the current CPU is executing an interrupt handler.
+pub fn in_interrupt() -> bool {
+    with_irq_depth(|depth| depth.load(Ordering::Acquire)) > 0
+}
+
+/// Check that the caller is allowed to block.
+///
+/// Called through [`might_sleep!`](crate::might_sleep). Panics if the
+/// current CPU has preemption disabled or is running an interrupt handler,
+/// since blocking there would either deadlock or stall the CPU.
+#[track_caller]
+pub fn might_sleep_check() {
+    let depth = preempt_count();
+    let irq = in_interrupt();
+    
+    if depth > 0 || irq {
+        GLOBAL_METRICS.system_counters().record_might_sleep_violation();
+        if irq {
+            panic!("blocking call from interrupt context");
+        }
+        panic!("blocking call with preemption disabled (depth {})", depth);
+    }
+}
+
+/// Assert that the current context is allowed to block.
+///
+/// Place at the top of any primitive that may block or yield. In debug
+/// builds this panics when called with preemption disabled or from an
+/// interrupt handler; in release builds it compiles to nothing.
+#[macro_export]
+macro_rules! might_sleep {
+    () => {
+        #[cfg(debug_assertions)]
+        {
+            $crate::time::timer::might_sleep_check();
+        }
+    };
 }
 
 /// Handle a timer interrupt for preemptive scheduling.
@@ -189,11 +314,29 @@ fn is_preemption_enabled() -> bool {
 /// The caller must ensure that the interrupt frame is properly saved
 /// and that this function doesn't corrupt the interrupted context.
 pub unsafe fn handle_timer_interrupt() {
+    irq_enter();
+    
     // Increment global tick counter
     super::tick::GLOBAL_TICK_COUNTER.increment();
     
+    // Keep this CPU's view of the clock source in step with the tick
+    super::clock::CLOCK.on_tick(super::tick::GLOBAL_TICK_COUNTER.now());
+    
+    // Publish the tick and calibration to user mode
+    super::vdso::VDSO_DATA.on_tick(super::tick::GLOBAL_TICK_COUNTER.now());
+    
+    // Hold back timeouts and preemption during boot phases
+    if crate::kernel::defer_tick() {
+        irq_exit();
+        return;
+    }
+    
+    // Expire blocking-operation timeouts
+    super::wheel::TIMER_WHEEL.advance_to(super::tick::GLOBAL_TICK_COUNTER.ticks());
+    
     // Only preempt if preemption is enabled
     if !is_preemption_enabled() {
+        irq_exit();
         return;
     }
     
@@ -202,6 +345,8 @@ pub unsafe fn handle_timer_interrupt() {
     
     // For now, just a placeholder
     schedule_if_needed();
+    
+    irq_exit();
 }
 
 /// Check if scheduling is needed and trigger it.
@@ -236,12 +381,89 @@ mod tests {
         {
             let _guard = PreemptGuard::enter();
             assert!(PreemptGuard::is_disabled());
+            
+            {
+                let _nested = PreemptGuard::enter();
+                assert_eq!(PreemptGuard::depth(), 2);
+            }
+            
+            // Inner guard must not re-enable preemption
+            assert!(PreemptGuard::is_disabled());
+            assert_eq!(PreemptGuard::depth(), 1);
         } // Guard dropped here
         
         // Should be re-enabled after guard drop
         assert!(!PreemptGuard::is_disabled());
     }
     
+    fn system_counter(counter: &portable_atomic::AtomicU64) -> u64 {
+        counter.load(Ordering::Acquire)
+    }
+
+    #[test]
+    fn test_preempt_disable_counters() {
+        let counters = GLOBAL_METRICS.system_counters();
+        let disables = system_counter(&counters.preempt_disables);
+
+        {
+            let _guard = PreemptGuard::enter();
+            let _nested = PreemptGuard::enter();
+            assert_eq!(preempt_count(), 2);
+        }
+        assert_eq!(preempt_count(), 0);
+
+        // Only the outermost guard counts as a disable; the depth is a
+        // high-water mark
+        assert!(system_counter(&counters.preempt_disables) > disables);
+        assert!(system_counter(&counters.max_preempt_depth) >= 2);
+    }
+
+    #[test]
+    fn test_preempt_disable_restores_interrupts() {
+        drop(PreemptGuard::enter());
+        assert!(DefaultArch::interrupts_enabled());
+
+        DefaultArch::disable_interrupts();
+        drop(PreemptGuard::enter());
+        let enabled = DefaultArch::interrupts_enabled();
+        DefaultArch::enable_interrupts();
+        assert!(!enabled);
+    }
+
+    #[test]
+    fn test_might_sleep_allowed() {
+        crate::might_sleep!();
+    }
+
+    #[test]
+    #[should_panic(expected = "blocking call with preemption disabled (depth 1)")]
+    fn test_might_sleep_with_preemption_disabled() {
+        let _guard = PreemptGuard::enter();
+        crate::might_sleep!();
+    }
+
+    #[test]
+    #[should_panic(expected = "blocking call from interrupt context")]
+    fn test_might_sleep_in_interrupt() {
+        irq_enter();
+        crate::might_sleep!();
+    }
+
+    #[test]
+    fn test_might_sleep_violation_counted() {
+        let counters = GLOBAL_METRICS.system_counters();
+        let violations = system_counter(&counters.might_sleep_violations);
+
+        let result = std::panic::catch_unwind(|| {
+            let _guard = PreemptGuard::enter();
+            crate::might_sleep!();
+        });
+
+        assert!(result.is_err());
+        assert_eq!(preempt_count(), 0);
+        assert!(system_counter(&counters.might_sleep_violations) > violations);
+    }
+
     #[test]
     fn test_timer_error_types() {
         let error = TimerError::NotInitialized;
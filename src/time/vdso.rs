@@ -0,0 +1,289 @@
+//! Data page for reading time and thread ids from user mode.
+//!
+//! [`VDSO_DATA`] is one page-aligned page the kernel keeps up to date: the
+//! timer tick and tick frequency, and for each CPU the cycle counter
+//! calibration and the thread running there. Platforms map it read-only
+//! into user address spaces (see [`data_page`]); user threads then read the
+//! time, their CPU and their thread id with plain loads and an unprivileged
+//! counter read, without a syscall.
+//!
+//! The kernel side is updated from [`handle_timer_interrupt`] and on every
+//! context switch. The reader, [`Vdso`], is built with the `vdso` feature
+//! and only relies on the page and on [`Arch::user_counter`] and
+//! [`Arch::user_cpu_id`], so it can be linked into user programs.
+//!
+//! Per-CPU entries are published with a sequence counter: odd while the
+//! owning CPU updates them, bumped again when done. Readers retry until
+//! they see the same even value before and after.
+//!
+//! [`handle_timer_interrupt`]: super::timer::handle_timer_interrupt
+//! [`Arch::user_counter`]: crate::arch::Arch::user_counter
+//! [`Arch::user_cpu_id`]: crate::arch::Arch::user_cpu_id
+
+use super::Instant;
+use crate::arch::percpu::{cpu_id, MAX_CPUS};
+use crate::arch::{Arch, DefaultArch};
+use crate::thread_new::ThreadId;
+use portable_atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Size of the data page.
+pub const VDSO_PAGE_SIZE: usize = 4096;
+
+/// Layout version; bumped whenever [`VdsoData`] changes.
+pub const VDSO_VERSION: u32 = 1;
+
+/// Calibration and current thread of one CPU.
+#[repr(C)]
+struct VdsoCpu {
+    /// Odd while the CPU updates this entry
+    seq: AtomicU32,
+    /// Whether the CPU id was published to user mode
+    published: AtomicU32,
+    /// User-readable counter value at the last tick
+    base_raw: AtomicU64,
+    /// Time of the last tick (nanoseconds)
+    base_ns: AtomicU64,
+    /// Counter frequency in Hz, or 0 if the counter can't be used
+    hz: AtomicU64,
+    /// Id of the thread running on the CPU (0 = none)
+    thread: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IDLE_CPU: VdsoCpu = VdsoCpu {
+    seq: AtomicU32::new(0),
+    published: AtomicU32::new(0),
+    base_raw: AtomicU64::new(0),
+    base_ns: AtomicU64::new(0),
+    hz: AtomicU64::new(0),
+    thread: AtomicU64::new(0),
+};
+
+impl VdsoCpu {
+    /// Update the entry; only called by the CPU that owns it.
+    fn write(&self, f: impl FnOnce()) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        portable_atomic::fence(Ordering::Release);
+        f();
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Take a consistent `(base_raw, base_ns, hz)` snapshot.
+    #[cfg_attr(not(any(test, feature = "vdso")), allow(dead_code))]
+    fn read(&self) -> (u64, u64, u64) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                let snapshot = (
+                    self.base_raw.load(Ordering::Acquire),
+                    self.base_ns.load(Ordering::Acquire),
+                    self.hz.load(Ordering::Acquire),
+                );
+                portable_atomic::fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return snapshot;
+                }
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// The shared data page.
+#[repr(C, align(4096))]
+pub struct VdsoData {
+    /// [`VDSO_VERSION`] of the kernel that fills the page
+    version: AtomicU32,
+    /// Timer tick frequency in Hz
+    tick_hz: AtomicU32,
+    /// Timer ticks since boot
+    ticks: AtomicU64,
+    /// Time of the last tick on any CPU (nanoseconds)
+    tick_ns: AtomicU64,
+    cpus: [VdsoCpu; MAX_CPUS],
+}
+
+const _: () = assert!(core::mem::size_of::<VdsoData>() == VDSO_PAGE_SIZE);
+
+impl VdsoData {
+    /// Create an empty page.
+    pub const fn new() -> Self {
+        Self {
+            version: AtomicU32::new(VDSO_VERSION),
+            tick_hz: AtomicU32::new(0),
+            ticks: AtomicU64::new(0),
+            tick_ns: AtomicU64::new(0),
+            cpus: [IDLE_CPU; MAX_CPUS],
+        }
+    }
+
+    /// Publish the tick and this CPU's counter calibration.
+    ///
+    /// Called on every CPU from its timer interrupt, with the tick time.
+    /// The first call on a CPU also publishes its id to user mode.
+    pub fn on_tick(&self, reference: Instant) {
+        let counter = &super::tick::GLOBAL_TICK_COUNTER;
+        self.tick_hz.store(counter.frequency(), Ordering::Relaxed);
+        self.ticks.store(counter.ticks(), Ordering::Release);
+        self.tick_ns.fetch_max(reference.as_nanos(), Ordering::AcqRel);
+
+        let cpu_index = cpu_id();
+        let cpu = &self.cpus[cpu_index];
+        if cpu.published.swap(1, Ordering::Relaxed) == 0 {
+            // Safety: runs in the timer interrupt of CPU `cpu_index`
+            unsafe { DefaultArch::publish_cpu_id(cpu_index) };
+        }
+
+        let calibration = DefaultArch::user_counter().zip(DefaultArch::cycle_counter());
+        cpu.write(|| match calibration {
+            Some((raw, (_, hz))) => {
+                cpu.base_raw.store(raw, Ordering::Relaxed);
+                cpu.base_ns.store(reference.as_nanos(), Ordering::Relaxed);
+                cpu.hz.store(hz, Ordering::Relaxed);
+            }
+            None => cpu.hz.store(0, Ordering::Relaxed),
+        });
+    }
+
+    /// Record the thread now running on `cpu`.
+    pub fn set_current_thread(&self, cpu: usize, thread: Option<ThreadId>) {
+        if let Some(entry) = self.cpus.get(cpu) {
+            entry.thread.store(thread.map_or(0, ThreadId::as_u64), Ordering::Release);
+        }
+    }
+}
+
+impl Default for VdsoData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Data page maintained by this kernel.
+pub static VDSO_DATA: VdsoData = VdsoData::new();
+
+/// Get the address of [`VDSO_DATA`], to map read-only into user space.
+pub fn data_page() -> usize {
+    &VDSO_DATA as *const VdsoData as usize
+}
+
+/// Reader for a mapped data page.
+#[cfg(feature = "vdso")]
+#[derive(Clone, Copy)]
+pub struct Vdso {
+    data: &'static VdsoData,
+}
+
+#[cfg(feature = "vdso")]
+impl Vdso {
+    /// Reader for the page mapped at `addr`.
+    ///
+    /// Returns `None` if the page was filled by a kernel with a different
+    /// layout version.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be the address the data page is mapped at, and the
+    /// mapping must stay for the rest of the program.
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        // Safety: forwarded from the caller
+        let data = unsafe { &*(addr as *const VdsoData) };
+        Self::new(data)
+    }
+
+    /// Reader for `data`, if its layout version matches.
+    pub fn new(data: &'static VdsoData) -> Option<Self> {
+        (data.version.load(Ordering::Relaxed) == VDSO_VERSION).then_some(Self { data })
+    }
+
+    /// Reader for the kernel's own page, for use without a mapping.
+    pub fn kernel() -> Self {
+        Self { data: &VDSO_DATA }
+    }
+
+    /// Get the number of timer ticks since boot.
+    pub fn ticks(&self) -> u64 {
+        self.data.ticks.load(Ordering::Acquire)
+    }
+
+    /// Get the timer tick frequency in Hz.
+    pub fn tick_frequency(&self) -> u32 {
+        self.data.tick_hz.load(Ordering::Relaxed)
+    }
+
+    /// Get the CPU the caller is running on.
+    ///
+    /// The answer may be stale as soon as it is returned.
+    pub fn cpu(&self) -> Option<usize> {
+        DefaultArch::user_cpu_id().filter(|&cpu| cpu < MAX_CPUS)
+    }
+
+    /// Get the id of the calling thread.
+    pub fn thread_id(&self) -> Option<ThreadId> {
+        loop {
+            let cpu = self.cpu()?;
+            let id = self.data.cpus[cpu].thread.load(Ordering::Acquire);
+            // Migrated in between: the entry may belong to another thread
+            if self.cpu() == Some(cpu) {
+                return (id != 0).then(|| ThreadId::new(id));
+            }
+        }
+    }
+
+    /// Read the monotonic clock.
+    ///
+    /// Uses the cycle counter calibrated at the last tick on the caller's
+    /// CPU. Falls back to the time of the last tick, at tick resolution,
+    /// if the CPU or the counter can't be read from user mode.
+    pub fn now(&self) -> Instant {
+        let tick_ns = self.data.tick_ns.load(Ordering::Acquire);
+        loop {
+            let Some(cpu) = self.cpu() else {
+                return Instant::from_nanos(tick_ns);
+            };
+            let (base_raw, base_ns, hz) = self.data.cpus[cpu].read();
+            let Some(raw) = DefaultArch::user_counter().filter(|_| hz != 0) else {
+                return Instant::from_nanos(tick_ns);
+            };
+            if self.cpu() != Some(cpu) {
+                continue;
+            }
+
+            let elapsed = (raw.wrapping_sub(base_raw) as u128 * 1_000_000_000 / hz as u128) as u64;
+            return Instant::from_nanos(base_ns.saturating_add(elapsed).max(tick_ns));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vdso_page_updates() {
+        let page = VdsoData::new();
+        page.on_tick(Instant::from_nanos(5_000_000));
+        assert_eq!(page.tick_ns.load(Ordering::Relaxed), 5_000_000);
+        assert_eq!(page.ticks.load(Ordering::Relaxed), super::super::tick::GLOBAL_TICK_COUNTER.ticks());
+
+        page.set_current_thread(3, Some(ThreadId::new(45_001)));
+        assert_eq!(page.cpus[3].thread.load(Ordering::Relaxed), 45_001);
+        page.set_current_thread(3, None);
+        assert_eq!(page.cpus[3].thread.load(Ordering::Relaxed), 0);
+        // Out-of-range CPUs are ignored
+        page.set_current_thread(MAX_CPUS, Some(ThreadId::new(45_002)));
+
+        // Each update leaves the sequence even and visible to readers
+        let cpu = &page.cpus[cpu_id()];
+        let seq = cpu.seq.load(Ordering::Relaxed);
+        assert_eq!(seq & 1, 0);
+        cpu.write(|| {
+            cpu.base_raw.store(1_000, Ordering::Relaxed);
+            cpu.base_ns.store(7_000_000, Ordering::Relaxed);
+            cpu.hz.store(1_000_000_000, Ordering::Relaxed);
+        });
+        assert_eq!(cpu.seq.load(Ordering::Relaxed), seq + 2);
+        assert_eq!(cpu.read(), (1_000, 7_000_000, 1_000_000_000));
+    }
+}
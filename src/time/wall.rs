@@ -0,0 +1,317 @@
+//! Wall-clock time.
+//!
+//! [`SystemTime`] is real-world time, counted in nanoseconds since the Unix
+//! epoch. It is kept as an offset over the monotonic clock, set either from
+//! a registered [`RtcDriver`] or by calling [`set_time`]. Corrections are
+//! smeared: rather than jumping, the offset moves linearly to its new value
+//! over a smear window, so timestamps taken around the correction stay in
+//! order.
+//!
+//! Until the time has been set, [`WallClock::now`] returns `None` and
+//! [`SystemTime::now`] counts from the epoch as if the system had booted
+//! at midnight on 1 January 1970.
+
+use super::{Duration, Instant};
+use crate::sync::SpinLockIrqSave;
+extern crate alloc;
+use alloc::boxed::Box;
+use core::fmt;
+use portable_atomic::{AtomicPtr, Ordering};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A point in real-world time, in nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(u64);
+
+/// 1970-01-01 00:00:00 UTC.
+pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+impl SystemTime {
+    /// Create a time from nanoseconds since the Unix epoch.
+    pub const fn from_unix_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    /// Create a time from seconds since the Unix epoch.
+    pub const fn from_unix_secs(secs: u64) -> Self {
+        Self(secs * NANOS_PER_SEC)
+    }
+
+    /// Get nanoseconds since the Unix epoch.
+    pub fn as_unix_nanos(self) -> u64 {
+        self.0
+    }
+
+    /// Get whole seconds since the Unix epoch.
+    pub fn as_unix_secs(self) -> u64 {
+        self.0 / NANOS_PER_SEC
+    }
+
+    /// Get the current wall-clock time.
+    ///
+    /// Counts from [`UNIX_EPOCH`] at boot if the time has not been set.
+    pub fn now() -> Self {
+        WALL_CLOCK
+            .now()
+            .unwrap_or_else(|| Self(Instant::now().as_nanos()))
+    }
+
+    /// Get the duration since `earlier`, or `None` if `earlier` is later.
+    pub fn duration_since(self, earlier: SystemTime) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    /// Add a duration, returning `None` on overflow.
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        self.0.checked_add(duration.as_nanos()).map(Self)
+    }
+}
+
+/// Formats as an RFC 3339 UTC timestamp, e.g. `2024-03-01T12:00:00.000000000Z`.
+impl fmt::Display for SystemTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.as_unix_secs();
+        let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+        // Civil date from days since the epoch (Howard Hinnant's algorithm)
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+            year,
+            month,
+            day,
+            secs_of_day / 3_600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            self.0 % NANOS_PER_SEC,
+        )
+    }
+}
+
+/// A battery-backed real-time clock.
+pub trait RtcDriver: Send + Sync {
+    /// Read the current time, or `None` if the RTC has not been set or
+    /// cannot be read.
+    fn read(&self) -> Option<SystemTime>;
+
+    /// Store `time` in the RTC. Returns whether the RTC was updated.
+    fn write(&self, _time: SystemTime) -> bool {
+        false
+    }
+}
+
+/// Offset of wall-clock time over the monotonic clock.
+struct Offset {
+    /// Offset when the current smear started (nanoseconds)
+    from: i128,
+    /// Offset once the smear completes (nanoseconds)
+    to: i128,
+    /// Monotonic time the smear started (nanoseconds)
+    start: u64,
+    /// Length of the smear (nanoseconds); 0 means the offset is `to`
+    length: u64,
+}
+
+impl Offset {
+    fn at(&self, mono: u64) -> i128 {
+        let elapsed = mono.saturating_sub(self.start);
+        if elapsed >= self.length {
+            self.to
+        } else {
+            self.from + (self.to - self.from) * elapsed as i128 / self.length as i128
+        }
+    }
+}
+
+struct WallState {
+    offset: Option<Offset>,
+    /// Window over which corrections are smeared (nanoseconds)
+    smear_ns: u64,
+}
+
+/// Wall-clock time kept as a smeared offset over [`Instant`].
+pub struct WallClock {
+    state: SpinLockIrqSave<WallState>,
+    /// Registered RTC, as a leaked box holding the trait object
+    rtc: AtomicPtr<&'static dyn RtcDriver>,
+}
+
+impl WallClock {
+    /// Create a clock whose time has not been set.
+    pub const fn new() -> Self {
+        Self {
+            state: SpinLockIrqSave::new(WallState {
+                offset: None,
+                smear_ns: DEFAULT_SMEAR_NS,
+            }),
+            rtc: AtomicPtr::new(core::ptr::null_mut()),
+        }
+    }
+
+    /// Set the window over which [`set_time`](Self::set_time) smears
+    /// corrections. A zero window makes every correction a step.
+    pub fn set_smear_window(&self, window: Duration) {
+        self.state.lock().smear_ns = window.as_nanos();
+    }
+
+    /// Get the current wall-clock time, or `None` if it has not been set.
+    pub fn now(&self) -> Option<SystemTime> {
+        self.now_at(Instant::now())
+    }
+
+    /// Check whether the time has been set.
+    pub fn is_set(&self) -> bool {
+        self.state.lock().offset.is_some()
+    }
+
+    /// Set the current time to `time`.
+    ///
+    /// The first time the clock is set it steps; later corrections are
+    /// smeared over the smear window. Backward corrections are smeared over
+    /// at least twice their size, so the clock slows down but never runs
+    /// backwards. A registered RTC is updated as well.
+    pub fn set_time(&self, time: SystemTime) {
+        self.set_time_at(time, Instant::now(), false);
+        if let Some(rtc) = self.rtc() {
+            rtc.write(time);
+        }
+    }
+
+    /// Set the current time to `time` immediately, without smearing.
+    pub fn step_time(&self, time: SystemTime) {
+        self.set_time_at(time, Instant::now(), true);
+        if let Some(rtc) = self.rtc() {
+            rtc.write(time);
+        }
+    }
+
+    /// Register `rtc` and step the clock to its time.
+    pub fn register_rtc(&self, rtc: &'static dyn RtcDriver) {
+        // Any previous box is leaked on purpose: other CPUs may still be
+        // using the old driver
+        self.rtc.store(Box::into_raw(Box::new(rtc)), Ordering::Release);
+        if let Some(time) = rtc.read() {
+            self.set_time_at(time, Instant::now(), true);
+        }
+    }
+
+    /// Get the registered RTC.
+    pub fn rtc(&self) -> Option<&'static dyn RtcDriver> {
+        // Safety: non-null pointers come from a leaked box in `register_rtc`
+        unsafe { self.rtc.load(Ordering::Acquire).as_ref().copied() }
+    }
+
+    /// Smear the clock towards the registered RTC's time.
+    ///
+    /// Returns whether the RTC could be read.
+    pub fn sync_rtc(&self) -> bool {
+        match self.rtc().and_then(|rtc| rtc.read()) {
+            Some(time) => {
+                self.set_time_at(time, Instant::now(), false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn now_at(&self, mono: Instant) -> Option<SystemTime> {
+        let state = self.state.lock();
+        let offset = state.offset.as_ref()?.at(mono.as_nanos());
+        let nanos = (mono.as_nanos() as i128 + offset).clamp(0, u64::MAX as i128);
+        Some(SystemTime(nanos as u64))
+    }
+
+    fn set_time_at(&self, time: SystemTime, mono: Instant, step: bool) {
+        let mono = mono.as_nanos();
+        let to = time.0 as i128 - mono as i128;
+        let mut state = self.state.lock();
+
+        let offset = match &state.offset {
+            Some(current) if !step && state.smear_ns > 0 => {
+                let from = current.at(mono);
+                let backwards = (from - to).max(0) as u64;
+                Offset {
+                    from,
+                    to,
+                    start: mono,
+                    length: state.smear_ns.max(backwards.saturating_mul(2)),
+                }
+            }
+            _ => Offset { from: to, to, start: mono, length: 0 },
+        };
+        state.offset = Some(offset);
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Default smear window: one second.
+const DEFAULT_SMEAR_NS: u64 = NANOS_PER_SEC;
+
+/// Global wall clock.
+pub static WALL_CLOCK: WallClock = WallClock::new();
+
+/// Set the global wall-clock time, smearing the correction.
+pub fn set_time(time: SystemTime) {
+    WALL_CLOCK.set_time(time);
+}
+
+/// Register the RTC that backs the global wall clock.
+pub fn register_rtc(rtc: &'static dyn RtcDriver) {
+    WALL_CLOCK.register_rtc(rtc);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    const SEC: u64 = NANOS_PER_SEC;
+
+    #[test]
+    fn test_set_time_smears() {
+        let clock = WallClock::new();
+        let mono = |secs: u64| Instant::from_nanos(secs * SEC);
+        let base = 1_700_000_000;
+
+        assert_eq!(clock.now_at(mono(1)), None);
+
+        // First set steps
+        clock.set_time_at(SystemTime::from_unix_secs(base), mono(10), false);
+        assert_eq!(clock.now_at(mono(11)), Some(SystemTime::from_unix_secs(base + 1)));
+
+        // Forward by 1s, smeared over the default 1s window
+        clock.set_time_at(SystemTime::from_unix_secs(base + 2), mono(11), false);
+        assert_eq!(
+            clock.now_at(Instant::from_nanos(11 * SEC + SEC / 2)),
+            Some(SystemTime::from_unix_nanos((base + 2) * SEC))
+        );
+        assert_eq!(clock.now_at(mono(12)), Some(SystemTime::from_unix_secs(base + 3)));
+
+        // Back by 2s: smeared over 4s, so time keeps moving forward
+        clock.set_time_at(SystemTime::from_unix_secs(base + 1), mono(12), false);
+        assert_eq!(clock.now_at(mono(14)), Some(SystemTime::from_unix_secs(base + 4)));
+        assert_eq!(clock.now_at(mono(16)), Some(SystemTime::from_unix_secs(base + 5)));
+
+        assert_eq!(
+            SystemTime::from_unix_nanos(1_709_294_400 * SEC + 5).to_string(),
+            "2024-03-01T12:00:00.000000005Z"
+        );
+        assert_eq!(UNIX_EPOCH.to_string(), "1970-01-01T00:00:00.000000000Z");
+    }
+}
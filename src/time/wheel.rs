@@ -0,0 +1,276 @@
+//! Timer wheel for blocking-operation timeouts.
+//!
+//! Timeouts are hashed into a ring of slots by the tick they expire on, so
+//! arming, cancelling and expiring a timeout are all cheap regardless of
+//! how many are pending. The wheel is advanced from the timer interrupt;
+//! waiters poll the [`Timeout`] they armed, and are unparked when it
+//! expires.
+//!
+//! A thread with [timer slack](crate::ThreadBuilder::timer_slack) lets its
+//! timeouts expire late: their deadlines are rounded up to a multiple of
+//! the largest power-of-two number of ticks within the slack, so the
+//! timeouts of many such threads expire on the same few ticks. Real-time
+//! threads always get exact deadlines, and when several timeouts expire on
+//! the same advance, those of real-time threads expire first, highest
+//! real-time priority first.
+
+use super::Duration;
+use crate::sync::SpinLockIrqSave;
+use crate::testing::faults::{self, Fault};
+use crate::thread_new::{self, Thread};
+extern crate alloc;
+use alloc::{sync::Arc, vec::Vec};
+use portable_atomic::{AtomicBool, Ordering};
+
+/// Number of slots in the wheel.
+const SLOTS: usize = 256;
+
+struct Entry {
+    id: u64,
+    deadline: u64,
+    fired: Arc<AtomicBool>,
+    /// Thread that armed the timeout, unparked when it expires
+    thread: Option<Thread>,
+    /// Real-time priority of that thread (0 = not real-time)
+    rt_priority: u8,
+}
+
+struct WheelState {
+    /// Last tick the wheel was advanced to
+    now: u64,
+    next_id: u64,
+    slots: [Vec<Entry>; SLOTS],
+}
+
+const EMPTY_SLOT: Vec<Entry> = Vec::new();
+
+/// Hashed timer wheel driven by the tick.
+pub struct TimerWheel {
+    /// Length of one tick (nanoseconds)
+    tick_ns: u64,
+    state: SpinLockIrqSave<WheelState>,
+}
+
+/// A pending timeout armed on a [`TimerWheel`].
+///
+/// Dropping the timeout cancels it.
+pub struct Timeout<'a> {
+    wheel: &'a TimerWheel,
+    id: u64,
+    deadline: u64,
+    fired: Arc<AtomicBool>,
+}
+
+impl<'a> Timeout<'a> {
+    /// Check whether the timeout has expired.
+    pub fn expired(&self) -> bool {
+        self.fired.load(Ordering::Acquire)
+    }
+
+    /// Get the tick the timeout expires on.
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+impl<'a> Drop for Timeout<'a> {
+    fn drop(&mut self) {
+        if !self.expired() {
+            self.wheel.cancel(self.id, self.deadline);
+        }
+    }
+}
+
+impl TimerWheel {
+    /// Create a wheel whose ticks are `tick_ns` nanoseconds long.
+    pub const fn new(tick_ns: u64) -> Self {
+        Self {
+            tick_ns,
+            state: SpinLockIrqSave::new(WheelState {
+                now: 0,
+                next_id: 0,
+                slots: [EMPTY_SLOT; SLOTS],
+            }),
+        }
+    }
+
+    /// Get the tick the wheel was last advanced to.
+    pub fn current_tick(&self) -> u64 {
+        self.state.lock().now
+    }
+
+    /// Arm a timeout that expires once `after` has elapsed.
+    ///
+    /// The timeout is rounded up to whole ticks, and further by the current
+    /// thread's timer slack. A zero timeout has already expired.
+    pub fn arm(&self, after: Duration) -> Timeout<'_> {
+        self.arm_for(after, thread_new::current())
+    }
+
+    /// [`arm`](Self::arm) on behalf of `thread`.
+    pub(crate) fn arm_for(&self, after: Duration, thread: Option<Thread>) -> Timeout<'_> {
+        let ticks = (after.as_nanos() + self.tick_ns - 1) / self.tick_ns;
+        let fired = Arc::new(AtomicBool::new(ticks == 0));
+        let rt_priority = thread.as_ref().map_or(0, Thread::realtime_priority);
+        let slack_ticks = match &thread {
+            Some(thread) if rt_priority == 0 => thread.timer_slack().as_nanos() / self.tick_ns,
+            _ => 0,
+        };
+
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        let mut deadline = state.now + ticks;
+        if slack_ticks > 1 {
+            // Round up to a multiple of the largest power of two within the slack
+            let grain = 1u64 << (63 - slack_ticks.leading_zeros());
+            deadline = (deadline + grain - 1) / grain * grain;
+        }
+
+        if ticks > 0 {
+            state.slots[deadline as usize % SLOTS].push(Entry {
+                id,
+                deadline,
+                fired: fired.clone(),
+                thread,
+                rt_priority,
+            });
+        }
+
+        Timeout {
+            wheel: self,
+            id,
+            deadline,
+            fired,
+        }
+    }
+
+    /// Advance the wheel to `tick`, expiring every timeout due by then.
+    ///
+    /// Returns the number of timeouts that expired. Called from the timer
+    /// interrupt with the global tick count.
+    pub fn advance_to(&self, tick: u64) -> usize {
+        let due = self.take_due(tick);
+        for entry in &due {
+            entry.fired.store(true, Ordering::Release);
+            if let Some(thread) = &entry.thread {
+                thread.unpark();
+            }
+        }
+        due.len()
+    }
+
+    /// Advance the wheel to `tick` and take the timeouts due by then, in
+    /// the order they expire: real-time ones first, by descending
+    /// real-time priority, then by deadline.
+    fn take_due(&self, tick: u64) -> Vec<Entry> {
+        let mut state = self.state.lock();
+        if tick <= state.now {
+            return Vec::new();
+        }
+        // A missed tick: its timeouts expire on the next advance
+        if faults::inject(Fault::TimerMiss) {
+            return Vec::new();
+        }
+
+        // Every slot that can hold a deadline in (now, tick]
+        let steps = (tick - state.now).min(SLOTS as u64);
+        let first = state.now + 1;
+        let mut due = Vec::new();
+
+        for step in 0..steps {
+            let slot = &mut state.slots[(first + step) as usize % SLOTS];
+            let mut index = 0;
+            while index < slot.len() {
+                if slot[index].deadline <= tick {
+                    due.push(slot.swap_remove(index));
+                } else {
+                    index += 1; // Expires on a later turn of the wheel
+                }
+            }
+        }
+
+        state.now = tick;
+        drop(state);
+
+        due.sort_by(|a, b| {
+            b.rt_priority.cmp(&a.rt_priority).then(a.deadline.cmp(&b.deadline)).then(a.id.cmp(&b.id))
+        });
+        due
+    }
+
+    /// Get the number of timeouts still pending.
+    pub fn pending(&self) -> usize {
+        self.state.lock().slots.iter().map(Vec::len).sum()
+    }
+
+    fn cancel(&self, id: u64, deadline: u64) {
+        self.state.lock().slots[deadline as usize % SLOTS].retain(|entry| entry.id != id);
+    }
+}
+
+/// Global timer wheel, advanced on every timer interrupt.
+pub static TIMER_WHEEL: TimerWheel = TimerWheel::new(1_000_000_000 / super::TIMER_FREQUENCY_HZ as u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: u64 = 1_000_000;
+
+    #[test]
+    fn test_timeouts_expire_and_cancel() {
+        let wheel = TimerWheel::new(TICK);
+
+        assert!(wheel.arm(Duration::from_nanos(0)).expired());
+
+        let short = wheel.arm(Duration::from_nanos(TICK / 2)); // Rounds up to 1 tick
+        let long = wheel.arm(Duration::from_nanos(300 * TICK)); // Wraps the wheel
+        let cancelled = wheel.arm(Duration::from_nanos(5 * TICK));
+        assert_eq!(wheel.pending(), 3);
+
+        drop(cancelled);
+        assert_eq!(wheel.pending(), 2);
+
+        assert_eq!(wheel.advance_to(1), 1);
+        assert!(short.expired());
+        assert!(!long.expired());
+
+        // A full turn later the long timeout is still not due
+        assert_eq!(wheel.advance_to(1 + SLOTS as u64), 0);
+        assert!(!long.expired());
+
+        assert_eq!(wheel.advance_to(1_000), 1);
+        assert!(long.expired());
+        assert_eq!(wheel.pending(), 0);
+    }
+
+    #[cfg(feature = "std-shim")]
+    #[test]
+    fn test_timer_slack_and_rt_expiry_order() {
+        use crate::mem::{StackPool, StackSizeClass};
+        use crate::thread_new::ThreadId;
+
+        let wheel = TimerWheel::new(TICK);
+        let pool = StackPool::new();
+        let thread = |id: usize| {
+            let stack = pool.allocate(StackSizeClass::Small).unwrap();
+            Thread::new(unsafe { ThreadId::new_unchecked(id) }, stack, || {}, 128).0
+        };
+        let lax = thread(49_290);
+        lax.set_timer_slack(Duration::from_nanos(5 * TICK));
+        let rt = thread(49_291);
+        rt.set_timer_slack(Duration::from_nanos(5 * TICK));
+        rt.set_realtime_priority(10).unwrap();
+
+        // Slack rounds the deadline up to a multiple of 4 ticks, and the
+        // real-time thread's slack is ignored
+        let slack = wheel.arm_for(Duration::from_nanos(2 * TICK), Some(lax.clone()));
+        let plain = wheel.arm_for(Duration::from_nanos(3 * TICK), None);
+        let exact = wheel.arm_for(Duration::from_nanos(4 * TICK), Some(rt));
+        assert_eq!((slack.deadline(), plain.deadline(), exact.deadline()), (4, 3, 4));
+
+        let order: Vec<u64> = wheel.take_due(4).iter().map(|entry| entry.id).collect();
+        assert_eq!(order, [exact.id, plain.id, slack.id]);
+    }
+}
@@ -323,6 +323,12 @@ impl Timer for ApicTimer {
     }
 }
 
+/// Calibrated TSC frequency in Hz, or 0 before the APIC timer is initialized.
+pub fn tsc_frequency() -> u64 {
+    // Safety: only the atomic field is read
+    unsafe { APIC_TIMER.tsc_frequency.load(Ordering::Acquire) }
+}
+
 /// Read the current TSC value and convert to an Instant.
 ///
 /// This provides high-resolution timing for the scheduler and other
@@ -8,8 +8,8 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::println;
 
 use preemptive_threads::{
-    ThreadBuilder, ThreadId, JoinHandle,
-    StackPool, StackSizeClass,
+    DefaultArch, Kernel, RoundRobinScheduler, ThreadBuilder, JoinHandle,
+    StackSizeClass,
     yield_now, yield_thread,
     Mutex, MutexGuard,
     Duration, Instant,
@@ -17,11 +17,17 @@ use preemptive_threads::{
     ObservabilityConfig, init_observability,
 };
 
+/// Kernel the tests spawn through
+fn test_kernel() -> Kernel<DefaultArch, RoundRobinScheduler> {
+    let kernel = Kernel::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("kernel initialization failed");
+    kernel
+}
+
 /// Basic thread spawn and join test
 #[test]
 fn test_basic_thread_spawn_join() {
-    let pool = StackPool::new();
-    let thread_id = unsafe { ThreadId::new_unchecked(100) };
+    let kernel = test_kernel();
     
     let shared_counter = Arc::new(AtomicUsize::new(0));
     let counter_clone = shared_counter.clone();
@@ -30,15 +36,15 @@ fn test_basic_thread_spawn_join() {
         .name("basic-test-thread")
         .priority(128);
     
-    let result = builder.spawn(thread_id, &pool, move || {
+    let result = kernel.spawn(builder, move || {
         counter_clone.store(42, Ordering::SeqCst);
     });
     
-    assert!(result.is_ok(), "Thread spawn failed: {:?}", result);
-    let (thread, join_handle) = result.unwrap();
+    assert!(result.is_ok(), "Thread spawn failed: {:?}", result.as_ref().err());
+    let join_handle = result.unwrap();
     
-    // Thread should have correct ID
-    assert_eq!(thread.id(), thread_id);
+    // Handle should refer to the spawned thread
+    assert_eq!(join_handle.thread().id(), join_handle.thread_id());
     
     // After some time, counter should be set
     std::thread::sleep(std::time::Duration::from_millis(10));
@@ -48,20 +54,19 @@ fn test_basic_thread_spawn_join() {
 /// Test multiple thread spawning
 #[test]
 fn test_multiple_threads() {
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let shared_counter = Arc::new(AtomicUsize::new(0));
     
     let mut handles = Vec::new();
     
     for i in 0..10 {
-        let thread_id = unsafe { ThreadId::new_unchecked(200 + i) };
         let counter_clone = shared_counter.clone();
         
         let builder = ThreadBuilder::new()
             .name(&format!("worker-{}", i))
             .stack_size_class(StackSizeClass::Small);
         
-        let result = builder.spawn(thread_id, &pool, move || {
+        let result = kernel.spawn(builder, move || {
             counter_clone.fetch_add(1, Ordering::SeqCst);
         });
         
@@ -79,37 +84,29 @@ fn test_multiple_threads() {
 /// Test thread priority and scheduling
 #[test]
 fn test_thread_priority() {
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let execution_order = Arc::new(Mutex::new(Vec::new()));
     
     // Spawn low priority thread first
     let order_clone1 = execution_order.clone();
-    let low_priority = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("low-priority")
-        .priority(10)
-        .spawn(
-            unsafe { ThreadId::new_unchecked(300) },
-            &pool,
-            move || {
-                std::thread::sleep(std::time::Duration::from_millis(5));
-                let mut guard = order_clone1.lock();
-                guard.push(1);
-            }
-        );
+        .priority(10);
+    let low_priority = kernel.spawn(builder, move || {
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let mut guard = order_clone1.lock();
+        guard.push(1);
+    });
     
     // Spawn high priority thread second
     let order_clone2 = execution_order.clone();
-    let high_priority = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("high-priority")
-        .priority(200)
-        .spawn(
-            unsafe { ThreadId::new_unchecked(301) },
-            &pool,
-            move || {
-                let mut guard = order_clone2.lock();
-                guard.push(2);
-            }
-        );
+        .priority(200);
+    let high_priority = kernel.spawn(builder, move || {
+        let mut guard = order_clone2.lock();
+        guard.push(2);
+    });
     
     assert!(low_priority.is_ok());
     assert!(high_priority.is_ok());
@@ -126,7 +123,7 @@ fn test_thread_priority() {
 /// Test stack size configuration
 #[test]
 fn test_stack_sizes() {
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     
     let test_sizes = [
         StackSizeClass::Small,
@@ -135,15 +132,13 @@ fn test_stack_sizes() {
     ];
     
     for (i, size_class) in test_sizes.iter().enumerate() {
-        let thread_id = unsafe { ThreadId::new_unchecked(400 + i as u64) };
-        
-        let result = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("stack-test-{}", i))
-            .stack_size_class(*size_class)
-            .spawn(thread_id, &pool, || {
-                // Just verify we can execute
-                let _x = 42;
-            });
+            .stack_size_class(*size_class);
+        let result = kernel.spawn(builder, || {
+            // Just verify we can execute
+            let _x = 42;
+        });
         
         assert!(result.is_ok(), "Failed with stack size {:?}", size_class);
     }
@@ -152,16 +147,15 @@ fn test_stack_sizes() {
 /// Test custom stack size
 #[test]
 fn test_custom_stack_size() {
-    let pool = StackPool::new();
-    let thread_id = unsafe { ThreadId::new_unchecked(500) };
+    let kernel = test_kernel();
     
-    let result = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("custom-stack")
-        .stack_size(32768) // 32KB custom size
-        .spawn(thread_id, &pool, || {
-            // Allocate some stack space
-            let _large_array = [0u8; 16384];
-        });
+        .stack_size(32768); // 32KB custom size
+    let result = kernel.spawn(builder, || {
+        // Allocate some stack space
+        let _large_array = [0u8; 16384];
+    });
     
     assert!(result.is_ok(), "Failed with custom stack size");
 }
@@ -169,44 +163,41 @@ fn test_custom_stack_size() {
 /// Test thread names and debugging info
 #[test]
 fn test_thread_metadata() {
-    let pool = StackPool::new();
-    let thread_id = unsafe { ThreadId::new_unchecked(600) };
+    let kernel = test_kernel();
     
     let thread_name = "metadata-test-thread";
-    let result = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name(thread_name)
         .debug_info(true)
-        .group_id(42)
-        .spawn(thread_id, &pool, || {});
+        .group_id(42);
+    let result = kernel.spawn(builder, || {});
     
     assert!(result.is_ok());
-    let (thread, _) = result.unwrap();
+    let thread = result.unwrap().thread();
     
     // Verify thread has correct metadata
-    assert_eq!(thread.id(), thread_id);
-    // Note: name getter would need to be implemented
+    assert_eq!(thread.name().as_deref(), Some(thread_name));
 }
 
 /// Test yielding and cooperative scheduling
 #[test]
 fn test_yield_operations() {
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let yield_count = Arc::new(AtomicUsize::new(0));
     
     let mut handles = Vec::new();
     
     for i in 0..5 {
-        let thread_id = unsafe { ThreadId::new_unchecked(700 + i) };
         let count_clone = yield_count.clone();
         
-        let result = ThreadBuilder::new()
-            .name(&format!("yielder-{}", i))
-            .spawn(thread_id, &pool, move || {
-                for _ in 0..3 {
-                    count_clone.fetch_add(1, Ordering::SeqCst);
-                    yield_thread();
-                }
-            });
+        let builder = ThreadBuilder::new()
+            .name(&format!("yielder-{}", i));
+        let result = kernel.spawn(builder, move || {
+            for _ in 0..3 {
+                count_clone.fetch_add(1, Ordering::SeqCst);
+                yield_thread();
+            }
+        });
         
         assert!(result.is_ok());
         handles.push(result.unwrap());
@@ -223,20 +214,19 @@ fn test_yield_operations() {
 #[test]
 fn test_mutex_basic() {
     let shared_data = Arc::new(Mutex::new(0));
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     
     let mut handles = Vec::new();
     
     for i in 0..10 {
-        let thread_id = unsafe { ThreadId::new_unchecked(800 + i) };
         let data_clone = shared_data.clone();
         
-        let result = ThreadBuilder::new()
-            .name(&format!("mutex-thread-{}", i))
-            .spawn(thread_id, &pool, move || {
-                let mut guard = data_clone.lock();
-                *guard += 1;
-            });
+        let builder = ThreadBuilder::new()
+            .name(&format!("mutex-thread-{}", i));
+        let result = kernel.spawn(builder, move || {
+            let mut guard = data_clone.lock();
+            *guard += 1;
+        });
         
         assert!(result.is_ok());
         handles.push(result.unwrap());
@@ -260,6 +250,7 @@ fn test_security_features() {
         enable_aslr: false,
         enable_audit_logging: true,
         use_secure_rng: true,
+        enable_speculation_barriers: false,
         panic_on_violation: false,
     };
     
@@ -267,17 +258,16 @@ fn test_security_features() {
     assert!(result.is_ok(), "Security initialization failed: {:?}", result);
     
     // Now spawn a thread with security enabled
-    let pool = StackPool::new();
-    let thread_id = unsafe { ThreadId::new_unchecked(900) };
+    let kernel = test_kernel();
     
-    let result = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("secure-thread")
         .stack_canary(true)
-        .stack_guard_pages(cfg!(feature = "mmu"))
-        .spawn(thread_id, &pool, || {
-            // Thread with security features
-            let _secure_data = 42;
-        });
+        .stack_guard_pages(cfg!(feature = "mmu"));
+    let result = kernel.spawn(builder, || {
+        // Thread with security features
+        let _secure_data = 42;
+    });
     
     assert!(result.is_ok(), "Secure thread spawn failed");
 }
@@ -301,41 +291,29 @@ fn test_observability() {
 /// Test thread builder validation
 #[test]
 fn test_builder_validation() {
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     
     // Test invalid stack size (too small)
-    let result = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("invalid-stack")
-        .stack_size(1024) // Too small
-        .spawn(
-            unsafe { ThreadId::new_unchecked(1000) },
-            &pool,
-            || {}
-        );
+        .stack_size(1024); // Too small
+    let result = kernel.spawn(builder, || {});
     
     assert!(result.is_err(), "Should fail with invalid stack size");
     
     // Test invalid CPU affinity
-    let result = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("invalid-affinity")
-        .cpu_affinity(0) // Invalid: no CPUs selected
-        .spawn(
-            unsafe { ThreadId::new_unchecked(1001) },
-            &pool,
-            || {}
-        );
+        .cpu_affinity(0); // Invalid: no CPUs selected
+    let result = kernel.spawn(builder, || {});
     
     assert!(result.is_err(), "Should fail with invalid CPU affinity");
     
     // Test very long name
     let long_name = "a".repeat(100);
-    let result = ThreadBuilder::new()
-        .name(&long_name)
-        .spawn(
-            unsafe { ThreadId::new_unchecked(1002) },
-            &pool,
-            || {}
-        );
+    let builder = ThreadBuilder::new()
+        .name(&long_name);
+    let result = kernel.spawn(builder, || {});
     
     assert!(result.is_err(), "Should fail with too long name");
 }
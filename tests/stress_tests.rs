@@ -9,17 +9,24 @@ use std::time::{Duration as StdDuration, Instant as StdInstant};
 use std::println;
 
 use preemptive_threads::{
-    ThreadBuilder, ThreadId,
-    StackPool, StackSizeClass,
+    DefaultArch, Kernel, RoundRobinScheduler, ThreadBuilder,
+    StackSizeClass,
     yield_thread,
     Mutex,
 };
 
+/// Kernel the tests spawn through
+fn test_kernel() -> Kernel<DefaultArch, RoundRobinScheduler> {
+    let kernel = Kernel::new(RoundRobinScheduler::new(1));
+    kernel.init().expect("kernel initialization failed");
+    kernel
+}
+
 /// Stress test: Spawn many threads rapidly
 #[test]
 fn stress_test_mass_spawn() {
     println!("\n=== STRESS TEST: Mass Thread Spawn ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let thread_count = 1000;
     let completed = Arc::new(AtomicUsize::new(0));
     
@@ -27,21 +34,20 @@ fn stress_test_mass_spawn() {
     let mut handles = Vec::new();
     
     for i in 0..thread_count {
-        let thread_id = unsafe { ThreadId::new_unchecked(10000 + i) };
         let completed_clone = completed.clone();
         
-        let result = ThreadBuilder::new()
+        let builder = ThreadBuilder::new()
             .name(&format!("stress-{}", i))
             .stack_size_class(StackSizeClass::Small)
-            .priority((i % 256) as u8)
-            .spawn(thread_id, &pool, move || {
-                // Simulate some work
-                let mut sum = 0u64;
-                for j in 0..1000 {
-                    sum = sum.wrapping_add(j);
-                }
-                completed_clone.fetch_add(1, Ordering::SeqCst);
-            });
+            .priority((i % 256) as u8);
+        let result = kernel.spawn(builder, move || {
+            // Simulate some work
+            let mut sum = 0u64;
+            for j in 0..1000 {
+                sum = sum.wrapping_add(j);
+            }
+            completed_clone.fetch_add(1, Ordering::SeqCst);
+        });
         
         match result {
             Ok(handle) => handles.push(handle),
@@ -77,7 +83,7 @@ fn stress_test_mass_spawn() {
 #[test]
 fn stress_test_mutex_contention() {
     println!("\n=== STRESS TEST: Mutex Contention ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let shared_counter = Arc::new(Mutex::new(0u64));
     let thread_count = 100;
     let iterations_per_thread = 1000;
@@ -86,19 +92,18 @@ fn stress_test_mutex_contention() {
     let mut handles = Vec::new();
     
     for i in 0..thread_count {
-        let thread_id = unsafe { ThreadId::new_unchecked(20000 + i) };
         let counter_clone = shared_counter.clone();
         
-        let result = ThreadBuilder::new()
-            .name(&format!("contention-{}", i))
-            .spawn(thread_id, &pool, move || {
-                for _ in 0..iterations_per_thread {
-                    let mut guard = counter_clone.lock();
-                    *guard += 1;
-                    // Hold lock briefly to increase contention
-                    std::thread::sleep(StdDuration::from_micros(1));
-                }
-            });
+        let builder = ThreadBuilder::new()
+            .name(&format!("contention-{}", i));
+        let result = kernel.spawn(builder, move || {
+            for _ in 0..iterations_per_thread {
+                let mut guard = counter_clone.lock();
+                *guard += 1;
+                // Hold lock briefly to increase contention
+                std::thread::sleep(StdDuration::from_micros(1));
+            }
+        });
         
         if let Ok(handle) = result {
             handles.push(handle);
@@ -124,7 +129,7 @@ fn stress_test_mutex_contention() {
 #[test]
 fn stress_test_churn() {
     println!("\n=== STRESS TEST: Thread Churn ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let cycles = 100;
     let threads_per_cycle = 10;
     let total_created = Arc::new(AtomicUsize::new(0));
@@ -136,17 +141,16 @@ fn stress_test_churn() {
         let mut handles = Vec::new();
         
         for i in 0..threads_per_cycle {
-            let thread_id = unsafe { ThreadId::new_unchecked(30000 + cycle * 1000 + i) };
             let completed_clone = total_completed.clone();
             
-            let result = ThreadBuilder::new()
+            let builder = ThreadBuilder::new()
                 .name(&format!("churn-{}-{}", cycle, i))
-                .stack_size_class(StackSizeClass::Small)
-                .spawn(thread_id, &pool, move || {
-                    // Quick work
-                    yield_thread();
-                    completed_clone.fetch_add(1, Ordering::SeqCst);
-                });
+                .stack_size_class(StackSizeClass::Small);
+            let result = kernel.spawn(builder, move || {
+                // Quick work
+                yield_thread();
+                completed_clone.fetch_add(1, Ordering::SeqCst);
+            });
             
             if let Ok(handle) = result {
                 handles.push(handle);
@@ -177,7 +181,7 @@ fn stress_test_churn() {
 #[test]
 fn stress_test_memory_pressure() {
     println!("\n=== STRESS TEST: Memory Pressure ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let mut handles = Vec::new();
     let mut total_memory = 0usize;
     
@@ -190,16 +194,14 @@ fn stress_test_memory_pressure() {
     
     for (size_class, count) in sizes.iter() {
         for i in 0..*count {
-            let thread_id = unsafe { ThreadId::new_unchecked(40000 + i) };
-            
-            let result = ThreadBuilder::new()
+            let builder = ThreadBuilder::new()
                 .name(&format!("memory-{:?}-{}", size_class, i))
-                .stack_size_class(*size_class)
-                .spawn(thread_id, &pool, || {
-                    // Allocate some stack memory
-                    let _data = vec![0u8; 1024];
-                    std::thread::sleep(StdDuration::from_millis(100));
-                });
+                .stack_size_class(*size_class);
+            let result = kernel.spawn(builder, || {
+                // Allocate some stack memory
+                let _data = vec![0u8; 1024];
+                std::thread::sleep(StdDuration::from_millis(100));
+            });
             
             if let Ok(handle) = result {
                 handles.push(handle);
@@ -226,7 +228,7 @@ fn stress_test_memory_pressure() {
 #[test]
 fn stress_test_yield_storm() {
     println!("\n=== STRESS TEST: Yield Storm ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let thread_count = 50;
     let yields_per_thread = 100;
     let yield_count = Arc::new(AtomicUsize::new(0));
@@ -235,17 +237,16 @@ fn stress_test_yield_storm() {
     let mut handles = Vec::new();
     
     for i in 0..thread_count {
-        let thread_id = unsafe { ThreadId::new_unchecked(50000 + i) };
         let yield_clone = yield_count.clone();
         
-        let result = ThreadBuilder::new()
-            .name(&format!("yielder-{}", i))
-            .spawn(thread_id, &pool, move || {
-                for _ in 0..yields_per_thread {
-                    yield_clone.fetch_add(1, Ordering::SeqCst);
-                    yield_thread();
-                }
-            });
+        let builder = ThreadBuilder::new()
+            .name(&format!("yielder-{}", i));
+        let result = kernel.spawn(builder, move || {
+            for _ in 0..yields_per_thread {
+                yield_clone.fetch_add(1, Ordering::SeqCst);
+                yield_thread();
+            }
+        });
         
         if let Ok(handle) = result {
             handles.push(handle);
@@ -270,27 +271,23 @@ fn stress_test_yield_storm() {
 #[test]
 fn stress_test_priority_inversion() {
     println!("\n=== STRESS TEST: Priority Inversion ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let shared_resource = Arc::new(Mutex::new(0));
     let high_pri_started = Arc::new(AtomicBool::new(false));
     let high_pri_completed = Arc::new(AtomicBool::new(false));
     
     // Low priority thread that holds lock
     let resource_clone = shared_resource.clone();
-    let low_pri = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("low-priority-holder")
-        .priority(10)
-        .spawn(
-            unsafe { ThreadId::new_unchecked(60001) },
-            &pool,
-            move || {
-                let mut guard = resource_clone.lock();
-                *guard = 1;
-                // Hold lock for a while
-                std::thread::sleep(StdDuration::from_millis(100));
-                *guard = 2;
-            }
-        );
+        .priority(10);
+    let low_pri = kernel.spawn(builder, move || {
+        let mut guard = resource_clone.lock();
+        *guard = 1;
+        // Hold lock for a while
+        std::thread::sleep(StdDuration::from_millis(100));
+        *guard = 2;
+    });
     
     // Let low priority thread acquire lock
     std::thread::sleep(StdDuration::from_millis(10));
@@ -299,36 +296,28 @@ fn stress_test_priority_inversion() {
     let resource_clone = shared_resource.clone();
     let started_clone = high_pri_started.clone();
     let completed_clone = high_pri_completed.clone();
-    let high_pri = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("high-priority-waiter")
-        .priority(250)
-        .spawn(
-            unsafe { ThreadId::new_unchecked(60002) },
-            &pool,
-            move || {
-                started_clone.store(true, Ordering::SeqCst);
-                let mut guard = resource_clone.lock();
-                *guard = 3;
-                completed_clone.store(true, Ordering::SeqCst);
-            }
-        );
+        .priority(250);
+    let high_pri = kernel.spawn(builder, move || {
+        started_clone.store(true, Ordering::SeqCst);
+        let mut guard = resource_clone.lock();
+        *guard = 3;
+        completed_clone.store(true, Ordering::SeqCst);
+    });
     
     // Medium priority thread that doesn't need lock (can cause inversion)
     let medium_work_done = Arc::new(AtomicUsize::new(0));
     let work_clone = medium_work_done.clone();
-    let medium_pri = ThreadBuilder::new()
+    let builder = ThreadBuilder::new()
         .name("medium-priority-worker")
-        .priority(128)
-        .spawn(
-            unsafe { ThreadId::new_unchecked(60003) },
-            &pool,
-            move || {
-                for i in 0..1000 {
-                    work_clone.store(i, Ordering::SeqCst);
-                    yield_thread();
-                }
-            }
-        );
+        .priority(128);
+    let medium_pri = kernel.spawn(builder, move || {
+        for i in 0..1000 {
+            work_clone.store(i, Ordering::SeqCst);
+            yield_thread();
+        }
+    });
     
     assert!(low_pri.is_ok() && high_pri.is_ok() && medium_pri.is_ok());
     
@@ -352,27 +341,23 @@ fn stress_test_priority_inversion() {
 #[test]
 fn stress_test_recursive_mutex() {
     println!("\n=== STRESS TEST: Recursive Mutex ===");
-    let pool = StackPool::new();
+    let kernel = test_kernel();
     let mutex = Arc::new(Mutex::new(0));
     
     let mutex_clone = mutex.clone();
-    let result = ThreadBuilder::new()
-        .name("recursive-tester")
-        .spawn(
-            unsafe { ThreadId::new_unchecked(70001) },
-            &pool,
-            move || {
-                let mut guard1 = mutex_clone.lock();
-                *guard1 = 1;
-                
-                // Try to lock again (would deadlock with non-recursive mutex)
-                // Our implementation should handle this gracefully
-                // Note: This tests the library's deadlock detection/prevention
-                
-                // For now, just verify single lock works
-                *guard1 = 2;
-            }
-        );
+    let builder = ThreadBuilder::new()
+        .name("recursive-tester");
+    let result = kernel.spawn(builder, move || {
+        let mut guard1 = mutex_clone.lock();
+        *guard1 = 1;
+        
+        // Try to lock again (would deadlock with non-recursive mutex)
+        // Our implementation should handle this gracefully
+        // Note: This tests the library's deadlock detection/prevention
+        
+        // For now, just verify single lock works
+        *guard1 = 2;
+    });
     
     assert!(result.is_ok());
     std::thread::sleep(StdDuration::from_millis(100));